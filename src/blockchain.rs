@@ -1,11 +1,75 @@
+//! Where traces get their transactions from.
+//!
+//! In the core: the `BlockchainDataSource` trait with the types its methods speak
+//! (`TxStatus`, `TxMetadata`, `Method`), and `BlockchainError` with its
+//! `ErrorContext`. Each backend is behind its feature; the wrappers (rate limiting,
+//! fallback, recording, `DataSourceBuilder`) come with any of them, and
+//! `CachingDataSource` with `cache`.
+
+pub(crate) mod batch;
+#[cfg(all(feature = "rpc", not(target_arch = "wasm32")))]
 pub mod bitcoin_rpc;
+#[cfg(all(feature = "bitcoind-rest", not(target_arch = "wasm32")))]
+pub mod bitcoind_rest;
+#[cfg(feature = "blockbook")]
+pub mod blockbook;
+#[cfg(feature = "runtime")]
+pub mod builder;
+#[cfg(feature = "cache")]
 pub mod cache;
+#[cfg(all(feature = "electrum", not(target_arch = "wasm32")))]
+pub mod electrum;
 pub mod error;
+#[cfg(feature = "esplora")]
 pub mod esplora;
+#[cfg(feature = "runtime")]
+pub mod fallback;
+#[cfg(feature = "metrics")]
+pub mod metered;
+#[cfg(feature = "runtime")]
+pub mod ratelimit;
+#[cfg(all(feature = "runtime", not(target_arch = "wasm32")))]
+pub mod record;
+#[cfg(feature = "runtime")]
+pub(crate) mod redact;
 pub mod source;
+#[cfg(feature = "runtime")]
+pub mod tip;
 
+pub use batch::DEFAULT_MAX_BATCH_SIZE;
+#[cfg(all(feature = "rpc", not(target_arch = "wasm32")))]
 pub use bitcoin_rpc::BitcoinRpcClient;
-pub use cache::{CacheKey, CachedEntry, CachingDataSource};
-pub use error::{BlockchainError, Result};
+#[cfg(all(feature = "bitcoind-rest", not(target_arch = "wasm32")))]
+pub use bitcoind_rest::{BitcoindRestClient, ChainInfo};
+#[cfg(feature = "blockbook")]
+pub use blockbook::BlockbookClient;
+#[cfg(feature = "runtime")]
+pub use builder::{BackendConfig, DataSourceBuilder};
+#[cfg(feature = "moka-cache")]
+pub use cache::MokaBackend;
+#[cfg(feature = "persistent-cache")]
+pub use cache::SledBackend;
+#[cfg(feature = "cache")]
+pub use cache::{
+    CacheBackend, CacheLookup, CachePolicy, CacheStats, CachedEntry, CachingDataSource,
+    CachingDataSourceBuilder, JanitorHandle, KindStats, MemoryBackend, PrefetchSummary,
+    SnapshotReport, TtlPolicy,
+};
+#[cfg(all(feature = "electrum", not(target_arch = "wasm32")))]
+pub use electrum::{ElectrumClient, ServerVersion};
+pub use error::{BlockchainError, ErrorContext, Result, ResultExt, RetryPolicy};
+#[cfg(feature = "runtime")]
+pub use error::{execute, execute_with_clock};
+#[cfg(feature = "esplora")]
 pub use esplora::EsploraClient;
-pub use source::BlockchainDataSource;
+#[cfg(feature = "runtime")]
+pub use fallback::{Backend, ErrorClass, FallbackDataSource, FallbackStats};
+#[cfg(feature = "metrics")]
+pub use metered::MeteredDataSource;
+#[cfg(feature = "runtime")]
+pub use ratelimit::RateLimitedDataSource;
+#[cfg(all(feature = "runtime", not(target_arch = "wasm32")))]
+pub use record::{RecordingDataSource, ReplayDataSource};
+pub use source::{BlockchainDataSource, CacheKey, DynDataSource, Method, TxMetadata, TxStatus};
+#[cfg(feature = "runtime")]
+pub use tip::{DEFAULT_TIP_MAX_AGE, TipCache};
@@ -0,0 +1,163 @@
+//! What every batch lookup does before asking its backend.
+//!
+//! Each distinct input is asked about once, however many times the batch repeats
+//! it, and the answers are spread back over the batch in its order: duplicates get
+//! the same answer. Backends that send a batch in one request, or keep its lookups
+//! in flight together, ask at most their max batch size at a time, the rest in
+//! further chunks rather than failing.
+//!
+//! An empty batch asks nothing and answers an empty `Vec`: it is not an error.
+
+use crate::blockchain::{BlockchainError, Result};
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::future::Future;
+use std::hash::Hash;
+
+/// Inputs a backend asks about at once unless given its own max batch size
+pub const DEFAULT_MAX_BATCH_SIZE: usize = 100;
+
+/// A batch with its duplicates set aside
+#[derive(Debug)]
+pub(crate) struct Dedup<K> {
+    /// Each distinct input, in the order it first appears
+    unique: Vec<K>,
+    /// Index in `unique` of each input of the batch
+    slots: Vec<usize>,
+}
+
+impl<K: Copy + Eq + Hash> Dedup<K> {
+    pub(crate) fn new(inputs: &[K]) -> Self {
+        let mut index = HashMap::with_capacity(inputs.len());
+        let mut unique = Vec::new();
+        let slots = inputs
+            .iter()
+            .map(|&input| match index.entry(input) {
+                Entry::Occupied(entry) => *entry.get(),
+                Entry::Vacant(entry) => {
+                    unique.push(input);
+                    *entry.insert(unique.len() - 1)
+                }
+            })
+            .collect();
+        Self { unique, slots }
+    }
+
+    /// The distinct inputs, in the order they first appear
+    pub(crate) fn unique(&self) -> &[K] {
+        &self.unique
+    }
+
+    /// One answer per input of the batch, from the answers to the distinct inputs
+    /// in order
+    ///
+    /// # Errors
+    /// `DataInconsistency` if there is not one answer per distinct input
+    pub(crate) fn spread<T: Clone>(&self, answers: Vec<T>) -> Result<Vec<T>> {
+        check_count(answers.len(), self.unique.len())?;
+        Ok(self
+            .slots
+            .iter()
+            .map(|&slot| answers[slot].clone())
+            .collect())
+    }
+}
+
+/// Answers a batch with `fetch`, called with each distinct input once, at most
+/// `max_size` of them a call (0 counts as 1), one call after the other.
+///
+/// # Errors
+/// - The first error of `fetch`
+/// - `DataInconsistency` - `fetch` answered the wrong number of inputs
+pub(crate) async fn run<K, T, F, Fut>(inputs: &[K], max_size: usize, mut fetch: F) -> Result<Vec<T>>
+where
+    K: Copy + Eq + Hash,
+    T: Clone,
+    F: FnMut(Vec<K>) -> Fut,
+    Fut: Future<Output = Result<Vec<T>>>,
+{
+    let dedup = Dedup::new(inputs);
+    let mut answers = Vec::with_capacity(dedup.unique().len());
+    for chunk in dedup.unique().chunks(max_size.max(1)) {
+        let fetched = fetch(chunk.to_vec()).await?;
+        check_count(fetched.len(), chunk.len())?;
+        answers.extend(fetched);
+    }
+    dedup.spread(answers)
+}
+
+fn check_count(answers: usize, inputs: usize) -> Result<()> {
+    if answers != inputs {
+        return Err(BlockchainError::DataInconsistency(format!(
+            "Batch lookup returned {} results for {} requests",
+            answers, inputs
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// Runs a batch of `inputs` answered with each input doubled, returning the
+    /// answers and the chunks `fetch` was called with
+    async fn doubled(inputs: &[u32], max_size: usize) -> (Vec<u32>, Vec<Vec<u32>>) {
+        let calls = Mutex::new(Vec::new());
+        let answers = run(inputs, max_size, |chunk| {
+            calls.lock().unwrap().push(chunk.clone());
+            async move { Ok(chunk.iter().map(|input| input * 2).collect()) }
+        })
+        .await
+        .unwrap();
+        (answers, calls.into_inner().unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_duplicates_are_asked_once_and_share_the_answer() {
+        let (answers, calls) = doubled(&[3, 1, 3, 3, 2, 1], 10).await;
+
+        assert_eq!(answers, [6, 2, 6, 6, 4, 2]);
+        assert_eq!(calls, [vec![3, 1, 2]]);
+    }
+
+    #[tokio::test]
+    async fn test_chunks_split_the_distinct_inputs_at_the_max_size() {
+        let inputs: Vec<u32> = (0..5).collect();
+
+        let (answers, calls) = doubled(&inputs, 2).await;
+        assert_eq!(answers, [0, 2, 4, 6, 8]);
+        assert_eq!(calls, [vec![0, 1], vec![2, 3], vec![4]]);
+
+        // A batch of exactly the max size is one call, duplicates not counted
+        let (_, calls) = doubled(&[0, 1, 0, 2, 1], 3).await;
+        assert_eq!(calls, [vec![0, 1, 2]]);
+        let (_, calls) = doubled(&inputs, 0).await;
+        assert_eq!(calls.len(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_empty_batch_asks_nothing() {
+        let (answers, calls) = doubled(&[], 10).await;
+
+        assert!(answers.is_empty());
+        assert!(calls.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_wrong_number_of_answers_is_inconsistent() {
+        let err = run(
+            &[1, 2, 3],
+            2,
+            |chunk| async move { Ok(chunk[1..].to_vec()) },
+        )
+        .await
+        .unwrap_err();
+
+        assert!(
+            matches!(err, BlockchainError::DataInconsistency(_)),
+            "{err}"
+        );
+    }
+}
@@ -1,14 +1,39 @@
-use crate::blockchain::{BlockchainDataSource, BlockchainError, Result};
+use crate::blockchain::{
+    BlockchainDataSource, BlockchainError, DEFAULT_MAX_BATCH_SIZE, ErrorContext, Result, ResultExt,
+    RetryPolicy, TipCache, TxMetadata, TxStatus, batch, execute, redact,
+};
 use async_trait::async_trait;
+use bitcoin::Amount;
 use bitcoin::consensus::encode::deserialize_hex;
 use serde_json::{Value, json};
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{Instrument, Span, field};
 
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub struct BitcoinRpcClient {
     url: String,
     username: String,
     password: String,
     client: reqwest::Client,
+    /// Shared by clones, so they count confirmations against the same tip
+    tip: Arc<TipCache>,
+    retry: RetryPolicy,
+    max_batch_size: usize,
+}
+
+/// Leaves the password out, and the credentials of the URL
+impl fmt::Debug for BitcoinRpcClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BitcoinRpcClient")
+            .field("url", &redact::url(&self.url))
+            .field("username", &self.username)
+            .field("password", &redact::REDACTED)
+            .field("retry", &self.retry)
+            .field("max_batch_size", &self.max_batch_size)
+            .finish_non_exhaustive()
+    }
 }
 
 impl BitcoinRpcClient {
@@ -18,9 +43,122 @@ impl BitcoinRpcClient {
             username,
             password,
             client: reqwest::Client::new(),
+            tip: Arc::new(TipCache::default()),
+            retry: RetryPolicy::default(),
+            max_batch_size: DEFAULT_MAX_BATCH_SIZE,
         }
     }
 
+    /// Gives up on requests that get no full answer within `timeout`, failing with
+    /// an error `is_timeout` holds for (none unless set)
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.client = reqwest::Client::builder()
+            .timeout(timeout)
+            .build()
+            .expect("HTTP client with a timeout");
+        self
+    }
+
+    /// Retries of calls whose request fails with a retryable error (none unless set)
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Calls sent in one JSON-RPC batch request at most, larger batches split over
+    /// several (`DEFAULT_MAX_BATCH_SIZE` unless set)
+    pub fn max_batch_size(mut self, size: usize) -> Self {
+        self.max_batch_size = size;
+        self
+    }
+
+    /// How long the tip height confirmations are counted against is reused
+    /// (`DEFAULT_TIP_MAX_AGE` unless set)
+    pub fn tip_max_age(mut self, max_age: Duration) -> Self {
+        self.tip = Arc::new(TipCache::new(max_age));
+        self
+    }
+
+    /// Height of the chain tip (`getblockcount`), as cached for the tip max age.
+    pub async fn tip_height(&self) -> Result<u32> {
+        self.tip
+            .height(|| async {
+                let count = self.rpc_call("getblockcount", vec![]).await?;
+                count
+                    .as_u64()
+                    .and_then(|height| u32::try_from(height).ok())
+                    .ok_or_else(|| {
+                        BlockchainError::DataInconsistency(format!(
+                            "RPC getblockcount result {} is not a height",
+                            count
+                        ))
+                    })
+            })
+            .await
+            .with_ctx(|| self.context("tip_height"))
+    }
+
+    /// Transaction `txid` from the `hex` field of its verbose `getrawtransaction`
+    /// result
+    fn transaction_of(txid: bitcoin::Txid, rpc_result: &Value) -> Result<bitcoin::Transaction> {
+        // Extract hex string
+        let hex_str = rpc_result
+            .get("hex")
+            .and_then(|h| h.as_str())
+            .ok_or_else(|| {
+                BlockchainError::DataInconsistency(
+                    "RPC response is missing 'hex' field or type is invalid".to_string(),
+                )
+            })?;
+
+        // Deserialize hex value into a bitcoin::Transaction
+        deserialize_hex(hex_str)
+            .map_err(|e| BlockchainError::decode(format!("transaction {}", txid), e))
+    }
+
+    /// Status of transaction `txid` from its verbose `getrawtransaction` result and
+    /// the header of its block
+    async fn status_of(&self, txid: bitcoin::Txid, rpc_result: &Value) -> Result<TxStatus> {
+        // Mempool transactions have no blockhash
+        let Some(hash) = rpc_result.get("blockhash").and_then(|h| h.as_str()) else {
+            return Ok(TxStatus::unconfirmed());
+        };
+        let block_hash: bitcoin::BlockHash = hash.parse().map_err(|e| {
+            BlockchainError::decode(format!("blockhash {:?} of transaction {}", hash, txid), e)
+        })?;
+
+        // getrawtransaction does not report the height, the block header does
+        let header: Value = self
+            .rpc_call("getblockheader", vec![json!(block_hash), json!(true)])
+            .await?;
+        let block_height = header
+            .get("height")
+            .and_then(|h| h.as_u64())
+            .and_then(|h| u32::try_from(h).ok())
+            .ok_or_else(|| {
+                BlockchainError::DataInconsistency(format!(
+                    "RPC getblockheader result for block {} has no valid 'height'",
+                    block_hash
+                ))
+            })?;
+        let block_time = rpc_result
+            .get("blocktime")
+            .or_else(|| header.get("time"))
+            .and_then(|t| t.as_u64());
+
+        Ok(TxStatus {
+            confirmed: true,
+            block_height: Some(block_height),
+            block_hash: Some(block_hash),
+            block_time,
+        })
+    }
+
+    /// Context of the errors of `operation`, naming this backend
+    fn context(&self, operation: &'static str) -> ErrorContext {
+        ErrorContext::new(operation).url(&self.url)
+    }
+
     pub async fn rpc_call(
         &self,
         method: &str,
@@ -36,24 +174,118 @@ impl BitcoinRpcClient {
             "id": id,
         });
 
-        // Post request to RPC server
-        let response = self
-            .client
-            .post(&self.url)
-            .basic_auth(&self.username, Some(&self.password))
-            .json(&rpc_request_body)
-            .send()
-            .await
-            .map_err(|e| BlockchainError::NetworkFailure(e.to_string()))?;
-        // print!("response {:?}", response);
+        let json_response = self.post(method, &rpc_request_body).await?;
+        Self::result_of(method, &json_response)
+    }
+
+    /// Calls `method` once with each of `params`, all in one JSON-RPC batch request.
+    ///
+    /// # Returns
+    /// The result of each call, in the order of `params`, its RPC error mapped as
+    /// `rpc_call` maps them
+    ///
+    /// # Errors
+    /// As `rpc_call` for the request itself, also `DataInconsistency` for an answer
+    /// that is not one response per call
+    async fn rpc_batch(&self, method: &str, params: Vec<Vec<Value>>) -> Result<Vec<Result<Value>>> {
+        let calls = params.len();
+        let rpc_request_body: Vec<Value> = params
+            .into_iter()
+            .enumerate()
+            .map(|(id, params)| {
+                json!({
+                    "jsonrpc": "2.0",
+                    "method": method,
+                    "params": params,
+                    "id": id,
+                })
+            })
+            .collect();
+        let json_response = self.post(method, &json!(rpc_request_body)).await?;
+
+        // Responses may come in any order, matched to their calls by id
+        let responses = json_response.as_array().ok_or_else(|| {
+            BlockchainError::DataInconsistency(format!(
+                "RPC {} batch response is not an array",
+                method
+            ))
+        })?;
+        let mut results: Vec<Option<Result<Value>>> = (0..calls).map(|_| None).collect();
+        for response in responses {
+            let slot = response
+                .get("id")
+                .and_then(|id| id.as_u64())
+                .and_then(|id| results.get_mut(id as usize))
+                .ok_or_else(|| {
+                    BlockchainError::DataInconsistency(format!(
+                        "RPC {} batch response has an unknown id",
+                        method
+                    ))
+                })?;
+            *slot = Some(Self::result_of(method, response));
+        }
+        results
+            .into_iter()
+            .map(|result| {
+                result.ok_or_else(|| {
+                    BlockchainError::DataInconsistency(format!(
+                        "RPC {} batch response is missing a call",
+                        method
+                    ))
+                })
+            })
+            .collect()
+    }
+
+    /// POSTs a JSON-RPC request body, tried again as the retry policy allows, and
+    /// reads the JSON answer
+    async fn post(&self, method: &str, rpc_request_body: &Value) -> Result<Value> {
+        // Post request to RPC server. The span names the method and endpoint only:
+        // the params and the authorization header stay out of the logs
+        let response = execute(&self.retry, || {
+            let span = tracing::debug_span!(
+                "rpc_request",
+                method,
+                url = %redact::url(&self.url),
+                status = field::Empty,
+                elapsed_ms = field::Empty,
+            );
+            async {
+                let sent = Instant::now();
+                let response = self
+                    .client
+                    .post(&self.url)
+                    .basic_auth(&self.username, Some(&self.password))
+                    .json(rpc_request_body)
+                    .send()
+                    .await;
+                let span = Span::current();
+                span.record("elapsed_ms", sent.elapsed().as_millis() as u64);
+                match response {
+                    Ok(response) => {
+                        span.record("status", response.status().as_u16());
+                        tracing::debug!("response");
+                        Ok(response)
+                    }
+                    Err(e) => {
+                        tracing::debug!(timeout = e.is_timeout(), "no response");
+                        Err(BlockchainError::request_after(&self.url, e, sent.elapsed()))
+                    }
+                }
+            }
+            .instrument(span)
+        })
+        .await?;
 
         // convert response to serde_json value
-        let json_response: serde_json::Value = response
+        response
             .json()
             .await
-            .map_err(|e| BlockchainError::NetworkFailure(e.to_string()))?;
-        // print!("json_response from rpc call {:?}", json_response);
+            .map_err(|e| BlockchainError::decode(format!("RPC {} response", method), e))
+    }
 
+    /// The result of one JSON-RPC response, its error mapped to a `BlockchainError`
+    fn result_of(method: &str, json_response: &Value) -> Result<Value> {
         if let Some(rpc_error) = json_response.get("error").and_then(|e| e.as_object())
             && !rpc_error.is_empty()
         {
@@ -67,6 +299,14 @@ impl BitcoinRpcClient {
             // Map JSON RPC errors to BlockchainError
             // Codes are specific to transaction related errors
             match code {
+                // Without -txindex, only mempool and wallet transactions are found
+                -5 if method == "getrawtransaction" && message.contains("-txindex") => {
+                    return Err(BlockchainError::unsupported(
+                        "bitcoind",
+                        "getrawtransaction",
+                        "start bitcoind with -txindex=1 to look up confirmed transactions",
+                    ));
+                }
                 -5 | -20 => return Err(BlockchainError::NotFound(message.to_string())),
                 -8 | -22 => return Err(BlockchainError::InvalidInput(message.to_string())),
                 -32603 => return Err(BlockchainError::Other(message.to_string())),
@@ -90,52 +330,542 @@ impl BitcoinRpcClient {
 #[async_trait]
 impl BlockchainDataSource for BitcoinRpcClient {
     async fn get_transaction(&self, txid: bitcoin::Txid) -> Result<bitcoin::Transaction> {
-        let rpc_result: Value = self
-            .rpc_call("getrawtransaction", vec![json!(txid), json!(1)])
-            .await?;
-
-        // Extract hex string
-        let hex_str = rpc_result
-            .get("hex")
-            .and_then(|h| h.as_str())
-            .ok_or_else(|| {
-                BlockchainError::DataInconsistency(
-                    "RPC response is missing 'hex' field or type is invalid".to_string(),
-                )
-            })?;
-
-        // Deserialize hex value into a bitcoin::Transaction
-        let transaction = deserialize_hex(hex_str).map_err(|e| {
-            BlockchainError::DataInconsistency(format!(
-                "Failed to deserialize Hex {:?}, for Txid {:?}: {:?}",
-                hex_str, txid, e
-            ))
-        })?;
+        async {
+            let rpc_result: Value = self
+                .rpc_call("getrawtransaction", vec![json!(txid), json!(1)])
+                .await?;
 
-        Ok(transaction)
+            Self::transaction_of(txid, &rpc_result)
+        }
+        .await
+        .with_ctx(|| self.context("get_transaction").txid(txid))
     }
     async fn get_spending_transaction(
         &self,
-        _outpoint: bitcoin::OutPoint,
+        outpoint: bitcoin::OutPoint,
     ) -> Result<Option<bitcoin::Transaction>> {
-        todo!()
+        // bitcoind keeps no index of spenders
+        Err(BlockchainError::unsupported(
+            "bitcoind",
+            "get_spending_transaction",
+            "use an Esplora backend to look up the spender of an output",
+        ))
+        .with_ctx(|| self.context("get_spending_transaction").outpoint(outpoint))
     }
     async fn get_address_transactions(
         &self,
         _address: bitcoin::Address,
     ) -> Result<Vec<bitcoin::Transaction>> {
-        todo!()
+        // bitcoind keeps no index of addresses outside its wallet
+        Err(BlockchainError::unsupported(
+            "bitcoind",
+            "get_address_transactions",
+            "use an Esplora backend to look up the history of an address",
+        ))
+        .with_ctx(|| self.context("get_address_transactions"))
     }
+    /// Fetches transactions by their txids with batches of `getrawtransaction`
+    /// calls, each distinct txid asked once and at most the max batch size a
+    /// request, `None` for those bitcoind does not know. An empty batch makes no
+    /// request.
     async fn get_transactions_batch(
         &self,
-        _txids: &[bitcoin::Txid],
+        txids: &[bitcoin::Txid],
     ) -> Result<Vec<Option<bitcoin::Transaction>>> {
-        todo!()
+        batch::run(txids, self.max_batch_size, |txids| async move {
+            let params = txids
+                .iter()
+                .map(|txid| vec![json!(txid), json!(1)])
+                .collect();
+            let results = self
+                .rpc_batch("getrawtransaction", params)
+                .await
+                .with_ctx(|| self.context("get_transactions_batch"))?;
+            txids
+                .iter()
+                .zip(results)
+                .map(|(&txid, result)| {
+                    match result.and_then(|rpc_result| Self::transaction_of(txid, &rpc_result)) {
+                        Ok(tx) => Ok(Some(tx)),
+                        Err(BlockchainError::NotFound(_)) => Ok(None),
+                        Err(e) => Err(e),
+                    }
+                    .with_ctx(|| self.context("get_transactions_batch").txid(txid))
+                })
+                .collect()
+        })
+        .await
+    }
+    async fn get_block_raw(&self, block_hash: bitcoin::BlockHash) -> Result<bitcoin::Block> {
+        async {
+            // verbosity 0 returns the serialized block as a hex string
+            let rpc_result: Value = self
+                .rpc_call("getblock", vec![json!(block_hash), json!(0)])
+                .await?;
+
+            let hex_str = rpc_result.as_str().ok_or_else(|| {
+                BlockchainError::DataInconsistency(format!(
+                    "RPC getblock result for block {} is not a hex string",
+                    block_hash
+                ))
+            })?;
+
+            deserialize_hex(hex_str)
+                .map_err(|e| BlockchainError::decode(format!("block {}", block_hash), e))
+        }
+        .await
+        .with_ctx(|| self.context("get_block_raw"))
     }
-    async fn get_spending_transactions_batch(
+    async fn get_transaction_status(&self, txid: bitcoin::Txid) -> Result<TxStatus> {
+        async {
+            let rpc_result: Value = self
+                .rpc_call("getrawtransaction", vec![json!(txid), json!(1)])
+                .await?;
+
+            self.status_of(txid, &rpc_result).await
+        }
+        .await
+        .with_ctx(|| self.context("get_transaction_status").txid(txid))
+    }
+    async fn get_block_header(
         &self,
-        _outpoints: &[bitcoin::OutPoint],
-    ) -> Result<Vec<Option<bitcoin::Transaction>>> {
-        todo!()
+        block_hash: bitcoin::BlockHash,
+    ) -> Result<bitcoin::block::Header> {
+        async {
+            // verbose = false returns the serialized header as a hex string
+            let rpc_result: Value = self
+                .rpc_call("getblockheader", vec![json!(block_hash), json!(false)])
+                .await?;
+
+            let hex_str = rpc_result.as_str().ok_or_else(|| {
+                BlockchainError::DataInconsistency(format!(
+                    "RPC getblockheader result for block {} is not a hex string",
+                    block_hash
+                ))
+            })?;
+
+            deserialize_hex(hex_str)
+                .map_err(|e| BlockchainError::decode(format!("header of block {}", block_hash), e))
+        }
+        .await
+        .with_ctx(|| self.context("get_block_header"))
+    }
+    async fn get_transaction_with_metadata(
+        &self,
+        txid: bitcoin::Txid,
+    ) -> Result<(bitcoin::Transaction, TxMetadata)> {
+        async {
+            // verbosity 2 adds the fee, in BTC, when the node has the block's undo data
+            let rpc_result: Value = self
+                .rpc_call("getrawtransaction", vec![json!(txid), json!(2)])
+                .await?;
+
+            let transaction = Self::transaction_of(txid, &rpc_result)?;
+            let status = self.status_of(txid, &rpc_result).await?;
+            let fee = match rpc_result.get("fee").and_then(|f| f.as_f64()) {
+                Some(btc) => Some(Amount::from_btc(btc).map_err(|e| {
+                    BlockchainError::DataInconsistency(format!(
+                        "Invalid fee {} for Txid {}: {}",
+                        btc, txid, e
+                    ))
+                })?),
+                // Coinbases pay none, but the node reports no fee for them
+                None if transaction.is_coinbase() => Some(Amount::ZERO),
+                None => None,
+            };
+            let tip = self.tip_height().await?;
+
+            let metadata = TxMetadata::new(&transaction, status, fee, tip);
+            Ok((transaction, metadata))
+        }
+        .await
+        .with_ctx(|| self.context("get_transaction_with_metadata").txid(txid))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::hashes::Hash;
+    use bitcoin::{Network, constants::genesis_block};
+    use wiremock::matchers::{body_partial_json, method};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn test_get_block_raw_round_trip() {
+        let server = MockServer::start().await;
+        let block = genesis_block(Network::Bitcoin);
+        let hash = block.block_hash();
+
+        Mock::given(method("POST"))
+            .and(body_partial_json(json!({
+                "method": "getblock",
+                "params": [hash, 0],
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "result": bitcoin::consensus::encode::serialize_hex(&block),
+                "error": null,
+                "id": 1,
+            })))
+            .mount(&server)
+            .await;
+
+        let client = BitcoinRpcClient::new(server.uri(), "user".into(), "pass".into());
+        let fetched = client.get_block_raw(hash).await.unwrap();
+
+        assert_eq!(fetched, block);
+    }
+
+    #[tokio::test]
+    async fn test_get_block_raw_bad_hex_names_block() {
+        let server = MockServer::start().await;
+        let hash = genesis_block(Network::Bitcoin).block_hash();
+
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "result": "deadbeef",
+                "error": null,
+                "id": 1,
+            })))
+            .mount(&server)
+            .await;
+
+        let client = BitcoinRpcClient::new(server.uri(), "user".into(), "pass".into());
+        match client.get_block_raw(hash).await {
+            Err(error) if matches!(error.inner(), BlockchainError::Decode { .. }) => {
+                assert!(error.is_decode());
+                assert!(error.to_string().contains(&hash.to_string()))
+            }
+            other => panic!("expected Decode, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_slow_node_is_a_timeout() {
+        let server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(5)))
+            .mount(&server)
+            .await;
+
+        let client = BitcoinRpcClient::new(server.uri(), "user".into(), "pass".into())
+            .timeout(Duration::from_millis(50));
+        let error = client.tip_height().await.unwrap_err();
+
+        assert!(error.is_timeout(), "{:?}", error);
+        assert!(error.is_retryable());
+        assert!(matches!(
+            error.inner(),
+            BlockchainError::Timeout { elapsed, url: Some(_) } if *elapsed >= Duration::from_millis(50)
+        ));
+        assert!(error.to_string().contains(&server.uri()));
+    }
+
+    #[tokio::test]
+    async fn test_missing_txindex_is_unsupported_with_a_hint() {
+        let server = MockServer::start().await;
+        let txid = genesis_block(Network::Bitcoin).txdata[0].compute_txid();
+
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "result": null,
+                "error": {
+                    "code": -5,
+                    "message": "No such mempool transaction. Use -txindex or provide a block hash to enable blockchain transaction queries. Use gettransaction for wallet transactions.",
+                },
+                "id": 1,
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = BitcoinRpcClient::new(server.uri(), "user".into(), "pass".into())
+            .retry(RetryPolicy::new(3).base_delay(Duration::from_millis(10)));
+        let error = client.get_transaction(txid).await.unwrap_err();
+
+        assert!(matches!(
+            error.inner(),
+            BlockchainError::UnsupportedOperation {
+                backend: "bitcoind",
+                ..
+            }
+        ));
+        assert!(!error.is_retryable());
+        assert!(error.hint().unwrap().contains("-txindex=1"));
+    }
+
+    #[tokio::test]
+    async fn test_get_transaction_status_reads_height_from_header() {
+        let server = MockServer::start().await;
+        let block = genesis_block(Network::Bitcoin);
+        let txid = block.txdata[0].compute_txid();
+        let hash = block.block_hash();
+
+        Mock::given(method("POST"))
+            .and(body_partial_json(json!({"method": "getrawtransaction"})))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "result": {"blockhash": hash, "blocktime": 1231006505},
+                "error": null,
+                "id": 1,
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(body_partial_json(json!({
+                "method": "getblockheader",
+                "params": [hash, true],
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "result": {"height": 0, "time": 1231006505},
+                "error": null,
+                "id": 1,
+            })))
+            .mount(&server)
+            .await;
+
+        let client = BitcoinRpcClient::new(server.uri(), "user".into(), "pass".into());
+        let status = client.get_transaction_status(txid).await.unwrap();
+
+        assert_eq!(
+            status,
+            TxStatus {
+                confirmed: true,
+                block_height: Some(0),
+                block_hash: Some(hash),
+                block_time: Some(1231006505),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_transaction_status_of_mempool_transaction() {
+        let server = MockServer::start().await;
+        let txid = genesis_block(Network::Bitcoin).txdata[0].compute_txid();
+
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "result": {"hex": "00"},
+                "error": null,
+                "id": 1,
+            })))
+            .mount(&server)
+            .await;
+
+        let client = BitcoinRpcClient::new(server.uri(), "user".into(), "pass".into());
+
+        assert_eq!(
+            client.get_transaction_status(txid).await.unwrap(),
+            TxStatus::unconfirmed()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_transaction_with_metadata() {
+        let server = MockServer::start().await;
+        let block = genesis_block(Network::Bitcoin);
+        let tx = &block.txdata[0];
+        let txid = tx.compute_txid();
+        let hash = block.block_hash();
+
+        Mock::given(method("POST"))
+            .and(body_partial_json(json!({
+                "method": "getrawtransaction",
+                "params": [txid, 2],
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "result": {
+                    "hex": bitcoin::consensus::encode::serialize_hex(tx),
+                    "blockhash": hash,
+                    "blocktime": 1231006505,
+                    "confirmations": 1,
+                },
+                "error": null,
+                "id": 1,
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(body_partial_json(json!({"method": "getblockheader"})))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "result": {"height": 0, "time": 1231006505},
+                "error": null,
+                "id": 1,
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(body_partial_json(json!({"method": "getblockcount"})))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "result": 100,
+                "error": null,
+                "id": 1,
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = BitcoinRpcClient::new(server.uri(), "user".into(), "pass".into());
+        let (fetched, metadata) = client.get_transaction_with_metadata(txid).await.unwrap();
+
+        assert_eq!(&fetched, tx);
+        // Confirmations come from the tip, not the node's count
+        assert_eq!(
+            metadata,
+            TxMetadata {
+                confirmed: true,
+                block_height: Some(0),
+                block_hash: Some(hash),
+                block_time: Some(1231006505),
+                confirmations: Some(101),
+                fee: Some(Amount::ZERO),
+                vsize: 204,
+                weight: bitcoin::Weight::from_wu(816),
+            }
+        );
+        let (_, again) = client.get_transaction_with_metadata(txid).await.unwrap();
+        assert_eq!(again, metadata);
+    }
+
+    #[tokio::test]
+    async fn test_get_transaction_with_metadata_reads_the_fee() {
+        let server = MockServer::start().await;
+        let mut tx = genesis_block(Network::Bitcoin).txdata[0].clone();
+        tx.input[0].previous_output = bitcoin::OutPoint::new(tx.compute_txid(), 0);
+        let txid = tx.compute_txid();
+
+        Mock::given(method("POST"))
+            .and(body_partial_json(json!({"method": "getrawtransaction"})))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "result": {
+                    "hex": bitcoin::consensus::encode::serialize_hex(&tx),
+                    "fee": 0.00012345,
+                },
+                "error": null,
+                "id": 1,
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(body_partial_json(json!({"method": "getblockcount"})))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "result": 100,
+                "error": null,
+                "id": 1,
+            })))
+            .mount(&server)
+            .await;
+
+        let client = BitcoinRpcClient::new(server.uri(), "user".into(), "pass".into());
+        let (_, metadata) = client.get_transaction_with_metadata(txid).await.unwrap();
+
+        assert_eq!(metadata.fee, Some(Amount::from_sat(12_345)));
+        assert!(!metadata.confirmed);
+        assert_eq!(metadata.confirmations, Some(0));
+    }
+
+    #[tokio::test]
+    async fn test_get_block_header_round_trip() {
+        let server = MockServer::start().await;
+        let header = genesis_block(Network::Bitcoin).header;
+        let hash = header.block_hash();
+
+        Mock::given(method("POST"))
+            .and(body_partial_json(json!({
+                "method": "getblockheader",
+                "params": [hash, false],
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "result": bitcoin::consensus::encode::serialize_hex(&header),
+                "error": null,
+                "id": 1,
+            })))
+            .mount(&server)
+            .await;
+
+        let client = BitcoinRpcClient::new(server.uri(), "user".into(), "pass".into());
+
+        assert_eq!(client.get_block_header(hash).await.unwrap(), header);
+    }
+
+    #[tokio::test]
+    async fn test_transactions_batch_is_one_request() {
+        let server = MockServer::start().await;
+        let tx = &genesis_block(Network::Bitcoin).txdata[0];
+        let txid = tx.compute_txid();
+        let unknown = bitcoin::Txid::from_byte_array([7; 32]);
+
+        // Answered out of order, as a node may
+        Mock::given(method("POST"))
+            .and(body_partial_json(json!([
+                {"method": "getrawtransaction", "params": [txid, 1], "id": 0},
+                {"method": "getrawtransaction", "params": [unknown, 1], "id": 1},
+            ])))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!([
+                {
+                    "result": null,
+                    "error": {"code": -5, "message": "No such mempool or blockchain transaction"},
+                    "id": 1,
+                },
+                {
+                    "result": {"hex": bitcoin::consensus::encode::serialize_hex(tx)},
+                    "error": null,
+                    "id": 0,
+                },
+            ])))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = BitcoinRpcClient::new(server.uri(), "user".into(), "pass".into());
+        let fetched = client
+            .get_transactions_batch(&[txid, unknown])
+            .await
+            .unwrap();
+        assert_eq!(fetched, vec![Some(tx.clone()), None]);
+        assert!(client.get_transactions_batch(&[]).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_transactions_batch_is_split_at_the_max_batch_size() {
+        let server = MockServer::start().await;
+        let tx = &genesis_block(Network::Bitcoin).txdata[0];
+        let txid = tx.compute_txid();
+        let unknown = bitcoin::Txid::from_byte_array([7; 32]);
+        let answer = |id: usize, result: Value| {
+            json!([{
+                "result": result,
+                "error": if result.is_null() {
+                    json!({"code": -5, "message": "No such mempool or blockchain transaction"})
+                } else {
+                    Value::Null
+                },
+                "id": id,
+            }])
+        };
+        // Each distinct txid once, one call a request
+        Mock::given(method("POST"))
+            .and(body_partial_json(json!([{"params": [txid, 1]}])))
+            .respond_with(ResponseTemplate::new(200).set_body_json(answer(
+                0,
+                json!({"hex": bitcoin::consensus::encode::serialize_hex(tx)}),
+            )))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(body_partial_json(json!([{"params": [unknown, 1]}])))
+            .respond_with(ResponseTemplate::new(200).set_body_json(answer(0, Value::Null)))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client =
+            BitcoinRpcClient::new(server.uri(), "user".into(), "pass".into()).max_batch_size(1);
+        let fetched = client
+            .get_transactions_batch(&[txid, unknown, txid])
+            .await
+            .unwrap();
+        assert_eq!(fetched, vec![Some(tx.clone()), None, Some(tx.clone())]);
     }
 }
@@ -0,0 +1,593 @@
+//! Client of bitcoind's REST interface, which reads without credentials.
+
+use crate::blockchain::{
+    BlockchainDataSource, BlockchainError, ErrorContext, Result, ResultExt, RetryPolicy, TipCache,
+    execute, redact,
+};
+use async_trait::async_trait;
+use bitcoin::{Address, Block, BlockHash, OutPoint, Transaction, Txid, block::Header};
+use serde::Deserialize;
+use std::time::{Duration, Instant};
+use tracing::{Instrument, Span, field};
+
+/// Name of this backend in `UnsupportedOperation` errors
+const BACKEND: &str = "bitcoind REST";
+
+/// Outpoints per `getutxos` request, the most bitcoind takes
+const GETUTXOS_MAX: usize = 15;
+
+/// bitcoind REST client used to retrieve blockchain data.
+///
+/// Bitcoin Core started with `-rest` serves transactions, blocks and UTXO set
+/// lookups under `/rest/` to anyone who can reach its RPC port, no credentials
+/// asked: it suits read-only tracing against a node whose RPC password should not
+/// be handed out.
+///
+/// # Limits
+/// - Confirmed transactions are found only with `-txindex`, or while their block
+///   is the one asked for
+/// - Blocks of a pruned node are found only if not pruned yet
+/// - bitcoind has no index of spenders or addresses: `is_spent` tells whether an
+///   output is spent, not by what, and address lookups are `UnsupportedOperation`
+///
+/// A node started without `-rest` answers every request with an empty 404, which
+/// fails with `UnsupportedOperation` rather than `NotFound`.
+pub struct BitcoindRestClient {
+    base_url: String,
+    client: reqwest::Client,
+    tip: TipCache,
+    retry: RetryPolicy,
+}
+
+/// The state of the node's chain, from `/rest/chaininfo.json`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ChainInfo {
+    /// "main", "test", "testnet4", "signet" or "regtest"
+    pub chain: String,
+    /// Height of the tip
+    pub blocks: u32,
+    #[serde(rename = "bestblockhash")]
+    pub best_block_hash: BlockHash,
+    /// Whether blocks below `prune_height` were deleted
+    #[serde(default)]
+    pub pruned: bool,
+    #[serde(default, rename = "pruneheight")]
+    pub prune_height: Option<u32>,
+}
+
+/// The answer of `/rest/getutxos`: bit `i` of `bitmap` is whether outpoint `i` is
+/// unspent.
+#[derive(Deserialize, Debug)]
+struct GetUtxosResponse {
+    bitmap: String,
+}
+
+impl BitcoindRestClient {
+    /// Creates a new bitcoind REST client
+    ///
+    /// # Arguments
+    /// * `base_url` - URL of the node's RPC port, without `/rest` (e.g.
+    ///   "http://127.0.0.1:8332")
+    ///
+    /// Automatically trims trailing slashes to ensure proper URL construction.
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self {
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            client: reqwest::Client::new(),
+            tip: TipCache::default(),
+            retry: RetryPolicy::default(),
+        }
+    }
+
+    /// Gives up on requests that get no full answer within `timeout`, failing with
+    /// an error `is_timeout` holds for (none unless set)
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.client = reqwest::Client::builder()
+            .timeout(timeout)
+            .build()
+            .expect("HTTP client with a timeout");
+        self
+    }
+
+    /// Retries of requests failing with a retryable error (none unless set)
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// How long the tip height is reused (`DEFAULT_TIP_MAX_AGE` unless set)
+    pub fn tip_max_age(mut self, max_age: Duration) -> Self {
+        self.tip = TipCache::new(max_age);
+        self
+    }
+
+    /// The state of the node's chain.
+    ///
+    /// Uses the `/rest/chaininfo.json` endpoint.
+    ///
+    /// # Errors
+    /// - `UnsupportedOperation` - The node was started without `-rest`
+    /// - `Decode` - Response is not valid chain info
+    pub async fn chain_info(&self) -> Result<ChainInfo> {
+        async {
+            let url = format!("{}/rest/chaininfo.json", self.base_url);
+            let body = self.get(&url, "chain_info").await?;
+            serde_json::from_slice(&body).map_err(|e| BlockchainError::decode("chain info", e))
+        }
+        .await
+        .with_ctx(|| self.context("chain_info"))
+    }
+
+    /// Height of the chain tip, as cached for the tip max age
+    pub async fn tip_height(&self) -> Result<u32> {
+        self.tip
+            .height(|| async { Ok(self.chain_info().await?.blocks) })
+            .await
+    }
+
+    /// Whether `outpoint` is missing from the UTXO set, the mempool's spends
+    /// counted: spent, or never created at all.
+    ///
+    /// Uses the `/rest/getutxos/checkmempool/{txid}-{vout}.json` endpoint.
+    pub async fn is_spent(&self, outpoint: OutPoint) -> Result<bool> {
+        Ok(!self.unspent(&[outpoint]).await?[0])
+    }
+
+    /// Whether each of `outpoints` is in the UTXO set, the mempool's spends counted,
+    /// asked `GETUTXOS_MAX` a request
+    pub async fn unspent(&self, outpoints: &[OutPoint]) -> Result<Vec<bool>> {
+        async {
+            let mut unspent = Vec::with_capacity(outpoints.len());
+            for chunk in outpoints.chunks(GETUTXOS_MAX) {
+                let path: Vec<String> = chunk
+                    .iter()
+                    .map(|outpoint| format!("{}-{}", outpoint.txid, outpoint.vout))
+                    .collect();
+                let url = format!(
+                    "{}/rest/getutxos/checkmempool/{}.json",
+                    self.base_url,
+                    path.join("/")
+                );
+                let body = self.get(&url, "getutxos").await?;
+                let response: GetUtxosResponse = serde_json::from_slice(&body)
+                    .map_err(|e| BlockchainError::decode("getutxos response", e))?;
+                if response.bitmap.len() != chunk.len() {
+                    return Err(BlockchainError::DataInconsistency(format!(
+                        "getutxos bitmap {:?} does not cover {} outpoints",
+                        response.bitmap,
+                        chunk.len()
+                    )));
+                }
+                unspent.extend(response.bitmap.chars().map(|bit| bit == '1'));
+            }
+            Ok(unspent)
+        }
+        .await
+        .with_ctx(|| self.context("unspent"))
+    }
+
+    /// Context of the errors of `operation`, naming this backend
+    fn context(&self, operation: &'static str) -> ErrorContext {
+        ErrorContext::new(operation).url(&self.base_url)
+    }
+
+    /// Body of `url`, tried again as the retry policy allows: binary, unlike the
+    /// hex of RPC and the other backends, for `.bin` endpoints.
+    ///
+    /// # Errors
+    /// - `Request` - HTTP request failed
+    /// - `Timeout` - No answer within the timeout
+    /// - `NotFound` - HTTP 404 saying what was not found
+    /// - `UnsupportedOperation` - An empty 404 for `operation`: REST is off
+    /// - `NetworkFailure` - Any other HTTP error status
+    async fn get(&self, url: &str, operation: &'static str) -> Result<Vec<u8>> {
+        execute(&self.retry, || {
+            let span = tracing::debug_span!(
+                "http_request",
+                method = "GET",
+                url = %redact::url(url),
+                status = field::Empty,
+                elapsed_ms = field::Empty,
+            );
+            self.attempt(url, operation).instrument(span)
+        })
+        .await
+    }
+
+    /// One GET of `url`, as `get` makes them, recording its status and elapsed time
+    /// on the current span
+    async fn attempt(&self, url: &str, operation: &'static str) -> Result<Vec<u8>> {
+        let sent = Instant::now();
+        let sent_response = self.client.get(url).send().await;
+        let elapsed = sent.elapsed();
+        let span = Span::current();
+        span.record("elapsed_ms", elapsed.as_millis() as u64);
+        let response = match sent_response {
+            Ok(response) => response,
+            Err(e) => {
+                tracing::debug!(timeout = e.is_timeout(), "no response");
+                return Err(BlockchainError::request_after(url, e, elapsed));
+            }
+        };
+
+        let status = response.status();
+        span.record("status", status.as_u16());
+        tracing::debug!("response");
+        let body = response
+            .bytes()
+            .await
+            .map_err(|e| BlockchainError::request(url, e))?
+            .to_vec();
+        if status.is_success() {
+            return Ok(body);
+        }
+        let message = String::from_utf8_lossy(&body).trim().to_string();
+        match status.as_u16() {
+            // bitcoind answers paths it has no handler for with an empty 404
+            404 if message.is_empty() => Err(BlockchainError::unsupported(
+                BACKEND,
+                operation,
+                "start bitcoind with -rest=1",
+            )),
+            // "<txid> not found", "<hash> not available (pruned data)"
+            404 => Err(BlockchainError::NotFound(message)),
+            _ => Err(BlockchainError::NetworkFailure(format!(
+                "HTTP {} for {}: {}",
+                status, url, message
+            ))),
+        }
+    }
+}
+
+/// What asking REST for the spender of a spent output fails with
+fn spender_unsupported() -> BlockchainError {
+    BlockchainError::unsupported(
+        BACKEND,
+        "get_spending_transaction",
+        "use an Esplora or Electrum backend to look up the spender of a spent output",
+    )
+}
+
+#[async_trait]
+impl BlockchainDataSource for BitcoindRestClient {
+    /// Fetches a transaction by its txid.
+    ///
+    /// Uses the `/rest/tx/{txid}.bin` endpoint, which answers with the consensus
+    /// encoded transaction. Confirmed transactions are found with `-txindex` only.
+    ///
+    /// # Errors
+    /// - `Request` - HTTP request failed
+    /// - `NotFound` - Transaction not found
+    /// - `UnsupportedOperation` - The node was started without `-rest`
+    /// - `Decode` - Body is not a valid transaction
+    async fn get_transaction(&self, txid: Txid) -> Result<Transaction> {
+        async {
+            let url = format!("{}/rest/tx/{}.bin", self.base_url, txid);
+            let body = self
+                .get(&url, "get_transaction")
+                .await
+                .map_err(|e| match e {
+                    BlockchainError::NotFound(_) => BlockchainError::NotFound(format!(
+                        "Transaction {} not found (confirmed ones need -txindex)",
+                        txid
+                    )),
+                    e => e,
+                })?;
+            bitcoin::consensus::deserialize(&body)
+                .map_err(|e| BlockchainError::decode(format!("transaction {}", txid), e))
+        }
+        .await
+        .with_ctx(|| self.context("get_transaction").txid(txid))
+    }
+
+    /// Tells unspent outputs apart with `is_spent`; the spender of a spent one is
+    /// beyond REST, which fails with `UnsupportedOperation`.
+    async fn get_spending_transaction(&self, outpoint: OutPoint) -> Result<Option<Transaction>> {
+        async {
+            match self.is_spent(outpoint).await? {
+                false => Ok(None),
+                true => Err(spender_unsupported()),
+            }
+        }
+        .await
+        .with_ctx(|| self.context("get_spending_transaction").outpoint(outpoint))
+    }
+
+    async fn get_address_transactions(&self, _address: Address) -> Result<Vec<Transaction>> {
+        // bitcoind keeps no index of addresses
+        Err(BlockchainError::unsupported(
+            BACKEND,
+            "get_address_transactions",
+            "use an Esplora or Electrum backend to look up the history of an address",
+        ))
+        .with_ctx(|| self.context("get_address_transactions"))
+    }
+
+    async fn get_address_utxos(&self, _address: Address) -> Result<Vec<OutPoint>> {
+        Err(BlockchainError::unsupported(
+            BACKEND,
+            "get_address_utxos",
+            "use an Esplora or Electrum backend to list the outputs of an address",
+        ))
+        .with_ctx(|| self.context("get_address_utxos"))
+    }
+
+    /// `None` for each unspent output, asked of `getutxos` together; any spent one
+    /// fails with `UnsupportedOperation`, as `get_spending_transaction` does.
+    async fn get_spending_transactions_batch(
+        &self,
+        outpoints: &[OutPoint],
+    ) -> Result<Vec<Option<Transaction>>> {
+        let unspent = self.unspent(outpoints).await?;
+        match unspent.iter().position(|unspent| !unspent) {
+            Some(spent) => Err(spender_unsupported()).with_ctx(|| {
+                self.context("get_spending_transactions_batch")
+                    .outpoint(outpoints[spent])
+            }),
+            None => Ok(vec![None; outpoints.len()]),
+        }
+    }
+
+    /// Asks `getutxos` alone, with no spender to find: as `BitcoindRestClient::is_spent`,
+    /// an output never created counts as spent.
+    async fn is_spent(&self, outpoint: OutPoint) -> Result<bool> {
+        BitcoindRestClient::is_spent(self, outpoint).await
+    }
+
+    /// Fetches a full block by its hash.
+    ///
+    /// Uses the `/rest/block/{hash}.bin` endpoint, which returns the consensus
+    /// encoded block as binary.
+    ///
+    /// # Errors
+    /// - `Request` - HTTP request failed
+    /// - `NotFound` - Block not found, or pruned
+    /// - `UnsupportedOperation` - The node was started without `-rest`
+    /// - `Decode` - Body is not a valid consensus-encoded block
+    async fn get_block_raw(&self, block_hash: BlockHash) -> Result<Block> {
+        async {
+            let url = format!("{}/rest/block/{}.bin", self.base_url, block_hash);
+            let body = self.get(&url, "get_block_raw").await?;
+            bitcoin::consensus::deserialize(&body)
+                .map_err(|e| BlockchainError::decode(format!("block {}", block_hash), e))
+        }
+        .await
+        .with_ctx(|| self.context("get_block_raw"))
+    }
+
+    /// Fetches the header of a block by its hash.
+    ///
+    /// Uses the `/rest/headers/{hash}.bin?count=1` endpoint, which answers with no
+    /// headers at all for unknown blocks.
+    ///
+    /// # Errors
+    /// - `Request` - HTTP request failed
+    /// - `NotFound` - Block not found
+    /// - `UnsupportedOperation` - The node was started without `-rest`
+    /// - `Decode` - Body is not a valid header
+    async fn get_block_header(&self, block_hash: BlockHash) -> Result<Header> {
+        async {
+            let url = format!("{}/rest/headers/{}.bin?count=1", self.base_url, block_hash);
+            let body = self.get(&url, "get_block_header").await?;
+            if body.is_empty() {
+                return Err(BlockchainError::NotFound(format!(
+                    "Block {} not found",
+                    block_hash
+                )));
+            }
+            bitcoin::consensus::deserialize(&body)
+                .map_err(|e| BlockchainError::decode(format!("header of block {}", block_hash), e))
+        }
+        .await
+        .with_ctx(|| self.context("get_block_header"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::hashes::Hash;
+    use bitcoin::{Network, constants::genesis_block};
+    use wiremock::matchers::{method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    /// Fixture `name` of `testdata/bitcoind_rest`
+    fn fixture(name: &str) -> Vec<u8> {
+        let path = format!(
+            "{}/src/blockchain/testdata/bitcoind_rest/{}",
+            env!("CARGO_MANIFEST_DIR"),
+            name
+        );
+        std::fs::read(&path).unwrap_or_else(|e| panic!("{}: {}", path, e))
+    }
+
+    async fn serve(server: &MockServer, at: &str, status: u16, body: Vec<u8>) {
+        Mock::given(method("GET"))
+            .and(path(at))
+            .respond_with(ResponseTemplate::new(status).set_body_bytes(body))
+            .mount(server)
+            .await;
+    }
+
+    #[tokio::test]
+    async fn test_binary_transactions_blocks_and_headers_decode() {
+        let server = MockServer::start().await;
+        let genesis = genesis_block(Network::Bitcoin);
+        let hash = genesis.block_hash();
+        let coinbase = genesis.txdata[0].compute_txid();
+        serve(
+            &server,
+            &format!("/rest/tx/{}.bin", coinbase),
+            200,
+            fixture("genesis_coinbase.bin"),
+        )
+        .await;
+        serve(
+            &server,
+            &format!("/rest/block/{}.bin", hash),
+            200,
+            fixture("genesis_block.bin"),
+        )
+        .await;
+        Mock::given(method("GET"))
+            .and(path(format!("/rest/headers/{}.bin", hash)))
+            .and(query_param("count", "1"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(fixture("genesis_header.bin")))
+            .mount(&server)
+            .await;
+        let client = BitcoindRestClient::new(server.uri());
+
+        let tx = client.get_transaction(coinbase).await.unwrap();
+        assert_eq!(tx, genesis.txdata[0]);
+        let block = client.get_block_raw(hash).await.unwrap();
+        assert_eq!(block, genesis);
+        let header = client.get_block_header(hash).await.unwrap();
+        assert_eq!(header, genesis.header);
+
+        // Binary, not hex: a hex body does not decode
+        let other = Txid::from_byte_array([7; 32]);
+        let hex = bitcoin::consensus::encode::serialize_hex(&genesis.txdata[0]);
+        serve(
+            &server,
+            &format!("/rest/tx/{}.bin", other),
+            200,
+            hex.into_bytes(),
+        )
+        .await;
+        let err = client.get_transaction(other).await.unwrap_err();
+        assert!(err.is_decode(), "{err}");
+    }
+
+    #[tokio::test]
+    async fn test_missing_data_is_not_found_and_disabled_rest_unsupported() {
+        let server = MockServer::start().await;
+        let txid = Txid::from_byte_array([1; 32]);
+        let pruned = BlockHash::from_byte_array([2; 32]);
+        serve(
+            &server,
+            &format!("/rest/tx/{}.bin", txid),
+            404,
+            format!("{} not found\r\n", txid).into_bytes(),
+        )
+        .await;
+        serve(
+            &server,
+            &format!("/rest/block/{}.bin", pruned),
+            404,
+            format!("{} not available (pruned data)\r\n", pruned).into_bytes(),
+        )
+        .await;
+        let client = BitcoindRestClient::new(server.uri());
+
+        let err = client.get_transaction(txid).await.unwrap_err();
+        assert!(matches!(err.inner(), BlockchainError::NotFound(_)), "{err}");
+        assert!(err.to_string().contains("-txindex"), "{err}");
+        let batch = client.get_transactions_batch(&[txid]).await.unwrap();
+        assert_eq!(batch, [None]);
+        let err = client.get_block_raw(pruned).await.unwrap_err();
+        assert!(err.to_string().contains("pruned data"), "{err}");
+
+        // Without -rest, bitcoind has no handler for the path: an empty 404
+        let disabled = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(404))
+            .mount(&disabled)
+            .await;
+        let client = BitcoindRestClient::new(disabled.uri());
+        let err = client.get_transaction(txid).await.unwrap_err();
+        assert!(
+            matches!(
+                err.inner(),
+                BlockchainError::UnsupportedOperation {
+                    operation: "get_transaction",
+                    ..
+                }
+            ),
+            "{err}"
+        );
+        assert!(err.to_string().contains("-rest=1"), "{err}");
+        assert!(client.chain_info().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_getutxos_tells_spent_outputs_apart() {
+        let server = MockServer::start().await;
+        let unspent: OutPoint =
+            "0e3e2357e806b6cdb1f70b54c3a3a17b6714ee1f0e68bebb44a74b1efd512098:0"
+                .parse()
+                .unwrap();
+        let genesis = OutPoint::new(genesis_block(Network::Bitcoin).txdata[0].compute_txid(), 0);
+        serve(
+            &server,
+            &format!(
+                "/rest/getutxos/checkmempool/{}-0/{}-0.json",
+                unspent.txid, genesis.txid
+            ),
+            200,
+            fixture("getutxos.json"),
+        )
+        .await;
+        serve(
+            &server,
+            "/rest/chaininfo.json",
+            200,
+            fixture("chaininfo.json"),
+        )
+        .await;
+        let client = BitcoindRestClient::new(server.uri());
+
+        assert_eq!(
+            client.unspent(&[unspent, genesis]).await.unwrap(),
+            [true, false]
+        );
+        let err = client
+            .get_spending_transactions_batch(&[unspent, genesis])
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(
+                err.inner(),
+                BlockchainError::UnsupportedOperation {
+                    operation: "get_spending_transaction",
+                    ..
+                }
+            ),
+            "{err}"
+        );
+        let address = Address::p2pkh(
+            bitcoin::PubkeyHash::from_byte_array([0; 20]),
+            Network::Bitcoin,
+        );
+        let err = client.get_address_transactions(address).await.unwrap_err();
+        assert!(
+            matches!(err.inner(), BlockchainError::UnsupportedOperation { .. }),
+            "{err}"
+        );
+
+        let info = client.chain_info().await.unwrap();
+        assert_eq!(info.chain, "main");
+        assert!(info.pruned);
+        assert_eq!(info.prune_height, Some(797_743));
+        assert_eq!(client.tip_height().await.unwrap(), 800_000);
+    }
+
+    #[cfg(feature = "cache")]
+    #[tokio::test]
+    async fn test_is_spent_is_asked_of_getutxos_through_the_cache() {
+        let server = MockServer::start().await;
+        let genesis = OutPoint::new(genesis_block(Network::Bitcoin).txdata[0].compute_txid(), 0);
+        serve(
+            &server,
+            &format!("/rest/getutxos/checkmempool/{}-0.json", genesis.txid),
+            200,
+            br#"{"chainHeight": 800000, "chaintipHash": "00000000000000000002a7c4c1e48d76c5a37902165a270156b7a8d72728a054", "bitmap": "0", "utxos": []}"#.to_vec(),
+        )
+        .await;
+        let cached =
+            crate::blockchain::CachingDataSource::builder(BitcoindRestClient::new(server.uri()))
+                .build();
+
+        // Without getutxos, the default would look for the spender bitcoind cannot find
+        assert!(cached.is_spent(genesis).await.unwrap());
+    }
+}
@@ -0,0 +1,692 @@
+use crate::blockchain::{
+    BlockchainDataSource, BlockchainError, ErrorContext, Result, ResultExt, RetryPolicy,
+    TxMetadata, TxStatus, batch, execute, redact,
+};
+use crate::time::Instant;
+use async_trait::async_trait;
+use bitcoin::{Address, Amount, BlockHash, OutPoint, Transaction, Txid};
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::time::Duration;
+use tracing::{Instrument, Span, field};
+
+/// Transactions per page of an address history, the most Blockbook serves
+const ADDRESS_PAGE_SIZE: usize = 1000;
+
+/// Blockbook HTTP client used to retrieve blockchain data.
+///
+/// This client talks to the v2 API of a Blockbook indexer, as Trezor runs them
+/// (e.g. "https://btc1.trezor.io"). Blockbook's transaction JSON names the spender
+/// of each spent output, so spenders need no lookup of their own.
+///
+/// Fields differ across Blockbook versions: the raw `hex` and `spentTxId` are read
+/// when there, the raw transaction otherwise fetched from `/api/v2/tx-specific`.
+///
+/// # Rate Limiting
+/// As with `EsploraClient`, wrap it in a `RateLimitedDataSource` for public
+/// instances; answers of HTTP 429 fail with `RateLimited`.
+pub struct BlockbookClient {
+    base_url: String,
+    client: reqwest::Client,
+    timeout: Option<Duration>,
+    retry: RetryPolicy,
+}
+
+impl BlockbookClient {
+    /// Creates a new Blockbook client
+    ///
+    /// # Arguments
+    /// * `base_url` - Base URL of the Blockbook instance, without `/api` (e.g.
+    ///   "https://btc1.trezor.io")
+    ///
+    /// Automatically trims trailing slashes to ensure proper URL construction.
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self {
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            client: reqwest::Client::new(),
+            timeout: None,
+            retry: RetryPolicy::default(),
+        }
+    }
+
+    /// Gives up on requests that get no full answer within `timeout`, failing with
+    /// an error `is_timeout` holds for (none unless set)
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Retries of requests failing with a retryable error (none unless set)
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Context of the errors of `operation`, naming this backend
+    fn context(&self, operation: &'static str) -> ErrorContext {
+        ErrorContext::new(operation).url(&self.base_url)
+    }
+
+    /// Blockbook's JSON of transaction `txid`, spenders of its outputs included
+    ///
+    /// Uses the `/api/v2/tx/{txid}` endpoint.
+    async fn details(&self, txid: Txid) -> Result<TxResponse> {
+        let url = format!("{}/api/v2/tx/{}", self.base_url, txid);
+        self.get_json(&url, || format!("transaction {}", txid))
+            .await?
+            .ok_or_else(|| BlockchainError::NotFound(format!("Transaction {} not found", txid)))
+    }
+
+    /// The transaction `details` describe, decoded from their hex or, from versions
+    /// leaving it out, from that of `/api/v2/tx-specific/{txid}`
+    async fn transaction_of(&self, details: TxResponse) -> Result<Transaction> {
+        let txid = details.txid;
+        let hex = match details.hex {
+            Some(hex) => hex,
+            None => {
+                let url = format!("{}/api/v2/tx-specific/{}", self.base_url, txid);
+                let specific: TxSpecificResponse = self
+                    .get_json(&url, || format!("transaction {}", txid))
+                    .await?
+                    .ok_or_else(|| {
+                        BlockchainError::NotFound(format!("Transaction {} not found", txid))
+                    })?;
+                specific.hex
+            }
+        };
+        bitcoin::consensus::encode::deserialize_hex(&hex)
+            .map_err(|e| BlockchainError::decode(format!("transaction {}", txid), e))
+    }
+
+    /// GET `url` and decode its JSON as `what` names it; `None` if nothing is there
+    async fn get_json<T: DeserializeOwned>(
+        &self,
+        url: &str,
+        what: impl FnOnce() -> String,
+    ) -> Result<Option<T>> {
+        let Some(response) = self.get(url).await? else {
+            return Ok(None);
+        };
+        response
+            .json()
+            .await
+            .map(Some)
+            .map_err(|e| BlockchainError::decode(what(), e))
+    }
+
+    /// GET `url`, tried again as the retry policy allows.
+    ///
+    /// # Returns
+    /// - `Ok(Some(response))` - The response, its status a success
+    /// - `Ok(None)` - Nothing at `url`: a 404, or the 400 Blockbook answers unknown
+    ///   transactions and addresses with
+    ///
+    /// # Errors
+    /// - `Request` - HTTP request failed
+    /// - `Timeout` - No answer within the timeout
+    /// - `RateLimited` - HTTP 429, with the wait `Retry-After` asks for
+    /// - `NetworkFailure` - Any other HTTP error status
+    async fn get(&self, url: &str) -> Result<Option<reqwest::Response>> {
+        execute(&self.retry, || {
+            let span = tracing::debug_span!(
+                "http_request",
+                method = "GET",
+                url = %redact::url(url),
+                status = field::Empty,
+                elapsed_ms = field::Empty,
+            );
+            self.attempt(url).instrument(span)
+        })
+        .await
+    }
+
+    /// One GET of `url`, as `get` makes them, recording its status and elapsed time
+    /// on the current span
+    async fn attempt(&self, url: &str) -> Result<Option<reqwest::Response>> {
+        let sent = Instant::now();
+        let mut request = self.client.get(url);
+        if let Some(timeout) = self.timeout {
+            request = request.timeout(timeout);
+        }
+        let sent_response = request.send().await;
+        let elapsed = sent.elapsed();
+        let span = Span::current();
+        span.record("elapsed_ms", elapsed.as_millis() as u64);
+        let response = match sent_response {
+            Ok(response) => response,
+            Err(e) => {
+                tracing::debug!(timeout = e.is_timeout(), "no response");
+                return Err(BlockchainError::request_after(url, e, elapsed));
+            }
+        };
+
+        let status = response.status();
+        span.record("status", status.as_u16());
+        tracing::debug!("response");
+        if status == 404 {
+            return Ok(None);
+        }
+        if status == 429 {
+            let retry_after = response
+                .headers()
+                .get(reqwest::header::RETRY_AFTER)
+                .and_then(|value| value.to_str().ok()?.trim().parse().ok())
+                .map(Duration::from_secs);
+            return Err(BlockchainError::RateLimited { retry_after });
+        }
+        if !status.is_success() {
+            let body = response
+                .text()
+                .await
+                .unwrap_or_else(|_| "Failed to read body".to_string());
+            // {"error": "Transaction '...' not found"}
+            if status == 400 && body.to_lowercase().contains("not found") {
+                return Ok(None);
+            }
+            return Err(BlockchainError::NetworkFailure(format!(
+                "HTTP {} for {}: {}",
+                status, url, body
+            )));
+        }
+        Ok(Some(response))
+    }
+}
+
+/// The fields of Blockbook's transaction JSON this client reads, all but the txid
+/// optional, as versions and mempool transactions leave some out.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct TxResponse {
+    txid: Txid,
+    #[serde(default)]
+    vout: Vec<VoutResponse>,
+    /// Absent while unconfirmed
+    #[serde(default)]
+    block_hash: Option<BlockHash>,
+    /// -1 (or absent) while unconfirmed
+    #[serde(default)]
+    block_height: Option<i64>,
+    /// Time of the block, or when the mempool first saw the transaction
+    #[serde(default)]
+    block_time: Option<u64>,
+    #[serde(default)]
+    confirmations: u32,
+    /// Fee in sats, as a decimal string
+    #[serde(default)]
+    fees: Option<String>,
+    /// Raw transaction, which older versions leave out
+    #[serde(default)]
+    hex: Option<String>,
+}
+
+impl TxResponse {
+    /// Confirmation status, as `TxStatus` has it
+    fn status(&self) -> TxStatus {
+        let height = self
+            .block_height
+            .and_then(|height| u32::try_from(height).ok());
+        match (self.confirmations, height) {
+            (1.., Some(height)) => TxStatus {
+                confirmed: true,
+                block_height: Some(height),
+                block_hash: self.block_hash,
+                block_time: self.block_time,
+            },
+            _ => TxStatus::unconfirmed(),
+        }
+    }
+}
+
+/// An output of Blockbook's transaction JSON: `spent` is left out of unspent ones,
+/// `spentTxId` out of those of versions without spending details.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct VoutResponse {
+    #[serde(default)]
+    n: Option<u32>,
+    #[serde(default)]
+    spent: bool,
+    #[serde(default)]
+    spent_tx_id: Option<Txid>,
+}
+
+/// The raw transaction of the `/api/v2/tx-specific` JSON, bitcoind's verbose one.
+#[derive(Deserialize, Debug)]
+struct TxSpecificResponse {
+    hex: String,
+}
+
+/// A page of Blockbook's address JSON, with `details=txids`. Addresses with no
+/// transactions have no `txids`.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct AddressResponse {
+    #[serde(default)]
+    total_pages: u32,
+    #[serde(default)]
+    txids: Vec<Txid>,
+}
+
+/// An entry of Blockbook's UTXO JSON; unconfirmed ones have no height.
+#[derive(Deserialize, Debug)]
+struct UtxoResponse {
+    txid: Txid,
+    vout: u32,
+    #[serde(default)]
+    height: Option<u32>,
+}
+
+/// The spender of output `vout` of the transaction `details` describe, which has
+/// none if unspent
+fn spender_of(details: &TxResponse, outpoint: OutPoint) -> Result<Option<Txid>> {
+    let output = details
+        .vout
+        .iter()
+        .enumerate()
+        .find(|(position, output)| output.n.unwrap_or(*position as u32) == outpoint.vout)
+        .map(|(_, output)| output)
+        .ok_or_else(|| BlockchainError::NotFound(format!("Output {} not found", outpoint)))?;
+    match (output.spent, output.spent_tx_id) {
+        (_, Some(txid)) => Ok(Some(txid)),
+        (false, None) => Ok(None),
+        (true, None) => Err(BlockchainError::DataInconsistency(format!(
+            "Output {} marked spent but no spentTxId returned",
+            outpoint
+        ))),
+    }
+}
+
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+impl BlockchainDataSource for BlockbookClient {
+    /// Fetches a transaction by its txid.
+    ///
+    /// Uses the `/api/v2/tx/{txid}` endpoint, decoding its `hex`, or that of
+    /// `/api/v2/tx-specific/{txid}` when the version leaves it out.
+    ///
+    /// # Errors
+    /// - `Request` - HTTP request failed
+    /// - `RateLimited` - HTTP 429
+    /// - `NetworkFailure` - HTTP error status
+    /// - `NotFound` - Transaction not found
+    /// - `Decode` - Invalid JSON or hex, or deserialization failure
+    async fn get_transaction(&self, txid: Txid) -> Result<Transaction> {
+        async {
+            let details = self.details(txid).await?;
+            self.transaction_of(details).await
+        }
+        .await
+        .with_ctx(|| self.context("get_transaction").txid(txid))
+    }
+
+    /// Finds the transaction that spends a specific OutPoint.
+    ///
+    /// The `/api/v2/tx/{txid}` JSON of the outpoint's transaction names the spender
+    /// of each spent output (`spentTxId`), which is then fetched: no outspend
+    /// lookup is needed.
+    ///
+    /// # Returns
+    /// - `Ok(Some(tx))` - The transaction that spent this outpoint
+    /// - `Ok(None)` - The outpoint is still unspent
+    /// - `Err(NotFound)` - The original transaction or its output doesn't exist
+    /// - `Err(DataInconsistency)` - Output marked spent without a spender, as
+    ///   versions without spending details answer
+    async fn get_spending_transaction(&self, outpoint: OutPoint) -> Result<Option<Transaction>> {
+        async {
+            let details = self.details(outpoint.txid).await?;
+            match spender_of(&details, outpoint)? {
+                Some(txid) => self.get_transaction(txid).await.map(Some),
+                None => Ok(None),
+            }
+        }
+        .await
+        .with_ctx(|| self.context("get_spending_transaction").outpoint(outpoint))
+    }
+
+    /// Fetches every transaction paying or spending from an address, unconfirmed
+    /// ones first, then the confirmed ones newest first.
+    ///
+    /// Uses the `/api/v2/address/{address}?details=txids` endpoint for the txids,
+    /// `ADDRESS_PAGE_SIZE` a page, then fetches each transaction by its txid.
+    ///
+    /// # Errors
+    /// - `Request` - HTTP request failed
+    /// - `RateLimited` - HTTP 429
+    /// - `NetworkFailure` - HTTP error status
+    /// - `NotFound` - Address or one of its transactions not found
+    /// - `Decode` - A page is not valid JSON, or a transaction does not decode
+    async fn get_address_transactions(&self, address: Address) -> Result<Vec<Transaction>> {
+        async {
+            let mut txids = Vec::new();
+            let mut page = 1;
+            loop {
+                let url = format!(
+                    "{}/api/v2/address/{}?details=txids&page={}&pageSize={}",
+                    self.base_url, address, page, ADDRESS_PAGE_SIZE
+                );
+                let response: AddressResponse = self
+                    .get_json(&url, || format!("history of address {}", address))
+                    .await?
+                    .ok_or_else(|| {
+                        BlockchainError::NotFound(format!("Address {} not found", address))
+                    })?;
+                txids.extend(response.txids);
+                if page >= response.total_pages {
+                    break;
+                }
+                page += 1;
+            }
+
+            let mut transactions = Vec::with_capacity(txids.len());
+            for txid in txids {
+                transactions.push(self.get_transaction(txid).await?);
+            }
+            Ok(transactions)
+        }
+        .await
+        .with_ctx(|| self.context("get_address_transactions"))
+    }
+
+    /// Lists the unspent outputs paying an address, oldest transaction first.
+    ///
+    /// Uses the `/api/v2/utxo/{address}` endpoint, which lists them without the
+    /// transactions, newest first.
+    async fn get_address_utxos(&self, address: Address) -> Result<Vec<OutPoint>> {
+        async {
+            let url = format!("{}/api/v2/utxo/{}", self.base_url, address);
+            let mut utxos: Vec<UtxoResponse> = self
+                .get_json(&url, || format!("unspent outputs of address {}", address))
+                .await?
+                .ok_or_else(|| {
+                    BlockchainError::NotFound(format!("Address {} not found", address))
+                })?;
+            // Confirmed by height, then unconfirmed
+            utxos.sort_by_key(|utxo| (utxo.height.is_none(), utxo.height));
+            Ok(utxos
+                .into_iter()
+                .map(|utxo| OutPoint::new(utxo.txid, utxo.vout))
+                .collect())
+        }
+        .await
+        .with_ctx(|| self.context("get_address_utxos"))
+    }
+
+    /// Finds the spenders of `outpoints`, each distinct one once, fetching the JSON
+    /// of each of their transactions once however many of its outputs are asked
+    /// about.
+    async fn get_spending_transactions_batch(
+        &self,
+        outpoints: &[OutPoint],
+    ) -> Result<Vec<Option<Transaction>>> {
+        batch::run(outpoints, usize::MAX, |outpoints| async move {
+            let mut details = HashMap::new();
+            let mut spenders = Vec::with_capacity(outpoints.len());
+            for outpoint in outpoints {
+                let spender = async {
+                    let funding = match details.entry(outpoint.txid) {
+                        Entry::Occupied(entry) => entry.into_mut(),
+                        Entry::Vacant(entry) => entry.insert(self.details(outpoint.txid).await?),
+                    };
+                    match spender_of(funding, outpoint)? {
+                        Some(txid) => self.get_transaction(txid).await.map(Some),
+                        None => Ok(None),
+                    }
+                }
+                .await
+                .with_ctx(|| {
+                    self.context("get_spending_transactions_batch")
+                        .outpoint(outpoint)
+                })?;
+                spenders.push(spender);
+            }
+            Ok(spenders)
+        })
+        .await
+    }
+
+    /// Fetches the confirmation status of a transaction.
+    ///
+    /// Uses the `/api/v2/tx/{txid}` endpoint: mempool transactions have a height of
+    /// -1 and no confirmations.
+    ///
+    /// # Errors
+    /// - `Request` - HTTP request failed
+    /// - `RateLimited` - HTTP 429
+    /// - `NetworkFailure` - HTTP error status
+    /// - `NotFound` - Transaction not found
+    /// - `Decode` - Response is not valid JSON
+    async fn get_transaction_status(&self, txid: Txid) -> Result<TxStatus> {
+        async { Ok(self.details(txid).await?.status()) }
+            .await
+            .with_ctx(|| self.context("get_transaction_status").txid(txid))
+    }
+
+    /// Fetches a transaction with its status, fee and size.
+    ///
+    /// Uses the `/api/v2/tx/{txid}` endpoint, whose JSON counts the confirmations
+    /// itself: no tip height is needed.
+    ///
+    /// # Errors
+    /// As `get_transaction`
+    async fn get_transaction_with_metadata(&self, txid: Txid) -> Result<(Transaction, TxMetadata)> {
+        async {
+            let details = self.details(txid).await?;
+            let status = details.status();
+            let confirmations = details.confirmations;
+            let fee = details
+                .fees
+                .as_deref()
+                .and_then(|fees| fees.parse().ok())
+                .map(Amount::from_sat);
+            let tx = self.transaction_of(details).await?;
+
+            let tip = status
+                .block_height
+                .map_or(0, |height| height + confirmations.saturating_sub(1));
+            let metadata = TxMetadata::new(&tx, status, fee, tip);
+            Ok((tx, metadata))
+        }
+        .await
+        .with_ctx(|| self.context("get_transaction_with_metadata").txid(txid))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    const FUNDING: &str = "148dfeac2b4eaf2423401cdf820df90bfb1dabd48cd875b36df7c668a37fc5e8";
+    const HOP0: &str = "ce26aad4ed05969f4cc4d207394b409e88007d8ad972acefa0cb578bf382b398";
+    const HOP1: &str = "7a20f9ac60dccf35b49c5c004adf927082f6311c57b7e71fca4422d9a0904097";
+
+    /// Fixture `name` of `testdata/blockbook`
+    fn fixture(name: &str) -> String {
+        let path = format!(
+            "{}/src/blockchain/testdata/blockbook/{}",
+            env!("CARGO_MANIFEST_DIR"),
+            name
+        );
+        std::fs::read_to_string(&path).unwrap_or_else(|e| panic!("{}: {}", path, e))
+    }
+
+    fn txid(hex: &str) -> Txid {
+        hex.parse().unwrap()
+    }
+
+    fn address(address: &str) -> Address {
+        address.parse::<Address<_>>().unwrap().assume_checked()
+    }
+
+    /// Blockbook serving the fixtures of a funding transaction and a peel chain of
+    /// two hops spending it, the last still in the mempool
+    async fn server() -> MockServer {
+        let server = MockServer::start().await;
+        for (txid, name) in [
+            (FUNDING, "tx_funding.json"),
+            (HOP0, "tx_hop0.json"),
+            (HOP1, "tx_hop1.json"),
+        ] {
+            Mock::given(method("GET"))
+                .and(path(format!("/api/v2/tx/{}", txid)))
+                .respond_with(ResponseTemplate::new(200).set_body_string(fixture(name)))
+                .mount(&server)
+                .await;
+        }
+        Mock::given(method("GET"))
+            .and(path(format!("/api/v2/tx-specific/{}", HOP0)))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_string(fixture("tx_specific_hop0.json")),
+            )
+            .mount(&server)
+            .await;
+        // Unknown transactions are a 400, not a 404
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(400).set_body_string(fixture("not_found.json")))
+            .with_priority(u8::MAX)
+            .mount(&server)
+            .await;
+        server
+    }
+
+    #[tokio::test]
+    async fn test_transactions_decode_with_or_without_their_hex() {
+        let server = server().await;
+        let client = BlockbookClient::new(server.uri());
+
+        let funding = client.get_transaction(txid(FUNDING)).await.unwrap();
+        assert_eq!(funding.compute_txid(), txid(FUNDING));
+        // Left out of the older version's JSON, the hex is fetched from tx-specific
+        let hop0 = client.get_transaction(txid(HOP0)).await.unwrap();
+        assert_eq!(hop0.compute_txid(), txid(HOP0));
+
+        let missing = txid(&format!("{:0>64}", 1));
+        let err = client.get_transaction(missing).await.unwrap_err();
+        assert!(matches!(err.inner(), BlockchainError::NotFound(_)), "{err}");
+        let batch = client
+            .get_transactions_batch(&[txid(HOP1), missing])
+            .await
+            .unwrap();
+        assert_eq!(
+            batch[0].as_ref().map(Transaction::compute_txid),
+            Some(txid(HOP1))
+        );
+        assert_eq!(batch[1], None);
+    }
+
+    #[tokio::test]
+    async fn test_spenders_are_read_from_the_spent_outputs() {
+        let server = server().await;
+        let client = BlockbookClient::new(server.uri());
+
+        let spender = client
+            .get_spending_transaction(OutPoint::new(txid(FUNDING), 0))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(spender.compute_txid(), txid(HOP0));
+        // Unspent outputs have no "spent" at all
+        let peel = OutPoint::new(txid(HOP0), 1);
+        assert_eq!(client.get_spending_transaction(peel).await.unwrap(), None);
+        let err = client
+            .get_spending_transaction(OutPoint::new(txid(HOP0), 2))
+            .await
+            .unwrap_err();
+        assert!(matches!(err.inner(), BlockchainError::NotFound(_)), "{err}");
+
+        // Both outputs of hop 0 from one fetch of its JSON
+        let batch = client
+            .get_spending_transactions_batch(&[OutPoint::new(txid(HOP0), 0), peel])
+            .await
+            .unwrap();
+        assert_eq!(
+            batch[0].as_ref().map(Transaction::compute_txid),
+            Some(txid(HOP1))
+        );
+        assert_eq!(batch[1], None);
+        let hop0_fetches = server
+            .received_requests()
+            .await
+            .unwrap()
+            .iter()
+            .filter(|request| request.url.path() == format!("/api/v2/tx/{}", HOP0))
+            .count();
+        // One for the spender of the funding, two for hop 0's outputs, one for the batch
+        assert_eq!(hop0_fetches, 4);
+    }
+
+    #[tokio::test]
+    async fn test_status_and_metadata_come_from_the_transaction_json() {
+        let server = server().await;
+        let client = BlockbookClient::new(server.uri());
+
+        let status = client.get_transaction_status(txid(HOP0)).await.unwrap();
+        assert!(status.confirmed);
+        assert_eq!(status.block_height, Some(800_001));
+        assert_eq!(status.block_time, Some(1_690_169_364));
+        let status = client.get_transaction_status(txid(HOP1)).await.unwrap();
+        assert_eq!(status, TxStatus::unconfirmed());
+
+        let (tx, metadata) = client
+            .get_transaction_with_metadata(txid(FUNDING))
+            .await
+            .unwrap();
+        assert_eq!(metadata.confirmations, Some(3));
+        assert_eq!(metadata.block_height, Some(800_000));
+        assert_eq!(metadata.fee, Some(Amount::ZERO));
+        assert_eq!(metadata.weight, tx.weight());
+        let (_, metadata) = client
+            .get_transaction_with_metadata(txid(HOP1))
+            .await
+            .unwrap();
+        assert_eq!(metadata.confirmations, Some(0));
+        assert_eq!(metadata.fee, Some(Amount::from_sat(1_000)));
+    }
+
+    #[tokio::test]
+    async fn test_address_histories_are_paged_and_utxos_listed() {
+        let server = server().await;
+        let change = address("bc1qqqqqqqkycnzvf3xycnzvf3xycnzvf3xy4hppjd");
+        for page in [1, 2] {
+            Mock::given(method("GET"))
+                .and(path(format!("/api/v2/address/{}", change)))
+                .and(query_param("details", "txids"))
+                .and(query_param("page", page.to_string()))
+                .respond_with(
+                    ResponseTemplate::new(200)
+                        .set_body_string(fixture(&format!("address_page{}.json", page))),
+                )
+                .mount(&server)
+                .await;
+        }
+        let unused = address("bc1qqqqqqpxycnzvf3xycnzvf3xycnzvf3xyptt0lx");
+        Mock::given(method("GET"))
+            .and(path(format!("/api/v2/address/{}", unused)))
+            .respond_with(ResponseTemplate::new(200).set_body_string(fixture("address_empty.json")))
+            .mount(&server)
+            .await;
+        let peel = address("bc1qqqqqqq7ycnzvf3xycnzvf3xycnzvf3xyd048k8");
+        Mock::given(method("GET"))
+            .and(path(format!("/api/v2/utxo/{}", peel)))
+            .respond_with(ResponseTemplate::new(200).set_body_string(fixture("utxo.json")))
+            .mount(&server)
+            .await;
+        let client = BlockbookClient::new(server.uri());
+
+        let history = client.get_address_transactions(change).await.unwrap();
+        let txids: Vec<Txid> = history.iter().map(Transaction::compute_txid).collect();
+        assert_eq!(txids, [txid(HOP1), txid(HOP0)]);
+        assert!(
+            client
+                .get_address_transactions(unused)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        let utxos = client.get_address_utxos(peel).await.unwrap();
+        assert_eq!(utxos, [OutPoint::new(txid(HOP0), 1)]);
+    }
+}
@@ -5,57 +5,166 @@
 //!
 //! Critical for performance when handling large traces where paths converge.
 
-use crate::blockchain::{BlockchainDataSource, Result};
+pub mod backend;
+mod codec;
+pub mod janitor;
+#[cfg(feature = "moka-cache")]
+pub mod moka;
+#[cfg(feature = "persistent-cache")]
+pub mod persistent;
+pub mod policy;
+pub mod prefetch;
+pub mod snapshot;
+pub mod stats;
+pub mod ttl;
+
+pub use backend::{CacheBackend, CacheLookup, MemoryBackend};
+pub use janitor::JanitorHandle;
+#[cfg(feature = "moka-cache")]
+pub use moka::MokaBackend;
+#[cfg(feature = "persistent-cache")]
+pub use persistent::SledBackend;
+pub use policy::{CacheAll, CachePolicy, MaxEntrySize};
+pub use prefetch::PrefetchSummary;
+pub use snapshot::SnapshotReport;
+pub use stats::{CacheStats, KindStats};
+use ttl::Jitter;
+pub use ttl::{
+    DEFAULT_ADDRESS_TTL, DEFAULT_NEGATIVE_TTL, DEFAULT_TTL, DEFAULT_UNCONFIRMED_TTL, TtlPolicy,
+};
+
+use crate::blockchain::{
+    BlockchainDataSource, BlockchainError, CacheKey, Result, TxMetadata, TxStatus, batch,
+};
+use crate::time::Clock;
 use async_trait::async_trait;
-use bitcoin::{Address, OutPoint, Transaction, Txid};
+use bitcoin::{Address, Block, BlockHash, OutPoint, Transaction, Txid, block::Header};
+use stats::{StatsCounters, bump};
 use std::{
     collections::HashMap,
-    sync::{Arc, RwLock},
+    path::Path,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
     time::Duration,
 };
-use tokio::time::Instant;
+use tokio::sync::watch;
 
-/// Cache key type distinguishing between transaction lookups and spending lookups
+/// A cached lookup result.
 ///
-/// # Fields
+/// Transactions are shared, so handing out a cache hit is a reference count bump
+/// rather than a deep clone of every input and witness.
 ///
-/// * `Transaction(Txid)` - Direct Transaction lookup with
-/// * `Spending(OutPoint)` - Spending Tx lookup by outpoint (which tx spent this output?)
-#[derive(PartialEq, Eq, Hash, Debug, Clone)]
-pub enum CacheKey {
-    Transaction(Txid),
-    Spending(OutPoint),
+/// # Variants
+/// * `Found(Arc<Transaction>)` - a cached bitcoin::Transaction
+/// * `NotFound` - the source reported `NotFound` for this key (negative caching)
+/// * `Unspent` - the outpoint was unspent when checked (only with `cache_unspent`)
+/// * `History(Arc<Vec<Transaction>>)` - the transaction history of an address
+#[derive(Debug, Clone, PartialEq)]
+pub enum CachedEntry {
+    Found(Arc<Transaction>),
+    NotFound,
+    Unspent,
+    History(Arc<Vec<Transaction>>),
 }
 
-/// A cached transaction entry with insertion timestamp for TTL checking.
-///
-/// # Fields
-/// * `transaction` - a cached bitcoin::Transaction
-/// * `inserted_at` - timestamp for TTL cechking
-#[derive(Debug, Clone)]
-pub struct CachedEntry {
-    transaction: Transaction,
-    inserted_at: Instant,
+impl CachedEntry {
+    /// Serialized size of the cached transactions in bytes, used by byte-bounded
+    /// backends (negative and unspent markers weigh nothing)
+    pub fn weight(&self) -> usize {
+        match self {
+            CachedEntry::Found(tx) => tx.total_size(),
+            CachedEntry::History(txs) => txs.iter().map(Transaction::total_size).sum(),
+            CachedEntry::NotFound | CachedEntry::Unspent => 0,
+        }
+    }
 }
 
 /// Decorator that adds TTL-based caching to any `BlockchainDataSource`.
 ///
-/// Uses `Arc<RwLock<HashMap>>` for thread-safe concurrent access:
-/// - Read locks for cache lookups (allows concurrent reads)
-/// - Write locks for cache inserts (exclusive)
+/// Entries live in a `CacheBackend`, by default the in-memory `MemoryBackend`.
+/// When built with `max_entries`, inserting beyond the cap evicts the least recently
+/// used entry.
+///
+/// `NotFound` results are cached too (negative caching) under a separate, shorter TTL
+/// so repeated lookups of a bad txid don't hit the source every time. Network failures
+/// and rate limits are never cached.
+///
+/// Unspent outpoints are not cached by default since they may be spent at any time.
+/// `cache_unspent` opts into caching them under their own short TTL, which pays off
+/// when converging trace paths keep checking the same unspent frontier.
+///
+/// Concurrent misses on the same key are coalesced into a single request to the inner
+/// source; every waiter receives the same result (errors included).
+///
+/// Cloning is cheap and the clones share the inner source, the cached entries, the
+/// in-flight lookups and the stats, so one warmed cache can be handed to several
+/// concurrent trace tasks.
+///
+/// A fetched spender is also cached under its own txid, since the next hop of a trace
+/// looks it up right away. With `index_spenders`, a transaction fetched by txid is
+/// likewise cached as the spender of each of its prevouts.
+///
+/// With `check_status`, the status of each fetched transaction is asked of the inner
+/// source, so that an unconfirmed one is kept for the unconfirmed TTL at most and the
+/// policy can tell it apart.
+///
+/// Batch lookups are served from the cache where possible and only the missing subset
+/// is forwarded to the inner batch method. Address histories are cached under their
+/// own, short TTL since new transactions can show up at any time.
 ///
 /// # Example
 /// ```ignore
 /// let esplora = EsploraClient::new("https://mempool.space/api".to_string());
 /// let cached = CachingDataSource::new(esplora, Duration::from_secs(300));
+///
+/// let bounded = CachingDataSource::builder(esplora)
+///     .ttl(Duration::from_secs(300))
+///     .max_entries(100_000)
+///     .build();
+///
+/// let persistent = CachingDataSource::builder(esplora)
+///     .backend(SledBackend::open("pathfinder-cache")?)
+///     .build();
+///
+/// let tinylfu = CachingDataSource::builder(esplora)
+///     .backend(MokaBackend::new())
+///     .max_bytes(256 << 20)
+///     .build();
 /// ```
-pub struct CachingDataSource<C> {
-    /// Inner data source (Esplora, Bitcoin Core RPC, etc.)
-    inner: C,
-    /// Thread-safe cache with TTL eviction
-    cache: Arc<RwLock<HashMap<CacheKey, CachedEntry>>>,
-    /// Time to live for cache entries
-    ttl: Duration,
+pub struct CachingDataSource<C, B = MemoryBackend> {
+    /// Inner data source (Esplora, Bitcoin Core RPC, etc.), shared between clones
+    inner: Arc<C>,
+    /// Thread-safe entry storage with TTL (and LRU eviction for bounded memory backends)
+    cache: Arc<B>,
+    /// Time to live for each kind of cache entry
+    ttl: TtlPolicy,
+    /// Also cache fetched transactions as the spenders of their prevouts
+    index_spenders: bool,
+    /// Ask the inner source for the status of fetched transactions
+    check_status: bool,
+    /// Random spread of entry TTLs (none by default)
+    jitter: Option<Arc<Jitter>>,
+    /// Filter deciding which fetched entries are cached at all
+    policy: Arc<dyn CachePolicy>,
+    /// Lookups currently being fetched, so concurrent misses share one request
+    inflight: Arc<InflightMap>,
+    /// Hit/miss counters
+    stats: Arc<StatsCounters>,
+}
+
+impl<C, B> Clone for CachingDataSource<C, B> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: Arc::clone(&self.inner),
+            cache: Arc::clone(&self.cache),
+            ttl: self.ttl,
+            index_spenders: self.index_spenders,
+            check_status: self.check_status,
+            jitter: self.jitter.clone(),
+            policy: Arc::clone(&self.policy),
+            inflight: Arc::clone(&self.inflight),
+            stats: Arc::clone(&self.stats),
+        }
+    }
 }
 
 impl<C> CachingDataSource<C> {
@@ -63,102 +172,2137 @@ impl<C> CachingDataSource<C> {
     ///
     /// # Arguments
     /// * `inner` - The underlying blockchain data source
-    /// * `ttl` - How long cached entries remain valid
+    /// * `ttl` - How long cached entries remain valid (`TtlPolicy::uniform`)
     pub fn new(inner: C, ttl: Duration) -> Self {
-        Self {
+        Self::builder(inner).ttl(ttl).build()
+    }
+
+    /// Starts building a caching wrapper around the given data source.
+    pub fn builder(inner: C) -> CachingDataSourceBuilder<C> {
+        CachingDataSourceBuilder {
             inner,
-            cache: Arc::new(RwLock::new(HashMap::new())),
-            ttl,
+            backend: MemoryBackend::new(),
+            ttl: TtlPolicy::default(),
+            index_spenders: false,
+            check_status: false,
+            jitter: 0.0,
+            jitter_seed: None,
+            policy: Arc::new(CacheAll),
         }
     }
 }
 
-#[async_trait]
-impl<C: BlockchainDataSource + std::marker::Sync> BlockchainDataSource for CachingDataSource<C> {
-    /// Fetches a transaction by txid, checking cache first.
+impl<C, B: CacheBackend> CachingDataSource<C, B> {
+    /// Replaces the write-through policy deciding which fetched entries are cached,
+    /// e.g. `MaxEntrySize(100_000)`. Entries already cached are kept.
+    ///
+    /// Clones made before this call keep the previous policy.
+    pub fn with_policy(mut self, policy: impl CachePolicy + 'static) -> Self {
+        self.policy = Arc::new(policy);
+        self
+    }
+
+    /// The wrapped data source, shared between clones
+    pub fn inner(&self) -> &C {
+        &self.inner
+    }
+
+    /// Number of entries currently held, including expired entries not yet evicted.
+    pub fn len(&self) -> usize {
+        self.cache.len()
+    }
+
+    /// Returns true if the cache holds no entries.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Removes every cached entry.
+    pub fn clear(&self) {
+        self.cache.clear();
+    }
+
+    /// Drops the cached lookup of `txid`, returning whether there was one.
+    ///
+    /// Like every invalidation, only takes the storage lock briefly, so it is safe to
+    /// call while other tasks use the cache. A fetch already in flight for the key
+    /// still stores its result when it completes.
+    pub fn invalidate_transaction(&self, txid: Txid) -> bool {
+        self.cache.remove(&CacheKey::Transaction(txid))
+    }
+
+    /// Drops the cached spender (or unspent marker) of `outpoint`, e.g. after an RBF
+    /// replacement, returning whether there was one.
+    pub fn invalidate_spending(&self, outpoint: OutPoint) -> bool {
+        self.cache.remove(&CacheKey::Spending(outpoint))
+    }
+
+    /// Drops the cached history of `address`, returning whether there was one.
+    pub fn invalidate_address(&self, address: &Address) -> bool {
+        self.cache.remove(&CacheKey::Address(address.clone()))
+    }
+
+    /// Drops every cached spending lookup, returning whether any was removed.
+    ///
+    /// A coarse response to a new block or a reorg at `block_hash`: any outpoint may
+    /// have been spent (or unspent) by it, while transactions themselves stay valid.
+    pub fn invalidate_block(&self, _block_hash: BlockHash) -> bool {
+        self.cache
+            .remove_matching(&|key| matches!(key, CacheKey::Spending(_)))
+            > 0
+    }
+
+    /// Snapshot of the hit/miss counters since creation or the last `reset_stats`.
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            weight_bytes: self.cache.weight() as u64,
+            ..self.stats.snapshot()
+        }
+    }
+
+    /// Resets all counters to zero (`weight_bytes` reflects the current contents and is
+    /// not affected).
+    pub fn reset_stats(&self) {
+        self.stats.reset();
+    }
+
+    /// Writes every fresh entry to a snapshot file at `path`, replacing any existing file.
+    ///
+    /// Each entry keeps its remaining TTL, as a wall-clock expiry, so a snapshot loaded
+    /// later does not resurrect entries that would have expired in the meantime.
+    ///
+    /// # Errors
+    /// - `Other` - the file could not be written
+    pub fn save_snapshot(&self, path: impl AsRef<Path>) -> Result<()> {
+        snapshot::write(path.as_ref(), &self.cache.fresh_entries())
+    }
+
+    /// Loads the entries of a snapshot written by `save_snapshot` into this cache.
+    ///
+    /// Malformed entries and entries that expired since the snapshot was taken are
+    /// skipped rather than failing the whole load.
+    ///
+    /// # Errors
+    /// - `Other` - the file could not be read
+    /// - `InvalidInput` - the file is not a snapshot, or has an unsupported version
+    pub fn load_snapshot(&self, path: impl AsRef<Path>) -> Result<SnapshotReport> {
+        let (entries, skipped) = snapshot::read(path.as_ref())?;
+        let report = SnapshotReport {
+            loaded: entries.len(),
+            skipped,
+        };
+        for (key, _, _) in &entries {
+            bump(&self.stats.kind(key).insertions);
+        }
+        for key in &self.cache.insert_many(entries) {
+            bump(&self.stats.kind(key).evictions);
+        }
+        Ok(report)
+    }
+
+    /// Spawns a background task removing expired entries every `interval`.
+    ///
+    /// Each sweep removes entries in small batches so lookups are not starved on large
+    /// caches, and records how many it removed in `CacheStats::last_sweep_removed`.
+    /// The task stops when the returned handle is dropped, or when this cache is.
+    ///
+    /// Must be called from within a tokio runtime, or in the browser on wasm.
+    pub fn spawn_janitor(&self, interval: Duration) -> JanitorHandle
+    where
+        B: 'static,
+    {
+        janitor::spawn(
+            Arc::downgrade(&self.cache),
+            Arc::downgrade(&self.stats),
+            interval,
+        )
+    }
+}
+
+/// Builder for `CachingDataSource`.
+///
+/// Defaults to an unbounded `MemoryBackend` and `TtlPolicy::default()`: a 300s TTL,
+/// a 60s address TTL, a 30s negative TTL and no unspent caching.
+pub struct CachingDataSourceBuilder<C, B = MemoryBackend> {
+    inner: C,
+    backend: B,
+    ttl: TtlPolicy,
+    index_spenders: bool,
+    check_status: bool,
+    jitter: f64,
+    jitter_seed: Option<u64>,
+    policy: Arc<dyn CachePolicy>,
+}
+
+impl<C> CachingDataSourceBuilder<C> {
+    /// Caps the number of cached entries, evicting least recently used entries beyond it
+    pub fn max_entries(mut self, max_entries: usize) -> Self {
+        self.backend = MemoryBackend::with_limits(Some(max_entries), self.backend.max_bytes())
+            .with_clock(Arc::clone(self.backend.clock()));
+        self
+    }
+
+    /// Caps the total serialized size of cached transactions, evicting least recently
+    /// used entries beyond it. Can be combined with `max_entries`.
+    pub fn max_bytes(mut self, max_bytes: usize) -> Self {
+        self.backend = MemoryBackend::with_limits(self.backend.max_entries(), Some(max_bytes))
+            .with_clock(Arc::clone(self.backend.clock()));
+        self
+    }
+
+    /// Measures TTLs on `clock` instead of the `SystemClock`, e.g. a
+    /// `testing::MockClock`
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.backend = self.backend.with_clock(clock);
+        self
+    }
+}
+
+#[cfg(feature = "moka-cache")]
+impl<C> CachingDataSourceBuilder<C, MokaBackend> {
+    /// Caps the number of cached entries, letting moka choose which entries to evict
+    pub fn max_entries(mut self, max_entries: usize) -> Self {
+        self.backend = MokaBackend::with_limits(Some(max_entries), self.backend.max_bytes())
+            .with_clock(Arc::clone(self.backend.clock()));
+        self
+    }
+
+    /// Caps the total serialized size of cached transactions, letting moka choose which
+    /// entries to evict. Can be combined with `max_entries`.
+    pub fn max_bytes(mut self, max_bytes: usize) -> Self {
+        self.backend = MokaBackend::with_limits(self.backend.max_entries(), Some(max_bytes))
+            .with_clock(Arc::clone(self.backend.clock()));
+        self
+    }
+
+    /// Measures TTLs on `clock` instead of the `SystemClock`
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.backend = self.backend.with_clock(clock);
+        self
+    }
+}
+
+impl<C, B: CacheBackend> CachingDataSourceBuilder<C, B> {
+    /// Stores entries in `backend` instead of the default `MemoryBackend`
+    pub fn backend<B2: CacheBackend>(self, backend: B2) -> CachingDataSourceBuilder<C, B2> {
+        CachingDataSourceBuilder {
+            inner: self.inner,
+            backend,
+            ttl: self.ttl,
+            index_spenders: self.index_spenders,
+            check_status: self.check_status,
+            jitter: self.jitter,
+            jitter_seed: self.jitter_seed,
+            policy: self.policy,
+        }
+    }
+
+    /// How long cached transaction and spending lookups remain valid
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl.transaction = Some(ttl);
+        self.ttl.spending = Some(ttl);
+        self
+    }
+
+    /// How long a cached address history remains valid
+    pub fn address_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl.address = Some(ttl);
+        self
+    }
+
+    /// How long a `NotFound` result is served from the cache
+    pub fn negative_ttl(mut self, negative_ttl: Duration) -> Self {
+        self.ttl.negative = Some(negative_ttl);
+        self
+    }
+
+    /// Caches unspent outpoints for `ttl`, so repeated checks of the same unspent
+    /// output within that window don't trigger another outspend request
+    pub fn cache_unspent(mut self, ttl: Duration) -> Self {
+        self.ttl.unspent = Some(ttl);
+        self
+    }
+
+    /// Also cache every transaction fetched by txid as the spender of each outpoint it
+    /// spends, so walking a trace backwards needs no outspend requests.
+    ///
+    /// Off by default since it adds one entry per input.
+    pub fn index_spenders(mut self, enabled: bool) -> Self {
+        self.index_spenders = enabled;
+        self
+    }
+
+    /// Ask the inner source for the status of every transaction fetched, by txid or as
+    /// a spender, before caching it: an unconfirmed one is kept for the unconfirmed TTL
+    /// at most, and the policy sees the status.
+    ///
+    /// Off by default since it adds one request per transaction fetched. A status the
+    /// inner source cannot report is left unknown.
+    pub fn check_status(mut self, enabled: bool) -> Self {
+        self.check_status = enabled;
+        self
+    }
+
+    /// Spreads every finite TTL by a random factor of up to `±fraction` (clamped to
+    /// `0.0..=1.0`, a NaN or infinite one meaning none), decided when the entry is
+    /// inserted. A TTL too long to spread is kept as is.
+    ///
+    /// Entries cached during the same burst then expire gradually instead of all at
+    /// once. Defaults to 0 (no jitter).
+    pub fn ttl_jitter(mut self, fraction: f64) -> Self {
+        self.jitter = fraction;
+        self
+    }
+
+    /// Seeds the jitter random number generator, for reproducible expiries
+    pub fn jitter_seed(mut self, seed: u64) -> Self {
+        self.jitter_seed = Some(seed);
+        self
+    }
+
+    /// Only caches entries accepted by `policy` (`CacheAll` by default)
+    pub fn policy(mut self, policy: impl CachePolicy + 'static) -> Self {
+        self.policy = Arc::new(policy);
+        self
+    }
+
+    /// Replaces the whole TTL policy, e.g. `TtlPolicy::immutable_transactions`
+    pub fn ttl_policy(mut self, policy: TtlPolicy) -> Self {
+        self.ttl = policy;
+        self
+    }
+
+    pub fn build(self) -> CachingDataSource<C, B> {
+        CachingDataSource {
+            inner: Arc::new(self.inner),
+            cache: Arc::new(self.backend),
+            ttl: self.ttl,
+            index_spenders: self.index_spenders,
+            check_status: self.check_status,
+            jitter: Jitter::new(self.jitter, self.jitter_seed).map(Arc::new),
+            policy: self.policy,
+            inflight: Arc::new(Mutex::new(HashMap::new())),
+            stats: Arc::new(StatsCounters::default()),
+        }
+    }
+}
+
+impl<C, B: CacheBackend> CachingDataSource<C, B> {
+    /// Looks up a fresh cache entry (read lock) and turns it back into a lookup result,
+    /// recording the outcome in the stats.
+    ///
+    /// Returns `None` on a miss or an expired entry.
+    fn lookup(&self, key: &CacheKey) -> Option<Result<CachedEntry>> {
+        self.read(key, true)
+    }
+
+    /// Same as `lookup` without touching the stats.
+    fn peek(&self, key: &CacheKey) -> Option<Result<CachedEntry>> {
+        self.read(key, false)
+    }
+
+    fn read(&self, key: &CacheKey, record: bool) -> Option<Result<CachedEntry>> {
+        let counters = self.stats.kind(key);
+        let record = |counter, outcome: &str| {
+            if record {
+                bump(counter);
+                tracing::debug!(%key, outcome, "cache lookup");
+            }
+        };
+        let entry = match self.cache.get(key) {
+            CacheLookup::Fresh(entry) => entry,
+            CacheLookup::Expired => {
+                record(&counters.expired_hits, "expired");
+                return None;
+            }
+            CacheLookup::Missing => {
+                record(&counters.misses, "miss");
+                return None;
+            }
+        };
+        if entry == CachedEntry::NotFound {
+            record(&counters.negative_hits, "negative hit");
+            return Some(Err(BlockchainError::NotFound(format!(
+                "{} not found (cached)",
+                key
+            ))));
+        }
+        record(&counters.hits, "hit");
+        Some(Ok(entry))
+    }
+
+    /// Stores a lookup result (write lock), with the TTL the policy gives its kind.
+    ///
+    /// - A transaction is stored under the transaction or spending TTL
+    /// - An address history is stored under the address TTL
+    /// - `Unspent` is stored only if unspent caching is enabled
+    /// - `NotFound` is stored under the negative TTL
+    /// - Any other error is passed through without touching the cache
+    ///
+    /// A spender is also stored under its own txid, and with `index_spenders` a
+    /// transaction is also stored as the spender of its prevouts, all in one backend
+    /// write. Each key the policy rejects is skipped and counted as rejected.
+    ///
+    /// `status`, that of the transaction of a `Found` entry if known, caps its TTL at
+    /// the unconfirmed TTL when unconfirmed, and is shown to the policy.
+    fn store(&self, key: CacheKey, result: &Result<CachedEntry>, status: Option<&TxStatus>) {
+        let entry = match result {
+            Ok(CachedEntry::Unspent) if self.ttl.unspent.is_none() => return,
+            Ok(entry) => entry.clone(),
+            Err(error) if matches!(error.inner(), BlockchainError::NotFound(_)) => {
+                CachedEntry::NotFound
+            }
+            Err(_) => return,
+        };
+
+        let mut keys = vec![key];
+        if let CachedEntry::Found(tx) = &entry {
+            match &keys[0] {
+                CacheKey::Spending(_) => keys.push(CacheKey::Transaction(tx.compute_txid())),
+                CacheKey::Transaction(_) if self.index_spenders => keys.extend(
+                    tx.input
+                        .iter()
+                        .filter(|input| !input.previous_output.is_null())
+                        .map(|input| CacheKey::Spending(input.previous_output)),
+                ),
+                _ => {}
+            }
+        }
+
+        let entries: Vec<_> = keys
+            .into_iter()
+            .filter(|key| {
+                let accepted = self.policy.should_cache(key, &entry, status);
+                if !accepted {
+                    bump(&self.stats.kind(key).rejected);
+                }
+                accepted
+            })
+            .map(|key| {
+                bump(&self.stats.kind(&key).insertions);
+                let ttl = self.ttl.ttl_for(&key, &entry, status);
+                let ttl = match &self.jitter {
+                    Some(jitter) => ttl.map(|ttl| jitter.apply(ttl)),
+                    None => ttl,
+                };
+                (key, entry.clone(), ttl)
+            })
+            .collect();
+        if entries.is_empty() {
+            return;
+        }
+        for key in &self.cache.insert_many(entries) {
+            bump(&self.stats.kind(key).evictions);
+        }
+    }
+
+    /// Joins the in-flight lookup for `key`, or registers a new one led by the caller.
+    fn join_flight(&self, key: &CacheKey) -> Flight {
+        let mut inflight = lock_inflight(&self.inflight);
+        if let Some(receiver) = inflight.get(key) {
+            return Flight::Wait(receiver.clone());
+        }
+        // The leader stores into the cache before leaving the in-flight map,
+        // so re-check to avoid fetching a result that just landed
+        if let Some(hit) = self.peek(key) {
+            return Flight::Hit(hit);
+        }
+        let (sender, receiver) = watch::channel(None);
+        inflight.insert(key.clone(), receiver);
+        Flight::Lead(sender)
+    }
+}
+
+impl<C, B> CachingDataSource<C, B>
+where
+    C: BlockchainDataSource + Send + Sync,
+    B: CacheBackend,
+{
+    /// Serves `key` from the cache, or runs `fetch` and caches its result.
+    ///
+    /// Concurrent misses on the same key are coalesced (single-flight): the first task
+    /// registers an in-flight slot and runs `fetch`, the others wait for its result.
+    /// If the leading task is cancelled before finishing, a waiter takes over.
+    async fn get_or_fetch<F>(&self, key: CacheKey, fetch: F) -> Result<CachedEntry>
+    where
+        F: Future<Output = Result<CachedEntry>>,
+    {
+        loop {
+            if let Some(hit) = self.lookup(&key) {
+                return hit;
+            }
+
+            let mut waiting = match self.join_flight(&key) {
+                Flight::Hit(hit) => return hit,
+                Flight::Lead(sender) => return self.lead_flight(key, sender, fetch).await,
+                Flight::Wait(receiver) => receiver,
+            };
+
+            match waiting.wait_for(Option::is_some).await {
+                Ok(result) => return result.clone().expect("waited for Some"),
+                // Leader dropped without a result, go around and try again
+                Err(_) => continue,
+            }
+        }
+    }
+
+    async fn lead_flight<F>(
+        &self,
+        key: CacheKey,
+        sender: watch::Sender<Option<Result<CachedEntry>>>,
+        fetch: F,
+    ) -> Result<CachedEntry>
+    where
+        F: Future<Output = Result<CachedEntry>>,
+    {
+        // Removes the in-flight slot even if this future is dropped mid-fetch
+        let _slot = InflightSlot {
+            inflight: &self.inflight,
+            key: key.clone(),
+        };
+
+        let result = fetch.await;
+        let status = self.status_of(&result).await;
+        self.store(key, &result, status.as_ref());
+        sender.send_replace(Some(result.clone()));
+        result
+    }
+
+    /// Serves a batch of transaction or spending lookups, forwarding only the keys
+    /// missing from the cache to `fetch_missing` in a single call.
+    ///
+    /// `fetch_missing` receives the positions (into `keys`) of the misses and must
+    /// return one result per position, in order. A `None` result is cached as
+    /// `NotFound` for transaction keys and as `Unspent` for spending keys.
+    ///
+    /// Batches bypass single-flight: a key missing from two concurrent batches is
+    /// fetched by both.
+    ///
+    /// # Errors
+    /// - Any error of `fetch_missing`, in which case nothing is cached
+    /// - `DataInconsistency` - `fetch_missing` returned the wrong number of results
+    async fn get_batch<F, Fut>(
+        &self,
+        keys: Vec<CacheKey>,
+        fetch_missing: F,
+    ) -> Result<Vec<Option<Arc<Transaction>>>>
+    where
+        F: FnOnce(Vec<usize>) -> Fut,
+        Fut: Future<Output = Result<Vec<Option<Transaction>>>>,
+    {
+        let mut results = Vec::with_capacity(keys.len());
+        let mut missing = Vec::new();
+        for (i, key) in keys.iter().enumerate() {
+            match self.lookup(key) {
+                Some(Ok(CachedEntry::Found(tx))) => results.push(Some(tx)),
+                Some(Ok(CachedEntry::Unspent)) | Some(Err(_)) => results.push(None),
+                _ => {
+                    results.push(None);
+                    missing.push(i);
+                }
+            }
+        }
+        if missing.is_empty() {
+            return Ok(results);
+        }
+
+        let fetched = fetch_missing(missing.clone()).await?;
+        if fetched.len() != missing.len() {
+            return Err(BlockchainError::DataInconsistency(format!(
+                "Batch lookup returned {} results for {} requests",
+                fetched.len(),
+                missing.len()
+            )));
+        }
+
+        for (i, tx) in missing.into_iter().zip(fetched) {
+            let key = keys[i].clone();
+            let tx = tx.map(Arc::new);
+            let result = match &tx {
+                Some(tx) => Ok(CachedEntry::Found(tx.clone())),
+                None => absent_entry(&key),
+            };
+            let status = self.status_of(&result).await;
+            self.store(key, &result, status.as_ref());
+            results[i] = tx;
+        }
+        Ok(results)
+    }
+
+    /// Status of the transaction of a fetched `Found` entry, asked of the inner source
+    /// with `check_status`. `None` for any other result, or when it cannot be had.
+    async fn status_of(&self, result: &Result<CachedEntry>) -> Option<TxStatus> {
+        let Ok(CachedEntry::Found(tx)) = result else {
+            return None;
+        };
+        if !self.check_status {
+            return None;
+        }
+        let txid = tx.compute_txid();
+        match self.inner.get_transaction_status(txid).await {
+            Ok(status) => Some(status),
+            Err(e) => {
+                tracing::debug!(%txid, error = %e, "status unknown, cached as is");
+                None
+            }
+        }
+    }
+}
+
+/// What a `None` batch result means for `key`
+fn absent_entry(key: &CacheKey) -> Result<CachedEntry> {
+    match key {
+        CacheKey::Spending(_) => Ok(CachedEntry::Unspent),
+        _ => Err(BlockchainError::NotFound(format!("{} not found", key))),
+    }
+}
+
+/// Error for a cached entry of the wrong shape for the lookup serving it
+fn unexpected_entry(key: &CacheKey, entry: &CachedEntry) -> BlockchainError {
+    BlockchainError::DataInconsistency(format!("{} cached as {:?}", key, entry))
+}
+
+/// In-flight lookups, keyed by cache key, with a channel carrying the shared result
+type InflightMapInner = HashMap<CacheKey, watch::Receiver<Option<Result<CachedEntry>>>>;
+type InflightMap = Mutex<InflightMapInner>;
+
+/// The caller's role in a single-flight lookup
+enum Flight {
+    /// The result landed in the cache while joining
+    Hit(Result<CachedEntry>),
+    /// No lookup in flight, the caller fetches and publishes the result
+    Lead(watch::Sender<Option<Result<CachedEntry>>>),
+    /// Another task is fetching, wait for its result
+    Wait(watch::Receiver<Option<Result<CachedEntry>>>),
+}
+
+/// Guard removing a key from the in-flight map when the leading fetch finishes or is dropped.
+struct InflightSlot<'a> {
+    inflight: &'a InflightMap,
+    key: CacheKey,
+}
+
+impl Drop for InflightSlot<'_> {
+    fn drop(&mut self) {
+        lock_inflight(self.inflight).remove(&self.key);
+    }
+}
+
+/// Locks the in-flight map, recovering it if poisoned.
+///
+/// A stale slot left behind by a panicking task only has a dropped sender, which makes
+/// its waiters retry, so the map is safe to keep using.
+fn lock_inflight(inflight: &InflightMap) -> MutexGuard<'_, InflightMapInner> {
+    inflight.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl<C, B> CachingDataSource<C, B>
+where
+    C: BlockchainDataSource + Send + Sync,
+    B: CacheBackend,
+{
+    /// Same as `get_transaction`, without cloning the cached transaction.
     ///
     /// Cache strategy:
     /// 1. Check cache with read lock
-    /// 2. If hit and not expired, return cached tx
-    /// 3. If miss or expired, fetch from inner source
+    /// 2. If hit and not expired, return cached tx (or the cached `NotFound`)
+    /// 3. If miss or expired, fetch from inner source (once, even for concurrent misses)
     /// 4. Store result in cache with write lock
-    async fn get_transaction(&self, txid: Txid) -> Result<Transaction> {
+    pub async fn get_transaction_shared(&self, txid: Txid) -> Result<Arc<Transaction>> {
         let key = CacheKey::Transaction(txid);
+        let fetch = async {
+            self.inner
+                .get_transaction(txid)
+                .await
+                .map(|tx| CachedEntry::Found(Arc::new(tx)))
+        };
 
-        // Check the cache (read lock)
-        {
-            let cache = self.cache.read().unwrap();
-            if let Some(entry) = cache.get(&key)
-                && entry.inserted_at.elapsed() < self.ttl
-            {
-                return Ok(entry.transaction.clone());
-            }
-            // Entry expired, fetch it
+        match self.get_or_fetch(key.clone(), fetch).await? {
+            CachedEntry::Found(tx) => Ok(tx),
+            entry => Err(unexpected_entry(&key, &entry)),
         }
+    }
 
-        // cache miss or expired, fetch Transaction from source
-        let tx = self.inner.get_transaction(txid).await?;
+    /// Same as `get_spending_transaction`, without cloning the cached spender.
+    pub async fn get_spending_transaction_shared(
+        &self,
+        outpoint: OutPoint,
+    ) -> Result<Option<Arc<Transaction>>> {
+        let key = CacheKey::Spending(outpoint);
+        let fetch = async {
+            let spender = self.inner.get_spending_transaction(outpoint).await?;
+            Ok(spender.map_or(CachedEntry::Unspent, |tx| CachedEntry::Found(Arc::new(tx))))
+        };
 
-        // Store the fetched Tx into cache (write lock)
-        {
-            let mut cache = self.cache.write().unwrap();
-            let entry = CachedEntry {
-                transaction: tx.clone(),
-                inserted_at: Instant::now(),
-            };
-            cache.insert(key, entry);
+        match self.get_or_fetch(key.clone(), fetch).await? {
+            CachedEntry::Found(tx) => Ok(Some(tx)),
+            CachedEntry::Unspent => Ok(None),
+            entry => Err(unexpected_entry(&key, &entry)),
         }
+    }
+
+    /// Same as `get_address_transactions`, without cloning the cached history.
+    pub async fn get_address_transactions_shared(
+        &self,
+        address: Address,
+    ) -> Result<Arc<Vec<Transaction>>> {
+        let key = CacheKey::Address(address.clone());
+        let fetch = async {
+            self.inner
+                .get_address_transactions(address)
+                .await
+                .map(|txs| CachedEntry::History(Arc::new(txs)))
+        };
 
-        Ok(tx)
+        match self.get_or_fetch(key.clone(), fetch).await? {
+            CachedEntry::History(txs) => Ok(txs),
+            entry => Err(unexpected_entry(&key, &entry)),
+        }
+    }
+}
+
+/// Unwraps batch results for the owned-`Transaction` trait interface
+fn into_owned(results: Vec<Option<Arc<Transaction>>>) -> Vec<Option<Transaction>> {
+    results
+        .into_iter()
+        .map(|tx| tx.map(Arc::unwrap_or_clone))
+        .collect()
+}
+
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+impl<C, B> BlockchainDataSource for CachingDataSource<C, B>
+where
+    C: BlockchainDataSource + Send + Sync,
+    B: CacheBackend,
+{
+    /// Fetches a transaction by txid, checking cache first.
+    ///
+    /// The trait hands out owned transactions, so this clones the cached one; use
+    /// `get_transaction_shared` on hot paths.
+    async fn get_transaction(&self, txid: Txid) -> Result<Transaction> {
+        self.get_transaction_shared(txid)
+            .await
+            .map(Arc::unwrap_or_clone)
     }
 
     /// Fetches the transaction that spent the given outpoint, checking cache first.
     ///
     /// Returns `None` if the output is unspent. Unspent outputs are NOT cached
+    /// unless `cache_unspent` was set, in which case they use its shorter TTL.
     /// NOTE:(they may be spent between checks).
     async fn get_spending_transaction(&self, outpoint: OutPoint) -> Result<Option<Transaction>> {
-        let key = CacheKey::Spending(outpoint);
+        let spender = self.get_spending_transaction_shared(outpoint).await?;
+        Ok(spender.map(Arc::unwrap_or_clone))
+    }
 
-        // check the cache (read lock)
-        {
-            let cache = self.cache.read().unwrap();
-            if let Some(entry) = cache.get(&key)
-                && entry.inserted_at.elapsed() < self.ttl
-            {
-                return Ok(Some(entry.transaction.clone()));
+    /// Fetches the transaction history of an address, checking cache first.
+    ///
+    /// Histories are cached under the address TTL (`DEFAULT_ADDRESS_TTL` unless set
+    /// with `address_ttl`), so a new transaction shows up at most that late.
+    async fn get_address_transactions(&self, address: Address) -> Result<Vec<Transaction>> {
+        self.get_address_transactions_shared(address)
+            .await
+            .map(Arc::unwrap_or_clone)
+    }
+
+    /// UTXOs change with every spend, forwarded straight to the inner source, which
+    /// may list them without walking the history.
+    async fn get_address_utxos(&self, address: Address) -> Result<Vec<OutPoint>> {
+        self.inner.get_address_utxos(address).await
+    }
+
+    /// Fetches a batch of transactions, forwarding only the uncached txids to the
+    /// inner source in one batch call, each once however often `txids` repeats it.
+    /// Results keep the order of `txids`; the inner source splits the call by its
+    /// own max batch size.
+    ///
+    /// Transactions the source does not know (`None`) are negatively cached.
+    async fn get_transactions_batch(&self, txids: &[Txid]) -> Result<Vec<Option<Transaction>>> {
+        let inner = &self.inner;
+
+        batch::run(txids, usize::MAX, |txids| async move {
+            let keys = txids.iter().copied().map(CacheKey::Transaction).collect();
+            let txids = &txids;
+            self.get_batch(keys, |missing| async move {
+                let missing: Vec<Txid> = missing.into_iter().map(|i| txids[i]).collect();
+                inner.get_transactions_batch(&missing).await
+            })
+            .await
+        })
+        .await
+        .map(into_owned)
+    }
+
+    /// Fetches the spenders of a batch of outpoints, forwarding only the uncached
+    /// outpoints to the inner source in one batch call, each once however often
+    /// `outpoints` repeats it. Results keep the order of `outpoints`.
+    ///
+    /// Unspent outpoints (`None`) are cached only if `cache_unspent` was set.
+    async fn get_spending_transactions_batch(
+        &self,
+        outpoints: &[OutPoint],
+    ) -> Result<Vec<Option<Transaction>>> {
+        let inner = &self.inner;
+
+        batch::run(outpoints, usize::MAX, |outpoints| async move {
+            let keys = outpoints.iter().copied().map(CacheKey::Spending).collect();
+            let outpoints = &outpoints;
+            self.get_batch(keys, |missing| async move {
+                let missing: Vec<OutPoint> = missing.into_iter().map(|i| outpoints[i]).collect();
+                inner.get_spending_transactions_batch(&missing).await
+            })
+            .await
+        })
+        .await
+        .map(into_owned)
+    }
+
+    /// Answered from a fresh spender or unspent marker of the outpoint when one is
+    /// cached, otherwise forwarded to the inner source. Its answer is not cached, as
+    /// it carries no spender to keep.
+    async fn is_spent(&self, outpoint: OutPoint) -> Result<bool> {
+        match self.peek(&CacheKey::Spending(outpoint)) {
+            Some(Ok(CachedEntry::Found(_))) => Ok(true),
+            Some(Ok(CachedEntry::Unspent)) => Ok(false),
+            Some(Err(e)) => Err(e),
+            Some(Ok(CachedEntry::NotFound | CachedEntry::History(_))) | None => {
+                self.inner.is_spent(outpoint).await
+            }
+        }
+    }
+
+    /// Blocks are not cached, forwarded straight to the inner source.
+    async fn get_block_raw(&self, block_hash: BlockHash) -> Result<Block> {
+        self.inner.get_block_raw(block_hash).await
+    }
+
+    /// Statuses change as transactions confirm, forwarded straight to the inner source.
+    async fn get_transaction_status(&self, txid: Txid) -> Result<TxStatus> {
+        self.inner.get_transaction_status(txid).await
+    }
+
+    /// Headers are not cached, forwarded straight to the inner source.
+    async fn get_block_header(&self, block_hash: BlockHash) -> Result<Header> {
+        self.inner.get_block_header(block_hash).await
+    }
+
+    /// Confirmations change with every block, forwarded straight to the inner source.
+    async fn get_transaction_with_metadata(&self, txid: Txid) -> Result<(Transaction, TxMetadata)> {
+        self.inner.get_transaction_with_metadata(txid).await
+    }
+
+    /// Whether `key` has a fresh entry here or in the inner source, without touching
+    /// the stats. A fetch in flight for the key does not count.
+    fn is_cached(&self, key: &CacheKey) -> bool {
+        self.peek(key).is_some() || self.inner.is_cached(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::{BlockchainError, ErrorContext, ResultExt};
+    use crate::testing::{MockClock, tx};
+    use bitcoin::hashes::Hash;
+    use std::collections::HashSet;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// In-memory source counting every call that reaches it
+    #[derive(Default)]
+    struct CountingSource {
+        txs: HashMap<Txid, Transaction>,
+        spenders: std::sync::Mutex<HashMap<OutPoint, Transaction>>,
+        calls: AtomicUsize,
+        /// Number of keys requested by each batch call
+        batch_sizes: std::sync::Mutex<Vec<usize>>,
+        /// Simulated network latency per call
+        delay: Option<Duration>,
+        /// Fail every call with a network error
+        offline: bool,
+        /// Transactions reported unconfirmed, the others confirmed
+        unconfirmed: HashSet<Txid>,
+    }
+
+    impl CountingSource {
+        fn with_txs(txs: &[Transaction]) -> Self {
+            Self {
+                txs: txs.iter().map(|t| (t.compute_txid(), t.clone())).collect(),
+                ..Default::default()
+            }
+        }
+
+        fn spend(&self, outpoint: OutPoint, spender: Transaction) {
+            self.spenders.lock().unwrap().insert(outpoint, spender);
+        }
+
+        fn calls(&self) -> usize {
+            self.calls.load(Ordering::SeqCst)
+        }
+
+        fn batch_sizes(&self) -> Vec<usize> {
+            self.batch_sizes.lock().unwrap().clone()
+        }
+
+        fn record_batch(&self, size: usize) {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            self.batch_sizes.lock().unwrap().push(size);
+        }
+    }
+
+    #[async_trait]
+    impl BlockchainDataSource for CountingSource {
+        async fn get_transaction(&self, txid: Txid) -> Result<Transaction> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            if let Some(delay) = self.delay {
+                tokio::time::sleep(delay).await;
+            }
+            if self.offline {
+                return Err(BlockchainError::NetworkFailure("offline".to_string()))
+                    .with_ctx(|| ErrorContext::new("get_transaction").txid(txid));
+            }
+            self.txs
+                .get(&txid)
+                .cloned()
+                .ok_or_else(|| BlockchainError::NotFound(txid.to_string()))
+                .with_ctx(|| ErrorContext::new("get_transaction").txid(txid))
+        }
+        async fn get_spending_transaction(
+            &self,
+            outpoint: OutPoint,
+        ) -> Result<Option<Transaction>> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            Ok(self.spenders.lock().unwrap().get(&outpoint).cloned())
+        }
+        /// Every address "received" all known transactions
+        async fn get_address_transactions(&self, _: Address) -> Result<Vec<Transaction>> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            Ok(self.txs.values().cloned().collect())
+        }
+        async fn get_transactions_batch(&self, txids: &[Txid]) -> Result<Vec<Option<Transaction>>> {
+            self.record_batch(txids.len());
+            if self.offline {
+                return Err(BlockchainError::NetworkFailure("offline".to_string()));
+            }
+            Ok(txids
+                .iter()
+                .map(|txid| self.txs.get(txid).cloned())
+                .collect())
+        }
+        async fn get_spending_transactions_batch(
+            &self,
+            outpoints: &[OutPoint],
+        ) -> Result<Vec<Option<Transaction>>> {
+            self.record_batch(outpoints.len());
+            let spenders = self.spenders.lock().unwrap();
+            Ok(outpoints.iter().map(|o| spenders.get(o).cloned()).collect())
+        }
+        async fn get_transaction_status(&self, txid: Txid) -> Result<TxStatus> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            Ok(match self.unconfirmed.contains(&txid) {
+                true => TxStatus::unconfirmed(),
+                false => TxStatus {
+                    confirmed: true,
+                    block_height: Some(800_000),
+                    block_hash: None,
+                    block_time: None,
+                },
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_max_entries_keeps_cache_bounded() {
+        let txs: Vec<_> = (0..20).map(tx).collect();
+        let cache = CachingDataSource::builder(CountingSource::with_txs(&txs))
+            .max_entries(10)
+            .build();
+
+        for t in &txs {
+            cache.get_transaction(t.compute_txid()).await.unwrap();
+            assert!(cache.len() <= 10);
+        }
+        assert_eq!(cache.len(), 10);
+    }
+
+    #[tokio::test]
+    async fn test_hot_keys_survive_eviction() {
+        let txs: Vec<_> = (0..4).map(tx).collect();
+        let cache = CachingDataSource::builder(CountingSource::with_txs(&txs))
+            .max_entries(3)
+            .build();
+
+        for t in &txs[..3] {
+            cache.get_transaction(t.compute_txid()).await.unwrap();
+        }
+        // Touch the oldest entry so it becomes the most recently used
+        cache.get_transaction(txs[0].compute_txid()).await.unwrap();
+        assert_eq!(cache.inner.calls(), 3);
+
+        // Inserting a fourth entry evicts txs[1], the least recently used
+        cache.get_transaction(txs[3].compute_txid()).await.unwrap();
+        assert_eq!(cache.len(), 3);
+
+        cache.get_transaction(txs[0].compute_txid()).await.unwrap();
+        assert_eq!(cache.inner.calls(), 4);
+        cache.get_transaction(txs[1].compute_txid()).await.unwrap();
+        assert_eq!(cache.inner.calls(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_clear_empties_cache() {
+        let txs: Vec<_> = (0..3).map(tx).collect();
+        let cache = CachingDataSource::new(CountingSource::with_txs(&txs), DEFAULT_TTL);
+
+        for t in &txs {
+            cache.get_transaction(t.compute_txid()).await.unwrap();
+        }
+        assert_eq!(cache.len(), 3);
+
+        cache.clear();
+        assert!(cache.is_empty());
+        cache.get_transaction(txs[0].compute_txid()).await.unwrap();
+        assert_eq!(cache.inner.calls(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_entries_expire_exactly_at_their_ttl() {
+        let present = tx(1);
+        let clock = MockClock::new();
+        let cache =
+            CachingDataSource::builder(CountingSource::with_txs(std::slice::from_ref(&present)))
+                .clock(Arc::new(clock.clone()))
+                .max_entries(100)
+                .ttl(Duration::from_secs(300))
+                .build();
+        let txid = present.compute_txid();
+
+        cache.get_transaction(txid).await.unwrap();
+        clock.advance(Duration::from_millis(299_999));
+        cache.get_transaction(txid).await.unwrap();
+        assert_eq!(cache.inner.calls(), 1);
+        assert_eq!(cache.stats().transaction.hits, 1);
+
+        clock.advance(Duration::from_millis(1));
+        cache.get_transaction(txid).await.unwrap();
+        assert_eq!(cache.inner.calls(), 2);
+        assert_eq!(cache.stats().transaction.expired_hits, 1);
+        // Refetched, so fresh for another full TTL
+        clock.advance(Duration::from_secs(299));
+        cache.get_transaction(txid).await.unwrap();
+        assert_eq!(cache.inner.calls(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_not_found_is_negatively_cached() {
+        let clock = MockClock::new();
+        let cache = CachingDataSource::builder(CountingSource::default())
+            .negative_ttl(Duration::from_secs(30))
+            .clock(Arc::new(clock.clone()))
+            .build();
+        let missing = tx(42).compute_txid();
+
+        for _ in 0..5 {
+            let error = cache.get_transaction(missing).await.unwrap_err();
+            assert!(matches!(error.inner(), BlockchainError::NotFound(_)));
+        }
+        assert_eq!(cache.inner.calls(), 1);
+
+        clock.advance(Duration::from_secs(31));
+        assert!(cache.get_transaction(missing).await.is_err());
+        assert_eq!(cache.inner.calls(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_negative_ttl_is_independent_of_ttl() {
+        let present = tx(1);
+        let clock = MockClock::new();
+        let cache =
+            CachingDataSource::builder(CountingSource::with_txs(std::slice::from_ref(&present)))
+                .ttl(Duration::from_secs(300))
+                .negative_ttl(Duration::from_secs(10))
+                .clock(Arc::new(clock.clone()))
+                .build();
+        let missing = tx(2).compute_txid();
+
+        cache.get_transaction(present.compute_txid()).await.unwrap();
+        let _ = cache.get_transaction(missing).await;
+        assert_eq!(cache.inner.calls(), 2);
+
+        clock.advance(Duration::from_secs(11));
+        cache.get_transaction(present.compute_txid()).await.unwrap();
+        let _ = cache.get_transaction(missing).await;
+        assert_eq!(cache.inner.calls(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_immutable_transactions_never_expire() {
+        let funding = tx(1);
+        let outpoint = OutPoint::new(funding.compute_txid(), 0);
+        let source = CountingSource::with_txs(std::slice::from_ref(&funding));
+        source.spend(outpoint, tx(2));
+        let clock = MockClock::new();
+        let cache = CachingDataSource::builder(source)
+            .ttl_policy(TtlPolicy::immutable_transactions(Duration::from_secs(60)))
+            .clock(Arc::new(clock.clone()))
+            .build();
+
+        cache.get_transaction(funding.compute_txid()).await.unwrap();
+        cache.get_spending_transaction(outpoint).await.unwrap();
+        assert_eq!(cache.inner.calls(), 2);
+
+        clock.advance(Duration::from_secs(24 * 3600));
+        cache.get_transaction(funding.compute_txid()).await.unwrap();
+        assert_eq!(cache.inner.calls(), 2);
+        cache.get_spending_transaction(outpoint).await.unwrap();
+        assert_eq!(cache.inner.calls(), 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_spending_ttl_is_independent_of_transaction_ttl() {
+        let funding = tx(1);
+        let outpoint = OutPoint::new(funding.compute_txid(), 0);
+        let source = CountingSource::with_txs(std::slice::from_ref(&funding));
+        source.spend(outpoint, tx(2));
+        let cache = CachingDataSource::builder(source)
+            .ttl_policy(TtlPolicy {
+                transaction: Some(Duration::from_secs(10)),
+                spending: Some(Duration::from_secs(100)),
+                ..TtlPolicy::default()
+            })
+            .build();
+
+        cache.get_transaction(funding.compute_txid()).await.unwrap();
+        cache.get_spending_transaction(outpoint).await.unwrap();
+
+        tokio::time::advance(Duration::from_secs(11)).await;
+        cache.get_transaction(funding.compute_txid()).await.unwrap();
+        cache.get_spending_transaction(outpoint).await.unwrap();
+        assert_eq!(cache.inner.calls(), 3);
+
+        tokio::time::advance(Duration::from_secs(90)).await;
+        cache.get_spending_transaction(outpoint).await.unwrap();
+        assert_eq!(cache.inner.calls(), 4);
+    }
+
+    #[test]
+    fn test_uniform_policy_matches_constructor() {
+        let policy = TtlPolicy::uniform(Duration::from_secs(5));
+        assert_eq!(policy.transaction, Some(Duration::from_secs(5)));
+        assert_eq!(policy.spending, Some(Duration::from_secs(5)));
+        assert_eq!(policy.negative, Some(DEFAULT_NEGATIVE_TTL));
+        assert_eq!(policy.unspent, None);
+        assert_eq!(policy.unconfirmed, Some(DEFAULT_UNCONFIRMED_TTL));
+
+        let cache = CachingDataSource::new(CountingSource::default(), Duration::from_secs(5));
+        assert_eq!(cache.ttl, policy);
+    }
+
+    #[tokio::test]
+    async fn test_batch_only_fetches_missing_txids() {
+        let txs: Vec<_> = (0..10).map(tx).collect();
+        let txids: Vec<_> = txs.iter().map(Transaction::compute_txid).collect();
+        let cache = CachingDataSource::new(CountingSource::with_txs(&txs), DEFAULT_TTL);
+
+        for &i in &[0, 1, 2, 4, 5, 7, 9] {
+            cache.get_transaction(txids[i]).await.unwrap();
+        }
+        let results = cache.get_transactions_batch(&txids).await.unwrap();
+
+        assert_eq!(cache.inner.batch_sizes(), vec![3]);
+        let expected: Vec<_> = txs.iter().cloned().map(Some).collect();
+        assert_eq!(results, expected);
+
+        // Everything is cached now
+        cache.get_transactions_batch(&txids).await.unwrap();
+        assert_eq!(cache.inner.batch_sizes(), vec![3]);
+    }
+
+    #[tokio::test]
+    async fn test_batch_forwards_duplicates_once() {
+        let txs: Vec<_> = (0..3).map(tx).collect();
+        let txids: Vec<_> = txs.iter().map(Transaction::compute_txid).collect();
+        let cache = CachingDataSource::new(CountingSource::with_txs(&txs), DEFAULT_TTL);
+
+        let batch = [txids[2], txids[0], txids[2], txids[1], txids[0]];
+        let results = cache.get_transactions_batch(&batch).await.unwrap();
+
+        assert_eq!(cache.inner.batch_sizes(), vec![3]);
+        let expected: Vec<_> = [2, 0, 2, 1, 0].map(|i| Some(txs[i].clone())).into();
+        assert_eq!(results, expected);
+        assert!(cache.get_transactions_batch(&[]).await.unwrap().is_empty());
+        assert_eq!(cache.inner.batch_sizes(), vec![3]);
+    }
+
+    #[tokio::test]
+    async fn test_batch_negatively_caches_unknown_txids() {
+        let known = tx(1);
+        let unknown = tx(2).compute_txid();
+        let cache = CachingDataSource::new(
+            CountingSource::with_txs(std::slice::from_ref(&known)),
+            DEFAULT_TTL,
+        );
+        let txids = [unknown, known.compute_txid()];
+
+        let results = cache.get_transactions_batch(&txids).await.unwrap();
+        assert_eq!(results, vec![None, Some(known)]);
+
+        cache.get_transactions_batch(&txids).await.unwrap();
+        assert!(matches!(
+            cache.get_transaction(unknown).await,
+            Err(BlockchainError::NotFound(_))
+        ));
+        assert_eq!(cache.inner.calls(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_batch_errors_are_not_cached() {
+        let source = CountingSource {
+            offline: true,
+            ..Default::default()
+        };
+        let cache = CachingDataSource::new(source, DEFAULT_TTL);
+        let txids = [tx(1).compute_txid()];
+
+        assert!(cache.get_transactions_batch(&txids).await.is_err());
+        assert!(cache.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_errors_keep_the_source_context() {
+        let txid = tx(1).compute_txid();
+        let expected = ErrorContext::new("get_transaction").txid(txid);
+
+        let offline = CachingDataSource::new(
+            CountingSource {
+                offline: true,
+                ..Default::default()
+            },
+            DEFAULT_TTL,
+        );
+        let error = offline.get_transaction(txid).await.unwrap_err();
+        assert_eq!(error.context(), Some(&expected));
+        assert!(matches!(error.inner(), BlockchainError::NetworkFailure(_)));
+
+        // A miss is still cached as not found under its context
+        let cache = CachingDataSource::new(CountingSource::default(), DEFAULT_TTL);
+        let error = cache.get_transaction(txid).await.unwrap_err();
+        assert_eq!(error.context(), Some(&expected));
+        assert!(matches!(error.inner(), BlockchainError::NotFound(_)));
+        assert!(cache.get_transaction(txid).await.is_err());
+        assert_eq!(cache.inner.calls(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_spending_batch_merges_in_order() {
+        let outpoints: Vec<_> = (0..4)
+            .map(|n| OutPoint::new(tx(n).compute_txid(), 0))
+            .collect();
+        let source = CountingSource::default();
+        source.spend(outpoints[1], tx(11));
+        source.spend(outpoints[2], tx(12));
+        let cache = CachingDataSource::builder(source)
+            .cache_unspent(Duration::from_secs(10))
+            .build();
+
+        cache.get_spending_transaction(outpoints[2]).await.unwrap();
+        cache.get_spending_transaction(outpoints[3]).await.unwrap();
+        let results = cache
+            .get_spending_transactions_batch(&outpoints)
+            .await
+            .unwrap();
+
+        assert_eq!(results, vec![None, Some(tx(11)), Some(tx(12)), None]);
+        assert_eq!(cache.inner.batch_sizes(), vec![2]);
+        // Four outpoints plus the two spenders under their own txids
+        assert_eq!(cache.len(), 6);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_address_history_uses_address_ttl() {
+        let address: Address = "bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq"
+            .parse::<Address<_>>()
+            .unwrap()
+            .assume_checked();
+        let cache = CachingDataSource::builder(CountingSource::with_txs(&[tx(1), tx(2)]))
+            .ttl(Duration::from_secs(300))
+            .address_ttl(Duration::from_secs(10))
+            .build();
+
+        let history = cache
+            .get_address_transactions(address.clone())
+            .await
+            .unwrap();
+        assert_eq!(history.len(), 2);
+        cache
+            .get_address_transactions(address.clone())
+            .await
+            .unwrap();
+        assert_eq!(cache.inner.calls(), 1);
+        assert_eq!(cache.stats().address.hits, 1);
+
+        tokio::time::advance(Duration::from_secs(11)).await;
+        cache.get_address_transactions(address).await.unwrap();
+        assert_eq!(cache.inner.calls(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_spender_is_cached_by_txid() {
+        let outpoint = OutPoint::new(tx(1).compute_txid(), 0);
+        let spender = tx(2);
+        let source = CountingSource::default();
+        source.spend(outpoint, spender.clone());
+        let cache = CachingDataSource::new(source, DEFAULT_TTL);
+
+        cache.get_spending_transaction(outpoint).await.unwrap();
+        let fetched = cache.get_transaction(spender.compute_txid()).await.unwrap();
+
+        assert_eq!(fetched, spender);
+        assert_eq!(cache.inner.calls(), 1);
+        assert_eq!(cache.stats().transaction.hits, 1);
+    }
+
+    #[tokio::test]
+    async fn test_is_cached_peeks_without_counting() {
+        let outpoint = OutPoint::new(tx(1).compute_txid(), 0);
+        let spender = tx(2);
+        let source = CountingSource::default();
+        source.spend(outpoint, spender.clone());
+        let cache = CachingDataSource::new(source, DEFAULT_TTL);
+        let key = CacheKey::Transaction(spender.compute_txid());
+
+        assert!(!cache.is_cached(&key));
+        cache.get_spending_transaction(outpoint).await.unwrap();
+
+        assert!(cache.is_cached(&key));
+        assert!(cache.is_cached(&CacheKey::Spending(outpoint)));
+        // Unspent outputs are not cached by default
+        let unspent = CacheKey::Spending(OutPoint::new(spender.compute_txid(), 0));
+        cache
+            .get_spending_transaction(OutPoint::new(spender.compute_txid(), 0))
+            .await
+            .unwrap();
+        assert!(!cache.is_cached(&unspent));
+        assert_eq!(cache.stats().transaction.hits, 0);
+        assert_eq!(cache.stats().transaction.misses, 0);
+    }
+
+    #[tokio::test]
+    async fn test_index_spenders_caches_prevouts() {
+        let prevout = OutPoint::new(tx(1).compute_txid(), 0);
+        let spender = Transaction {
+            input: vec![bitcoin::TxIn {
+                previous_output: prevout,
+                ..Default::default()
+            }],
+            ..tx(2)
+        };
+        let source = CountingSource::with_txs(std::slice::from_ref(&spender));
+
+        let cache = CachingDataSource::builder(source)
+            .index_spenders(true)
+            .build();
+        cache.get_transaction(spender.compute_txid()).await.unwrap();
+        let fetched = cache.get_spending_transaction(prevout).await.unwrap();
+        assert_eq!(fetched, Some(spender.clone()));
+        assert_eq!(cache.inner.calls(), 1);
+
+        // Off by default
+        let cache = CachingDataSource::new(
+            CountingSource::with_txs(std::slice::from_ref(&spender)),
+            DEFAULT_TTL,
+        );
+        cache.get_transaction(spender.compute_txid()).await.unwrap();
+        assert_eq!(cache.len(), 1);
+    }
+
+    /// Builds a transaction with `inputs` witness-carrying inputs
+    fn large_tx(inputs: usize) -> Transaction {
+        let input = bitcoin::TxIn {
+            witness: bitcoin::Witness::from_slice(&[vec![0u8; 72], vec![0u8; 33]]),
+            ..Default::default()
+        };
+        Transaction {
+            input: vec![input; inputs],
+            ..tx(inputs as u32)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_shared_hits_do_not_copy_the_transaction() {
+        let small = tx(1);
+        let large = large_tx(2_000);
+        let cache = CachingDataSource::new(
+            CountingSource::with_txs(&[small.clone(), large.clone()]),
+            DEFAULT_TTL,
+        );
+
+        for t in [&small, &large] {
+            let first = cache
+                .get_transaction_shared(t.compute_txid())
+                .await
+                .unwrap();
+            let hits: Vec<_> = shared_hits(&cache, t.compute_txid(), 1_000).await;
+
+            // Every hit is the same allocation, whatever the transaction size
+            assert!(hits.iter().all(|hit| Arc::ptr_eq(hit, &first)));
+            assert_eq!(Arc::strong_count(&first), 1_000 + 2);
+        }
+        assert_eq!(cache.inner.calls(), 2);
+    }
+
+    async fn shared_hits(
+        cache: &CachingDataSource<CountingSource>,
+        txid: Txid,
+        n: usize,
+    ) -> Vec<Arc<Transaction>> {
+        let mut hits = Vec::with_capacity(n);
+        for _ in 0..n {
+            hits.push(cache.get_transaction_shared(txid).await.unwrap());
+        }
+        hits
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_warms_a_new_cache() {
+        let txs: Vec<_> = (0..5).map(tx).collect();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cache.snapshot");
+
+        let cache = CachingDataSource::new(CountingSource::with_txs(&txs), DEFAULT_TTL);
+        for t in &txs {
+            cache.get_transaction(t.compute_txid()).await.unwrap();
+        }
+        cache.save_snapshot(&path).unwrap();
+
+        let warm = CachingDataSource::new(CountingSource::with_txs(&txs), DEFAULT_TTL);
+        let report = warm.load_snapshot(&path).unwrap();
+        assert_eq!(
+            report,
+            SnapshotReport {
+                loaded: 5,
+                skipped: 0
             }
-            // Entry expired, fetch it
+        );
+
+        for t in &txs {
+            assert_eq!(&warm.get_transaction(t.compute_txid()).await.unwrap(), t);
         }
+        assert_eq!(warm.inner.calls(), 0);
+    }
 
-        // cache miss or expired, fetch Transaction from source
-        let tx = self.inner.get_spending_transaction(outpoint).await?;
+    #[tokio::test]
+    async fn test_invalidated_entries_are_refetched() {
+        let funding = tx(1);
+        let outpoint = OutPoint::new(funding.compute_txid(), 0);
+        let address: Address = "bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq"
+            .parse::<Address<_>>()
+            .unwrap()
+            .assume_checked();
+        let source = CountingSource::with_txs(std::slice::from_ref(&funding));
+        source.spend(outpoint, tx(2));
+        let cache = CachingDataSource::new(source, DEFAULT_TTL);
+
+        cache.get_transaction(funding.compute_txid()).await.unwrap();
+        cache.get_spending_transaction(outpoint).await.unwrap();
+        cache
+            .get_address_transactions(address.clone())
+            .await
+            .unwrap();
+        assert_eq!(cache.inner.calls(), 3);
+
+        assert!(cache.invalidate_transaction(funding.compute_txid()));
+        assert!(!cache.invalidate_transaction(funding.compute_txid()));
+        assert!(cache.invalidate_spending(outpoint));
+        assert!(cache.invalidate_address(&address));
+
+        cache.get_transaction(funding.compute_txid()).await.unwrap();
+        cache.get_spending_transaction(outpoint).await.unwrap();
+        cache.get_address_transactions(address).await.unwrap();
+        assert_eq!(cache.inner.calls(), 6);
+    }
+
+    #[tokio::test]
+    async fn test_invalidate_block_drops_only_spending_entries() {
+        let outpoints: Vec<_> = (0..3)
+            .map(|n| OutPoint::new(tx(n).compute_txid(), 0))
+            .collect();
+        let source = CountingSource::with_txs(&[tx(10)]);
+        source.spend(outpoints[0], tx(11));
+        let cache = CachingDataSource::builder(source)
+            .cache_unspent(Duration::from_secs(60))
+            .build();
+
+        cache.get_transaction(tx(10).compute_txid()).await.unwrap();
+        for outpoint in &outpoints {
+            cache.get_spending_transaction(*outpoint).await.unwrap();
+        }
+        // tx(10), three outpoints and tx(11) under its own txid
+        assert_eq!(cache.len(), 5);
+
+        assert!(cache.invalidate_block(BlockHash::all_zeros()));
+        assert_eq!(cache.len(), 2);
+        assert!(!cache.invalidate_block(BlockHash::all_zeros()));
+
+        let calls = cache.inner.calls();
+        cache.get_spending_transaction(outpoints[1]).await.unwrap();
+        assert_eq!(cache.inner.calls(), calls + 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_ttl_jitter_spreads_expiries() {
+        let txs: Vec<_> = (0..200).map(tx).collect();
+        let cache = CachingDataSource::builder(CountingSource::with_txs(&txs))
+            .ttl(Duration::from_secs(100))
+            .ttl_jitter(0.1)
+            .jitter_seed(7)
+            .build();
+        for t in &txs {
+            cache.get_transaction(t.compute_txid()).await.unwrap();
+        }
+        let expired = || cache.cache.expired_keys().len();
+
+        // Nothing expires before 90s, everything has by 110s
+        tokio::time::advance(Duration::from_millis(89_999)).await;
+        assert_eq!(expired(), 0);
+
+        // In between, expiries are spread out rather than landing all at once
+        let mut seen = Vec::new();
+        for _ in 0..4 {
+            tokio::time::advance(Duration::from_secs(5)).await;
+            seen.push(expired());
+        }
+        assert!(seen.windows(2).all(|w| w[0] < w[1]), "{:?}", seen);
+        assert!(seen[0] > 0 && seen[2] < txs.len(), "{:?}", seen);
+
+        tokio::time::advance(Duration::from_secs(1)).await;
+        assert_eq!(expired(), txs.len());
+    }
+
+    #[test]
+    fn test_jitter_ignores_non_finite_fractions_and_keeps_huge_ttls() {
+        assert!(Jitter::new(f64::NAN, None).is_none());
+        assert!(Jitter::new(f64::INFINITY, None).is_none());
+        assert!(Jitter::new(-0.5, None).is_none());
+
+        let jitter = Jitter::new(0.5, Some(7)).unwrap();
+        let spread: Vec<_> = (0..100).map(|_| jitter.apply(Duration::MAX)).collect();
+        // Spreading past the longest Duration keeps the TTL instead of panicking
+        assert!(spread.contains(&Duration::MAX));
+        assert!(spread.iter().any(|&ttl| ttl < Duration::MAX));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_no_jitter_by_default() {
+        let txs: Vec<_> = (0..50).map(tx).collect();
+        let cache =
+            CachingDataSource::new(CountingSource::with_txs(&txs), Duration::from_secs(100));
+        for t in &txs {
+            cache.get_transaction(t.compute_txid()).await.unwrap();
+        }
+
+        tokio::time::advance(Duration::from_millis(99_999)).await;
+        assert_eq!(cache.cache.expired_keys().len(), 0);
+        tokio::time::advance(Duration::from_millis(1)).await;
+        assert_eq!(cache.cache.expired_keys().len(), txs.len());
+    }
+
+    #[tokio::test]
+    async fn test_stats_report_cached_bytes() {
+        let txs: Vec<_> = (0..3).map(tx).collect();
+        let cache = CachingDataSource::builder(CountingSource::with_txs(&txs))
+            .max_entries(10)
+            .max_bytes(1_000_000)
+            .build();
+
+        for t in &txs {
+            cache.get_transaction(t.compute_txid()).await.unwrap();
+        }
+        let expected: usize = txs.iter().map(Transaction::total_size).sum();
+        assert_eq!(cache.stats().weight_bytes, expected as u64);
+        assert_eq!(cache.cache.max_entries(), Some(10));
+        assert_eq!(cache.cache.max_bytes(), Some(1_000_000));
+    }
+
+    #[tokio::test]
+    async fn test_prefetch_skips_cached_and_counts_outcomes() {
+        let txs: Vec<_> = (0..250).map(tx).collect();
+        let mut txids: Vec<_> = txs.iter().map(Transaction::compute_txid).collect();
+        let cache = CachingDataSource::new(CountingSource::with_txs(&txs), DEFAULT_TTL);
+        cache.get_transaction(txids[0]).await.unwrap();
+        txids.push(tx(999).compute_txid());
+        txids.push(txids[1]);
+
+        let summary = cache.prefetch_transactions(&txids).await;
+
+        assert_eq!(
+            summary,
+            PrefetchSummary {
+                fetched: 249,
+                already_cached: 1,
+                not_found: 1,
+                failed: 0,
+            }
+        );
+        let mut sizes = cache.inner.batch_sizes();
+        sizes.sort();
+        assert_eq!(sizes, vec![50, 100, 100]);
+
+        let calls = cache.inner.calls();
+        for txid in &txids {
+            let _ = cache.get_transaction(*txid).await;
+        }
+        assert_eq!(cache.inner.calls(), calls);
+    }
+
+    #[tokio::test]
+    async fn test_prefetch_failures_do_not_abort() {
+        let source = CountingSource {
+            offline: true,
+            ..Default::default()
+        };
+        let cache = CachingDataSource::new(source, DEFAULT_TTL);
+        let txids: Vec<_> = (0..150).map(|n| tx(n).compute_txid()).collect();
+
+        let summary = cache.prefetch_transactions(&txids).await;
+
+        assert_eq!(summary.failed, 150);
+        assert_eq!(cache.inner.batch_sizes().len(), 2);
+        assert!(cache.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_prefetch_spending() {
+        let outpoints: Vec<_> = (0..3)
+            .map(|n| OutPoint::new(tx(n).compute_txid(), 0))
+            .collect();
+        let source = CountingSource::default();
+        source.spend(outpoints[0], tx(10));
+        let cache = CachingDataSource::new(source, DEFAULT_TTL);
+
+        let summary = cache.prefetch_spending(&outpoints).await;
+
+        assert_eq!((summary.fetched, summary.not_found), (1, 2));
+        assert_eq!(
+            cache.get_spending_transaction(outpoints[0]).await.unwrap(),
+            Some(tx(10))
+        );
+        assert_eq!(cache.inner.calls(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_clones_share_entries_and_stats() {
+        let txs: Vec<_> = (0..2).map(tx).collect();
+        let cache = CachingDataSource::new(CountingSource::with_txs(&txs), DEFAULT_TTL);
+        let clone = cache.clone();
+
+        cache.get_transaction(txs[0].compute_txid()).await.unwrap();
+        clone.get_transaction(txs[0].compute_txid()).await.unwrap();
+        clone.get_transaction(txs[1].compute_txid()).await.unwrap();
+        cache.get_transaction(txs[1].compute_txid()).await.unwrap();
+
+        assert_eq!(cache.inner.calls(), 2);
+        assert_eq!(cache.len(), 2);
+        assert_eq!(clone.stats(), cache.stats());
+        assert_eq!(cache.stats().transaction.hits, 2);
+
+        let task = tokio::spawn({
+            let clone = clone.clone();
+            let txid = txs[0].compute_txid();
+            async move { clone.get_transaction(txid).await }
+        });
+        task.await.unwrap().unwrap();
+        assert_eq!(cache.inner.calls(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_shared_and_borrowed_sources_compose() {
+        let target = tx(1);
+        let source = Arc::new(CountingSource::with_txs(std::slice::from_ref(&target)));
+
+        let by_arc = CachingDataSource::new(Arc::clone(&source), DEFAULT_TTL);
+        let by_ref = CachingDataSource::new(source.as_ref(), DEFAULT_TTL);
+        by_arc.get_transaction(target.compute_txid()).await.unwrap();
+        by_ref.get_transaction(target.compute_txid()).await.unwrap();
+
+        // Separate caches over the same source
+        assert_eq!(source.calls(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_unspent_not_cached_by_default() {
+        let cache = CachingDataSource::new(CountingSource::default(), DEFAULT_TTL);
+        let outpoint = OutPoint::new(tx(1).compute_txid(), 0);
+
+        assert!(
+            cache
+                .get_spending_transaction(outpoint)
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert!(
+            cache
+                .get_spending_transaction(outpoint)
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert_eq!(cache.inner.calls(), 2);
+    }
+
+    /// The decorator behaves the same on top of moka: hits, negative hits, expiry
+    #[cfg(feature = "moka-cache")]
+    #[tokio::test]
+    async fn test_moka_backend_serves_the_decorator() {
+        let found = tx(1);
+        let missing = tx(2).compute_txid();
+        let clock = MockClock::new();
+        let cache =
+            CachingDataSource::builder(CountingSource::with_txs(std::slice::from_ref(&found)))
+                .backend(MokaBackend::new())
+                .clock(Arc::new(clock.clone()))
+                .max_entries(1_000)
+                .ttl(Duration::from_secs(60))
+                .build();
+
+        for _ in 0..2 {
+            cache.get_transaction(found.compute_txid()).await.unwrap();
+            assert!(cache.get_transaction(missing).await.is_err());
+        }
+        assert_eq!(cache.inner.calls(), 2);
+        clock.advance(Duration::from_secs(61));
+        cache.get_transaction(found.compute_txid()).await.unwrap();
+
+        let stats = cache.stats();
+        assert_eq!(cache.inner.calls(), 3);
+        assert_eq!(stats.transaction.hits, 1);
+        assert_eq!(stats.transaction.negative_hits, 1);
+        assert_eq!(stats.transaction.expired_hits, 1);
+        assert_eq!(stats.weight_bytes, found.total_size() as u64);
+    }
+
+    #[tokio::test]
+    async fn test_size_policy_skips_oversized_transactions() {
+        let (small, big) = (tx(1), large_tx(50));
+        let cache = CachingDataSource::new(
+            CountingSource::with_txs(&[small.clone(), big.clone()]),
+            DEFAULT_TTL,
+        )
+        .with_policy(MaxEntrySize(1_000));
+        assert!(big.total_size() > 1_000);
+
+        for _ in 0..2 {
+            cache.get_transaction(small.compute_txid()).await.unwrap();
+            assert_eq!(
+                cache.get_transaction(big.compute_txid()).await.unwrap(),
+                big
+            );
+        }
+
+        // The oversized transaction is refetched every time
+        assert_eq!(cache.inner.calls(), 3);
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.stats().transaction.rejected, 2);
+        assert_eq!(cache.stats().transaction.insertions, 1);
+    }
+
+    #[tokio::test]
+    async fn test_status_policy_skips_spend_status() {
+        let funding = tx(1);
+        let outpoint = OutPoint::new(funding.compute_txid(), 0);
+        let spender = tx(2);
+        let source = CountingSource::with_txs(std::slice::from_ref(&funding));
+        source.spend(outpoint, spender.clone());
+        // Spend status can change under a replacement, only keep transactions by txid
+        let cache = CachingDataSource::builder(source)
+            .cache_unspent(DEFAULT_TTL)
+            .policy(
+                |key: &CacheKey, entry: &CachedEntry, _: Option<&TxStatus>| {
+                    matches!(key, CacheKey::Transaction(_))
+                        && matches!(entry, CachedEntry::Found(_))
+                },
+            )
+            .build();
+
+        let unspent = OutPoint::new(funding.compute_txid(), 1);
+        let missing = tx(3).compute_txid();
+        for _ in 0..2 {
+            cache.get_spending_transaction(outpoint).await.unwrap();
+            assert!(
+                cache
+                    .get_spending_transaction(unspent)
+                    .await
+                    .unwrap()
+                    .is_none()
+            );
+            assert!(cache.get_transaction(missing).await.is_err());
+        }
+        // The spender still lands under its own txid
+        cache.get_transaction(spender.compute_txid()).await.unwrap();
+
+        assert_eq!(cache.inner.calls(), 6);
+        assert_eq!(cache.len(), 1);
+        let stats = cache.stats();
+        assert_eq!(stats.spending.rejected, 4);
+        assert_eq!(stats.transaction.rejected, 2);
+        assert_eq!(stats.transaction.hits, 1);
+    }
+
+    #[tokio::test]
+    async fn test_status_policy_skips_unconfirmed_transactions() {
+        let (confirmed, pending) = (tx(1), tx(2));
+        let source = CountingSource {
+            unconfirmed: HashSet::from([pending.compute_txid()]),
+            ..CountingSource::with_txs(&[confirmed.clone(), pending.clone()])
+        };
+        let cache = CachingDataSource::builder(source)
+            .check_status(true)
+            .policy(|_: &CacheKey, _: &CachedEntry, status: Option<&TxStatus>| {
+                status.is_some_and(|status| status.confirmed)
+            })
+            .build();
+
+        for _ in 0..2 {
+            cache
+                .get_transaction(confirmed.compute_txid())
+                .await
+                .unwrap();
+            cache.get_transaction(pending.compute_txid()).await.unwrap();
+        }
+
+        // A transaction and a status lookup each time the pending one is fetched
+        assert_eq!(cache.inner.calls(), 6);
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.stats().transaction.rejected, 2);
+        assert_eq!(cache.stats().transaction.hits, 1);
+    }
+
+    #[tokio::test]
+    async fn test_unconfirmed_transactions_expire_on_the_unconfirmed_ttl() {
+        let (confirmed, pending) = (tx(1), tx(2));
+        let spent = OutPoint::new(confirmed.compute_txid(), 0);
+        let source = CountingSource {
+            unconfirmed: HashSet::from([pending.compute_txid()]),
+            ..CountingSource::with_txs(&[confirmed.clone(), pending.clone()])
+        };
+        source.spend(spent, pending.clone());
+        let clock = MockClock::new();
+        let cache = CachingDataSource::builder(source)
+            .clock(Arc::new(clock.clone()))
+            .check_status(true)
+            .ttl_policy(TtlPolicy {
+                unconfirmed: Some(Duration::from_secs(10)),
+                ..TtlPolicy::immutable_transactions(Duration::from_secs(300))
+            })
+            .build();
+
+        cache
+            .get_transaction(confirmed.compute_txid())
+            .await
+            .unwrap();
+        cache.get_spending_transaction(spent).await.unwrap();
+        cache.get_transaction(pending.compute_txid()).await.unwrap();
+        assert_eq!(cache.inner.calls(), 4);
+
+        // The pending spender, also cached under its txid, expires on the short TTL
+        clock.advance(Duration::from_secs(10));
+        cache
+            .get_transaction(confirmed.compute_txid())
+            .await
+            .unwrap();
+        assert_eq!(cache.inner.calls(), 4);
+        cache.get_spending_transaction(spent).await.unwrap();
+        cache.get_transaction(pending.compute_txid()).await.unwrap();
+        assert_eq!(cache.inner.calls(), 6);
+        assert_eq!(cache.stats().spending.expired_hits, 1);
+        assert_eq!(cache.stats().transaction.hits, 3);
+
+        // Without a status to go by, the transaction TTL applies
+        let source = CountingSource::with_txs(std::slice::from_ref(&pending));
+        let clock = MockClock::new();
+        let cache = CachingDataSource::builder(source)
+            .clock(Arc::new(clock.clone()))
+            .ttl_policy(TtlPolicy::immutable_transactions(Duration::from_secs(300)))
+            .build();
+        cache.get_transaction(pending.compute_txid()).await.unwrap();
+        clock.advance(Duration::from_secs(24 * 3600));
+        cache.get_transaction(pending.compute_txid()).await.unwrap();
+        assert_eq!(cache.inner.calls(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_unspent_cached_until_expiry_then_spent_path_takes_over() {
+        let cache = CachingDataSource::builder(CountingSource::default())
+            .cache_unspent(Duration::from_secs(60))
+            .build();
+        let outpoint = OutPoint::new(tx(1).compute_txid(), 0);
+        let spender = tx(2);
+
+        assert!(
+            cache
+                .get_spending_transaction(outpoint)
+                .await
+                .unwrap()
+                .is_none()
+        );
+        cache.inner.spend(outpoint, spender.clone());
+
+        // Still within the unspent window, served from cache
+        assert!(
+            cache
+                .get_spending_transaction(outpoint)
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert_eq!(cache.inner.calls(), 1);
+
+        tokio::time::advance(Duration::from_secs(61)).await;
+        let found = cache.get_spending_transaction(outpoint).await.unwrap();
+        assert_eq!(found, Some(spender.clone()));
+        assert_eq!(cache.inner.calls(), 2);
+
+        // The spender replaced the unspent marker and uses the regular TTL
+        tokio::time::advance(Duration::from_secs(61)).await;
+        let found = cache.get_spending_transaction(outpoint).await.unwrap();
+        assert_eq!(found, Some(spender));
+        assert_eq!(cache.inner.calls(), 2);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_misses_are_coalesced() {
+        let target = tx(7);
+        let source = CountingSource {
+            delay: Some(Duration::from_millis(50)),
+            ..CountingSource::with_txs(std::slice::from_ref(&target))
+        };
+        let cache = Arc::new(CachingDataSource::new(source, DEFAULT_TTL));
+        let txid = target.compute_txid();
+
+        let tasks: Vec<_> = (0..50)
+            .map(|_| {
+                let cache = Arc::clone(&cache);
+                tokio::spawn(async move { cache.get_transaction(txid).await })
+            })
+            .collect();
+        for task in tasks {
+            assert_eq!(task.await.unwrap().unwrap(), target);
+        }
+
+        assert_eq!(cache.inner.calls(), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_coalesced_errors_reach_all_waiters_and_are_not_cached() {
+        let source = CountingSource {
+            delay: Some(Duration::from_millis(50)),
+            offline: true,
+            ..Default::default()
+        };
+        let cache = Arc::new(CachingDataSource::new(source, DEFAULT_TTL));
+        let txid = tx(7).compute_txid();
+
+        let tasks: Vec<_> = (0..50)
+            .map(|_| {
+                let cache = Arc::clone(&cache);
+                tokio::spawn(async move { cache.get_transaction(txid).await })
+            })
+            .collect();
+        for task in tasks {
+            let error = task.await.unwrap().unwrap_err();
+            assert!(matches!(error.inner(), BlockchainError::NetworkFailure(_)));
+            assert!(error.context().is_some());
+        }
+        assert_eq!(cache.inner.calls(), 1);
+
+        let _ = cache.get_transaction(txid).await;
+        assert_eq!(cache.inner.calls(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_stats_count_scripted_sequence() {
+        let present = tx(1);
+        let cache =
+            CachingDataSource::builder(CountingSource::with_txs(std::slice::from_ref(&present)))
+                .ttl(Duration::from_secs(60))
+                .negative_ttl(Duration::from_secs(10))
+                .max_entries(2)
+                .build();
+        let txid = present.compute_txid();
+        let missing = tx(2).compute_txid();
+        let outpoint = OutPoint::new(txid, 0);
+
+        cache.get_transaction(txid).await.unwrap(); // miss + insert
+        cache.get_transaction(txid).await.unwrap(); // hit
+        let _ = cache.get_transaction(missing).await; // miss + negative insert
+        let _ = cache.get_transaction(missing).await; // negative hit
+        cache.get_spending_transaction(outpoint).await.unwrap(); // miss, unspent not cached
+
+        tokio::time::advance(Duration::from_secs(61)).await;
+        cache.get_transaction(txid).await.unwrap(); // expired + insert
+
+        let stats = cache.stats();
+        assert_eq!(
+            stats.transaction,
+            KindStats {
+                hits: 1,
+                negative_hits: 1,
+                misses: 2,
+                expired_hits: 1,
+                insertions: 3,
+                evictions: 0,
+                rejected: 0,
+            }
+        );
+        assert_eq!(
+            stats.spending,
+            KindStats {
+                misses: 1,
+                ..Default::default()
+            }
+        );
+        assert_eq!(stats.total().lookups(), 6);
+
+        cache.reset_stats();
+        // The cached weight describes the current contents and survives a reset
+        let stats = cache.stats();
+        assert_eq!(stats.weight_bytes, present.total_size() as u64);
+        assert_eq!(
+            stats,
+            CacheStats {
+                weight_bytes: stats.weight_bytes,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_stats_count_evictions() {
+        let txs: Vec<_> = (0..5).map(tx).collect();
+        let cache = CachingDataSource::builder(CountingSource::with_txs(&txs))
+            .max_entries(2)
+            .build();
+
+        for t in &txs {
+            cache.get_transaction(t.compute_txid()).await.unwrap();
+        }
+
+        let stats = cache.stats().transaction;
+        assert_eq!(stats.insertions, 5);
+        assert_eq!(stats.evictions, 3);
+    }
+
+    #[cfg(feature = "persistent-cache")]
+    #[tokio::test]
+    async fn test_persistent_backend_hits_survive_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cache");
+        let target = tx(9);
+        let txid = target.compute_txid();
 
-        // Update cache only if we got a transaction (write lock)
-        // Note: None (unspent) is not cached to avoid stale data
         {
-            if let Some(ref transaction) = tx {
-                let mut cache = self.cache.write().unwrap();
-                cache.insert(
-                    key,
-                    CachedEntry {
-                        transaction: transaction.clone(),
-                        inserted_at: Instant::now(),
-                    },
-                );
-            } // Write lock released (or skipped if None)
+            let cache =
+                CachingDataSource::builder(CountingSource::with_txs(std::slice::from_ref(&target)))
+                    .backend(SledBackend::open(&path).unwrap())
+                    .build();
+            cache.get_transaction(txid).await.unwrap();
+            assert_eq!(cache.inner.calls(), 1);
         }
-        Ok(tx)
+
+        let cache = CachingDataSource::builder(CountingSource::default())
+            .backend(SledBackend::open(&path).unwrap())
+            .build();
+        assert_eq!(cache.get_transaction(txid).await.unwrap(), target);
+        assert_eq!(cache.inner.calls(), 0);
     }
-    async fn get_address_transactions(&self, _address: Address) -> Result<Vec<Transaction>> {
-        todo!()
+
+    #[tokio::test]
+    async fn test_poisoned_cache_lock_does_not_panic() {
+        let txs: Vec<_> = (0..2).map(tx).collect();
+        let cache = CachingDataSource::new(CountingSource::with_txs(&txs), DEFAULT_TTL);
+        let txid = txs[0].compute_txid();
+        let outpoint = OutPoint::new(txid, 0);
+
+        cache.get_transaction(txid).await.unwrap();
+        cache.cache.poison();
+
+        // Poisoned read is a miss, the write that follows recovers the lock
+        assert_eq!(cache.get_transaction(txid).await.unwrap(), txs[0]);
+        assert!(
+            cache
+                .get_spending_transaction(outpoint)
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert_eq!(cache.inner.calls(), 3);
+
+        // Back to normal caching
+        cache.get_transaction(txid).await.unwrap();
+        assert_eq!(cache.inner.calls(), 3);
+        assert_eq!(cache.len(), 1);
     }
-    async fn get_transactions_batch(&self, _txids: &[Txid]) -> Result<Vec<Option<Transaction>>> {
-        todo!()
+
+    #[tokio::test]
+    async fn test_poisoned_inflight_lock_does_not_panic() {
+        let target = tx(1);
+        let cache = CachingDataSource::new(
+            CountingSource::with_txs(std::slice::from_ref(&target)),
+            DEFAULT_TTL,
+        );
+
+        std::thread::scope(|scope| {
+            let _ = scope
+                .spawn(|| {
+                    let _guard = cache.inflight.lock().unwrap();
+                    panic!("poisoning the in-flight lock");
+                })
+                .join();
+        });
+        assert!(cache.inflight.is_poisoned());
+
+        let txid = target.compute_txid();
+        assert_eq!(cache.get_transaction(txid).await.unwrap(), target);
+        assert_eq!(cache.get_transaction(txid).await.unwrap(), target);
+        assert_eq!(cache.inner.calls(), 1);
     }
-    async fn get_spending_transactions_batch(
-        &self,
-        _outpoints: &[OutPoint],
-    ) -> Result<Vec<Option<Transaction>>> {
-        todo!()
+
+    #[tokio::test(start_paused = true)]
+    async fn test_janitor_removes_expired_entries_without_reads() {
+        let txs: Vec<_> = (0..5).map(tx).collect();
+        let cache = CachingDataSource::new(CountingSource::with_txs(&txs), Duration::from_secs(5));
+        let _janitor = cache.spawn_janitor(Duration::from_secs(1));
+
+        for t in &txs[..3] {
+            cache.get_transaction(t.compute_txid()).await.unwrap();
+        }
+        tokio::time::sleep(Duration::from_secs(3)).await;
+        for t in &txs[3..] {
+            cache.get_transaction(t.compute_txid()).await.unwrap();
+        }
+        assert_eq!(cache.len(), 5);
+
+        // First three expire at t=5s, the last two at t=8s
+        tokio::time::sleep(Duration::from_secs(3)).await;
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.stats().last_sweep_removed, 3);
+
+        tokio::time::sleep(Duration::from_secs(3)).await;
+        assert!(cache.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_dropping_janitor_handle_stops_sweeps() {
+        let txs: Vec<_> = (0..2).map(tx).collect();
+        let cache = CachingDataSource::new(CountingSource::with_txs(&txs), Duration::from_secs(1));
+        let janitor = cache.spawn_janitor(Duration::from_secs(1));
+        drop(janitor);
+
+        for t in &txs {
+            cache.get_transaction(t.compute_txid()).await.unwrap();
+        }
+        tokio::time::sleep(Duration::from_secs(5)).await;
+        assert_eq!(cache.len(), 2);
     }
 }
@@ -0,0 +1,831 @@
+//! Storage backends for `CachingDataSource`.
+//!
+//! `CacheBackend` is the storage interface the caching decorator is generic over.
+//! `MemoryBackend` is the default in-memory implementation with optional LRU bound;
+//! `SledBackend` (behind the `persistent-cache` feature) keeps entries on disk across runs;
+//! `MokaBackend` (behind the `moka-cache` feature) bounds memory with moka's TinyLFU.
+
+use super::{CacheKey, CachedEntry};
+use crate::time::{self, Clock, Instant};
+use std::{
+    collections::{BTreeMap, HashMap},
+    hash::{BuildHasher, RandomState},
+    sync::{
+        Arc, RwLock, RwLockReadGuard, RwLockWriteGuard,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+/// Result of looking a key up in a `CacheBackend`
+#[derive(Debug, Clone, PartialEq)]
+pub enum CacheLookup {
+    /// Entry present and within its TTL
+    Fresh(CachedEntry),
+    /// Entry present but past its TTL
+    Expired,
+    /// No entry for this key
+    Missing,
+}
+
+/// Storage used by `CachingDataSource`.
+///
+/// Backends own the TTL bookkeeping: `insert` receives the TTL decided by the cache and
+/// `get` reports whether the entry is still fresh. All methods take `&self`, so
+/// implementations handle their own synchronization, and must never block on I/O for
+/// long since they are called from async code.
+///
+/// Storage failures are not surfaced: a backend that cannot read an entry reports it as
+/// `Missing`, and a failed insert is dropped. A cache failing should only ever cost a
+/// refetch.
+pub trait CacheBackend: Send + Sync {
+    /// Looks up an entry, marking it as recently used if the backend tracks recency
+    fn get(&self, key: &CacheKey) -> CacheLookup;
+
+    /// Stores an entry valid for `ttl` (forever if `None`), replacing any previous entry
+    /// for the key.
+    ///
+    /// Returns the keys evicted to make room, if the backend is bounded.
+    fn insert(&self, key: CacheKey, entry: CachedEntry, ttl: Option<Duration>) -> Vec<CacheKey>;
+
+    /// Stores several entries at once, as if by `insert` for each in order.
+    ///
+    /// Backends with locking override this to take their lock only once. Returns the
+    /// keys evicted to make room.
+    fn insert_many(
+        &self,
+        entries: Vec<(CacheKey, CachedEntry, Option<Duration>)>,
+    ) -> Vec<CacheKey> {
+        entries
+            .into_iter()
+            .flat_map(|(key, entry, ttl)| self.insert(key, entry, ttl))
+            .collect()
+    }
+
+    /// Removes an entry, returning whether one was present
+    fn remove(&self, key: &CacheKey) -> bool;
+
+    /// Removes every entry whose key matches, returning how many were removed
+    fn remove_matching(&self, matches: &dyn Fn(&CacheKey) -> bool) -> usize;
+
+    /// Number of stored entries, including expired entries not yet removed
+    fn len(&self) -> usize;
+
+    /// Total `CachedEntry::weight` of the stored entries, or 0 if the backend does not
+    /// track it
+    fn weight(&self) -> usize {
+        0
+    }
+
+    /// Returns true if no entries are stored
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Removes every entry
+    fn clear(&self);
+
+    /// Keys of all entries past their TTL
+    fn expired_keys(&self) -> Vec<CacheKey>;
+
+    /// All fresh entries with the time they have left (`None` = never expires)
+    fn fresh_entries(&self) -> Vec<(CacheKey, CachedEntry, Option<Duration>)>;
+
+    /// Removes those of `keys` that are still expired, returning how many were removed.
+    ///
+    /// Entries refreshed since `expired_keys` reported them are kept. Called by the
+    /// janitor with small batches so a sweep never holds the storage for long.
+    fn remove_expired(&self, keys: &[CacheKey]) -> usize {
+        keys.iter()
+            .filter(|key| self.get(key) == CacheLookup::Expired && self.remove(key))
+            .count()
+    }
+}
+
+/// A `CachedEntry` stored in the map with its TTL bookkeeping.
+///
+/// # Fields
+/// * `entry` - the cached result
+/// * `inserted_at` - timestamp for TTL cechking
+/// * `ttl` - how long this entry stays valid (depends on the entry kind, `None` = forever)
+/// * `last_used` - recency stamp, bumped on every hit under the read lock
+/// * `indexed_at` - stamp under which the entry is currently filed in the LRU index
+/// * `weight` - serialized size of the entry's transactions, see `CachedEntry::weight`
+#[derive(Debug)]
+struct Slot {
+    entry: CachedEntry,
+    weight: usize,
+    inserted_at: Instant,
+    ttl: Option<Duration>,
+    last_used: AtomicU64,
+    indexed_at: u64,
+}
+
+impl Slot {
+    fn is_fresh(&self, now: Instant) -> bool {
+        self.ttl
+            .is_none_or(|ttl| now.saturating_duration_since(self.inserted_at) < ttl)
+    }
+
+    /// Time left before expiry as of `now`, `None` if the entry never expires
+    fn remaining(&self, now: Instant) -> Option<Duration> {
+        self.ttl
+            .map(|ttl| ttl.saturating_sub(now.saturating_duration_since(self.inserted_at)))
+    }
+}
+
+/// HashMap with a generation-based LRU index.
+///
+/// Every access takes a fresh stamp from `tick` and stores it in the entry's atomic
+/// `last_used`, so hits only need the read lock. The `recency` index is only touched
+/// under the write lock: when evicting, the oldest indexed stamp is popped and if the
+/// entry was used since it was filed, it is re-filed under its newer stamp instead of
+/// being evicted.
+///
+/// `weight` is the sum of the weights of all entries, kept up to date on every change.
+#[derive(Debug, Default)]
+struct LruMap {
+    entries: HashMap<CacheKey, Slot>,
+    recency: BTreeMap<u64, CacheKey>,
+    tick: AtomicU64,
+    weight: usize,
+}
+
+/// Bounds enforced by `LruMap::insert`, `None` = unbounded
+#[derive(Debug, Default, Clone, Copy)]
+struct Limits {
+    entries: Option<usize>,
+    bytes: Option<usize>,
+}
+
+impl Limits {
+    fn exceeded_by(&self, map: &LruMap) -> bool {
+        self.entries.is_some_and(|max| map.entries.len() > max)
+            || self.bytes.is_some_and(|max| map.weight > max)
+    }
+}
+
+impl LruMap {
+    fn next_stamp(&self) -> u64 {
+        self.tick.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Looks up an entry, marking it as recently used if fresh as of `now`.
+    fn get(&self, key: &CacheKey, now: Instant) -> CacheLookup {
+        match self.entries.get(key) {
+            Some(slot) if slot.is_fresh(now) => {
+                slot.last_used.store(self.next_stamp(), Ordering::Relaxed);
+                CacheLookup::Fresh(slot.entry.clone())
+            }
+            Some(_) => CacheLookup::Expired,
+            None => CacheLookup::Missing,
+        }
+    }
+
+    /// Inserts an entry at `now` then evicts least recently used entries until both
+    /// `limits` hold again. An entry heavier than the byte limit on its own is evicted
+    /// too.
+    ///
+    /// Returns the keys that were evicted.
+    fn insert(
+        &mut self,
+        key: CacheKey,
+        entry: CachedEntry,
+        ttl: Option<Duration>,
+        limits: Limits,
+        now: Instant,
+    ) -> Vec<CacheKey> {
+        let stamp = self.next_stamp();
+        let weight = entry.weight();
+        self.weight += weight;
+        let slot = Slot {
+            entry,
+            weight,
+            inserted_at: now,
+            ttl,
+            last_used: AtomicU64::new(stamp),
+            indexed_at: stamp,
+        };
+        if let Some(old) = self.entries.insert(key.clone(), slot) {
+            self.recency.remove(&old.indexed_at);
+            self.weight -= old.weight;
+        }
+        self.recency.insert(stamp, key);
+
+        let mut evicted = Vec::new();
+        while limits.exceeded_by(self) {
+            match self.evict_one() {
+                Some(key) => evicted.push(key),
+                None => break,
+            }
+        }
+        evicted
+    }
+
+    fn evict_one(&mut self) -> Option<CacheKey> {
+        while let Some((stamp, key)) = self.recency.pop_first() {
+            let Some(slot) = self.entries.get_mut(&key) else {
+                continue;
+            };
+            let last_used = slot.last_used.load(Ordering::Relaxed);
+            if last_used == stamp {
+                if let Some(slot) = self.entries.remove(&key) {
+                    self.weight -= slot.weight;
+                }
+                return Some(key);
+            }
+            // Touched since it was filed, give it a second chance under its newer stamp
+            slot.indexed_at = last_used;
+            self.recency.insert(last_used, key);
+        }
+        None
+    }
+
+    fn remove(&mut self, key: &CacheKey) -> bool {
+        match self.entries.remove(key) {
+            Some(slot) => {
+                self.recency.remove(&slot.indexed_at);
+                self.weight -= slot.weight;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.recency.clear();
+        self.weight = 0;
+    }
+}
+
+/// Number of shards of an unbounded `MemoryBackend`
+pub const DEFAULT_SHARDS: usize = 16;
+
+/// Bounded backends only shard when every shard can hold at least this many entries,
+/// small caches keep a single exact LRU instead
+const MIN_ENTRIES_PER_SHARD: usize = 256;
+
+/// Same as `MIN_ENTRIES_PER_SHARD` for the byte bound, comfortably above the largest
+/// standard transaction
+const MIN_BYTES_PER_SHARD: usize = 16 * 1024 * 1024;
+
+/// In-memory `CacheBackend`, the default for `CachingDataSource`.
+///
+/// Entries are spread over independent `RwLock` shards by key hash, so lookups and
+/// inserts on different keys proceed in parallel:
+/// - Read locks for cache lookups (allows concurrent reads)
+/// - Write locks for cache inserts (exclusive over one shard only)
+///
+/// When created with `max_entries` and/or `max_bytes`, inserting beyond either cap
+/// evicts least recently used entries. Bytes are counted as the serialized size of the
+/// cached transactions (`CachedEntry::weight`), so a few huge transactions cannot blow
+/// the memory budget while the entry count looks fine. Large bounded caches split the
+/// caps evenly over the shards, so eviction picks the least recently used entry of the
+/// shard being inserted into.
+///
+/// A panic while holding a lock poisons its shard; rather than propagating the panic to
+/// every later caller, a poisoned read is treated as a miss and the next write clears
+/// the shard (its LRU index may be half updated) and the poison flag.
+///
+/// Entries age on the `SystemClock` unless given another with `with_clock`.
+#[derive(Debug)]
+pub struct MemoryBackend {
+    shards: Box<[RwLock<LruMap>]>,
+    hasher: RandomState,
+    /// Caps of the whole backend
+    limits: Limits,
+    /// Caps of each shard, `limits` split evenly
+    shard_limits: Limits,
+    /// What entries are timestamped and checked for expiry with
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for MemoryBackend {
+    fn default() -> Self {
+        Self::with_shards(DEFAULT_SHARDS, Limits::default())
+    }
+}
+
+impl MemoryBackend {
+    /// Creates an unbounded in-memory backend.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates an in-memory backend holding at most `max_entries` entries.
+    pub fn bounded(max_entries: usize) -> Self {
+        Self::with_limits(Some(max_entries), None)
+    }
+
+    /// Creates an in-memory backend holding at most `max_entries` entries and at most
+    /// `max_bytes` bytes of serialized transactions, both optional.
+    pub fn with_limits(max_entries: Option<usize>, max_bytes: Option<usize>) -> Self {
+        let shards = [
+            max_entries.map(|max| max / MIN_ENTRIES_PER_SHARD),
+            max_bytes.map(|max| max / MIN_BYTES_PER_SHARD),
+        ]
+        .into_iter()
+        .flatten()
+        .min()
+        .unwrap_or(DEFAULT_SHARDS)
+        .clamp(1, DEFAULT_SHARDS);
+        let limits = Limits {
+            entries: max_entries,
+            bytes: max_bytes,
+        };
+        Self::with_shards(shards, limits)
+    }
+
+    fn with_shards(shards: usize, limits: Limits) -> Self {
+        Self {
+            shards: (0..shards).map(|_| RwLock::default()).collect(),
+            hasher: RandomState::new(),
+            limits,
+            shard_limits: Limits {
+                entries: limits.entries.map(|max| max / shards),
+                bytes: limits.bytes.map(|max| max / shards),
+            },
+            clock: time::system(),
+        }
+    }
+
+    /// Measures TTLs on `clock` instead of the `SystemClock`
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// The clock TTLs are measured on
+    pub fn clock(&self) -> &Arc<dyn Clock> {
+        &self.clock
+    }
+
+    /// Entry cap, if any
+    pub fn max_entries(&self) -> Option<usize> {
+        self.limits.entries
+    }
+
+    /// Byte cap, if any
+    pub fn max_bytes(&self) -> Option<usize> {
+        self.limits.bytes
+    }
+
+    fn shard_index(&self, key: &CacheKey) -> usize {
+        (self.hasher.hash_one(key) % self.shards.len() as u64) as usize
+    }
+
+    fn shard(&self, key: &CacheKey) -> &RwLock<LruMap> {
+        &self.shards[self.shard_index(key)]
+    }
+
+    /// Write lock, starting over from an empty shard if the lock is poisoned
+    fn write(shard: &RwLock<LruMap>) -> RwLockWriteGuard<'_, LruMap> {
+        shard.write().unwrap_or_else(|poisoned| {
+            let mut map = poisoned.into_inner();
+            map.clear();
+            shard.clear_poison();
+            map
+        })
+    }
+
+    /// Read locks of the shards that are not poisoned
+    fn readable_shards(&self) -> impl Iterator<Item = RwLockReadGuard<'_, LruMap>> {
+        self.shards.iter().filter_map(|shard| shard.read().ok())
+    }
+
+    /// Poisons every shard lock by panicking on another thread while holding it
+    #[cfg(test)]
+    pub(crate) fn poison(&self) {
+        for shard in &self.shards {
+            std::thread::scope(|scope| {
+                let _ = scope
+                    .spawn(|| {
+                        let _guard = shard.write().unwrap();
+                        panic!("poisoning the cache lock");
+                    })
+                    .join();
+            });
+            assert!(shard.is_poisoned());
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+}
+
+impl CacheBackend for MemoryBackend {
+    fn get(&self, key: &CacheKey) -> CacheLookup {
+        match self.shard(key).read() {
+            Ok(map) => map.get(key, self.clock.now()),
+            Err(_) => CacheLookup::Missing,
+        }
+    }
+
+    fn insert(&self, key: CacheKey, entry: CachedEntry, ttl: Option<Duration>) -> Vec<CacheKey> {
+        Self::write(self.shard(&key)).insert(key, entry, ttl, self.shard_limits, self.clock.now())
+    }
+
+    /// Groups the entries by shard so each shard is locked once.
+    fn insert_many(
+        &self,
+        entries: Vec<(CacheKey, CachedEntry, Option<Duration>)>,
+    ) -> Vec<CacheKey> {
+        let mut by_shard: BTreeMap<usize, Vec<_>> = BTreeMap::new();
+        for entry in entries {
+            by_shard
+                .entry(self.shard_index(&entry.0))
+                .or_default()
+                .push(entry);
+        }
+        let now = self.clock.now();
+        let mut evicted = Vec::new();
+        for (index, entries) in by_shard {
+            let mut map = Self::write(&self.shards[index]);
+            for (key, entry, ttl) in entries {
+                evicted.extend(map.insert(key, entry, ttl, self.shard_limits, now));
+            }
+        }
+        evicted
+    }
+
+    fn remove(&self, key: &CacheKey) -> bool {
+        Self::write(self.shard(key)).remove(key)
+    }
+
+    fn remove_matching(&self, matches: &dyn Fn(&CacheKey) -> bool) -> usize {
+        let mut removed = 0;
+        for shard in &self.shards {
+            let mut map = Self::write(shard);
+            let keys: Vec<_> = map
+                .entries
+                .keys()
+                .filter(|key| matches(key))
+                .cloned()
+                .collect();
+            removed += keys.iter().filter(|key| map.remove(key)).count();
+        }
+        removed
+    }
+
+    fn len(&self) -> usize {
+        self.readable_shards().map(|map| map.entries.len()).sum()
+    }
+
+    fn weight(&self) -> usize {
+        self.readable_shards().map(|map| map.weight).sum()
+    }
+
+    fn clear(&self) {
+        for shard in &self.shards {
+            Self::write(shard).clear();
+        }
+    }
+
+    fn remove_expired(&self, keys: &[CacheKey]) -> usize {
+        let now = self.clock.now();
+        keys.iter()
+            .filter(|key| {
+                let mut map = Self::write(self.shard(key));
+                let expired = map.entries.get(key).is_some_and(|slot| !slot.is_fresh(now));
+                expired && map.remove(key)
+            })
+            .count()
+    }
+
+    fn expired_keys(&self) -> Vec<CacheKey> {
+        let now = self.clock.now();
+        self.readable_shards()
+            .flat_map(|map| {
+                map.entries
+                    .iter()
+                    .filter(|(_, slot)| !slot.is_fresh(now))
+                    .map(|(key, _)| key.clone())
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    fn fresh_entries(&self) -> Vec<(CacheKey, CachedEntry, Option<Duration>)> {
+        let now = self.clock.now();
+        self.readable_shards()
+            .flat_map(|map| {
+                map.entries
+                    .iter()
+                    .filter(|(_, slot)| slot.is_fresh(now))
+                    .map(|(key, slot)| (key.clone(), slot.entry.clone(), slot.remaining(now)))
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+}
+
+/// Behaviour every in-memory backend must share, run against each of them so tests
+/// written for `CachingDataSource` hold whichever backend it uses.
+#[cfg(test)]
+pub(crate) mod conformance {
+    use super::*;
+    use crate::testing::tx;
+    use bitcoin::OutPoint;
+    use std::sync::Arc;
+
+    const HOUR: Option<Duration> = Some(Duration::from_secs(3600));
+
+    fn found(n: u32) -> (CacheKey, CachedEntry) {
+        let tx = tx(n);
+        (
+            CacheKey::Transaction(tx.compute_txid()),
+            CachedEntry::Found(Arc::new(tx)),
+        )
+    }
+
+    /// Inserts `count` distinct transactions that never expire
+    pub(crate) fn fill(backend: &dyn CacheBackend, count: u32) {
+        for n in 0..count {
+            let (key, entry) = found(n);
+            backend.insert(key, entry, None);
+        }
+    }
+
+    /// Runs every check, each against a fresh backend
+    pub(crate) fn run_all<B: CacheBackend>(new: impl Fn() -> B, bounded: impl Fn(usize) -> B) {
+        every_entry_kind_round_trips(&new());
+        reinserting_replaces(&new());
+        zero_ttl_is_expired_until_removed(&new());
+        removals(&new());
+        fresh_entries_report_remaining_ttl(&new());
+        weight_is_tracked(&new());
+        bound_is_respected(&bounded(64), 64);
+    }
+
+    fn every_entry_kind_round_trips(backend: &dyn CacheBackend) {
+        let outpoint = OutPoint::new(tx(1).compute_txid(), 0);
+        let entries = [
+            found(1),
+            (
+                CacheKey::Transaction(tx(2).compute_txid()),
+                CachedEntry::NotFound,
+            ),
+            (CacheKey::Spending(outpoint), CachedEntry::Unspent),
+            (
+                CacheKey::Spending(OutPoint::new(outpoint.txid, 1)),
+                CachedEntry::History(Arc::new(vec![tx(3), tx(4)])),
+            ),
+        ];
+        for (key, entry) in &entries {
+            backend.insert(key.clone(), entry.clone(), HOUR);
+        }
+
+        assert_eq!(backend.len(), entries.len());
+        for (key, entry) in entries {
+            assert_eq!(backend.get(&key), CacheLookup::Fresh(entry));
+        }
+        assert_eq!(
+            backend.get(&CacheKey::Transaction(tx(9).compute_txid())),
+            CacheLookup::Missing
+        );
+    }
+
+    fn reinserting_replaces(backend: &dyn CacheBackend) {
+        let (key, entry) = found(1);
+        backend.insert(key.clone(), CachedEntry::NotFound, Some(Duration::ZERO));
+        backend.insert(key.clone(), entry.clone(), None);
+
+        assert_eq!(backend.len(), 1);
+        assert_eq!(backend.get(&key), CacheLookup::Fresh(entry));
+    }
+
+    fn zero_ttl_is_expired_until_removed(backend: &dyn CacheBackend) {
+        let (key, entry) = found(1);
+        let (fresh, fresh_entry) = found(2);
+        backend.insert(key.clone(), entry, Some(Duration::ZERO));
+        backend.insert(fresh.clone(), fresh_entry, HOUR);
+
+        assert_eq!(backend.get(&key), CacheLookup::Expired);
+        assert_eq!(backend.expired_keys(), vec![key.clone()]);
+        assert_eq!(backend.remove_expired(&[key.clone(), fresh.clone()]), 1);
+        assert_eq!(backend.get(&key), CacheLookup::Missing);
+        assert_eq!(backend.len(), 1);
+    }
+
+    fn removals(backend: &dyn CacheBackend) {
+        fill(backend, 10);
+        let (key, _) = found(0);
+        let outpoint = OutPoint::new(tx(1).compute_txid(), 0);
+        backend.insert(CacheKey::Spending(outpoint), CachedEntry::Unspent, HOUR);
+
+        assert!(backend.remove(&key));
+        assert!(!backend.remove(&key));
+        assert_eq!(
+            backend.remove_matching(&|key| matches!(key, CacheKey::Spending(_))),
+            1
+        );
+        assert_eq!(backend.len(), 9);
+        backend.clear();
+        assert!(backend.is_empty());
+    }
+
+    fn fresh_entries_report_remaining_ttl(backend: &dyn CacheBackend) {
+        let (forever, entry) = found(1);
+        backend.insert(forever.clone(), entry.clone(), None);
+        let (timed, timed_entry) = found(2);
+        backend.insert(timed.clone(), timed_entry, HOUR);
+        let (expired, expired_entry) = found(3);
+        backend.insert(expired, expired_entry, Some(Duration::ZERO));
+
+        let mut fresh = backend.fresh_entries();
+        fresh.sort_by_key(|(_, _, ttl)| ttl.is_some());
+        assert_eq!(fresh.len(), 2);
+        assert_eq!(fresh[0], (forever, entry, None));
+        assert_eq!(fresh[1].0, timed);
+        assert!(fresh[1].2.is_some_and(|left| left <= HOUR.unwrap()));
+    }
+
+    fn weight_is_tracked(backend: &dyn CacheBackend) {
+        let (key, entry) = found(1);
+        let weight = entry.weight();
+        backend.insert(key.clone(), entry, None);
+        backend.insert(
+            CacheKey::Transaction(tx(2).compute_txid()),
+            CachedEntry::NotFound,
+            None,
+        );
+
+        assert_eq!(backend.weight(), weight);
+        backend.remove(&key);
+        assert_eq!(backend.weight(), 0);
+    }
+
+    fn bound_is_respected(backend: &dyn CacheBackend, cap: usize) {
+        fill(backend, 1_000);
+        assert!(backend.len() <= cap, "{} > {}", backend.len(), cap);
+        assert!(!backend.is_empty());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::tx;
+    use bitcoin::Transaction;
+    use std::{sync::Arc, time::Instant as StdInstant};
+
+    fn key(n: u32) -> (CacheKey, CachedEntry) {
+        let tx = tx(n);
+        (
+            CacheKey::Transaction(tx.compute_txid()),
+            CachedEntry::Found(Arc::new(tx)),
+        )
+    }
+
+    #[test]
+    fn test_conformance() {
+        conformance::run_all(MemoryBackend::new, MemoryBackend::bounded);
+    }
+
+    #[test]
+    fn test_small_bounded_backends_keep_one_shard() {
+        assert_eq!(MemoryBackend::new().shard_count(), DEFAULT_SHARDS);
+        assert_eq!(MemoryBackend::bounded(10).shard_count(), 1);
+        assert_eq!(MemoryBackend::bounded(1_000).shard_count(), 3);
+        assert_eq!(
+            MemoryBackend::bounded(1_000_000).shard_count(),
+            DEFAULT_SHARDS
+        );
+    }
+
+    #[test]
+    fn test_byte_bound_evicts_before_count_bound() {
+        let backend = MemoryBackend::with_limits(Some(100), Some(50_000));
+        let huge = |n: u32| {
+            let tx = Transaction {
+                output: vec![bitcoin::TxOut {
+                    value: bitcoin::Amount::ZERO,
+                    script_pubkey: bitcoin::ScriptBuf::from_bytes(vec![0x6a; 20_000]),
+                }],
+                ..tx(n)
+            };
+            (
+                CacheKey::Transaction(tx.compute_txid()),
+                CachedEntry::Found(Arc::new(tx)),
+            )
+        };
+
+        for n in 0..10 {
+            let (key, entry) = key(n);
+            assert!(backend.insert(key, entry, None).is_empty());
+        }
+        let (first, entry) = huge(100);
+        assert!(backend.insert(first.clone(), entry, None).is_empty());
+        let (second, entry) = huge(101);
+        assert!(backend.insert(second, entry, None).is_empty());
+
+        // The third huge transaction goes over 50kB with only 13 entries cached
+        let (third, entry) = huge(102);
+        let evicted = backend.insert(third.clone(), entry, None);
+        assert!(evicted.contains(&first), "{:?}", evicted);
+        assert!(backend.weight() <= 50_000);
+        assert!(backend.len() < 13);
+        assert!(matches!(backend.get(&third), CacheLookup::Fresh(_)));
+    }
+
+    #[test]
+    fn test_weight_follows_removals() {
+        let backend = MemoryBackend::new();
+        let (key, entry) = key(1);
+        let weight = entry.weight();
+
+        backend.insert(key.clone(), entry.clone(), None);
+        backend.insert(key.clone(), entry, None);
+        assert_eq!(backend.weight(), weight);
+        backend.remove(&key);
+        assert_eq!(backend.weight(), 0);
+    }
+
+    #[test]
+    fn test_sharded_bound_is_respected() {
+        let backend = MemoryBackend::bounded(4_096);
+        for n in 0..10_000 {
+            let (key, entry) = key(n);
+            backend.insert(key, entry, None);
+        }
+        assert!(backend.len() <= 4_096);
+        assert!(backend.len() > 3_000);
+    }
+
+    /// Many threads mixing hits, misses, inserts and expirations; every fresh read must
+    /// return the entry stored under that key.
+    #[test]
+    fn test_concurrent_mixed_access() {
+        let backend = MemoryBackend::new();
+        let entries: Vec<_> = (0..512).map(key).collect();
+
+        std::thread::scope(|scope| {
+            for thread in 0..32 {
+                let backend = &backend;
+                let entries = &entries;
+                scope.spawn(move || {
+                    for i in 0..2_000 {
+                        let (key, entry) = &entries[(thread * 31 + i * 7) % entries.len()];
+                        match backend.get(key) {
+                            CacheLookup::Fresh(found) => assert_eq!(&found, entry),
+                            CacheLookup::Expired | CacheLookup::Missing => {
+                                // Odd keys expire immediately, even keys never
+                                let ttl = (i % 2 == 1).then_some(Duration::ZERO);
+                                backend.insert(key.clone(), entry.clone(), ttl);
+                            }
+                        }
+                    }
+                });
+            }
+        });
+
+        for key in backend.expired_keys() {
+            assert_eq!(backend.get(&key), CacheLookup::Expired);
+        }
+        assert!(backend.len() <= entries.len());
+    }
+
+    /// Rough throughput comparison against a single-lock backend. Timing dependent, run
+    /// with `cargo test -- --ignored` on a machine of 4 cores or more: below that the
+    /// shards are never locked in parallel, and the test passes without running.
+    #[test]
+    #[ignore]
+    fn test_sharding_improves_write_heavy_throughput() {
+        fn hammer(backend: &MemoryBackend) -> std::time::Duration {
+            let entries: Vec<_> = (0..4_096).map(key).collect();
+            let start = StdInstant::now();
+            std::thread::scope(|scope| {
+                for thread in 0..32 {
+                    let entries = &entries;
+                    scope.spawn(move || {
+                        for i in 0..20_000 {
+                            let (key, entry) = &entries[(thread * 131 + i) % entries.len()];
+                            if i % 4 == 0 {
+                                backend.insert(key.clone(), entry.clone(), None);
+                            } else {
+                                backend.get(key);
+                            }
+                        }
+                    });
+                }
+            });
+            start.elapsed()
+        }
+
+        let cores = std::thread::available_parallelism().map_or(1, |n| n.get());
+        if cores < 4 {
+            return;
+        }
+        let single = hammer(&MemoryBackend::with_shards(1, Limits::default()));
+        let sharded = hammer(&MemoryBackend::new());
+        assert!(
+            sharded < single,
+            "sharded took {:?}, single lock {:?}",
+            sharded,
+            single
+        );
+    }
+}
@@ -0,0 +1,140 @@
+//! Binary encoding of cache keys and entries, shared by the persistent backend and
+//! cache snapshots.
+//!
+//! `Instant` cannot be written to disk, so entries carry their insertion time as
+//! wall-clock milliseconds since the unix epoch together with their TTL.
+//!
+//! # Layout
+//! * key - `0x00 || txid` for transaction lookups, `0x01 || outpoint` for spending lookups,
+//!   `0x02 || address string` for address lookups
+//! * value - `tag || inserted_at_ms (u64 BE) || ttl_ms (u64 BE) || consensus-encoded body`
+//!   where `tag` is `0x00` found (body is the tx), `0x01` not found, `0x02` unspent (both
+//!   without body) or `0x03` history (body is the tx list), and `ttl_ms` is `u64::MAX`
+//!   for entries that never expire
+
+use super::{CacheKey, CachedEntry};
+use bitcoin::{
+    Address, Transaction,
+    consensus::{deserialize, serialize},
+};
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+const KEY_TRANSACTION: u8 = 0x00;
+const KEY_SPENDING: u8 = 0x01;
+const KEY_ADDRESS: u8 = 0x02;
+
+const ENTRY_FOUND: u8 = 0x00;
+const ENTRY_NOT_FOUND: u8 = 0x01;
+const ENTRY_UNSPENT: u8 = 0x02;
+const ENTRY_HISTORY: u8 = 0x03;
+
+/// Size of the value header: tag + inserted_at + ttl
+const HEADER_LEN: usize = 17;
+
+/// `ttl_ms` of entries that never expire
+const NEVER_EXPIRES: u64 = u64::MAX;
+
+pub(crate) fn encode_key(key: &CacheKey) -> Vec<u8> {
+    let (tag, body) = match key {
+        CacheKey::Transaction(txid) => (KEY_TRANSACTION, serialize(txid)),
+        CacheKey::Spending(outpoint) => (KEY_SPENDING, serialize(outpoint)),
+        CacheKey::Address(address) => (KEY_ADDRESS, address.to_string().into_bytes()),
+    };
+    let mut bytes = Vec::with_capacity(1 + body.len());
+    bytes.push(tag);
+    bytes.extend(body);
+    bytes
+}
+
+pub(crate) fn decode_key(bytes: &[u8]) -> Option<CacheKey> {
+    let (tag, body) = bytes.split_first()?;
+    match *tag {
+        KEY_TRANSACTION => deserialize(body).ok().map(CacheKey::Transaction),
+        KEY_SPENDING => deserialize(body).ok().map(CacheKey::Spending),
+        // Only addresses valid for the configured network were ever stored
+        KEY_ADDRESS => std::str::from_utf8(body)
+            .ok()?
+            .parse::<Address<_>>()
+            .ok()
+            .map(|address| CacheKey::Address(address.assume_checked())),
+        _ => None,
+    }
+}
+
+pub(crate) fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+pub(crate) fn encode_entry(
+    entry: &CachedEntry,
+    inserted_at_ms: u64,
+    ttl: Option<Duration>,
+) -> Vec<u8> {
+    let (tag, body) = match entry {
+        CachedEntry::Found(tx) => (ENTRY_FOUND, Some(serialize(tx.as_ref()))),
+        CachedEntry::NotFound => (ENTRY_NOT_FOUND, None),
+        CachedEntry::Unspent => (ENTRY_UNSPENT, None),
+        CachedEntry::History(txs) => (ENTRY_HISTORY, Some(serialize(txs.as_ref()))),
+    };
+    let mut bytes = Vec::with_capacity(HEADER_LEN + body.as_ref().map_or(0, Vec::len));
+    bytes.push(tag);
+    bytes.extend(inserted_at_ms.to_be_bytes());
+    let ttl_ms = ttl.map_or(NEVER_EXPIRES, |ttl| ttl.as_millis() as u64);
+    bytes.extend(ttl_ms.to_be_bytes());
+    bytes.extend(body.unwrap_or_default());
+    bytes
+}
+
+/// Header fields of a stored entry, without decoding the transaction
+///
+/// Returns `(tag, inserted_at_ms, ttl_ms)`.
+pub(crate) fn decode_header(bytes: &[u8]) -> Option<(u8, u64, u64)> {
+    if bytes.len() < HEADER_LEN {
+        return None;
+    }
+    let inserted_at_ms = u64::from_be_bytes(bytes[1..9].try_into().ok()?);
+    let ttl_ms = u64::from_be_bytes(bytes[9..17].try_into().ok()?);
+    Some((bytes[0], inserted_at_ms, ttl_ms))
+}
+
+/// Time an entry has left, `Some(None)` if it never expires and `None` if it already
+/// expired
+pub(crate) fn remaining_ttl(inserted_at_ms: u64, ttl_ms: u64) -> Option<Option<Duration>> {
+    if ttl_ms == NEVER_EXPIRES {
+        return Some(None);
+    }
+    let expires_at_ms = inserted_at_ms.saturating_add(ttl_ms);
+    expires_at_ms
+        .checked_sub(now_ms())
+        .filter(|left| *left > 0)
+        .map(|left| Some(Duration::from_millis(left)))
+}
+
+/// Decodes a whole stored entry, `None` if it is malformed
+///
+/// Returns the entry with its `inserted_at_ms` and `ttl_ms` header fields.
+pub(crate) fn decode_value(bytes: &[u8]) -> Option<(CachedEntry, u64, u64)> {
+    let (tag, inserted_at_ms, ttl_ms) = decode_header(bytes)?;
+    let entry = decode_entry(tag, &bytes[HEADER_LEN..])?;
+    Some((entry, inserted_at_ms, ttl_ms))
+}
+
+pub(crate) fn decode_entry(tag: u8, body: &[u8]) -> Option<CachedEntry> {
+    match tag {
+        ENTRY_FOUND => deserialize(body)
+            .ok()
+            .map(|tx| CachedEntry::Found(Arc::new(tx))),
+        ENTRY_NOT_FOUND if body.is_empty() => Some(CachedEntry::NotFound),
+        ENTRY_UNSPENT if body.is_empty() => Some(CachedEntry::Unspent),
+        ENTRY_HISTORY => deserialize::<Vec<Transaction>>(body)
+            .ok()
+            .map(|txs| CachedEntry::History(Arc::new(txs))),
+        _ => None,
+    }
+}
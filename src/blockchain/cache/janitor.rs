@@ -0,0 +1,76 @@
+//! Background expiry sweeps for `CachingDataSource`.
+//!
+//! Expired entries are otherwise only noticed when their exact key is requested again,
+//! so a cache used for one big trace would keep every entry until the process exits.
+
+use super::{CacheBackend, stats::StatsCounters};
+use crate::time::{self, MissedTickBehavior, interval};
+use futures::future::{AbortHandle, abortable};
+use std::{
+    sync::{Arc, Weak, atomic::Ordering},
+    time::Duration,
+};
+
+/// Maximum number of entries removed per storage lock acquisition
+pub const SWEEP_BATCH_SIZE: usize = 1024;
+
+/// Handle to a running janitor task, stops the task when dropped.
+///
+/// The task also stops on its own once the cache it sweeps has been dropped.
+#[derive(Debug)]
+pub struct JanitorHandle {
+    task: AbortHandle,
+}
+
+impl JanitorHandle {
+    /// Stops the janitor (same as dropping the handle).
+    pub fn stop(self) {}
+}
+
+impl Drop for JanitorHandle {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Spawns a task sweeping expired entries out of `backend` every `every`.
+pub(crate) fn spawn<B: CacheBackend + 'static>(
+    backend: Weak<B>,
+    stats: Weak<StatsCounters>,
+    every: Duration,
+) -> JanitorHandle {
+    let (sweeps, task) = abortable(async move {
+        let mut ticks = interval(every);
+        ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        // The first tick completes immediately, nothing has had time to expire yet
+        ticks.tick().await;
+
+        loop {
+            ticks.tick().await;
+            let (Some(backend), Some(stats)) = (backend.upgrade(), stats.upgrade()) else {
+                // Cache dropped, nothing left to sweep
+                return;
+            };
+            let removed = sweep(&backend).await;
+            stats
+                .last_sweep_removed
+                .store(removed as u64, Ordering::Relaxed);
+        }
+    });
+    time::spawn(async move {
+        let _ = sweeps.await;
+    });
+    JanitorHandle { task }
+}
+
+/// Removes every expired entry, in batches of `SWEEP_BATCH_SIZE` so readers get the
+/// storage back between batches.
+pub(crate) async fn sweep<B: CacheBackend>(backend: &Arc<B>) -> usize {
+    let expired = backend.expired_keys();
+    let mut removed = 0;
+    for batch in expired.chunks(SWEEP_BATCH_SIZE) {
+        removed += backend.remove_expired(batch);
+        tokio::task::yield_now().await;
+    }
+    removed
+}
@@ -0,0 +1,262 @@
+//! Cache backend on top of moka.
+//!
+//! Trades the hand-rolled LRU of `MemoryBackend` for moka's TinyLFU admission and
+//! eviction, which keeps frequently used transactions around under scan-heavy traces.
+//!
+//! The freshness of an entry is still decided here, against the backend's `Clock`
+//! like `MemoryBackend`, so lookups report `Expired` the same way on both backends. moka
+//! reclaims an entry on its own `RECLAIM_DELAY` after its TTL; until then it is reported
+//! as expired, afterwards as missing.
+
+use super::{CacheBackend, CacheKey, CacheLookup, CachedEntry};
+use crate::time::{self, Clock, Instant};
+use ::moka::{Expiry, notification::RemovalCause, sync::Cache};
+use std::{
+    sync::{Arc, Mutex, PoisonError},
+    time::Duration,
+};
+
+/// How long moka keeps an entry past its TTL before reclaiming it
+const RECLAIM_DELAY: Duration = Duration::from_secs(60);
+
+/// A `CachedEntry` with its TTL bookkeeping, as stored in moka
+#[derive(Debug, Clone)]
+struct Slot {
+    entry: CachedEntry,
+    inserted_at: Instant,
+    ttl: Option<Duration>,
+}
+
+impl Slot {
+    fn is_fresh(&self, now: Instant) -> bool {
+        self.ttl
+            .is_none_or(|ttl| now.saturating_duration_since(self.inserted_at) < ttl)
+    }
+
+    /// Time left before expiry as of `now`, `None` if the entry never expires
+    fn remaining(&self, now: Instant) -> Option<Duration> {
+        self.ttl
+            .map(|ttl| ttl.saturating_sub(now.saturating_duration_since(self.inserted_at)))
+    }
+}
+
+/// Per-entry moka expiry: the entry's TTL plus `RECLAIM_DELAY`
+struct ReclaimAfterTtl;
+
+impl Expiry<CacheKey, Slot> for ReclaimAfterTtl {
+    fn expire_after_create(
+        &self,
+        _key: &CacheKey,
+        slot: &Slot,
+        _created_at: std::time::Instant,
+    ) -> Option<Duration> {
+        slot.ttl.map(|ttl| ttl.saturating_add(RECLAIM_DELAY))
+    }
+
+    fn expire_after_update(
+        &self,
+        _key: &CacheKey,
+        slot: &Slot,
+        _updated_at: std::time::Instant,
+        _duration_until_expiry: Option<Duration>,
+    ) -> Option<Duration> {
+        slot.ttl.map(|ttl| ttl.saturating_add(RECLAIM_DELAY))
+    }
+}
+
+/// `CacheBackend` storing entries in a `moka::sync::Cache`.
+///
+/// moka is synchronous behind this interface like every other backend; its maintenance
+/// runs on the calling threads during writes.
+///
+/// moka enforces a single capacity. With only `max_entries` each entry counts as one;
+/// with `max_bytes` entries are weighed by `CachedEntry::weight`. With both, every entry
+/// weighs at least `max_bytes / max_entries`, so neither cap can be exceeded.
+///
+/// Evictions are applied by moka's maintenance, so they are reported by a later
+/// `insert` than the one that caused them, and `TinyLFU` may decline to admit a new
+/// entry at all rather than evict an older one.
+pub struct MokaBackend {
+    cache: Cache<CacheKey, Slot>,
+    limits: (Option<usize>, Option<usize>),
+    /// Keys evicted for size since the last insert, filled by the eviction listener
+    evicted: Arc<Mutex<Vec<CacheKey>>>,
+    /// What entries are timestamped and checked for expiry with
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for MokaBackend {
+    fn default() -> Self {
+        Self::with_limits(None, None)
+    }
+}
+
+impl MokaBackend {
+    /// Creates an unbounded moka backend.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a moka backend holding at most `max_entries` entries.
+    pub fn bounded(max_entries: usize) -> Self {
+        Self::with_limits(Some(max_entries), None)
+    }
+
+    /// Creates a moka backend holding at most `max_entries` entries and at most
+    /// `max_bytes` bytes of serialized transactions, both optional.
+    pub fn with_limits(max_entries: Option<usize>, max_bytes: Option<usize>) -> Self {
+        let evicted = Arc::new(Mutex::new(Vec::new()));
+        let listener = {
+            let evicted = Arc::clone(&evicted);
+            move |key: Arc<CacheKey>, _: Slot, cause: RemovalCause| {
+                if cause == RemovalCause::Size {
+                    lock(&evicted).push(CacheKey::clone(&key));
+                }
+            }
+        };
+        let builder = Cache::builder()
+            .expire_after(ReclaimAfterTtl)
+            .eviction_listener(listener);
+        let builder = match (max_entries, max_bytes) {
+            (None, None) => builder,
+            (Some(entries), None) => builder.max_capacity(entries as u64),
+            (entries, Some(bytes)) => {
+                let floor = entries.map_or(0, |entries| bytes / entries.max(1));
+                builder
+                    .max_capacity(bytes as u64)
+                    .weigher(move |_, slot: &Slot| {
+                        u32::try_from(slot.entry.weight().max(floor)).unwrap_or(u32::MAX)
+                    })
+            }
+        };
+        Self {
+            cache: builder.build(),
+            limits: (max_entries, max_bytes),
+            evicted,
+            clock: time::system(),
+        }
+    }
+
+    /// Measures TTLs on `clock` instead of the `SystemClock`. moka reclaims entries on
+    /// its own clock all the same.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// The clock TTLs are measured on
+    pub fn clock(&self) -> &Arc<dyn Clock> {
+        &self.clock
+    }
+
+    /// Entry cap, if any
+    pub fn max_entries(&self) -> Option<usize> {
+        self.limits.0
+    }
+
+    /// Byte cap, if any
+    pub fn max_bytes(&self) -> Option<usize> {
+        self.limits.1
+    }
+
+    fn take_evicted(&self) -> Vec<CacheKey> {
+        std::mem::take(&mut *lock(&self.evicted))
+    }
+}
+
+/// The listener only pushes keys, a poisoned lock still holds a valid list
+fn lock(evicted: &Mutex<Vec<CacheKey>>) -> std::sync::MutexGuard<'_, Vec<CacheKey>> {
+    evicted.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl CacheBackend for MokaBackend {
+    fn get(&self, key: &CacheKey) -> CacheLookup {
+        match self.cache.get(key) {
+            Some(slot) if slot.is_fresh(self.clock.now()) => CacheLookup::Fresh(slot.entry),
+            Some(_) => CacheLookup::Expired,
+            None => CacheLookup::Missing,
+        }
+    }
+
+    fn insert(&self, key: CacheKey, entry: CachedEntry, ttl: Option<Duration>) -> Vec<CacheKey> {
+        let slot = Slot {
+            entry,
+            inserted_at: self.clock.now(),
+            ttl,
+        };
+        self.cache.insert(key, slot);
+        self.take_evicted()
+    }
+
+    fn remove(&self, key: &CacheKey) -> bool {
+        self.cache.remove(key).is_some()
+    }
+
+    fn remove_matching(&self, matches: &dyn Fn(&CacheKey) -> bool) -> usize {
+        let keys: Vec<_> = self
+            .cache
+            .iter()
+            .filter(|(key, _)| matches(key))
+            .map(|(key, _)| CacheKey::clone(&key))
+            .collect();
+        keys.iter().filter(|key| self.remove(key)).count()
+    }
+
+    /// Runs moka's pending maintenance first, so the count is exact.
+    fn len(&self) -> usize {
+        self.cache.run_pending_tasks();
+        self.cache.entry_count() as usize
+    }
+
+    /// Walks every entry, unlike `MemoryBackend` which keeps a running total.
+    fn weight(&self) -> usize {
+        self.cache.iter().map(|(_, slot)| slot.entry.weight()).sum()
+    }
+
+    fn clear(&self) {
+        self.cache.invalidate_all();
+        self.cache.run_pending_tasks();
+    }
+
+    fn expired_keys(&self) -> Vec<CacheKey> {
+        let now = self.clock.now();
+        self.cache
+            .iter()
+            .filter(|(_, slot)| !slot.is_fresh(now))
+            .map(|(key, _)| CacheKey::clone(&key))
+            .collect()
+    }
+
+    fn fresh_entries(&self) -> Vec<(CacheKey, CachedEntry, Option<Duration>)> {
+        let now = self.clock.now();
+        self.cache
+            .iter()
+            .filter(|(_, slot)| slot.is_fresh(now))
+            .map(|(key, slot)| {
+                (
+                    CacheKey::clone(&key),
+                    slot.entry.clone(),
+                    slot.remaining(now),
+                )
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::cache::backend::conformance;
+
+    #[test]
+    fn test_conformance() {
+        conformance::run_all(MokaBackend::new, MokaBackend::bounded);
+    }
+
+    #[test]
+    fn test_both_caps_hold() {
+        let backend = MokaBackend::with_limits(Some(50), Some(1_000_000));
+        conformance::fill(&backend, 1_000);
+        assert!(backend.len() <= 50);
+    }
+}
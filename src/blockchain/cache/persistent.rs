@@ -0,0 +1,273 @@
+//! Persistent cache backend on top of sled.
+//!
+//! Confirmed transactions are immutable, so keeping them on disk between runs saves
+//! refetching thousands of them when re-tracing the same cluster.
+//!
+//! Keys and entries use the encoding of the `codec` module.
+
+use super::{
+    CacheBackend, CacheKey, CacheLookup, CachedEntry,
+    codec::{
+        decode_header, decode_key, decode_value, encode_entry, encode_key, now_ms, remaining_ttl,
+    },
+};
+use crate::blockchain::{BlockchainError, Result};
+use std::{path::Path, time::Duration};
+
+/// Attempts to open a database still locked by a handle being dropped
+const OPEN_RETRIES: u32 = 10;
+const OPEN_RETRY_DELAY: Duration = Duration::from_millis(20);
+
+/// `CacheBackend` persisting entries in a sled database.
+///
+/// Unbounded: entries only leave through TTL expiry (`expired_keys` + `remove`) or `clear`.
+pub struct SledBackend {
+    db: sled::Db,
+}
+
+impl SledBackend {
+    /// Opens (or creates) the cache database at `path`.
+    ///
+    /// sled releases its file lock from a background thread after the last handle is
+    /// dropped, so reopening right after closing briefly retries a locked database.
+    ///
+    /// # Errors
+    /// - `Other` - the database is corrupt, locked by another process, or unreadable
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let mut attempt = 0;
+        let db = loop {
+            match sled::open(path) {
+                Err(sled::Error::Io(e)) if is_locked(&e) && attempt < OPEN_RETRIES => {
+                    attempt += 1;
+                    std::thread::sleep(OPEN_RETRY_DELAY);
+                }
+                result => {
+                    break result.map_err(|e| {
+                        BlockchainError::Other(format!(
+                            "Failed to open persistent cache at {}: {}",
+                            path.display(),
+                            e
+                        ))
+                    })?;
+                }
+            }
+        };
+        Ok(Self { db })
+    }
+
+    /// Writes pending changes to disk.
+    pub fn flush(&self) -> Result<()> {
+        self.db
+            .flush()
+            .map(|_| ())
+            .map_err(|e| BlockchainError::Other(format!("Failed to flush persistent cache: {}", e)))
+    }
+}
+
+/// sled reports lock contention as a plain `ErrorKind::Other`, only its message tells
+fn is_locked(error: &std::io::Error) -> bool {
+    error.to_string().contains("could not acquire lock")
+}
+
+impl Drop for SledBackend {
+    fn drop(&mut self) {
+        let _ = self.db.flush();
+    }
+}
+
+impl CacheBackend for SledBackend {
+    fn get(&self, key: &CacheKey) -> CacheLookup {
+        let db_key = encode_key(key);
+        let Ok(Some(bytes)) = self.db.get(&db_key) else {
+            return CacheLookup::Missing;
+        };
+        let decoded = decode_value(&bytes).map(|(entry, inserted_at_ms, ttl_ms)| {
+            if remaining_ttl(inserted_at_ms, ttl_ms).is_some() {
+                CacheLookup::Fresh(entry)
+            } else {
+                CacheLookup::Expired
+            }
+        });
+        decoded.unwrap_or_else(|| {
+            // Corrupt entry, drop it so it gets refetched
+            let _ = self.db.remove(&db_key);
+            CacheLookup::Missing
+        })
+    }
+
+    fn insert(&self, key: CacheKey, entry: CachedEntry, ttl: Option<Duration>) -> Vec<CacheKey> {
+        let _ = self
+            .db
+            .insert(encode_key(&key), encode_entry(&entry, now_ms(), ttl));
+        Vec::new()
+    }
+
+    fn insert_many(
+        &self,
+        entries: Vec<(CacheKey, CachedEntry, Option<Duration>)>,
+    ) -> Vec<CacheKey> {
+        let inserted_at_ms = now_ms();
+        let mut batch = sled::Batch::default();
+        for (key, entry, ttl) in &entries {
+            batch.insert(encode_key(key), encode_entry(entry, inserted_at_ms, *ttl));
+        }
+        let _ = self.db.apply_batch(batch);
+        Vec::new()
+    }
+
+    fn remove(&self, key: &CacheKey) -> bool {
+        matches!(self.db.remove(encode_key(key)), Ok(Some(_)))
+    }
+
+    fn remove_matching(&self, matches: &dyn Fn(&CacheKey) -> bool) -> usize {
+        self.db
+            .iter()
+            .keys()
+            .filter_map(|key| key.ok())
+            .filter(|key| decode_key(key).is_some_and(|key| matches(&key)))
+            .filter(|key| matches!(self.db.remove(key), Ok(Some(_))))
+            .count()
+    }
+
+    fn len(&self) -> usize {
+        self.db.len()
+    }
+
+    fn clear(&self) {
+        let _ = self.db.clear();
+    }
+
+    fn expired_keys(&self) -> Vec<CacheKey> {
+        self.db
+            .iter()
+            .filter_map(|item| item.ok())
+            .filter(|(_, value)| match decode_header(value) {
+                Some((_, inserted_at_ms, ttl_ms)) => {
+                    remaining_ttl(inserted_at_ms, ttl_ms).is_none()
+                }
+                // Unreadable entries are as good as expired
+                None => true,
+            })
+            .filter_map(|(key, _)| decode_key(&key))
+            .collect()
+    }
+
+    fn fresh_entries(&self) -> Vec<(CacheKey, CachedEntry, Option<Duration>)> {
+        self.db
+            .iter()
+            .filter_map(|item| item.ok())
+            .filter_map(|(key, value)| {
+                let (entry, inserted_at_ms, ttl_ms) = decode_value(&value)?;
+                let remaining = remaining_ttl(inserted_at_ms, ttl_ms)?;
+                Some((decode_key(&key)?, entry, remaining))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::tx;
+    use bitcoin::{Address, OutPoint};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_entries_survive_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cache");
+        let found = tx(1);
+        let tx_key = CacheKey::Transaction(found.compute_txid());
+        let spend_key = CacheKey::Spending(OutPoint::new(tx(2).compute_txid(), 3));
+        let missing_key = CacheKey::Transaction(tx(3).compute_txid());
+
+        {
+            let backend = SledBackend::open(&path).unwrap();
+            let ttl = Some(Duration::from_secs(3600));
+            backend.insert(
+                tx_key.clone(),
+                CachedEntry::Found(Arc::new(found.clone())),
+                ttl,
+            );
+            backend.insert(spend_key.clone(), CachedEntry::Unspent, ttl);
+            backend.insert(missing_key.clone(), CachedEntry::NotFound, ttl);
+        }
+
+        let backend = SledBackend::open(&path).unwrap();
+        assert_eq!(backend.len(), 3);
+        assert_eq!(
+            backend.get(&tx_key),
+            CacheLookup::Fresh(CachedEntry::Found(Arc::new(found)))
+        );
+        assert_eq!(
+            backend.get(&spend_key),
+            CacheLookup::Fresh(CachedEntry::Unspent)
+        );
+        assert_eq!(
+            backend.get(&missing_key),
+            CacheLookup::Fresh(CachedEntry::NotFound)
+        );
+    }
+
+    #[test]
+    fn test_address_history_round_trips() {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = SledBackend::open(dir.path().join("cache")).unwrap();
+        let address = "bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq"
+            .parse::<Address<_>>()
+            .unwrap()
+            .assume_checked();
+        let key = CacheKey::Address(address);
+        let history = CachedEntry::History(Arc::new(vec![tx(1), tx(2)]));
+
+        backend.insert(key.clone(), history.clone(), Some(Duration::ZERO));
+
+        assert_eq!(backend.expired_keys(), vec![key.clone()]);
+        backend.insert(key.clone(), history.clone(), None);
+        assert_eq!(backend.get(&key), CacheLookup::Fresh(history));
+    }
+
+    #[test]
+    fn test_expired_entries_are_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = SledBackend::open(dir.path().join("cache")).unwrap();
+        let key = CacheKey::Transaction(tx(1).compute_txid());
+
+        backend.insert(
+            key.clone(),
+            CachedEntry::Found(Arc::new(tx(1))),
+            Some(Duration::ZERO),
+        );
+
+        assert_eq!(backend.get(&key), CacheLookup::Expired);
+        assert_eq!(backend.expired_keys(), vec![key.clone()]);
+        assert!(backend.remove(&key));
+        assert!(backend.is_empty());
+    }
+
+    #[test]
+    fn test_corrupt_entry_is_a_miss() {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = SledBackend::open(dir.path().join("cache")).unwrap();
+        let key = CacheKey::Transaction(tx(1).compute_txid());
+
+        backend.db.insert(encode_key(&key), vec![0xff; 3]).unwrap();
+
+        assert_eq!(backend.get(&key), CacheLookup::Missing);
+        assert!(backend.is_empty());
+    }
+
+    #[test]
+    fn test_locked_database_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cache");
+        let _first = SledBackend::open(&path).unwrap();
+
+        match SledBackend::open(&path) {
+            Err(BlockchainError::Other(msg)) => assert!(msg.contains("persistent cache")),
+            Err(e) => panic!("unexpected error {:?}", e),
+            Ok(_) => panic!("second open of a locked database succeeded"),
+        }
+    }
+}
@@ -0,0 +1,59 @@
+//! Write-through filter deciding which fetched entries are cached at all.
+//!
+//! A policy sees what would be stored, the key and the fetched entry, and the
+//! confirmation status of its transaction when the cache knows it. Spend status is
+//! visible through the `Unspent` and `NotFound` markers, and a transaction's own
+//! contents (size, inputs, lock time) through `CachedEntry::Found`.
+
+use super::{CacheKey, CachedEntry};
+use crate::blockchain::TxStatus;
+
+/// Decides whether a fetched entry is written to the cache.
+///
+/// Consulted once per key on every insert, including the extra keys written by
+/// cross-population (a spender under its own txid, `index_spenders`). A rejected entry
+/// is still returned to the caller, it just isn't kept, and is counted in
+/// `KindStats::rejected`.
+///
+/// `status` is that of the transaction of a `Found` entry, asked of the inner source
+/// by caches built with `check_status`. It is `None` for every other entry, and when
+/// the status is unknown.
+///
+/// Any `Fn(&CacheKey, &CachedEntry, Option<&TxStatus>) -> bool + Send + Sync` closure
+/// is a policy.
+pub trait CachePolicy: Send + Sync {
+    /// Returns true to cache `entry` under `key`
+    fn should_cache(&self, key: &CacheKey, entry: &CachedEntry, status: Option<&TxStatus>) -> bool;
+}
+
+impl<F> CachePolicy for F
+where
+    F: Fn(&CacheKey, &CachedEntry, Option<&TxStatus>) -> bool + Send + Sync,
+{
+    fn should_cache(&self, key: &CacheKey, entry: &CachedEntry, status: Option<&TxStatus>) -> bool {
+        self(key, entry, status)
+    }
+}
+
+/// Caches everything, the default.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CacheAll;
+
+impl CachePolicy for CacheAll {
+    fn should_cache(&self, _: &CacheKey, _: &CachedEntry, _: Option<&TxStatus>) -> bool {
+        true
+    }
+}
+
+/// Skips entries heavier than a number of serialized bytes (`CachedEntry::weight`).
+///
+/// Markers weigh nothing and are always cached; an address history is measured as a
+/// whole.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MaxEntrySize(pub usize);
+
+impl CachePolicy for MaxEntrySize {
+    fn should_cache(&self, _: &CacheKey, entry: &CachedEntry, _: Option<&TxStatus>) -> bool {
+        entry.weight() <= self.0
+    }
+}
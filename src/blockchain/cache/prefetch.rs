@@ -0,0 +1,123 @@
+//! Bulk cache warming over the batch endpoints of the inner source.
+//!
+//! When the txids of a session are known up front (e.g. from a previous trace export),
+//! fetching them in a few batch requests beats paying per-hop latency during the trace.
+
+use super::{CacheBackend, CacheKey, CachedEntry, CachingDataSource, absent_entry};
+use crate::blockchain::{BlockchainDataSource, Result};
+use bitcoin::{OutPoint, Transaction, Txid};
+use futures::stream::{self, StreamExt};
+use std::{collections::HashSet, hash::Hash, sync::Arc};
+
+/// Number of ids sent to the inner source per batch call
+pub const PREFETCH_BATCH_SIZE: usize = 100;
+
+/// Maximum number of batch calls in flight at once
+pub const PREFETCH_CONCURRENCY: usize = 4;
+
+/// Outcome of a prefetch.
+///
+/// # Fields
+/// * `fetched` - entries fetched and cached
+/// * `already_cached` - ids skipped because a fresh entry was cached already
+/// * `not_found` - ids the source does not know (for spending prefetches: unspent
+///   outpoints)
+/// * `failed` - ids whose batch call failed; they are left uncached
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PrefetchSummary {
+    pub fetched: usize,
+    pub already_cached: usize,
+    pub not_found: usize,
+    pub failed: usize,
+}
+
+impl<C, B> CachingDataSource<C, B>
+where
+    C: BlockchainDataSource + Send + Sync,
+    B: CacheBackend,
+{
+    /// Fetches every uncached transaction of `txids` through the inner batch method and
+    /// caches the results, including negative results for unknown txids.
+    ///
+    /// Runs at most `PREFETCH_CONCURRENCY` batches of `PREFETCH_BATCH_SIZE` at a time.
+    /// A failing batch is counted in `failed` and does not stop the others.
+    /// With `check_status`, the statuses of the fetched transactions are then asked
+    /// one at a time.
+    pub async fn prefetch_transactions(&self, txids: &[Txid]) -> PrefetchSummary {
+        let inner = &self.inner;
+        self.prefetch(txids, CacheKey::Transaction, |chunk| async move {
+            inner.get_transactions_batch(&chunk).await
+        })
+        .await
+    }
+
+    /// Fetches the spenders of every uncached outpoint of `outpoints` through the inner
+    /// batch method and caches them, like `prefetch_transactions`.
+    ///
+    /// Unspent outpoints are counted in `not_found` and only cached with `cache_unspent`.
+    pub async fn prefetch_spending(&self, outpoints: &[OutPoint]) -> PrefetchSummary {
+        let inner = &self.inner;
+        self.prefetch(outpoints, CacheKey::Spending, |chunk| async move {
+            inner.get_spending_transactions_batch(&chunk).await
+        })
+        .await
+    }
+
+    async fn prefetch<T, F, Fut>(
+        &self,
+        ids: &[T],
+        key: fn(T) -> CacheKey,
+        fetch_chunk: F,
+    ) -> PrefetchSummary
+    where
+        T: Copy + Eq + Hash,
+        F: Fn(Vec<T>) -> Fut,
+        Fut: Future<Output = Result<Vec<Option<Transaction>>>>,
+    {
+        let mut summary = PrefetchSummary::default();
+        let mut seen = HashSet::new();
+        let mut missing = Vec::new();
+        for &id in ids {
+            if !seen.insert(id) {
+                continue;
+            }
+            match self.peek(&key(id)) {
+                Some(_) => summary.already_cached += 1,
+                None => missing.push(id),
+            }
+        }
+
+        let mut batches = stream::iter(missing.chunks(PREFETCH_BATCH_SIZE))
+            .map(|chunk| {
+                let fetch = fetch_chunk(chunk.to_vec());
+                async move { (chunk, fetch.await) }
+            })
+            .buffer_unordered(PREFETCH_CONCURRENCY);
+
+        while let Some((chunk, result)) = batches.next().await {
+            let txs = match result {
+                Ok(txs) if txs.len() == chunk.len() => txs,
+                _ => {
+                    summary.failed += chunk.len();
+                    continue;
+                }
+            };
+            for (&id, tx) in chunk.iter().zip(txs) {
+                let key = key(id);
+                let result = match tx {
+                    Some(tx) => {
+                        summary.fetched += 1;
+                        Ok(CachedEntry::Found(Arc::new(tx)))
+                    }
+                    None => {
+                        summary.not_found += 1;
+                        absent_entry(&key)
+                    }
+                };
+                let status = self.status_of(&result).await;
+                self.store(key, &result, status.as_ref());
+            }
+        }
+        summary
+    }
+}
@@ -0,0 +1,221 @@
+//! Cache snapshots: dumping the fresh entries of a cache to a file and loading them back.
+//!
+//! Lighter than a persistent backend when related traces run one after another. Entries
+//! use the `codec` encoding, with the remaining TTL turned into a wall-clock expiry so it
+//! keeps counting down while the snapshot sits on disk.
+//!
+//! # Layout
+//! `MAGIC || version (u16 BE) || records`, each record being
+//! `key_len (u32 BE) || key || value_len (u32 BE) || value`.
+
+use super::{
+    CacheKey, CachedEntry,
+    codec::{decode_key, decode_value, encode_entry, encode_key, now_ms, remaining_ttl},
+};
+use crate::blockchain::{BlockchainError, Result};
+use std::{path::Path, time::Duration};
+
+/// Identifies a snapshot file
+pub const MAGIC: &[u8; 8] = b"PFCACHE\0";
+
+/// Current snapshot format version
+pub const VERSION: u16 = 1;
+
+/// An entry as stored in or loaded from a snapshot, with its remaining TTL
+pub(crate) type SnapshotEntry = (CacheKey, CachedEntry, Option<Duration>);
+
+/// Outcome of loading a snapshot.
+///
+/// # Fields
+/// * `loaded` - entries inserted into the cache
+/// * `skipped` - entries that were malformed or had expired since the snapshot was taken
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SnapshotReport {
+    pub loaded: usize,
+    pub skipped: usize,
+}
+
+/// Writes `entries` to a snapshot file at `path`, replacing any existing file.
+///
+/// # Errors
+/// - `Other` - the file could not be written
+pub(crate) fn write(path: &Path, entries: &[SnapshotEntry]) -> Result<()> {
+    let saved_at_ms = now_ms();
+    let mut bytes = Vec::from(&MAGIC[..]);
+    bytes.extend(VERSION.to_be_bytes());
+    for (key, entry, remaining) in entries {
+        for field in [
+            encode_key(key),
+            encode_entry(entry, saved_at_ms, *remaining),
+        ] {
+            bytes.extend((field.len() as u32).to_be_bytes());
+            bytes.extend(field);
+        }
+    }
+    std::fs::write(path, bytes).map_err(|e| {
+        BlockchainError::Other(format!(
+            "Failed to write cache snapshot to {}: {}",
+            path.display(),
+            e
+        ))
+    })
+}
+
+/// Reads the entries of the snapshot at `path` that are well-formed and still fresh.
+///
+/// Returns them together with the number of skipped entries. A truncated file keeps
+/// the entries before the damage and counts the damaged tail as one skipped entry.
+///
+/// # Errors
+/// - `Other` - the file could not be read
+/// - `InvalidInput` - the file is not a snapshot, or has an unsupported version
+pub(crate) fn read(path: &Path) -> Result<(Vec<SnapshotEntry>, usize)> {
+    let bytes = std::fs::read(path).map_err(|e| {
+        BlockchainError::Other(format!(
+            "Failed to read cache snapshot at {}: {}",
+            path.display(),
+            e
+        ))
+    })?;
+
+    let Some(rest) = bytes.strip_prefix(&MAGIC[..]) else {
+        return Err(BlockchainError::InvalidInput(format!(
+            "{} is not a cache snapshot",
+            path.display()
+        )));
+    };
+    let (version, mut records) = match rest.split_first_chunk::<2>() {
+        Some((version, records)) => (u16::from_be_bytes(*version), records),
+        None => (0, rest),
+    };
+    if version != VERSION {
+        return Err(BlockchainError::InvalidInput(format!(
+            "Unsupported cache snapshot version {} in {}",
+            version,
+            path.display()
+        )));
+    }
+
+    let mut entries = Vec::new();
+    let mut skipped = 0;
+    while !records.is_empty() {
+        let Some((key, value)) = next_field(&mut records).zip(next_field(&mut records)) else {
+            skipped += 1;
+            break;
+        };
+        let decoded = decode_key(key).zip(decode_value(value));
+        match decoded.and_then(|(key, (entry, saved_at_ms, ttl_ms))| {
+            Some((key, entry, remaining_ttl(saved_at_ms, ttl_ms)?))
+        }) {
+            Some(entry) => entries.push(entry),
+            None => skipped += 1,
+        }
+    }
+    Ok((entries, skipped))
+}
+
+/// Splits one length-prefixed field off the front of `records`
+fn next_field<'a>(records: &mut &'a [u8]) -> Option<&'a [u8]> {
+    let (len, rest) = records.split_first_chunk::<4>()?;
+    let len = u32::from_be_bytes(*len) as usize;
+    if rest.len() < len {
+        return None;
+    }
+    let (field, rest) = rest.split_at(len);
+    *records = rest;
+    Some(field)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::tx;
+    use bitcoin::OutPoint;
+    use std::sync::Arc;
+
+    fn sample() -> Vec<SnapshotEntry> {
+        let hour = Some(Duration::from_secs(3600));
+        vec![
+            (
+                CacheKey::Transaction(tx(1).compute_txid()),
+                CachedEntry::Found(Arc::new(tx(1))),
+                None,
+            ),
+            (
+                CacheKey::Spending(OutPoint::new(tx(2).compute_txid(), 1)),
+                CachedEntry::Unspent,
+                hour,
+            ),
+            (
+                CacheKey::Transaction(tx(3).compute_txid()),
+                CachedEntry::NotFound,
+                hour,
+            ),
+        ]
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cache.snapshot");
+
+        write(&path, &sample()).unwrap();
+        let (entries, skipped) = read(&path).unwrap();
+
+        assert_eq!(skipped, 0);
+        assert_eq!(entries.len(), 3);
+        for ((key, entry, ttl), (want_key, want_entry, want_ttl)) in entries.iter().zip(sample()) {
+            assert_eq!((key, entry), (&want_key, &want_entry));
+            match (ttl, want_ttl) {
+                (None, None) => {}
+                (Some(ttl), Some(want)) => assert!(*ttl <= want && *ttl > want / 2),
+                other => panic!("ttl mismatch {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_expired_entries_are_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cache.snapshot");
+        let mut entries = sample();
+        entries[1].2 = Some(Duration::ZERO);
+
+        write(&path, &entries).unwrap();
+        let (loaded, skipped) = read(&path).unwrap();
+
+        assert_eq!((loaded.len(), skipped), (2, 1));
+    }
+
+    #[test]
+    fn test_corrupted_records_are_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cache.snapshot");
+        write(&path, &sample()).unwrap();
+
+        // Break the first record's key tag, then cut the last record short
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[MAGIC.len() + 2 + 4] = 0xff;
+        bytes.truncate(bytes.len() - 3);
+        std::fs::write(&path, bytes).unwrap();
+
+        let (loaded, skipped) = read(&path).unwrap();
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[0].0, sample()[1].0);
+        assert_eq!(skipped, 2);
+    }
+
+    #[test]
+    fn test_foreign_file_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cache.snapshot");
+
+        std::fs::write(&path, b"not a snapshot at all").unwrap();
+        assert!(matches!(read(&path), Err(BlockchainError::InvalidInput(_))));
+
+        let mut future = Vec::from(&MAGIC[..]);
+        future.extend((VERSION + 1).to_be_bytes());
+        std::fs::write(&path, future).unwrap();
+        assert!(matches!(read(&path), Err(BlockchainError::InvalidInput(_))));
+    }
+}
@@ -0,0 +1,163 @@
+//! Hit/miss accounting for `CachingDataSource`.
+//!
+//! Counters are plain atomics updated with relaxed ordering, so keeping them costs a
+//! handful of uncontended increments on the read path.
+
+use super::CacheKey;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Counters for one kind of cache key.
+///
+/// # Fields
+/// * `hits` - lookups served from a fresh entry
+/// * `negative_hits` - lookups served from a cached `NotFound`
+/// * `misses` - lookups with no entry at all
+/// * `expired_hits` - lookups that found an entry past its TTL
+/// * `insertions` - entries written to the cache
+/// * `evictions` - entries removed to respect the size bound
+/// * `rejected` - fetched entries the `CachePolicy` declined to cache
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct KindStats {
+    pub hits: u64,
+    pub negative_hits: u64,
+    pub misses: u64,
+    pub expired_hits: u64,
+    pub insertions: u64,
+    pub evictions: u64,
+    pub rejected: u64,
+}
+
+impl KindStats {
+    /// Total number of lookups
+    pub fn lookups(&self) -> u64 {
+        self.hits + self.negative_hits + self.misses + self.expired_hits
+    }
+
+    fn add(self, other: KindStats) -> KindStats {
+        KindStats {
+            hits: self.hits + other.hits,
+            negative_hits: self.negative_hits + other.negative_hits,
+            misses: self.misses + other.misses,
+            expired_hits: self.expired_hits + other.expired_hits,
+            insertions: self.insertions + other.insertions,
+            evictions: self.evictions + other.evictions,
+            rejected: self.rejected + other.rejected,
+        }
+    }
+}
+
+/// Snapshot of the cache counters, per key kind.
+///
+/// # Fields
+/// * `transaction` - counters for `CacheKey::Transaction` lookups
+/// * `spending` - counters for `CacheKey::Spending` lookups
+/// * `address` - counters for `CacheKey::Address` lookups
+/// * `last_sweep_removed` - expired entries removed by the janitor's most recent sweep
+/// * `weight_bytes` - current serialized size of the cached transactions (0 if the
+///   backend does not track it)
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CacheStats {
+    pub transaction: KindStats,
+    pub spending: KindStats,
+    pub address: KindStats,
+    pub last_sweep_removed: u64,
+    pub weight_bytes: u64,
+}
+
+impl CacheStats {
+    /// Counters summed across all key kinds
+    pub fn total(&self) -> KindStats {
+        self.transaction.add(self.spending).add(self.address)
+    }
+
+    /// Fraction of lookups served from the cache (positive or negative), 0.0 if none
+    pub fn hit_rate(&self) -> f64 {
+        let total = self.total();
+        match total.lookups() {
+            0 => 0.0,
+            lookups => (total.hits + total.negative_hits) as f64 / lookups as f64,
+        }
+    }
+}
+
+/// Live atomic counters for one key kind
+#[derive(Debug, Default)]
+pub(crate) struct KindCounters {
+    pub(crate) hits: AtomicU64,
+    pub(crate) negative_hits: AtomicU64,
+    pub(crate) misses: AtomicU64,
+    pub(crate) expired_hits: AtomicU64,
+    pub(crate) insertions: AtomicU64,
+    pub(crate) evictions: AtomicU64,
+    pub(crate) rejected: AtomicU64,
+}
+
+impl KindCounters {
+    fn snapshot(&self) -> KindStats {
+        KindStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            negative_hits: self.negative_hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            expired_hits: self.expired_hits.load(Ordering::Relaxed),
+            insertions: self.insertions.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            rejected: self.rejected.load(Ordering::Relaxed),
+        }
+    }
+
+    fn reset(&self) {
+        for counter in [
+            &self.hits,
+            &self.negative_hits,
+            &self.misses,
+            &self.expired_hits,
+            &self.insertions,
+            &self.evictions,
+            &self.rejected,
+        ] {
+            counter.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Live atomic counters shared by a cache
+#[derive(Debug, Default)]
+pub(crate) struct StatsCounters {
+    transaction: KindCounters,
+    spending: KindCounters,
+    address: KindCounters,
+    pub(crate) last_sweep_removed: AtomicU64,
+}
+
+impl StatsCounters {
+    /// Counters for the kind of `key`
+    pub(crate) fn kind(&self, key: &CacheKey) -> &KindCounters {
+        match key {
+            CacheKey::Transaction(_) => &self.transaction,
+            CacheKey::Spending(_) => &self.spending,
+            CacheKey::Address(_) => &self.address,
+        }
+    }
+
+    pub(crate) fn snapshot(&self) -> CacheStats {
+        CacheStats {
+            transaction: self.transaction.snapshot(),
+            spending: self.spending.snapshot(),
+            address: self.address.snapshot(),
+            last_sweep_removed: self.last_sweep_removed.load(Ordering::Relaxed),
+            weight_bytes: 0,
+        }
+    }
+
+    pub(crate) fn reset(&self) {
+        self.transaction.reset();
+        self.spending.reset();
+        self.address.reset();
+        self.last_sweep_removed.store(0, Ordering::Relaxed);
+    }
+}
+
+/// Increments a counter by one
+pub(crate) fn bump(counter: &AtomicU64) {
+    counter.fetch_add(1, Ordering::Relaxed);
+}
@@ -0,0 +1,147 @@
+//! Expiry policy for cache entries.
+
+use super::{CacheKey, CachedEntry};
+use crate::blockchain::TxStatus;
+use std::{
+    sync::{Mutex, PoisonError},
+    time::Duration,
+};
+
+/// Default time to live for cache entries
+pub const DEFAULT_TTL: Duration = Duration::from_secs(300);
+
+/// Default time to live for cached address histories
+pub const DEFAULT_ADDRESS_TTL: Duration = Duration::from_secs(60);
+
+/// Default time to live for cached `NotFound` results
+pub const DEFAULT_NEGATIVE_TTL: Duration = Duration::from_secs(30);
+
+/// Default time to live for transactions known to be unconfirmed
+pub const DEFAULT_UNCONFIRMED_TTL: Duration = Duration::from_secs(30);
+
+/// Per-kind time to live for cache entries.
+///
+/// A confirmed transaction is immutable, so transaction lookups can safely never expire,
+/// while spending lookups can go stale (a mempool spender gets replaced) and want a
+/// short TTL.
+///
+/// `BlockchainDataSource` returns bare transactions without confirmation status. A
+/// cache built with `check_status` asks for it, and keeps a transaction it is told is
+/// unconfirmed for the `unconfirmed` TTL at most, even under a `None` transaction TTL.
+/// Without it, a `None` transaction TTL also applies to mempool transactions: only use
+/// it then when the traced transactions are known to be confirmed.
+///
+/// # Fields
+/// * `transaction` - transactions fetched by txid (`None` = never expire)
+/// * `spending` - spenders fetched by outpoint (`None` = never expire)
+/// * `address` - address histories, which grow with every new payment (`None` = never
+///   expire)
+/// * `negative` - cached `NotFound` results (`None` = never expire)
+/// * `unspent` - unspent markers; `None` disables unspent caching altogether since an
+///   unspent output can be spent at any moment
+/// * `unconfirmed` - cap on the TTL of transactions known to be unconfirmed, which
+///   can be replaced or dropped from the mempool (`None` = no cap)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TtlPolicy {
+    pub transaction: Option<Duration>,
+    pub spending: Option<Duration>,
+    pub address: Option<Duration>,
+    pub negative: Option<Duration>,
+    pub unspent: Option<Duration>,
+    pub unconfirmed: Option<Duration>,
+}
+
+impl TtlPolicy {
+    /// Same TTL for transaction and spending lookups, default address, negative and
+    /// unconfirmed TTLs, no unspent caching. This is what `CachingDataSource::new` uses.
+    pub fn uniform(ttl: Duration) -> Self {
+        Self {
+            transaction: Some(ttl),
+            spending: Some(ttl),
+            address: Some(DEFAULT_ADDRESS_TTL),
+            negative: Some(DEFAULT_NEGATIVE_TTL),
+            unspent: None,
+            unconfirmed: Some(DEFAULT_UNCONFIRMED_TTL),
+        }
+    }
+
+    /// Transactions never expire, everything else keeps the uniform defaults with
+    /// `spending` as the TTL for spending lookups.
+    pub fn immutable_transactions(spending: Duration) -> Self {
+        Self {
+            transaction: None,
+            ..Self::uniform(spending)
+        }
+    }
+
+    /// TTL for `entry` stored under `key`, its transaction of status `status` if
+    /// known, or `None` if it never expires
+    pub(crate) fn ttl_for(
+        &self,
+        key: &CacheKey,
+        entry: &CachedEntry,
+        status: Option<&TxStatus>,
+    ) -> Option<Duration> {
+        let ttl = match (entry, key) {
+            (CachedEntry::NotFound, _) => self.negative,
+            (CachedEntry::Unspent, _) => self.unspent,
+            (_, CacheKey::Transaction(_)) => self.transaction,
+            (_, CacheKey::Spending(_)) => self.spending,
+            (_, CacheKey::Address(_)) => self.address,
+        };
+        match (status, self.unconfirmed) {
+            (Some(status), Some(cap)) if !status.confirmed => {
+                Some(ttl.map_or(cap, |ttl| ttl.min(cap)))
+            }
+            _ => ttl,
+        }
+    }
+}
+
+impl Default for TtlPolicy {
+    fn default() -> Self {
+        Self::uniform(DEFAULT_TTL)
+    }
+}
+
+/// Random spread applied to every finite TTL at insert time.
+///
+/// Entries cached during the same burst would otherwise all expire together and be
+/// refetched in one stampede.
+#[derive(Debug)]
+pub(crate) struct Jitter {
+    /// Maximum deviation as a fraction of the TTL (0.1 = ±10%)
+    fraction: f64,
+    rng: Mutex<fastrand::Rng>,
+}
+
+impl Jitter {
+    /// Returns `None` for a zero fraction, so the common case draws no random numbers,
+    /// and for a NaN or infinite one. `seed` makes the spread reproducible.
+    pub(crate) fn new(fraction: f64, seed: Option<u64>) -> Option<Self> {
+        if !fraction.is_finite() {
+            return None;
+        }
+        let fraction = fraction.clamp(0.0, 1.0);
+        if fraction == 0.0 {
+            return None;
+        }
+        let rng = seed.map_or_else(fastrand::Rng::new, fastrand::Rng::with_seed);
+        Some(Self {
+            fraction,
+            rng: Mutex::new(rng),
+        })
+    }
+
+    /// `ttl` scaled by a uniform random factor in `[1 - fraction, 1 + fraction]`, or
+    /// `ttl` itself if the scaled one would not fit in a `Duration`
+    pub(crate) fn apply(&self, ttl: Duration) -> Duration {
+        let unit = self
+            .rng
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .f64();
+        let factor = 1.0 + self.fraction * (2.0 * unit - 1.0);
+        Duration::try_from_secs_f64(ttl.as_secs_f64() * factor).unwrap_or(ttl)
+    }
+}
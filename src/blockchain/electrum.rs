@@ -0,0 +1,1259 @@
+//! Electrum server client, over the protocol's newline-delimited JSON-RPC.
+//!
+//! Electrum servers (electrs, Fulcrum, ElectrumX) index outputs by the hash of their
+//! script rather than by outpoint, so the spender of an output is found among the
+//! transactions of its script's history.
+
+use crate::blockchain::{
+    BlockchainDataSource, BlockchainError, DEFAULT_MAX_BATCH_SIZE, ErrorContext, Result, ResultExt,
+    RetryPolicy, TxStatus, batch, execute, redact,
+};
+use async_trait::async_trait;
+use bitcoin::hashes::{Hash, sha256};
+use bitcoin::hex::DisplayHex;
+use bitcoin::{Address, OutPoint, Script, Transaction, Txid, block::Header};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tracing::{Instrument, Span, field};
+
+/// Protocol version asked for on connecting: the first without the `address` methods,
+/// which every server still running speaks
+pub const PROTOCOL_VERSION: &str = "1.4";
+
+/// Name this client gives itself in `server.version`
+const CLIENT_NAME: &str = concat!("pathfinder ", env!("CARGO_PKG_VERSION"));
+
+/// Port of `tcp://` servers given none
+const TCP_PORT: u16 = 50001;
+/// Port of `ssl://` servers given none
+const SSL_PORT: u16 = 50002;
+
+/// Electrum client used to retrieve blockchain data.
+///
+/// Requests go over one persistent connection, opened on the first of them with a
+/// `server.version` handshake and opened again, on the next request, once the server
+/// drops it: the requests it left unanswered fail with `NetworkFailure`, which the
+/// `retry` policy retries. Concurrent requests share the connection, their answers
+/// told apart by request id.
+///
+/// # Servers
+/// `tcp://host:port` (or `host:port`) for plain TCP, `ssl://host:port` for TLS,
+/// which needs the `electrum-tls` feature and checks certificates against the
+/// platform's roots, so self-signed servers are turned down.
+pub struct ElectrumClient {
+    url: String,
+    timeout: Option<Duration>,
+    retry: RetryPolicy,
+    max_batch_size: usize,
+    connection: tokio::sync::Mutex<Option<Arc<Connection>>>,
+    next_id: AtomicU64,
+}
+
+impl fmt::Debug for ElectrumClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ElectrumClient")
+            .field("url", &redact::url(&self.url))
+            .field("timeout", &self.timeout)
+            .field("retry", &self.retry)
+            .field("max_batch_size", &self.max_batch_size)
+            .finish_non_exhaustive()
+    }
+}
+
+/// What the server said of itself in the `server.version` handshake
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ServerVersion {
+    /// Server software and version, e.g. "electrs/0.10.6"
+    pub software: String,
+    /// Protocol version both ends settled on
+    pub protocol: String,
+}
+
+impl ElectrumClient {
+    /// Creates a new Electrum client
+    ///
+    /// # Arguments
+    /// * `url` - Server to connect to (e.g. "ssl://electrum.blockstream.info:50002")
+    ///
+    /// Nothing is connected until the first request.
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            timeout: None,
+            retry: RetryPolicy::default(),
+            max_batch_size: DEFAULT_MAX_BATCH_SIZE,
+            connection: tokio::sync::Mutex::new(None),
+            next_id: AtomicU64::new(0),
+        }
+    }
+
+    /// Gives up on requests that get no answer within `timeout`, connecting
+    /// included, failing with an error `is_timeout` holds for (none unless set)
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Retries of requests failing with a retryable error (none unless set)
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Requests of a batch lookup in flight together at most, the rest sent as
+    /// those are answered (`DEFAULT_MAX_BATCH_SIZE` unless set)
+    pub fn max_batch_size(mut self, size: usize) -> Self {
+        self.max_batch_size = size;
+        self
+    }
+
+    /// What the server said of itself on connecting, connecting if need be
+    pub async fn server_version(&self) -> Result<ServerVersion> {
+        Ok(self.connection().await?.version.clone())
+    }
+
+    /// The server's banner, its operator's message to clients
+    ///
+    /// Uses `server.banner`.
+    pub async fn banner(&self) -> Result<String> {
+        let banner = self.call("server.banner", json!([])).await?;
+        serde_json::from_value(banner).map_err(|e| BlockchainError::decode("server banner", e))
+    }
+
+    /// Context of the errors of `operation`, naming this backend
+    fn context(&self, operation: &'static str) -> ErrorContext {
+        ErrorContext::new(operation).url(redact::url(&self.url))
+    }
+
+    /// Result of `method` called with `params`, tried again as the retry policy
+    /// allows.
+    ///
+    /// # Errors
+    /// - `NetworkFailure` - No connection, or it was lost before the answer came
+    /// - `Timeout` - No answer within the timeout
+    /// - `NotFound` - The server found nothing to answer with
+    /// - `Other` - Any other error the server answered with
+    async fn call(&self, method: &'static str, params: Value) -> Result<Value> {
+        execute(&self.retry, || {
+            let span = tracing::debug_span!(
+                "electrum_request",
+                method,
+                url = %redact::url(&self.url),
+                elapsed_ms = field::Empty,
+            );
+            self.attempt(method, params.clone()).instrument(span)
+        })
+        .await
+    }
+
+    /// One call of `method`, as `call` makes them, recording its elapsed time on the
+    /// current span
+    async fn attempt(&self, method: &'static str, params: Value) -> Result<Value> {
+        let sent = Instant::now();
+        let answer = async {
+            let connection = self.connection().await?;
+            connection.request(self.id(), method, params).await
+        };
+        let answer = match self.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, answer)
+                .await
+                .unwrap_or_else(|_| {
+                    Err(BlockchainError::Timeout {
+                        elapsed: timeout,
+                        url: Some(redact::url(&self.url).into_owned()),
+                    })
+                }),
+            None => answer.await,
+        };
+        Span::current().record("elapsed_ms", sent.elapsed().as_millis() as u64);
+        match &answer {
+            Ok(_) => tracing::debug!("response"),
+            Err(e) => tracing::debug!(error = %e, "no response"),
+        }
+        answer
+    }
+
+    /// Id of the next request
+    fn id(&self) -> u64 {
+        self.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// The open connection, opened again if the server dropped it
+    async fn connection(&self) -> Result<Arc<Connection>> {
+        let mut current = self.connection.lock().await;
+        if let Some(connection) = current.as_ref().filter(|c| c.is_open()) {
+            return Ok(connection.clone());
+        }
+        let connection = Arc::new(self.connect().await?);
+        *current = Some(connection.clone());
+        Ok(connection)
+    }
+
+    /// New connection to the server, past its `server.version` handshake
+    async fn connect(&self) -> Result<Connection> {
+        let endpoint = Endpoint::parse(&self.url)?;
+        let stream = TcpStream::connect((endpoint.host.as_str(), endpoint.port))
+            .await
+            .map_err(|e| {
+                BlockchainError::NetworkFailure(format!(
+                    "Could not connect to Electrum server {}: {}",
+                    endpoint, e
+                ))
+            })?;
+        let mut connection = match endpoint.tls {
+            false => Connection::open(stream, endpoint.to_string()),
+            true => tls(stream, &endpoint).await?,
+        };
+
+        let version = connection
+            .request(
+                self.id(),
+                "server.version",
+                json!([CLIENT_NAME, PROTOCOL_VERSION]),
+            )
+            .await?;
+        let (software, protocol): (String, String) = serde_json::from_value(version)
+            .map_err(|e| BlockchainError::decode("server.version response", e))?;
+        tracing::debug!(server = %endpoint, software, protocol, "connected");
+        connection.version = ServerVersion { software, protocol };
+        Ok(connection)
+    }
+
+    /// Transactions paying or spending from `script`, confirmed ones by height, then
+    /// unconfirmed ones
+    ///
+    /// Uses `blockchain.scripthash.get_history`.
+    async fn history(&self, script: &Script) -> Result<Vec<HistoryEntry>> {
+        let hash = script_hash(script);
+        let history = self
+            .call("blockchain.scripthash.get_history", json!([hash]))
+            .await?;
+        serde_json::from_value(history)
+            .map_err(|e| BlockchainError::decode(format!("history of script hash {}", hash), e))
+    }
+
+    /// Header of the block at `height`
+    ///
+    /// Uses `blockchain.block.header`, which answers with the header's hex.
+    async fn header_at(&self, height: u32) -> Result<Header> {
+        let header = self
+            .call("blockchain.block.header", json!([height]))
+            .await?;
+        let hex = header.as_str().ok_or_else(|| {
+            BlockchainError::DataInconsistency(format!(
+                "Header of block {} is not a hex string",
+                height
+            ))
+        })?;
+        bitcoin::consensus::encode::deserialize_hex(hex)
+            .map_err(|e| BlockchainError::decode(format!("header of block {}", height), e))
+    }
+}
+
+/// Electrum's script hash of `script`: its SHA256, bytes reversed, in hex
+pub fn script_hash(script: &Script) -> String {
+    let mut hash = sha256::Hash::hash(script.as_bytes()).to_byte_array();
+    hash.reverse();
+    hash.to_lower_hex_string()
+}
+
+/// Server address, as parsed from the client's URL
+#[derive(Debug)]
+struct Endpoint {
+    host: String,
+    port: u16,
+    tls: bool,
+}
+
+impl Endpoint {
+    /// Endpoint of `url`: `tcp://host:port`, `ssl://host:port`, or `host:port` for
+    /// plain TCP
+    fn parse(url: &str) -> Result<Self> {
+        let (scheme, address) = url.split_once("://").unwrap_or(("tcp", url));
+        let tls = match scheme {
+            "tcp" => false,
+            "ssl" | "tls" => true,
+            _ => {
+                return Err(BlockchainError::InvalidInput(format!(
+                    "Electrum server {} is neither tcp:// nor ssl://",
+                    redact::url(url)
+                )));
+            }
+        };
+        let address = address.trim_end_matches('/');
+        // The port follows the last colon, unless it closes an IPv6 address
+        let (host, port) = match address.rsplit_once(':') {
+            Some((host, port)) if !port.ends_with(']') => {
+                let port = port.parse().map_err(|_| {
+                    BlockchainError::InvalidInput(format!(
+                        "Electrum server {} has no valid port",
+                        redact::url(url)
+                    ))
+                })?;
+                (host, port)
+            }
+            _ => (address, if tls { SSL_PORT } else { TCP_PORT }),
+        };
+        Ok(Self {
+            host: host
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .to_string(),
+            port,
+            tls,
+        })
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let scheme = if self.tls { "ssl" } else { "tcp" };
+        match self.host.contains(':') {
+            true => write!(f, "{}://[{}]:{}", scheme, self.host, self.port),
+            false => write!(f, "{}://{}:{}", scheme, self.host, self.port),
+        }
+    }
+}
+
+/// TLS session over `stream`, the server's certificate checked for its host name
+#[cfg(feature = "electrum-tls")]
+async fn tls(stream: TcpStream, endpoint: &Endpoint) -> Result<Connection> {
+    use rustls_platform_verifier::ConfigVerifierExt;
+    use tokio_rustls::rustls::{ClientConfig, pki_types::ServerName};
+
+    let failure = |e: &dyn fmt::Display| {
+        BlockchainError::NetworkFailure(format!(
+            "TLS with Electrum server {} failed: {}",
+            endpoint, e
+        ))
+    };
+    let config = ClientConfig::with_platform_verifier().map_err(|e| failure(&e))?;
+    let name = ServerName::try_from(endpoint.host.clone()).map_err(|e| {
+        BlockchainError::InvalidInput(format!(
+            "Electrum server {} has no valid host name: {}",
+            endpoint, e
+        ))
+    })?;
+    let stream = tokio_rustls::TlsConnector::from(Arc::new(config))
+        .connect(name, stream)
+        .await
+        .map_err(|e| failure(&e))?;
+    Ok(Connection::open(stream, endpoint.to_string()))
+}
+
+#[cfg(not(feature = "electrum-tls"))]
+async fn tls(_stream: TcpStream, _endpoint: &Endpoint) -> Result<Connection> {
+    Err(BlockchainError::unsupported(
+        "ElectrumClient",
+        "ssl:// servers",
+        "build with the electrum-tls feature",
+    ))
+}
+
+/// Requests sent and not yet answered, by id: `None` once the connection is lost
+type Pending = Arc<Mutex<PendingInner>>;
+type PendingInner = Option<HashMap<u64, oneshot::Sender<Result<Value>>>>;
+
+/// Locks the pending requests, recovering them if poisoned.
+///
+/// Every change to them is a single insert, remove or take, so a panic while they
+/// are locked cannot leave them half updated.
+fn lock_pending(pending: &Pending) -> MutexGuard<'_, PendingInner> {
+    pending.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// A connection to an Electrum server, its answers read by a task of its own
+struct Connection {
+    server: String,
+    version: ServerVersion,
+    writer: tokio::sync::Mutex<Box<dyn AsyncWrite + Send + Unpin>>,
+    pending: Pending,
+    reader: JoinHandle<()>,
+}
+
+impl Connection {
+    /// Connection over `stream` to `server`
+    fn open<S>(stream: S, server: String) -> Self
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let (reader, writer) = tokio::io::split(stream);
+        let pending: Pending = Arc::new(Mutex::new(Some(HashMap::new())));
+        let reader = tokio::spawn(read_answers(reader, pending.clone(), server.clone()));
+        Self {
+            server,
+            version: ServerVersion::default(),
+            writer: tokio::sync::Mutex::new(Box::new(writer)),
+            pending,
+            reader,
+        }
+    }
+
+    /// Whether the server has not dropped the connection
+    fn is_open(&self) -> bool {
+        lock_pending(&self.pending).is_some()
+    }
+
+    /// Result of request `id`, calling `method` with `params`
+    async fn request(&self, id: u64, method: &str, params: Value) -> Result<Value> {
+        let (sender, answer) = oneshot::channel();
+        if let Some(pending) = lock_pending(&self.pending).as_mut() {
+            pending.insert(id, sender);
+        } else {
+            return Err(self.lost("dropped the connection"));
+        }
+        // Forgets the request if this future is dropped unanswered, on a timeout
+        let _waiting = Waiting {
+            id,
+            pending: &self.pending,
+        };
+
+        let mut line = json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": method,
+            "params": params,
+        })
+        .to_string();
+        line.push('\n');
+        let written = {
+            let mut writer = self.writer.lock().await;
+            match writer.write_all(line.as_bytes()).await {
+                Ok(()) => writer.flush().await,
+                Err(e) => Err(e),
+            }
+        };
+        if let Err(e) = written {
+            let error = self.lost(e);
+            fail_pending(&self.pending, &error);
+            return Err(error);
+        }
+
+        answer
+            .await
+            .unwrap_or_else(|_| Err(self.lost("dropped the connection")))
+    }
+
+    /// `NetworkFailure` of a connection lost for `reason`
+    fn lost(&self, reason: impl fmt::Display) -> BlockchainError {
+        BlockchainError::NetworkFailure(format!("Electrum server {}: {}", self.server, reason))
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
+
+/// Removes request `id` from the pending ones when dropped
+struct Waiting<'a> {
+    id: u64,
+    pending: &'a Pending,
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        if let Some(pending) = lock_pending(self.pending).as_mut() {
+            pending.remove(&self.id);
+        }
+    }
+}
+
+/// Hands each answer read from `reader` to the request of its id, until the
+/// connection is lost; the requests left then fail
+async fn read_answers(reader: impl AsyncRead + Unpin, pending: Pending, server: String) {
+    let mut lines = BufReader::new(reader).lines();
+    let reason = loop {
+        match lines.next_line().await {
+            Ok(Some(line)) => dispatch(&line, &pending),
+            Ok(None) => break "closed the connection".to_string(),
+            Err(e) => break e.to_string(),
+        }
+    };
+    tracing::debug!(server, reason, "connection lost");
+    fail_pending(
+        &pending,
+        &BlockchainError::NetworkFailure(format!("Electrum server {} {}", server, reason)),
+    );
+}
+
+/// Fails every request still waiting on the connection with `error`, marking it lost
+fn fail_pending(pending: &Pending, error: &BlockchainError) {
+    let waiting = lock_pending(pending).take();
+    for (_, sender) in waiting.into_iter().flatten() {
+        let _ = sender.send(Err(error.clone()));
+    }
+}
+
+/// Hands the answer, or the batch of answers, on `line` to the requests of their ids.
+/// Notifications, which have none, are skipped.
+fn dispatch(line: &str, pending: &Pending) {
+    let answers = match serde_json::from_str(line) {
+        Ok(Value::Array(answers)) => answers,
+        Ok(answer) => vec![answer],
+        Err(e) => {
+            tracing::debug!(error = %e, "undecodable line");
+            return;
+        }
+    };
+    for mut answer in answers {
+        let Some(id) = answer.get("id").and_then(Value::as_u64) else {
+            continue;
+        };
+        let sender = lock_pending(pending)
+            .as_mut()
+            .and_then(|pending| pending.remove(&id));
+        if let Some(sender) = sender {
+            let result = match answer.get("error") {
+                Some(error) if !error.is_null() => Err(server_error(error)),
+                _ => Ok(answer["result"].take()),
+            };
+            let _ = sender.send(result);
+        }
+    }
+}
+
+/// `BlockchainError` of an error answer, an object of a code and message or, from
+/// older servers, a bare message
+fn server_error(error: &Value) -> BlockchainError {
+    let code = error.get("code").and_then(Value::as_i64).unwrap_or(0);
+    let message = error
+        .get("message")
+        .or(Some(error))
+        .and_then(Value::as_str)
+        .unwrap_or("Unknown Electrum error");
+    // Servers relay bitcoind's "No such mempool or blockchain transaction"
+    let lowercase = message.to_lowercase();
+    match lowercase.contains("no such") || lowercase.contains("not found") {
+        true => BlockchainError::NotFound(message.to_string()),
+        false => BlockchainError::Other(format!("Electrum error {}: {}", code, message)),
+    }
+}
+
+/// A transaction of a script's history
+#[derive(Deserialize, Debug)]
+struct HistoryEntry {
+    tx_hash: Txid,
+    /// Height of its block, 0 or -1 while unconfirmed
+    height: i64,
+}
+
+/// An unspent output paying a script
+#[derive(Deserialize, Debug)]
+struct UnspentEntry {
+    tx_hash: Txid,
+    tx_pos: u32,
+    /// Height of its transaction's block, 0 while unconfirmed
+    height: i64,
+}
+
+#[async_trait]
+impl BlockchainDataSource for ElectrumClient {
+    /// Fetches a transaction by its txid.
+    ///
+    /// Uses `blockchain.transaction.get`, which answers with the raw transaction's
+    /// hex.
+    ///
+    /// # Errors
+    /// - `NetworkFailure` - Connection failed or lost
+    /// - `Timeout` - No answer within the timeout
+    /// - `NotFound` - Transaction not found
+    /// - `Decode` - Invalid hex or deserialization failure
+    async fn get_transaction(&self, txid: Txid) -> Result<Transaction> {
+        async {
+            let hex = self
+                .call("blockchain.transaction.get", json!([txid, false]))
+                .await
+                .map_err(|e| match e {
+                    BlockchainError::NotFound(_) => {
+                        BlockchainError::NotFound(format!("Transaction {} not found", txid))
+                    }
+                    e => e,
+                })?;
+            let hex = hex.as_str().ok_or_else(|| {
+                BlockchainError::DataInconsistency(format!(
+                    "Transaction {} is not a hex string",
+                    txid
+                ))
+            })?;
+
+            bitcoin::consensus::encode::deserialize_hex(hex)
+                .map_err(|e| BlockchainError::decode(format!("transaction {}", txid), e))
+        }
+        .await
+        .with_ctx(|| self.context("get_transaction").txid(txid))
+    }
+
+    /// Finds the transaction that spends a specific OutPoint.
+    ///
+    /// Electrum has no outpoint index: this fetches the transaction of the outpoint,
+    /// then the history of its output's script, and scans the inputs of the other
+    /// transactions of that history for the outpoint. Once the funding transaction
+    /// is confirmed, the confirmed ones below its height are skipped; unconfirmed
+    /// ones are always scanned, as servers list the mempool by txid rather than in
+    /// spending order.
+    ///
+    /// # Returns
+    /// - `Ok(Some(tx))` - The transaction that spent this outpoint
+    /// - `Ok(None)` - The outpoint is still unspent, or cannot be spent (OP_RETURN)
+    /// - `Err(NotFound)` - The original transaction or its output doesn't exist
+    async fn get_spending_transaction(&self, outpoint: OutPoint) -> Result<Option<Transaction>> {
+        async {
+            let funding = self.get_transaction(outpoint.txid).await?;
+            let output = funding.output.get(outpoint.vout as usize).ok_or_else(|| {
+                BlockchainError::NotFound(format!("Output {} not found", outpoint))
+            })?;
+            if output.script_pubkey.is_op_return() {
+                return Ok(None);
+            }
+
+            let history = self.history(&output.script_pubkey).await?;
+            let funded_at = history
+                .iter()
+                .find(|entry| entry.tx_hash == outpoint.txid)
+                .map(|entry| entry.height)
+                .filter(|&height| height >= 1);
+            // A spender is no lower than what it spends, unless that is unconfirmed
+            let candidates = history.iter().filter(|entry| {
+                entry.tx_hash != outpoint.txid
+                    && funded_at
+                        .is_none_or(|funded_at| entry.height < 1 || entry.height >= funded_at)
+            });
+            for entry in candidates {
+                let candidate = self.get_transaction(entry.tx_hash).await?;
+                if candidate
+                    .input
+                    .iter()
+                    .any(|input| input.previous_output == outpoint)
+                {
+                    return Ok(Some(candidate));
+                }
+            }
+            Ok(None)
+        }
+        .await
+        .with_ctx(|| self.context("get_spending_transaction").outpoint(outpoint))
+    }
+
+    /// Fetches every transaction paying or spending from an address, unconfirmed
+    /// ones first, then the confirmed ones newest first.
+    ///
+    /// Uses `blockchain.scripthash.get_history` for the txids, then fetches each
+    /// transaction by its txid.
+    ///
+    /// # Errors
+    /// - `NetworkFailure` - Connection failed or lost
+    /// - `Timeout` - No answer within the timeout
+    /// - `NotFound` - One of its transactions not found
+    /// - `Decode` - The history is not valid JSON, or a transaction does not decode
+    async fn get_address_transactions(&self, address: Address) -> Result<Vec<Transaction>> {
+        async {
+            let history = self.history(&address.script_pubkey()).await?;
+            let mut transactions = Vec::with_capacity(history.len());
+            for entry in history.iter().rev() {
+                transactions.push(self.get_transaction(entry.tx_hash).await?);
+            }
+            Ok(transactions)
+        }
+        .await
+        .with_ctx(|| self.context("get_address_transactions"))
+    }
+
+    /// Lists the unspent outputs paying an address, oldest transaction first.
+    ///
+    /// Uses `blockchain.scripthash.listunspent`, which lists them without the
+    /// transactions.
+    async fn get_address_utxos(&self, address: Address) -> Result<Vec<OutPoint>> {
+        async {
+            let hash = script_hash(&address.script_pubkey());
+            let unspent = self
+                .call("blockchain.scripthash.listunspent", json!([hash]))
+                .await?;
+            let mut unspent: Vec<UnspentEntry> = serde_json::from_value(unspent).map_err(|e| {
+                BlockchainError::decode(format!("unspent outputs of address {}", address), e)
+            })?;
+            // Confirmed by height, then unconfirmed
+            unspent.sort_by_key(|entry| (entry.height <= 0, entry.height));
+            Ok(unspent
+                .into_iter()
+                .map(|entry| OutPoint::new(entry.tx_hash, entry.tx_pos))
+                .collect())
+        }
+        .await
+        .with_ctx(|| self.context("get_address_utxos"))
+    }
+
+    /// Fetches transactions by txid, each distinct one once, the max batch size of
+    /// them in flight at a time.
+    async fn get_transactions_batch(&self, txids: &[Txid]) -> Result<Vec<Option<Transaction>>> {
+        // Requests share the connection, answered as the server gets to them
+        batch::run(txids, self.max_batch_size, |txids| {
+            futures::future::try_join_all(txids.into_iter().map(|txid| async move {
+                match self.get_transaction(txid).await {
+                    Ok(tx) => Ok(Some(tx)),
+                    Err(e) if matches!(e.inner(), BlockchainError::NotFound(_)) => Ok(None),
+                    Err(e) => Err(e),
+                }
+            }))
+        })
+        .await
+    }
+
+    /// Finds the spenders of outpoints, each distinct one once, the max batch size
+    /// of them in flight at a time.
+    async fn get_spending_transactions_batch(
+        &self,
+        outpoints: &[OutPoint],
+    ) -> Result<Vec<Option<Transaction>>> {
+        batch::run(outpoints, self.max_batch_size, |outpoints| {
+            futures::future::try_join_all(
+                outpoints
+                    .into_iter()
+                    .map(|outpoint| self.get_spending_transaction(outpoint)),
+            )
+        })
+        .await
+    }
+
+    /// Fetches the confirmation status of a transaction.
+    ///
+    /// Electrum reports heights in script histories only: this finds the
+    /// transaction in the history of its first spendable output's script, trying
+    /// the next ones if it is missing there, then the hash and time of the block
+    /// at its height with `blockchain.block.header`.
+    ///
+    /// # Errors
+    /// - `NotFound` - Transaction not found
+    /// - `DataInconsistency` - The transaction has no spendable outputs, or is
+    ///   missing from the history of each of their scripts
+    async fn get_transaction_status(&self, txid: Txid) -> Result<TxStatus> {
+        async {
+            let tx = self.get_transaction(txid).await?;
+            // Servers index histories by script: any output with a script the
+            // transaction can be found under will do, the first one preferably
+            let scripts: Vec<&Script> = tx
+                .output
+                .iter()
+                .map(|output| output.script_pubkey.as_script())
+                .filter(|script| !script.is_empty() && !script.is_op_return())
+                .collect();
+            if scripts.is_empty() {
+                return Err(BlockchainError::DataInconsistency(format!(
+                    "Transaction {} has no spendable outputs",
+                    txid
+                )));
+            }
+            let mut found = None;
+            for script in scripts {
+                let history = self.history(script).await?;
+                if let Some(entry) = history.into_iter().find(|entry| entry.tx_hash == txid) {
+                    found = Some(entry);
+                    break;
+                }
+            }
+            let entry = found.ok_or_else(|| {
+                BlockchainError::DataInconsistency(format!(
+                    "Transaction {} is missing from the history of its outputs",
+                    txid
+                ))
+            })?;
+            let Ok(height @ 1..) = u32::try_from(entry.height) else {
+                return Ok(TxStatus::unconfirmed());
+            };
+
+            let header = self.header_at(height).await?;
+            Ok(TxStatus {
+                confirmed: true,
+                block_height: Some(height),
+                block_hash: Some(header.block_hash()),
+                block_time: Some(u64::from(header.time)),
+            })
+        }
+        .await
+        .with_ctx(|| self.context("get_transaction_status").txid(txid))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::ChainBuilder;
+    use bitcoin::{
+        BlockHash, CompactTarget, Network, ScriptBuf, TxMerkleNode, block::Version,
+        consensus::encode::serialize_hex,
+    };
+    use std::collections::HashSet;
+    use std::sync::atomic::AtomicUsize;
+    use tokio::net::TcpListener;
+
+    /// Height the first transaction of an `Index` confirms at
+    const FIRST_HEIGHT: i64 = 800_000;
+
+    /// What a fake server knows: transactions, confirmed one block each in order
+    /// unless left unconfirmed
+    #[derive(Clone)]
+    struct Index {
+        txs: Vec<Transaction>,
+        unconfirmed: HashSet<Txid>,
+    }
+
+    impl Index {
+        fn new(txs: &[Transaction]) -> Self {
+            Self {
+                txs: txs.to_vec(),
+                unconfirmed: HashSet::new(),
+            }
+        }
+
+        fn unconfirmed(mut self, txid: Txid) -> Self {
+            self.unconfirmed.insert(txid);
+            self
+        }
+
+        fn height(&self, position: usize) -> i64 {
+            match self
+                .unconfirmed
+                .contains(&self.txs[position].compute_txid())
+            {
+                true => 0,
+                false => FIRST_HEIGHT + position as i64,
+            }
+        }
+
+        fn header(height: u32) -> Header {
+            Header {
+                version: Version::TWO,
+                prev_blockhash: BlockHash::all_zeros(),
+                merkle_root: TxMerkleNode::all_zeros(),
+                time: 1_700_000_000 + height,
+                bits: CompactTarget::from_consensus(0x1d00ffff),
+                nonce: height,
+            }
+        }
+
+        /// Whether transaction `position` pays or spends from the script of `hash`.
+        /// OP_RETURN outputs are left out, as servers leave them out of histories.
+        fn touches(&self, position: usize, hash: &str) -> bool {
+            let tx = &self.txs[position];
+            let pays = |output: &bitcoin::TxOut| {
+                !output.script_pubkey.is_op_return() && script_hash(&output.script_pubkey) == hash
+            };
+            tx.output.iter().any(pays)
+                || tx.input.iter().any(|input| {
+                    let spent = input.previous_output;
+                    self.txs
+                        .iter()
+                        .find(|funding| funding.compute_txid() == spent.txid)
+                        .and_then(|funding| funding.output.get(spent.vout as usize))
+                        .is_some_and(pays)
+                })
+        }
+
+        /// Result, or error, of `method` called with `params`
+        fn answer(&self, method: &str, params: &Value) -> std::result::Result<Value, Value> {
+            // Confirmed entries by height, then the mempool by txid, as servers list them
+            let by_height = |mut entries: Vec<(i64, Value)>| {
+                entries.sort_by_key(|(height, entry)| {
+                    let txid = entry["tx_hash"].as_str().unwrap_or_default().to_string();
+                    (
+                        *height <= 0,
+                        *height,
+                        if *height <= 0 { txid } else { String::new() },
+                    )
+                });
+                Value::Array(entries.into_iter().map(|(_, entry)| entry).collect())
+            };
+            match method {
+                "server.version" => Ok(json!(["FakeElectrum 1.0", PROTOCOL_VERSION])),
+                "server.banner" => Ok(json!("Welcome to FakeElectrum")),
+                "blockchain.transaction.get" => self
+                    .txs
+                    .iter()
+                    .find(|tx| json!(tx.compute_txid()) == params[0])
+                    .map(|tx| json!(serialize_hex(tx)))
+                    .ok_or_else(|| {
+                        json!({"code": 2, "message": "daemon error: No such mempool or blockchain transaction"})
+                    }),
+                "blockchain.scripthash.get_history" => {
+                    let hash = params[0].as_str().unwrap();
+                    Ok(by_height(
+                        (0..self.txs.len())
+                            .filter(|&position| self.touches(position, hash))
+                            .map(|position| {
+                                let height = self.height(position);
+                                let txid = self.txs[position].compute_txid();
+                                (height, json!({"tx_hash": txid, "height": height}))
+                            })
+                            .collect(),
+                    ))
+                }
+                "blockchain.scripthash.listunspent" => {
+                    let hash = params[0].as_str().unwrap();
+                    let spent: HashSet<OutPoint> = self
+                        .txs
+                        .iter()
+                        .flat_map(|tx| tx.input.iter().map(|input| input.previous_output))
+                        .collect();
+                    // Listed newest first, as servers may
+                    let mut unspent = Vec::new();
+                    for (position, tx) in self.txs.iter().enumerate().rev() {
+                        for (vout, output) in tx.output.iter().enumerate() {
+                            let outpoint = OutPoint::new(tx.compute_txid(), vout as u32);
+                            if script_hash(&output.script_pubkey) == hash
+                                && !spent.contains(&outpoint)
+                            {
+                                let height = self.height(position);
+                                unspent.push((height, json!({
+                                    "tx_hash": outpoint.txid,
+                                    "tx_pos": vout,
+                                    "height": height,
+                                    "value": output.value.to_sat(),
+                                })));
+                            }
+                        }
+                    }
+                    Ok(Value::Array(unspent.into_iter().map(|(_, entry)| entry).collect()))
+                }
+                "blockchain.block.header" => {
+                    let height = params[0].as_u64().unwrap() as u32;
+                    Ok(json!(serialize_hex(&Self::header(height))))
+                }
+                _ => Err(json!({"code": -32601, "message": format!("unknown method {}", method)})),
+            }
+        }
+    }
+
+    /// Electrum server on localhost answering from an `Index`
+    struct FakeServer {
+        url: String,
+        connections: Arc<AtomicUsize>,
+        methods: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl FakeServer {
+        /// Serves `index`, dropping the first connection instead of answering its
+        /// request number `drop_at` (counted from 1), if given
+        async fn start(index: Index, drop_at: Option<usize>) -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let url = format!("tcp://{}", listener.local_addr().unwrap());
+            let connections = Arc::new(AtomicUsize::new(0));
+            let methods = Arc::new(Mutex::new(Vec::new()));
+            let server = Self {
+                url,
+                connections: connections.clone(),
+                methods: methods.clone(),
+            };
+            tokio::spawn(async move {
+                loop {
+                    let (stream, _) = listener.accept().await.unwrap();
+                    let number = connections.fetch_add(1, Ordering::SeqCst) + 1;
+                    let drop_at = drop_at.filter(|_| number == 1);
+                    let (index, methods) = (index.clone(), methods.clone());
+                    tokio::spawn(async move {
+                        let (reader, mut writer) = stream.into_split();
+                        let mut lines = BufReader::new(reader).lines();
+                        let mut requests = 0;
+                        while let Ok(Some(line)) = lines.next_line().await {
+                            requests += 1;
+                            if Some(requests) == drop_at {
+                                return;
+                            }
+                            let request: Value = serde_json::from_str(&line).unwrap();
+                            let method = request["method"].as_str().unwrap();
+                            methods.lock().unwrap().push(method.to_string());
+                            let mut answer = json!({"jsonrpc": "2.0", "id": request["id"]});
+                            match index.answer(method, &request["params"]) {
+                                Ok(result) => answer["result"] = result,
+                                Err(error) => answer["error"] = error,
+                            }
+                            let mut line = answer.to_string();
+                            line.push('\n');
+                            writer.write_all(line.as_bytes()).await.unwrap();
+                        }
+                    });
+                }
+            });
+            server
+        }
+
+        fn connections(&self) -> usize {
+            self.connections.load(Ordering::SeqCst)
+        }
+
+        fn methods(&self) -> Vec<String> {
+            self.methods.lock().unwrap().clone()
+        }
+    }
+
+    fn address_of(script: &Script) -> Address {
+        Address::from_script(script, Network::Bitcoin).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_spenders_are_found_in_the_history_of_their_outputs_script() {
+        let mut chain = ChainBuilder::new();
+        let funding = chain.fund(&[1_000_000]);
+        let root = OutPoint::new(funding.compute_txid(), 0);
+        let hops = chain.peel_chain(root, 3, 100_000, 1_000);
+        let server = FakeServer::start(Index::new(chain.transactions()), None).await;
+        let client = ElectrumClient::new(&server.url);
+
+        assert_eq!(
+            client
+                .get_transaction(hops[1].compute_txid())
+                .await
+                .unwrap(),
+            hops[1]
+        );
+        let spender = client.get_spending_transaction(root).await.unwrap();
+        assert_eq!(spender, Some(hops[0].clone()));
+        let last = OutPoint::new(hops[2].compute_txid(), 0);
+        assert_eq!(client.get_spending_transaction(last).await.unwrap(), None);
+
+        let missing = funding.input[0].previous_output.txid;
+        let err = client.get_transaction(missing).await.unwrap_err();
+        assert!(matches!(err.inner(), BlockchainError::NotFound(_)), "{err}");
+        let batch = client
+            .get_transactions_batch(&[hops[0].compute_txid(), missing])
+            .await
+            .unwrap();
+        assert_eq!(batch, [Some(hops[0].clone()), None]);
+
+        // One handshake on one connection, ahead of every request
+        let methods = server.methods();
+        assert_eq!(methods[0], "server.version");
+        assert_eq!(methods.iter().filter(|m| *m == "server.version").count(), 1);
+        assert_eq!(server.connections(), 1);
+        let version = client.server_version().await.unwrap();
+        assert_eq!(version.software, "FakeElectrum 1.0");
+        assert_eq!(version.protocol, PROTOCOL_VERSION);
+        assert_eq!(client.banner().await.unwrap(), "Welcome to FakeElectrum");
+    }
+
+    #[tokio::test]
+    async fn test_unconfirmed_spenders_listed_before_their_funding_are_found() {
+        // Unconfirmed entries are listed by txid: find a spender sorting first
+        let (chain, funding, spender) = (0..)
+            .map(|padding| {
+                let mut chain = ChainBuilder::new();
+                for _ in 0..padding {
+                    chain.fund(&[1_000]);
+                }
+                let funding = chain.fund(&[1_000_000]);
+                let spender = chain.spend(&[OutPoint::new(funding.compute_txid(), 0)], &[990_000]);
+                (chain, funding, spender)
+            })
+            .find(|(_, funding, spender)| {
+                spender.compute_txid().to_string() < funding.compute_txid().to_string()
+            })
+            .unwrap();
+        let index = Index::new(chain.transactions())
+            .unconfirmed(funding.compute_txid())
+            .unconfirmed(spender.compute_txid());
+        let server = FakeServer::start(index, None).await;
+        let client = ElectrumClient::new(&server.url);
+
+        let root = OutPoint::new(funding.compute_txid(), 0);
+        assert_eq!(
+            client.get_spending_transaction(root).await.unwrap(),
+            Some(spender)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_address_histories_and_utxos_are_listed_by_script_hash() {
+        let mut chain = ChainBuilder::new();
+        let funding = chain.fund(&[500_000, 200_000]);
+        let txid = funding.compute_txid();
+        let spender = chain.spend(&[OutPoint::new(txid, 1)], &[150_000, 49_000]);
+        let pending = chain.spend(&[OutPoint::new(txid, 0)], &[499_000]);
+        let index = Index::new(chain.transactions()).unconfirmed(pending.compute_txid());
+        let server = FakeServer::start(index, None).await;
+        let client = ElectrumClient::new(&server.url);
+
+        // Paid by the funding, spent by the others: the unconfirmed one first
+        let address = address_of(&funding.output[0].script_pubkey);
+        let history = client.get_address_transactions(address).await.unwrap();
+        assert_eq!(history, [pending.clone(), funding.clone()]);
+
+        let change = address_of(&spender.output[1].script_pubkey);
+        let utxos = client.get_address_utxos(change).await.unwrap();
+        assert_eq!(utxos, [OutPoint::new(spender.compute_txid(), 1)]);
+        let spent = address_of(&funding.output[1].script_pubkey);
+        assert!(client.get_address_utxos(spent).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_status_is_read_from_the_history_and_the_block_header() {
+        let mut chain = ChainBuilder::new();
+        let funding = chain.fund(&[100_000]);
+        let pending = chain.spend(&[OutPoint::new(funding.compute_txid(), 0)], &[99_000]);
+        let index = Index::new(chain.transactions()).unconfirmed(pending.compute_txid());
+        let server = FakeServer::start(index, None).await;
+        let client = ElectrumClient::new(&server.url);
+
+        let status = client
+            .get_transaction_status(funding.compute_txid())
+            .await
+            .unwrap();
+        let header = Index::header(FIRST_HEIGHT as u32);
+        assert_eq!(
+            status,
+            TxStatus {
+                confirmed: true,
+                block_height: Some(FIRST_HEIGHT as u32),
+                block_hash: Some(header.block_hash()),
+                block_time: Some(u64::from(header.time)),
+            }
+        );
+        let status = client
+            .get_transaction_status(pending.compute_txid())
+            .await
+            .unwrap();
+        assert_eq!(status, TxStatus::unconfirmed());
+    }
+
+    #[tokio::test]
+    async fn test_status_is_read_from_the_history_of_a_spendable_output() {
+        let mut chain = ChainBuilder::new();
+        let mut tagged = chain.fund(&[0, 50_000]);
+        tagged.output[0].script_pubkey = ScriptBuf::new_op_return(b"memo");
+        let server = FakeServer::start(Index::new(&[tagged.clone()]), None).await;
+        let client = ElectrumClient::new(&server.url);
+
+        let status = client
+            .get_transaction_status(tagged.compute_txid())
+            .await
+            .unwrap();
+        assert_eq!(status.block_height, Some(FIRST_HEIGHT as u32));
+        // Only the history of the spendable output is asked for
+        let histories = server.methods();
+        let histories = histories
+            .iter()
+            .filter(|m| *m == "blockchain.scripthash.get_history");
+        assert_eq!(histories.count(), 1);
+
+        let mut burnt = chain.fund(&[0]);
+        burnt.output[0].script_pubkey = ScriptBuf::new_op_return(b"memo");
+        let server = FakeServer::start(Index::new(&[burnt.clone()]), None).await;
+        let client = ElectrumClient::new(&server.url);
+        let err = client
+            .get_transaction_status(burnt.compute_txid())
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(err.inner(), BlockchainError::DataInconsistency(_)),
+            "{err}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_answers_are_matched_to_requests_by_id() {
+        let mut chain = ChainBuilder::new();
+        let a = chain.fund(&[10_000]);
+        let b = chain.fund(&[20_000]);
+        let index = Index::new(chain.transactions());
+        // Answers the handshake, then two requests in reverse, a notification first
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = stream.into_split();
+            let mut lines = BufReader::new(reader).lines();
+            let answer = |request: &Value| {
+                let method = request["method"].as_str().unwrap();
+                let result = index.answer(method, &request["params"]).unwrap();
+                json!({"jsonrpc": "2.0", "id": request["id"], "result": result}).to_string() + "\n"
+            };
+            let mut requests = Vec::new();
+            while let Ok(Some(line)) = lines.next_line().await {
+                requests.push(serde_json::from_str::<Value>(&line).unwrap());
+                let out = match requests.len() {
+                    1 => answer(&requests[0]),
+                    3 => {
+                        let notification = json!({
+                            "jsonrpc": "2.0",
+                            "method": "blockchain.headers.subscribe",
+                            "params": [],
+                        });
+                        notification.to_string()
+                            + "\n"
+                            + &answer(&requests[2])
+                            + &answer(&requests[1])
+                    }
+                    _ => continue,
+                };
+                writer.write_all(out.as_bytes()).await.unwrap();
+            }
+        });
+
+        let client = ElectrumClient::new(url);
+        let (got_a, got_b) = tokio::join!(
+            client.get_transaction(a.compute_txid()),
+            client.get_transaction(b.compute_txid()),
+        );
+        assert_eq!(got_a.unwrap(), a);
+        assert_eq!(got_b.unwrap(), b);
+    }
+
+    #[tokio::test]
+    async fn test_dropped_connections_are_opened_again() {
+        let mut chain = ChainBuilder::new();
+        let funding = chain.fund(&[10_000]);
+        let txid = funding.compute_txid();
+        // The first connection is dropped on its first request past the handshake
+        let server = FakeServer::start(Index::new(chain.transactions()), Some(2)).await;
+        let client = ElectrumClient::new(&server.url);
+
+        let err = client.get_transaction(txid).await.unwrap_err();
+        assert!(
+            matches!(err.inner(), BlockchainError::NetworkFailure(_)),
+            "{err}"
+        );
+        assert!(err.is_retryable());
+        assert_eq!(client.get_transaction(txid).await.unwrap(), funding);
+        assert_eq!(server.connections(), 2);
+
+        // Retried, the lost request is answered on a connection of its own
+        let server = FakeServer::start(Index::new(chain.transactions()), Some(2)).await;
+        let client = ElectrumClient::new(&server.url)
+            .retry(RetryPolicy::new(2).base_delay(Duration::from_millis(1)));
+        assert_eq!(client.get_transaction(txid).await.unwrap(), funding);
+        assert_eq!(server.connections(), 2);
+    }
+
+    #[test]
+    fn test_server_urls_are_parsed_with_default_ports() {
+        let endpoint = |url| Endpoint::parse(url).unwrap().to_string();
+        assert_eq!(
+            endpoint("electrum.example:50001"),
+            "tcp://electrum.example:50001"
+        );
+        assert_eq!(
+            endpoint("ssl://electrum.example"),
+            "ssl://electrum.example:50002"
+        );
+        assert_eq!(endpoint("tcp://[::1]:60001/"), "tcp://[::1]:60001");
+        assert_eq!(endpoint("tcp://[::1]"), "tcp://[::1]:50001");
+        assert!(Endpoint::parse("http://electrum.example").is_err());
+        assert!(Endpoint::parse("tcp://electrum.example:port").is_err());
+        // The example of the protocol's documentation
+        let script =
+            bitcoin::ScriptBuf::from_hex("76a91462e907b15cbf27d5425399ebf6f0fb50ebb88f1888ac")
+                .unwrap();
+        assert_eq!(
+            script_hash(&script),
+            "8b01df4e368ea28f8dc0423bcf7a4923e3a12d307c875e47a0cfbf90b5c39161"
+        );
+    }
+}
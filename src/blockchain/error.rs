@@ -1,19 +1,703 @@
+use bitcoin::{OutPoint, Txid};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::error::Error as StdError;
+use std::fmt;
+use std::hash::{BuildHasher, RandomState};
+use std::sync::Arc;
+use std::time::Duration;
 use thiserror::Error;
 
-#[derive(Error, Debug)]
+#[derive(Error, Debug, Clone)]
 pub enum BlockchainError {
-    #[error("NetworkFailure, Check internet connection")]
+    #[error("Network failure: {0}")]
     NetworkFailure(String),
-    #[error("No such UTXO, please check your entry")]
+    /// Request to `url` got no answer: the connection failed, timed out, or the
+    /// body could not be read. `source` is the HTTP client's error
+    #[error("Request to {url} failed: {source}")]
+    Request {
+        url: String,
+        #[source]
+        source: Arc<dyn StdError + Send + Sync>,
+    },
+    #[error("Not found: {0}")]
     NotFound(String),
-    #[error("Invalid Input please check your entry")]
+    #[error("Invalid input: {0}")]
     InvalidInput(String),
-    #[error("Rate Limited")]
-    RateLimited,
-    #[error("Data is inconsistent")]
+    /// The backend turned the request down for now, asking to wait `retry_after`
+    /// when it says how long
+    #[error("Rate limited by the data source{}", retry_after_hint(retry_after))]
+    RateLimited { retry_after: Option<Duration> },
+    #[error("Inconsistent data: {0}")]
     DataInconsistency(String),
+    /// The answer for `what` (a transaction, a block, a response) did not decode:
+    /// `source` is the JSON, hex or consensus decoding error
+    #[error("Could not decode {what}: {source}")]
+    Decode {
+        what: String,
+        #[source]
+        source: Arc<dyn StdError + Send + Sync>,
+    },
+    /// `backend` cannot do `operation` at all, or not as it is set up: `hint` says
+    /// what to use or change instead, for the user to read as is
+    #[error("{backend} does not support {operation}{}", hint_suffix(hint))]
+    UnsupportedOperation {
+        backend: &'static str,
+        operation: &'static str,
+        hint: String,
+    },
+    /// A request, to `url` when known, got no full answer within `elapsed`
+    #[error("Timed out after {}s{}", elapsed.as_secs_f64(), url_suffix(url))]
+    Timeout {
+        elapsed: Duration,
+        url: Option<String>,
+    },
     #[error("{0}")]
     Other(String),
+    /// `source`, with what it failed doing (see `ResultExt::with_ctx`)
+    #[error("{context}: {source}")]
+    Context {
+        context: Box<ErrorContext>,
+        #[source]
+        source: Box<BlockchainError>,
+    },
+}
+
+impl BlockchainError {
+    /// `Request` error for `url`
+    #[cfg(feature = "http")]
+    pub fn request(url: impl Into<String>, source: reqwest::Error) -> Self {
+        BlockchainError::Request {
+            url: url.into(),
+            source: Arc::new(source),
+        }
+    }
+
+    /// `Request` error for `url`, or `Timeout` if `source` timed out `elapsed` after
+    /// the request was sent
+    #[cfg(feature = "http")]
+    pub fn request_after(
+        url: impl Into<String>,
+        source: reqwest::Error,
+        elapsed: Duration,
+    ) -> Self {
+        match source.is_timeout() {
+            true => BlockchainError::Timeout {
+                elapsed,
+                url: Some(url.into()),
+            },
+            false => Self::request(url, source),
+        }
+    }
+
+    /// `UnsupportedOperation` error, `hint` saying what to do instead
+    pub fn unsupported(
+        backend: &'static str,
+        operation: &'static str,
+        hint: impl Into<String>,
+    ) -> Self {
+        BlockchainError::UnsupportedOperation {
+            backend,
+            operation,
+            hint: hint.into(),
+        }
+    }
+
+    /// `Decode` error for `what`
+    pub fn decode(what: impl Into<String>, source: impl StdError + Send + Sync + 'static) -> Self {
+        BlockchainError::Decode {
+            what: what.into(),
+            source: Arc::new(source),
+        }
+    }
+
+    /// This error with `context`, unless it has one already: the innermost context,
+    /// closest to the failure, is kept
+    pub fn with_context(self, context: ErrorContext) -> Self {
+        match self {
+            BlockchainError::Context { .. } => self,
+            error => BlockchainError::Context {
+                context: Box::new(context),
+                source: Box::new(error),
+            },
+        }
+    }
+
+    /// What the operation that failed was doing, if it said
+    pub fn context(&self) -> Option<&ErrorContext> {
+        match self {
+            BlockchainError::Context { context, .. } => Some(context),
+            _ => None,
+        }
+    }
+
+    /// The error without its context: the one to match variants on
+    pub fn inner(&self) -> &BlockchainError {
+        match self {
+            BlockchainError::Context { source, .. } => source.inner(),
+            error => error,
+        }
+    }
+
+    /// What to do about an unsupported operation, meant to be shown as is
+    pub fn hint(&self) -> Option<&str> {
+        match self.inner() {
+            BlockchainError::UnsupportedOperation { hint, .. } if !hint.is_empty() => Some(hint),
+            _ => None,
+        }
+    }
+
+    /// Whether the operation may succeed if tried again: a network failure, a
+    /// request that got no answer or timed out, or a rate limit. A missing item, a
+    /// bad input, an unsupported operation or an answer that did not decode fails
+    /// the same way each time.
+    pub fn is_retryable(&self) -> bool {
+        match self.inner() {
+            BlockchainError::NetworkFailure(_)
+            | BlockchainError::RateLimited { .. }
+            | BlockchainError::Timeout { .. } => true,
+            BlockchainError::Request { .. } => {
+                !self.http_error_is(HttpFailure::Decode)
+                    && !self.http_error_is(HttpFailure::Builder)
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether a request timed out
+    pub fn is_timeout(&self) -> bool {
+        matches!(self.inner(), BlockchainError::Timeout { .. })
+            || self.http_error_is(HttpFailure::Timeout)
+    }
+
+    /// Whether a request failed to connect to the backend: never on wasm, where the
+    /// browser's fetch does not tell such failures apart
+    pub fn is_connect(&self) -> bool {
+        self.http_error_is(HttpFailure::Connect)
+    }
+
+    /// Whether an answer did not decode
+    pub fn is_decode(&self) -> bool {
+        matches!(self.inner(), BlockchainError::Decode { .. })
+            || self.http_error_is(HttpFailure::Decode)
+    }
+
+    /// Whether the HTTP client error behind this one failed as `failure`: reading a
+    /// JSON body fails with one even when the connection, not the JSON, is to blame
+    #[cfg(feature = "http")]
+    fn http_error_is(&self, failure: HttpFailure) -> bool {
+        let source = match self.inner() {
+            BlockchainError::Request { source, .. } | BlockchainError::Decode { source, .. } => {
+                source.downcast_ref::<reqwest::Error>()
+            }
+            _ => None,
+        };
+        source.is_some_and(|error| match failure {
+            HttpFailure::Timeout => error.is_timeout(),
+            #[cfg(not(target_arch = "wasm32"))]
+            HttpFailure::Connect => error.is_connect(),
+            #[cfg(target_arch = "wasm32")]
+            HttpFailure::Connect => false,
+            HttpFailure::Decode => error.is_decode(),
+            HttpFailure::Builder => error.is_builder(),
+        })
+    }
+
+    /// Built without an HTTP client, no error has one behind it
+    #[cfg(not(feature = "http"))]
+    fn http_error_is(&self, _failure: HttpFailure) -> bool {
+        false
+    }
+}
+
+/// How an HTTP client's request failed, as `BlockchainError::http_error_is` checks
+#[derive(Debug, Clone, Copy)]
+enum HttpFailure {
+    Timeout,
+    Connect,
+    Decode,
+    Builder,
+}
+
+/// What a failed operation was doing: its name and the transaction, output and
+/// backend it involved.
+///
+/// Displays compactly, as `get_spending_transaction 15e10745..8521:3 via
+/// https://mempool.space`; the alternate flag (`{:#}`) prints the txid in full.
+///
+/// # Fields
+/// * `operation` - Name of the operation, as that of the data source method
+/// * `txid` - Transaction looked up, if any
+/// * `outpoint` - Output looked up, if any
+/// * `url` - Backend the request went to, if any
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ErrorContext {
+    pub operation: Cow<'static, str>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub txid: Option<Txid>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub outpoint: Option<OutPoint>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+}
+
+impl ErrorContext {
+    pub fn new(operation: &'static str) -> Self {
+        Self {
+            operation: Cow::Borrowed(operation),
+            txid: None,
+            outpoint: None,
+            url: None,
+        }
+    }
+
+    pub fn txid(mut self, txid: Txid) -> Self {
+        self.txid = Some(txid);
+        self
+    }
+
+    pub fn outpoint(mut self, outpoint: OutPoint) -> Self {
+        self.outpoint = Some(outpoint);
+        self
+    }
+
+    pub fn url(mut self, url: impl Into<String>) -> Self {
+        self.url = Some(url.into());
+        self
+    }
+}
+
+impl fmt::Display for ErrorContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let full = f.alternate();
+        let short = |txid: &Txid| {
+            let hex = txid.to_string();
+            match full {
+                true => hex,
+                false => format!("{}..{}", &hex[..8], &hex[hex.len() - 4..]),
+            }
+        };
+        write!(f, "{}", self.operation)?;
+        if let Some(txid) = &self.txid {
+            write!(f, " {}", short(txid))?;
+        }
+        if let Some(outpoint) = &self.outpoint {
+            write!(f, " {}:{}", short(&outpoint.txid), outpoint.vout)?;
+        }
+        if let Some(url) = &self.url {
+            write!(f, " via {}", url)?;
+        }
+        Ok(())
+    }
+}
+
+/// Attaching an `ErrorContext` to the error of a `Result`.
+pub trait ResultExt<T> {
+    /// The result, its error given the context `context` builds unless it has one
+    /// already (see `BlockchainError::with_context`)
+    fn with_ctx<F>(self, context: F) -> Result<T>
+    where
+        F: FnOnce() -> ErrorContext;
+}
+
+impl<T> ResultExt<T> for Result<T> {
+    fn with_ctx<F>(self, context: F) -> Result<T>
+    where
+        F: FnOnce() -> ErrorContext,
+    {
+        self.map_err(|error| error.with_context(context()))
+    }
+}
+
+fn retry_after_hint(retry_after: &Option<Duration>) -> String {
+    match retry_after {
+        Some(wait) => format!(", retry after {}s", wait.as_secs_f64()),
+        None => String::new(),
+    }
+}
+
+fn hint_suffix(hint: &str) -> String {
+    match hint {
+        "" => String::new(),
+        hint => format!(": {}", hint),
+    }
+}
+
+fn url_suffix(url: &Option<String>) -> String {
+    match url {
+        Some(url) => format!(" waiting for {}", url),
+        None => String::new(),
+    }
 }
 
 pub type Result<T> = std::result::Result<T, BlockchainError>;
+
+/// Retries of an operation failing with a retryable error (see
+/// `BlockchainError::is_retryable`), shared by the backends.
+///
+/// # Fields
+/// * `max_attempts` - times the operation is tried in all (1 by default: it is never
+///   retried)
+/// * `base_delay` - wait before the first retry, doubled before each further one
+/// * `max_delay` - longest wait between two attempts, a rate limit's `retry_after`
+///   aside
+/// * `jitter` - up to this much is added at random to each wait, so that clients
+///   failing together do not all retry together
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    pub jitter: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 1,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            jitter: Duration::from_millis(100),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Tries each operation up to `max_attempts` times
+    pub fn new(max_attempts: u32) -> Self {
+        Self {
+            max_attempts,
+            ..Self::default()
+        }
+    }
+
+    pub fn base_delay(mut self, delay: Duration) -> Self {
+        self.base_delay = delay;
+        self
+    }
+
+    pub fn max_delay(mut self, delay: Duration) -> Self {
+        self.max_delay = delay;
+        self
+    }
+
+    pub fn jitter(mut self, jitter: Duration) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Wait before retry `retry` (0 for the first), its jitter drawn at random
+    pub fn delay(&self, retry: u32) -> Duration {
+        let backoff = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(self.max_delay);
+        let jitter_nanos = self.jitter.as_nanos() as u64;
+        let jitter = match jitter_nanos {
+            0 => Duration::ZERO,
+            nanos => Duration::from_nanos(RandomState::new().hash_one(retry) % (nanos + 1)),
+        };
+        backoff + jitter
+    }
+}
+
+/// Result of `op`, called again after a retryable error as `policy` allows.
+///
+/// A rate limit asking to wait `retry_after` is waited out for at least that long.
+///
+/// # Errors
+/// The first error that is not retryable, else the last one once `max_attempts`
+/// calls failed
+#[cfg(feature = "runtime")]
+pub async fn execute<T, F, Fut>(policy: &RetryPolicy, op: F) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    execute_with_clock(policy, &crate::time::SystemClock, op).await
+}
+
+/// `execute`, waiting between attempts on `clock`
+///
+/// # Errors
+/// Those of `execute`
+#[cfg(feature = "runtime")]
+pub async fn execute_with_clock<T, F, Fut>(
+    policy: &RetryPolicy,
+    clock: &dyn crate::time::Clock,
+    mut op: F,
+) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let mut attempt = 1;
+    loop {
+        let error = match op().await {
+            Ok(value) => return Ok(value),
+            Err(error) => error,
+        };
+        if attempt >= policy.max_attempts || !error.is_retryable() {
+            return Err(error);
+        }
+        let mut wait = policy.delay(attempt - 1);
+        if let BlockchainError::RateLimited {
+            retry_after: Some(retry_after),
+        } = error.inner()
+        {
+            wait = wait.max(*retry_after);
+        }
+        clock.sleep(wait).await;
+        attempt += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "runtime")]
+    use crate::testing::MockClock;
+    #[cfg(feature = "runtime")]
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    #[test]
+    fn test_messages_include_the_detail() {
+        let txid = "15e10745f15593a899cef391191bdd3d7c12412cc4696b7bcb669d0feadc8521";
+        let cases = [
+            (
+                BlockchainError::NetworkFailure(format!(
+                    "HTTP 502 for /tx/{}/hex: bad gateway",
+                    txid
+                )),
+                format!(
+                    "Network failure: HTTP 502 for /tx/{}/hex: bad gateway",
+                    txid
+                ),
+            ),
+            (
+                BlockchainError::NotFound(format!("Transaction {} not found", txid)),
+                format!("Not found: Transaction {} not found", txid),
+            ),
+            (
+                BlockchainError::InvalidInput("Invalid parameter".to_string()),
+                "Invalid input: Invalid parameter".to_string(),
+            ),
+            (
+                BlockchainError::RateLimited { retry_after: None },
+                "Rate limited by the data source".to_string(),
+            ),
+            (
+                BlockchainError::RateLimited {
+                    retry_after: Some(Duration::from_secs(30)),
+                },
+                "Rate limited by the data source, retry after 30s".to_string(),
+            ),
+            (
+                BlockchainError::DataInconsistency("1 results for 2 keys".to_string()),
+                "Inconsistent data: 1 results for 2 keys".to_string(),
+            ),
+            (
+                BlockchainError::decode(
+                    format!("transaction {}", txid),
+                    "x".parse::<u32>().unwrap_err(),
+                ),
+                format!(
+                    "Could not decode transaction {}: invalid digit found in string",
+                    txid
+                ),
+            ),
+            (
+                BlockchainError::unsupported(
+                    "bitcoind",
+                    "getrawtransaction",
+                    "start bitcoind with -txindex=1",
+                ),
+                "bitcoind does not support getrawtransaction: start bitcoind with -txindex=1"
+                    .to_string(),
+            ),
+            (
+                BlockchainError::unsupported("MockDataSource", "get_block_raw", ""),
+                "MockDataSource does not support get_block_raw".to_string(),
+            ),
+            (
+                BlockchainError::Timeout {
+                    elapsed: Duration::from_millis(2500),
+                    url: Some(format!("https://mempool.space/api/tx/{}/hex", txid)),
+                },
+                format!(
+                    "Timed out after 2.5s waiting for https://mempool.space/api/tx/{}/hex",
+                    txid
+                ),
+            ),
+            (
+                BlockchainError::Timeout {
+                    elapsed: Duration::from_secs(30),
+                    url: None,
+                },
+                "Timed out after 30s".to_string(),
+            ),
+            (
+                BlockchainError::Other("RPC error -1: boom".to_string()),
+                "RPC error -1: boom".to_string(),
+            ),
+        ];
+        for (error, message) in cases {
+            assert_eq!(error.to_string(), message);
+        }
+    }
+
+    #[test]
+    fn test_context_names_the_failed_lookup() {
+        let txid: Txid = "15e10745f15593a899cef391191bdd3d7c12412cc4696b7bcb669d0feadc8521"
+            .parse()
+            .unwrap();
+        let outpoint = OutPoint::new(txid, 3);
+        let failing: Result<()> = Err(BlockchainError::NetworkFailure("HTTP 502".to_string()));
+
+        let error = failing
+            .with_ctx(|| {
+                ErrorContext::new("get_spending_transaction")
+                    .outpoint(outpoint)
+                    .url("https://mempool.space")
+            })
+            .unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "get_spending_transaction 15e10745..8521:3 via https://mempool.space: \
+             Network failure: HTTP 502"
+        );
+        assert_eq!(
+            format!("{:#}", error.context().unwrap()),
+            format!(
+                "get_spending_transaction {}:3 via https://mempool.space",
+                txid
+            )
+        );
+        assert!(matches!(error.inner(), BlockchainError::NetworkFailure(_)));
+        assert!(error.is_retryable());
+
+        // The innermost context, closest to the failure, is kept
+        let error = error.with_context(ErrorContext::new("trace_forward"));
+        assert_eq!(
+            error.context().unwrap().operation,
+            "get_spending_transaction"
+        );
+    }
+
+    /// Calls of an operation failing with each of `errors` in turn, then succeeding
+    #[cfg(feature = "runtime")]
+    async fn attempts(policy: RetryPolicy, errors: Vec<BlockchainError>) -> (Result<u32>, u32) {
+        let calls = AtomicU32::new(0);
+        let result = execute(&policy, || async {
+            let call = calls.fetch_add(1, Ordering::SeqCst);
+            match errors.get(call as usize) {
+                Some(error) => Err(error.clone()),
+                None => Ok(call),
+            }
+        })
+        .await;
+        (result, calls.load(Ordering::SeqCst))
+    }
+
+    #[cfg(feature = "runtime")]
+    #[tokio::test(start_paused = true)]
+    async fn test_execute_retries_transient_errors_only() {
+        let policy = RetryPolicy::new(3).base_delay(Duration::from_millis(100));
+        let offline = || BlockchainError::NetworkFailure("offline".to_string());
+
+        let (result, calls) = attempts(policy, vec![offline(), offline()]).await;
+        assert_eq!((result.unwrap(), calls), (2, 3));
+        // The third failure is the last attempt
+        let (result, calls) = attempts(policy, vec![offline(), offline(), offline()]).await;
+        assert!(matches!(result, Err(BlockchainError::NetworkFailure(_))));
+        assert_eq!(calls, 3);
+
+        for error in [
+            BlockchainError::NotFound("tx".to_string()),
+            BlockchainError::InvalidInput("txid".to_string()),
+            BlockchainError::DataInconsistency("2 for 1".to_string()),
+            BlockchainError::unsupported("bitcoind", "get_spending_transaction", ""),
+        ] {
+            assert!(!error.is_retryable());
+            let (result, calls) = attempts(policy, vec![error]).await;
+            assert!(result.is_err());
+            assert_eq!(calls, 1);
+        }
+
+        let timeout = BlockchainError::Timeout {
+            elapsed: Duration::from_secs(5),
+            url: None,
+        };
+        assert!(timeout.is_timeout());
+        let (result, calls) = attempts(policy, vec![timeout]).await;
+        assert_eq!((result.unwrap(), calls), (1, 2));
+
+        // Never retried by default
+        let (result, calls) = attempts(RetryPolicy::default(), vec![offline()]).await;
+        assert!(result.is_err());
+        assert_eq!(calls, 1);
+    }
+
+    #[cfg(feature = "runtime")]
+    #[tokio::test(start_paused = true)]
+    async fn test_execute_waits_out_rate_limits() {
+        let policy = RetryPolicy::new(2)
+            .base_delay(Duration::from_millis(100))
+            .jitter(Duration::ZERO);
+        let start = tokio::time::Instant::now();
+        let limited = BlockchainError::RateLimited {
+            retry_after: Some(Duration::from_secs(10)),
+        };
+        let (result, calls) = attempts(policy, vec![limited]).await;
+        assert_eq!((result.unwrap(), calls), (1, 2));
+        assert_eq!(start.elapsed(), Duration::from_secs(10));
+
+        let start = tokio::time::Instant::now();
+        let limited = BlockchainError::RateLimited { retry_after: None };
+        let (result, _) = attempts(policy, vec![limited]).await;
+        assert!(result.is_ok());
+        assert_eq!(start.elapsed(), Duration::from_millis(100));
+    }
+
+    #[cfg(feature = "runtime")]
+    #[tokio::test]
+    async fn test_retries_wait_on_the_given_clock() {
+        let clock = MockClock::new();
+        let policy = RetryPolicy::new(4)
+            .base_delay(Duration::from_secs(1))
+            .jitter(Duration::ZERO);
+        let calls = AtomicU32::new(0);
+        let result = execute_with_clock(&policy, &clock, || async {
+            match calls.fetch_add(1, Ordering::SeqCst) {
+                0 | 1 => Err(BlockchainError::NetworkFailure("offline".to_string())),
+                2 => Err(BlockchainError::RateLimited {
+                    retry_after: Some(Duration::from_secs(30)),
+                }),
+                call => Ok(call),
+            }
+        })
+        .await;
+
+        // Backoffs of 1s and 2s, then the 30s the rate limit asked for
+        assert_eq!(result.unwrap(), 3);
+        assert_eq!(clock.elapsed(), Duration::from_secs(33));
+    }
+
+    #[test]
+    fn test_delays_double_up_to_the_max() {
+        let policy = RetryPolicy::new(5)
+            .base_delay(Duration::from_secs(1))
+            .max_delay(Duration::from_secs(5))
+            .jitter(Duration::ZERO);
+        let delays: Vec<_> = (0..4).map(|retry| policy.delay(retry).as_secs()).collect();
+        assert_eq!(delays, [1, 2, 4, 5]);
+
+        let jittered = policy.jitter(Duration::from_millis(100));
+        for retry in 0..4 {
+            let delay = jittered.delay(retry);
+            assert!(delay >= policy.delay(retry));
+            assert!(delay <= policy.delay(retry) + Duration::from_millis(100));
+        }
+    }
+}
@@ -1,7 +1,16 @@
-use crate::blockchain::{BlockchainDataSource, BlockchainError, Result};
+use crate::blockchain::{
+    BlockchainDataSource, BlockchainError, ErrorContext, Result, ResultExt, RetryPolicy, TipCache,
+    TxMetadata, TxStatus, batch, execute, redact,
+};
+use crate::time::Instant;
 use async_trait::async_trait;
-use bitcoin::{Address, OutPoint, Transaction, Txid};
+use bitcoin::{Address, Amount, Block, BlockHash, OutPoint, Transaction, Txid, block::Header};
 use serde::Deserialize;
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{Instrument, Span, field};
 
 /// Esplora HTTP client used to retrieve blockchain data.
 ///
@@ -9,13 +18,21 @@ use serde::Deserialize;
 /// mempool.space) to fetch transaction data and spend information.
 ///
 /// # Rate Limiting
-/// Includes a helper to avoid overwhelming public API (100ms delay).
-/// This is important since UTXO tracing can result in hundreds of sequential requests.
+/// The client does not pace its requests: wrap it in a `RateLimitedDataSource` to
+/// avoid overwhelming a public API, since UTXO tracing can result in hundreds of
+/// requests. Answers of HTTP 429 fail with `RateLimited`, retried as the `retry`
+/// policy allows.
 ///
 /// Ideally you should run your own esplora instance.
+///
+/// Clones are cheap and share the connection pool and the cached tip height.
+#[derive(Clone)]
 pub struct EsploraClient {
-    base_url: String,
+    base_url: Arc<str>,
     client: reqwest::Client,
+    timeout: Option<Duration>,
+    tip: Arc<TipCache>,
+    retry: RetryPolicy,
 }
 
 impl EsploraClient {
@@ -27,18 +44,209 @@ impl EsploraClient {
     /// Automatically trims trailing slashes to ensure proper URL construction.
     pub fn new(base_url: impl Into<String>) -> Self {
         Self {
-            base_url: base_url.into().trim_end_matches('/').to_string(),
+            base_url: base_url.into().trim_end_matches('/').into(),
             client: reqwest::Client::new(),
+            timeout: None,
+            tip: Arc::default(),
+            retry: RetryPolicy::default(),
         }
     }
 
-    /// Helper that applies a small delay to prevent rate limiting
+    /// Gives up on requests that get no full answer within `timeout`, failing with
+    /// an error `is_timeout` holds for (none unless set)
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Retries of requests failing with a retryable error (none unless set)
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// How long the tip height confirmations are counted against is reused
+    /// (`DEFAULT_TIP_MAX_AGE` unless set)
+    pub fn tip_max_age(mut self, max_age: Duration) -> Self {
+        self.tip = Arc::new(TipCache::new(max_age));
+        self
+    }
+
+    /// Height of the chain tip, as cached for the tip max age.
     ///
-    /// 100ms which limits us to 10 req/sec, ideally preventing rate limits
+    /// Uses the `/blocks/tip/height` endpoint, which returns the height as text.
     ///
-    /// NOTE:For testing only - Should run own esplora indexer for better reliably
-    async fn throttle(&self) {
-        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    /// # Errors
+    /// - `Request` - HTTP request failed
+    /// - `RateLimited` - HTTP 429
+    /// - `NetworkFailure` - HTTP error status
+    /// - `Decode` - Body is not a height
+    pub async fn tip_height(&self) -> Result<u32> {
+        self.tip
+            .height(|| async {
+                let url = format!("{}/blocks/tip/height", self.base_url);
+
+                let Some(response) = self.get(&url).await? else {
+                    return Err(BlockchainError::NotFound(format!(
+                        "No tip height at {}",
+                        url
+                    )));
+                };
+
+                let text = response
+                    .text()
+                    .await
+                    .map_err(|e| BlockchainError::request(&url, e))?;
+
+                text.trim()
+                    .parse()
+                    .map_err(|e| BlockchainError::decode(format!("tip height {:?}", text), e))
+            })
+            .await
+            .with_ctx(|| self.context("tip_height"))
+    }
+
+    /// The outspends of every output of a transaction, in output order, from the
+    /// `/tx/{txid}/outspends` endpoint
+    ///
+    /// # Errors
+    /// As `get`, also `NotFound` for an unknown transaction and `Decode` for a body
+    /// that is not a JSON array of outspends
+    async fn outspends(&self, txid: Txid) -> Result<Vec<OutspendResponse>> {
+        let url = format!("{}/tx/{}/outspends", self.base_url, txid);
+        let Some(response) = self.get(&url).await? else {
+            return Err(BlockchainError::NotFound(format!(
+                "Transaction {} not found",
+                txid
+            )));
+        };
+        response
+            .json()
+            .await
+            .map_err(|e| BlockchainError::decode(format!("outspends of {}", txid), e))
+    }
+
+    /// The transaction an outspend names, `None` if the output is unspent
+    ///
+    /// # Errors
+    /// As `get_transaction`, also `DataInconsistency` for an output marked spent
+    /// without a spender
+    async fn spender(&self, outspend: &OutspendResponse) -> Result<Option<Transaction>> {
+        if !outspend.spent {
+            return Ok(None);
+        }
+        match outspend.txid {
+            Some(txid) => self.get_transaction(txid).await.map(Some),
+            None => Err(BlockchainError::DataInconsistency(
+                "Outspend marked spent but no txid returned".to_string(),
+            )),
+        }
+    }
+
+    /// The spenders of distinct `outpoints`, in order, asking `/tx/{txid}/outspends`
+    /// once per funding transaction
+    async fn spenders(&self, outpoints: Vec<OutPoint>) -> Result<Vec<Option<Transaction>>> {
+        let mut outspends: HashMap<Txid, Vec<OutspendResponse>> = HashMap::new();
+        let mut spenders = Vec::with_capacity(outpoints.len());
+        for outpoint in outpoints {
+            let spender = async {
+                let outputs = match outspends.entry(outpoint.txid) {
+                    Entry::Occupied(entry) => entry.into_mut(),
+                    Entry::Vacant(entry) => entry.insert(self.outspends(outpoint.txid).await?),
+                };
+                let outspend = outputs.get(outpoint.vout as usize).ok_or_else(|| {
+                    BlockchainError::NotFound(format!("Output {} not found", outpoint))
+                })?;
+                self.spender(outspend).await
+            }
+            .await
+            .with_ctx(|| {
+                self.context("get_spending_transactions_batch")
+                    .outpoint(outpoint)
+            })?;
+            spenders.push(spender);
+        }
+        Ok(spenders)
+    }
+
+    /// Context of the errors of `operation`, naming this backend
+    fn context(&self, operation: &'static str) -> ErrorContext {
+        ErrorContext::new(operation).url(&*self.base_url)
+    }
+
+    /// GET `url`, tried again as the retry policy allows.
+    ///
+    /// # Returns
+    /// - `Ok(Some(response))` - The response, its status a success
+    /// - `Ok(None)` - Nothing at `url` (404)
+    ///
+    /// # Errors
+    /// - `Request` - HTTP request failed
+    /// - `Timeout` - No answer within the timeout
+    /// - `RateLimited` - HTTP 429, with the wait `Retry-After` asks for
+    /// - `NetworkFailure` - Any other HTTP error status
+    async fn get(&self, url: &str) -> Result<Option<reqwest::Response>> {
+        execute(&self.retry, || {
+            let span = tracing::debug_span!(
+                "http_request",
+                method = "GET",
+                url = %redact::url(url),
+                status = field::Empty,
+                elapsed_ms = field::Empty,
+            );
+            self.attempt(url).instrument(span)
+        })
+        .await
+    }
+
+    /// One GET of `url`, as `get` makes them, recording its status and elapsed time
+    /// on the current span
+    async fn attempt(&self, url: &str) -> Result<Option<reqwest::Response>> {
+        let sent = Instant::now();
+        let mut request = self.client.get(url);
+        // Set on each request, as the browser's fetch has no client-wide timeout
+        if let Some(timeout) = self.timeout {
+            request = request.timeout(timeout);
+        }
+        let sent_response = request.send().await;
+        let elapsed = sent.elapsed();
+        let span = Span::current();
+        span.record("elapsed_ms", elapsed.as_millis() as u64);
+        let response = match sent_response {
+            Ok(response) => response,
+            Err(e) => {
+                // The error names the URL, credentials included
+                tracing::debug!(timeout = e.is_timeout(), "no response");
+                return Err(BlockchainError::request_after(url, e, elapsed));
+            }
+        };
+
+        let status = response.status();
+        span.record("status", status.as_u16());
+        tracing::debug!("response");
+        if status == 404 {
+            return Ok(None);
+        }
+        if status == 429 {
+            let retry_after = response
+                .headers()
+                .get(reqwest::header::RETRY_AFTER)
+                .and_then(|value| value.to_str().ok()?.trim().parse().ok())
+                .map(Duration::from_secs);
+            return Err(BlockchainError::RateLimited { retry_after });
+        }
+        // handle any other 4**/5** errors
+        if !status.is_success() {
+            let body = response
+                .text()
+                .await
+                .unwrap_or_else(|_| "Failed to read body".to_string());
+            return Err(BlockchainError::NetworkFailure(format!(
+                "HTTP {} for {}: {}",
+                status, url, body
+            )));
+        }
+        Ok(Some(response))
     }
 }
 
@@ -57,7 +265,26 @@ struct OutspendResponse {
     _vin: Option<u32>,
 }
 
-#[async_trait]
+/// Confirmed transactions per page of an address history
+const ADDRESS_PAGE: usize = 25;
+
+/// The fields of Esplora's address history entries needed to page through it.
+#[derive(Deserialize, Debug)]
+struct AddressTxResponse {
+    txid: Txid,
+    status: TxStatus,
+}
+
+/// The fields of Esplora's transaction JSON that the raw transaction lacks.
+#[derive(Deserialize, Debug)]
+struct TxInfoResponse {
+    /// Fee in sats, 0 for coinbases
+    fee: u64,
+    status: TxStatus,
+}
+
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
 impl BlockchainDataSource for EsploraClient {
     /// Fetches a transaction by its txid.
     ///
@@ -65,47 +292,32 @@ impl BlockchainDataSource for EsploraClient {
     /// then deserializes into a `bitcoin::Transaction`.
     ///
     /// # Errors
-    /// - `NetworkFailure` - HTTP request failed
+    /// - `Request` - HTTP request failed
+    /// - `RateLimited` - HTTP 429
+    /// - `NetworkFailure` - HTTP error status
     /// - `NotFound` - Transaction not found (404)
-    /// - `DataInconsistency` - Invalid hex or deserialization failure
+    /// - `Decode` - Invalid hex or deserialization failure
     async fn get_transaction(&self, txid: Txid) -> Result<Transaction> {
-        let url = format!("{}/tx/{}/hex", self.base_url, txid);
-
-        let response = self
-            .client
-            .get(&url)
-            .send()
-            .await
-            .map_err(|e| BlockchainError::NetworkFailure(e.to_string()))?;
+        async {
+            let url = format!("{}/tx/{}/hex", self.base_url, txid);
 
-        // Immediately check for 404 as it would mean transaction id does not exist
-        if response.status() == 404 {
-            return Err(BlockchainError::NotFound(format!(
-                "Transaction {} not found",
-                txid
-            )));
-        }
+            let Some(response) = self.get(&url).await? else {
+                return Err(BlockchainError::NotFound(format!(
+                    "Transaction {} not found",
+                    txid
+                )));
+            };
 
-        // handle any other 4**/5** errors
-        if !response.status().is_success() {
-            let status = response.status();
-            let body = response
+            let hex = response
                 .text()
                 .await
-                .unwrap_or_else(|_| "Failed to read body".to_string());
-            return Err(BlockchainError::NetworkFailure(format!(
-                "HTTP {} for {}: {}",
-                status, url, body
-            )));
-        }
-
-        let hex = response
-            .text()
-            .await
-            .map_err(|e| BlockchainError::NetworkFailure(e.to_string()))?;
+                .map_err(|e| BlockchainError::request(&url, e))?;
 
-        bitcoin::consensus::encode::deserialize_hex(&hex)
-            .map_err(|e| BlockchainError::DataInconsistency(format!("Invalid hex: {}", e)))
+            bitcoin::consensus::encode::deserialize_hex(&hex)
+                .map_err(|e| BlockchainError::decode(format!("transaction {}", txid), e))
+        }
+        .await
+        .with_ctx(|| self.context("get_transaction").txid(txid))
     }
 
     /// Finds the transaction that spends a specific OutPoint.
@@ -120,106 +332,699 @@ impl BlockchainDataSource for EsploraClient {
     /// - `Ok(Some(tx))` - The transaction that spent this outpoint
     /// - `Ok(None)` - The outpoint is still unspent
     /// - `Err(NotFound)` - The original transaction doesn't exist
-    /// - `Err(DataInconsistency)` - API returned invalid data
+    /// - `Err(Decode)` - API returned invalid JSON
+    /// - `Err(DataInconsistency)` - Output marked spent without a spender
     async fn get_spending_transaction(&self, outpoint: OutPoint) -> Result<Option<Transaction>> {
-        // protect against mempool.space rate limiting
-        self.throttle().await;
+        async {
+            let url = format!(
+                "{}/tx/{}/outspend/{}",
+                self.base_url, outpoint.txid, outpoint.vout
+            );
 
-        let url = format!(
-            "{}/tx/{}/outspend/{}",
-            self.base_url, outpoint.txid, outpoint.vout
-        );
+            let Some(response) = self.get(&url).await? else {
+                return Err(BlockchainError::NotFound(format!(
+                    "Transaction {} not found",
+                    outpoint.txid
+                )));
+            };
 
-        let response = self
-            .client
-            .get(&url)
-            .send()
-            .await
-            .map_err(|e| BlockchainError::NetworkFailure(e.to_string()))?;
+            // Deserialize the response into our OutspendResponse Struct
+            let outspend: OutspendResponse = response
+                .json()
+                .await
+                .map_err(|e| BlockchainError::decode(format!("outspend of {}", outpoint), e))?;
 
-        // Immediately check for 404 as it would mean transaction id does not exist
-        if response.status() == 404 {
-            return Err(BlockchainError::NotFound(format!(
-                "Transaction {} not found",
-                outpoint.txid
-            )));
+            self.spender(&outspend).await
         }
+        .await
+        .with_ctx(|| self.context("get_spending_transaction").outpoint(outpoint))
+    }
 
-        // handle any other 4**/5** errors
-        if !response.status().is_success() {
-            let status = response.status();
-            let body = response
-                .text()
-                .await
-                .unwrap_or_else(|_| "Failed to read body".to_string());
+    /// Fetches every transaction paying or spending from an address, unconfirmed
+    /// ones first, then the confirmed ones newest first.
+    ///
+    /// Uses the `/address/{address}/txs` endpoint for the txids, paging through the
+    /// confirmed history `ADDRESS_PAGE` at a time with `/txs/chain/{last_txid}`, then
+    /// fetches each transaction by its txid.
+    ///
+    /// # Errors
+    /// - `Request` - HTTP request failed
+    /// - `RateLimited` - HTTP 429
+    /// - `NetworkFailure` - HTTP error status
+    /// - `NotFound` - Address or one of its transactions not found (404)
+    /// - `Decode` - The history is not valid JSON, or a transaction does not decode
+    async fn get_address_transactions(&self, address: Address) -> Result<Vec<Transaction>> {
+        async {
+            let mut url = format!("{}/address/{}/txs", self.base_url, address);
+            let mut txids = Vec::new();
+            loop {
+                let Some(response) = self.get(&url).await? else {
+                    return Err(BlockchainError::NotFound(format!(
+                        "Address {} not found",
+                        address
+                    )));
+                };
+                let page: Vec<AddressTxResponse> = response.json().await.map_err(|e| {
+                    BlockchainError::decode(format!("history of address {}", address), e)
+                })?;
+                let confirmed = page.iter().filter(|tx| tx.status.confirmed).count();
+                let last = page.last().map(|tx| tx.txid);
+                txids.extend(page.into_iter().map(|tx| tx.txid));
+                match last {
+                    Some(last) if confirmed == ADDRESS_PAGE => {
+                        url = format!("{}/address/{}/txs/chain/{}", self.base_url, address, last);
+                    }
+                    _ => break,
+                }
+            }
 
-            return Err(BlockchainError::NetworkFailure(format!(
-                "HTTP {} for {}: {}",
-                status, url, body
-            )));
+            let mut transactions = Vec::with_capacity(txids.len());
+            for txid in txids {
+                transactions.push(self.get_transaction(txid).await?);
+            }
+            Ok(transactions)
         }
+        .await
+        .with_ctx(|| self.context("get_address_transactions"))
+    }
 
-        // Deserialize the response into our OutspendResponse Struct
-        let outspend: OutspendResponse = response
-            .json()
-            .await
-            .map_err(|e| BlockchainError::DataInconsistency(e.to_string()))?;
+    /// Finds the transactions spending each outpoint, in order, each distinct one
+    /// looked up once.
+    ///
+    /// Uses the `/tx/{txid}/outspends` endpoint once per funding transaction, however
+    /// many of its outputs are asked about, then fetches each spender. Esplora is
+    /// asked one request after the other, so there is no max batch size.
+    ///
+    /// # Errors
+    /// As `get_spending_transaction`, also `NotFound` for an output past the last
+    /// of its transaction
+    async fn get_spending_transactions_batch(
+        &self,
+        outpoints: &[OutPoint],
+    ) -> Result<Vec<Option<Transaction>>> {
+        batch::run(outpoints, usize::MAX, |outpoints| self.spenders(outpoints)).await
+    }
 
-        // if output is not spent return None Immediately
-        if !outspend.spent {
-            return Ok(None);
-        }
+    /// Fetches a full block by its hash.
+    ///
+    /// Uses the `/block/{hash}/raw` endpoint, which returns the consensus-encoded
+    /// block as binary (not hex), so the body is read as bytes.
+    ///
+    /// # Errors
+    /// - `Request` - HTTP request failed
+    /// - `RateLimited` - HTTP 429
+    /// - `NetworkFailure` - HTTP error status
+    /// - `NotFound` - Block not found (404)
+    /// - `Decode` - Body is not a valid consensus-encoded block
+    async fn get_block_raw(&self, block_hash: BlockHash) -> Result<Block> {
+        async {
+            let url = format!("{}/block/{}/raw", self.base_url, block_hash);
 
-        // If spent, fetch the full transaction data using the txid that was found
-        match outspend.txid {
-            Some(txid) => self.get_transaction(txid).await.map(Some),
-            None => Err(BlockchainError::DataInconsistency(
-                "Outspend marked spent but no txid returned".to_string(),
-            )),
+            let Some(response) = self.get(&url).await? else {
+                return Err(BlockchainError::NotFound(format!(
+                    "Block {} not found",
+                    block_hash
+                )));
+            };
+
+            let bytes = response
+                .bytes()
+                .await
+                .map_err(|e| BlockchainError::request(&url, e))?;
+
+            bitcoin::consensus::deserialize(&bytes)
+                .map_err(|e| BlockchainError::decode(format!("block {}", block_hash), e))
         }
+        .await
+        .with_ctx(|| self.context("get_block_raw"))
     }
 
-    async fn get_address_transactions(&self, _address: Address) -> Result<Vec<Transaction>> {
-        todo!()
+    /// Fetches the confirmation status of a transaction.
+    ///
+    /// Uses the `/tx/{txid}/status` endpoint, whose JSON maps onto `TxStatus`.
+    ///
+    /// # Errors
+    /// - `Request` - HTTP request failed
+    /// - `RateLimited` - HTTP 429
+    /// - `NetworkFailure` - HTTP error status
+    /// - `NotFound` - Transaction not found (404)
+    /// - `Decode` - Response is not a valid status
+    async fn get_transaction_status(&self, txid: Txid) -> Result<TxStatus> {
+        async {
+            let url = format!("{}/tx/{}/status", self.base_url, txid);
+
+            let Some(response) = self.get(&url).await? else {
+                return Err(BlockchainError::NotFound(format!(
+                    "Transaction {} not found",
+                    txid
+                )));
+            };
+
+            response
+                .json()
+                .await
+                .map_err(|e| BlockchainError::decode(format!("status of transaction {}", txid), e))
+        }
+        .await
+        .with_ctx(|| self.context("get_transaction_status").txid(txid))
     }
 
-    async fn get_transactions_batch(&self, _txids: &[Txid]) -> Result<Vec<Option<Transaction>>> {
-        todo!()
+    /// Fetches the header of a block by its hash.
+    ///
+    /// Uses the `/block/{hash}/header` endpoint, which returns the 80-byte header
+    /// as hex.
+    ///
+    /// # Errors
+    /// - `Request` - HTTP request failed
+    /// - `RateLimited` - HTTP 429
+    /// - `NetworkFailure` - HTTP error status
+    /// - `NotFound` - Block not found (404)
+    /// - `Decode` - Body is not a valid hex header
+    async fn get_block_header(&self, block_hash: BlockHash) -> Result<Header> {
+        async {
+            let url = format!("{}/block/{}/header", self.base_url, block_hash);
+
+            let Some(response) = self.get(&url).await? else {
+                return Err(BlockchainError::NotFound(format!(
+                    "Block {} not found",
+                    block_hash
+                )));
+            };
+
+            let hex = response
+                .text()
+                .await
+                .map_err(|e| BlockchainError::request(&url, e))?;
+
+            bitcoin::consensus::encode::deserialize_hex(hex.trim())
+                .map_err(|e| BlockchainError::decode(format!("header of block {}", block_hash), e))
+        }
+        .await
+        .with_ctx(|| self.context("get_block_header"))
     }
 
-    async fn get_spending_transactions_batch(
-        &self,
-        _outpoints: &[OutPoint],
-    ) -> Result<Vec<Option<Transaction>>> {
-        todo!()
+    /// Fetches a transaction with its status, fee and size.
+    ///
+    /// Uses the `/tx/{txid}/hex` endpoint for the transaction and the `/tx/{txid}`
+    /// JSON for its fee and status; confirmations are counted against the cached tip
+    /// height (see `tip_height`).
+    ///
+    /// # Errors
+    /// - `Request` - HTTP request failed
+    /// - `RateLimited` - HTTP 429
+    /// - `NetworkFailure` - HTTP error status
+    /// - `NotFound` - Transaction not found (404)
+    /// - `Decode` - Invalid hex, or the JSON is not a transaction
+    async fn get_transaction_with_metadata(&self, txid: Txid) -> Result<(Transaction, TxMetadata)> {
+        async {
+            let tx = self.get_transaction(txid).await?;
+            let url = format!("{}/tx/{}", self.base_url, txid);
+
+            let Some(response) = self.get(&url).await? else {
+                return Err(BlockchainError::NotFound(format!(
+                    "Transaction {} not found",
+                    txid
+                )));
+            };
+
+            let info: TxInfoResponse = response
+                .json()
+                .await
+                .map_err(|e| BlockchainError::decode(format!("transaction {}", txid), e))?;
+            let tip = self.tip_height().await?;
+
+            let metadata = TxMetadata::new(&tx, info.status, Some(Amount::from_sat(info.fee)), tip);
+            Ok((tx, metadata))
+        }
+        .await
+        .with_ctx(|| self.context("get_transaction_with_metadata").txid(txid))
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::testing::ChainBuilder;
+    use bitcoin::{Network, constants::genesis_block};
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
 
-    /// Uses real network and could fail for many reasons. Will improve in the future.
     #[tokio::test]
-    #[ignore] // Hits real API, don't want this running in CI yet.
-    async fn test_esplora_outspend() {
-        let client = EsploraClient::new("https://mempool.space/api".to_string());
+    async fn test_get_block_raw_round_trip() {
+        let server = MockServer::start().await;
+        let block = genesis_block(Network::Bitcoin);
+        let hash = block.block_hash();
 
-        let outpoint = OutPoint::new(
-            "a260cc34b85217c01f0f8a14d0213c9536952592f3022a0f414e7485a4b016ec"
-                .parse()
-                .unwrap(),
-            27,
+        Mock::given(method("GET"))
+            .and(path(format!("/block/{}/raw", hash)))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_bytes(bitcoin::consensus::serialize(&block)),
+            )
+            .mount(&server)
+            .await;
+
+        let client = EsploraClient::new(server.uri());
+        let fetched = client.get_block_raw(hash).await.unwrap();
+
+        assert_eq!(fetched, block);
+        assert_eq!(fetched.block_hash(), hash);
+    }
+
+    #[tokio::test]
+    async fn test_get_block_raw_corrupt_body_names_block() {
+        let server = MockServer::start().await;
+        let hash = genesis_block(Network::Bitcoin).block_hash();
+
+        Mock::given(method("GET"))
+            .and(path(format!("/block/{}/raw", hash)))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(vec![0u8; 10]))
+            .mount(&server)
+            .await;
+
+        let client = EsploraClient::new(server.uri());
+        match client.get_block_raw(hash).await {
+            Err(error) if matches!(error.inner(), BlockchainError::Decode { .. }) => {
+                assert!(error.is_decode());
+                assert!(error.to_string().contains(&hash.to_string()))
+            }
+            other => panic!("expected Decode, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_block_raw_not_found() {
+        let server = MockServer::start().await;
+        let hash = genesis_block(Network::Bitcoin).block_hash();
+
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(404))
+            .mount(&server)
+            .await;
+
+        let client = EsploraClient::new(server.uri());
+        let error = client.get_block_raw(hash).await.unwrap_err();
+        assert!(matches!(error.inner(), BlockchainError::NotFound(_)));
+        assert_eq!(
+            error.context().unwrap().url.as_deref(),
+            Some(&*server.uri())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_slow_answer_is_a_timeout() {
+        let server = MockServer::start().await;
+        let txid = genesis_block(Network::Bitcoin).txdata[0].compute_txid();
+
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(5)))
+            .mount(&server)
+            .await;
+
+        let client = EsploraClient::new(server.uri()).timeout(Duration::from_millis(50));
+        let error = client.get_transaction(txid).await.unwrap_err();
+
+        assert!(error.is_timeout(), "{:?}", error);
+        assert!(!error.is_connect() && !error.is_decode());
+        assert!(matches!(
+            error.inner(),
+            BlockchainError::Timeout { url: Some(url), .. } if url.contains(&txid.to_string())
+        ));
+        assert!(error.to_string().contains(&txid.to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_rate_limits_are_retried_as_the_policy_allows() {
+        let server = MockServer::start().await;
+        let block = genesis_block(Network::Bitcoin);
+        let hash = block.block_hash();
+
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "0"))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        // Not retried by default
+        let client = EsploraClient::new(server.uri());
+        assert!(matches!(
+            client.get_block_header(hash).await.unwrap_err().inner(),
+            BlockchainError::RateLimited {
+                retry_after: Some(Duration::ZERO)
+            }
+        ));
+
+        server.verify().await;
+        server.reset().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "0"))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string(bitcoin::consensus::encode::serialize_hex(&block.header)),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+        let client = client.retry(RetryPolicy::new(2).base_delay(Duration::from_millis(10)));
+        assert_eq!(client.get_block_header(hash).await.unwrap(), block.header);
+    }
+
+    #[tokio::test]
+    async fn test_refused_connection_is_a_connect_error() {
+        // Bound then dropped: nothing listens on the port
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let hash = genesis_block(Network::Bitcoin).block_hash();
+
+        let client = EsploraClient::new(format!("http://127.0.0.1:{}", port));
+        let error = client.get_block_header(hash).await.unwrap_err();
+
+        assert!(error.is_connect(), "{:?}", error);
+        assert!(!error.is_timeout());
+    }
+
+    #[tokio::test]
+    async fn test_malformed_json_is_a_decode_error() {
+        let server = MockServer::start().await;
+        let txid = genesis_block(Network::Bitcoin).txdata[0].compute_txid();
+
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("{\"confirmed\":"))
+            .mount(&server)
+            .await;
+
+        let client = EsploraClient::new(server.uri());
+        let error = client.get_transaction_status(txid).await.unwrap_err();
+
+        assert!(error.is_decode(), "{:?}", error);
+        assert!(error.to_string().contains(&txid.to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_get_transaction_status() {
+        let server = MockServer::start().await;
+        let block = genesis_block(Network::Bitcoin);
+        let txid = block.txdata[0].compute_txid();
+        let hash = block.block_hash();
+
+        Mock::given(method("GET"))
+            .and(path(format!("/tx/{}/status", txid)))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "confirmed": true,
+                "block_height": 0,
+                "block_hash": hash,
+                "block_time": 1231006505,
+            })))
+            .mount(&server)
+            .await;
+
+        let client = EsploraClient::new(server.uri());
+        let status = client.get_transaction_status(txid).await.unwrap();
+
+        assert!(status.confirmed);
+        assert_eq!(status.block_height, Some(0));
+        assert_eq!(status.block_hash, Some(hash));
+        assert_eq!(status.block_time, Some(1231006505));
+    }
+
+    #[tokio::test]
+    async fn test_get_transaction_status_unconfirmed() {
+        let server = MockServer::start().await;
+        let txid = genesis_block(Network::Bitcoin).txdata[0].compute_txid();
+
+        Mock::given(method("GET"))
+            .and(path(format!("/tx/{}/status", txid)))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(serde_json::json!({"confirmed": false})),
+            )
+            .mount(&server)
+            .await;
+
+        let client = EsploraClient::new(server.uri());
+
+        assert_eq!(
+            client.get_transaction_status(txid).await.unwrap(),
+            TxStatus::unconfirmed()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_block_header() {
+        let server = MockServer::start().await;
+        let header = genesis_block(Network::Bitcoin).header;
+        let hash = header.block_hash();
+
+        Mock::given(method("GET"))
+            .and(path(format!("/block/{}/header", hash)))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string(bitcoin::consensus::encode::serialize_hex(&header)),
+            )
+            .mount(&server)
+            .await;
+
+        let client = EsploraClient::new(server.uri());
+        let fetched = client.get_block_header(hash).await.unwrap();
+
+        assert_eq!(fetched, header);
+        assert_eq!(fetched.time, 1231006505);
+    }
+
+    /// Mounts a transaction at height 0 and a tip at height 100, the tip served once
+    async fn mount_metadata(server: &MockServer, tx: &Transaction, status: serde_json::Value) {
+        let txid = tx.compute_txid();
+        Mock::given(method("GET"))
+            .and(path(format!("/tx/{}/hex", txid)))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string(bitcoin::consensus::encode::serialize_hex(tx)),
+            )
+            .mount(server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path(format!("/tx/{}", txid)))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "txid": txid,
+                "version": 1,
+                "size": tx.total_size(),
+                "weight": tx.weight().to_wu(),
+                "fee": 0,
+                "status": status,
+            })))
+            .mount(server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/blocks/tip/height"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("100"))
+            .expect(1)
+            .mount(server)
+            .await;
+    }
+
+    #[tokio::test]
+    async fn test_get_transaction_with_metadata() {
+        let server = MockServer::start().await;
+        let block = genesis_block(Network::Bitcoin);
+        let tx = &block.txdata[0];
+        let hash = block.block_hash();
+        mount_metadata(
+            &server,
+            tx,
+            serde_json::json!({
+                "confirmed": true,
+                "block_height": 0,
+                "block_hash": hash,
+                "block_time": 1231006505,
+            }),
+        )
+        .await;
+
+        let client = EsploraClient::new(server.uri());
+        let (fetched, metadata) = client
+            .get_transaction_with_metadata(tx.compute_txid())
+            .await
+            .unwrap();
+
+        assert_eq!(&fetched, tx);
+        assert_eq!(
+            metadata,
+            TxMetadata {
+                confirmed: true,
+                block_height: Some(0),
+                block_hash: Some(hash),
+                block_time: Some(1231006505),
+                confirmations: Some(101),
+                fee: Some(Amount::ZERO),
+                vsize: 204,
+                weight: bitcoin::Weight::from_wu(816),
+            }
         );
 
-        let result = client.get_spending_transaction(outpoint).await;
-        assert!(result.is_ok());
+        // The tip height is reused, not fetched again
+        let (_, again) = client
+            .get_transaction_with_metadata(tx.compute_txid())
+            .await
+            .unwrap();
+        assert_eq!(again, metadata);
+    }
+
+    #[tokio::test]
+    async fn test_get_transaction_with_metadata_unconfirmed() {
+        let server = MockServer::start().await;
+        let tx = &genesis_block(Network::Bitcoin).txdata[0];
+        mount_metadata(&server, tx, serde_json::json!({"confirmed": false})).await;
+
+        let client = EsploraClient::new(server.uri());
+        let (_, metadata) = client
+            .get_transaction_with_metadata(tx.compute_txid())
+            .await
+            .unwrap();
+
+        assert!(!metadata.confirmed);
+        assert_eq!(metadata.block_height, None);
+        assert_eq!(metadata.confirmations, Some(0));
+    }
+
+    #[tokio::test]
+    async fn test_address_history_pages_through_confirmed_transactions() {
+        let server = MockServer::start().await;
+        let tx = &genesis_block(Network::Bitcoin).txdata[0];
+        let txid = tx.compute_txid();
+        let address: Address = "bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq"
+            .parse::<Address<_>>()
+            .unwrap()
+            .assume_checked();
+        let entry =
+            |confirmed| serde_json::json!({"txid": txid, "status": {"confirmed": confirmed}});
+
+        // One unconfirmed transaction, then a full page of confirmed ones
+        let mut first = vec![entry(false)];
+        first.extend((0..ADDRESS_PAGE).map(|_| entry(true)));
+        Mock::given(method("GET"))
+            .and(path(format!("/address/{}/txs", address)))
+            .respond_with(ResponseTemplate::new(200).set_body_json(first))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path(format!("/address/{}/txs/chain/{}", address, txid)))
+            .respond_with(ResponseTemplate::new(200).set_body_json(vec![entry(true)]))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path(format!("/tx/{}/hex", txid)))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string(bitcoin::consensus::encode::serialize_hex(tx)),
+            )
+            .mount(&server)
+            .await;
 
-        let tx = result.unwrap();
-        assert!(tx.is_some());
+        let client = EsploraClient::new(server.uri());
+        let history = client.get_address_transactions(address).await.unwrap();
+
+        assert_eq!(history.len(), ADDRESS_PAGE + 2);
+        assert!(history.iter().all(|t| t == tx));
+    }
+
+    #[tokio::test]
+    async fn test_esplora_outspend() {
+        let server = MockServer::start().await;
+        let mut chain = ChainBuilder::new();
+        let funding = chain.fund(&[1_000_000]);
+        let split = chain.fan_out(OutPoint::new(funding.compute_txid(), 0), 28, 1_000);
+        let outpoint = OutPoint::new(split.compute_txid(), 27);
+        let spender = chain.spend(&[outpoint], &[30_000]);
+        let outspend = |vout: u32, body: serde_json::Value| {
+            Mock::given(method("GET"))
+                .and(path(format!(
+                    "/tx/{}/outspend/{}",
+                    split.compute_txid(),
+                    vout
+                )))
+                .respond_with(ResponseTemplate::new(200).set_body_json(body))
+        };
+        outspend(
+            27,
+            serde_json::json!({"spent": true, "txid": spender.compute_txid(), "vin": 0}),
+        )
+        .mount(&server)
+        .await;
+        outspend(26, serde_json::json!({"spent": false}))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path(format!("/tx/{}/hex", spender.compute_txid())))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string(bitcoin::consensus::encode::serialize_hex(&spender)),
+            )
+            .mount(&server)
+            .await;
+
+        let client = EsploraClient::new(server.uri());
+        let tx = client.get_spending_transaction(outpoint).await.unwrap();
+        let unspent = OutPoint::new(split.compute_txid(), 26);
+
+        assert_eq!(tx, Some(spender));
         assert_eq!(
-            tx.unwrap().compute_txid().to_string(),
-            "59c738932e6d039e76c7eb9da8d6e36def35ee9ed092fb678f6e469d1a160312"
+            client.get_spending_transaction(unspent).await.unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_spending_batch_asks_once_per_funding_transaction() {
+        let server = MockServer::start().await;
+        let mut chain = ChainBuilder::new();
+        let funding = chain.fund(&[1_000_000]);
+        let split = chain.fan_out(OutPoint::new(funding.compute_txid(), 0), 3, 1_000);
+        let spent = OutPoint::new(split.compute_txid(), 2);
+        let spender = chain.spend(&[spent], &[30_000]);
+        Mock::given(method("GET"))
+            .and(path(format!("/tx/{}/outspends", split.compute_txid())))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+                {"spent": false},
+                {"spent": false},
+                {"spent": true, "txid": spender.compute_txid(), "vin": 0},
+            ])))
+            // Once per batch
+            .expect(2)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path(format!("/tx/{}/hex", spender.compute_txid())))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string(bitcoin::consensus::encode::serialize_hex(&spender)),
+            )
+            .mount(&server)
+            .await;
+
+        let client = EsploraClient::new(server.uri());
+        let unspent = OutPoint::new(split.compute_txid(), 0);
+        let spenders = client
+            .get_spending_transactions_batch(&[spent, unspent, spent])
+            .await
+            .unwrap();
+        assert_eq!(spenders, vec![Some(spender.clone()), None, Some(spender)]);
+
+        let past_the_end = OutPoint::new(split.compute_txid(), 3);
+        let err = client
+            .get_spending_transactions_batch(&[past_the_end])
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(err.inner(), BlockchainError::NotFound(_)),
+            "{}",
+            err
         );
     }
 }
@@ -0,0 +1,628 @@
+//! Composite data source answering from two backends, the second filling in for what
+//! the first cannot answer.
+//!
+//! Meant for setups where neither backend alone has every index: a local bitcoind
+//! without `txindex` serves transactions fast but cannot find spenders, a public
+//! Esplora finds everything but is slower and rate limited.
+
+use crate::blockchain::{
+    BlockchainDataSource, BlockchainError, CacheKey, Method, Result, TxMetadata, TxStatus,
+};
+use async_trait::async_trait;
+use bitcoin::{Address, Block, BlockHash, OutPoint, Transaction, Txid, block::Header};
+use std::{
+    collections::HashMap,
+    fmt,
+    future::Future,
+    sync::{Mutex, PoisonError},
+};
+
+/// One of the two backends of a `FallbackDataSource`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Backend {
+    Primary,
+    Secondary,
+}
+
+impl Backend {
+    fn other(self) -> Backend {
+        match self {
+            Backend::Primary => Backend::Secondary,
+            Backend::Secondary => Backend::Primary,
+        }
+    }
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Backend::Primary => f.write_str("primary"),
+            Backend::Secondary => f.write_str("secondary"),
+        }
+    }
+}
+
+/// Classes of errors a `FallbackDataSource` can fall back on
+///
+/// # Variants
+/// * `NotFound` - the backend does not hold what was asked, e.g. bitcoind without
+///   `txindex` asked for a transaction outside its wallet and mempool
+/// * `Unsupported` - the backend cannot answer the method at all
+/// * `Network` - the request failed or timed out on the way
+/// * `RateLimited` - the backend turned the request down for now
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorClass {
+    NotFound,
+    Unsupported,
+    Network,
+    RateLimited,
+}
+
+impl ErrorClass {
+    /// Class of `error`, `None` for errors no other backend would answer better,
+    /// such as invalid input or an answer that does not decode
+    pub fn of(error: &BlockchainError) -> Option<ErrorClass> {
+        match error.inner() {
+            BlockchainError::NotFound(_) => Some(ErrorClass::NotFound),
+            BlockchainError::UnsupportedOperation { .. } => Some(ErrorClass::Unsupported),
+            BlockchainError::RateLimited { .. } => Some(ErrorClass::RateLimited),
+            _ if error.is_retryable() => Some(ErrorClass::Network),
+            _ => None,
+        }
+    }
+}
+
+/// Calls of a `FallbackDataSource`, and who answered them.
+///
+/// # Fields
+/// * `primary` - calls the primary backend answered
+/// * `secondary` - calls the secondary backend answered
+/// * `fallbacks` - calls the preferred backend failed, passed on to the other one
+/// * `failures` - calls that failed, whichever backend failed them last
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FallbackStats {
+    pub primary: u64,
+    pub secondary: u64,
+    pub fallbacks: u64,
+    pub failures: u64,
+}
+
+impl FallbackStats {
+    fn add(self, other: FallbackStats) -> FallbackStats {
+        FallbackStats {
+            primary: self.primary + other.primary,
+            secondary: self.secondary + other.secondary,
+            fallbacks: self.fallbacks + other.fallbacks,
+            failures: self.failures + other.failures,
+        }
+    }
+}
+
+/// Data source asking a primary backend first and, when it fails with an error of a
+/// class set to fall back on, the secondary one.
+///
+/// Every class falls back by default; `fall_back_on` turns classes off. Methods ask
+/// the primary first unless `prefer` makes the secondary their first choice, e.g.
+/// for spending lookups that only an indexing backend can answer. A batch of
+/// transactions the first choice answered in part has the ones it did not find asked
+/// of the other backend, when `NotFound` falls back.
+///
+/// Which backend answered each call is logged at debug level and counted by method
+/// in `stats`. Wrap the composite, not each backend, in a `CachingDataSource`, so
+/// that both share one cache.
+///
+/// # Example
+/// ```ignore
+/// let rpc = BitcoinRpcClient::new(url, user, pass);
+/// let esplora = EsploraClient::new("https://mempool.space/api".to_string());
+/// let source = FallbackDataSource::new(rpc, esplora)
+///     .prefer(Method::GetSpendingTransaction, Backend::Secondary)
+///     .prefer(Method::GetSpendingTransactionsBatch, Backend::Secondary)
+///     .fall_back_on(ErrorClass::RateLimited, false);
+/// let cached = CachingDataSource::new(source, Duration::from_secs(300));
+/// ```
+#[derive(Debug)]
+pub struct FallbackDataSource<P, S> {
+    primary: P,
+    secondary: S,
+    /// Error classes set not to fall back on
+    disabled: Vec<ErrorClass>,
+    /// Methods asking a backend other than the primary first
+    preferences: HashMap<Method, Backend>,
+    stats: Mutex<HashMap<Method, FallbackStats>>,
+}
+
+impl<P, S> FallbackDataSource<P, S> {
+    /// Asks `primary` first and `secondary` when it fails, on every error class
+    pub fn new(primary: P, secondary: S) -> Self {
+        Self {
+            primary,
+            secondary,
+            disabled: Vec::new(),
+            preferences: HashMap::new(),
+            stats: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Sets whether errors of `class` fall back to the other backend
+    pub fn fall_back_on(mut self, class: ErrorClass, enabled: bool) -> Self {
+        self.disabled.retain(|&disabled| disabled != class);
+        if !enabled {
+            self.disabled.push(class);
+        }
+        self
+    }
+
+    /// Makes `backend` the first choice for calls of `method`
+    pub fn prefer(mut self, method: Method, backend: Backend) -> Self {
+        self.preferences.insert(method, backend);
+        self
+    }
+
+    pub fn primary(&self) -> &P {
+        &self.primary
+    }
+
+    pub fn secondary(&self) -> &S {
+        &self.secondary
+    }
+
+    /// Backend calls of `method` ask first
+    pub fn preferred(&self, method: Method) -> Backend {
+        self.preferences
+            .get(&method)
+            .copied()
+            .unwrap_or(Backend::Primary)
+    }
+
+    /// Whether `error` falls back to the other backend
+    pub fn falls_back_on(&self, error: &BlockchainError) -> bool {
+        ErrorClass::of(error).is_some_and(|class| !self.disabled.contains(&class))
+    }
+
+    /// Counters of the calls of `method` so far
+    pub fn stats_for(&self, method: Method) -> FallbackStats {
+        self.lock_stats().get(&method).copied().unwrap_or_default()
+    }
+
+    /// Counters of the calls so far, summed across methods
+    pub fn stats(&self) -> FallbackStats {
+        self.lock_stats()
+            .values()
+            .fold(FallbackStats::default(), |total, stats| total.add(*stats))
+    }
+
+    fn lock_stats(&self) -> std::sync::MutexGuard<'_, HashMap<Method, FallbackStats>> {
+        // Counters stay consistent whatever panicked while holding the lock
+        self.stats.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Counts a call of `method` `backend` answered with `result`, after falling back
+    /// if `fell_back`
+    fn count<T>(&self, method: Method, backend: Backend, result: &Result<T>, fell_back: bool) {
+        tracing::debug!(%method, %backend, ok = result.is_ok(), "fallback source answered");
+        let mut stats = self.lock_stats();
+        let stats = stats.entry(method).or_default();
+        stats.fallbacks += u64::from(fell_back);
+        match (result, backend) {
+            (Err(_), _) => stats.failures += 1,
+            (Ok(_), Backend::Primary) => stats.primary += 1,
+            (Ok(_), Backend::Secondary) => stats.secondary += 1,
+        }
+    }
+
+    /// Calls `method` through the backend it prefers, then through the other one if
+    /// it fails with an error to fall back on. `primary` and `secondary` are the call
+    /// made through each backend, run only if needed.
+    async fn call<T, F>(&self, method: Method, primary: F, secondary: F) -> (Result<T>, Backend)
+    where
+        F: Future<Output = Result<T>>,
+    {
+        let first = self.preferred(method);
+        let (first_call, second_call) = match first {
+            Backend::Primary => (primary, secondary),
+            Backend::Secondary => (secondary, primary),
+        };
+        match first_call.await {
+            Err(error) if self.falls_back_on(&error) => {
+                tracing::debug!(%method, backend = %first, %error, "falling back");
+                let result = second_call.await;
+                self.count(method, first.other(), &result, true);
+                (result, first.other())
+            }
+            result => {
+                self.count(method, first, &result, false);
+                (result, first)
+            }
+        }
+    }
+}
+
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+impl<P, S> BlockchainDataSource for FallbackDataSource<P, S>
+where
+    P: BlockchainDataSource + Send + Sync,
+    S: BlockchainDataSource + Send + Sync,
+{
+    async fn get_transaction(&self, txid: Txid) -> Result<Transaction> {
+        self.call(
+            Method::GetTransaction,
+            self.primary.get_transaction(txid),
+            self.secondary.get_transaction(txid),
+        )
+        .await
+        .0
+    }
+
+    async fn get_spending_transaction(&self, outpoint: OutPoint) -> Result<Option<Transaction>> {
+        self.call(
+            Method::GetSpendingTransaction,
+            self.primary.get_spending_transaction(outpoint),
+            self.secondary.get_spending_transaction(outpoint),
+        )
+        .await
+        .0
+    }
+
+    async fn get_address_transactions(&self, address: Address) -> Result<Vec<Transaction>> {
+        self.call(
+            Method::GetAddressTransactions,
+            self.primary.get_address_transactions(address.clone()),
+            self.secondary.get_address_transactions(address),
+        )
+        .await
+        .0
+    }
+
+    async fn get_address_utxos(&self, address: Address) -> Result<Vec<OutPoint>> {
+        self.call(
+            Method::GetAddressUtxos,
+            self.primary.get_address_utxos(address.clone()),
+            self.secondary.get_address_utxos(address),
+        )
+        .await
+        .0
+    }
+
+    async fn get_transactions_batch(&self, txids: &[Txid]) -> Result<Vec<Option<Transaction>>> {
+        let method = Method::GetTransactionsBatch;
+        let (result, answered_by) = self
+            .call(
+                method,
+                self.primary.get_transactions_batch(txids),
+                self.secondary.get_transactions_batch(txids),
+            )
+            .await;
+        let mut txs = result?;
+        let missing: Vec<Txid> = txids
+            .iter()
+            .zip(&txs)
+            .filter(|(_, tx)| tx.is_none())
+            .map(|(&txid, _)| txid)
+            .collect();
+        let fell_back = answered_by != self.preferred(method);
+        if missing.is_empty() || fell_back || self.disabled.contains(&ErrorClass::NotFound) {
+            return Ok(txs);
+        }
+        // Asked of the other backend, only for what the first choice did not find
+        let other = answered_by.other();
+        tracing::debug!(%method, backend = %answered_by, missing = missing.len(), "falling back");
+        let found = match other {
+            Backend::Primary => self.primary.get_transactions_batch(&missing).await,
+            Backend::Secondary => self.secondary.get_transactions_batch(&missing).await,
+        };
+        let found: HashMap<Txid, Transaction> = match found {
+            Ok(found) => missing
+                .into_iter()
+                .zip(found)
+                .filter_map(|(txid, tx)| Some((txid, tx?)))
+                .collect(),
+            // The first answer stands: what it did not find is reported missing
+            Err(error) => {
+                tracing::debug!(%method, backend = %other, %error, "fallback failed");
+                HashMap::new()
+            }
+        };
+        let mut stats = self.lock_stats();
+        let stats = stats.entry(method).or_default();
+        stats.fallbacks += 1;
+        match other {
+            Backend::Primary => stats.primary += u64::from(!found.is_empty()),
+            Backend::Secondary => stats.secondary += u64::from(!found.is_empty()),
+        }
+        for (txid, tx) in txids.iter().zip(&mut txs) {
+            if tx.is_none() {
+                *tx = found.get(txid).cloned();
+            }
+        }
+        Ok(txs)
+    }
+
+    async fn get_spending_transactions_batch(
+        &self,
+        outpoints: &[OutPoint],
+    ) -> Result<Vec<Option<Transaction>>> {
+        self.call(
+            Method::GetSpendingTransactionsBatch,
+            self.primary.get_spending_transactions_batch(outpoints),
+            self.secondary.get_spending_transactions_batch(outpoints),
+        )
+        .await
+        .0
+    }
+
+    async fn is_spent(&self, outpoint: OutPoint) -> Result<bool> {
+        self.call(
+            Method::IsSpent,
+            self.primary.is_spent(outpoint),
+            self.secondary.is_spent(outpoint),
+        )
+        .await
+        .0
+    }
+
+    async fn get_block_raw(&self, block_hash: BlockHash) -> Result<Block> {
+        self.call(
+            Method::GetBlockRaw,
+            self.primary.get_block_raw(block_hash),
+            self.secondary.get_block_raw(block_hash),
+        )
+        .await
+        .0
+    }
+
+    async fn get_transaction_status(&self, txid: Txid) -> Result<TxStatus> {
+        self.call(
+            Method::GetTransactionStatus,
+            self.primary.get_transaction_status(txid),
+            self.secondary.get_transaction_status(txid),
+        )
+        .await
+        .0
+    }
+
+    async fn get_block_header(&self, block_hash: BlockHash) -> Result<Header> {
+        self.call(
+            Method::GetBlockHeader,
+            self.primary.get_block_header(block_hash),
+            self.secondary.get_block_header(block_hash),
+        )
+        .await
+        .0
+    }
+
+    async fn get_transaction_with_metadata(&self, txid: Txid) -> Result<(Transaction, TxMetadata)> {
+        self.call(
+            Method::GetTransactionWithMetadata,
+            self.primary.get_transaction_with_metadata(txid),
+            self.secondary.get_transaction_with_metadata(txid),
+        )
+        .await
+        .0
+    }
+
+    fn is_cached(&self, key: &CacheKey) -> bool {
+        self.primary.is_cached(key) || self.secondary.is_cached(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{ChainBuilder, MockDataSource};
+    use bitcoin::hashes::Hash;
+    use std::time::Duration;
+
+    /// Backend without a spend index, holding the funding transaction only, and one
+    /// holding it and its spender
+    fn sources() -> (MockDataSource, MockDataSource, Transaction, Transaction) {
+        let mut chain = ChainBuilder::new();
+        let funding = chain.fund(&[50_000]);
+        let spender = chain.spend(&[OutPoint::new(funding.compute_txid(), 0)], &[49_000]);
+        let partial = MockDataSource::new(std::slice::from_ref(&funding));
+        (partial, chain.source(), funding, spender)
+    }
+
+    #[tokio::test]
+    async fn test_each_error_class_falls_back_to_the_secondary() {
+        let (partial, full, funding, spender) = sources();
+        let txid = funding.compute_txid();
+        for error in [
+            BlockchainError::NetworkFailure("reset".into()),
+            BlockchainError::RateLimited { retry_after: None },
+            BlockchainError::Timeout {
+                elapsed: Duration::from_secs(30),
+                url: None,
+            },
+        ] {
+            partial.fail_next(Method::GetTransaction, error);
+        }
+        partial.fail_next(
+            Method::GetTransactionStatus,
+            BlockchainError::unsupported("bitcoind", "get_transaction_status", "no txindex"),
+        );
+        let source = FallbackDataSource::new(partial, full);
+
+        // Network failure, rate limit, timeout
+        for _ in 0..3 {
+            assert_eq!(source.get_transaction(txid).await.unwrap(), funding);
+        }
+        assert_eq!(source.get_transaction(txid).await.unwrap(), funding);
+        // Not found by the primary
+        assert_eq!(
+            source
+                .get_transaction(spender.compute_txid())
+                .await
+                .unwrap(),
+            spender
+        );
+        // Unsupported
+        assert!(source.get_transaction_status(txid).await.is_ok());
+
+        assert_eq!(
+            source.stats_for(Method::GetTransaction),
+            FallbackStats {
+                primary: 1,
+                secondary: 4,
+                fallbacks: 4,
+                failures: 0,
+            }
+        );
+        assert_eq!(source.stats_for(Method::GetTransactionStatus).secondary, 1);
+        assert_eq!(source.secondary().calls(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_counters_tell_which_backend_answered() {
+        let (partial, full, funding, spender) = sources();
+        let source = FallbackDataSource::new(partial, full);
+
+        source
+            .get_transaction(funding.compute_txid())
+            .await
+            .unwrap();
+        source
+            .get_transaction(funding.compute_txid())
+            .await
+            .unwrap();
+        source
+            .get_transaction(spender.compute_txid())
+            .await
+            .unwrap();
+        let unknown = Txid::all_zeros();
+        assert!(matches!(
+            source.get_transaction(unknown).await,
+            Err(BlockchainError::NotFound(_))
+        ));
+
+        assert_eq!(
+            source.stats_for(Method::GetTransaction),
+            FallbackStats {
+                primary: 2,
+                secondary: 1,
+                fallbacks: 2,
+                failures: 1,
+            }
+        );
+        assert_eq!(source.stats(), source.stats_for(Method::GetTransaction));
+        assert_eq!(source.primary().calls(), 4);
+        assert_eq!(source.secondary().calls(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_disabled_error_classes_do_not_fall_back() {
+        let (partial, full, funding, spender) = sources();
+        partial.fail_next(
+            Method::GetTransaction,
+            BlockchainError::RateLimited { retry_after: None },
+        );
+        let source = FallbackDataSource::new(partial, full)
+            .fall_back_on(ErrorClass::RateLimited, false)
+            .fall_back_on(ErrorClass::NotFound, false);
+
+        assert!(matches!(
+            source.get_transaction(funding.compute_txid()).await,
+            Err(BlockchainError::RateLimited { .. })
+        ));
+        assert!(matches!(
+            source.get_transaction(spender.compute_txid()).await,
+            Err(BlockchainError::NotFound(_))
+        ));
+        assert_eq!(
+            source
+                .get_transactions_batch(&[spender.compute_txid()])
+                .await
+                .unwrap(),
+            [None]
+        );
+        assert_eq!(source.secondary().calls(), 0);
+
+        // Errors of no class, such as bad input, never fall back
+        let source = source.fall_back_on(ErrorClass::RateLimited, true);
+        source.primary().fail_next(
+            Method::GetTransaction,
+            BlockchainError::InvalidInput("bad txid".into()),
+        );
+        assert!(
+            source
+                .get_transaction(funding.compute_txid())
+                .await
+                .is_err()
+        );
+        assert_eq!(source.secondary().calls(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_methods_can_prefer_the_secondary() {
+        let (partial, full, funding, spender) = sources();
+        let root = OutPoint::new(funding.compute_txid(), 0);
+        let source = FallbackDataSource::new(partial, full)
+            .prefer(Method::GetSpendingTransaction, Backend::Secondary);
+
+        assert_eq!(
+            source.get_spending_transaction(root).await.unwrap(),
+            Some(spender)
+        );
+        // Not preferred, the batch asks the primary, which has no spend index
+        assert_eq!(
+            source
+                .get_spending_transactions_batch(&[root])
+                .await
+                .unwrap(),
+            [None]
+        );
+        assert_eq!(
+            source.preferred(Method::GetSpendingTransaction),
+            Backend::Secondary
+        );
+        assert_eq!(
+            source.preferred(Method::GetSpendingTransactionsBatch),
+            Backend::Primary
+        );
+        assert_eq!(source.primary().calls_to(Method::GetSpendingTransaction), 0);
+
+        // Failing on the secondary, a preferred method falls back to the primary
+        source.secondary().fail_next(
+            Method::GetSpendingTransaction,
+            BlockchainError::NetworkFailure("reset".into()),
+        );
+        assert_eq!(source.get_spending_transaction(root).await.unwrap(), None);
+        assert_eq!(
+            source.stats_for(Method::GetSpendingTransaction),
+            FallbackStats {
+                primary: 1,
+                secondary: 1,
+                fallbacks: 1,
+                failures: 0,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_batches_fill_in_what_the_first_choice_did_not_find() {
+        let (partial, full, funding, spender) = sources();
+        let source = FallbackDataSource::new(partial, full);
+
+        let txs = source
+            .get_transactions_batch(&[
+                funding.compute_txid(),
+                spender.compute_txid(),
+                Txid::all_zeros(),
+            ])
+            .await
+            .unwrap();
+
+        assert_eq!(txs, [Some(funding), Some(spender), None]);
+        assert_eq!(source.secondary().calls_to(Method::GetTransactionsBatch), 1);
+        assert_eq!(
+            source.stats_for(Method::GetTransactionsBatch),
+            FallbackStats {
+                primary: 1,
+                secondary: 1,
+                fallbacks: 1,
+                failures: 0,
+            }
+        );
+    }
+}
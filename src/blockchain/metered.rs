@@ -0,0 +1,406 @@
+//! Operational metrics of any data source, recorded through the `metrics` facade so
+//! that the application picks the exporter (Prometheus, statsd, ...).
+//!
+//! # Metrics
+//! * `pathfinder_datasource_requests_total{method, backend, outcome}` - counter of
+//!   calls, `outcome` being `ok` or `error`
+//! * `pathfinder_datasource_errors_total{method, backend, class}` - counter of failed
+//!   calls, by the `error_class` of their error
+//! * `pathfinder_datasource_request_duration_seconds{method, backend}` - histogram
+//!   of call latencies, failed calls included
+//!
+//! `method` is the `BlockchainDataSource` method called, as `Method::name` spells it,
+//! and `backend` the name of the wrapped source type unless set. These names and
+//! labels are stable: dashboards can rely on them.
+//!
+//! Without a recorder installed, the facade's no-op one takes the calls, so that
+//! metering costs a clock read and a few branches per call.
+
+use crate::blockchain::{
+    BlockchainDataSource, BlockchainError, CacheKey, Method, Result, TxMetadata, TxStatus,
+    source::backend_name,
+};
+use crate::time::Instant;
+use async_trait::async_trait;
+use bitcoin::{Address, Block, BlockHash, OutPoint, Transaction, Txid, block::Header};
+use std::future::Future;
+
+/// Counter of calls, labelled `method`, `backend` and `outcome`
+pub const REQUESTS_TOTAL: &str = "pathfinder_datasource_requests_total";
+
+/// Counter of failed calls, labelled `method`, `backend` and `class`
+pub const ERRORS_TOTAL: &str = "pathfinder_datasource_errors_total";
+
+/// Histogram of call latencies in seconds, labelled `method` and `backend`
+pub const REQUEST_DURATION_SECONDS: &str = "pathfinder_datasource_request_duration_seconds";
+
+/// `class` label of an error: `not_found`, `invalid_input`, `rate_limited`,
+/// `timeout`, `network`, `decode`, `inconsistent`, `unsupported` or `other`
+pub fn error_class(error: &BlockchainError) -> &'static str {
+    match error.inner() {
+        BlockchainError::NotFound(_) => "not_found",
+        BlockchainError::InvalidInput(_) => "invalid_input",
+        BlockchainError::RateLimited { .. } => "rate_limited",
+        _ if error.is_timeout() => "timeout",
+        _ if error.is_decode() => "decode",
+        BlockchainError::NetworkFailure(_) | BlockchainError::Request { .. } => "network",
+        BlockchainError::DataInconsistency(_) => "inconsistent",
+        BlockchainError::UnsupportedOperation { .. } => "unsupported",
+        _ => "other",
+    }
+}
+
+/// Describes the metrics to the installed recorder, for exporters that publish help
+/// texts. `MeteredDataSource::new` calls it.
+pub fn describe() {
+    metrics::describe_counter!(REQUESTS_TOTAL, "Calls made to a blockchain data source");
+    metrics::describe_counter!(
+        ERRORS_TOTAL,
+        "Calls to a blockchain data source that failed"
+    );
+    metrics::describe_histogram!(
+        REQUEST_DURATION_SECONDS,
+        metrics::Unit::Seconds,
+        "Latency of the calls to a blockchain data source"
+    );
+}
+
+/// Decorator recording the metrics of the module docs for every call to the source
+/// it wraps.
+///
+/// # Example
+/// ```ignore
+/// metrics_exporter_prometheus::PrometheusBuilder::new().install()?;
+/// let esplora = EsploraClient::new("https://mempool.space/api".to_string());
+/// let metered = MeteredDataSource::new(esplora).backend("mempool");
+/// let cached = CachingDataSource::new(metered, Duration::from_secs(300));
+/// ```
+#[derive(Debug)]
+pub struct MeteredDataSource<D> {
+    inner: D,
+    backend: &'static str,
+}
+
+impl<D> MeteredDataSource<D> {
+    /// Meters the calls to `inner`, labelled with the name of its type
+    pub fn new(inner: D) -> Self {
+        describe();
+        Self {
+            inner,
+            backend: backend_name::<D>(),
+        }
+    }
+
+    /// Labels the calls with `backend` instead, to tell apart two sources of a type
+    pub fn backend(mut self, backend: &'static str) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    pub fn inner(&self) -> &D {
+        &self.inner
+    }
+
+    /// Runs `call` of `method`, recording its outcome and latency
+    async fn measure<T>(&self, method: Method, call: impl Future<Output = Result<T>>) -> Result<T> {
+        let start = Instant::now();
+        let result = call.await;
+        let method = method.name();
+        metrics::histogram!(
+            REQUEST_DURATION_SECONDS,
+            "method" => method,
+            "backend" => self.backend
+        )
+        .record(start.elapsed());
+        let outcome = match &result {
+            Ok(_) => "ok",
+            Err(error) => {
+                metrics::counter!(
+                    ERRORS_TOTAL,
+                    "method" => method,
+                    "backend" => self.backend,
+                    "class" => error_class(error)
+                )
+                .increment(1);
+                "error"
+            }
+        };
+        metrics::counter!(
+            REQUESTS_TOTAL,
+            "method" => method,
+            "backend" => self.backend,
+            "outcome" => outcome
+        )
+        .increment(1);
+        result
+    }
+}
+
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+impl<D> BlockchainDataSource for MeteredDataSource<D>
+where
+    D: BlockchainDataSource + Send + Sync,
+{
+    async fn get_transaction(&self, txid: Txid) -> Result<Transaction> {
+        self.measure(Method::GetTransaction, self.inner.get_transaction(txid))
+            .await
+    }
+
+    async fn get_spending_transaction(&self, outpoint: OutPoint) -> Result<Option<Transaction>> {
+        self.measure(
+            Method::GetSpendingTransaction,
+            self.inner.get_spending_transaction(outpoint),
+        )
+        .await
+    }
+
+    async fn get_address_transactions(&self, address: Address) -> Result<Vec<Transaction>> {
+        self.measure(
+            Method::GetAddressTransactions,
+            self.inner.get_address_transactions(address),
+        )
+        .await
+    }
+
+    async fn get_address_utxos(&self, address: Address) -> Result<Vec<OutPoint>> {
+        self.measure(
+            Method::GetAddressUtxos,
+            self.inner.get_address_utxos(address),
+        )
+        .await
+    }
+
+    async fn get_transactions_batch(&self, txids: &[Txid]) -> Result<Vec<Option<Transaction>>> {
+        self.measure(
+            Method::GetTransactionsBatch,
+            self.inner.get_transactions_batch(txids),
+        )
+        .await
+    }
+
+    async fn get_spending_transactions_batch(
+        &self,
+        outpoints: &[OutPoint],
+    ) -> Result<Vec<Option<Transaction>>> {
+        self.measure(
+            Method::GetSpendingTransactionsBatch,
+            self.inner.get_spending_transactions_batch(outpoints),
+        )
+        .await
+    }
+
+    async fn is_spent(&self, outpoint: OutPoint) -> Result<bool> {
+        self.measure(Method::IsSpent, self.inner.is_spent(outpoint))
+            .await
+    }
+
+    async fn get_block_raw(&self, block_hash: BlockHash) -> Result<Block> {
+        self.measure(Method::GetBlockRaw, self.inner.get_block_raw(block_hash))
+            .await
+    }
+
+    async fn get_transaction_status(&self, txid: Txid) -> Result<TxStatus> {
+        self.measure(
+            Method::GetTransactionStatus,
+            self.inner.get_transaction_status(txid),
+        )
+        .await
+    }
+
+    async fn get_block_header(&self, block_hash: BlockHash) -> Result<Header> {
+        self.measure(
+            Method::GetBlockHeader,
+            self.inner.get_block_header(block_hash),
+        )
+        .await
+    }
+
+    async fn get_transaction_with_metadata(&self, txid: Txid) -> Result<(Transaction, TxMetadata)> {
+        self.measure(
+            Method::GetTransactionWithMetadata,
+            self.inner.get_transaction_with_metadata(txid),
+        )
+        .await
+    }
+
+    fn is_cached(&self, key: &CacheKey) -> bool {
+        self.inner.is_cached(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::ChainBuilder;
+    use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+    use std::collections::BTreeMap;
+
+    /// Name and sorted labels of a metric, with its value
+    type Recorded = BTreeMap<(String, Vec<(String, String)>), DebugValue>;
+
+    fn recorded(recorder: &DebuggingRecorder) -> Recorded {
+        recorder
+            .snapshotter()
+            .snapshot()
+            .into_vec()
+            .into_iter()
+            .map(|(key, _, _, value)| {
+                let (_, key) = key.into_parts();
+                let mut labels: Vec<_> = key
+                    .labels()
+                    .map(|label| (label.key().to_string(), label.value().to_string()))
+                    .collect();
+                labels.sort();
+                ((key.name().to_string(), labels), value)
+            })
+            .collect()
+    }
+
+    fn labels(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        let mut labels: Vec<_> = pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        labels.sort();
+        labels
+    }
+
+    fn counter(recorded: &Recorded, name: &str, pairs: &[(&str, &str)]) -> Option<u64> {
+        match recorded.get(&(name.to_string(), labels(pairs)))? {
+            DebugValue::Counter(count) => Some(*count),
+            value => panic!("{} is no counter: {:?}", name, value),
+        }
+    }
+
+    #[test]
+    fn test_calls_are_counted_by_method_and_outcome() {
+        let recorder = DebuggingRecorder::new();
+        let mut chain = ChainBuilder::new();
+        let funding = chain.fund(&[50_000]);
+        let txid = funding.compute_txid();
+        let source = MeteredDataSource::new(chain.source());
+        source.inner().fail_next(
+            Method::GetTransaction,
+            BlockchainError::RateLimited { retry_after: None },
+        );
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+
+        // The recorder only sees what runs on this thread, as the runtime does
+        metrics::with_local_recorder(&recorder, || {
+            runtime.block_on(async {
+                assert!(source.get_transaction(txid).await.is_err());
+                source.get_transaction(txid).await.unwrap();
+                source.get_transaction(txid).await.unwrap();
+                let unknown = Txid::from_raw_hash(bitcoin::hashes::Hash::all_zeros());
+                assert!(source.get_transaction(unknown).await.is_err());
+                source
+                    .get_spending_transaction(OutPoint::new(txid, 0))
+                    .await
+                    .unwrap();
+                assert!(
+                    source
+                        .get_block_raw(BlockHash::from_raw_hash(bitcoin::hashes::Hash::all_zeros()))
+                        .await
+                        .is_err()
+                );
+            })
+        });
+
+        let recorded = recorded(&recorder);
+        let get = |outcome| {
+            [
+                ("method", "get_transaction"),
+                ("backend", "MockDataSource"),
+                ("outcome", outcome),
+            ]
+        };
+        assert_eq!(counter(&recorded, REQUESTS_TOTAL, &get("ok")), Some(2));
+        assert_eq!(counter(&recorded, REQUESTS_TOTAL, &get("error")), Some(2));
+        let error = |class| {
+            [
+                ("method", "get_transaction"),
+                ("backend", "MockDataSource"),
+                ("class", class),
+            ]
+        };
+        assert_eq!(
+            counter(&recorded, ERRORS_TOTAL, &error("rate_limited")),
+            Some(1)
+        );
+        assert_eq!(
+            counter(&recorded, ERRORS_TOTAL, &error("not_found")),
+            Some(1)
+        );
+        assert_eq!(
+            counter(
+                &recorded,
+                REQUESTS_TOTAL,
+                &[
+                    ("method", "get_spending_transaction"),
+                    ("backend", "MockDataSource"),
+                    ("outcome", "ok"),
+                ]
+            ),
+            Some(1)
+        );
+        assert_eq!(
+            counter(
+                &recorded,
+                ERRORS_TOTAL,
+                &[
+                    ("method", "get_block_raw"),
+                    ("backend", "MockDataSource"),
+                    ("class", "unsupported"),
+                ]
+            ),
+            Some(1)
+        );
+        let latencies = recorded
+            .get(&(
+                REQUEST_DURATION_SECONDS.to_string(),
+                labels(&[("method", "get_transaction"), ("backend", "MockDataSource")]),
+            ))
+            .unwrap();
+        assert!(matches!(latencies, DebugValue::Histogram(values) if values.len() == 4));
+    }
+
+    #[test]
+    fn test_error_classes_are_stable() {
+        let classes: Vec<_> = [
+            BlockchainError::NotFound("tx".into()),
+            BlockchainError::InvalidInput("txid".into()),
+            BlockchainError::RateLimited { retry_after: None },
+            BlockchainError::Timeout {
+                elapsed: std::time::Duration::from_secs(1),
+                url: None,
+            },
+            BlockchainError::NetworkFailure("reset".into()),
+            BlockchainError::decode("transaction", std::fmt::Error),
+            BlockchainError::DataInconsistency("spent twice".into()),
+            BlockchainError::unsupported("Mock", "get_block_raw", "none"),
+            BlockchainError::Other("boom".into()),
+        ]
+        .iter()
+        .map(error_class)
+        .collect();
+
+        assert_eq!(
+            classes,
+            [
+                "not_found",
+                "invalid_input",
+                "rate_limited",
+                "timeout",
+                "network",
+                "decode",
+                "inconsistent",
+                "unsupported",
+                "other"
+            ]
+        );
+    }
+}
@@ -0,0 +1,401 @@
+//! Client-side rate limiting for any data source: a token bucket paces the requests,
+//! an optional semaphore bounds how many are in flight at once.
+
+use crate::blockchain::{BlockchainDataSource, CacheKey, Result, TxMetadata, TxStatus};
+use crate::time::{self, Clock, Instant};
+use async_trait::async_trait;
+use bitcoin::{Address, Block, BlockHash, OutPoint, Transaction, Txid, block::Header};
+use std::{
+    sync::{
+        Arc, Mutex, PoisonError,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Tokens of a bucket, as of the last time they were counted
+#[derive(Debug)]
+struct BucketState {
+    /// Below zero when calls have reserved tokens not refilled yet
+    tokens: f64,
+    refilled: Instant,
+}
+
+/// Token bucket refilled at `rate` tokens per second, up to `burst` tokens
+#[derive(Debug)]
+struct TokenBucket {
+    rate: f64,
+    burst: f64,
+    state: Mutex<BucketState>,
+    clock: Arc<dyn Clock>,
+}
+
+impl TokenBucket {
+    fn new(rate: f64, burst: f64, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            rate,
+            burst,
+            state: Mutex::new(BucketState {
+                tokens: burst,
+                refilled: clock.now(),
+            }),
+            clock,
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BucketState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Adds the tokens refilled since they were last counted
+    fn refill(&self, state: &mut BucketState) {
+        let now = self.clock.now();
+        let refilled = now.duration_since(state.refilled).as_secs_f64() * self.rate;
+        state.tokens = (state.tokens + refilled).min(self.burst);
+        state.refilled = now;
+    }
+
+    /// Takes `tokens`, returning how long to wait until they are refilled. Tokens not
+    /// refilled yet are reserved, so that calls go out in the order they took them.
+    fn reserve(&self, tokens: f64) -> Duration {
+        let mut state = self.lock();
+        self.refill(&mut state);
+        state.tokens -= tokens;
+        match state.tokens {
+            available if available >= 0.0 => Duration::ZERO,
+            owed => Duration::from_secs_f64(-owed / self.rate),
+        }
+    }
+
+    /// Tokens available now, none while calls wait for theirs
+    fn available(&self) -> f64 {
+        let mut state = self.lock();
+        self.refill(&mut state);
+        state.tokens.max(0.0)
+    }
+}
+
+/// Counts a call as waiting for capacity until dropped, the call cancelled or not
+struct Waiting<'a>(&'a AtomicUsize);
+
+impl<'a> Waiting<'a> {
+    fn new(waiting: &'a AtomicUsize) -> Self {
+        waiting.fetch_add(1, Ordering::Relaxed);
+        Self(waiting)
+    }
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Decorator pacing the calls to any `BlockchainDataSource` with a token bucket.
+///
+/// The bucket holds up to `burst` tokens and refills at `requests_per_second`. Each
+/// call takes a token, a batch one per item it asks about, and waits for it when the
+/// bucket is empty; calls get their tokens in the order they were made. With
+/// `max_concurrent`, a call first waits for one of that many permits, held until it
+/// returns.
+///
+/// Cloning is cheap and the clones share the inner source, the bucket and the
+/// permits, so the concurrent tasks of a trace all draw from one bucket. Put it under
+/// a `CachingDataSource` so that cache hits do not spend tokens.
+///
+/// # Example
+/// ```ignore
+/// let esplora = EsploraClient::new("https://mempool.space/api".to_string());
+/// let limited = RateLimitedDataSource::new(esplora, 10.0, 10).max_concurrent(4);
+/// let cached = CachingDataSource::new(limited, Duration::from_secs(300));
+/// ```
+pub struct RateLimitedDataSource<D> {
+    inner: Arc<D>,
+    bucket: Arc<TokenBucket>,
+    /// Permits of the calls in flight, unbounded when `None`
+    permits: Option<Arc<Semaphore>>,
+    /// Calls waiting for a token or a permit
+    waiting: Arc<AtomicUsize>,
+}
+
+impl<D> Clone for RateLimitedDataSource<D> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: Arc::clone(&self.inner),
+            bucket: Arc::clone(&self.bucket),
+            permits: self.permits.clone(),
+            waiting: Arc::clone(&self.waiting),
+        }
+    }
+}
+
+impl<D> RateLimitedDataSource<D> {
+    /// Wraps `inner` in a bucket of `burst` tokens refilled at `requests_per_second`,
+    /// full to begin with
+    ///
+    /// # Panics
+    /// If `requests_per_second` is not a positive number or `burst` is 0
+    pub fn new(inner: D, requests_per_second: f64, burst: u32) -> Self {
+        assert!(
+            requests_per_second > 0.0 && requests_per_second.is_finite(),
+            "requests per second must be positive, got {}",
+            requests_per_second
+        );
+        assert!(burst > 0, "burst must be at least 1");
+        Self {
+            inner: Arc::new(inner),
+            bucket: Arc::new(TokenBucket::new(
+                requests_per_second,
+                f64::from(burst),
+                time::system(),
+            )),
+            permits: None,
+            waiting: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Lets at most `calls` calls be in flight at once
+    ///
+    /// # Panics
+    /// If `calls` is 0
+    pub fn max_concurrent(mut self, calls: usize) -> Self {
+        assert!(calls > 0, "at least 1 concurrent call must be allowed");
+        self.permits = Some(Arc::new(Semaphore::new(calls)));
+        self
+    }
+
+    /// Paces calls on `clock` instead of the `SystemClock`, with a full bucket
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        let bucket = TokenBucket::new(self.bucket.rate, self.bucket.burst, clock);
+        self.bucket = Arc::new(bucket);
+        self
+    }
+
+    pub fn inner(&self) -> &D {
+        &self.inner
+    }
+
+    /// How close the source is to its limit: the share of the bucket spent, from 0.0
+    /// when it is full to 1.0 when it is empty or calls wait for a permit
+    pub fn saturation(&self) -> f64 {
+        let permits_left = self
+            .permits
+            .as_ref()
+            .is_none_or(|permits| permits.available_permits() > 0);
+        match permits_left {
+            true => 1.0 - self.bucket.available() / self.bucket.burst,
+            false => 1.0,
+        }
+    }
+
+    /// Calls waiting for a token or a permit
+    pub fn waiting(&self) -> usize {
+        self.waiting.load(Ordering::Relaxed)
+    }
+
+    /// Waits for a permit, if calls are bounded, then for `tokens`
+    async fn acquire(&self, tokens: usize) -> Option<OwnedSemaphorePermit> {
+        let _waiting = Waiting::new(&self.waiting);
+        let permit = match &self.permits {
+            // The semaphore is never closed
+            Some(permits) => Arc::clone(permits).acquire_owned().await.ok(),
+            None => None,
+        };
+        let wait = self.bucket.reserve(tokens as f64);
+        if !wait.is_zero() {
+            self.bucket.clock.sleep(wait).await;
+        }
+        permit
+    }
+}
+
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+impl<D> BlockchainDataSource for RateLimitedDataSource<D>
+where
+    D: BlockchainDataSource + Send + Sync,
+{
+    async fn get_transaction(&self, txid: Txid) -> Result<Transaction> {
+        let _permit = self.acquire(1).await;
+        self.inner.get_transaction(txid).await
+    }
+
+    async fn get_spending_transaction(&self, outpoint: OutPoint) -> Result<Option<Transaction>> {
+        let _permit = self.acquire(1).await;
+        self.inner.get_spending_transaction(outpoint).await
+    }
+
+    async fn get_address_transactions(&self, address: Address) -> Result<Vec<Transaction>> {
+        let _permit = self.acquire(1).await;
+        self.inner.get_address_transactions(address).await
+    }
+
+    async fn get_address_utxos(&self, address: Address) -> Result<Vec<OutPoint>> {
+        let _permit = self.acquire(1).await;
+        self.inner.get_address_utxos(address).await
+    }
+
+    async fn get_transactions_batch(&self, txids: &[Txid]) -> Result<Vec<Option<Transaction>>> {
+        let _permit = self.acquire(txids.len()).await;
+        self.inner.get_transactions_batch(txids).await
+    }
+
+    async fn get_spending_transactions_batch(
+        &self,
+        outpoints: &[OutPoint],
+    ) -> Result<Vec<Option<Transaction>>> {
+        let _permit = self.acquire(outpoints.len()).await;
+        self.inner.get_spending_transactions_batch(outpoints).await
+    }
+
+    async fn is_spent(&self, outpoint: OutPoint) -> Result<bool> {
+        let _permit = self.acquire(1).await;
+        self.inner.is_spent(outpoint).await
+    }
+
+    async fn get_block_raw(&self, block_hash: BlockHash) -> Result<Block> {
+        let _permit = self.acquire(1).await;
+        self.inner.get_block_raw(block_hash).await
+    }
+
+    async fn get_transaction_status(&self, txid: Txid) -> Result<TxStatus> {
+        let _permit = self.acquire(1).await;
+        self.inner.get_transaction_status(txid).await
+    }
+
+    async fn get_block_header(&self, block_hash: BlockHash) -> Result<Header> {
+        let _permit = self.acquire(1).await;
+        self.inner.get_block_header(block_hash).await
+    }
+
+    async fn get_transaction_with_metadata(&self, txid: Txid) -> Result<(Transaction, TxMetadata)> {
+        let _permit = self.acquire(1).await;
+        self.inner.get_transaction_with_metadata(txid).await
+    }
+
+    fn is_cached(&self, key: &CacheKey) -> bool {
+        self.inner.is_cached(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::Method;
+    use crate::testing::{ChainBuilder, MockClock, MockDataSource};
+    use futures::future::join_all;
+
+    /// Source holding one transaction, and its txid
+    fn source() -> (MockDataSource, Txid) {
+        let mut chain = ChainBuilder::new();
+        let txid = chain.fund(&[50_000]).compute_txid();
+        (chain.source(), txid)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_calls_are_paced_after_the_burst() {
+        let (mock, txid) = source();
+        let limited = RateLimitedDataSource::new(mock, 10.0, 5);
+        let start = Instant::now();
+
+        let calls = (0..100).map(|_| {
+            let limited = limited.clone();
+            async move {
+                limited.get_transaction(txid).await.unwrap();
+                start.elapsed()
+            }
+        });
+        let mut done = join_all(calls).await;
+
+        // 5 at once, then one every 100ms: the last one 95 refills in
+        done.sort();
+        assert!(done[..5].iter().all(|elapsed| elapsed.is_zero()));
+        assert_eq!(done[5], Duration::from_millis(100));
+        assert_eq!(done[99], Duration::from_millis(9_500));
+        for pair in done[5..].windows(2) {
+            assert_eq!(pair[1] - pair[0], Duration::from_millis(100));
+        }
+        assert_eq!(limited.inner().calls(), 100);
+        assert_eq!(limited.waiting(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_calls_get_their_tokens_in_the_order_they_were_made() {
+        let (mock, txid) = source();
+        let limited = RateLimitedDataSource::new(mock, 20.0, 1);
+        let order = Mutex::new(Vec::new());
+
+        let calls = (0..100).map(|i| {
+            let limited = limited.clone();
+            let order = &order;
+            async move {
+                limited.get_transaction(txid).await.unwrap();
+                order.lock().unwrap().push(i);
+            }
+        });
+        join_all(calls).await;
+
+        // Every call went out in turn: none overtook another, none was starved
+        assert_eq!(*order.lock().unwrap(), (0..100).collect::<Vec<_>>());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_concurrent_calls_are_bounded_and_saturation_reported() {
+        let (mut mock, txid) = source();
+        mock.set_latency(Method::GetTransaction, Duration::from_secs(1));
+        let limited = RateLimitedDataSource::new(mock, 1_000.0, 100).max_concurrent(4);
+        assert_eq!(limited.saturation(), 0.0);
+
+        let calls = (0..100).map(|_| {
+            let limited = limited.clone();
+            async move { limited.get_transaction(txid).await.unwrap() }
+        });
+        let probe = async {
+            tokio::time::sleep(Duration::from_millis(500)).await;
+            (limited.waiting(), limited.saturation())
+        };
+        let start = Instant::now();
+        let (_, (waiting, saturation)) = tokio::join!(join_all(calls), probe);
+
+        // 25 rounds of 4 calls of 1s each
+        assert_eq!(start.elapsed(), Duration::from_secs(25));
+        assert_eq!(waiting, 96);
+        assert_eq!(saturation, 1.0);
+        // The bucket refilled meanwhile, and the permits are back
+        assert_eq!(limited.saturation(), 0.0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_batches_take_a_token_per_item() {
+        let (mock, txid) = source();
+        let limited = RateLimitedDataSource::new(mock, 10.0, 10);
+
+        limited.get_transactions_batch(&[txid; 8]).await.unwrap();
+        assert!((limited.saturation() - 0.8).abs() < 1e-9);
+        let start = Instant::now();
+        limited.get_transactions_batch(&[txid; 4]).await.unwrap();
+        assert_eq!(start.elapsed(), Duration::from_millis(200));
+    }
+
+    #[tokio::test]
+    async fn test_calls_are_paced_on_the_given_clock() {
+        let (mock, txid) = source();
+        let clock = MockClock::new();
+        let limited = RateLimitedDataSource::new(mock, 10.0, 5).clock(Arc::new(clock.clone()));
+
+        for _ in 0..5 {
+            limited.get_transaction(txid).await.unwrap();
+        }
+        assert!(clock.elapsed().is_zero());
+        // The bucket is empty, each call waits out one refill
+        for _ in 0..3 {
+            limited.get_transaction(txid).await.unwrap();
+        }
+        assert_eq!(clock.elapsed(), Duration::from_millis(300));
+
+        // Refilled while the clock moved on
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(limited.saturation(), 0.0);
+    }
+}
@@ -0,0 +1,945 @@
+//! Recording and replaying data sources, for deterministic tests against real chain
+//! data without a network.
+//!
+//! `RecordingDataSource` writes every answer of the backend it wraps to a directory,
+//! and `ReplayDataSource` answers from that directory alone, so that a trace captured
+//! once against a live backend can be run again offline, as often as needed.
+//!
+//! # Layout
+//! One file per request, at `<dir>/<method>/<argument>`: `method` is the
+//! `BlockchainDataSource` method asked, `argument` the txid, address or block hash it
+//! was asked about, or the outpoint as `<txid>_<vout>`. Files are text, written the
+//! same way for the same answer:
+//! * `get_transaction`, `get_block_header`, `get_block_raw` - the consensus encoding in
+//!   hex
+//! * `get_spending_transaction` - the spender in hex, or `unspent`
+//! * `is_spent` - `spent` or `unspent`
+//! * `get_address_transactions` - a transaction in hex per line, in the backend's order
+//! * `get_address_utxos` - an outpoint as `<txid>:<vout>` per line, in the backend's
+//!   order
+//! * `get_transaction_status` - the status as JSON
+//! * `get_transaction_with_metadata` - the transaction in hex, then the metadata as JSON
+//! * any request the backend answered with `NotFound` - `not found`
+//!
+//! Batch requests are recorded, and replayed, as the single requests they are made
+//! of. A replay without a recorded `is_spent` or `get_address_utxos` answer derives
+//! it from the recorded spender or history, if there is one. Errors other than
+//! `NotFound` are not recorded.
+
+use crate::blockchain::{
+    BlockchainDataSource, BlockchainError, CacheKey, Result, TxMetadata, TxStatus,
+    source::unspent_outputs,
+};
+use async_trait::async_trait;
+use bitcoin::{
+    Address, Block, BlockHash, OutPoint, Transaction, Txid,
+    block::Header,
+    consensus::encode::{Decodable, deserialize_hex, serialize_hex},
+};
+use std::{
+    collections::HashMap,
+    fmt, io,
+    path::{Path, PathBuf},
+};
+
+/// Recorded answer of a request the backend answered with `NotFound`
+const NOT_FOUND: &str = "not found";
+
+/// Recorded answer of a spending lookup of an unspent output
+const UNSPENT: &str = "unspent";
+
+/// Recorded answer of `is_spent` for a spent output
+const SPENT: &str = "spent";
+
+/// A request to a data source: the method asked and what about
+#[derive(Debug, Clone)]
+enum Request {
+    Transaction(Txid),
+    Spender(OutPoint),
+    Spent(OutPoint),
+    Address(Address),
+    Utxos(Address),
+    Status(Txid),
+    Header(BlockHash),
+    Block(BlockHash),
+    Metadata(Txid),
+}
+
+impl Request {
+    fn method(&self) -> &'static str {
+        match self {
+            Request::Transaction(_) => "get_transaction",
+            Request::Spender(_) => "get_spending_transaction",
+            Request::Spent(_) => "is_spent",
+            Request::Address(_) => "get_address_transactions",
+            Request::Utxos(_) => "get_address_utxos",
+            Request::Status(_) => "get_transaction_status",
+            Request::Header(_) => "get_block_header",
+            Request::Block(_) => "get_block_raw",
+            Request::Metadata(_) => "get_transaction_with_metadata",
+        }
+    }
+
+    /// What the request is about, as errors name it
+    fn subject(&self) -> String {
+        match self {
+            Request::Transaction(txid) | Request::Status(txid) | Request::Metadata(txid) => {
+                txid.to_string()
+            }
+            Request::Spender(outpoint) | Request::Spent(outpoint) => outpoint.to_string(),
+            Request::Address(address) | Request::Utxos(address) => address.to_string(),
+            Request::Header(hash) | Request::Block(hash) => hash.to_string(),
+        }
+    }
+
+    /// Name of the request's file in the directory of its method
+    fn file_name(&self) -> String {
+        match self {
+            Request::Spender(outpoint) | Request::Spent(outpoint) => {
+                format!("{}_{}", outpoint.txid, outpoint.vout)
+            }
+            request => request.subject(),
+        }
+    }
+}
+
+impl fmt::Display for Request {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.method(), self.subject())
+    }
+}
+
+/// Directory of recordings, in the layout of the module docs
+#[derive(Debug, Clone)]
+struct Recordings {
+    dir: PathBuf,
+}
+
+impl Recordings {
+    fn path(&self, request: &Request) -> PathBuf {
+        self.dir.join(request.method()).join(request.file_name())
+    }
+
+    /// Recorded answer to `request`, `None` if it was not recorded
+    async fn read(&self, request: &Request) -> Result<Option<String>> {
+        let path = self.path(request);
+        match tokio::fs::read_to_string(&path).await {
+            Ok(answer) => Ok(Some(answer)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(BlockchainError::Other(format!(
+                "Failed to read the recording of {} from {}: {}",
+                request,
+                path.display(),
+                e
+            ))),
+        }
+    }
+
+    /// Records `answer` to `request`, replacing any earlier recording. It is written
+    /// to a temporary file first, then renamed, so that a replay never reads half of
+    /// it.
+    async fn write(&self, request: &Request, answer: &str) -> Result<()> {
+        let dir = self.dir.join(request.method());
+        let path = dir.join(request.file_name());
+        let temporary = dir.join(format!(
+            ".{}.{}.tmp",
+            request.file_name(),
+            uuid::Uuid::new_v4()
+        ));
+        let written = async {
+            tokio::fs::create_dir_all(&dir).await?;
+            tokio::fs::write(&temporary, answer).await?;
+            tokio::fs::rename(&temporary, &path).await
+        }
+        .await;
+        written.map_err(|e| {
+            BlockchainError::Other(format!(
+                "Failed to record {} to {}: {}",
+                request,
+                path.display(),
+                e
+            ))
+        })
+    }
+}
+
+fn encode_transaction(tx: &Transaction) -> String {
+    format!("{}\n", serialize_hex(tx))
+}
+
+fn encode_spender(spender: &Option<Transaction>) -> String {
+    match spender {
+        Some(tx) => encode_transaction(tx),
+        None => format!("{}\n", UNSPENT),
+    }
+}
+
+fn encode_spent(spent: &bool) -> String {
+    format!("{}\n", if *spent { SPENT } else { UNSPENT })
+}
+
+// Takes the answer as `record` hands it over
+#[allow(clippy::ptr_arg)]
+fn encode_history(history: &Vec<Transaction>) -> String {
+    history.iter().map(encode_transaction).collect()
+}
+
+// Takes the answer as `record` hands it over
+#[allow(clippy::ptr_arg)]
+fn encode_utxos(utxos: &Vec<OutPoint>) -> String {
+    utxos
+        .iter()
+        .map(|outpoint| format!("{}\n", outpoint))
+        .collect()
+}
+
+fn encode_json<T: serde::Serialize>(value: &T) -> String {
+    // Serializing a status or metadata cannot fail: their keys are all strings
+    format!("{}\n", serde_json::to_string(value).unwrap_or_default())
+}
+
+fn encode_header(header: &Header) -> String {
+    format!("{}\n", serialize_hex(header))
+}
+
+fn encode_block(block: &Block) -> String {
+    format!("{}\n", serialize_hex(block))
+}
+
+fn encode_metadata((tx, metadata): &(Transaction, TxMetadata)) -> String {
+    encode_transaction(tx) + &encode_json(metadata)
+}
+
+fn decode_hex<T: Decodable>(what: &'static str, hex: &str) -> Result<T> {
+    deserialize_hex(hex.trim()).map_err(|e| BlockchainError::decode(what, e))
+}
+
+fn decode_transaction(answer: &str) -> Result<Transaction> {
+    decode_hex("recorded transaction", answer)
+}
+
+fn decode_spender(answer: &str) -> Result<Option<Transaction>> {
+    match answer.trim() {
+        UNSPENT => Ok(None),
+        hex => decode_transaction(hex).map(Some),
+    }
+}
+
+fn decode_spent(answer: &str) -> Result<bool> {
+    match answer.trim() {
+        SPENT => Ok(true),
+        UNSPENT => Ok(false),
+        other => Err(BlockchainError::decode(
+            "recorded spent flag",
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("expected {} or {}, got {:?}", SPENT, UNSPENT, other),
+            ),
+        )),
+    }
+}
+
+fn decode_history(answer: &str) -> Result<Vec<Transaction>> {
+    answer.lines().map(decode_transaction).collect()
+}
+
+fn decode_utxos(answer: &str) -> Result<Vec<OutPoint>> {
+    answer
+        .lines()
+        .map(|line| {
+            line.parse()
+                .map_err(|e| BlockchainError::decode("recorded outpoint", e))
+        })
+        .collect()
+}
+
+fn decode_status(answer: &str) -> Result<TxStatus> {
+    serde_json::from_str(answer).map_err(|e| BlockchainError::decode("recorded status", e))
+}
+
+fn decode_header(answer: &str) -> Result<Header> {
+    decode_hex("recorded block header", answer)
+}
+
+fn decode_block(answer: &str) -> Result<Block> {
+    decode_hex("recorded block", answer)
+}
+
+fn decode_metadata(answer: &str) -> Result<(Transaction, TxMetadata)> {
+    let (hex, json) = answer.split_once('\n').unwrap_or((answer, ""));
+    let metadata =
+        serde_json::from_str(json).map_err(|e| BlockchainError::decode("recorded metadata", e))?;
+    Ok((decode_transaction(hex)?, metadata))
+}
+
+/// Decorator recording every answer of the source it wraps to a directory, for a
+/// `ReplayDataSource` to serve later.
+///
+/// Answers are recorded as they come back, `NotFound` included, in the layout of the
+/// module docs; the directory is created on the first one. An answer that cannot be
+/// recorded fails the request, so that a recording is never silently incomplete.
+///
+/// # Example
+/// ```ignore
+/// let esplora = EsploraClient::new("https://mempool.space/api".to_string());
+/// let recording = RecordingDataSource::new(esplora, "tests/recordings/peel");
+/// Tracer::new(&recording).trace_forward(root, &config).await?;
+///
+/// // Later, offline
+/// let replay = ReplayDataSource::open("tests/recordings/peel")?;
+/// Tracer::new(&replay).trace_forward(root, &config).await?;
+/// ```
+#[derive(Debug)]
+pub struct RecordingDataSource<D> {
+    inner: D,
+    recordings: Recordings,
+}
+
+impl<D> RecordingDataSource<D> {
+    /// Wraps `inner`, recording its answers into `dir`
+    pub fn new(inner: D, dir: impl Into<PathBuf>) -> Self {
+        Self {
+            inner,
+            recordings: Recordings { dir: dir.into() },
+        }
+    }
+
+    pub fn inner(&self) -> &D {
+        &self.inner
+    }
+
+    /// Directory the answers are recorded into
+    pub fn dir(&self) -> &Path {
+        &self.recordings.dir
+    }
+
+    /// Records the answer `result` to `request`, passing it on
+    async fn record<T>(
+        &self,
+        request: Request,
+        result: Result<T>,
+        encode: fn(&T) -> String,
+    ) -> Result<T> {
+        let answer = match &result {
+            Ok(value) => encode(value),
+            Err(e) if matches!(e.inner(), BlockchainError::NotFound(_)) => {
+                format!("{}\n", NOT_FOUND)
+            }
+            Err(_) => return result,
+        };
+        self.recordings.write(&request, &answer).await?;
+        result
+    }
+}
+
+#[async_trait]
+impl<D> BlockchainDataSource for RecordingDataSource<D>
+where
+    D: BlockchainDataSource + Send + Sync,
+{
+    async fn get_transaction(&self, txid: Txid) -> Result<Transaction> {
+        let result = self.inner.get_transaction(txid).await;
+        self.record(Request::Transaction(txid), result, encode_transaction)
+            .await
+    }
+
+    async fn get_spending_transaction(&self, outpoint: OutPoint) -> Result<Option<Transaction>> {
+        let result = self.inner.get_spending_transaction(outpoint).await;
+        self.record(Request::Spender(outpoint), result, encode_spender)
+            .await
+    }
+
+    async fn get_address_transactions(&self, address: Address) -> Result<Vec<Transaction>> {
+        let result = self.inner.get_address_transactions(address.clone()).await;
+        self.record(Request::Address(address), result, encode_history)
+            .await
+    }
+
+    async fn get_address_utxos(&self, address: Address) -> Result<Vec<OutPoint>> {
+        let result = self.inner.get_address_utxos(address.clone()).await;
+        self.record(Request::Utxos(address), result, encode_utxos)
+            .await
+    }
+
+    async fn get_transactions_batch(&self, txids: &[Txid]) -> Result<Vec<Option<Transaction>>> {
+        let txs = self.inner.get_transactions_batch(txids).await?;
+        for (&txid, tx) in txids.iter().zip(&txs) {
+            let answer = match tx {
+                Some(tx) => encode_transaction(tx),
+                None => format!("{}\n", NOT_FOUND),
+            };
+            self.recordings
+                .write(&Request::Transaction(txid), &answer)
+                .await?;
+        }
+        Ok(txs)
+    }
+
+    async fn get_spending_transactions_batch(
+        &self,
+        outpoints: &[OutPoint],
+    ) -> Result<Vec<Option<Transaction>>> {
+        let spenders = self
+            .inner
+            .get_spending_transactions_batch(outpoints)
+            .await?;
+        for (&outpoint, spender) in outpoints.iter().zip(&spenders) {
+            self.recordings
+                .write(&Request::Spender(outpoint), &encode_spender(spender))
+                .await?;
+        }
+        Ok(spenders)
+    }
+
+    async fn is_spent(&self, outpoint: OutPoint) -> Result<bool> {
+        let result = self.inner.is_spent(outpoint).await;
+        self.record(Request::Spent(outpoint), result, encode_spent)
+            .await
+    }
+
+    async fn get_block_raw(&self, block_hash: BlockHash) -> Result<Block> {
+        let result = self.inner.get_block_raw(block_hash).await;
+        self.record(Request::Block(block_hash), result, encode_block)
+            .await
+    }
+
+    async fn get_transaction_status(&self, txid: Txid) -> Result<TxStatus> {
+        let result = self.inner.get_transaction_status(txid).await;
+        self.record(Request::Status(txid), result, encode_json)
+            .await
+    }
+
+    async fn get_block_header(&self, block_hash: BlockHash) -> Result<Header> {
+        let result = self.inner.get_block_header(block_hash).await;
+        self.record(Request::Header(block_hash), result, encode_header)
+            .await
+    }
+
+    async fn get_transaction_with_metadata(&self, txid: Txid) -> Result<(Transaction, TxMetadata)> {
+        let result = self.inner.get_transaction_with_metadata(txid).await;
+        self.record(Request::Metadata(txid), result, encode_metadata)
+            .await
+    }
+
+    fn is_cached(&self, key: &CacheKey) -> bool {
+        self.inner.is_cached(key)
+    }
+}
+
+/// Fallback of a `ReplayDataSource` that has none: it cannot be built, so a replay
+/// without a fallback never asks it anything.
+#[derive(Debug, Clone, Copy)]
+pub enum NoFallback {}
+
+#[async_trait]
+impl BlockchainDataSource for NoFallback {
+    async fn get_transaction(&self, _txid: Txid) -> Result<Transaction> {
+        match *self {}
+    }
+
+    async fn get_spending_transaction(&self, _outpoint: OutPoint) -> Result<Option<Transaction>> {
+        match *self {}
+    }
+}
+
+/// What the recordings hold for a request
+enum Replay<T> {
+    Answered(T),
+    NotFound,
+    Unrecorded,
+}
+
+/// Data source answering from the recordings of a `RecordingDataSource`, without a
+/// network.
+///
+/// A request that was not recorded fails with `Other`, naming the request and the
+/// directory, rather than passing for a transaction the chain does not hold; with
+/// `record_misses`, it is asked of a fallback source instead and its answer recorded
+/// for the next replay. Recorded `NotFound` answers fail with `NotFound` again.
+///
+/// Lookups are never reported as cached, so a replayed trace spends its request
+/// budget as the recorded one did without a cache.
+#[derive(Debug)]
+pub struct ReplayDataSource<D = NoFallback> {
+    recordings: Recordings,
+    fallback: Option<RecordingDataSource<D>>,
+}
+
+impl ReplayDataSource {
+    /// Replays the recordings in `dir`, failing any request not among them
+    ///
+    /// # Errors
+    /// - `InvalidInput` - `dir` is not a directory
+    pub fn open(dir: impl Into<PathBuf>) -> Result<Self> {
+        let dir = dir.into();
+        if !dir.is_dir() {
+            return Err(BlockchainError::InvalidInput(format!(
+                "{} is not a directory of recordings",
+                dir.display()
+            )));
+        }
+        Ok(Self {
+            recordings: Recordings { dir },
+            fallback: None,
+        })
+    }
+
+    /// Asks requests that were not recorded of `fallback` instead of failing them,
+    /// recording its answers into the same directory
+    pub fn record_misses<D>(self, fallback: D) -> ReplayDataSource<D> {
+        ReplayDataSource {
+            fallback: Some(RecordingDataSource {
+                inner: fallback,
+                recordings: self.recordings.clone(),
+            }),
+            recordings: self.recordings,
+        }
+    }
+}
+
+impl<D> ReplayDataSource<D> {
+    /// Directory the recordings are replayed from
+    pub fn dir(&self) -> &Path {
+        &self.recordings.dir
+    }
+
+    async fn replay<T>(
+        &self,
+        request: &Request,
+        decode: fn(&str) -> Result<T>,
+    ) -> Result<Replay<T>> {
+        match self.recordings.read(request).await? {
+            None => Ok(Replay::Unrecorded),
+            Some(answer) if answer.trim() == NOT_FOUND => Ok(Replay::NotFound),
+            Some(answer) => decode(&answer).map(Replay::Answered),
+        }
+    }
+
+    /// Fallback to ask `request` of, which was not recorded
+    ///
+    /// # Errors
+    /// - `Other` - the replay has no fallback
+    fn fallback(&self, request: &Request) -> Result<&RecordingDataSource<D>> {
+        self.fallback.as_ref().ok_or_else(|| {
+            BlockchainError::Other(format!(
+                "{} was not recorded in {}",
+                request,
+                self.recordings.dir.display()
+            ))
+        })
+    }
+}
+
+#[async_trait]
+impl<D> BlockchainDataSource for ReplayDataSource<D>
+where
+    D: BlockchainDataSource + Send + Sync,
+{
+    async fn get_transaction(&self, txid: Txid) -> Result<Transaction> {
+        let request = Request::Transaction(txid);
+        match self.replay(&request, decode_transaction).await? {
+            Replay::Answered(tx) => Ok(tx),
+            Replay::NotFound => Err(BlockchainError::NotFound(request.subject())),
+            Replay::Unrecorded => self.fallback(&request)?.get_transaction(txid).await,
+        }
+    }
+
+    async fn get_spending_transaction(&self, outpoint: OutPoint) -> Result<Option<Transaction>> {
+        let request = Request::Spender(outpoint);
+        match self.replay(&request, decode_spender).await? {
+            Replay::Answered(spender) => Ok(spender),
+            Replay::NotFound => Err(BlockchainError::NotFound(request.subject())),
+            Replay::Unrecorded => {
+                self.fallback(&request)?
+                    .get_spending_transaction(outpoint)
+                    .await
+            }
+        }
+    }
+
+    async fn get_address_transactions(&self, address: Address) -> Result<Vec<Transaction>> {
+        let request = Request::Address(address.clone());
+        match self.replay(&request, decode_history).await? {
+            Replay::Answered(history) => Ok(history),
+            Replay::NotFound => Err(BlockchainError::NotFound(request.subject())),
+            Replay::Unrecorded => {
+                self.fallback(&request)?
+                    .get_address_transactions(address)
+                    .await
+            }
+        }
+    }
+
+    /// Answers the recorded UTXOs of `address`, or those of its recorded history when
+    /// they were not recorded themselves
+    async fn get_address_utxos(&self, address: Address) -> Result<Vec<OutPoint>> {
+        let request = Request::Utxos(address.clone());
+        match self.replay(&request, decode_utxos).await? {
+            Replay::Answered(utxos) => return Ok(utxos),
+            Replay::NotFound => return Err(BlockchainError::NotFound(request.subject())),
+            Replay::Unrecorded => {}
+        }
+        match self
+            .replay(&Request::Address(address.clone()), decode_history)
+            .await?
+        {
+            Replay::Answered(history) => Ok(unspent_outputs(&address, &history)),
+            Replay::NotFound => Err(BlockchainError::NotFound(request.subject())),
+            Replay::Unrecorded => self.fallback(&request)?.get_address_utxos(address).await,
+        }
+    }
+
+    async fn get_transactions_batch(&self, txids: &[Txid]) -> Result<Vec<Option<Transaction>>> {
+        let mut txs = Vec::with_capacity(txids.len());
+        let mut missed = Vec::new();
+        for &txid in txids {
+            let request = Request::Transaction(txid);
+            txs.push(match self.replay(&request, decode_transaction).await? {
+                Replay::Answered(tx) => Some(tx),
+                Replay::NotFound => None,
+                Replay::Unrecorded => {
+                    self.fallback(&request)?;
+                    missed.push(txid);
+                    None
+                }
+            });
+        }
+        if let Some(fallback) = self.fallback.as_ref().filter(|_| !missed.is_empty()) {
+            let fetched: HashMap<Txid, Option<Transaction>> = missed
+                .iter()
+                .copied()
+                .zip(fallback.get_transactions_batch(&missed).await?)
+                .collect();
+            for (txid, tx) in txids.iter().zip(&mut txs) {
+                if let Some(found) = fetched.get(txid) {
+                    tx.clone_from(found);
+                }
+            }
+        }
+        Ok(txs)
+    }
+
+    async fn get_spending_transactions_batch(
+        &self,
+        outpoints: &[OutPoint],
+    ) -> Result<Vec<Option<Transaction>>> {
+        let mut spenders = Vec::with_capacity(outpoints.len());
+        let mut missed = Vec::new();
+        for &outpoint in outpoints {
+            let request = Request::Spender(outpoint);
+            spenders.push(match self.replay(&request, decode_spender).await? {
+                Replay::Answered(spender) => spender,
+                Replay::NotFound => return Err(BlockchainError::NotFound(request.subject())),
+                Replay::Unrecorded => {
+                    self.fallback(&request)?;
+                    missed.push(outpoint);
+                    None
+                }
+            });
+        }
+        if let Some(fallback) = self.fallback.as_ref().filter(|_| !missed.is_empty()) {
+            let fetched: HashMap<OutPoint, Option<Transaction>> = missed
+                .iter()
+                .copied()
+                .zip(fallback.get_spending_transactions_batch(&missed).await?)
+                .collect();
+            for (outpoint, spender) in outpoints.iter().zip(&mut spenders) {
+                if let Some(found) = fetched.get(outpoint) {
+                    spender.clone_from(found);
+                }
+            }
+        }
+        Ok(spenders)
+    }
+
+    /// Answers the recorded flag of `outpoint`, or whether its recorded spending
+    /// lookup found a spender when the flag was not recorded itself
+    async fn is_spent(&self, outpoint: OutPoint) -> Result<bool> {
+        let request = Request::Spent(outpoint);
+        match self.replay(&request, decode_spent).await? {
+            Replay::Answered(spent) => return Ok(spent),
+            Replay::NotFound => return Err(BlockchainError::NotFound(request.subject())),
+            Replay::Unrecorded => {}
+        }
+        match self
+            .replay(&Request::Spender(outpoint), decode_spender)
+            .await?
+        {
+            Replay::Answered(spender) => Ok(spender.is_some()),
+            Replay::NotFound => Err(BlockchainError::NotFound(request.subject())),
+            Replay::Unrecorded => self.fallback(&request)?.is_spent(outpoint).await,
+        }
+    }
+
+    async fn get_block_raw(&self, block_hash: BlockHash) -> Result<Block> {
+        let request = Request::Block(block_hash);
+        match self.replay(&request, decode_block).await? {
+            Replay::Answered(block) => Ok(block),
+            Replay::NotFound => Err(BlockchainError::NotFound(request.subject())),
+            Replay::Unrecorded => self.fallback(&request)?.get_block_raw(block_hash).await,
+        }
+    }
+
+    async fn get_transaction_status(&self, txid: Txid) -> Result<TxStatus> {
+        let request = Request::Status(txid);
+        match self.replay(&request, decode_status).await? {
+            Replay::Answered(status) => Ok(status),
+            Replay::NotFound => Err(BlockchainError::NotFound(request.subject())),
+            Replay::Unrecorded => self.fallback(&request)?.get_transaction_status(txid).await,
+        }
+    }
+
+    async fn get_block_header(&self, block_hash: BlockHash) -> Result<Header> {
+        let request = Request::Header(block_hash);
+        match self.replay(&request, decode_header).await? {
+            Replay::Answered(header) => Ok(header),
+            Replay::NotFound => Err(BlockchainError::NotFound(request.subject())),
+            Replay::Unrecorded => self.fallback(&request)?.get_block_header(block_hash).await,
+        }
+    }
+
+    async fn get_transaction_with_metadata(&self, txid: Txid) -> Result<(Transaction, TxMetadata)> {
+        let request = Request::Metadata(txid);
+        match self.replay(&request, decode_metadata).await? {
+            Replay::Answered(answer) => Ok(answer),
+            Replay::NotFound => Err(BlockchainError::NotFound(request.subject())),
+            Replay::Unrecorded => {
+                self.fallback(&request)?
+                    .get_transaction_with_metadata(txid)
+                    .await
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{ChainBuilder, Method};
+    #[cfg(feature = "trace-engine")]
+    use crate::tracer::{TerminalReason, TraceConfig, Tracer};
+
+    /// Recording of the forward trace of `peel_chain`, from its funding output
+    #[cfg(feature = "trace-engine")]
+    const RECORDING: &str = "src/blockchain/testdata/peel_chain";
+
+    /// Funding transaction and the 4 hops of a peel chain spending it
+    fn peel_chain() -> (ChainBuilder, Transaction, Vec<Transaction>) {
+        let mut chain = ChainBuilder::new();
+        let funding = chain.fund(&[1_000_000]);
+        let hops = chain.peel_chain(OutPoint::new(funding.compute_txid(), 0), 4, 100_000, 1_000);
+        (chain, funding, hops)
+    }
+
+    #[cfg(feature = "trace-engine")]
+    #[tokio::test]
+    async fn test_a_committed_recording_replays_through_the_tracer() {
+        let (chain, funding, hops) = peel_chain();
+        let root = OutPoint::new(funding.compute_txid(), 0);
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join(RECORDING);
+
+        // UPDATE_GOLDEN=1 cargo test records the trace again after an intended change
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            let _ = std::fs::remove_dir_all(&dir);
+            let recording = RecordingDataSource::new(chain.source(), &dir);
+            Tracer::new(&recording)
+                .trace_forward(root, &TraceConfig::default())
+                .await
+                .unwrap();
+        }
+        let replay = ReplayDataSource::open(&dir).unwrap();
+        let outcome = Tracer::new(&replay)
+            .trace_forward(root, &TraceConfig::default())
+            .await
+            .unwrap();
+
+        let graph = outcome.graph();
+        assert_eq!(graph.len(), 1 + hops.len());
+        for pair in hops.windows(2) {
+            let spent = OutPoint::new(pair[0].compute_txid(), 0);
+            assert_eq!(
+                graph.edge(&spent).unwrap().spent_by,
+                Some(pair[1].compute_txid())
+            );
+        }
+        // Every peel and the last change were left unspent
+        let unspent: Vec<OutPoint> = graph
+            .leaves()
+            .into_iter()
+            .filter(|(_, reason)| **reason == TerminalReason::Unspent)
+            .map(|(edge, _)| edge.outpoint)
+            .collect();
+        assert_eq!(unspent.len(), hops.len() + 1);
+        assert!(unspent.contains(&OutPoint::new(hops[3].compute_txid(), 0)));
+    }
+
+    #[tokio::test]
+    async fn test_answers_are_recorded_one_file_per_request() {
+        let dir = tempfile::tempdir().unwrap();
+        let (chain, funding, hops) = peel_chain();
+        let recording = RecordingDataSource::new(chain.source(), dir.path());
+        let txid = funding.compute_txid();
+        let missing = funding.input[0].previous_output.txid;
+        let last = OutPoint::new(hops[3].compute_txid(), 0);
+
+        recording.get_transaction(txid).await.unwrap();
+        assert!(recording.get_transaction(missing).await.is_err());
+        recording
+            .get_spending_transactions_batch(&[OutPoint::new(txid, 0), last])
+            .await
+            .unwrap();
+        recording.get_transaction_status(txid).await.unwrap();
+
+        let read = |method: &str, name: String| {
+            std::fs::read_to_string(dir.path().join(method).join(name)).unwrap()
+        };
+        assert_eq!(
+            read("get_transaction", txid.to_string()),
+            format!("{}\n", serialize_hex(&funding))
+        );
+        assert_eq!(read("get_transaction", missing.to_string()), "not found\n");
+        assert_eq!(
+            read("get_spending_transaction", format!("{}_0", txid)),
+            format!("{}\n", serialize_hex(&hops[0]))
+        );
+        assert_eq!(
+            read("get_spending_transaction", format!("{}_0", last.txid)),
+            "unspent\n"
+        );
+        assert_eq!(
+            read("get_transaction_status", txid.to_string()),
+            "{\"confirmed\":false,\"block_height\":null,\"block_hash\":null,\"block_time\":null}\n"
+        );
+        let mut methods: Vec<_> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        methods.sort();
+        assert_eq!(
+            methods,
+            [
+                "get_spending_transaction",
+                "get_transaction",
+                "get_transaction_status"
+            ]
+        );
+
+        // A replay answers the same, the batch from single answers and back
+        let replay = ReplayDataSource::open(dir.path()).unwrap();
+        assert_eq!(replay.get_transaction(txid).await.unwrap(), funding);
+        assert!(matches!(
+            replay.get_transaction(missing).await,
+            Err(BlockchainError::NotFound(_))
+        ));
+        assert_eq!(
+            replay
+                .get_transactions_batch(&[txid, missing])
+                .await
+                .unwrap(),
+            [Some(funding), None]
+        );
+        assert_eq!(replay.get_spending_transaction(last).await.unwrap(), None);
+        assert_eq!(
+            replay.get_transaction_status(txid).await.unwrap(),
+            TxStatus::unconfirmed()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_spent_flags_and_utxos_are_recorded_as_answered() {
+        let dir = tempfile::tempdir().unwrap();
+        let (chain, funding, hops) = peel_chain();
+        let recording = RecordingDataSource::new(chain.source(), dir.path());
+        let spent = OutPoint::new(funding.compute_txid(), 0);
+        let last = OutPoint::new(hops[3].compute_txid(), 0);
+        let address =
+            Address::from_script(&hops[3].output[0].script_pubkey, bitcoin::Network::Bitcoin)
+                .unwrap();
+
+        assert!(recording.is_spent(spent).await.unwrap());
+        assert!(!recording.is_spent(last).await.unwrap());
+        assert_eq!(
+            recording.get_address_utxos(address.clone()).await.unwrap(),
+            [last]
+        );
+        let read = |method: &str, name: String| {
+            std::fs::read_to_string(dir.path().join(method).join(name)).unwrap()
+        };
+        assert_eq!(read("is_spent", format!("{}_0", spent.txid)), "spent\n");
+        assert_eq!(read("is_spent", format!("{}_0", last.txid)), "unspent\n");
+        assert_eq!(
+            read("get_address_utxos", address.to_string()),
+            format!("{}\n", last)
+        );
+
+        let replay = ReplayDataSource::open(dir.path()).unwrap();
+        assert!(replay.is_spent(spent).await.unwrap());
+        assert!(!replay.is_spent(last).await.unwrap());
+        assert_eq!(replay.get_address_utxos(address).await.unwrap(), [last]);
+        // Without a recorded flag, the recorded spender answers
+        recording
+            .get_spending_transaction(OutPoint::new(hops[0].compute_txid(), 0))
+            .await
+            .unwrap();
+        assert!(
+            replay
+                .is_spent(OutPoint::new(hops[0].compute_txid(), 0))
+                .await
+                .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unrecorded_requests_fail_unless_recorded_on_the_way() {
+        let dir = tempfile::tempdir().unwrap();
+        let (chain, funding, hops) = peel_chain();
+        let txid = funding.compute_txid();
+        let spent = OutPoint::new(txid, 0);
+
+        let error = ReplayDataSource::open(dir.path())
+            .unwrap()
+            .get_spending_transaction(spent)
+            .await
+            .unwrap_err();
+        assert!(matches!(error, BlockchainError::Other(_)));
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "get_spending_transaction {} was not recorded in {}",
+                spent,
+                dir.path().display()
+            )
+        );
+        assert!(
+            ReplayDataSource::open(dir.path().join("missing"))
+                .unwrap_err()
+                .to_string()
+                .contains("is not a directory of recordings")
+        );
+
+        let replay = ReplayDataSource::open(dir.path())
+            .unwrap()
+            .record_misses(chain.source());
+        assert_eq!(
+            replay.get_spending_transaction(spent).await.unwrap(),
+            Some(hops[0].clone())
+        );
+        assert_eq!(
+            replay
+                .get_transactions_batch(&[txid, hops[0].compute_txid()])
+                .await
+                .unwrap(),
+            [Some(funding.clone()), Some(hops[0].clone())]
+        );
+        let fallback = replay.fallback.as_ref().unwrap().inner();
+        assert_eq!(fallback.calls(), 2);
+        // Answered from the recordings made on the way, without asking the fallback
+        replay.get_spending_transaction(spent).await.unwrap();
+        replay.get_transactions_batch(&[txid]).await.unwrap();
+        assert_eq!(fallback.calls(), 2);
+        assert_eq!(fallback.calls_to(Method::GetTransactionsBatch), 1);
+
+        let offline = ReplayDataSource::open(dir.path()).unwrap();
+        assert_eq!(offline.get_transaction(txid).await.unwrap(), funding);
+    }
+}
@@ -1,23 +1,685 @@
-use crate::blockchain::Result;
+use crate::blockchain::{BlockchainError, Result, batch};
 use async_trait::async_trait;
+use bitcoin::{Address, Amount, OutPoint, Txid, Weight};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::Arc;
 
-#[async_trait]
+/// Cache key type distinguishing between transaction, spending and address lookups
+///
+/// # Fields
+///
+/// * `Transaction(Txid)` - Direct Transaction lookup with
+/// * `Spending(OutPoint)` - Spending Tx lookup by outpoint (which tx spent this output?)
+/// * `Address(Address)` - Transaction history of an address
+#[derive(PartialEq, Eq, Hash, Debug, Clone)]
+pub enum CacheKey {
+    Transaction(Txid),
+    Spending(OutPoint),
+    Address(Address),
+}
+
+impl CacheKey {
+    /// The transaction this key refers to (the funding tx for spending lookups),
+    /// `None` for address lookups
+    pub fn txid(&self) -> Option<Txid> {
+        match self {
+            CacheKey::Transaction(txid) => Some(*txid),
+            CacheKey::Spending(outpoint) => Some(outpoint.txid),
+            CacheKey::Address(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for CacheKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CacheKey::Transaction(txid) => write!(f, "Transaction {}", txid),
+            CacheKey::Spending(outpoint) => write!(f, "Outpoint {}", outpoint),
+            CacheKey::Address(address) => write!(f, "Address {}", address),
+        }
+    }
+}
+
+/// Confirmation status of a transaction.
+///
+/// # Fields
+/// * `confirmed` - whether the transaction is in a block
+/// * `block_height` - height of that block, `None` while unconfirmed
+/// * `block_hash` - hash of that block, `None` while unconfirmed
+/// * `block_time` - timestamp of that block's header, `None` while unconfirmed or
+///   when the backend does not report it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TxStatus {
+    pub confirmed: bool,
+    #[serde(default)]
+    pub block_height: Option<u32>,
+    #[serde(default)]
+    pub block_hash: Option<bitcoin::BlockHash>,
+    #[serde(default)]
+    pub block_time: Option<u64>,
+}
+
+impl TxStatus {
+    /// Status of a transaction still in the mempool
+    pub fn unconfirmed() -> Self {
+        Self {
+            confirmed: false,
+            block_height: None,
+            block_hash: None,
+            block_time: None,
+        }
+    }
+}
+
+/// Where a transaction stands in the chain and what it paid.
+///
+/// # Fields
+/// * `confirmed`, `block_height`, `block_hash`, `block_time` - as in `TxStatus`
+/// * `confirmations` - blocks from the transaction's to the tip, itself included: 0
+///   while unconfirmed, `None` when the height of its block is unknown
+/// * `fee` - fee the transaction paid, `None` when the backend does not know its
+///   prevouts
+/// * `vsize` - virtual size in vbytes
+/// * `weight` - weight in weight units
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TxMetadata {
+    pub confirmed: bool,
+    pub block_height: Option<u32>,
+    pub block_hash: Option<bitcoin::BlockHash>,
+    pub block_time: Option<u64>,
+    pub confirmations: Option<u32>,
+    pub fee: Option<Amount>,
+    pub vsize: u32,
+    pub weight: Weight,
+}
+
+impl TxMetadata {
+    /// Metadata of `tx`, of status `status` and fee `fee`, its confirmations counted
+    /// against the tip at height `tip`. A tip below the transaction's block is taken
+    /// as stale: the transaction then has 1 confirmation.
+    pub fn new(tx: &bitcoin::Transaction, status: TxStatus, fee: Option<Amount>, tip: u32) -> Self {
+        let confirmations = match (status.confirmed, status.block_height) {
+            (false, _) => Some(0),
+            (true, Some(height)) => Some(tip.saturating_sub(height) + 1),
+            (true, None) => None,
+        };
+        Self {
+            confirmed: status.confirmed,
+            block_height: status.block_height,
+            block_hash: status.block_hash,
+            block_time: status.block_time,
+            confirmations,
+            fee,
+            vsize: u32::try_from(tx.vsize()).unwrap_or(u32::MAX),
+            weight: tx.weight(),
+        }
+    }
+}
+
+/// Methods of `BlockchainDataSource`, to configure, count and fail calls by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Method {
+    GetTransaction,
+    GetSpendingTransaction,
+    GetAddressTransactions,
+    GetAddressUtxos,
+    GetTransactionsBatch,
+    GetSpendingTransactionsBatch,
+    IsSpent,
+    GetBlockRaw,
+    GetTransactionStatus,
+    GetBlockHeader,
+    GetTransactionWithMetadata,
+}
+
+impl Method {
+    /// Name of the method, as the trait spells it
+    pub fn name(self) -> &'static str {
+        match self {
+            Method::GetTransaction => "get_transaction",
+            Method::GetSpendingTransaction => "get_spending_transaction",
+            Method::GetAddressTransactions => "get_address_transactions",
+            Method::GetAddressUtxos => "get_address_utxos",
+            Method::GetTransactionsBatch => "get_transactions_batch",
+            Method::GetSpendingTransactionsBatch => "get_spending_transactions_batch",
+            Method::IsSpent => "is_spent",
+            Method::GetBlockRaw => "get_block_raw",
+            Method::GetTransactionStatus => "get_transaction_status",
+            Method::GetBlockHeader => "get_block_header",
+            Method::GetTransactionWithMetadata => "get_transaction_with_metadata",
+        }
+    }
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Async interface for blockchain queries, implemented by every backend.
+///
+/// The futures of its methods are `Send` on native targets. On wasm they are not,
+/// as the browser's fetch is not, so implementations there take
+/// `#[async_trait(?Send)]`.
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
 pub trait BlockchainDataSource {
     async fn get_transaction(&self, txid: bitcoin::Txid) -> Result<bitcoin::Transaction>;
     async fn get_spending_transaction(
         &self,
         outpoint: bitcoin::OutPoint,
     ) -> Result<Option<bitcoin::Transaction>>;
+
+    /// Lists the transactions paying or spending from an address, newest first.
+    ///
+    /// Optional capability: backends without an index of addresses keep this
+    /// default, which returns `UnsupportedOperation`.
+    async fn get_address_transactions(
+        &self,
+        _address: bitcoin::Address,
+    ) -> Result<Vec<bitcoin::Transaction>> {
+        Err(BlockchainError::unsupported(
+            backend_name::<Self>(),
+            "get_address_transactions",
+            "use a backend that indexes addresses, such as Esplora or Electrum",
+        ))
+    }
+
+    /// Fetches transactions by txid, in the order of `txids`, `None` for those the
+    /// backend does not know. A txid given twice is looked up once, and an empty
+    /// batch answers an empty `Vec` without asking anything.
+    ///
+    /// This default looks them up one after the other with `get_transaction`.
+    /// Backends that can ask for many at once override it, keeping to the same
+    /// contract with the `batch` helpers.
+    ///
+    /// # Errors
+    /// The first error other than `NotFound`
+    async fn get_transactions_batch(
+        &self,
+        txids: &[bitcoin::Txid],
+    ) -> Result<Vec<Option<bitcoin::Transaction>>> {
+        batch::run(txids, usize::MAX, |txids| async move {
+            let mut transactions = Vec::with_capacity(txids.len());
+            for txid in txids {
+                transactions.push(match self.get_transaction(txid).await {
+                    Ok(tx) => Some(tx),
+                    Err(e) if matches!(e.inner(), BlockchainError::NotFound(_)) => None,
+                    Err(e) => return Err(e),
+                });
+            }
+            Ok(transactions)
+        })
+        .await
+    }
+
+    /// Finds the spenders of outpoints, in the order of `outpoints`, `None` for those
+    /// still unspent. As for `get_transactions_batch`, duplicates are looked up once
+    /// and an empty batch asks nothing.
+    ///
+    /// This default looks them up one after the other with
+    /// `get_spending_transaction`. Backends that can ask for many at once override it.
+    ///
+    /// # Errors
+    /// The first error, `NotFound` included: an output of an unknown transaction is
+    /// not an unspent one
+    async fn get_spending_transactions_batch(
+        &self,
+        outpoints: &[bitcoin::OutPoint],
+    ) -> Result<Vec<Option<bitcoin::Transaction>>> {
+        batch::run(outpoints, usize::MAX, |outpoints| async move {
+            let mut spenders = Vec::with_capacity(outpoints.len());
+            for outpoint in outpoints {
+                spenders.push(self.get_spending_transaction(outpoint).await?);
+            }
+            Ok(spenders)
+        })
+        .await
+    }
+
+    /// Whether another transaction spends `outpoint`.
+    ///
+    /// This default finds its spender with `get_spending_transaction`. Backends that
+    /// tell spent outputs apart without finding their spender override it.
+    async fn is_spent(&self, outpoint: bitcoin::OutPoint) -> Result<bool> {
+        Ok(self.get_spending_transaction(outpoint).await?.is_some())
+    }
+
+    /// Lists the unspent outputs paying an address, oldest transaction first.
+    ///
+    /// This default derives them from `get_address_transactions`: the outputs of the
+    /// history paying the address, less those its transactions spend. Backends that
+    /// list UTXOs directly can override it.
+    async fn get_address_utxos(&self, address: bitcoin::Address) -> Result<Vec<bitcoin::OutPoint>> {
+        let history = self.get_address_transactions(address.clone()).await?;
+        Ok(unspent_outputs(&address, &history))
+    }
+
+    /// Fetches a full block by hash, deserialized from its consensus encoding.
+    ///
+    /// Optional capability: backends that cannot serve raw blocks keep this
+    /// default, which returns `UnsupportedOperation`.
+    async fn get_block_raw(&self, _block_hash: bitcoin::BlockHash) -> Result<bitcoin::Block> {
+        Err(BlockchainError::unsupported(
+            backend_name::<Self>(),
+            "get_block_raw",
+            "use a backend that serves raw blocks, such as Esplora or bitcoind",
+        ))
+    }
+
+    /// Fetches the confirmation status of a transaction.
+    ///
+    /// Optional capability: backends that cannot report it keep this default, which
+    /// returns `UnsupportedOperation`.
+    async fn get_transaction_status(&self, _txid: bitcoin::Txid) -> Result<TxStatus> {
+        Err(BlockchainError::unsupported(
+            backend_name::<Self>(),
+            "get_transaction_status",
+            "use a backend that reports confirmations, such as Esplora or bitcoind",
+        ))
+    }
+
+    /// Fetches the header of a block by hash.
+    ///
+    /// Optional capability: backends that cannot serve headers keep this default,
+    /// which returns `UnsupportedOperation`.
+    async fn get_block_header(
+        &self,
+        _block_hash: bitcoin::BlockHash,
+    ) -> Result<bitcoin::block::Header> {
+        Err(BlockchainError::unsupported(
+            backend_name::<Self>(),
+            "get_block_header",
+            "use a backend that serves block headers, such as Esplora or bitcoind",
+        ))
+    }
+
+    /// Fetches a transaction by txid together with its confirmation status, fee and
+    /// size.
+    ///
+    /// Optional capability: backends that cannot report them keep this default,
+    /// which returns `UnsupportedOperation`.
+    async fn get_transaction_with_metadata(
+        &self,
+        _txid: bitcoin::Txid,
+    ) -> Result<(bitcoin::Transaction, TxMetadata)> {
+        Err(BlockchainError::unsupported(
+            backend_name::<Self>(),
+            "get_transaction_with_metadata",
+            "use a backend that reports fees, such as Esplora or bitcoind",
+        ))
+    }
+
+    /// Whether a lookup of `key` would be answered without a request to the backend,
+    /// e.g. from a cache. Lets callers budget the requests that actually go out.
+    ///
+    /// Optional capability: sources without a cache keep this default, `false`.
+    fn is_cached(&self, _key: &CacheKey) -> bool {
+        false
+    }
+}
+
+/// A data source of a type only known at runtime, e.g. picked by a configuration
+/// file; `DataSourceBuilder` builds one as a `Box<DynDataSource>`.
+///
+/// The trait is object safe: its async methods return boxed futures, so a boxed or
+/// shared `DynDataSource` is itself a source, and can be cached or traced like any
+/// other.
+pub type DynDataSource = dyn BlockchainDataSource + Send + Sync;
+
+/// Name of the backend type `T`, without its module path or generic parameters,
+/// for the errors of the default methods
+pub(crate) fn backend_name<T: ?Sized>() -> &'static str {
+    let name = std::any::type_name::<T>();
+    let name = name.split('<').next().unwrap_or(name);
+    name.rsplit("::").next().unwrap_or(name)
+}
+
+/// Outputs of `history` paying `address` that none of its transactions spend, oldest
+/// transaction first, for a history listed newest first as backends list them
+pub(crate) fn unspent_outputs(
+    address: &bitcoin::Address,
+    history: &[bitcoin::Transaction],
+) -> Vec<bitcoin::OutPoint> {
+    let script = address.script_pubkey();
+    let spent: HashSet<bitcoin::OutPoint> = history
+        .iter()
+        .flat_map(|tx| tx.input.iter().map(|input| input.previous_output))
+        .collect();
+    history
+        .iter()
+        .rev()
+        .flat_map(|tx| {
+            let txid = tx.compute_txid();
+            (0..)
+                .zip(&tx.output)
+                .filter(|(_, output)| output.script_pubkey == script)
+                .map(move |(vout, _)| bitcoin::OutPoint::new(txid, vout))
+        })
+        .filter(|outpoint| !spent.contains(outpoint))
+        .collect()
+}
+
+/// Forwards every method to the shared source, so decorators can wrap an `Arc`.
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+impl<T> BlockchainDataSource for Arc<T>
+where
+    T: BlockchainDataSource + Send + Sync + ?Sized,
+{
+    async fn get_transaction(&self, txid: bitcoin::Txid) -> Result<bitcoin::Transaction> {
+        (**self).get_transaction(txid).await
+    }
+    async fn get_spending_transaction(
+        &self,
+        outpoint: bitcoin::OutPoint,
+    ) -> Result<Option<bitcoin::Transaction>> {
+        (**self).get_spending_transaction(outpoint).await
+    }
     async fn get_address_transactions(
         &self,
         address: bitcoin::Address,
-    ) -> Result<Vec<bitcoin::Transaction>>;
+    ) -> Result<Vec<bitcoin::Transaction>> {
+        (**self).get_address_transactions(address).await
+    }
+    async fn get_address_utxos(&self, address: bitcoin::Address) -> Result<Vec<bitcoin::OutPoint>> {
+        (**self).get_address_utxos(address).await
+    }
     async fn get_transactions_batch(
         &self,
         txids: &[bitcoin::Txid],
-    ) -> Result<Vec<Option<bitcoin::Transaction>>>;
+    ) -> Result<Vec<Option<bitcoin::Transaction>>> {
+        (**self).get_transactions_batch(txids).await
+    }
     async fn get_spending_transactions_batch(
         &self,
         outpoints: &[bitcoin::OutPoint],
-    ) -> Result<Vec<Option<bitcoin::Transaction>>>;
+    ) -> Result<Vec<Option<bitcoin::Transaction>>> {
+        (**self).get_spending_transactions_batch(outpoints).await
+    }
+    async fn is_spent(&self, outpoint: bitcoin::OutPoint) -> Result<bool> {
+        (**self).is_spent(outpoint).await
+    }
+    async fn get_block_raw(&self, block_hash: bitcoin::BlockHash) -> Result<bitcoin::Block> {
+        (**self).get_block_raw(block_hash).await
+    }
+    async fn get_transaction_status(&self, txid: bitcoin::Txid) -> Result<TxStatus> {
+        (**self).get_transaction_status(txid).await
+    }
+    async fn get_block_header(
+        &self,
+        block_hash: bitcoin::BlockHash,
+    ) -> Result<bitcoin::block::Header> {
+        (**self).get_block_header(block_hash).await
+    }
+    async fn get_transaction_with_metadata(
+        &self,
+        txid: bitcoin::Txid,
+    ) -> Result<(bitcoin::Transaction, TxMetadata)> {
+        (**self).get_transaction_with_metadata(txid).await
+    }
+    fn is_cached(&self, key: &CacheKey) -> bool {
+        (**self).is_cached(key)
+    }
+}
+
+/// Forwards every method to the borrowed source, so decorators can wrap a reference.
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+impl<T> BlockchainDataSource for &T
+where
+    T: BlockchainDataSource + Sync + ?Sized,
+{
+    async fn get_transaction(&self, txid: bitcoin::Txid) -> Result<bitcoin::Transaction> {
+        (**self).get_transaction(txid).await
+    }
+    async fn get_spending_transaction(
+        &self,
+        outpoint: bitcoin::OutPoint,
+    ) -> Result<Option<bitcoin::Transaction>> {
+        (**self).get_spending_transaction(outpoint).await
+    }
+    async fn get_address_transactions(
+        &self,
+        address: bitcoin::Address,
+    ) -> Result<Vec<bitcoin::Transaction>> {
+        (**self).get_address_transactions(address).await
+    }
+    async fn get_address_utxos(&self, address: bitcoin::Address) -> Result<Vec<bitcoin::OutPoint>> {
+        (**self).get_address_utxos(address).await
+    }
+    async fn get_transactions_batch(
+        &self,
+        txids: &[bitcoin::Txid],
+    ) -> Result<Vec<Option<bitcoin::Transaction>>> {
+        (**self).get_transactions_batch(txids).await
+    }
+    async fn get_spending_transactions_batch(
+        &self,
+        outpoints: &[bitcoin::OutPoint],
+    ) -> Result<Vec<Option<bitcoin::Transaction>>> {
+        (**self).get_spending_transactions_batch(outpoints).await
+    }
+    async fn is_spent(&self, outpoint: bitcoin::OutPoint) -> Result<bool> {
+        (**self).is_spent(outpoint).await
+    }
+    async fn get_block_raw(&self, block_hash: bitcoin::BlockHash) -> Result<bitcoin::Block> {
+        (**self).get_block_raw(block_hash).await
+    }
+    async fn get_transaction_status(&self, txid: bitcoin::Txid) -> Result<TxStatus> {
+        (**self).get_transaction_status(txid).await
+    }
+    async fn get_block_header(
+        &self,
+        block_hash: bitcoin::BlockHash,
+    ) -> Result<bitcoin::block::Header> {
+        (**self).get_block_header(block_hash).await
+    }
+    async fn get_transaction_with_metadata(
+        &self,
+        txid: bitcoin::Txid,
+    ) -> Result<(bitcoin::Transaction, TxMetadata)> {
+        (**self).get_transaction_with_metadata(txid).await
+    }
+    fn is_cached(&self, key: &CacheKey) -> bool {
+        (**self).is_cached(key)
+    }
+}
+
+/// Forwards every method to the boxed source, so a backend chosen at runtime can be
+/// held as a `Box<DynDataSource>`.
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+impl<T> BlockchainDataSource for Box<T>
+where
+    T: BlockchainDataSource + Send + Sync + ?Sized,
+{
+    async fn get_transaction(&self, txid: bitcoin::Txid) -> Result<bitcoin::Transaction> {
+        (**self).get_transaction(txid).await
+    }
+    async fn get_spending_transaction(
+        &self,
+        outpoint: bitcoin::OutPoint,
+    ) -> Result<Option<bitcoin::Transaction>> {
+        (**self).get_spending_transaction(outpoint).await
+    }
+    async fn get_address_transactions(
+        &self,
+        address: bitcoin::Address,
+    ) -> Result<Vec<bitcoin::Transaction>> {
+        (**self).get_address_transactions(address).await
+    }
+    async fn get_address_utxos(&self, address: bitcoin::Address) -> Result<Vec<bitcoin::OutPoint>> {
+        (**self).get_address_utxos(address).await
+    }
+    async fn get_transactions_batch(
+        &self,
+        txids: &[bitcoin::Txid],
+    ) -> Result<Vec<Option<bitcoin::Transaction>>> {
+        (**self).get_transactions_batch(txids).await
+    }
+    async fn get_spending_transactions_batch(
+        &self,
+        outpoints: &[bitcoin::OutPoint],
+    ) -> Result<Vec<Option<bitcoin::Transaction>>> {
+        (**self).get_spending_transactions_batch(outpoints).await
+    }
+    async fn is_spent(&self, outpoint: bitcoin::OutPoint) -> Result<bool> {
+        (**self).is_spent(outpoint).await
+    }
+    async fn get_block_raw(&self, block_hash: bitcoin::BlockHash) -> Result<bitcoin::Block> {
+        (**self).get_block_raw(block_hash).await
+    }
+    async fn get_transaction_status(&self, txid: bitcoin::Txid) -> Result<TxStatus> {
+        (**self).get_transaction_status(txid).await
+    }
+    async fn get_block_header(
+        &self,
+        block_hash: bitcoin::BlockHash,
+    ) -> Result<bitcoin::block::Header> {
+        (**self).get_block_header(block_hash).await
+    }
+    async fn get_transaction_with_metadata(
+        &self,
+        txid: bitcoin::Txid,
+    ) -> Result<(bitcoin::Transaction, TxMetadata)> {
+        (**self).get_transaction_with_metadata(txid).await
+    }
+    fn is_cached(&self, key: &CacheKey) -> bool {
+        (**self).is_cached(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::ChainBuilder;
+    use bitcoin::{Transaction, hashes::Hash};
+    use std::collections::HashMap;
+
+    /// Backend implementing the two required methods only
+    struct Minimal {
+        txs: HashMap<Txid, Transaction>,
+        spenders: HashMap<OutPoint, Transaction>,
+        /// Txid answered with a network failure
+        failing: Option<Txid>,
+    }
+
+    #[async_trait]
+    impl BlockchainDataSource for Minimal {
+        async fn get_transaction(&self, txid: Txid) -> Result<Transaction> {
+            if self.failing == Some(txid) {
+                return Err(BlockchainError::NetworkFailure("offline".to_string()));
+            }
+            self.txs
+                .get(&txid)
+                .cloned()
+                .ok_or_else(|| BlockchainError::NotFound(txid.to_string()))
+        }
+
+        async fn get_spending_transaction(
+            &self,
+            outpoint: OutPoint,
+        ) -> Result<Option<Transaction>> {
+            if !self.txs.contains_key(&outpoint.txid) {
+                return Err(BlockchainError::NotFound(outpoint.txid.to_string()));
+            }
+            Ok(self.spenders.get(&outpoint).cloned())
+        }
+    }
+
+    /// A funding transaction of two outputs, the first spent, in a `Minimal` backend
+    fn minimal() -> (Minimal, Transaction, Transaction) {
+        let mut chain = ChainBuilder::new();
+        let funding = chain.fund(&[50_000, 20_000]);
+        let spender = chain.spend(&[OutPoint::new(funding.compute_txid(), 0)], &[49_000]);
+        let source = Minimal {
+            txs: [&funding, &spender]
+                .into_iter()
+                .map(|tx| (tx.compute_txid(), tx.clone()))
+                .collect(),
+            spenders: HashMap::from([(OutPoint::new(funding.compute_txid(), 0), spender.clone())]),
+            failing: None,
+        };
+        (source, funding, spender)
+    }
+
+    #[tokio::test]
+    async fn test_default_batches_keep_the_order_of_the_input() {
+        let (source, funding, spender) = minimal();
+        let unknown = Txid::all_zeros();
+
+        let txs = source
+            .get_transactions_batch(&[spender.compute_txid(), unknown, funding.compute_txid()])
+            .await
+            .unwrap();
+        assert_eq!(txs, [Some(spender.clone()), None, Some(funding.clone())]);
+
+        let outpoints = [1, 0].map(|vout| OutPoint::new(funding.compute_txid(), vout));
+        let spenders = source
+            .get_spending_transactions_batch(&outpoints)
+            .await
+            .unwrap();
+        assert_eq!(spenders, [None, Some(spender)]);
+        assert!(source.get_transactions_batch(&[]).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_default_batches_fail_on_hard_errors() {
+        let (mut source, funding, spender) = minimal();
+        source.failing = Some(spender.compute_txid());
+
+        let error = source
+            .get_transactions_batch(&[funding.compute_txid(), spender.compute_txid()])
+            .await
+            .unwrap_err();
+        assert!(matches!(error.inner(), BlockchainError::NetworkFailure(_)));
+
+        // Unknown is not unspent
+        let error = source
+            .get_spending_transactions_batch(&[OutPoint::new(Txid::all_zeros(), 0)])
+            .await
+            .unwrap_err();
+        assert!(matches!(error.inner(), BlockchainError::NotFound(_)));
+    }
+
+    #[tokio::test]
+    async fn test_default_optional_methods() {
+        let (source, funding, _) = minimal();
+
+        assert!(
+            source
+                .is_spent(OutPoint::new(funding.compute_txid(), 0))
+                .await
+                .unwrap()
+        );
+        assert!(
+            !source
+                .is_spent(OutPoint::new(funding.compute_txid(), 1))
+                .await
+                .unwrap()
+        );
+        let address =
+            Address::from_script(&funding.output[0].script_pubkey, bitcoin::Network::Bitcoin)
+                .unwrap();
+        let error = source.get_address_transactions(address).await.unwrap_err();
+        assert!(matches!(
+            error.inner(),
+            BlockchainError::UnsupportedOperation {
+                backend: "Minimal",
+                ..
+            }
+        ));
+        // Forwarded through a box, as a backend picked at runtime
+        let boxed: Box<DynDataSource> = Box::new(source);
+        assert!(
+            boxed
+                .is_spent(OutPoint::new(funding.compute_txid(), 0))
+                .await
+                .unwrap()
+        );
+    }
 }
@@ -0,0 +1,102 @@
+//! Height of the chain tip, cached between lookups.
+//!
+//! Counting the confirmations of a transaction needs the tip height, which moves
+//! once a block, so the backends keep the last height they fetched for `max_age`
+//! rather than asking for it on every call.
+
+use crate::blockchain::Result;
+use crate::time::Instant;
+use std::future::Future;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+/// How long a fetched tip height is used before it is fetched again
+pub const DEFAULT_TIP_MAX_AGE: Duration = Duration::from_secs(30);
+
+/// Last tip height a backend fetched, and when.
+#[derive(Debug)]
+pub struct TipCache {
+    max_age: Duration,
+    cached: Mutex<Option<(u32, Instant)>>,
+}
+
+impl Default for TipCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_TIP_MAX_AGE)
+    }
+}
+
+impl TipCache {
+    pub fn new(max_age: Duration) -> Self {
+        Self {
+            max_age,
+            cached: Mutex::new(None),
+        }
+    }
+
+    /// The cached height if it is younger than `max_age`, else the height `fetch`
+    /// returns, cached from now on.
+    ///
+    /// # Errors
+    /// Whatever `fetch` fails with; the stale height, if any, is kept
+    pub async fn height<F, Fut>(&self, fetch: F) -> Result<u32>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<u32>>,
+    {
+        if let Some((height, fetched_at)) = *self.lock()
+            && fetched_at.elapsed() < self.max_age
+        {
+            return Ok(height);
+        }
+        let height = fetch().await?;
+        *self.lock() = Some((height, Instant::now()));
+        Ok(height)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Option<(u32, Instant)>> {
+        // A height and its fetch time are written together, so whatever panicked
+        // while holding the lock left them consistent
+        self.cached.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::BlockchainError;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    #[tokio::test(start_paused = true)]
+    async fn test_height_is_fetched_again_once_stale() {
+        let tip = TipCache::new(Duration::from_secs(10));
+        let fetches = AtomicU32::new(0);
+        let fetch = || async { Ok(800_000 + fetches.fetch_add(1, Ordering::SeqCst)) };
+
+        assert_eq!(tip.height(fetch).await.unwrap(), 800_000);
+        tokio::time::advance(Duration::from_secs(9)).await;
+        assert_eq!(tip.height(fetch).await.unwrap(), 800_000);
+        tokio::time::advance(Duration::from_secs(1)).await;
+        assert_eq!(tip.height(fetch).await.unwrap(), 800_001);
+        assert_eq!(fetches.load(Ordering::SeqCst), 2);
+
+        // A failed refresh is not cached
+        tokio::time::advance(Duration::from_secs(10)).await;
+        let failing = || async { Err(BlockchainError::RateLimited { retry_after: None }) };
+        assert!(tip.height(failing).await.is_err());
+        assert_eq!(tip.height(fetch).await.unwrap(), 800_002);
+    }
+
+    #[tokio::test]
+    async fn test_height_survives_a_poisoned_lock() {
+        let tip = TipCache::default();
+        let _ = std::panic::catch_unwind(|| {
+            let _guard = tip.cached.lock().unwrap();
+            panic!("poisoned");
+        });
+        assert!(tip.cached.is_poisoned());
+
+        assert_eq!(tip.height(|| async { Ok(800_000) }).await.unwrap(), 800_000);
+        assert_eq!(tip.height(|| async { Ok(800_001) }).await.unwrap(), 800_000);
+    }
+}
@@ -0,0 +1,224 @@
+//! Synchronous wrappers over the async API, for build scripts and simple tools that
+//! run no tokio runtime of their own.
+//!
+//! ```ignore
+//! use pathfinder::blocking::BlockingDataSource;
+//! use pathfinder::blockchain::EsploraClient;
+//! use pathfinder::tracer::TraceConfig;
+//!
+//! let source = BlockingDataSource::new(EsploraClient::new("https://mempool.space/api"));
+//! let tx = source.get_transaction(txid)?;
+//! let outcome = source.trace_forward(OutPoint::new(txid, 0), &TraceConfig::default())?;
+//! ```
+
+use crate::blockchain::{self, BlockchainDataSource, TxMetadata, TxStatus};
+use crate::tracer::{
+    self, ReplacementStatus, TraceCheckpoint, TraceConfig, TraceOutcome, TraceReport, Tracer,
+};
+use bitcoin::{Address, Block, BlockHash, OutPoint, Transaction, Txid, block::Header};
+use std::future::Future;
+use std::sync::OnceLock;
+use tokio::runtime::{Builder, Handle, Runtime};
+
+/// Any data source, and a tracer over it, behind plain synchronous methods.
+///
+/// Each call runs its async counterpart to completion on a current-thread runtime
+/// the wrapper starts on first use and owns. Calling one from within an async
+/// runtime panics, as blocking the runtime's thread on a future could deadlock it:
+/// await the wrapped source there instead, or call from `spawn_blocking`.
+pub struct BlockingDataSource<D> {
+    tracer: Tracer<D>,
+    runtime: OnceLock<Runtime>,
+}
+
+impl<D: BlockchainDataSource + Sync> BlockingDataSource<D> {
+    pub fn new(source: D) -> Self {
+        Self {
+            tracer: Tracer::new(source),
+            runtime: OnceLock::new(),
+        }
+    }
+
+    /// The wrapped source
+    pub fn source(&self) -> &D {
+        self.tracer.source()
+    }
+
+    /// Runs `future` to completion on the owned runtime
+    ///
+    /// # Panics
+    /// If called from within an async runtime
+    fn block_on<F: Future>(&self, future: F) -> F::Output {
+        if Handle::try_current().is_ok() {
+            panic!(
+                "BlockingDataSource called from within an async runtime, where it would \
+                 block the runtime's thread: await the async source instead, or call it \
+                 from tokio::task::spawn_blocking"
+            );
+        }
+        self.runtime
+            .get_or_init(|| {
+                Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                    .expect("tokio runtime for blocking calls")
+            })
+            .block_on(future)
+    }
+
+    /// `BlockchainDataSource::get_transaction`, blocking
+    pub fn get_transaction(&self, txid: Txid) -> blockchain::Result<Transaction> {
+        self.block_on(self.source().get_transaction(txid))
+    }
+
+    /// `BlockchainDataSource::get_spending_transaction`, blocking
+    pub fn get_spending_transaction(
+        &self,
+        outpoint: OutPoint,
+    ) -> blockchain::Result<Option<Transaction>> {
+        self.block_on(self.source().get_spending_transaction(outpoint))
+    }
+
+    /// `BlockchainDataSource::get_address_transactions`, blocking
+    pub fn get_address_transactions(
+        &self,
+        address: Address,
+    ) -> blockchain::Result<Vec<Transaction>> {
+        self.block_on(self.source().get_address_transactions(address))
+    }
+
+    /// `BlockchainDataSource::get_address_utxos`, blocking
+    pub fn get_address_utxos(&self, address: Address) -> blockchain::Result<Vec<OutPoint>> {
+        self.block_on(self.source().get_address_utxos(address))
+    }
+
+    /// `BlockchainDataSource::get_transactions_batch`, blocking
+    pub fn get_transactions_batch(
+        &self,
+        txids: &[Txid],
+    ) -> blockchain::Result<Vec<Option<Transaction>>> {
+        self.block_on(self.source().get_transactions_batch(txids))
+    }
+
+    /// `BlockchainDataSource::get_spending_transactions_batch`, blocking
+    pub fn get_spending_transactions_batch(
+        &self,
+        outpoints: &[OutPoint],
+    ) -> blockchain::Result<Vec<Option<Transaction>>> {
+        self.block_on(self.source().get_spending_transactions_batch(outpoints))
+    }
+
+    /// `BlockchainDataSource::get_block_raw`, blocking
+    pub fn get_block_raw(&self, block_hash: BlockHash) -> blockchain::Result<Block> {
+        self.block_on(self.source().get_block_raw(block_hash))
+    }
+
+    /// `BlockchainDataSource::get_transaction_status`, blocking
+    pub fn get_transaction_status(&self, txid: Txid) -> blockchain::Result<TxStatus> {
+        self.block_on(self.source().get_transaction_status(txid))
+    }
+
+    /// `BlockchainDataSource::get_block_header`, blocking
+    pub fn get_block_header(&self, block_hash: BlockHash) -> blockchain::Result<Header> {
+        self.block_on(self.source().get_block_header(block_hash))
+    }
+
+    /// `BlockchainDataSource::get_transaction_with_metadata`, blocking
+    pub fn get_transaction_with_metadata(
+        &self,
+        txid: Txid,
+    ) -> blockchain::Result<(Transaction, TxMetadata)> {
+        self.block_on(self.source().get_transaction_with_metadata(txid))
+    }
+
+    /// `Tracer::trace_forward`, blocking
+    pub fn trace_forward(
+        &self,
+        root: OutPoint,
+        config: &TraceConfig,
+    ) -> tracer::Result<TraceOutcome> {
+        self.block_on(self.tracer.trace_forward(root, config))
+    }
+
+    /// `Tracer::trace_forward_with_report`, blocking
+    pub fn trace_forward_with_report(
+        &self,
+        root: OutPoint,
+        config: &TraceConfig,
+    ) -> tracer::Result<TraceReport> {
+        self.block_on(self.tracer.trace_forward_with_report(root, config))
+    }
+
+    /// `Tracer::trace_backward`, blocking
+    pub fn trace_backward(&self, txid: Txid, config: &TraceConfig) -> tracer::Result<TraceOutcome> {
+        self.block_on(self.tracer.trace_backward(txid, config))
+    }
+
+    /// `Tracer::trace_backward_with_report`, blocking
+    pub fn trace_backward_with_report(
+        &self,
+        txid: Txid,
+        config: &TraceConfig,
+    ) -> tracer::Result<TraceReport> {
+        self.block_on(self.tracer.trace_backward_with_report(txid, config))
+    }
+
+    /// `Tracer::resume`, blocking
+    pub fn resume(
+        &self,
+        checkpoint: TraceCheckpoint,
+        config: &TraceConfig,
+        force: bool,
+    ) -> tracer::Result<TraceOutcome> {
+        self.block_on(self.tracer.resume(checkpoint, config, force))
+    }
+
+    /// `Tracer::check_replacement`, blocking
+    pub fn check_replacement(&self, txid: Txid) -> tracer::Result<ReplacementStatus> {
+        self.block_on(self.tracer.check_replacement(txid))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{ChainBuilder, Method, MockDataSource};
+    use bitcoin::hashes::Hash;
+    use std::time::Duration;
+
+    #[test]
+    fn test_blocking_calls_run_without_a_runtime() {
+        let mut chain = ChainBuilder::new();
+        let funding = chain.fund(&[1_000_000]);
+        let root = OutPoint::new(funding.compute_txid(), 0);
+        let hops = chain.peel_chain(root, 3, 10_000, 1_000);
+        let mut mock = chain.source();
+        // Timers work on the owned runtime
+        mock.set_latency(Method::GetSpendingTransaction, Duration::from_millis(1));
+        let source = BlockingDataSource::new(mock);
+
+        assert_eq!(
+            source.get_transaction(funding.compute_txid()).unwrap(),
+            funding
+        );
+        assert_eq!(
+            source.get_spending_transaction(root).unwrap(),
+            Some(hops[0].clone())
+        );
+        let outcome = source.trace_forward(root, &TraceConfig::default()).unwrap();
+        assert_eq!(outcome.graph().len(), 1 + hops.len());
+        let calls = source.source().calls();
+        let report = source
+            .trace_forward_with_report(root, &TraceConfig::default().max_depth(1))
+            .unwrap();
+        assert_eq!(report.graph().len(), 2);
+        assert_eq!(source.source().calls(), calls + report.requests);
+    }
+
+    #[tokio::test]
+    #[should_panic(expected = "from within an async runtime")]
+    async fn test_blocking_calls_refuse_to_block_a_runtime() {
+        let source = BlockingDataSource::new(MockDataSource::default());
+        let _ = source.get_transaction(Txid::all_zeros());
+    }
+}
@@ -0,0 +1,991 @@
+//! Command line interface: looks up transactions, spenders and address histories,
+//! and runs traces, against an Esplora API or a bitcoind node.
+//!
+//! Failures exit with a code scripts can branch on (see `CliError::exit_code` and
+//! `usage_exit_code`).
+
+mod args;
+mod batch;
+mod credentials;
+mod progress;
+mod watch;
+
+use bitcoin::address::NetworkUnchecked;
+use bitcoin::{Address, Denomination, Network, OutPoint, Transaction, Txid};
+use clap::{ArgAction, Args, CommandFactory, Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
+use futures::{Stream, StreamExt};
+use pathfinder::blockchain::{
+    BackendConfig, BlockchainDataSource, BlockchainError, CachingDataSource, DataSourceBuilder,
+};
+use pathfinder::tracer::{
+    CancelToken, DotOptions, TablePrinter, TraceCheckpoint, TraceConfig, TraceGraph, TraceItem,
+    TraceSummary, Tracer, TracerError,
+};
+use progress::Progress;
+use serde_json::json;
+use std::fs::File;
+use std::io::{self, IsTerminal, LineWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+use tracing_subscriber::EnvFilter;
+use watch::{Target, WatchOptions};
+
+/// Esplora API queried unless another backend is chosen
+pub const DEFAULT_ESPLORA: &str = "https://mempool.space/api";
+
+/// Requests per second made to Esplora at most, once a burst of as many is spent
+pub const ESPLORA_REQUESTS_PER_SECOND: u32 = 10;
+
+/// Variable holding the `EnvFilter` directive of the logs, overridden by `-v` and `-q`
+pub const LOG_ENV: &str = "PATHFINDER_LOG";
+
+/// A failure, the arguments aside
+pub const EXIT_FAILURE: i32 = 1;
+/// The arguments did not parse (clap's own exit code)
+pub const EXIT_USAGE: i32 = 2;
+/// A txid, outpoint or address argument is not valid
+pub const EXIT_INVALID_INPUT: i32 = 3;
+/// The backend failed, or cannot answer
+pub const EXIT_BACKEND: i32 = 4;
+/// A trace was stopped by Ctrl-C, the work done saved (128 + SIGINT, as shells report)
+pub const EXIT_INTERRUPTED: i32 = 130;
+
+/// A Bitcoin UTXO tracing tool
+#[derive(Debug, Parser)]
+#[command(name = "pathfinder", version, about, after_help = credentials::RESOLUTION)]
+pub struct Cli {
+    #[command(flatten)]
+    pub backend: BackendArgs,
+    #[command(flatten)]
+    pub log: LogArgs,
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+/// Where the data comes from and how long it is cached.
+///
+/// Esplora is queried unless bitcoind is, through `--rpc` or the environment, with
+/// credentials resolved as `credentials::RESOLUTION` describes.
+#[derive(Debug, Args)]
+pub struct BackendArgs {
+    /// Esplora API to query [default: https://mempool.space/api]
+    #[arg(long, global = true, value_name = "URL", conflicts_with = "rpc")]
+    pub esplora: Option<String>,
+    /// bitcoind JSON-RPC endpoint to query instead of Esplora
+    #[arg(long, global = true, value_name = "URL")]
+    pub rpc: Option<String>,
+    /// bitcoind RPC user
+    #[arg(long, global = true)]
+    pub rpc_user: Option<String>,
+    /// bitcoind RPC password
+    #[arg(
+        long,
+        global = true,
+        requires = "rpc_user",
+        conflicts_with = "rpc_cookie"
+    )]
+    pub rpc_pass: Option<String>,
+    /// bitcoind cookie file, in place of a user and password
+    #[arg(long, global = true, value_name = "PATH")]
+    pub rpc_cookie: Option<PathBuf>,
+    /// Name of the RPC password stored in the OS keyring by `auth set`
+    #[arg(long, global = true, value_name = "NAME")]
+    pub rpc_name: Option<String>,
+    /// Seconds lookups are cached for
+    #[arg(long, global = true, value_name = "SECONDS", default_value_t = 300)]
+    pub cache_ttl: u64,
+    /// Network addresses are read and written for
+    #[arg(long, global = true, default_value_t = Network::Bitcoin)]
+    pub network: Network,
+}
+
+/// How much is logged to stderr.
+///
+/// `-v` and `-q` override `PATHFINDER_LOG`, an `EnvFilter` directive such as
+/// `pathfinder::blockchain=debug`; without either, warnings are logged.
+#[derive(Debug, Args)]
+pub struct LogArgs {
+    /// Log more, overriding PATHFINDER_LOG: -v for the traces run, -vv for every hop,
+    /// request and cache lookup
+    #[arg(short, long, global = true, action = ArgAction::Count, conflicts_with = "quiet")]
+    pub verbose: u8,
+    /// Log errors only, and show no trace progress
+    #[arg(short, long, global = true)]
+    pub quiet: bool,
+}
+
+impl LogArgs {
+    /// Filter directive of these flags, else of `env` (`PATHFINDER_LOG`)
+    pub fn filter(&self, env: Option<String>) -> String {
+        let level = match (self.quiet, self.verbose) {
+            (true, _) => return "error".to_string(),
+            (false, 0) => return env.unwrap_or_else(|| "warn".to_string()),
+            (false, 1) => "info",
+            (false, 2) => "debug",
+            (false, _) => "trace",
+        };
+        format!("warn,pathfinder={}", level)
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Print a transaction: its status, inputs and outputs
+    Tx {
+        /// Transaction id
+        #[arg(value_parser = args::txid)]
+        txid: Txid,
+    },
+    /// Print the transaction spending an output, or "unspent"
+    Spend {
+        /// Output, as <txid>:<vout>, <txid>#<vout> or "<txid> <vout>"
+        #[arg(value_parser = args::outpoint)]
+        outpoint: OutPoint,
+    },
+    /// List the transactions paying or spending from an address
+    Address {
+        /// Address, on the network of `--network`
+        #[arg(value_parser = args::address)]
+        address: Address<NetworkUnchecked>,
+    },
+    /// Follow an output forward through the transactions spending it
+    Trace {
+        /// Output to start from, as <txid>:<vout>, <txid>#<vout> or "<txid> <vout>"
+        #[arg(
+            value_parser = args::outpoint,
+            required_unless_present_any = ["input", "resume"],
+            conflicts_with_all = ["input", "resume"]
+        )]
+        outpoint: Option<OutPoint>,
+        /// File of outputs, as <txid>:<vout>, and addresses whose unspent outputs to
+        /// start from, one per line ('#' starts a comment), traced into one graph
+        #[arg(long, value_name = "FILE")]
+        input: Option<PathBuf>,
+        /// CSV file to write a row per seed of `--input` to: what it resolved to, the
+        /// transactions reached from it, or why it failed
+        #[arg(long, value_name = "FILE", requires = "input")]
+        seed_summary: Option<PathBuf>,
+        /// Checkpoint of an interrupted trace to continue, under the --depth,
+        /// --max-requests and --network it ran with
+        #[arg(long, value_name = "FILE", conflicts_with = "input")]
+        resume: Option<PathBuf>,
+        /// Hops to follow at most
+        #[arg(long)]
+        depth: Option<usize>,
+        /// Requests to make to the backend at most, cached lookups left out
+        #[arg(long)]
+        max_requests: Option<usize>,
+        /// How the traced graph is written
+        #[arg(long, value_enum, default_value_t = Format::Summary)]
+        format: Format,
+        /// File to write to instead of stdout
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<PathBuf>,
+    },
+    /// Fetch the transactions of the outputs and addresses of a file, and their
+    /// spenders, through the batch lookups of the backend, and print what was found
+    Warm {
+        /// File of outputs, as <txid>:<vout>, and addresses whose unspent outputs to
+        /// warm, one per line ('#' starts a comment), as `trace --input` reads them
+        file: PathBuf,
+    },
+    /// Print the completion script of a shell, e.g. for bash:
+    /// pathfinder completions bash > /etc/bash_completion.d/pathfinder
+    Completions {
+        #[arg(value_enum)]
+        shell: Shell,
+    },
+    /// Store or delete bitcoind RPC passwords in the OS keyring
+    #[cfg(feature = "keyring")]
+    Auth {
+        #[command(subcommand)]
+        action: AuthAction,
+    },
+    /// Poll outputs until they are spent, and addresses for spends from them
+    Watch {
+        /// Outputs, as <txid>:<vout>, and addresses to watch
+        targets: Vec<String>,
+        /// File of more targets, one per line ('#' starts a comment)
+        #[arg(long, value_name = "FILE", required_unless_present = "targets")]
+        targets_file: Option<PathBuf>,
+        /// Time between two polls of a target, e.g. 30s, 5m or 500ms
+        #[arg(long, value_name = "DURATION", default_value = "30s", value_parser = parse_duration)]
+        interval: Duration,
+        /// Stop watching after this long
+        #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+        timeout: Option<Duration>,
+        /// JSON trace file to extend with the spends of its outputs, saved after each
+        #[arg(long, value_name = "FILE")]
+        extend_trace: Option<PathBuf>,
+    },
+}
+
+/// Actions of the `auth` command
+#[cfg(feature = "keyring")]
+#[derive(Debug, Subcommand)]
+pub enum AuthAction {
+    /// Store the password read from stdin under NAME, for `--rpc-name NAME`
+    Set { name: String },
+    /// Delete the password stored under NAME
+    Delete { name: String },
+}
+
+/// Output formats of the `trace` command
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// The versioned JSON schema of `TraceGraph::to_json`
+    Json,
+    /// One JSON object per node and edge, written as the trace finds them, then a
+    /// summary object
+    Jsonl,
+    /// Graphviz DOT
+    Dot,
+    /// One row per output
+    Csv,
+    /// The one-screen `TraceSummary`
+    Summary,
+}
+
+#[derive(Error, Debug)]
+pub enum CliError {
+    #[error("{0}")]
+    InvalidInput(String),
+    #[error("{0}")]
+    Backend(#[from] BlockchainError),
+    #[error("{0}")]
+    Trace(TracerError),
+    /// The OS keyring failed
+    #[cfg(feature = "keyring")]
+    #[error("Keyring: {0}")]
+    Keyring(String),
+    /// A trace was stopped by Ctrl-C; `saved` tells where its work went, `resume` how
+    /// to continue it
+    #[error("Trace interrupted, {saved}")]
+    Interrupted {
+        saved: String,
+        resume: Option<String>,
+    },
+    /// `what` failed doing, e.g. "Failed to write out.json"
+    #[error("{what}: {source}")]
+    Io {
+        what: String,
+        #[source]
+        source: io::Error,
+    },
+}
+
+impl CliError {
+    /// Code the process exits with: `EXIT_INVALID_INPUT` for bad arguments,
+    /// `EXIT_BACKEND` for backend failures, `EXIT_INTERRUPTED` for an interrupted trace,
+    /// `EXIT_FAILURE` for anything else
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            CliError::InvalidInput(_) => EXIT_INVALID_INPUT,
+            CliError::Backend(_) => EXIT_BACKEND,
+            CliError::Interrupted { .. } => EXIT_INTERRUPTED,
+            CliError::Trace(_) | CliError::Io { .. } => EXIT_FAILURE,
+            #[cfg(feature = "keyring")]
+            CliError::Keyring(_) => EXIT_FAILURE,
+        }
+    }
+
+    /// What to do about the failure, meant to be shown as is
+    pub fn hint(&self) -> Option<&str> {
+        match self {
+            CliError::Backend(error) => error.hint(),
+            CliError::Interrupted { resume, .. } => resume.as_deref(),
+            _ => None,
+        }
+    }
+}
+
+impl From<TracerError> for CliError {
+    fn from(error: TracerError) -> Self {
+        match error {
+            TracerError::Source(error) => CliError::Backend(error),
+            TracerError::InvalidInput(detail) => CliError::InvalidInput(detail),
+            error => CliError::Trace(error),
+        }
+    }
+}
+
+/// Code the process exits with when the arguments do not parse: `EXIT_INVALID_INPUT`
+/// for a txid, outpoint or address `args` rejects, `EXIT_USAGE` for anything else, and
+/// 0 for `--help` and `--version`
+pub fn usage_exit_code(error: &clap::Error) -> i32 {
+    let invalid =
+        std::error::Error::source(error).is_some_and(|source| source.is::<args::InvalidArgument>());
+    match (error.use_stderr(), invalid) {
+        (false, _) => 0,
+        (true, true) => EXIT_INVALID_INPUT,
+        (true, false) => EXIT_USAGE,
+    }
+}
+
+/// `Io` error of `what`
+fn io_error(what: impl Into<String>) -> impl FnOnce(io::Error) -> CliError {
+    let what = what.into();
+    move |source| CliError::Io { what, source }
+}
+
+/// Prints `text` to stdout as is
+fn print(text: &str) -> Result<(), CliError> {
+    io::stdout()
+        .lock()
+        .write_all(text.as_bytes())
+        .map_err(io_error("Failed to write to stdout"))
+}
+
+/// Logs the events of the crate to stderr, as `args` and `PATHFINDER_LOG` filter
+/// them. A `PATHFINDER_LOG` that does not parse is reported, and warnings logged.
+pub fn init_logging(args: &LogArgs) {
+    let directive = args.filter(std::env::var(LOG_ENV).ok());
+    let filter = EnvFilter::try_new(&directive).unwrap_or_else(|error| {
+        eprintln!("Ignoring {}={:?}: {}", LOG_ENV, directive, error);
+        EnvFilter::new("warn")
+    });
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(io::stderr)
+        .with_ansi(io::stderr().is_terminal())
+        .init();
+}
+
+/// Runs the command of `cli` against the backend it chooses, behind a cache.
+///
+/// # Errors
+/// - `InvalidInput` - an argument or the RPC credentials are not valid
+/// - `Backend` - the backend failed
+/// - `Trace` - the trace failed other than through the backend
+/// - `Keyring` - the OS keyring failed (keyring feature)
+/// - `Io` - the output or cookie file could not be written or read
+pub async fn run(cli: Cli) -> Result<(), CliError> {
+    #[cfg(feature = "keyring")]
+    let store = Some(&credentials::KeyringStore as &dyn credentials::SecretStore);
+    #[cfg(not(feature = "keyring"))]
+    let store = None;
+    #[cfg(feature = "keyring")]
+    if let Command::Auth { action } = &cli.command {
+        return auth(action, &credentials::KeyringStore);
+    }
+    if let Command::Completions { shell } = cli.command {
+        let mut script = Vec::new();
+        clap_complete::generate(shell, &mut Cli::command(), "pathfinder", &mut script);
+        return print(&String::from_utf8_lossy(&script));
+    }
+
+    let backend = cli.backend;
+    let ttl = Duration::from_secs(backend.cache_ttl);
+    let network = backend.network;
+    let quiet = cli.log.quiet;
+    let config = match credentials::resolve(&backend, |name| std::env::var(name).ok(), store)? {
+        Some(rpc) => BackendConfig::Rpc {
+            url: rpc.url,
+            user: rpc.user,
+            pass: rpc.pass,
+        },
+        None => BackendConfig::Esplora {
+            url: backend.esplora.unwrap_or(DEFAULT_ESPLORA.to_string()),
+            requests_per_second: Some(ESPLORA_REQUESTS_PER_SECOND),
+        },
+    };
+    let source = CachingDataSource::new(DataSourceBuilder::new(config).build()?, ttl);
+    execute(cli.command, source, network, quiet).await
+}
+
+/// Stores or deletes an RPC password in `store`, the password read from stdin
+#[cfg(feature = "keyring")]
+fn auth(action: &AuthAction, store: &dyn credentials::SecretStore) -> Result<(), CliError> {
+    match action {
+        AuthAction::Set { name } => {
+            if io::stdin().is_terminal() {
+                eprint!("Password for {}: ", name);
+            }
+            let mut pass = String::new();
+            io::stdin()
+                .read_line(&mut pass)
+                .map_err(io_error("Failed to read the password from stdin"))?;
+            let pass = pass.trim_end_matches(['\r', '\n']);
+            if pass.is_empty() {
+                return Err(CliError::InvalidInput("Empty password".to_string()));
+            }
+            store.set(name, pass)
+        }
+        AuthAction::Delete { name } => match store.delete(name)? {
+            true => Ok(()),
+            false => Err(CliError::InvalidInput(format!(
+                "No password stored for {}",
+                name
+            ))),
+        },
+    }
+}
+
+/// Runs `command` against `source`; `quiet` hides the progress of a trace
+async fn execute<C>(
+    command: Command,
+    source: CachingDataSource<C>,
+    network: Network,
+    quiet: bool,
+) -> Result<(), CliError>
+where
+    C: BlockchainDataSource + Send + Sync,
+{
+    match command {
+        Command::Tx { txid } => {
+            let (tx, metadata) = source.get_transaction_with_metadata(txid).await?;
+            let status = match (metadata.confirmations, metadata.block_height) {
+                (Some(0), _) => "unconfirmed".to_string(),
+                (Some(confirmations), Some(height)) => {
+                    format!("{} confirmations, block {}", confirmations, height)
+                }
+                _ => "confirmed".to_string(),
+            };
+            let fee = metadata
+                .fee
+                .map_or("unknown".to_string(), |fee| format!("{} sat", fee.to_sat()));
+            print(&format!(
+                "Transaction {}\nStatus: {}\nFee: {}, size: {} vB\n\n{}\n{}",
+                txid,
+                status,
+                fee,
+                metadata.vsize,
+                inputs(&tx),
+                outputs(&tx, network)
+            ))
+        }
+        Command::Spend { outpoint } => match source.get_spending_transaction(outpoint).await? {
+            Some(spender) => print(&format!("spent by {}\n", spender.compute_txid())),
+            None => print("unspent\n"),
+        },
+        Command::Address { address } => {
+            let address = require_network(address, network)?;
+            let script = address.script_pubkey();
+            let history = source.get_address_transactions(address).await?;
+            let mut table = TablePrinter::new(&["Txid", "Received", "Outputs"])
+                .right_aligned(1)
+                .right_aligned(2);
+            for tx in &history {
+                let received = tx
+                    .output
+                    .iter()
+                    .filter(|output| output.script_pubkey == script)
+                    .map(|output| output.value)
+                    .sum();
+                table.row([
+                    tx.compute_txid().to_string(),
+                    btc(received),
+                    tx.output.len().to_string(),
+                ]);
+            }
+            print(&table.to_string())
+        }
+        Command::Trace {
+            outpoint,
+            input,
+            seed_summary,
+            resume,
+            depth,
+            max_requests,
+            format,
+            output,
+        } => {
+            let cancel = CancelToken::new();
+            let mut config = TraceConfig::default()
+                .network(network)
+                .cancel(cancel.clone());
+            // The flags shaping the graph, for resuming it
+            let mut flags = String::new();
+            if let Some(depth) = depth {
+                config = config.max_depth(depth);
+                flags += &format!(" --depth {}", depth);
+            }
+            if let Some(max_requests) = max_requests {
+                config = config.max_requests(max_requests);
+                flags += &format!(" --max-requests {}", max_requests);
+            }
+            if network != Network::Bitcoin {
+                flags += &format!(" --network {}", network);
+            }
+            let interrupt = tokio::spawn(interrupt(cancel.clone()));
+            let result = match input {
+                Some(input) => {
+                    trace_batch(
+                        &input,
+                        seed_summary,
+                        &source,
+                        config,
+                        network,
+                        format,
+                        output,
+                    )
+                    .await
+                }
+                None => {
+                    let start = match &resume {
+                        Some(path) => Start::Resume(Box::new(TraceCheckpoint::load(path)?)),
+                        None => Start::Outpoint(
+                            outpoint
+                                .expect("clap requires an outpoint without --input or --resume"),
+                        ),
+                    };
+                    let checkpoint = match resume {
+                        Some(path) => path,
+                        None => {
+                            let path = checkpoint_path(output.as_deref());
+                            // Left by an earlier trace to the same output, which this one
+                            // replaces
+                            let _ = std::fs::remove_file(&path);
+                            path
+                        }
+                    };
+                    let config = config.checkpoint_on_cancel(&checkpoint);
+                    match trace_one(start, &source, &config, quiet, format, output.as_deref()).await
+                    {
+                        Ok(true) => Err(interrupted(output.as_deref(), Some(&checkpoint), &flags)),
+                        result => result.map(|_| ()),
+                    }
+                }
+            };
+            interrupt.abort();
+            result
+        }
+        Command::Warm { file } => warm(&file, &source, network).await,
+        #[cfg(feature = "keyring")]
+        Command::Auth { .. } => unreachable!("auth needs no backend, run handles it"),
+        Command::Completions { .. } => {
+            unreachable!("completions need no backend, run handles them")
+        }
+        Command::Watch {
+            targets,
+            targets_file,
+            interval,
+            timeout,
+            extend_trace,
+        } => {
+            let mut targets = targets;
+            if let Some(path) = targets_file {
+                targets.extend(watch::read_targets(&path)?);
+            }
+            let targets = targets
+                .iter()
+                .map(|target| Target::parse(target, network))
+                .collect::<Result<Vec<_>, _>>()?;
+            let options = WatchOptions {
+                interval,
+                timeout,
+                extend_trace,
+            };
+            watch::watch(targets, &source, network, &options, io::stdout()).await
+        }
+    }
+}
+
+/// Traces the seeds listed in `input` into one graph written as `trace` writes it,
+/// after reporting the lines that do not parse and before reporting the seeds that
+/// could not be traced; `seed_summary` gets a CSV row per seed.
+async fn trace_batch<C>(
+    input: &Path,
+    seed_summary: Option<PathBuf>,
+    source: &CachingDataSource<C>,
+    config: TraceConfig,
+    network: Network,
+    format: Format,
+    output: Option<PathBuf>,
+) -> Result<(), CliError>
+where
+    C: BlockchainDataSource + Send + Sync,
+{
+    if format == Format::Jsonl {
+        return Err(CliError::InvalidInput(
+            "--format jsonl streams the trace of one outpoint, not of --input".to_string(),
+        ));
+    }
+    let mut seeds = batch::read_seeds(input, network)?;
+    let report = |seed: &batch::Seed| {
+        if let Some(error) = &seed.error {
+            eprintln!("{}:{}: {}", input.display(), seed.line, error);
+        }
+    };
+    seeds
+        .iter()
+        .filter(|seed| seed.is_malformed())
+        .for_each(report);
+
+    let outcome = batch::trace(&mut seeds, source, config).await?;
+    seeds
+        .iter()
+        .filter(|seed| !seed.is_malformed())
+        .for_each(report);
+    let failed = seeds.iter().filter(|seed| seed.error.is_some()).count();
+    eprintln!("Traced {} of {} seeds", seeds.len() - failed, seeds.len());
+    let cancelled = outcome.is_cancelled();
+    let graph = outcome.into_graph();
+    if let Some(path) = seed_summary {
+        let what = format!("Failed to write {}", path.display());
+        let file = File::create(&path).map_err(io_error(what.as_str()))?;
+        batch::write_summary(&seeds, &graph, file, &what)?;
+    }
+    write_graph(&graph, format, output.as_deref())?;
+    match cancelled {
+        // Batch traces are not checkpointed: the budget of their trace depends on the
+        // seeds' lookups
+        true => Err(interrupted(output.as_deref(), None, "")),
+        false => Ok(()),
+    }
+}
+
+/// Warms `source` with the seeds listed in `file`, reporting the lines that do not
+/// parse and the seeds that do not resolve, then prints what was fetched
+async fn warm<C>(
+    file: &Path,
+    source: &CachingDataSource<C>,
+    network: Network,
+) -> Result<(), CliError>
+where
+    C: BlockchainDataSource + Send + Sync,
+{
+    let mut seeds = batch::read_seeds(file, network)?;
+    let warmed = batch::warm(&mut seeds, source).await;
+    for seed in &seeds {
+        if let Some(error) = &seed.error {
+            eprintln!("{}:{}: {}", file.display(), seed.line, error);
+        }
+    }
+    let warmed = warmed?;
+    let failed = seeds.iter().filter(|seed| seed.error.is_some()).count();
+    eprintln!("Warmed {} of {} seeds", seeds.len() - failed, seeds.len());
+    let (txs, spenders) = (warmed.transactions, warmed.spenders);
+    print(&format!(
+        "Transactions: {} fetched, {} already cached, {} not found, {} failed\n\
+         Spenders: {} fetched, {} already cached, {} unspent, {} failed\n",
+        txs.fetched,
+        txs.already_cached,
+        txs.not_found,
+        txs.failed,
+        spenders.fetched,
+        spenders.already_cached,
+        spenders.not_found,
+        spenders.failed
+    ))
+}
+
+/// Where a trace starts from
+enum Start {
+    Outpoint(OutPoint),
+    /// Where an interrupted trace stopped
+    Resume(Box<TraceCheckpoint>),
+}
+
+/// Traces forward from `start` under `config`, writing the graph as `trace` writes it
+/// to `output`, or stdout, with the progress of a new trace shown unless `quiet`. True
+/// if the trace was cancelled, the graph written then being partial.
+async fn trace_one<C>(
+    start: Start,
+    source: &CachingDataSource<C>,
+    config: &TraceConfig,
+    quiet: bool,
+    format: Format,
+    output: Option<&Path>,
+) -> Result<bool, CliError>
+where
+    C: BlockchainDataSource + Send + Sync,
+{
+    let tracer = Tracer::new(source);
+    let cancel = config.cancel.clone().unwrap_or_default();
+    if format == Format::Jsonl {
+        let Start::Outpoint(outpoint) = start else {
+            return Err(CliError::InvalidInput(
+                "--format jsonl streams a new trace, it cannot continue one".to_string(),
+            ));
+        };
+        let items = tracer.trace_forward_streaming(outpoint, config);
+        match output {
+            Some(path) => {
+                let what = format!("Failed to write {}", path.display());
+                let file = File::create(path).map_err(io_error(what.as_str()))?;
+                write_lines(items, &cancel, LineWriter::new(file), &what).await?
+            }
+            // Line buffered already
+            None => write_lines(items, &cancel, io::stdout(), "Failed to write to stdout").await?,
+        };
+        return Ok(cancel.is_cancelled());
+    }
+
+    let outcome = match start {
+        Start::Outpoint(outpoint) => {
+            let max_requests = config.max_requests;
+            let hit_rate = || source.stats().hit_rate();
+            let mut progress = match (quiet, io::stdout().is_terminal()) {
+                (true, _) => Progress::log(io::sink(), max_requests, hit_rate),
+                (false, true) => Progress::bar(max_requests, hit_rate),
+                (false, false) => Progress::log(io::stderr(), max_requests, hit_rate),
+            };
+            let (trace, events) = tracer.trace_forward_with_events(outpoint, config);
+            let (outcome, ()) = tokio::join!(trace, progress.follow(events));
+            progress.finish();
+            outcome?
+        }
+        Start::Resume(checkpoint) => tracer.resume(*checkpoint, config, false).await?,
+    };
+    let cancelled = outcome.is_cancelled();
+    write_graph(&outcome.into_graph(), format, output)?;
+    Ok(cancelled)
+}
+
+/// Cancels `cancel` on Ctrl-C, the trace stopping where it is to save its work; a
+/// second Ctrl-C exits at once
+async fn interrupt(cancel: CancelToken) {
+    if tokio::signal::ctrl_c().await.is_err() {
+        return;
+    }
+    cancel.cancel();
+    eprintln!("Stopping the trace, Ctrl-C again to quit without saving it");
+    if tokio::signal::ctrl_c().await.is_ok() {
+        std::process::exit(EXIT_INTERRUPTED);
+    }
+}
+
+/// Where a trace writing to `output` saves its checkpoint if interrupted: next to
+/// `output`, else to `pathfinder-partial-<unix time>.json` in the working directory
+fn checkpoint_path(output: Option<&Path>) -> PathBuf {
+    match output {
+        Some(output) => output.with_extension("checkpoint.json"),
+        None => {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since| since.as_secs());
+            PathBuf::from(format!("pathfinder-partial-{}.json", now))
+        }
+    }
+}
+
+/// `Interrupted` error of a trace whose graph was written to `output`, or stdout, and
+/// whose `checkpoint` was saved, unless the trace stopped before it had one: it is then
+/// resumed with `flags`, those shaping the graph
+fn interrupted(output: Option<&Path>, checkpoint: Option<&Path>, flags: &str) -> CliError {
+    let graph = match output {
+        Some(path) => format!("the graph traced so far written to {}", path.display()),
+        None => "the graph traced so far written to stdout".to_string(),
+    };
+    match checkpoint.filter(|path| path.exists()) {
+        Some(path) => CliError::Interrupted {
+            saved: format!("{} and its checkpoint to {}", graph, path.display()),
+            resume: Some(format!(
+                "Resume it with: pathfinder trace --resume {}{}",
+                path.display(),
+                flags
+            )),
+        },
+        None => CliError::Interrupted {
+            saved: graph,
+            resume: None,
+        },
+    }
+}
+
+/// Writes `graph` in `format` to the file `output`, or stdout
+fn write_graph(graph: &TraceGraph, format: Format, output: Option<&Path>) -> Result<(), CliError> {
+    match output {
+        Some(path) => {
+            let what = format!("Failed to write {}", path.display());
+            let file = File::create(path).map_err(io_error(what.as_str()))?;
+            render(graph, format, file, &what)
+        }
+        None => render(
+            graph,
+            format,
+            io::stdout().lock(),
+            "Failed to write to stdout",
+        ),
+    }
+}
+
+/// Writes `graph` in `format` to `writer`, failing with `what`
+fn render<W: Write>(
+    graph: &TraceGraph,
+    format: Format,
+    mut writer: W,
+    what: &str,
+) -> Result<(), CliError> {
+    let text = match format {
+        Format::Json => graph.to_json() + "\n",
+        Format::Jsonl => unreachable!("JSON lines are streamed, not rendered"),
+        Format::Dot => graph.to_dot(&DotOptions::default()),
+        Format::Csv => return graph.to_csv(writer).map_err(CliError::from),
+        Format::Summary => TraceSummary::from_graph(graph).to_string(),
+    };
+    writer.write_all(text.as_bytes()).map_err(io_error(what))
+}
+
+/// Writes the nodes and edges of a streamed trace to `writer` as they come, one JSON
+/// object per line, then a summary line: nodes and edges written, whether
+/// the trace was cancelled, and the error that ended it, if any.
+///
+/// # Errors
+/// The error ending the trace, once the summary is written, or `Io` failing with
+/// `what`.
+async fn write_lines<W: Write>(
+    items: impl Stream<Item = Result<TraceItem, TracerError>>,
+    cancel: &CancelToken,
+    mut writer: W,
+    what: &str,
+) -> Result<(), CliError> {
+    let mut items = std::pin::pin!(items);
+    // Counted rather than collected, so that the trace is held nowhere
+    let (mut nodes, mut edges) = (0, 0);
+    let mut error = None;
+    while let Some(item) = items.next().await {
+        let item = match item {
+            Ok(item) => item,
+            Err(e) => {
+                error = Some(e);
+                break;
+            }
+        };
+        match &item {
+            TraceItem::Node(_) => nodes += 1,
+            TraceItem::Edge(_) => edges += 1,
+        }
+        writeln!(writer, "{}", item.to_json_line()).map_err(io_error(what))?;
+    }
+    let summary = json!({
+        "type": "summary",
+        "nodes": nodes,
+        "edges": edges,
+        "cancelled": cancel.is_cancelled(),
+        "error": error.as_ref().map(|e| e.to_string()),
+    });
+    writeln!(writer, "{}", summary)
+        .and_then(|()| writer.flush())
+        .map_err(io_error(what))?;
+    match error {
+        Some(error) => Err(error.into()),
+        None => Ok(()),
+    }
+}
+
+/// Table of the outputs `tx` spends
+fn inputs(tx: &Transaction) -> TablePrinter {
+    let mut table = TablePrinter::new(&["Input", "Spends"]).right_aligned(0);
+    for (vin, input) in tx.input.iter().enumerate() {
+        let spends = match tx.is_coinbase() {
+            true => "(coinbase)".to_string(),
+            false => input.previous_output.to_string(),
+        };
+        table.row([vin.to_string(), spends]);
+    }
+    table
+}
+
+/// Table of the outputs of `tx`, with the addresses they pay on `network`
+fn outputs(tx: &Transaction, network: Network) -> TablePrinter {
+    let mut table = TablePrinter::new(&["Output", "Value", "Sats", "Address"])
+        .right_aligned(0)
+        .right_aligned(1)
+        .right_aligned(2);
+    for (vout, output) in tx.output.iter().enumerate() {
+        let address = Address::from_script(&output.script_pubkey, network)
+            .map_or("-".to_string(), |address| address.to_string());
+        table.row([
+            vout.to_string(),
+            btc(output.value),
+            output.value.to_sat().to_string(),
+            address,
+        ]);
+    }
+    table
+}
+
+/// `amount` in BTC, with the unit
+fn btc(amount: bitcoin::Amount) -> String {
+    amount
+        .display_in(Denomination::Bitcoin)
+        .show_denomination()
+        .to_string()
+}
+
+/// `250ms`, `30s`, `5m`, `1h`, or seconds without a unit
+fn parse_duration(duration: &str) -> Result<Duration, String> {
+    let digits = duration
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(duration.len());
+    let (number, unit) = duration.split_at(digits);
+    let invalid = || {
+        format!(
+            "Invalid duration {:?}, expected e.g. 30s, 5m or 500ms",
+            duration
+        )
+    };
+    let number: u64 = number.parse().map_err(|_| invalid())?;
+    match unit {
+        "ms" => Ok(Duration::from_millis(number)),
+        "" | "s" => Ok(Duration::from_secs(number)),
+        "m" => Ok(Duration::from_secs(number.saturating_mul(60))),
+        "h" => Ok(Duration::from_secs(number.saturating_mul(3600))),
+        _ => Err(invalid()),
+    }
+}
+
+/// `outpoint` as `args::outpoint` reads it, for arguments clap does not parse
+fn parse_outpoint(outpoint: &str) -> Result<OutPoint, CliError> {
+    args::outpoint(outpoint).map_err(|e| CliError::InvalidInput(e.to_string()))
+}
+
+/// `address` as `args::address` reads it, on `network`, for arguments clap does not
+/// parse
+fn parse_address(address: &str, network: Network) -> Result<Address, CliError> {
+    let address = args::address(address).map_err(|e| CliError::InvalidInput(e.to_string()))?;
+    require_network(address, network)
+}
+
+fn require_network(
+    address: Address<NetworkUnchecked>,
+    network: Network,
+) -> Result<Address, CliError> {
+    address
+        .require_network(network)
+        .map_err(|e| CliError::InvalidInput(format!("Invalid address: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_durations_parse_with_units() {
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_duration("30s"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_duration("30"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_duration("5m"), Ok(Duration::from_secs(300)));
+        assert_eq!(parse_duration("2h"), Ok(Duration::from_secs(7200)));
+        for invalid in ["", "s", "1.5s", "10d", "-3s"] {
+            assert!(parse_duration(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_log_flags_override_the_environment() {
+        let filter = |flags: &[&str], env: Option<&str>| {
+            let args = ["pathfinder"]
+                .iter()
+                .chain(flags)
+                .chain(&["completions", "bash"]);
+            let log = Cli::try_parse_from(args).unwrap().log;
+            log.filter(env.map(str::to_string))
+        };
+
+        assert_eq!(filter(&[], None), "warn");
+        assert_eq!(
+            filter(&[], Some("pathfinder::blockchain=debug")),
+            "pathfinder::blockchain=debug"
+        );
+        assert_eq!(filter(&["-v"], Some("off")), "warn,pathfinder=info");
+        assert_eq!(filter(&["-vv"], None), "warn,pathfinder=debug");
+        assert_eq!(filter(&["-vvvv"], None), "warn,pathfinder=trace");
+        assert_eq!(filter(&["--quiet"], Some("debug")), "error");
+        assert!(Cli::try_parse_from(["pathfinder", "-v", "-q", "completions", "bash"]).is_err());
+    }
+}
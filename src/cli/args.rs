@@ -0,0 +1,202 @@
+//! Parsers of the txid, outpoint and address arguments, run by clap as it reads them
+//! so that a mistyped argument is reported precisely before any lookup.
+//!
+//! An argument they reject exits with `EXIT_INVALID_INPUT`, told apart from other
+//! usage errors by its `InvalidArgument` source.
+
+use bitcoin::address::NetworkUnchecked;
+use bitcoin::{Address, OutPoint, Txid};
+use thiserror::Error;
+
+/// Hex characters of a txid
+const TXID_LEN: usize = 64;
+
+/// A txid, outpoint or address argument that is not one, and why
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("{0}")]
+pub struct InvalidArgument(pub String);
+
+/// A txid: 64 hex characters, surrounding whitespace ignored
+pub fn txid(txid: &str) -> Result<Txid, InvalidArgument> {
+    let txid = txid.trim();
+    if let Some((position, c)) = txid
+        .chars()
+        .enumerate()
+        .find(|(_, c)| !c.is_ascii_hexdigit())
+    {
+        return Err(InvalidArgument(format!(
+            "txid must be hex, got {:?} at position {}",
+            c,
+            position + 1
+        )));
+    }
+    if txid.len() != TXID_LEN {
+        return Err(InvalidArgument(format!(
+            "txid must be {} hex chars, got {}",
+            TXID_LEN,
+            txid.len()
+        )));
+    }
+    txid.parse()
+        .map_err(|e| InvalidArgument(format!("invalid txid: {}", e)))
+}
+
+/// An output, as `<txid>:<vout>`, `<txid>#<vout>` or `<txid> <vout>`, the forms
+/// explorers show them in
+pub fn outpoint(outpoint: &str) -> Result<OutPoint, InvalidArgument> {
+    let outpoint = outpoint.trim();
+    let Some((txid_part, vout_part)) = outpoint
+        .split_once([':', '#'])
+        .or_else(|| outpoint.split_once(char::is_whitespace))
+    else {
+        return Err(InvalidArgument(format!(
+            "expected <txid>:<vout>, <txid>#<vout> or <txid> <vout>, got '{}'",
+            outpoint
+        )));
+    };
+    let txid = txid(txid_part)?;
+    let vout_part = vout_part.trim();
+    let vout = match vout_part.parse::<u32>() {
+        Ok(vout) => vout,
+        Err(_) if !vout_part.is_empty() && vout_part.bytes().all(|b| b.is_ascii_digit()) => {
+            return Err(InvalidArgument(format!(
+                "vout must be at most {}, got {}",
+                u32::MAX,
+                vout_part
+            )));
+        }
+        Err(_) => {
+            return Err(InvalidArgument(format!(
+                "vout must be a non-negative integer, got '{}'",
+                vout_part
+            )));
+        }
+    };
+    Ok(OutPoint::new(txid, vout))
+}
+
+/// An address, its checksum and length checked; its network is checked against
+/// `--network` once all arguments are read
+pub fn address(address: &str) -> Result<Address<NetworkUnchecked>, InvalidArgument> {
+    let address = address.trim();
+    address
+        .parse()
+        .map_err(|e| InvalidArgument(format!("invalid address '{}': {}", address, e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::Network;
+
+    const TXID: &str = "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b";
+
+    fn error<T: std::fmt::Debug>(result: Result<T, InvalidArgument>) -> String {
+        result.unwrap_err().0
+    }
+
+    #[test]
+    fn test_txids_are_64_hex_chars() {
+        assert_eq!(txid(TXID).unwrap().to_string(), TXID);
+        assert_eq!(txid(&format!("  {}\n", TXID)).unwrap().to_string(), TXID);
+        assert_eq!(
+            txid(&TXID.to_uppercase()).unwrap().to_string(),
+            TXID,
+            "upper case hex"
+        );
+
+        assert_eq!(error(txid(&TXID[1..])), "txid must be 64 hex chars, got 63");
+        assert_eq!(
+            error(txid(&format!("{}00", TXID))),
+            "txid must be 64 hex chars, got 66"
+        );
+        assert_eq!(error(txid("")), "txid must be 64 hex chars, got 0");
+        assert_eq!(
+            error(txid(&format!("{}g", &TXID[1..]))),
+            "txid must be hex, got 'g' at position 64"
+        );
+        assert_eq!(
+            error(txid(&format!("0x{}", &TXID[2..]))),
+            "txid must be hex, got 'x' at position 2"
+        );
+    }
+
+    #[test]
+    fn test_outpoints_take_the_forms_explorers_show() {
+        let expected = OutPoint::new(TXID.parse().unwrap(), 7);
+        for form in [
+            format!("{}:7", TXID),
+            format!("{}#7", TXID),
+            format!("{} 7", TXID),
+            format!("{}\t 7", TXID),
+            format!("  {}:7\n", TXID),
+            format!("{}: 7", TXID),
+        ] {
+            assert_eq!(outpoint(&form), Ok(expected), "{:?}", form);
+        }
+        assert_eq!(
+            outpoint(&format!("{}:{}", TXID, u32::MAX)).unwrap().vout,
+            u32::MAX
+        );
+    }
+
+    #[test]
+    fn test_outpoints_are_rejected_with_what_is_wrong() {
+        assert_eq!(
+            error(outpoint(&format!("{}:abc", TXID))),
+            "vout must be a non-negative integer, got 'abc'"
+        );
+        assert_eq!(
+            error(outpoint(&format!("{}:-1", TXID))),
+            "vout must be a non-negative integer, got '-1'"
+        );
+        assert_eq!(
+            error(outpoint(&format!("{}:", TXID))),
+            "vout must be a non-negative integer, got ''"
+        );
+        assert_eq!(
+            error(outpoint(&format!("{}:1:2", TXID))),
+            "vout must be a non-negative integer, got '1:2'"
+        );
+        assert_eq!(
+            error(outpoint(&format!("{}:4294967296", TXID))),
+            "vout must be at most 4294967295, got 4294967296"
+        );
+        assert_eq!(
+            error(outpoint(&format!("{}:0", &TXID[1..]))),
+            "txid must be 64 hex chars, got 63"
+        );
+        assert_eq!(
+            error(outpoint(TXID)),
+            format!(
+                "expected <txid>:<vout>, <txid>#<vout> or <txid> <vout>, got '{}'",
+                TXID
+            )
+        );
+    }
+
+    #[test]
+    fn test_addresses_are_checked_before_their_network() {
+        let mainnet = "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4";
+        let testnet = "tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx";
+        assert!(
+            address(mainnet)
+                .unwrap()
+                .is_valid_for_network(Network::Bitcoin)
+        );
+        assert!(address(&format!(" {} ", testnet)).is_ok());
+        assert!(address("1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN2").is_ok());
+
+        // Last character changed, breaking the checksum
+        let error = error(address("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t5"));
+        assert!(
+            error.starts_with("invalid address 'bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t5': "),
+            "{}",
+            error
+        );
+        assert!(address("1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN3").is_err());
+        assert!(address(&mainnet[..mainnet.len() - 1]).is_err());
+        assert!(address("not-an-address").is_err());
+        assert!(address("").is_err());
+    }
+}
@@ -0,0 +1,293 @@
+//! Batch traces: the seeds of a file, outputs and addresses, traced forward in one
+//! run into one graph, sharing the cache and the request budget. The same seeds can
+//! warm the cache instead, their transactions and spenders fetched in batches.
+//!
+//! Every line is parsed before the first lookup. A seed that cannot be traced (a
+//! malformed line, a transaction the backend does not know, an address without
+//! unspent outputs) is left out and reported, and the others are traced.
+
+use crate::cli::watch::Target;
+use crate::cli::{CliError, io_error};
+use bitcoin::{Network, OutPoint};
+use pathfinder::blockchain::{BlockchainDataSource, CachingDataSource, PrefetchSummary};
+use pathfinder::tracer::{TraceConfig, TraceGraph, TraceOutcome, Tracer};
+use std::io::{self, Write};
+use std::path::Path;
+
+/// Columns of the per-seed summary
+const SUMMARY_COLUMNS: [&str; 6] = [
+    "line",
+    "seed",
+    "status",
+    "outpoints",
+    "transactions",
+    "error",
+];
+
+/// A line of a seed file, and what came of it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Seed {
+    /// Line number in the file, from 1
+    pub line: usize,
+    /// The line, without its comment and surrounding whitespace
+    pub text: String,
+    /// Outpoints traced from the seed, once resolved: the seed itself, or the unspent
+    /// outputs of an address
+    pub outpoints: Vec<OutPoint>,
+    /// Why the seed is not traced
+    pub error: Option<String>,
+    target: Option<Target>,
+}
+
+impl Seed {
+    /// Whether the line is no outpoint or address
+    pub fn is_malformed(&self) -> bool {
+        self.target.is_none()
+    }
+
+    fn parse(line: usize, text: &str, network: Network) -> Self {
+        let (target, error) = match Target::parse(text, network) {
+            Ok(target) => (Some(target), None),
+            Err(error) => (None, Some(error.to_string())),
+        };
+        Seed {
+            line,
+            text: text.to_string(),
+            outpoints: Vec::new(),
+            error,
+            target,
+        }
+    }
+}
+
+/// Seeds listed in `path`, one `<txid>:<vout>` or address on `network` per line;
+/// blank lines and `#` comments are skipped. A line that does not parse is a seed
+/// with its `error` set.
+///
+/// # Errors
+/// `Io` - the file could not be read
+pub fn read_seeds(path: &Path, network: Network) -> Result<Vec<Seed>, CliError> {
+    let text = std::fs::read_to_string(path)
+        .map_err(io_error(format!("Failed to read {}", path.display())))?;
+    Ok(text
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.split('#').next().unwrap_or_default().trim()))
+        .filter(|(_, line)| !line.is_empty())
+        .map(|(line, text)| Seed::parse(line, text, network))
+        .collect())
+}
+
+/// What warming the cache with the seeds fetched
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Warmed {
+    /// Transactions of the outpoint seeds
+    pub transactions: PrefetchSummary,
+    /// Spenders of the outpoints the seeds resolved to
+    pub spenders: PrefetchSummary,
+}
+
+/// Traces the seeds forward into one graph under `config`, with `continue_on_error`
+/// set so that a lookup failing ends only its branch.
+///
+/// The seeds are resolved first, as `resolve` does. Those lookups come out of
+/// `config.max_requests`; the trace then finds them cached.
+///
+/// # Errors
+/// - `InvalidInput` - no seed resolved, or resolving them spent the request budget
+/// - any error of `Tracer::trace_forward_multi`
+pub async fn trace<C>(
+    seeds: &mut [Seed],
+    source: &CachingDataSource<C>,
+    config: TraceConfig,
+) -> Result<TraceOutcome, CliError>
+where
+    C: BlockchainDataSource + Send + Sync,
+{
+    let before = source.stats().total().misses;
+    resolve(seeds, source).await;
+
+    let outpoints = outpoints(seeds);
+    if outpoints.is_empty() {
+        return Err(CliError::InvalidInput(format!(
+            "None of the {} seeds can be traced",
+            seeds.len()
+        )));
+    }
+    let mut config = config.continue_on_error(true);
+    if let Some(max_requests) = config.max_requests {
+        let spent = (source.stats().total().misses - before) as usize;
+        if spent >= max_requests {
+            return Err(CliError::InvalidInput(format!(
+                "Resolving the seeds took all {} requests of --max-requests",
+                max_requests
+            )));
+        }
+        config = config.max_requests(max_requests - spent);
+    }
+    Ok(Tracer::new(source)
+        .trace_forward_multi(&outpoints, &config)
+        .await?)
+}
+
+/// Caches the transactions of the outpoint seeds, then the spenders of the outpoints
+/// the seeds resolve to, both through the batch lookups of `source`.
+///
+/// The seeds are resolved in between, as `resolve` does, their transactions then
+/// found cached.
+///
+/// # Errors
+/// `InvalidInput` - no seed resolved
+pub async fn warm<C>(seeds: &mut [Seed], source: &CachingDataSource<C>) -> Result<Warmed, CliError>
+where
+    C: BlockchainDataSource + Send + Sync,
+{
+    let txids: Vec<_> = seeds
+        .iter()
+        .filter_map(|seed| match &seed.target {
+            Some(Target::Output(outpoint)) => Some(outpoint.txid),
+            _ => None,
+        })
+        .collect();
+    let transactions = source.prefetch_transactions(&txids).await;
+    resolve(seeds, source).await;
+
+    let outpoints = outpoints(seeds);
+    if outpoints.is_empty() {
+        return Err(CliError::InvalidInput(format!(
+            "None of the {} seeds can be warmed",
+            seeds.len()
+        )));
+    }
+    let spenders = source.prefetch_spending(&outpoints).await;
+    Ok(Warmed {
+        transactions,
+        spenders,
+    })
+}
+
+/// Resolves the seeds through `source`: the transaction of an outpoint is looked up,
+/// and the unspent outputs of an address listed. A seed failing to resolve keeps its
+/// `error`.
+async fn resolve<C>(seeds: &mut [Seed], source: &CachingDataSource<C>)
+where
+    C: BlockchainDataSource + Send + Sync,
+{
+    for seed in seeds.iter_mut() {
+        let resolved = match &seed.target {
+            Some(Target::Output(outpoint)) => match source.get_transaction(outpoint.txid).await {
+                Ok(tx) if tx.output.len() > outpoint.vout as usize => Ok(vec![*outpoint]),
+                Ok(_) => Err(format!("{} has no output {}", outpoint.txid, outpoint.vout)),
+                Err(error) => Err(error.to_string()),
+            },
+            Some(Target::Address(address)) => {
+                match source.get_address_utxos(address.clone()).await {
+                    Ok(utxos) if utxos.is_empty() => Err("No unspent outputs".to_string()),
+                    Ok(utxos) => Ok(utxos),
+                    Err(error) => Err(error.to_string()),
+                }
+            }
+            None => continue,
+        };
+        match resolved {
+            Ok(outpoints) => seed.outpoints = outpoints,
+            Err(error) => seed.error = Some(error),
+        }
+    }
+}
+
+/// Outpoints of the resolved seeds, each once, in the order they first appear: the
+/// indexes of `TraceNode::seeds`
+fn outpoints(seeds: &[Seed]) -> Vec<OutPoint> {
+    let mut outpoints = Vec::new();
+    for outpoint in seeds.iter().flat_map(|seed| &seed.outpoints) {
+        if !outpoints.contains(outpoint) {
+            outpoints.push(*outpoint);
+        }
+    }
+    outpoints
+}
+
+/// Writes one CSV row per seed to `writer`: its line, the seed as written, whether it
+/// was `traced` or `failed`, the outpoints it resolved to, the transactions of
+/// `graph` reachable from them, and why it failed. Fails with `what`.
+pub fn write_summary<W: Write>(
+    seeds: &[Seed],
+    graph: &TraceGraph,
+    writer: W,
+    what: &str,
+) -> Result<(), CliError> {
+    let indexes = outpoints(seeds);
+    let mut csv = csv::Writer::from_writer(writer);
+    let mut write = || -> Result<(), csv::Error> {
+        csv.write_record(SUMMARY_COLUMNS)?;
+        for seed in seeds {
+            let seed_indexes: Vec<usize> = seed
+                .outpoints
+                .iter()
+                .filter_map(|outpoint| indexes.iter().position(|o| o == outpoint))
+                .collect();
+            let transactions = graph
+                .nodes()
+                .filter(|node| seed_indexes.iter().any(|i| node.seeds.contains(i)))
+                .count();
+            let status = match seed.error {
+                Some(_) => "failed",
+                None => "traced",
+            };
+            csv.write_record([
+                seed.line.to_string(),
+                seed.text.clone(),
+                status.to_string(),
+                seed.outpoints.len().to_string(),
+                transactions.to_string(),
+                seed.error.clone().unwrap_or_default(),
+            ])?;
+        }
+        csv.flush()?;
+        Ok(())
+    };
+    write().map_err(|error| CliError::Io {
+        what: what.to_string(),
+        source: io::Error::from(error),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seed_files_keep_line_numbers_and_parse_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("seeds.txt");
+        let txid = "ab".repeat(32);
+        std::fs::write(
+            &path,
+            format!(
+                "# seeds\n{txid}:1\n\nnot-a-seed  # typo\n  bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4 \n"
+            ),
+        )
+        .unwrap();
+
+        let seeds = read_seeds(&path, Network::Bitcoin).unwrap();
+
+        let lines: Vec<_> = seeds
+            .iter()
+            .map(|seed| (seed.line, seed.text.as_str()))
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                (2, format!("{}:1", txid).as_str()),
+                (4, "not-a-seed"),
+                (5, "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4"),
+            ]
+        );
+        assert!(matches!(seeds[0].target, Some(Target::Output(_))));
+        assert!(seeds[1].target.is_none());
+        assert!(seeds[1].error.as_ref().unwrap().contains("not-a-seed"));
+        assert!(matches!(seeds[2].target, Some(Target::Address(_))));
+        assert!(seeds[0].error.is_none() && seeds[2].error.is_none());
+    }
+}
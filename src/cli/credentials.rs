@@ -0,0 +1,325 @@
+//! bitcoind credentials, from the flags, the environment or the OS keyring, in the
+//! order of `RESOLUTION`.
+//!
+//! The environment and the keyring are passed in, so the order is tested against a
+//! fake environment and an in-memory store.
+
+use crate::cli::{BackendArgs, CliError, io_error};
+use std::path::Path;
+
+pub const ENV_URL: &str = "PATHFINDER_RPC_URL";
+pub const ENV_USER: &str = "PATHFINDER_RPC_USER";
+pub const ENV_PASS: &str = "PATHFINDER_RPC_PASS";
+pub const ENV_COOKIE: &str = "PATHFINDER_RPC_COOKIE";
+
+/// How the backend and its credentials are chosen, as `--help` shows it
+pub const RESOLUTION: &str = "\
+Backend: --rpc, else PATHFINDER_RPC_URL unless --esplora is given, else Esplora.
+
+bitcoind credentials, the first found of:
+  1. --rpc-cookie, or --rpc-user with --rpc-pass
+  2. PATHFINDER_RPC_COOKIE, or --rpc-user or PATHFINDER_RPC_USER with PATHFINDER_RPC_PASS
+  3. --rpc-user or PATHFINDER_RPC_USER with the password `pathfinder auth set <NAME>`
+     stored in the OS keyring, for --rpc-name <NAME> (builds with the keyring feature)";
+
+/// Where secrets are kept between runs, by name: the OS keyring, or memory in tests
+pub trait SecretStore {
+    /// The secret stored under `name`, if any
+    fn get(&self, name: &str) -> Result<Option<String>, CliError>;
+
+    /// Stores `secret` under `name`, replacing any previous one
+    #[cfg(any(feature = "keyring", test))]
+    fn set(&self, name: &str, secret: &str) -> Result<(), CliError>;
+
+    /// Deletes the secret stored under `name`, returning whether there was one
+    #[cfg(any(feature = "keyring", test))]
+    fn delete(&self, name: &str) -> Result<bool, CliError>;
+}
+
+/// Passwords in the OS keyring (Keychain, Credential Manager or Secret Service),
+/// under the `pathfinder` service
+#[cfg(feature = "keyring")]
+pub struct KeyringStore;
+
+#[cfg(feature = "keyring")]
+impl KeyringStore {
+    const SERVICE: &str = "pathfinder";
+
+    fn entry(name: &str) -> Result<keyring::Entry, CliError> {
+        keyring::Entry::new(Self::SERVICE, name).map_err(keyring_error)
+    }
+}
+
+#[cfg(feature = "keyring")]
+fn keyring_error(error: keyring::Error) -> CliError {
+    CliError::Keyring(error.to_string())
+}
+
+#[cfg(feature = "keyring")]
+impl SecretStore for KeyringStore {
+    fn get(&self, name: &str) -> Result<Option<String>, CliError> {
+        match Self::entry(name)?.get_password() {
+            Ok(secret) => Ok(Some(secret)),
+            Err(keyring::Error::NoEntry) => Ok(None),
+            Err(error) => Err(keyring_error(error)),
+        }
+    }
+
+    fn set(&self, name: &str, secret: &str) -> Result<(), CliError> {
+        Self::entry(name)?
+            .set_password(secret)
+            .map_err(keyring_error)
+    }
+
+    fn delete(&self, name: &str) -> Result<bool, CliError> {
+        match Self::entry(name)?.delete_credential() {
+            Ok(()) => Ok(true),
+            Err(keyring::Error::NoEntry) => Ok(false),
+            Err(error) => Err(keyring_error(error)),
+        }
+    }
+}
+
+/// A bitcoind endpoint and the credentials to query it with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RpcAuth {
+    pub url: String,
+    pub user: String,
+    pub pass: String,
+}
+
+/// The bitcoind endpoint and credentials of `args`, `env` and `store`, in the order
+/// of `RESOLUTION`, or `None` for Esplora.
+///
+/// # Errors
+/// - `InvalidInput` - credentials without an endpoint, an endpoint without
+///   credentials, or a cookie file not of the form `<user>:<password>`
+/// - `Io` - the cookie file could not be read
+/// - `Keyring` - the keyring could not be read (keyring feature)
+pub fn resolve(
+    args: &BackendArgs,
+    env: impl Fn(&str) -> Option<String>,
+    store: Option<&dyn SecretStore>,
+) -> Result<Option<RpcAuth>, CliError> {
+    let url = match (&args.rpc, &args.esplora) {
+        (Some(url), _) => Some(url.clone()),
+        (None, None) => env(ENV_URL),
+        (None, Some(_)) => None,
+    };
+    let Some(url) = url else {
+        return match args.rpc_user.is_some() || args.rpc_cookie.is_some() {
+            true => Err(CliError::InvalidInput(format!(
+                "RPC credentials need --rpc or {}",
+                ENV_URL
+            ))),
+            false => Ok(None),
+        };
+    };
+
+    let auth = |(user, pass)| RpcAuth {
+        url: url.clone(),
+        user,
+        pass,
+    };
+    if let Some(cookie) = &args.rpc_cookie {
+        return read_cookie(cookie).map(auth).map(Some);
+    }
+    if let (Some(user), Some(pass)) = (&args.rpc_user, &args.rpc_pass) {
+        return Ok(Some(auth((user.clone(), pass.clone()))));
+    }
+    if let Some(cookie) = env(ENV_COOKIE) {
+        return read_cookie(Path::new(&cookie)).map(auth).map(Some);
+    }
+    let user = args.rpc_user.clone().or_else(|| env(ENV_USER));
+    if let (Some(user), Some(pass)) = (&user, env(ENV_PASS)) {
+        return Ok(Some(auth((user.clone(), pass))));
+    }
+    if let (Some(user), Some(name)) = (&user, &args.rpc_name) {
+        let Some(store) = store else {
+            return Err(CliError::InvalidInput(
+                "--rpc-name needs a build with the keyring feature".to_string(),
+            ));
+        };
+        if let Some(pass) = store.get(name)? {
+            return Ok(Some(auth((user.clone(), pass))));
+        }
+    }
+    Err(CliError::InvalidInput(
+        "bitcoind needs credentials, see `pathfinder --help`".to_string(),
+    ))
+}
+
+/// User and password of the cookie file bitcoind writes, as `<user>:<password>`
+fn read_cookie(path: &Path) -> Result<(String, String), CliError> {
+    let cookie = std::fs::read_to_string(path).map_err(io_error(format!(
+        "Failed to read cookie file {}",
+        path.display()
+    )))?;
+    match cookie.trim().split_once(':') {
+        Some((user, pass)) => Ok((user.to_string(), pass.to_string())),
+        None => Err(CliError::InvalidInput(format!(
+            "Cookie file {} is not of the form <user>:<password>",
+            path.display()
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::Cli;
+    use clap::Parser;
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+
+    #[derive(Default)]
+    struct MemoryStore(RefCell<HashMap<String, String>>);
+
+    impl SecretStore for MemoryStore {
+        fn get(&self, name: &str) -> Result<Option<String>, CliError> {
+            Ok(self.0.borrow().get(name).cloned())
+        }
+
+        fn set(&self, name: &str, secret: &str) -> Result<(), CliError> {
+            self.0
+                .borrow_mut()
+                .insert(name.to_string(), secret.to_string());
+            Ok(())
+        }
+
+        fn delete(&self, name: &str) -> Result<bool, CliError> {
+            Ok(self.0.borrow_mut().remove(name).is_some())
+        }
+    }
+
+    fn backend(flags: &[&str]) -> BackendArgs {
+        let args = ["pathfinder"]
+            .iter()
+            .chain(flags)
+            .chain(&["completions", "bash"]);
+        Cli::try_parse_from(args).unwrap().backend
+    }
+
+    /// `resolve` with the variables of `vars` as the environment
+    fn resolve_with(
+        flags: &[&str],
+        vars: &[(&str, &str)],
+        store: Option<&dyn SecretStore>,
+    ) -> Result<Option<RpcAuth>, CliError> {
+        let env: HashMap<String, String> = vars
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        resolve(&backend(flags), |name| env.get(name).cloned(), store)
+    }
+
+    fn auth(url: &str, user: &str, pass: &str) -> Option<RpcAuth> {
+        Some(RpcAuth {
+            url: url.to_string(),
+            user: user.to_string(),
+            pass: pass.to_string(),
+        })
+    }
+
+    #[test]
+    fn test_flags_come_before_the_environment() {
+        let env = [
+            (ENV_URL, "http://env:8332"),
+            (ENV_USER, "env-user"),
+            (ENV_PASS, "env-pass"),
+        ];
+
+        let flags = [
+            "--rpc",
+            "http://flag:8332",
+            "--rpc-user",
+            "u",
+            "--rpc-pass",
+            "p",
+        ];
+        assert_eq!(
+            resolve_with(&flags, &env, None).unwrap(),
+            auth("http://flag:8332", "u", "p")
+        );
+        // A flag user goes with the environment's password
+        assert_eq!(
+            resolve_with(&["--rpc-user", "u"], &env, None).unwrap(),
+            auth("http://env:8332", "u", "env-pass")
+        );
+        assert_eq!(
+            resolve_with(&[], &env, None).unwrap(),
+            auth("http://env:8332", "env-user", "env-pass")
+        );
+        // --esplora overrides the environment's endpoint
+        assert_eq!(
+            resolve_with(&["--esplora", "http://esplora"], &env, None).unwrap(),
+            None
+        );
+        assert_eq!(resolve_with(&[], &[], None).unwrap(), None);
+    }
+
+    #[test]
+    fn test_cookies_come_before_passwords_at_their_level() {
+        let dir = tempfile::tempdir().unwrap();
+        let cookie = dir.path().join(".cookie");
+        std::fs::write(&cookie, "__cookie__:secret\n").unwrap();
+        let cookie = cookie.to_str().unwrap();
+        let env = [
+            (ENV_URL, "http://env:8332"),
+            (ENV_USER, "env-user"),
+            (ENV_PASS, "env-pass"),
+            (ENV_COOKIE, cookie),
+        ];
+
+        assert_eq!(
+            resolve_with(&[], &env, None).unwrap(),
+            auth("http://env:8332", "__cookie__", "secret")
+        );
+        let flags = ["--rpc-user", "u", "--rpc-pass", "p"];
+        assert_eq!(
+            resolve_with(&flags, &env, None).unwrap(),
+            auth("http://env:8332", "u", "p")
+        );
+        assert_eq!(
+            resolve_with(&["--rpc-cookie", cookie], &[(ENV_URL, "http://env")], None).unwrap(),
+            auth("http://env", "__cookie__", "secret")
+        );
+    }
+
+    #[test]
+    fn test_the_keyring_comes_last() {
+        let store = MemoryStore::default();
+        store.set("node", "stored").unwrap();
+        let env = [(ENV_URL, "http://env:8332"), (ENV_USER, "env-user")];
+        let named = ["--rpc-name", "node"];
+
+        assert_eq!(
+            resolve_with(&named, &env, Some(&store)).unwrap(),
+            auth("http://env:8332", "env-user", "stored")
+        );
+        let with_pass = [env[0], env[1], (ENV_PASS, "env-pass")];
+        assert_eq!(
+            resolve_with(&named, &with_pass, Some(&store)).unwrap(),
+            auth("http://env:8332", "env-user", "env-pass")
+        );
+
+        assert!(store.delete("node").unwrap());
+        assert!(!store.delete("node").unwrap());
+        assert!(matches!(
+            resolve_with(&named, &env, Some(&store)),
+            Err(CliError::InvalidInput(_))
+        ));
+    }
+
+    #[test]
+    fn test_credentials_need_an_endpoint() {
+        assert!(matches!(
+            resolve_with(&["--rpc-user", "u", "--rpc-pass", "p"], &[], None),
+            Err(CliError::InvalidInput(_))
+        ));
+        assert!(matches!(
+            resolve_with(&["--rpc", "http://flag:8332"], &[], None),
+            Err(CliError::InvalidInput(_))
+        ));
+    }
+}
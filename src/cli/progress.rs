@@ -0,0 +1,235 @@
+//! Signs of life of a running trace: a status line redrawn in place on a terminal,
+//! periodic log lines otherwise.
+
+use futures::StreamExt;
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use pathfinder::tracer::{TraceEvent, TraceEvents};
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+/// Least time between two log lines, the last one aside
+pub const LOG_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How often the status line is redrawn, events or not
+const TICK: Duration = Duration::from_millis(120);
+
+/// Counters of a trace, as its events report them.
+///
+/// # Fields
+/// * `visited` - transactions in the graph
+/// * `frontier` - outputs queued
+/// * `requests` - requests made to the data source
+/// * `depth` - deepest transaction fetched so far
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Status {
+    pub visited: usize,
+    pub frontier: usize,
+    pub requests: usize,
+    pub depth: usize,
+}
+
+enum Output<'a> {
+    Bar(ProgressBar),
+    Log {
+        out: Box<dyn Write + 'a>,
+        last: Option<Instant>,
+    },
+}
+
+/// Renders the events of a trace, out of the trace's way: the events queue drops the
+/// oldest ones rather than wait for it.
+///
+/// # Fields
+/// * `budget` - request budget of the trace, if it has one
+/// * `hit_rate` - share of lookups the cache answered so far
+pub struct Progress<'a> {
+    output: Output<'a>,
+    status: Status,
+    budget: Option<usize>,
+    hit_rate: Box<dyn Fn() -> f64 + 'a>,
+}
+
+impl<'a> Progress<'a> {
+    /// Status line on stderr, redrawn in place
+    pub fn bar(budget: Option<usize>, hit_rate: impl Fn() -> f64 + 'a) -> Self {
+        let bar = ProgressBar::with_draw_target(None, ProgressDrawTarget::stderr());
+        bar.set_style(ProgressStyle::with_template("{spinner} {elapsed} {msg}").expect("template"));
+        bar.enable_steady_tick(TICK);
+        Self::new(Output::Bar(bar), budget, hit_rate)
+    }
+
+    /// Status lines written to `out`, one per `LOG_INTERVAL` at most
+    pub fn log(
+        out: impl Write + 'a,
+        budget: Option<usize>,
+        hit_rate: impl Fn() -> f64 + 'a,
+    ) -> Self {
+        let output = Output::Log {
+            out: Box::new(out),
+            last: None,
+        };
+        Self::new(output, budget, hit_rate)
+    }
+
+    fn new(output: Output<'a>, budget: Option<usize>, hit_rate: impl Fn() -> f64 + 'a) -> Self {
+        Self {
+            output,
+            status: Status::default(),
+            budget,
+            hit_rate: Box::new(hit_rate),
+        }
+    }
+
+    /// Renders `events` until the trace ends them
+    pub async fn follow(&mut self, mut events: TraceEvents) {
+        while let Some(event) = events.next().await {
+            self.update(&event);
+        }
+    }
+
+    /// Takes `event` into account, redrawing or logging the status
+    pub fn update(&mut self, event: &TraceEvent) {
+        match event {
+            TraceEvent::TransactionFetched { depth, .. } => {
+                self.status.depth = self.status.depth.max(*depth);
+            }
+            TraceEvent::Progress {
+                visited,
+                frontier,
+                requests_made,
+            } => {
+                self.status.visited = *visited;
+                self.status.frontier = *frontier;
+                self.status.requests = *requests_made;
+            }
+            TraceEvent::RateLimited { wait } => {
+                self.notice(&format!("rate limited, waiting {:.1}s", wait.as_secs_f64()));
+            }
+            TraceEvent::Retrying { error, wait } => {
+                self.notice(&format!(
+                    "retrying in {:.1}s after: {}",
+                    wait.as_secs_f64(),
+                    error
+                ));
+            }
+            TraceEvent::BranchTerminated { .. } => {}
+        }
+        let line = self.status_line();
+        match &mut self.output {
+            Output::Bar(bar) => bar.set_message(line),
+            Output::Log { out, last } => {
+                if matches!(event, TraceEvent::Progress { .. })
+                    && last.is_none_or(|last| last.elapsed() >= LOG_INTERVAL)
+                {
+                    let _ = writeln!(out, "{}", line);
+                    *last = Some(Instant::now());
+                }
+            }
+        }
+    }
+
+    /// Clears the status line, or logs the final status, for the result to follow
+    pub fn finish(self) {
+        let line = self.status_line();
+        match self.output {
+            Output::Bar(bar) => bar.finish_and_clear(),
+            Output::Log { mut out, .. } => {
+                let _ = writeln!(out, "{} (done)", line);
+            }
+        }
+    }
+
+    /// A line of its own, above the status line
+    fn notice(&mut self, notice: &str) {
+        match &mut self.output {
+            Output::Bar(bar) => bar.println(notice),
+            Output::Log { out, .. } => {
+                let _ = writeln!(out, "{}", notice);
+            }
+        }
+    }
+
+    /// `depth 3, 120 transactions, 45 queued, 230/2000 requests, 34% cache hits`
+    fn status_line(&self) -> String {
+        let requests = match self.budget {
+            Some(budget) => format!("{}/{}", self.status.requests, budget),
+            None => self.status.requests.to_string(),
+        };
+        format!(
+            "depth {}, {} transactions, {} queued, {} requests, {:.0}% cache hits",
+            self.status.depth,
+            self.status.visited,
+            self.status.frontier,
+            requests,
+            (self.hit_rate)() * 100.0
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::Txid;
+    use bitcoin::hashes::Hash;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// Writer whose bytes stay readable once it is moved into a `Progress`
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn fetched(depth: usize) -> TraceEvent {
+        TraceEvent::TransactionFetched {
+            txid: Txid::all_zeros(),
+            depth,
+        }
+    }
+
+    fn counters(visited: usize, frontier: usize, requests_made: usize) -> TraceEvent {
+        TraceEvent::Progress {
+            visited,
+            frontier,
+            requests_made,
+        }
+    }
+
+    #[test]
+    fn test_log_lines_are_throttled_and_end_with_the_final_status() {
+        let out = Shared::default();
+        let mut progress = Progress::log(out.clone(), Some(100), || 0.25);
+
+        for event in [
+            fetched(0),
+            counters(1, 2, 10),
+            fetched(1),
+            TraceEvent::RateLimited {
+                wait: Duration::from_secs(2),
+            },
+            fetched(2),
+            // Within LOG_INTERVAL of the first line
+            counters(3, 1, 20),
+            counters(4, 0, 24),
+        ] {
+            progress.update(&event);
+        }
+        progress.finish();
+
+        let log = String::from_utf8(out.0.borrow().clone()).unwrap();
+        assert_eq!(
+            log,
+            "depth 0, 1 transactions, 2 queued, 10/100 requests, 25% cache hits\n\
+             rate limited, waiting 2.0s\n\
+             depth 2, 4 transactions, 0 queued, 24/100 requests, 25% cache hits (done)\n"
+        );
+    }
+}
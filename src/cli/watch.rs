@@ -0,0 +1,355 @@
+//! Long-running watch of outputs and addresses: each target is polled on its own
+//! schedule, an output until it is spent, an address for every spend from it, and a
+//! target whose lookups fail backs off alone.
+//!
+//! Both backends are polled: bitcoind cannot look spenders up, so watching through it
+//! fails with its `UnsupportedOperation` hint.
+
+use crate::cli::{CliError, io_error, outputs, parse_address, parse_outpoint};
+use bitcoin::{Address, Network, OutPoint, ScriptBuf, Transaction, Txid};
+use futures::future::join_all;
+use pathfinder::blockchain::{
+    BlockchainDataSource, BlockchainError, CachingDataSource, Result as BlockchainResult,
+};
+use pathfinder::tracer::TraceGraph;
+use std::collections::HashSet;
+use std::fmt;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::pin::pin;
+use std::time::Duration;
+use tokio::time::{Instant, sleep_until};
+
+/// Longest wait between two polls of a failing target
+pub const MAX_BACKOFF: Duration = Duration::from_secs(600);
+
+/// Something to watch
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    /// Output watched until it is spent
+    Output(OutPoint),
+    /// Address watched for every transaction spending from it
+    Address(Address),
+}
+
+impl Target {
+    /// An outpoint in a form of `args::outpoint`, or an address on `network`
+    pub fn parse(target: &str, network: Network) -> Result<Self, CliError> {
+        // Addresses have no separator of an outpoint
+        match target.trim().contains([':', '#', ' ', '\t']) {
+            true => parse_outpoint(target).map(Target::Output),
+            false => parse_address(target, network).map(Target::Address),
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Target::Output(outpoint) => write!(f, "{}", outpoint),
+            Target::Address(address) => write!(f, "{}", address),
+        }
+    }
+}
+
+/// Targets listed in `path`, one per line; blank lines and `#` comments are skipped
+pub fn read_targets(path: &Path) -> Result<Vec<String>, CliError> {
+    let text = std::fs::read_to_string(path)
+        .map_err(io_error(format!("Failed to read {}", path.display())))?;
+    Ok(text
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+/// How a watch runs.
+///
+/// # Fields
+/// * `interval` - time between two polls of a target
+/// * `timeout` - time after which the watch stops, if bounded
+/// * `extend_trace` - JSON trace file extended with, and saved after, each spend of
+///   an output it holds
+pub struct WatchOptions {
+    pub interval: Duration,
+    pub timeout: Option<Duration>,
+    pub extend_trace: Option<PathBuf>,
+}
+
+/// A target and where its watch stands
+struct Watched {
+    target: Target,
+    next_poll: Instant,
+    failures: u32,
+    /// History of an address, once first polled
+    history: Option<History>,
+    done: bool,
+}
+
+/// Transactions of an address seen so far, and its outputs among them
+#[derive(Default)]
+struct History {
+    seen: HashSet<Txid>,
+    outputs: HashSet<OutPoint>,
+}
+
+impl History {
+    /// Records `txs`, newest first as backends list them, returning those spending
+    /// from the address that were not seen before
+    fn update(&mut self, txs: Vec<Transaction>, script: &ScriptBuf) -> Vec<Transaction> {
+        let new: Vec<Transaction> = txs
+            .into_iter()
+            .rev()
+            .filter(|tx| self.seen.insert(tx.compute_txid()))
+            .collect();
+        for tx in &new {
+            let txid = tx.compute_txid();
+            self.outputs.extend(
+                (0..)
+                    .zip(&tx.output)
+                    .filter(|(_, output)| output.script_pubkey == *script)
+                    .map(|(vout, _)| OutPoint::new(txid, vout)),
+            );
+        }
+        new.into_iter()
+            .filter(|tx| {
+                tx.input
+                    .iter()
+                    .any(|input| self.outputs.contains(&input.previous_output))
+            })
+            .collect()
+    }
+}
+
+/// A poll's answer
+enum Answer {
+    Spender(Option<Transaction>),
+    History(Vec<Transaction>),
+}
+
+/// Watches `targets` until every output among them is spent, the timeout passes or
+/// Ctrl-C, writing each spend to `out` with the outputs of its transaction.
+///
+/// # Errors
+/// - `Backend` - the backend cannot look spends up at all
+/// - `InvalidInput` - the trace file to extend is not a trace
+/// - `Io` - the trace file could not be read or saved, or `out` written
+pub async fn watch<C>(
+    targets: Vec<Target>,
+    source: &CachingDataSource<C>,
+    network: Network,
+    options: &WatchOptions,
+    mut out: impl Write,
+) -> Result<(), CliError>
+where
+    C: BlockchainDataSource + Send + Sync,
+{
+    let mut trace = match &options.extend_trace {
+        Some(path) => Some(load_trace(path)?),
+        None => None,
+    };
+    let start = Instant::now();
+    let mut watched: Vec<Watched> = targets
+        .into_iter()
+        .map(|target| Watched {
+            target,
+            next_poll: start,
+            failures: 0,
+            history: None,
+            done: false,
+        })
+        .collect();
+    eprintln!(
+        "Watching {} targets every {:.1}s",
+        watched.len(),
+        options.interval.as_secs_f64()
+    );
+
+    let deadline = options.timeout.map(|timeout| start + timeout);
+    let mut stop = pin!(async {
+        match deadline {
+            Some(deadline) => tokio::select! {
+                _ = interrupted() => "Interrupted",
+                _ = sleep_until(deadline) => "Timed out",
+            },
+            None => {
+                interrupted().await;
+                "Interrupted"
+            }
+        }
+    });
+
+    while !watched.is_empty() {
+        let now = Instant::now();
+        let due: Vec<usize> = (0..watched.len())
+            .filter(|&i| watched[i].next_poll <= now)
+            .collect();
+        let polls = due.iter().map(|&i| poll(source, &watched[i].target));
+        let answers = tokio::select! {
+            answers = join_all(polls) => answers,
+            reason = &mut stop => {
+                stopped(reason, &watched);
+                return Ok(());
+            }
+        };
+
+        for (i, answer) in due.into_iter().zip(answers) {
+            let watched = &mut watched[i];
+            let answer = match answer {
+                Ok(answer) => answer,
+                Err(error) => {
+                    if matches!(error.inner(), BlockchainError::UnsupportedOperation { .. }) {
+                        return Err(error.into());
+                    }
+                    watched.failures += 1;
+                    let wait = backoff(options.interval, watched.failures);
+                    eprintln!(
+                        "{}: {}, retrying in {:.1}s",
+                        watched.target,
+                        error,
+                        wait.as_secs_f64()
+                    );
+                    watched.next_poll = Instant::now() + wait;
+                    continue;
+                }
+            };
+            watched.failures = 0;
+            watched.next_poll = Instant::now() + options.interval;
+            let spends = match (answer, &watched.target) {
+                (Answer::Spender(spender), _) => {
+                    watched.done = spender.is_some();
+                    spender.into_iter().collect()
+                }
+                (Answer::History(txs), Target::Address(address)) => {
+                    let first = watched.history.is_none();
+                    let history = watched.history.get_or_insert_with(History::default);
+                    let spends = history.update(txs, &address.script_pubkey());
+                    // Spends from before the watch are not news
+                    if first { Vec::new() } else { spends }
+                }
+                (Answer::History(_), Target::Output(_)) => unreachable!("outputs poll spenders"),
+            };
+            for spender in spends {
+                report(&mut out, &watched.target, &spender, network)?;
+                if let Some((path, graph)) = &mut trace
+                    && graph.extend(&spender, network)
+                {
+                    save_trace(path, graph)?;
+                    eprintln!(
+                        "Extended {} with {}",
+                        path.display(),
+                        spender.compute_txid()
+                    );
+                }
+            }
+        }
+        watched.retain(|watched| !watched.done);
+
+        let Some(next_poll) = watched.iter().map(|watched| watched.next_poll).min() else {
+            break;
+        };
+        tokio::select! {
+            _ = sleep_until(next_poll) => {}
+            reason = &mut stop => {
+                stopped(reason, &watched);
+                return Ok(());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Looks the target up afresh, past the cache
+async fn poll<C>(source: &CachingDataSource<C>, target: &Target) -> BlockchainResult<Answer>
+where
+    C: BlockchainDataSource + Send + Sync,
+{
+    match target {
+        Target::Output(outpoint) => {
+            source.invalidate_spending(*outpoint);
+            source
+                .get_spending_transaction(*outpoint)
+                .await
+                .map(Answer::Spender)
+        }
+        Target::Address(address) => {
+            source.invalidate_address(address);
+            source
+                .get_address_transactions(address.clone())
+                .await
+                .map(Answer::History)
+        }
+    }
+}
+
+/// Wait before the next poll of a target after `failures` failed polls in a row:
+/// `interval` doubled per failure, up to `MAX_BACKOFF` (or `interval`, if longer)
+fn backoff(interval: Duration, failures: u32) -> Duration {
+    interval
+        .saturating_mul(1 << failures.min(16))
+        .min(MAX_BACKOFF.max(interval))
+}
+
+/// Resolves on Ctrl-C, never if it cannot be listened for
+async fn interrupted() {
+    if tokio::signal::ctrl_c().await.is_err() {
+        std::future::pending::<()>().await;
+    }
+}
+
+/// Tells why the watch stopped and what it left unresolved
+fn stopped(reason: &str, watched: &[Watched]) {
+    eprintln!("{}, {} targets still watched", reason, watched.len());
+}
+
+/// `<target> spent by <txid>`, then the outputs of the spender
+fn report(
+    out: &mut impl Write,
+    target: &Target,
+    spender: &Transaction,
+    network: Network,
+) -> Result<(), CliError> {
+    writeln!(
+        out,
+        "{} spent by {}\n{}",
+        target,
+        spender.compute_txid(),
+        outputs(spender, network)
+    )
+    .and_then(|()| out.flush())
+    .map_err(io_error("Failed to write to stdout"))
+}
+
+fn load_trace(path: &Path) -> Result<(PathBuf, TraceGraph), CliError> {
+    let json = std::fs::read_to_string(path)
+        .map_err(io_error(format!("Failed to read {}", path.display())))?;
+    let graph = TraceGraph::from_json(&json)
+        .map_err(|e| CliError::InvalidInput(format!("{} is not a trace: {}", path.display(), e)))?;
+    Ok((path.to_path_buf(), graph))
+}
+
+/// Writes `graph` next to `path`, then moves it over, so an interrupted save leaves
+/// the previous trace whole
+fn save_trace(path: &Path, graph: &TraceGraph) -> Result<(), CliError> {
+    let what = format!("Failed to write {}", path.display());
+    let partial = path.with_extension("json.partial");
+    std::fs::write(&partial, graph.to_json() + "\n").map_err(io_error(what.as_str()))?;
+    std::fs::rename(&partial, path).map_err(io_error(what))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_doubles_up_to_the_cap() {
+        let interval = Duration::from_secs(30);
+
+        assert_eq!(backoff(interval, 1), Duration::from_secs(60));
+        assert_eq!(backoff(interval, 3), Duration::from_secs(240));
+        assert_eq!(backoff(interval, 40), MAX_BACKOFF);
+        let hourly = Duration::from_secs(3600);
+        assert_eq!(backoff(hourly, 2), hourly);
+    }
+}
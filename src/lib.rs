@@ -1,2 +1,21 @@
+//! Bitcoin UTXO tracing.
+//!
+//! The core of the crate, built without any feature, holds the types traces are
+//! made of and read back from: `tracer::TraceGraph` and its nodes, edges and
+//! terminals, their JSON, CSV, DOT and GraphML exports, the analyses run on a graph
+//! (peel chains, CoinJoins, change, clustering, taint), and the error types of both
+//! modules along with the `blockchain::BlockchainDataSource` trait. The backends
+//! (`esplora`, `rpc`, `blockbook`, `bitcoind-rest`, `electrum`), the caching layer
+//! (`cache`), the tracer walking a source (`trace-engine`) and the binary (`cli`)
+//! are features, all on by default but `bitcoind-rest` and `electrum`.
+
 pub mod blockchain;
+#[cfg(all(feature = "blocking", not(target_arch = "wasm32")))]
+pub mod blocking;
+#[cfg(all(feature = "cache", feature = "trace-engine"))]
+pub mod service;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod testing;
+#[cfg(feature = "runtime")]
+pub mod time;
 pub mod tracer;
@@ -0,0 +1,242 @@
+//! Tracing as a shared service: one source stack, cache and rate budget serving the
+//! concurrent traces of many tasks, such as the handlers of a web server.
+//!
+//! `PathfinderService` is cheap to clone, every clone sharing the same cache, so it
+//! can be stored in the shared state of an application. With axum:
+//!
+//! ```ignore
+//! use axum::{Json, Router, extract::{Path, State}, http::StatusCode, routing::get};
+//! use pathfinder::blockchain::{CachingDataSource, EsploraClient, RateLimitedDataSource};
+//! use pathfinder::service::PathfinderService;
+//! use pathfinder::tracer::TraceConfig;
+//! use std::time::Duration;
+//!
+//! type Service = PathfinderService<RateLimitedDataSource<EsploraClient>>;
+//!
+//! async fn trace(
+//!     State(service): State<Service>,
+//!     Path(outpoint): Path<String>,
+//! ) -> Result<String, StatusCode> {
+//!     let outpoint = outpoint.parse().map_err(|_| StatusCode::BAD_REQUEST)?;
+//!     let outcome = service
+//!         .trace(outpoint, &TraceConfig::default().max_requests(500))
+//!         .await
+//!         .map_err(|_| StatusCode::BAD_GATEWAY)?;
+//!     Ok(outcome.graph().to_json())
+//! }
+//!
+//! async fn stats(State(service): State<Service>) -> Json<u64> {
+//!     Json(service.stats().traces)
+//! }
+//!
+//! let esplora = EsploraClient::new("https://mempool.space/api");
+//! let limited = RateLimitedDataSource::new(esplora, 10.0, 10);
+//! let service = PathfinderService::new(CachingDataSource::new(limited, Duration::from_secs(300)));
+//! let app = Router::new()
+//!     .route("/trace/{outpoint}", get(trace))
+//!     .route("/stats", get(stats))
+//!     .with_state(service);
+//! ```
+
+use crate::blockchain::{
+    self, BlockchainDataSource, CacheBackend, CacheStats, CachingDataSource, MemoryBackend,
+};
+use crate::tracer::{self, TraceConfig, TraceOutcome, Tracer};
+use bitcoin::{OutPoint, Transaction, Txid};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Counters of a `PathfinderService`, shared by its clones.
+///
+/// # Fields
+/// * `traces` - traces finished, complete or cancelled
+/// * `failed_traces` - traces that ended in an error
+/// * `cache` - the counters of the shared cache
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ServiceStats {
+    pub traces: u64,
+    pub failed_traces: u64,
+    pub cache: CacheStats,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    traces: AtomicU64,
+    failed_traces: AtomicU64,
+}
+
+/// A configured source stack behind a shared cache, tracing for any number of
+/// concurrent callers.
+///
+/// Whatever wraps the backend below the cache (a `RateLimitedDataSource`, a
+/// `FallbackDataSource`) is shared by the clones too, so all traces draw from one
+/// rate budget, and a key looked up by two traces at once is fetched once.
+pub struct PathfinderService<C, B = MemoryBackend> {
+    tracer: Tracer<CachingDataSource<C, B>>,
+    counters: Arc<Counters>,
+}
+
+impl<C, B> Clone for PathfinderService<C, B> {
+    fn clone(&self) -> Self {
+        Self {
+            tracer: self.tracer.clone(),
+            counters: Arc::clone(&self.counters),
+        }
+    }
+}
+
+impl<C, B> PathfinderService<C, B>
+where
+    C: BlockchainDataSource + Send + Sync,
+    B: CacheBackend,
+{
+    /// Serves traces and lookups through `source`
+    pub fn new(source: CachingDataSource<C, B>) -> Self {
+        Self {
+            tracer: Tracer::new(source),
+            counters: Arc::default(),
+        }
+    }
+
+    /// The shared cache, to invalidate or snapshot it
+    pub fn source(&self) -> &CachingDataSource<C, B> {
+        self.tracer.source()
+    }
+
+    /// The tracer, for the traces `trace` does not cover (backward, with events,
+    /// resumed from a checkpoint)
+    pub fn tracer(&self) -> &Tracer<CachingDataSource<C, B>> {
+        &self.tracer
+    }
+
+    /// Traces `root` forward under `config`, as `Tracer::trace_forward`.
+    ///
+    /// Set a `max_requests` budget in `config` to keep one trace from spending the
+    /// shared rate budget of every other caller.
+    ///
+    /// # Errors
+    /// Those of `Tracer::trace_forward`
+    pub async fn trace(
+        &self,
+        root: OutPoint,
+        config: &TraceConfig,
+    ) -> tracer::Result<TraceOutcome> {
+        let result = self.tracer.trace_forward(root, config).await;
+        let counter = match result {
+            Ok(_) => &self.counters.traces,
+            Err(_) => &self.counters.failed_traces,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        result
+    }
+
+    /// The transaction `txid`, from the shared cache when it holds it
+    ///
+    /// # Errors
+    /// Those of the source's `get_transaction`
+    pub async fn get_tx(&self, txid: Txid) -> blockchain::Result<Arc<Transaction>> {
+        self.source().get_transaction_shared(txid).await
+    }
+
+    /// Snapshot of the trace and cache counters
+    pub fn stats(&self) -> ServiceStats {
+        ServiceStats {
+            traces: self.counters.traces.load(Ordering::Relaxed),
+            failed_traces: self.counters.failed_traces.load(Ordering::Relaxed),
+            cache: self.source().stats(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::{EsploraClient, RateLimitedDataSource};
+    use crate::testing::{ChainBuilder, Method, MockDataSource};
+    use std::time::Duration;
+
+    fn assert_shareable<T: Clone + Send + Sync + 'static>() {}
+
+    #[test]
+    fn test_source_stack_is_shareable() {
+        assert_shareable::<EsploraClient>();
+        assert_shareable::<CachingDataSource<EsploraClient>>();
+        assert_shareable::<Tracer<CachingDataSource<EsploraClient>>>();
+        assert_shareable::<PathfinderService<RateLimitedDataSource<EsploraClient>>>();
+    }
+
+    /// Fifty outputs, each the start of a peel chain of two hops, in a source taking
+    /// a moment per lookup so that concurrent traces overlap
+    fn chains() -> (MockDataSource, Vec<OutPoint>) {
+        let mut chain = ChainBuilder::new();
+        let funding = chain.fund(&[1_000_000; 50]);
+        let roots: Vec<OutPoint> = (0..50)
+            .map(|vout| OutPoint::new(funding.compute_txid(), vout))
+            .collect();
+        for &root in &roots {
+            chain.peel_chain(root, 2, 10_000, 1_000);
+        }
+        let mut source = chain.source();
+        source.set_latency(Method::GetTransaction, Duration::from_millis(5));
+        source.set_latency(Method::GetSpendingTransaction, Duration::from_millis(1));
+        (source, roots)
+    }
+
+    async fn trace_all(service: &PathfinderService<MockDataSource>, roots: &[OutPoint]) {
+        let traces: Vec<_> = roots
+            .iter()
+            .map(|&root| {
+                let service = service.clone();
+                tokio::spawn(
+                    async move { service.trace(root, &TraceConfig::default()).await.unwrap() },
+                )
+            })
+            .collect();
+        let outcomes = tokio::time::timeout(
+            Duration::from_secs(30),
+            futures::future::try_join_all(traces),
+        )
+        .await
+        .expect("traces deadlocked")
+        .unwrap();
+        assert!(outcomes.iter().all(|outcome| outcome.graph().len() == 3));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_traces_share_one_cache() {
+        let (source, roots) = chains();
+        let cache = CachingDataSource::new(source, Duration::from_secs(300));
+        let service = PathfinderService::new(cache);
+        let mock = service.source().inner();
+
+        // Each trace looks up the funding transaction they share, then the spenders
+        // of its root and of both outputs of each hop
+        trace_all(&service, &roots).await;
+        let stats = service.stats();
+        assert_eq!(stats.traces, 50);
+        assert_eq!(stats.cache.transaction.lookups(), 50);
+        assert_eq!(stats.cache.spending.lookups(), 50 * 5);
+        assert_eq!(stats.cache.spending.misses, 50 * 5);
+        // Fetched once, whichever trace asked first
+        assert_eq!(mock.calls_to(Method::GetTransaction), 1);
+        assert_eq!(mock.calls_to(Method::GetSpendingTransaction), 50 * 5);
+
+        // Traced again, all but the unspent outputs (not cached by default) are
+        // served from the cache
+        service.source().reset_stats();
+        trace_all(&service, &roots).await;
+        let stats = service.stats().cache;
+        assert_eq!(stats.transaction.hits, 50);
+        assert_eq!(stats.spending.hits, 50 * 2);
+        assert_eq!(stats.spending.misses, 50 * 3);
+        assert_eq!(mock.calls(), 1 + 50 * 5 + 50 * 3);
+
+        let funding = roots[0].txid;
+        assert_eq!(
+            service.get_tx(funding).await.unwrap().compute_txid(),
+            funding
+        );
+        assert_eq!(mock.calls_to(Method::GetTransaction), 1);
+        assert_eq!(service.stats().traces, 100);
+    }
+}
@@ -0,0 +1,56 @@
+//! Offline test infrastructure: an in-memory data source, a builder of consistent
+//! synthetic chains to fill it with, a decorator making any source flaky, and a
+//! clock that only moves when told to.
+//!
+//! Built for the crate's own tests, and for other crates with the `test-utils`
+//! feature, so that traces and caches can be tested without a network.
+//!
+//! ```
+//! use pathfinder::testing::ChainBuilder;
+//! use pathfinder::tracer::{TraceConfig, Tracer};
+//! use bitcoin::OutPoint;
+//!
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() {
+//! let mut chain = ChainBuilder::new();
+//! let funding = chain.fund(&[1_000_000]);
+//! let hops = chain.peel_chain(OutPoint::new(funding.compute_txid(), 0), 3, 50_000, 1_000);
+//! let source = chain.source();
+//!
+//! let root = OutPoint::new(funding.compute_txid(), 0);
+//! let outcome = Tracer::new(&source)
+//!     .trace_forward(root, &TraceConfig::default())
+//!     .await
+//!     .unwrap();
+//! assert_eq!(outcome.graph().len(), 1 + hops.len());
+//! # }
+//! ```
+
+mod chain;
+#[cfg(feature = "runtime")]
+mod chaos;
+#[cfg(feature = "runtime")]
+mod clock;
+#[cfg(feature = "runtime")]
+mod mock;
+
+pub use crate::blockchain::Method;
+pub use chain::ChainBuilder;
+#[cfg(feature = "runtime")]
+pub use chaos::{ChaosDataSource, ChaosStats};
+#[cfg(feature = "runtime")]
+pub use clock::MockClock;
+#[cfg(feature = "runtime")]
+pub use mock::MockDataSource;
+
+/// Distinct empty transaction per `n`, varied through the locktime, for the cache
+/// tests that need some transaction but not a chain of them
+#[cfg(all(test, feature = "cache"))]
+pub(crate) fn tx(n: u32) -> bitcoin::Transaction {
+    bitcoin::Transaction {
+        version: bitcoin::transaction::Version::TWO,
+        lock_time: bitcoin::absolute::LockTime::from_consensus(n),
+        input: vec![],
+        output: vec![],
+    }
+}
@@ -0,0 +1,255 @@
+//! Synthetic transaction chains, consistent down to their txids.
+
+#[cfg(feature = "runtime")]
+use crate::testing::MockDataSource;
+use bitcoin::{
+    Amount, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid, WPubkeyHash, Witness,
+    absolute::LockTime, hashes::Hash, transaction::Version,
+};
+use std::collections::HashMap;
+
+/// Builds chains of real, serializable transactions: every txid is computed from the
+/// transaction, every input spends an output the builder made (or one outside the
+/// chain, for funding transactions), and no transaction pays out more than it spends.
+///
+/// Each transaction gets a locktime of its own, so that otherwise identical ones are
+/// distinct, and each output a P2WPKH script of its own, so that no address is reused
+/// unless a test sets one.
+#[derive(Debug, Clone, Default)]
+pub struct ChainBuilder {
+    txs: Vec<Transaction>,
+    values: HashMap<OutPoint, Amount>,
+    scripts: u32,
+}
+
+impl ChainBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Transactions built so far, in the order they were
+    pub fn transactions(&self) -> &[Transaction] {
+        &self.txs
+    }
+
+    /// Value of `outpoint`, if the builder made it
+    pub fn value(&self, outpoint: OutPoint) -> Option<Amount> {
+        self.values.get(&outpoint).copied()
+    }
+
+    /// `MockDataSource` holding every transaction built so far
+    #[cfg(feature = "runtime")]
+    pub fn source(&self) -> MockDataSource {
+        MockDataSource::new(&self.txs)
+    }
+
+    /// Transaction paying `values` sats out of a coin from outside the chain, which
+    /// lookups do not find, such as an exchange withdrawal
+    pub fn fund(&mut self, values: &[u64]) -> Transaction {
+        // Made-up prevouts, told apart by their vout
+        let outside = OutPoint::new(Txid::all_zeros(), self.txs.len() as u32);
+        self.push(&[outside], values)
+    }
+
+    /// Coinbase transaction paying `values` sats
+    pub fn coinbase(&mut self, values: &[u64]) -> Transaction {
+        self.push(&[OutPoint::null()], values)
+    }
+
+    /// Transaction spending `inputs` into outputs of `values` sats
+    ///
+    /// # Panics
+    /// If an input is no output of the builder, or `values` add up to more than the
+    /// inputs
+    pub fn spend(&mut self, inputs: &[OutPoint], values: &[u64]) -> Transaction {
+        let spent: Amount = inputs.iter().map(|&input| self.spendable(input)).sum();
+        let paid: Amount = values.iter().copied().map(Amount::from_sat).sum();
+        assert!(
+            paid <= spent,
+            "outputs of {} exceed inputs of {}",
+            paid,
+            spent
+        );
+        self.push(inputs, values)
+    }
+
+    /// Peel chain of `hops` transactions from `from`: each pays `peel` sats to a new
+    /// address on output 1 and the rest, less `fee`, on output 0, which the next hop
+    /// spends. The last hop's output 0 is left unspent.
+    pub fn peel_chain(
+        &mut self,
+        from: OutPoint,
+        hops: usize,
+        peel: u64,
+        fee: u64,
+    ) -> Vec<Transaction> {
+        let mut previous = from;
+        (0..hops)
+            .map(|_| {
+                let rest = self.spendable(previous).to_sat() - peel - fee;
+                let hop = self.spend(&[previous], &[rest, peel]);
+                previous = OutPoint::new(hop.compute_txid(), 0);
+                hop
+            })
+            .collect()
+    }
+
+    /// Transaction splitting `from`, less `fee`, into `outputs` outputs of equal value,
+    /// the remainder of the split going to output 0
+    pub fn fan_out(&mut self, from: OutPoint, outputs: usize, fee: u64) -> Transaction {
+        let total = self.spendable(from).to_sat() - fee;
+        let share = total / outputs as u64;
+        let mut values = vec![share; outputs];
+        values[0] += total - share * outputs as u64;
+        self.spend(&[from], &values)
+    }
+
+    /// CoinJoin-shaped transaction: each of `inputs` pays `denomination` sats to an
+    /// output of its own, and what it has left, less `fee`, to change. The equal
+    /// outputs come first, then the change, none when an input pays the denomination
+    /// and fee exactly.
+    pub fn coinjoin(&mut self, inputs: &[OutPoint], denomination: u64, fee: u64) -> Transaction {
+        let mut values = vec![denomination; inputs.len()];
+        for &input in inputs {
+            let change = self.spendable(input).to_sat() - denomination - fee;
+            if change > 0 {
+                values.push(change);
+            }
+        }
+        self.spend(inputs, &values)
+    }
+
+    /// Value of `outpoint`, which must be an output of the builder
+    fn spendable(&self, outpoint: OutPoint) -> Amount {
+        self.value(outpoint)
+            .unwrap_or_else(|| panic!("{} is no output of the chain", outpoint))
+    }
+
+    fn push(&mut self, inputs: &[OutPoint], values: &[u64]) -> Transaction {
+        let tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::from_consensus(self.txs.len() as u32),
+            input: inputs
+                .iter()
+                .map(|&previous_output| TxIn {
+                    previous_output,
+                    script_sig: ScriptBuf::new(),
+                    sequence: Sequence::MAX,
+                    witness: Witness::new(),
+                })
+                .collect(),
+            output: values
+                .iter()
+                .map(|&sats| TxOut {
+                    value: Amount::from_sat(sats),
+                    script_pubkey: self.script(),
+                })
+                .collect(),
+        };
+        let txid = tx.compute_txid();
+        for (vout, output) in tx.output.iter().enumerate() {
+            self.values
+                .insert(OutPoint::new(txid, vout as u32), output.value);
+        }
+        self.txs.push(tx.clone());
+        tx
+    }
+
+    /// P2WPKH script of a key hash not used before
+    fn script(&mut self) -> ScriptBuf {
+        self.scripts += 1;
+        let mut hash = [0xc4; 20];
+        hash[..4].copy_from_slice(&self.scripts.to_be_bytes());
+        ScriptBuf::new_p2wpkh(&WPubkeyHash::from_byte_array(hash))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tracer::peel::guess_change;
+    use crate::tracer::{CoinJoinDetector, CoinJoinKind};
+    use bitcoin::consensus::encode::{deserialize, serialize};
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_txids_are_those_of_the_serialized_transactions() {
+        let mut chain = ChainBuilder::new();
+        let a = chain.fund(&[10_000]);
+        let b = chain.fund(&[10_000]);
+        let spender = chain.spend(&[OutPoint::new(a.compute_txid(), 0)], &[9_000]);
+
+        for tx in chain.transactions() {
+            let back: Transaction = deserialize(&serialize(tx)).unwrap();
+            assert_eq!(back.compute_txid(), tx.compute_txid());
+        }
+        // Alike but for their funding, yet distinct, down to their scripts
+        assert_ne!(a.compute_txid(), b.compute_txid());
+        assert_ne!(a.output[0].script_pubkey, b.output[0].script_pubkey);
+        assert_eq!(
+            chain.value(OutPoint::new(spender.compute_txid(), 0)),
+            Some(Amount::from_sat(9_000))
+        );
+        assert_eq!(chain.transactions().len(), 3);
+    }
+
+    #[test]
+    #[should_panic(expected = "exceed inputs")]
+    fn test_outputs_cannot_exceed_inputs() {
+        let mut chain = ChainBuilder::new();
+        let funding = chain.fund(&[10_000]);
+        chain.spend(&[OutPoint::new(funding.compute_txid(), 0)], &[10_001]);
+    }
+
+    #[test]
+    fn test_peel_chains_and_fan_outs_keep_their_values() {
+        let mut chain = ChainBuilder::new();
+        let funding = chain.fund(&[1_000_000]);
+        let root = OutPoint::new(funding.compute_txid(), 0);
+
+        let hops = chain.peel_chain(root, 3, 100_000, 1_000);
+        let fan = chain.fan_out(OutPoint::new(hops[2].compute_txid(), 1), 3, 999);
+
+        let rests: Vec<u64> = hops
+            .iter()
+            .map(|hop| hop.output[0].value.to_sat())
+            .collect();
+        assert_eq!(rests, [899_000, 798_000, 697_000]);
+        assert!(
+            hops.iter()
+                .all(|hop| hop.output[1].value.to_sat() == 100_000)
+        );
+        assert_eq!(
+            hops[1].input[0].previous_output.txid,
+            hops[0].compute_txid()
+        );
+        let split: Vec<u64> = fan.output.iter().map(|out| out.value.to_sat()).collect();
+        assert_eq!(split, [33_001, 33_000, 33_000]);
+        // Round payments, the change carrying the rest
+        let guess = guess_change(&hops[1].output, &hops[0].output[..1]).unwrap();
+        assert_eq!(guess.vout, 0);
+    }
+
+    #[test]
+    fn test_coinjoins_are_detected() {
+        let mut chain = ChainBuilder::new();
+        let inputs: Vec<OutPoint> = [120_000, 150_000, 100_500, 200_000, 130_000]
+            .iter()
+            .map(|&value| OutPoint::new(chain.fund(&[value]).compute_txid(), 0))
+            .collect();
+
+        let coinjoin = chain.coinjoin(&inputs, 100_000, 500);
+
+        assert_eq!(coinjoin.output.len(), 5 + 4);
+        let scripts: HashSet<_> = coinjoin
+            .output
+            .iter()
+            .map(|out| &out.script_pubkey)
+            .collect();
+        assert_eq!(scripts.len(), coinjoin.output.len());
+        let verdict = CoinJoinDetector::default().detect(&coinjoin, &[]).unwrap();
+        assert_eq!(verdict.equal_outputs, 5);
+        assert_eq!(verdict.denomination, Amount::from_sat(100_000));
+        assert_ne!(verdict.kind, CoinJoinKind::Whirlpool);
+    }
+}
@@ -0,0 +1,317 @@
+//! Decorator injecting failures, latencies and rate limits into any data source.
+
+use crate::blockchain::{
+    BlockchainDataSource, BlockchainError, CacheKey, Method, Result, TxMetadata, TxStatus,
+};
+use async_trait::async_trait;
+use bitcoin::{Address, Block, BlockHash, OutPoint, Transaction, Txid, block::Header};
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// What a `ChaosDataSource` injected so far.
+///
+/// # Fields
+/// * `calls` - calls made to it, those it failed included
+/// * `failures` - calls failed with `NetworkFailure`
+/// * `delays` - calls slowed by the injected latency
+/// * `rate_limits` - calls failed with `RateLimited`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChaosStats {
+    pub calls: usize,
+    pub failures: usize,
+    pub delays: usize,
+    pub rate_limits: usize,
+}
+
+/// Wraps a source (usually a `MockDataSource`) in the flakiness of a real backend,
+/// to test how traces and wrappers ride it out.
+///
+/// Each call may be slowed, then failed with `NetworkFailure`, as drawn from the
+/// seed, the call's method and arguments, and how many times they were asked
+/// before: the same calls fail the same way on every run, whatever order concurrent
+/// lookups come in, and a retried call draws anew. Every `n`th call, counting all
+/// methods, fails with `RateLimited` instead. Failed calls never reach the inner
+/// source.
+///
+/// ```ignore
+/// let source = ChaosDataSource::new(chain.source(), 7)
+///     .failure_rate(0.1)
+///     .latency(0.5, Duration::from_millis(200))
+///     .rate_limit_every(20);
+/// ```
+#[derive(Debug)]
+pub struct ChaosDataSource<D> {
+    inner: D,
+    seed: u64,
+    failure_rate: f64,
+    latency: Option<(f64, Duration)>,
+    rate_limit_every: Option<usize>,
+    /// Times each call, by the hash of its method and arguments, was made
+    attempts: Mutex<HashMap<u64, u64>>,
+    calls: AtomicUsize,
+    stats: Mutex<ChaosStats>,
+}
+
+impl<D: BlockchainDataSource + Sync> ChaosDataSource<D> {
+    /// Wraps `inner`, injecting nothing until told to; `seed` picks which calls
+    /// the injected faults hit
+    pub fn new(inner: D, seed: u64) -> Self {
+        Self {
+            inner,
+            seed,
+            failure_rate: 0.0,
+            latency: None,
+            rate_limit_every: None,
+            attempts: Mutex::new(HashMap::new()),
+            calls: AtomicUsize::new(0),
+            stats: Mutex::new(ChaosStats::default()),
+        }
+    }
+
+    /// Fails calls with `NetworkFailure` with probability `rate`
+    ///
+    /// # Panics
+    /// If `rate` is not within 0 and 1
+    pub fn failure_rate(mut self, rate: f64) -> Self {
+        assert!(
+            (0.0..=1.0).contains(&rate),
+            "failure rate {} out of 0..=1",
+            rate
+        );
+        self.failure_rate = rate;
+        self
+    }
+
+    /// Slows calls by `delay` with probability `rate`
+    ///
+    /// # Panics
+    /// If `rate` is not within 0 and 1
+    pub fn latency(mut self, rate: f64, delay: Duration) -> Self {
+        assert!(
+            (0.0..=1.0).contains(&rate),
+            "latency rate {} out of 0..=1",
+            rate
+        );
+        self.latency = Some((rate, delay));
+        self
+    }
+
+    /// Fails every `n`th call with `RateLimited`, no wait asked
+    ///
+    /// # Panics
+    /// If `n` is 0
+    pub fn rate_limit_every(mut self, n: usize) -> Self {
+        assert!(n > 0, "rate limit every 0 calls");
+        self.rate_limit_every = Some(n);
+        self
+    }
+
+    /// The wrapped source
+    pub fn inner(&self) -> &D {
+        &self.inner
+    }
+
+    /// What was injected so far
+    pub fn stats(&self) -> ChaosStats {
+        *self.stats.lock().unwrap()
+    }
+
+    /// Counts a call of `method` with arguments `args`, then slows or fails it as
+    /// drawn
+    async fn chaos(&self, method: Method, args: impl Hash) -> Result<()> {
+        let call = self.calls.fetch_add(1, Ordering::SeqCst) + 1;
+        let key = hash((self.seed, method, args));
+        let attempt = {
+            let mut attempts = self.attempts.lock().unwrap();
+            let attempt = attempts.entry(key).or_default();
+            *attempt += 1;
+            *attempt
+        };
+        // Both drawn whatever is set, so that failures do not move with the latency
+        let mut rng = fastrand::Rng::with_seed(hash((key, attempt)));
+        let (slow, fail) = (rng.f64(), rng.f64());
+        self.stats.lock().unwrap().calls += 1;
+
+        if self
+            .rate_limit_every
+            .is_some_and(|n| call.is_multiple_of(n))
+        {
+            self.stats.lock().unwrap().rate_limits += 1;
+            return Err(BlockchainError::RateLimited { retry_after: None });
+        }
+        if let Some((rate, delay)) = self.latency
+            && slow < rate
+        {
+            self.stats.lock().unwrap().delays += 1;
+            crate::time::sleep(delay).await;
+        }
+        if fail < self.failure_rate {
+            self.stats.lock().unwrap().failures += 1;
+            return Err(BlockchainError::NetworkFailure(format!(
+                "injected failure of {} (call {})",
+                method, call
+            )));
+        }
+        Ok(())
+    }
+}
+
+fn hash(value: impl Hash) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+impl<D: BlockchainDataSource + Sync> BlockchainDataSource for ChaosDataSource<D> {
+    async fn get_transaction(&self, txid: Txid) -> Result<Transaction> {
+        self.chaos(Method::GetTransaction, txid).await?;
+        self.inner.get_transaction(txid).await
+    }
+
+    async fn get_spending_transaction(&self, outpoint: OutPoint) -> Result<Option<Transaction>> {
+        self.chaos(Method::GetSpendingTransaction, outpoint).await?;
+        self.inner.get_spending_transaction(outpoint).await
+    }
+
+    async fn get_address_transactions(&self, address: Address) -> Result<Vec<Transaction>> {
+        self.chaos(Method::GetAddressTransactions, &address).await?;
+        self.inner.get_address_transactions(address).await
+    }
+
+    async fn get_address_utxos(&self, address: Address) -> Result<Vec<OutPoint>> {
+        self.chaos(Method::GetAddressUtxos, &address).await?;
+        self.inner.get_address_utxos(address).await
+    }
+
+    async fn get_transactions_batch(&self, txids: &[Txid]) -> Result<Vec<Option<Transaction>>> {
+        self.chaos(Method::GetTransactionsBatch, txids).await?;
+        self.inner.get_transactions_batch(txids).await
+    }
+
+    async fn get_spending_transactions_batch(
+        &self,
+        outpoints: &[OutPoint],
+    ) -> Result<Vec<Option<Transaction>>> {
+        self.chaos(Method::GetSpendingTransactionsBatch, outpoints)
+            .await?;
+        self.inner.get_spending_transactions_batch(outpoints).await
+    }
+
+    async fn is_spent(&self, outpoint: OutPoint) -> Result<bool> {
+        self.chaos(Method::IsSpent, outpoint).await?;
+        self.inner.is_spent(outpoint).await
+    }
+
+    async fn get_block_raw(&self, block_hash: BlockHash) -> Result<Block> {
+        self.chaos(Method::GetBlockRaw, block_hash).await?;
+        self.inner.get_block_raw(block_hash).await
+    }
+
+    async fn get_transaction_status(&self, txid: Txid) -> Result<TxStatus> {
+        self.chaos(Method::GetTransactionStatus, txid).await?;
+        self.inner.get_transaction_status(txid).await
+    }
+
+    async fn get_block_header(&self, block_hash: BlockHash) -> Result<Header> {
+        self.chaos(Method::GetBlockHeader, block_hash).await?;
+        self.inner.get_block_header(block_hash).await
+    }
+
+    async fn get_transaction_with_metadata(&self, txid: Txid) -> Result<(Transaction, TxMetadata)> {
+        self.chaos(Method::GetTransactionWithMetadata, txid).await?;
+        self.inner.get_transaction_with_metadata(txid).await
+    }
+
+    fn is_cached(&self, key: &CacheKey) -> bool {
+        self.inner.is_cached(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::ChainBuilder;
+
+    /// Which of the first `calls` spender lookups of each of `outpoints` fail
+    async fn failures(
+        source: &ChaosDataSource<impl BlockchainDataSource + Sync>,
+        outpoints: &[OutPoint],
+    ) -> Vec<bool> {
+        let mut failed = Vec::new();
+        for &outpoint in outpoints {
+            for _ in 0..3 {
+                failed.push(source.get_spending_transaction(outpoint).await.is_err());
+            }
+        }
+        failed
+    }
+
+    #[tokio::test]
+    async fn test_faults_are_drawn_from_the_seed_and_the_call() {
+        let mut chain = ChainBuilder::new();
+        let funding = chain.fund(&[10_000; 40]);
+        let outpoints: Vec<OutPoint> = (0..40)
+            .map(|vout| OutPoint::new(funding.compute_txid(), vout))
+            .collect();
+
+        let source = ChaosDataSource::new(chain.source(), 1).failure_rate(0.3);
+        let first = failures(&source, &outpoints).await;
+        // In another order, and slowed, the same calls fail
+        let source = ChaosDataSource::new(chain.source(), 1)
+            .failure_rate(0.3)
+            .latency(1.0, Duration::ZERO);
+        let mut reversed: Vec<OutPoint> = outpoints.clone();
+        reversed.reverse();
+        let mut again = failures(&source, &reversed).await;
+        again.reverse();
+        let by_outpoint = |failed: &[bool]| -> Vec<Vec<bool>> {
+            failed.chunks(3).map(|chunk| chunk.to_vec()).collect()
+        };
+        let mut expected = by_outpoint(&first);
+        expected.iter_mut().for_each(|attempts| attempts.reverse());
+        assert_eq!(by_outpoint(&again), expected);
+
+        let failed = first.iter().filter(|&&failed| failed).count();
+        assert!((20..=52).contains(&failed), "{} of 120 failed", failed);
+        assert_eq!(source.stats().delays, 120);
+        let other = ChaosDataSource::new(chain.source(), 2).failure_rate(0.3);
+        assert_ne!(failures(&other, &outpoints).await, first);
+        // Failed calls do not reach the inner source
+        assert_eq!(other.inner().calls(), 120 - other.stats().failures);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_rate_limits_and_latency_are_injected() {
+        let mut chain = ChainBuilder::new();
+        let funding = chain.fund(&[10_000]);
+        let txid = funding.compute_txid();
+        let source = ChaosDataSource::new(chain.source(), 3)
+            .latency(1.0, Duration::from_millis(250))
+            .rate_limit_every(3);
+
+        let start = tokio::time::Instant::now();
+        let mut limited = Vec::new();
+        for _ in 0..6 {
+            let result = source.get_transaction(txid).await;
+            limited.push(matches!(result, Err(BlockchainError::RateLimited { .. })));
+        }
+
+        assert_eq!(limited, [false, false, true, false, false, true]);
+        // Rate limited calls answer at once
+        assert_eq!(start.elapsed(), Duration::from_millis(4 * 250));
+        assert_eq!(
+            source.stats(),
+            ChaosStats {
+                calls: 6,
+                failures: 0,
+                delays: 4,
+                rate_limits: 2,
+            }
+        );
+    }
+}
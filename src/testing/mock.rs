@@ -0,0 +1,319 @@
+//! In-memory `BlockchainDataSource`, with injectable failures and latencies.
+
+use crate::blockchain::{BlockchainDataSource, BlockchainError, Method, Result, TxStatus};
+use async_trait::async_trait;
+use bitcoin::{Address, BlockHash, OutPoint, Transaction, Txid, block::Header};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+    time::Duration,
+};
+
+/// In-memory chain: transactions by txid, spenders by outpoint, and the statuses and
+/// block headers set on it (transactions without a status are unconfirmed).
+///
+/// Every call is counted by `Method`. A call first waits out the latency set on its
+/// method, then fails with the next error queued for it by `fail_next`, if any.
+/// Address lookups find the transactions paying the address; unspent outputs of an
+/// address are derived from them, as `BlockchainDataSource::get_address_utxos` does.
+#[derive(Debug, Default)]
+pub struct MockDataSource {
+    txs: HashMap<Txid, Transaction>,
+    spenders: HashMap<OutPoint, Txid>,
+    statuses: HashMap<Txid, TxStatus>,
+    headers: HashMap<BlockHash, Header>,
+    latencies: HashMap<Method, Duration>,
+    errors: Mutex<HashMap<Method, VecDeque<BlockchainError>>>,
+    calls: Mutex<HashMap<Method, usize>>,
+}
+
+impl MockDataSource {
+    /// Source holding `txs`, each registered as the spender of its inputs
+    pub fn new(txs: &[Transaction]) -> Self {
+        let mut source = Self::default();
+        for tx in txs {
+            source.add(tx.clone());
+        }
+        source
+    }
+
+    /// Adds `tx`, registered as the spender of its inputs
+    pub fn add(&mut self, tx: Transaction) -> Txid {
+        let txid = tx.compute_txid();
+        for input in &tx.input {
+            if !input.previous_output.is_null() {
+                self.spenders.insert(input.previous_output, txid);
+            }
+        }
+        self.txs.insert(txid, tx);
+        txid
+    }
+
+    /// Adds the transaction of consensus-encoded `hex`, as `add` does
+    ///
+    /// # Errors
+    /// - `Decode` - `hex` is no transaction
+    pub fn add_hex(&mut self, hex: &str) -> Result<Txid> {
+        let tx = bitcoin::consensus::encode::deserialize_hex(hex.trim())
+            .map_err(|e| BlockchainError::decode("transaction hex", e))?;
+        Ok(self.add(tx))
+    }
+
+    /// Makes `spender` the transaction spending `outpoint`, whatever its inputs say; it
+    /// is answered once `spender` is added
+    pub fn set_spender(&mut self, outpoint: OutPoint, spender: Txid) {
+        self.spenders.insert(outpoint, spender);
+    }
+
+    /// Leaves `outpoint` unspent, even if a transaction added spends it
+    pub fn unspend(&mut self, outpoint: OutPoint) {
+        self.spenders.remove(&outpoint);
+    }
+
+    /// Sets the status of `txid`
+    pub fn confirm(&mut self, txid: Txid, status: TxStatus) {
+        self.statuses.insert(txid, status);
+    }
+
+    pub fn add_header(&mut self, header: Header) {
+        self.headers.insert(header.block_hash(), header);
+    }
+
+    /// Makes every call of `method` take `latency` before answering
+    pub fn set_latency(&mut self, method: Method, latency: Duration) {
+        self.latencies.insert(method, latency);
+    }
+
+    /// Makes the next call of `method` not failed yet fail with `error`. Errors queued
+    /// for a method are returned in turn, one per call, before it answers again.
+    pub fn fail_next(&self, method: Method, error: BlockchainError) {
+        self.errors
+            .lock()
+            .unwrap()
+            .entry(method)
+            .or_default()
+            .push_back(error);
+    }
+
+    /// Calls made to the source so far
+    pub fn calls(&self) -> usize {
+        self.calls.lock().unwrap().values().sum()
+    }
+
+    /// Calls of `method` made so far
+    pub fn calls_to(&self, method: Method) -> usize {
+        self.calls
+            .lock()
+            .unwrap()
+            .get(&method)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Counts a call of `method`, waits out its latency and fails it if an error is
+    /// queued
+    async fn call(&self, method: Method) -> Result<()> {
+        *self.calls.lock().unwrap().entry(method).or_default() += 1;
+        if let Some(&latency) = self.latencies.get(&method) {
+            crate::time::sleep(latency).await;
+        }
+        let error = self
+            .errors
+            .lock()
+            .unwrap()
+            .get_mut(&method)
+            .and_then(VecDeque::pop_front);
+        match error {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+
+    fn spender(&self, outpoint: &OutPoint) -> Result<Option<Transaction>> {
+        let Some(txid) = self.spenders.get(outpoint) else {
+            return Ok(None);
+        };
+        match self.txs.get(txid) {
+            Some(tx) => Ok(Some(tx.clone())),
+            None => Err(BlockchainError::DataInconsistency(format!(
+                "{} is spent by {}, which is not in the source",
+                outpoint, txid
+            ))),
+        }
+    }
+}
+
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+impl BlockchainDataSource for MockDataSource {
+    async fn get_transaction(&self, txid: Txid) -> Result<Transaction> {
+        self.call(Method::GetTransaction).await?;
+        self.txs
+            .get(&txid)
+            .cloned()
+            .ok_or_else(|| BlockchainError::NotFound(txid.to_string()))
+    }
+
+    async fn get_spending_transaction(&self, outpoint: OutPoint) -> Result<Option<Transaction>> {
+        self.call(Method::GetSpendingTransaction).await?;
+        self.spender(&outpoint)
+    }
+
+    async fn get_address_transactions(&self, address: Address) -> Result<Vec<Transaction>> {
+        self.call(Method::GetAddressTransactions).await?;
+        let script = address.script_pubkey();
+        Ok(self
+            .txs
+            .values()
+            .filter(|tx| tx.output.iter().any(|out| out.script_pubkey == script))
+            .cloned()
+            .collect())
+    }
+
+    async fn get_transactions_batch(&self, txids: &[Txid]) -> Result<Vec<Option<Transaction>>> {
+        self.call(Method::GetTransactionsBatch).await?;
+        Ok(txids
+            .iter()
+            .map(|txid| self.txs.get(txid).cloned())
+            .collect())
+    }
+
+    async fn get_spending_transactions_batch(
+        &self,
+        outpoints: &[OutPoint],
+    ) -> Result<Vec<Option<Transaction>>> {
+        self.call(Method::GetSpendingTransactionsBatch).await?;
+        outpoints
+            .iter()
+            .map(|outpoint| self.spender(outpoint))
+            .collect()
+    }
+
+    async fn get_transaction_status(&self, txid: Txid) -> Result<TxStatus> {
+        self.call(Method::GetTransactionStatus).await?;
+        if !self.txs.contains_key(&txid) {
+            return Err(BlockchainError::NotFound(txid.to_string()));
+        }
+        Ok(self
+            .statuses
+            .get(&txid)
+            .copied()
+            .unwrap_or_else(TxStatus::unconfirmed))
+    }
+
+    async fn get_block_header(&self, block_hash: BlockHash) -> Result<Header> {
+        self.call(Method::GetBlockHeader).await?;
+        self.headers
+            .get(&block_hash)
+            .copied()
+            .ok_or_else(|| BlockchainError::NotFound(block_hash.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::ChainBuilder;
+    use bitcoin::consensus::encode::serialize_hex;
+
+    #[tokio::test]
+    async fn test_calls_are_counted_by_method() {
+        let mut chain = ChainBuilder::new();
+        let funding = chain.fund(&[50_000]);
+        let root = OutPoint::new(funding.compute_txid(), 0);
+        let spender = chain.spend(&[root], &[49_000]);
+        let source = chain.source();
+
+        assert_eq!(
+            source.get_spending_transaction(root).await.unwrap(),
+            Some(spender.clone())
+        );
+        let unspent = OutPoint::new(spender.compute_txid(), 0);
+        assert_eq!(
+            source.get_spending_transaction(unspent).await.unwrap(),
+            None
+        );
+        assert_eq!(
+            source
+                .get_transactions_batch(&[
+                    funding.compute_txid(),
+                    Txid::from_raw_hash(bitcoin::hashes::Hash::all_zeros())
+                ])
+                .await
+                .unwrap(),
+            [Some(funding), None]
+        );
+
+        assert_eq!(source.calls(), 3);
+        assert_eq!(source.calls_to(Method::GetSpendingTransaction), 2);
+        assert_eq!(source.calls_to(Method::GetTransactionsBatch), 1);
+        assert_eq!(source.calls_to(Method::GetTransaction), 0);
+    }
+
+    #[tokio::test]
+    async fn test_transactions_load_from_hex_and_spenders_can_be_set() {
+        let mut chain = ChainBuilder::new();
+        let funding = chain.fund(&[50_000, 20_000]);
+        let spender = chain.spend(&[OutPoint::new(funding.compute_txid(), 0)], &[49_000]);
+        let mut source = MockDataSource::default();
+
+        let txid = source.add_hex(&serialize_hex(&funding)).unwrap();
+        assert_eq!(txid, funding.compute_txid());
+        assert!(source.add_hex("00zz").unwrap_err().is_decode());
+        // Output 1 is said to be spent by a transaction the source does not hold yet
+        let other = OutPoint::new(txid, 1);
+        source.set_spender(other, spender.compute_txid());
+        assert!(matches!(
+            source.get_spending_transaction(other).await,
+            Err(BlockchainError::DataInconsistency(_))
+        ));
+        source.add(spender.clone());
+
+        assert_eq!(source.get_transaction(txid).await.unwrap(), funding);
+        assert_eq!(
+            source.get_spending_transaction(other).await.unwrap(),
+            Some(spender.clone())
+        );
+        source.unspend(OutPoint::new(txid, 0));
+        assert_eq!(
+            source
+                .get_spending_transaction(OutPoint::new(txid, 0))
+                .await
+                .unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_failures_and_latencies_are_injected_per_method() {
+        let mut chain = ChainBuilder::new();
+        let funding = chain.fund(&[50_000]);
+        let txid = funding.compute_txid();
+        let mut source = chain.source();
+        source.set_latency(Method::GetTransaction, Duration::from_millis(250));
+        source.fail_next(
+            Method::GetTransaction,
+            BlockchainError::NetworkFailure("one".into()),
+        );
+        source.fail_next(
+            Method::GetTransaction,
+            BlockchainError::RateLimited { retry_after: None },
+        );
+
+        let start = tokio::time::Instant::now();
+        assert!(matches!(
+            source.get_transaction(txid).await,
+            Err(BlockchainError::NetworkFailure(_))
+        ));
+        assert!(matches!(
+            source.get_transaction(txid).await,
+            Err(BlockchainError::RateLimited { .. })
+        ));
+        assert_eq!(source.get_transaction(txid).await.unwrap(), funding);
+        // Other methods answer at once, without failing
+        assert!(source.get_transaction_status(txid).await.is_ok());
+
+        assert_eq!(start.elapsed(), Duration::from_millis(750));
+        assert_eq!(source.calls_to(Method::GetTransaction), 3);
+    }
+}
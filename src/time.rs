@@ -0,0 +1,74 @@
+//! Time as the crate reads it: the `Clock` the cache, retries, rate limiter and
+//! tracer take the time from and sleep on, `SystemClock` unless one is given.
+//!
+//! Tests give them a `testing::MockClock` to expire entries and wait out backoffs
+//! without waiting. Native builds otherwise use tokio's timers, whose clock tests can
+//! also pause and advance. In the browser tokio has no timer and
+//! `std::time::Instant::now` panics, so wasm builds use `wasmtimer`'s, which mirror
+//! tokio's API over the JavaScript clock.
+
+use async_trait::async_trait;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use tokio::time::Instant;
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) use tokio::time::sleep;
+#[cfg(all(feature = "cache", not(target_arch = "wasm32")))]
+pub(crate) use tokio::time::{MissedTickBehavior, interval};
+#[cfg(target_arch = "wasm32")]
+pub use wasmtimer::std::Instant;
+#[cfg(target_arch = "wasm32")]
+pub(crate) use wasmtimer::tokio::sleep;
+#[cfg(all(feature = "cache", target_arch = "wasm32"))]
+pub(crate) use wasmtimer::tokio::{MissedTickBehavior, interval};
+
+/// Source of the current time, and of waits.
+///
+/// Clones of a source, cache or tracer share the clock it was given.
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+pub trait Clock: fmt::Debug + Send + Sync {
+    /// The current time
+    fn now(&self) -> Instant;
+
+    /// Returns once `duration` has passed on this clock
+    async fn sleep(&self, duration: Duration);
+}
+
+/// The platform's clock: tokio's natively, the browser's on wasm
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    async fn sleep(&self, duration: Duration) {
+        sleep(duration).await;
+    }
+}
+
+/// `SystemClock`, as the default clock of whatever takes one
+pub(crate) fn system() -> Arc<dyn Clock> {
+    Arc::new(SystemClock)
+}
+
+/// Runs `future` in the background, on tokio natively and on the browser's event
+/// loop on wasm
+#[cfg(all(feature = "cache", not(target_arch = "wasm32")))]
+pub(crate) fn spawn(future: impl Future<Output = ()> + Send + 'static) {
+    tokio::spawn(future);
+}
+
+/// Runs `future` in the background, on tokio natively and on the browser's event
+/// loop on wasm
+#[cfg(all(feature = "cache", target_arch = "wasm32"))]
+pub(crate) fn spawn(future: impl Future<Output = ()> + 'static) {
+    wasm_bindgen_futures::spawn_local(future);
+}
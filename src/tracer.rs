@@ -1,6 +1,93 @@
+pub mod annotate;
+#[cfg(feature = "trace-engine")]
+pub mod cancel;
+pub mod change;
+#[cfg(feature = "trace-engine")]
+pub mod checkpoint;
+pub mod cluster;
+pub mod coinbase;
+pub mod coinjoin;
+#[cfg(feature = "trace-engine")]
+pub mod config;
+pub mod csv;
+pub mod diff;
+#[cfg(feature = "petgraph")]
+pub mod digraph;
+pub mod dot;
+pub mod dust;
+#[cfg(feature = "trace-engine")]
 pub mod engine;
 pub mod error;
+#[cfg(feature = "trace-engine")]
+pub mod events;
+#[cfg(test)]
+mod fixtures;
+pub mod graph;
+pub mod graphml;
+#[cfg(feature = "trace-engine")]
+pub mod html;
+pub mod json;
+pub mod labels;
+pub mod lightning;
+#[cfg(feature = "trace-engine")]
+pub mod path;
+pub mod pattern;
+pub mod peel;
+pub mod rbf;
+#[cfg(feature = "trace-engine")]
+pub mod report;
+pub mod reuse;
+pub mod script;
+pub mod stream;
+pub mod summary;
+pub mod table;
+pub mod taint;
+pub mod timelock;
 pub mod types;
+pub mod value_match;
+#[cfg(feature = "trace-engine")]
+pub mod visited;
 
+pub use annotate::{Annotation, Annotators, TraceAnnotator, TraceContext};
+#[cfg(feature = "trace-engine")]
+pub use cancel::CancelToken;
+pub use change::{ChangeContext, ChangeDetector, ChangeScore, ChangeVerdict, ChangeWeights};
+#[cfg(feature = "trace-engine")]
+pub use checkpoint::{CheckpointSchedule, TraceCheckpoint};
+pub use cluster::{ClusterOptions, ClusterStats, Clustering, cluster_addresses};
+pub use coinbase::{CoinbaseOrigin, CoinbaseProvenance, PoolShare};
+pub use coinjoin::{CoinJoinDetector, CoinJoinKind, CoinJoinPolicy, CoinJoinVerdict};
+#[cfg(feature = "trace-engine")]
+pub use config::{BranchStrategy, RetryPolicy, StopCondition, TraceConfig};
+pub use diff::{FrontierSpend, NodeChange, TraceDiff};
+pub use dot::{DotOptions, LabelVerbosity};
+pub use dust::{Dusting, DustingDetector};
+#[cfg(feature = "trace-engine")]
+pub use engine::{TraceOutcome, Tracer};
 pub use error::{Result, TracerError};
+#[cfg(feature = "trace-engine")]
+pub use events::{TraceEvent, TraceEvents};
+pub use graph::{TraceEdge, TraceGraph, TraceNode};
+pub use graphml::GraphmlOptions;
+#[cfg(feature = "trace-engine")]
+pub use html::HtmlOptions;
+pub use labels::{EntityCategory, Label, LabelPolicy, LabelStore};
+pub use lightning::{LightningChannel, LightningPolicy};
+#[cfg(feature = "trace-engine")]
+pub use path::{PathHop, PathOptions, PathWeight, TracePath};
+pub use pattern::{BatchPolicy, PatternClassifier, TxPattern};
+pub use peel::{Confidence, PeelChain, PeelHop};
+pub use rbf::{ReplacementStatus, is_rbf_signaling};
+#[cfg(feature = "trace-engine")]
+pub use report::TraceReport;
+pub use reuse::{AddressReuse, ReuseOccurrence};
+pub use script::{DataCarrier, ScriptClass, classify_script};
+pub use stream::TraceItem;
+pub use summary::{FrontierOutput, TraceSummary};
+pub use table::TablePrinter;
+pub use taint::{FeeTaint, TaintModel, TaintShare};
+pub use timelock::{LocktimeKind, TimelockDetector, locktime_kind, relative_lock};
 pub use types::{Output, Terminal, TerminalReason, TraceResult, TraceStats, TransactionNode};
+pub use value_match::{SpeculativeEdge, ValueMatchFollower};
+#[cfg(feature = "trace-engine")]
+pub use visited::{BloomFilter, Direction, VisitedKey, VisitedSet};
@@ -0,0 +1,345 @@
+//! Custom heuristics run on every transaction of a trace.
+//!
+//! A `TraceAnnotator` looks at each transaction as the trace adds it to the graph and
+//! returns annotations, recorded on its node under keys namespaced by the annotator's
+//! name (`<name>.<key>`) and carried by the exports. A backward trace runs them again
+//! once it has fetched every prevout of a transaction it expands. Annotators run in the order they
+//! are installed with `TraceConfig::annotators`; one that panics leaves
+//! `<name>.error` on the node and the trace goes on.
+//!
+//! The built-in heuristics implement the trait too, so they can be installed to
+//! record their findings as annotations; the trace itself still follows the typed
+//! verdicts of `TraceConfig`, which its policies depend on.
+//!
+//! ```
+//! use bitcoin::{Amount, Transaction};
+//! use pathfinder::tracer::{Annotation, TraceAnnotator, TraceContext};
+//!
+//! /// Flags the outputs worth a round number of bitcoin
+//! struct RoundNumbers;
+//!
+//! impl TraceAnnotator for RoundNumbers {
+//!     fn name(&self) -> &str {
+//!         "round"
+//!     }
+//!
+//!     fn annotate(&self, tx: &Transaction, _ctx: &TraceContext) -> Vec<Annotation> {
+//!         tx.output
+//!             .iter()
+//!             .enumerate()
+//!             .filter(|(_, out)| out.value.to_sat() % Amount::ONE_BTC.to_sat() == 0)
+//!             .map(|(vout, out)| Annotation::new(vout.to_string(), out.value.to_string()))
+//!             .collect()
+//!     }
+//! }
+//!
+//! # #[cfg(feature = "trace-engine")] {
+//! use pathfinder::tracer::TraceConfig;
+//!
+//! let config = TraceConfig::default().annotators(vec![Box::new(RoundNumbers)]);
+//! assert_eq!(config.annotators.names(), ["round"]);
+//! # }
+//! ```
+
+use crate::tracer::{
+    ChangeContext, ChangeDetector, CoinJoinDetector, LabelStore, PatternClassifier, TraceGraph,
+    TxPattern,
+    timelock::{LocktimeKind, TimelockDetector, locktime_kind, relative_lock},
+};
+use bitcoin::{Amount, Transaction, TxOut, relative};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+
+/// What a trace knows around a transaction it annotates.
+///
+/// # Fields
+/// * `prevouts` - the outputs the transaction spends, as far as the trace knows them
+/// * `depth` - hops from the start of the trace
+/// * `graph` - the graph traced so far
+/// * `labels` - the trace's known entities, if it has any
+#[derive(Clone, Copy)]
+pub struct TraceContext<'a> {
+    pub prevouts: &'a [TxOut],
+    pub depth: usize,
+    pub graph: &'a TraceGraph,
+    pub labels: Option<&'a LabelStore>,
+}
+
+/// A finding of an annotator: `value` under `key`, in the annotator's namespace
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Annotation {
+    pub key: String,
+    pub value: String,
+}
+
+impl Annotation {
+    pub fn new(key: impl Into<String>, value: impl Into<String>) -> Self {
+        Self {
+            key: key.into(),
+            value: value.into(),
+        }
+    }
+}
+
+/// A heuristic annotating the transactions of a trace.
+pub trait TraceAnnotator {
+    /// Namespace of the annotator's keys on the nodes
+    fn name(&self) -> &str;
+
+    /// Annotations of `tx`, found `ctx.depth` hops from the start of the trace
+    fn annotate(&self, tx: &Transaction, ctx: &TraceContext) -> Vec<Annotation>;
+}
+
+/// Annotators of a trace, in the order they run.
+///
+/// Configurations compare equal when they share the same annotators.
+#[derive(Clone, Default)]
+pub struct Annotators(Vec<Arc<dyn TraceAnnotator + Send + Sync>>);
+
+impl Annotators {
+    pub fn new(annotators: Vec<Box<dyn TraceAnnotator + Send + Sync>>) -> Self {
+        Self(annotators.into_iter().map(Arc::from).collect())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Names of the annotators, in order
+    pub fn names(&self) -> Vec<&str> {
+        self.0.iter().map(|annotator| annotator.name()).collect()
+    }
+
+    /// Annotations of `tx` by every annotator, under their namespaced keys. A later
+    /// annotator overwrites the keys of an earlier one of the same name; one panicking
+    /// leaves `<name>.error` instead of its annotations.
+    pub fn run(&self, tx: &Transaction, ctx: &TraceContext) -> BTreeMap<String, String> {
+        let mut annotations = BTreeMap::new();
+        for annotator in &self.0 {
+            let name = annotator.name();
+            match panic::catch_unwind(AssertUnwindSafe(|| annotator.annotate(tx, ctx))) {
+                Ok(found) => {
+                    for Annotation { key, value } in found {
+                        annotations.insert(format!("{}.{}", name, key), value);
+                    }
+                }
+                Err(_) => {
+                    annotations.insert(format!("{}.error", name), "panicked".to_string());
+                }
+            }
+        }
+        annotations
+    }
+}
+
+impl fmt::Debug for Annotators {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.names()).finish()
+    }
+}
+
+impl PartialEq for Annotators {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.len() == other.0.len() && self.0.iter().zip(&other.0).all(|(a, b)| Arc::ptr_eq(a, b))
+    }
+}
+
+impl TraceAnnotator for CoinJoinDetector {
+    fn name(&self) -> &str {
+        "coinjoin"
+    }
+
+    fn annotate(&self, tx: &Transaction, ctx: &TraceContext) -> Vec<Annotation> {
+        self.detect(tx, ctx.prevouts)
+            .map(|verdict| {
+                vec![
+                    Annotation::new("kind", verdict.kind.code()),
+                    Annotation::new("score", format!("{:.2}", verdict.score)),
+                ]
+            })
+            .unwrap_or_default()
+    }
+}
+
+impl TraceAnnotator for PatternClassifier {
+    fn name(&self) -> &str {
+        "pattern"
+    }
+
+    fn annotate(&self, tx: &Transaction, ctx: &TraceContext) -> Vec<Annotation> {
+        let pattern = self.classify(tx, ctx.prevouts);
+        if pattern == TxPattern::Unknown {
+            return Vec::new();
+        }
+        let mut annotations = vec![Annotation::new("kind", pattern.code())];
+        if let Some(count) = pattern.count() {
+            annotations.push(Annotation::new("count", count.to_string()));
+        }
+        annotations
+    }
+}
+
+impl TraceAnnotator for ChangeDetector {
+    fn name(&self) -> &str {
+        "change"
+    }
+
+    /// The output most likely to be change, and its score. Without every prevout the
+    /// fee is unknown, as in a trace.
+    fn annotate(&self, tx: &Transaction, ctx: &TraceContext) -> Vec<Annotation> {
+        let input_value = (ctx.prevouts.len() == tx.input.len()).then(|| {
+            ctx.prevouts
+                .iter()
+                .map(|prevout| prevout.value)
+                .sum::<Amount>()
+        });
+        let output_value: Amount = tx.output.iter().map(|out| out.value).sum();
+        let context = ChangeContext {
+            fee: input_value.and_then(|value| value.checked_sub(output_value)),
+            seen: ctx
+                .graph
+                .edges()
+                .map(|edge| edge.script_pubkey.as_script())
+                .collect(),
+            clustered: None,
+        };
+        self.detect(&tx.output, ctx.prevouts, &context)
+            .map(|verdict| {
+                vec![
+                    Annotation::new("vout", verdict.change.to_string()),
+                    Annotation::new("score", format!("{:.2}", verdict.scores[verdict.change])),
+                    Annotation::new("confidence", verdict.confidence.code()),
+                ]
+            })
+            .unwrap_or_default()
+    }
+}
+
+impl TraceAnnotator for LabelStore {
+    fn name(&self) -> &str {
+        "labels"
+    }
+
+    /// The label of each output paying a known entity, keyed by its index
+    fn annotate(&self, tx: &Transaction, _ctx: &TraceContext) -> Vec<Annotation> {
+        self.outputs_of(tx)
+            .into_iter()
+            .map(|(vout, label)| Annotation::new(vout.to_string(), label.to_string()))
+            .collect()
+    }
+}
+
+impl TraceAnnotator for TimelockDetector {
+    fn name(&self) -> &str {
+        "timelock"
+    }
+
+    fn annotate(&self, tx: &Transaction, _ctx: &TraceContext) -> Vec<Annotation> {
+        let locktime = match locktime_kind(tx) {
+            LocktimeKind::None => None,
+            LocktimeKind::BlockHeight(height) => Some(format!("height {}", height)),
+            LocktimeKind::Timestamp(time) => Some(format!("time {}", time)),
+        };
+        let relative = (0..tx.input.len()).filter_map(|vin| {
+            let lock = match relative_lock(tx, vin)? {
+                relative::LockTime::Blocks(height) => format!("{} blocks", height.value()),
+                relative::LockTime::Time(time) => format!("{} s", u32::from(time.value()) * 512),
+            };
+            Some(Annotation::new(format!("relative.{}", vin), lock))
+        });
+        locktime
+            .map(|locktime| Annotation::new("locktime", locktime))
+            .into_iter()
+            .chain(relative)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tracer::fixtures::spend;
+    use bitcoin::OutPoint;
+
+    struct Fixed(&'static str, &'static [(&'static str, &'static str)]);
+
+    impl TraceAnnotator for Fixed {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn annotate(&self, _tx: &Transaction, _ctx: &TraceContext) -> Vec<Annotation> {
+            self.1
+                .iter()
+                .map(|(key, value)| Annotation::new(*key, *value))
+                .collect()
+        }
+    }
+
+    struct Panicking;
+
+    impl TraceAnnotator for Panicking {
+        fn name(&self) -> &str {
+            "broken"
+        }
+
+        fn annotate(&self, _tx: &Transaction, _ctx: &TraceContext) -> Vec<Annotation> {
+            panic!("annotator bug")
+        }
+    }
+
+    fn context(graph: &TraceGraph) -> TraceContext<'_> {
+        TraceContext {
+            prevouts: &[],
+            depth: 1,
+            graph,
+            labels: None,
+        }
+    }
+
+    #[test]
+    fn test_annotators_run_in_order_under_their_names() {
+        let tx = spend(1, &[OutPoint::null()], &[1_000]);
+        let graph = TraceGraph::new();
+        let annotators = Annotators::new(vec![
+            Box::new(Fixed("first", &[("a", "1"), ("b", "2")])),
+            Box::new(Fixed("second", &[("a", "3")])),
+            Box::new(Fixed("first", &[("b", "4")])),
+        ]);
+
+        assert_eq!(annotators.names(), ["first", "second", "first"]);
+        let annotations = annotators.run(&tx, &context(&graph));
+        assert_eq!(
+            annotations,
+            BTreeMap::from(
+                [("first.a", "1"), ("first.b", "4"), ("second.a", "3")]
+                    .map(|(key, value)| (key.to_string(), value.to_string()))
+            )
+        );
+    }
+
+    #[test]
+    fn test_panicking_annotator_is_isolated() {
+        let tx = spend(1, &[OutPoint::null()], &[1_000]);
+        let graph = TraceGraph::new();
+        let annotators = Annotators::new(vec![
+            Box::new(Panicking),
+            Box::new(Fixed("after", &[("ok", "yes")])),
+        ]);
+
+        let annotations = annotators.run(&tx, &context(&graph));
+        assert_eq!(annotations["broken.error"], "panicked");
+        assert_eq!(annotations["after.ok"], "yes");
+        assert_eq!(annotations.len(), 2);
+    }
+
+    #[test]
+    fn test_configs_with_the_same_annotators_are_equal() {
+        let annotators = Annotators::new(vec![Box::new(Fixed("a", &[]))]);
+        assert_eq!(annotators, annotators.clone());
+        assert_ne!(annotators, Annotators::new(vec![Box::new(Fixed("a", &[]))]));
+        assert_eq!(format!("{:?}", annotators), r#"["a"]"#);
+    }
+}
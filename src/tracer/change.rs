@@ -0,0 +1,645 @@
+//! Change detection.
+//!
+//! Telling the change of a transaction from its payments says where the sender's
+//! coins went next. Each output is scored on weighted signals, each pointing at
+//! change:
+//! - script type: it pays the same kind of script as the inputs spend
+//! - round value: it is not a round amount, in BTC or in sats (payments often are)
+//! - output index: it is the last output, where many wallets put change
+//! - fee sanity: with several inputs, it is worth less than the smallest input minus
+//!   the fee; were it the payment, that input alone would have paid it and the fee
+//! - fresh address: its script appears nowhere else in the trace
+//! - cluster reuse: its address is in the inputs' cluster, so it was spent later
+//!   along with them (retrospective, only with a `Clustering`)
+//!
+//! The score of an output is the weight of its signals over the weight of the
+//! signals that apply, from 0.0 to 1.0. The confidence of the verdict comes from the
+//! margin between the best score and the runner-up: outputs alike on every signal,
+//! such as equal payments to the same kind of script, leave it `Low`.
+
+use crate::tracer::{
+    Clustering, TraceEdge, TraceGraph,
+    peel::{Confidence, tx_out},
+    script::classify_script,
+};
+use bitcoin::{Amount, Script, TxOut, Txid};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/// An amount in sats divisible by this is round in BTC (0.0001 BTC)
+const ROUND_BTC_SATS: u64 = 10_000;
+
+/// Significant digits up to which an amount in sats is round (25_000, 1_200)
+const ROUND_SATS_DIGITS: u32 = 2;
+
+/// Weights of the change signals. A zero weight turns a signal off.
+///
+/// # Fields
+/// * `script_type` - the output pays the inputs' kind of script
+/// * `round_value` - the output is not a round amount
+/// * `output_index` - the output is the last one
+/// * `fee_sanity` - the output is smaller than the smallest input minus the fee
+/// * `fresh_address` - the output's script appears nowhere else in the trace
+/// * `cluster_reuse` - the output's address is in the inputs' cluster
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChangeWeights {
+    pub script_type: f64,
+    pub round_value: f64,
+    pub output_index: f64,
+    pub fee_sanity: f64,
+    pub fresh_address: f64,
+    pub cluster_reuse: f64,
+}
+
+impl Default for ChangeWeights {
+    fn default() -> Self {
+        Self {
+            script_type: 2.0,
+            round_value: 1.5,
+            output_index: 0.5,
+            fee_sanity: 1.5,
+            fresh_address: 1.0,
+            cluster_reuse: 2.0,
+        }
+    }
+}
+
+impl ChangeWeights {
+    /// Weight of the output paying the inputs' kind of script
+    pub fn script_type(mut self, weight: f64) -> Self {
+        self.script_type = weight;
+        self
+    }
+
+    /// Weight of the output not being a round amount
+    pub fn round_value(mut self, weight: f64) -> Self {
+        self.round_value = weight;
+        self
+    }
+
+    /// Weight of the output being the last one
+    pub fn output_index(mut self, weight: f64) -> Self {
+        self.output_index = weight;
+        self
+    }
+
+    /// Weight of the output being smaller than the smallest input minus the fee
+    pub fn fee_sanity(mut self, weight: f64) -> Self {
+        self.fee_sanity = weight;
+        self
+    }
+
+    /// Weight of the output's script appearing nowhere else in the trace
+    pub fn fresh_address(mut self, weight: f64) -> Self {
+        self.fresh_address = weight;
+        self
+    }
+
+    /// Weight of the output's address being in the inputs' cluster
+    pub fn cluster_reuse(mut self, weight: f64) -> Self {
+        self.cluster_reuse = weight;
+        self
+    }
+}
+
+/// What is known of a transaction beyond its outputs and inputs.
+///
+/// # Fields
+/// * `fee` - fee of the transaction, when every prevout is known
+/// * `seen` - scripts appearing elsewhere in the trace
+/// * `clustered` - scripts of the outputs whose address is in the inputs' cluster,
+///   `None` without a clustering
+#[derive(Debug, Clone, Default)]
+pub struct ChangeContext<'a> {
+    pub fee: Option<Amount>,
+    pub seen: HashSet<&'a Script>,
+    pub clustered: Option<HashSet<&'a Script>>,
+}
+
+/// Scores of the outputs of a transaction, and the one most likely to be change.
+///
+/// # Fields
+/// * `scores` - score of each output from 0.0 to 1.0, by output index (0.0 for an
+///   OP_RETURN output, never change)
+/// * `change` - index of the output scoring highest, the earlier one on ties
+/// * `confidence` - how far ahead of the runner-up `change` is
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChangeVerdict {
+    pub scores: Vec<f64>,
+    pub change: usize,
+    pub confidence: Confidence,
+}
+
+impl ChangeVerdict {
+    /// Change output, if the verdict is at least `confidence`
+    pub fn change_at(&self, confidence: Confidence) -> Option<usize> {
+        (self.confidence >= confidence).then_some(self.change)
+    }
+
+    /// What the verdict says about output `vout`, as recorded on its edge
+    pub fn score(&self, vout: usize) -> Option<ChangeScore> {
+        Some(ChangeScore {
+            score: *self.scores.get(vout)?,
+            change: vout == self.change,
+            confidence: self.confidence,
+        })
+    }
+}
+
+/// Change verdict of a transaction, as seen from one of its outputs.
+///
+/// # Fields
+/// * `score` - score of the output, from 0.0 to 1.0
+/// * `change` - the output scored highest among its transaction's
+/// * `confidence` - confidence of the transaction's verdict
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ChangeScore {
+    pub score: f64,
+    pub change: bool,
+    pub confidence: Confidence,
+}
+
+/// Scores the outputs of transactions as change.
+///
+/// # Fields
+/// * `weights` - weight of each signal
+/// * `medium_margin` - lead over the runner-up from which the verdict is `Medium`
+/// * `high_margin` - lead over the runner-up from which the verdict is `High`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChangeDetector {
+    pub weights: ChangeWeights,
+    pub medium_margin: f64,
+    pub high_margin: f64,
+}
+
+impl Default for ChangeDetector {
+    fn default() -> Self {
+        Self {
+            weights: ChangeWeights::default(),
+            medium_margin: 0.2,
+            high_margin: 0.4,
+        }
+    }
+}
+
+impl ChangeDetector {
+    /// Weight of each signal
+    pub fn weights(mut self, weights: ChangeWeights) -> Self {
+        self.weights = weights;
+        self
+    }
+
+    /// Leads over the runner-up from which the verdict is `Medium` and `High`
+    pub fn margins(mut self, medium: f64, high: f64) -> Self {
+        self.medium_margin = medium;
+        self.high_margin = high;
+        self
+    }
+
+    /// Scores `outputs` of a transaction spending `inputs` (all of them or only the
+    /// known ones).
+    ///
+    /// `None` when fewer than two outputs can be spent: there is no payment to tell
+    /// the change from.
+    pub fn detect(
+        &self,
+        outputs: &[TxOut],
+        inputs: &[TxOut],
+        context: &ChangeContext<'_>,
+    ) -> Option<ChangeVerdict> {
+        let candidates: Vec<usize> = (0..outputs.len())
+            .filter(|&vout| !outputs[vout].script_pubkey.is_op_return())
+            .collect();
+        if candidates.len() < 2 {
+            return None;
+        }
+        let weights = &self.weights;
+        let input_types: HashSet<_> = inputs
+            .iter()
+            .map(|input| classify_script(&input.script_pubkey))
+            .filter(|class| class.has_address())
+            .collect();
+        let smallest_input = inputs.iter().map(|input| input.value).min();
+        let fee = context.fee.filter(|_| inputs.len() >= 2);
+        let last = candidates.last().copied();
+
+        // (weight, whether the output has the signal) of the signals that apply
+        let signals = |vout: usize| {
+            let out = &outputs[vout];
+            let mut signals = vec![
+                (weights.round_value, !is_round(out.value)),
+                (weights.output_index, Some(vout) == last),
+                (
+                    weights.fresh_address,
+                    !context.seen.contains(out.script_pubkey.as_script()),
+                ),
+            ];
+            if !input_types.is_empty() {
+                let class = classify_script(&out.script_pubkey);
+                signals.push((weights.script_type, input_types.contains(&class)));
+            }
+            if let (Some(fee), Some(smallest)) = (fee, smallest_input) {
+                signals.push((weights.fee_sanity, out.value + fee < smallest));
+            }
+            if let Some(clustered) = &context.clustered {
+                signals.push((
+                    weights.cluster_reuse,
+                    clustered.contains(out.script_pubkey.as_script()),
+                ));
+            }
+            signals
+        };
+
+        let mut scores = vec![0.0; outputs.len()];
+        for &vout in &candidates {
+            let signals = signals(vout);
+            let total: f64 = signals.iter().map(|(weight, _)| weight).sum();
+            let held: f64 = signals
+                .iter()
+                .filter(|(_, held)| *held)
+                .map(|(weight, _)| weight)
+                .sum();
+            scores[vout] = if total > 0.0 { held / total } else { 0.0 };
+        }
+        let mut ranked = candidates.clone();
+        // Stable, so ties keep output order
+        ranked.sort_by(|a, b| scores[*b].total_cmp(&scores[*a]));
+        let (change, runner_up) = (ranked[0], ranked[1]);
+        let margin = scores[change] - scores[runner_up];
+        let confidence = if margin >= self.high_margin {
+            Confidence::High
+        } else if margin >= self.medium_margin {
+            Confidence::Medium
+        } else {
+            Confidence::Low
+        };
+        Some(ChangeVerdict {
+            scores,
+            change,
+            confidence,
+        })
+    }
+}
+
+/// Whether `value` is a round amount: a multiple of 0.0001 BTC, or at most
+/// `ROUND_SATS_DIGITS` significant digits in sats
+fn is_round(value: Amount) -> bool {
+    let sats = value.to_sat();
+    if sats == 0 || sats.is_multiple_of(ROUND_BTC_SATS) {
+        return true;
+    }
+    let mut digits = sats;
+    while digits.is_multiple_of(10) {
+        digits /= 10;
+    }
+    digits < 10u64.pow(ROUND_SATS_DIGITS)
+}
+
+impl TraceGraph {
+    /// Change verdict of transaction `txid` from the graph, `None` unless the graph
+    /// holds every output of it (forward traces do) and two of them can be spent.
+    ///
+    /// The trace's other outputs are the scripts already seen, and `clustering`, a
+    /// clustering of the graph (see `cluster_addresses`), adds the cluster reuse
+    /// signal.
+    pub fn detect_change(
+        &self,
+        txid: &Txid,
+        detector: &ChangeDetector,
+        clustering: Option<&Clustering>,
+    ) -> Option<ChangeVerdict> {
+        let node = self.node(txid)?;
+        let edges: Vec<&TraceEdge> = self.outputs_of(txid).collect();
+        let complete = edges.iter().map(|edge| edge.value).sum::<Amount>() == node.output_value
+            && edges
+                .iter()
+                .enumerate()
+                .all(|(vout, edge)| edge.outpoint.vout as usize == vout);
+        if !complete {
+            return None;
+        }
+        let inputs: Vec<&TraceEdge> = self.inputs_of(txid).collect();
+        let input_clusters: HashSet<usize> = clustering
+            .map(|clustering| {
+                inputs
+                    .iter()
+                    .filter_map(|edge| edge.address.as_ref())
+                    .filter_map(|address| clustering.cluster_of(address))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let context = ChangeContext {
+            fee: node.fee,
+            seen: self
+                .edges()
+                .filter(|edge| edge.from() != *txid)
+                .map(|edge| edge.script_pubkey.as_script())
+                .collect(),
+            clustered: clustering.map(|clustering| {
+                edges
+                    .iter()
+                    .filter(|edge| {
+                        edge.address
+                            .as_ref()
+                            .and_then(|address| clustering.cluster_of(address))
+                            .is_some_and(|cluster| input_clusters.contains(&cluster))
+                    })
+                    .map(|edge| edge.script_pubkey.as_script())
+                    .collect()
+            }),
+        };
+        detector.detect(
+            &edges.iter().map(|edge| tx_out(edge)).collect::<Vec<_>>(),
+            &inputs.iter().map(|edge| tx_out(edge)).collect::<Vec<_>>(),
+            &context,
+        )
+    }
+
+    /// Scores every transaction of the graph again and records the verdicts on their
+    /// outputs, replacing those the trace recorded. Meant to run after the trace, with
+    /// the clustering of the whole graph.
+    pub fn annotate_change(&mut self, detector: &ChangeDetector, clustering: Option<&Clustering>) {
+        let txids: Vec<Txid> = self.nodes().map(|node| node.txid).collect();
+        for txid in txids {
+            let Some(verdict) = self.detect_change(&txid, detector, clustering) else {
+                continue;
+            };
+            let edges: Vec<TraceEdge> = self.outputs_of(&txid).cloned().collect();
+            for edge in edges {
+                let change_score = verdict.score(edge.outpoint.vout as usize);
+                self.insert_edge(TraceEdge {
+                    change_score,
+                    ..edge
+                });
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "trace-engine"))]
+mod tests {
+    use super::*;
+    use crate::tracer::{
+        BranchStrategy, ClusterOptions, TerminalReason, TraceConfig, TraceNode, Tracer,
+        cluster_addresses,
+        fixtures::{MockDataSource, script, spend},
+    };
+    use bitcoin::{Network, OutPoint, PubkeyHash, ScriptBuf, Transaction, hashes::Hash};
+
+    /// Legacy script of a made-up payee, distinct per `n`
+    fn payee(n: u8) -> ScriptBuf {
+        ScriptBuf::new_p2pkh(&PubkeyHash::from_byte_array([n; 20]))
+    }
+
+    fn out(sats: u64, script_pubkey: ScriptBuf) -> TxOut {
+        TxOut {
+            value: Amount::from_sat(sats),
+            script_pubkey,
+        }
+    }
+
+    /// Spends `inputs` into `outputs`
+    fn pay(tag: u32, inputs: &[OutPoint], outputs: Vec<TxOut>) -> Transaction {
+        let mut tx = spend(tag, inputs, &[]);
+        tx.output = outputs;
+        tx
+    }
+
+    fn detect(outputs: &[TxOut], inputs: &[TxOut], fee: Option<u64>) -> ChangeVerdict {
+        let context = ChangeContext {
+            fee: fee.map(Amount::from_sat),
+            ..ChangeContext::default()
+        };
+        ChangeDetector::default()
+            .detect(outputs, inputs, &context)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_clear_payment_is_high_confidence() {
+        // A round payment to a legacy payee, odd change back to segwit
+        let inputs = [out(1_000_000, script(0))];
+        let outputs = [out(250_000, payee(1)), out(748_590, script(1))];
+
+        let verdict = detect(&outputs, &inputs, None);
+
+        assert_eq!(verdict.change, 1);
+        assert_eq!(verdict.confidence, Confidence::High);
+        assert!(verdict.scores[1] > verdict.scores[0]);
+        assert_eq!(verdict.change_at(Confidence::High), Some(1));
+    }
+
+    #[test]
+    fn test_change_before_the_payment() {
+        // Change first: only the index points the wrong way
+        let inputs = [out(1_000_000, script(0))];
+        let outputs = [out(748_590, script(1)), out(250_000, payee(1))];
+
+        let verdict = detect(&outputs, &inputs, None);
+
+        assert_eq!(verdict.change, 0);
+        assert_eq!(verdict.confidence, Confidence::High);
+    }
+
+    #[test]
+    fn test_equal_outputs_are_ambiguous() {
+        // Same value, same kind of script, both fresh: nothing tells them apart
+        let inputs = [out(500_000, script(0)), out(400_000, script(1))];
+        let outputs = [out(449_123, script(2)), out(449_123, script(3))];
+
+        let verdict = detect(&outputs, &inputs, Some(1_754));
+
+        assert_eq!(verdict.scores[0], verdict.scores[1] - 0.5 / 6.5);
+        assert_eq!(verdict.confidence, Confidence::Low);
+        assert_eq!(verdict.change_at(Confidence::Medium), None);
+    }
+
+    #[test]
+    fn test_fee_sanity_points_at_the_small_output() {
+        // Were 36_655 the payment, the 250_000 input alone would have paid it
+        let inputs = [out(300_000, script(0)), out(250_000, script(1))];
+        let outputs = [out(512_345, script(2)), out(36_655, script(3))];
+
+        let verdict = detect(&outputs, &inputs, Some(1_000));
+        assert_eq!(verdict.change, 1);
+        assert_eq!(verdict.confidence, Confidence::Medium);
+
+        // Without the fee the signal does not apply
+        let verdict = detect(&outputs, &inputs, None);
+        assert_eq!(verdict.confidence, Confidence::Low);
+    }
+
+    #[test]
+    fn test_weights_and_seen_scripts() {
+        let inputs = [out(1_000_000, script(0))];
+        let outputs = [out(250_000, payee(1)), out(748_590, script(1))];
+        let seen = ChangeContext {
+            seen: HashSet::from([outputs[1].script_pubkey.as_script()]),
+            ..ChangeContext::default()
+        };
+
+        // A change script seen before lowers its score
+        let detector = ChangeDetector::default();
+        let verdict = detector.detect(&outputs, &inputs, &seen).unwrap();
+        assert_eq!(verdict.change, 1);
+        assert_eq!(verdict.scores, vec![1.0 / 5.0, 4.0 / 5.0]);
+
+        // Left to freshness alone, the payment wins
+        let fresh_only = ChangeWeights {
+            script_type: 0.0,
+            round_value: 0.0,
+            output_index: 0.0,
+            fee_sanity: 0.0,
+            ..ChangeWeights::default()
+        };
+        let verdict = detector
+            .weights(fresh_only)
+            .detect(&outputs, &inputs, &seen)
+            .unwrap();
+        assert_eq!(verdict.change, 0);
+        assert_eq!(verdict.scores, vec![1.0, 0.0]);
+    }
+
+    #[test]
+    fn test_single_spendable_output_has_no_change() {
+        let detector = ChangeDetector::default();
+        let context = ChangeContext::default();
+
+        assert!(
+            detector
+                .detect(&[out(50_000, script(0))], &[], &context)
+                .is_none()
+        );
+        let tagged = [
+            out(50_000, script(0)),
+            out(0, ScriptBuf::from_hex("6a0474657374").unwrap()),
+        ];
+        assert!(detector.detect(&tagged, &[], &context).is_none());
+    }
+
+    #[test]
+    fn test_round_values() {
+        for sats in [0, 10_000, 5_000_000, 25_000, 1_200, 350, 7] {
+            assert!(is_round(Amount::from_sat(sats)), "{sats}");
+        }
+        for sats in [748_590, 1_234, 546, 449_123] {
+            assert!(!is_round(Amount::from_sat(sats)), "{sats}");
+        }
+    }
+
+    #[test]
+    fn test_cluster_reuse_is_retrospective() {
+        // `a` pays `p` and change `c`; `b` later spends `c` along with a coin of `x`,
+        // the address `a` spent from, so `c` is in the sender's cluster
+        let x = script(10);
+        let funding = pay(
+            1,
+            &[OutPoint::new(Txid::all_zeros(), 1)],
+            vec![out(500_000, x.clone())],
+        );
+        let other = pay(
+            2,
+            &[OutPoint::new(Txid::all_zeros(), 2)],
+            vec![out(100_000, x)],
+        );
+        let a = pay(
+            3,
+            &[OutPoint::new(funding.compute_txid(), 0)],
+            vec![out(123_456, script(11)), out(374_321, script(12))],
+        );
+        let b = pay(
+            4,
+            &[
+                OutPoint::new(a.compute_txid(), 1),
+                OutPoint::new(other.compute_txid(), 0),
+            ],
+            vec![out(473_000, script(13))],
+        );
+        let mut graph = TraceGraph::new();
+        for (tx, depth) in [(&funding, 0), (&other, 0), (&a, 1), (&b, 2)] {
+            graph.insert_node(TraceNode::new(tx, depth));
+            for (vout, output) in tx.output.iter().enumerate() {
+                let outpoint = OutPoint::new(tx.compute_txid(), vout as u32);
+                let spent_by = [&a, &b]
+                    .into_iter()
+                    .find(|spender| {
+                        spender
+                            .input
+                            .iter()
+                            .any(|input| input.previous_output == outpoint)
+                    })
+                    .map(|spender| spender.compute_txid());
+                graph.insert_edge(TraceEdge {
+                    spent_by,
+                    ..TraceEdge::new(outpoint, output, Network::Bitcoin)
+                });
+            }
+        }
+        let detector = ChangeDetector::default();
+        let txid = a.compute_txid();
+
+        let verdict = graph.detect_change(&txid, &detector, None).unwrap();
+        assert_eq!(verdict.change, 1);
+        assert_eq!(verdict.confidence, Confidence::Low);
+
+        let clustering = cluster_addresses(&graph, ClusterOptions::default());
+        let verdict = graph
+            .detect_change(&txid, &detector, Some(&clustering))
+            .unwrap();
+        assert_eq!(verdict.change, 1);
+        assert_eq!(verdict.confidence, Confidence::Medium);
+
+        graph.annotate_change(&detector, Some(&clustering));
+        let change = graph.edge(&OutPoint::new(txid, 1)).unwrap();
+        assert_eq!(change.change_score, verdict.score(1));
+        assert!(change.change_score.unwrap().change);
+        // Single-output transactions are not scored
+        let sweep = graph.edge(&OutPoint::new(b.compute_txid(), 0)).unwrap();
+        assert_eq!(sweep.change_score, None);
+    }
+
+    #[tokio::test]
+    async fn test_trace_follows_and_records_the_change() {
+        let funding = spend(0, &[OutPoint::new(Txid::all_zeros(), 5)], &[1_000_000]);
+        let root = OutPoint::new(funding.compute_txid(), 0);
+        let first = pay(
+            1,
+            &[root],
+            vec![out(250_000, payee(1)), out(748_590, script(1))],
+        );
+        let second = pay(
+            2,
+            &[OutPoint::new(first.compute_txid(), 1)],
+            vec![out(100_000, payee(2)), out(648_123, script(1))],
+        );
+        let tracer = Tracer::new(MockDataSource::new(&[
+            funding,
+            first.clone(),
+            second.clone(),
+        ]));
+        let config = TraceConfig::default().branch(BranchStrategy::FollowChange(Confidence::High));
+
+        let graph = tracer
+            .trace_forward(root, &config)
+            .await
+            .unwrap()
+            .into_graph();
+
+        let payment = graph.edge(&OutPoint::new(first.compute_txid(), 0)).unwrap();
+        assert_eq!(payment.terminal, Some(TerminalReason::NotFollowed));
+        let score = payment.change_score.unwrap();
+        assert!(!score.change);
+        assert_eq!(score.confidence, Confidence::High);
+        let change = graph.edge(&OutPoint::new(first.compute_txid(), 1)).unwrap();
+        assert_eq!(change.spent_by, Some(second.compute_txid()));
+        assert!(change.change_score.unwrap().change);
+        // The change of `second` reuses the script of the coin it spends
+        let next = graph
+            .edge(&OutPoint::new(second.compute_txid(), 1))
+            .unwrap();
+        assert_eq!(next.terminal, Some(TerminalReason::Unspent));
+        assert!(next.change_score.unwrap().score < 1.0);
+        // Funding, then the spender of each change: the payments were left alone
+        assert_eq!(tracer.source().calls(), 4);
+    }
+}
@@ -0,0 +1,205 @@
+//! Snapshots of running traces, to resume them after a crash.
+//!
+//! A trace configured with `TraceConfig::checkpoint_every` saves its whole state every
+//! so many requests: the graph so far, the work still queued and the caps' counters.
+//! A cancelled trace saves one last snapshot, of where it stopped. `Tracer::resume`
+//! picks the trace up from such a snapshot, and ends with the same graph an
+//! uninterrupted run would have.
+//!
+//! A checkpoint carries the fingerprint of the configuration it was taken under, since
+//! resuming under other limits or strategies would silently mix two different traces.
+
+use crate::tracer::{Result, TraceConfig, TraceEdge, TraceGraph, TracerError};
+use bitcoin::{OutPoint, Transaction, Txid};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Version of the checkpoint format, bumped on incompatible changes
+pub const CHECKPOINT_VERSION: u32 = 3;
+
+/// When and where a trace saves checkpoints.
+///
+/// # Fields
+/// * `every` - requests to the data source between two checkpoints; `None` saves one
+///   only when the trace is cancelled
+/// * `path` - file each checkpoint replaces
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckpointSchedule {
+    pub every: Option<usize>,
+    pub path: PathBuf,
+}
+
+/// Work a trace still had queued when the checkpoint was taken
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) enum Frontier {
+    /// Outputs to follow, as their edges, with the depth of the transaction creating them
+    Forward {
+        roots: Vec<OutPoint>,
+        pending: Vec<(TraceEdge, usize)>,
+    },
+    /// Transactions to expand with their depth, and every transaction fetched so far
+    /// (pending ones and the parents their inputs are valued from)
+    Backward {
+        start: Txid,
+        pending: Vec<(Txid, usize)>,
+        fetched: Vec<Transaction>,
+    },
+}
+
+/// Serializable state of a trace between two hops.
+///
+/// Written with `save` (or as JSON through serde) and read back with `load`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TraceCheckpoint {
+    version: u32,
+    config_fingerprint: u64,
+    graph: TraceGraph,
+    pub(crate) frontier: Frontier,
+    /// Transactions added at each depth, for `max_breadth`
+    pub(crate) per_depth: HashMap<usize, usize>,
+    pub(crate) requests: usize,
+}
+
+impl TraceCheckpoint {
+    pub(crate) fn new(
+        config: &TraceConfig,
+        graph: TraceGraph,
+        frontier: Frontier,
+        per_depth: HashMap<usize, usize>,
+        requests: usize,
+    ) -> Self {
+        Self {
+            version: CHECKPOINT_VERSION,
+            config_fingerprint: config.fingerprint(),
+            graph,
+            frontier,
+            per_depth,
+            requests,
+        }
+    }
+
+    /// Graph traced up to the checkpoint
+    pub fn graph(&self) -> &TraceGraph {
+        &self.graph
+    }
+
+    pub(crate) fn into_graph(self) -> TraceGraph {
+        self.graph
+    }
+
+    /// Requests made to the data source up to the checkpoint
+    pub fn requests(&self) -> usize {
+        self.requests
+    }
+
+    /// Fingerprint of the configuration the trace ran under (see
+    /// `TraceConfig::fingerprint`)
+    pub fn config_fingerprint(&self) -> u64 {
+        self.config_fingerprint
+    }
+
+    /// Checks the checkpoint can continue a trace under `config`; `force` skips the
+    /// configuration check, not the version one.
+    ///
+    /// # Errors
+    /// - `IncompatibleCheckpoint` - the checkpoint has another format version, or was
+    ///   taken under another configuration and `force` is not set
+    pub(crate) fn check(&self, config: &TraceConfig, force: bool) -> Result<()> {
+        if self.version != CHECKPOINT_VERSION {
+            return Err(TracerError::IncompatibleCheckpoint(format!(
+                "format version {}, expected {}",
+                self.version, CHECKPOINT_VERSION
+            )));
+        }
+        if !force && self.config_fingerprint != config.fingerprint() {
+            return Err(TracerError::IncompatibleCheckpoint(format!(
+                "taken under configuration {:016x}, resuming under {:016x}",
+                self.config_fingerprint,
+                config.fingerprint()
+            )));
+        }
+        Ok(())
+    }
+
+    /// Writes the checkpoint to `path`, atomically: to a temporary file next to it
+    /// first, then renamed over it, so a crash mid-write leaves the previous one intact.
+    ///
+    /// # Errors
+    /// - `Checkpoint` - writing or renaming failed
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        let json = serde_json::to_vec(self)
+            .map_err(|e| TracerError::TraceLogic(format!("checkpoint serialization: {}", e)))?;
+        fs::write(&temporary, json)?;
+        fs::rename(&temporary, path)?;
+        Ok(())
+    }
+
+    /// Reads a checkpoint written by `save`.
+    ///
+    /// # Errors
+    /// - `Checkpoint` - reading failed
+    /// - `MalformedJson` - the file is not a checkpoint
+    pub fn load(path: &Path) -> Result<Self> {
+        let json = fs::read(path)?;
+        serde_json::from_slice(&json).map_err(|e| TracerError::MalformedJson(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tracer::fixtures::sample_graph;
+
+    #[tokio::test]
+    async fn test_save_and_load_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("trace.checkpoint");
+        let config = TraceConfig::default();
+        let checkpoint = TraceCheckpoint::new(
+            &config,
+            sample_graph().await,
+            Frontier::Backward {
+                start: bitcoin::hashes::Hash::all_zeros(),
+                pending: vec![],
+                fetched: vec![],
+            },
+            HashMap::from([(0, 1), (1, 2)]),
+            7,
+        );
+
+        checkpoint.save(&path).unwrap();
+        let back = TraceCheckpoint::load(&path).unwrap();
+
+        assert_eq!(back, checkpoint);
+        assert!(!dir.path().join("trace.checkpoint.tmp").exists());
+        back.check(&config, false).unwrap();
+        let other = config.max_depth(3);
+        assert!(matches!(
+            back.check(&other, false),
+            Err(TracerError::IncompatibleCheckpoint(_))
+        ));
+        back.check(&other, true).unwrap();
+    }
+
+    #[test]
+    fn test_load_rejects_other_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.txt");
+        fs::write(&path, "not a checkpoint").unwrap();
+
+        assert!(matches!(
+            TraceCheckpoint::load(&path),
+            Err(TracerError::MalformedJson(_))
+        ));
+        assert!(matches!(
+            TraceCheckpoint::load(&dir.path().join("missing")),
+            Err(TracerError::Checkpoint(_))
+        ));
+    }
+}
@@ -0,0 +1,355 @@
+//! Address clustering by common input ownership.
+//!
+//! Spending several inputs in one transaction takes the keys of all of them, so the
+//! addresses they pay are assumed to belong to one entity. Clusters grow transitively:
+//! two transactions sharing one input address merge everything both of them spend.
+//!
+//! The heuristic breaks on CoinJoins, whose inputs come from many entities by design,
+//! so flagged transactions never merge anything. Large exchange batches can be left
+//! out too, with `ClusterOptions::max_inputs`.
+
+use crate::tracer::TraceGraph;
+use bitcoin::{Address, Amount};
+use std::collections::HashMap;
+
+/// Options of `cluster_addresses`.
+///
+/// # Fields
+/// * `max_inputs` - transactions with more traced inputs than this do not merge their
+///   input addresses, `None` for no limit
+#[derive(Debug, Clone, Default)]
+pub struct ClusterOptions {
+    pub max_inputs: Option<usize>,
+}
+
+impl ClusterOptions {
+    /// Leaves transactions with more than `max_inputs` inputs out of the merging
+    pub fn max_inputs(mut self, max_inputs: usize) -> Self {
+        self.max_inputs = Some(max_inputs);
+        self
+    }
+}
+
+/// Aggregates of one cluster over the traced graph.
+///
+/// # Fields
+/// * `addresses` - number of addresses in the cluster
+/// * `received` - total value of the traced outputs paying the cluster
+/// * `first_seen` - earliest block time of a transaction paying or spending from the
+///   cluster, when known
+/// * `last_seen` - latest such block time
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClusterStats {
+    pub addresses: usize,
+    pub received: Amount,
+    pub first_seen: Option<u64>,
+    pub last_seen: Option<u64>,
+}
+
+impl ClusterStats {
+    fn seen(&mut self, timestamp: u64) {
+        self.first_seen = Some(self.first_seen.map_or(timestamp, |t| t.min(timestamp)));
+        self.last_seen = Some(self.last_seen.map_or(timestamp, |t| t.max(timestamp)));
+    }
+}
+
+/// Addresses of a graph grouped into clusters.
+///
+/// Cluster ids run from 0 to `len() - 1`, numbered in the order the graph's edges
+/// first mention a cluster, so the same graph always gets the same ids.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Clustering {
+    clusters: HashMap<Address, usize>,
+    stats: Vec<ClusterStats>,
+}
+
+impl Clustering {
+    /// Cluster id of `address`, if the graph mentions it
+    pub fn cluster_of(&self, address: &Address) -> Option<usize> {
+        self.clusters.get(address).copied()
+    }
+
+    pub fn stats(&self, cluster: usize) -> Option<&ClusterStats> {
+        self.stats.get(cluster)
+    }
+
+    /// Addresses of `cluster`, in no particular order
+    pub fn members(&self, cluster: usize) -> impl Iterator<Item = &Address> {
+        self.clusters
+            .iter()
+            .filter(move |(_, id)| **id == cluster)
+            .map(|(address, _)| address)
+    }
+
+    /// Number of clusters
+    pub fn len(&self) -> usize {
+        self.stats.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stats.is_empty()
+    }
+}
+
+/// Clusters the addresses of `graph` by common input ownership.
+///
+/// Every address paid by a traced output is in exactly one cluster, alone if it never
+/// shares a transaction's inputs with another address. Only the traced inputs of a
+/// transaction are known, so an input left out of the trace merges nothing.
+pub fn cluster_addresses(graph: &TraceGraph, options: ClusterOptions) -> Clustering {
+    let mut index: HashMap<Address, usize> = HashMap::new();
+    let mut addresses = Vec::new();
+    for address in graph.edges().filter_map(|edge| edge.address.as_ref()) {
+        index.entry(address.clone()).or_insert_with(|| {
+            addresses.push(address.clone());
+            addresses.len() - 1
+        });
+    }
+
+    let mut sets = UnionFind::new(addresses.len());
+    for node in graph.nodes() {
+        if node.coinjoin.is_some() {
+            continue;
+        }
+        let inputs: Vec<usize> = graph
+            .inputs_of(&node.txid)
+            .filter_map(|edge| edge.address.as_ref())
+            .map(|address| index[address])
+            .collect();
+        if options.max_inputs.is_some_and(|max| inputs.len() > max) {
+            continue;
+        }
+        for pair in inputs.windows(2) {
+            sets.union(pair[0], pair[1]);
+        }
+    }
+
+    // Renumber the roots densely, in address order
+    let mut ids = HashMap::new();
+    let clusters: HashMap<Address, usize> = addresses
+        .iter()
+        .enumerate()
+        .map(|(i, address)| {
+            let next = ids.len();
+            let id = *ids.entry(sets.find(i)).or_insert(next);
+            (address.clone(), id)
+        })
+        .collect();
+    let mut stats = vec![ClusterStats::default(); ids.len()];
+    for id in clusters.values() {
+        stats[*id].addresses += 1;
+    }
+    for edge in graph.edges() {
+        let Some(id) = edge.address.as_ref().map(|address| clusters[address]) else {
+            continue;
+        };
+        let cluster = &mut stats[id];
+        cluster.received += edge.value;
+        let payer = graph.node(&edge.from());
+        let spender = edge.spent_by.and_then(|txid| graph.node(&txid));
+        for timestamp in [payer, spender]
+            .into_iter()
+            .flatten()
+            .filter_map(|node| node.timestamp)
+        {
+            cluster.seen(timestamp);
+        }
+    }
+
+    Clustering { clusters, stats }
+}
+
+/// Disjoint sets of indexes, with path halving and union by size
+struct UnionFind {
+    parent: Vec<usize>,
+    size: Vec<usize>,
+}
+
+impl UnionFind {
+    fn new(len: usize) -> Self {
+        Self {
+            parent: (0..len).collect(),
+            size: vec![1; len],
+        }
+    }
+
+    fn find(&mut self, mut i: usize) -> usize {
+        while self.parent[i] != i {
+            self.parent[i] = self.parent[self.parent[i]];
+            i = self.parent[i];
+        }
+        i
+    }
+
+    fn union(&mut self, a: usize, b: usize) {
+        let (mut a, mut b) = (self.find(a), self.find(b));
+        if a == b {
+            return;
+        }
+        if self.size[a] < self.size[b] {
+            std::mem::swap(&mut a, &mut b);
+        }
+        self.parent[b] = a;
+        self.size[a] += self.size[b];
+    }
+}
+
+#[cfg(all(test, feature = "trace-engine"))]
+mod tests {
+    use super::*;
+    use crate::tracer::{
+        CoinJoinKind, CoinJoinVerdict, TraceConfig, Tracer,
+        fixtures::{MockDataSource, script},
+    };
+    use bitcoin::{
+        Network, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid, Witness,
+        absolute::LockTime, hashes::Hash, transaction::Version,
+    };
+
+    /// Transaction spending `inputs` into outputs paying `payees` (script numbers),
+    /// 10_000 sats each
+    fn pay(tag: u32, inputs: &[OutPoint], payees: &[u8]) -> Transaction {
+        Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::from_consensus(tag),
+            input: inputs
+                .iter()
+                .map(|&previous_output| TxIn {
+                    previous_output,
+                    script_sig: ScriptBuf::new(),
+                    sequence: Sequence::MAX,
+                    witness: Witness::new(),
+                })
+                .collect(),
+            output: payees
+                .iter()
+                .map(|&n| TxOut {
+                    value: Amount::from_sat(10_000),
+                    script_pubkey: script(n),
+                })
+                .collect(),
+        }
+    }
+
+    fn out(tx: &Transaction, vout: u32) -> OutPoint {
+        OutPoint::new(tx.compute_txid(), vout)
+    }
+
+    fn address(n: u8) -> Address {
+        Address::from_script(&script(n), Network::Bitcoin).unwrap()
+    }
+
+    /// Two branches from one funding transaction. Each spends an output paying
+    /// address 20 three hops down, together with an output of its own branch: the
+    /// addresses of both branches end up co-spent with address 20.
+    ///
+    /// funding -> a1 -> a2 -> a3 (spends a2:0 [addr 1] + a2:1 [addr 20])
+    ///         -> b1 -> b2 -> b3 (spends b2:0 [addr 2] + b2:1 [addr 20])
+    struct Branches {
+        txs: Vec<Transaction>,
+    }
+
+    impl Branches {
+        fn new() -> Self {
+            let funding = pay(0, &[OutPoint::new(Txid::all_zeros(), 7)], &[10, 11]);
+            let a1 = pay(1, &[out(&funding, 0)], &[12]);
+            let a2 = pay(2, &[out(&a1, 0)], &[1, 20]);
+            let a3 = pay(3, &[out(&a2, 0), out(&a2, 1)], &[13]);
+            let b1 = pay(4, &[out(&funding, 1)], &[14]);
+            let b2 = pay(5, &[out(&b1, 0)], &[2, 20]);
+            let b3 = pay(6, &[out(&b2, 0), out(&b2, 1)], &[15]);
+            Self {
+                txs: vec![funding, a1, a2, a3, b1, b2, b3],
+            }
+        }
+
+        /// Both branches traced forward and merged
+        async fn graph(&self) -> TraceGraph {
+            let tracer = Tracer::new(MockDataSource::new(&self.txs));
+            let config = TraceConfig::default();
+            let mut graph = tracer
+                .trace_forward(out(&self.txs[0], 0), &config)
+                .await
+                .unwrap()
+                .into_graph();
+            graph.merge(
+                &tracer
+                    .trace_forward(out(&self.txs[0], 1), &config)
+                    .await
+                    .unwrap()
+                    .into_graph(),
+            );
+            graph
+        }
+    }
+
+    #[tokio::test]
+    async fn test_shared_input_address_joins_branches() {
+        let graph = Branches::new().graph().await;
+
+        let clustering = cluster_addresses(&graph, ClusterOptions::default());
+
+        let shared = clustering.cluster_of(&address(20)).unwrap();
+        assert_eq!(clustering.cluster_of(&address(1)), Some(shared));
+        assert_eq!(clustering.cluster_of(&address(2)), Some(shared));
+        assert_eq!(clustering.members(shared).count(), 3);
+        let stats = clustering.stats(shared).unwrap();
+        assert_eq!(stats.addresses, 3);
+        assert_eq!(stats.received, Amount::from_sat(40_000));
+        // Addresses never co-spent stay alone
+        assert_ne!(clustering.cluster_of(&address(12)), Some(shared));
+        assert_ne!(
+            clustering.cluster_of(&address(12)),
+            clustering.cluster_of(&address(14))
+        );
+        assert_eq!(clustering.len(), 7);
+    }
+
+    #[tokio::test]
+    async fn test_coinjoins_and_batches_do_not_merge() {
+        let branches = Branches::new();
+        let mut graph = branches.graph().await;
+        graph
+            .node_mut(&branches.txs[3].compute_txid())
+            .unwrap()
+            .coinjoin = Some(CoinJoinVerdict {
+            kind: CoinJoinKind::Generic,
+            score: 1.0,
+            denomination: Amount::from_sat(10_000),
+            equal_outputs: 5,
+        });
+
+        let clustering = cluster_addresses(&graph, ClusterOptions::default());
+        assert_ne!(
+            clustering.cluster_of(&address(1)),
+            clustering.cluster_of(&address(20))
+        );
+        assert_eq!(
+            clustering.cluster_of(&address(2)),
+            clustering.cluster_of(&address(20))
+        );
+
+        let clustering = cluster_addresses(&graph, ClusterOptions::default().max_inputs(1));
+        assert_eq!(clustering.len(), 9);
+    }
+
+    #[tokio::test]
+    async fn test_stats_track_block_times() {
+        let branches = Branches::new();
+        let mut graph = branches.graph().await;
+        for (tx, timestamp) in [(2, 1_000), (3, 3_000), (6, 2_000)] {
+            graph
+                .node_mut(&branches.txs[tx].compute_txid())
+                .unwrap()
+                .timestamp = Some(timestamp);
+        }
+
+        let clustering = cluster_addresses(&graph, ClusterOptions::default());
+        let stats = clustering
+            .stats(clustering.cluster_of(&address(20)).unwrap())
+            .unwrap();
+
+        assert_eq!(stats.first_seen, Some(1_000));
+        assert_eq!(stats.last_seen, Some(3_000));
+    }
+}
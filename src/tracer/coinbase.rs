@@ -0,0 +1,374 @@
+//! Where the coins a backward trace resolves to were mined.
+//!
+//! A coinbase says little about its miner on purpose, but pools tag the scriptSig of
+//! their coinbases ("/Foundry USA Pool/", "Mined by AntPool", ...) and pay their
+//! rewards to addresses that are often labelled. Since BIP 34 the scriptSig also
+//! starts with the height of its block.
+
+use crate::tracer::{TraceGraph, labels::Label};
+use bitcoin::{Amount, Script, Transaction, script::Instruction};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Markers of known mining pools in coinbase scriptSigs, and the pools' names
+const POOL_TAGS: &[(&[u8], &str)] = &[
+    (b"Foundry USA", "Foundry USA"),
+    (b"AntPool", "AntPool"),
+    (b"ViaBTC", "ViaBTC"),
+    (b"F2Pool", "F2Pool"),
+    // F2Pool's "seven-colored fairy fish"
+    ("七彩神仙鱼".as_bytes(), "F2Pool"),
+    (b"/slush/", "Braiins Pool"),
+    (b"Braiins", "Braiins Pool"),
+    (b"binance", "Binance Pool"),
+    (b"MARA Pool", "MARA Pool"),
+    (b"Luxor", "Luxor"),
+    (b"SpiderPool", "SpiderPool"),
+    (b"poolin", "Poolin"),
+    (b"BTC.COM", "BTC.com"),
+    (b"SBICrypto", "SBI Crypto"),
+    (b"OCEAN.XYZ", "OCEAN"),
+];
+
+/// Largest height a 3-byte push holds: coinbases pushing more are from before BIP 34
+const MAX_BIP34_HEIGHT: i64 = 0x7f_ffff;
+
+/// What a coinbase says about the block and pool that mined it.
+///
+/// # Fields
+/// * `height` - block height pushed first by the scriptSig (BIP 34), `None` for a
+///   coinbase from before BIP 34
+/// * `pool` - pool the scriptSig is tagged with, else the entity of the first
+///   labelled output
+/// * `tag_bytes` - the scriptSig after the height: the pool's tag and extra nonce
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CoinbaseOrigin {
+    pub height: Option<u32>,
+    pub pool: Option<String>,
+    pub tag_bytes: Vec<u8>,
+}
+
+impl CoinbaseOrigin {
+    /// Origin of `tx`, whose outputs paying known entities are `labels`; `None` unless
+    /// it is a coinbase
+    pub fn from_coinbase(tx: &Transaction, labels: &BTreeMap<u32, Label>) -> Option<Self> {
+        if !tx.is_coinbase() {
+            return None;
+        }
+        let script_sig = &tx.input.first()?.script_sig;
+        let push = bip34_push(script_sig);
+        let tag_bytes = match push {
+            Some((_, len)) => script_sig.as_bytes()[len..].to_vec(),
+            None => script_sig.to_bytes(),
+        };
+        let pool = pool_of_tag(&tag_bytes)
+            .map(str::to_string)
+            .or_else(|| labels.values().next().map(|label| label.entity.clone()));
+        Some(Self {
+            height: push.map(|(height, _)| height),
+            pool,
+            tag_bytes,
+        })
+    }
+}
+
+/// Height of the block of the coinbase scriptSig `script_sig`, from its first push
+/// (BIP 34).
+///
+/// Coinbases from before BIP 34 mostly start with a 4-byte push of the block's target
+/// instead, which no height needs before block 8,388,608: those have no height.
+pub fn bip34_height(script_sig: &Script) -> Option<u32> {
+    bip34_push(script_sig).map(|(height, _)| height)
+}
+
+/// Height pushed first by `script_sig`, and the length in bytes of the push
+fn bip34_push(script_sig: &Script) -> Option<(u32, usize)> {
+    let first = script_sig.instructions().next()?.ok()?;
+    let len = match first {
+        Instruction::PushBytes(bytes) if bytes.len() > 3 => return None,
+        Instruction::PushBytes(bytes) => 1 + bytes.len(),
+        Instruction::Op(_) => 1,
+    };
+    let height = first.script_num()?;
+    (0..=MAX_BIP34_HEIGHT)
+        .contains(&height)
+        .then_some((height as u32, len))
+}
+
+/// Name of the pool whose marker `tag` carries, if any
+pub fn pool_of_tag(tag: &[u8]) -> Option<&'static str> {
+    POOL_TAGS
+        .iter()
+        .find(|(marker, _)| tag.windows(marker.len()).any(|window| window == *marker))
+        .map(|(_, pool)| *pool)
+}
+
+/// Share of the coins a trace resolves to that one pool mined.
+///
+/// # Fields
+/// * `pool` - the pool, `None` for coinbases of no known pool
+/// * `value` - value of its coinbase outputs the trace went through
+/// * `share` - that value over the value of all the coinbase outputs, 0.0 to 1.0
+/// * `coinbases` - its coinbases in the graph
+/// * `heights` - lowest and highest height of those coinbases, when any is known
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PoolShare {
+    pub pool: Option<String>,
+    pub value: Amount,
+    pub share: f64,
+    pub coinbases: usize,
+    pub heights: Option<(u32, u32)>,
+}
+
+/// Mining pools behind the coinbases of a graph, largest share first.
+///
+/// A coinbase counts for its outputs spent within the graph, the coins of the
+/// ancestry of a backward trace, or for all its outputs when none is. Its height is
+/// that of BIP 34, else the block height of its node.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CoinbaseProvenance {
+    pub total: Amount,
+    pub pools: Vec<PoolShare>,
+}
+
+impl CoinbaseProvenance {
+    /// Provenance of the coinbases of `graph`, `None` if it has none
+    pub fn from_graph(graph: &TraceGraph) -> Option<Self> {
+        let mut pools: BTreeMap<Option<String>, PoolShare> = BTreeMap::new();
+        for node in graph.nodes().filter(|node| node.coinbase) {
+            let spent: Vec<_> = graph
+                .outputs_of(&node.txid)
+                .filter(|edge| edge.spent_by.is_some())
+                .map(|edge| edge.value)
+                .collect();
+            let value = if spent.is_empty() {
+                node.output_value
+            } else {
+                spent.into_iter().fold(Amount::ZERO, add)
+            };
+            let origin = node.origin.as_ref();
+            let pool = origin.and_then(|origin| origin.pool.clone());
+            let height = origin.and_then(|origin| origin.height).or(node.height);
+            let share = pools.entry(pool.clone()).or_insert_with(|| PoolShare {
+                pool,
+                value: Amount::ZERO,
+                share: 0.0,
+                coinbases: 0,
+                heights: None,
+            });
+            share.value = add(share.value, value);
+            share.coinbases += 1;
+            if let Some(height) = height {
+                share.heights = Some(match share.heights {
+                    Some((low, high)) => (low.min(height), high.max(height)),
+                    None => (height, height),
+                });
+            }
+        }
+        if pools.is_empty() {
+            return None;
+        }
+        let total = pools
+            .values()
+            .map(|share| share.value)
+            .fold(Amount::ZERO, add);
+        let mut pools: Vec<_> = pools.into_values().collect();
+        for share in &mut pools {
+            share.share = if total == Amount::ZERO {
+                0.0
+            } else {
+                share.value.to_sat() as f64 / total.to_sat() as f64
+            };
+        }
+        // Unknown pools last among equals
+        pools.sort_by(|a, b| {
+            b.value
+                .cmp(&a.value)
+                .then_with(|| a.pool.is_none().cmp(&b.pool.is_none()))
+                .then_with(|| a.pool.cmp(&b.pool))
+        });
+        Some(Self { total, pools })
+    }
+}
+
+impl fmt::Display for CoinbaseProvenance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, share) in self.pools.iter().enumerate() {
+            if index == 0 {
+                write!(f, "Ancestry resolves to ")?;
+            } else {
+                write!(f, "  ")?;
+            }
+            write!(f, "{:.0}% ", share.share * 100.0)?;
+            match &share.pool {
+                Some(pool) => write!(f, "{}-mined coins", pool)?,
+                None => write!(f, "coins of unknown pools")?,
+            }
+            match share.heights {
+                Some((low, high)) if low == high => writeln!(f, " at height {}", low)?,
+                Some((low, high)) => writeln!(f, " from heights {}–{}", low, high)?,
+                None => writeln!(f)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `a + b`, or the largest amount if that overflows
+fn add(a: Amount, b: Amount) -> Amount {
+    a.checked_add(b).unwrap_or(Amount::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tracer::{
+        EntityCategory, TraceEdge, TraceNode,
+        fixtures::{coinbase, spend},
+    };
+    use bitcoin::{Network, OutPoint, ScriptBuf, Txid, hashes::Hash};
+
+    /// Coinbase with the scriptSig `hex`, paying `sats`
+    fn mined(hex: &str, sats: u64) -> Transaction {
+        let mut tx = coinbase(sats as u32, &[sats]);
+        tx.input[0].script_sig = ScriptBuf::from_hex(hex).unwrap();
+        tx
+    }
+
+    /// Genesis coinbase scriptSig: the target, then The Times headline
+    const GENESIS: &str = "04ffff001d0104455468652054696d65732030332f4a616e2f32303039204368616e63656c6c6f72206f6e206272696e6b206f66207365636f6e64206261696c6f757420666f722062616e6b73";
+
+    #[test]
+    fn test_reads_heights_and_pool_tags() {
+        let cases = [
+            // Height 812345, "/Foundry USA Pool #dropgold/" and an extra nonce
+            (
+                "0339650c",
+                "2f466f756e6472792055534120506f6f6c202364726f70676f6c642f0c8f2a4b0000000000",
+                Some(812_345),
+                Some("Foundry USA"),
+            ),
+            // Height 830000, "Mined by AntPool"
+            (
+                "0330aa0c",
+                "194d696e656420627920416e74506f6f6c20d6002c00a1b2c3d4",
+                Some(830_000),
+                Some("AntPool"),
+            ),
+            // Height 815000, "/ViaBTC/Mined by anon/"
+            (
+                "03986f0c",
+                "2f5669614254432f4d696e656420627920616e6f6e2f10",
+                Some(815_000),
+                Some("ViaBTC"),
+            ),
+            // Height 700000, "/slush/"
+            (
+                "0360ae0a",
+                "2f736c7573682f",
+                Some(700_000),
+                Some("Braiins Pool"),
+            ),
+            // Regtest height 5, no tag
+            ("55", "00", Some(5), None),
+        ];
+        for (height, tag, expected, pool) in cases {
+            let tx = mined(&format!("{height}{tag}"), 625_000_000);
+            let origin = CoinbaseOrigin::from_coinbase(&tx, &BTreeMap::new()).unwrap();
+            assert_eq!(origin.height, expected, "{tag}");
+            assert_eq!(origin.pool.as_deref(), pool, "{tag}");
+            assert_eq!(ScriptBuf::from(origin.tag_bytes).to_hex_string(), tag);
+        }
+    }
+
+    #[test]
+    fn test_pre_bip34_coinbases_have_no_height() {
+        let genesis = mined(GENESIS, 5_000_000_000);
+        let origin = CoinbaseOrigin::from_coinbase(&genesis, &BTreeMap::new()).unwrap();
+        assert_eq!(origin.height, None);
+        assert_eq!(origin.pool, None);
+        assert_eq!(origin.tag_bytes, genesis.input[0].script_sig.to_bytes());
+
+        assert_eq!(bip34_height(&ScriptBuf::new()), None);
+        // A push cut short
+        assert_eq!(bip34_height(&ScriptBuf::from_hex("0339").unwrap()), None);
+        let payment = spend(1, &[OutPoint::new(Txid::all_zeros(), 0)], &[1]);
+        assert_eq!(
+            CoinbaseOrigin::from_coinbase(&payment, &BTreeMap::new()),
+            None
+        );
+    }
+
+    #[test]
+    fn test_labelled_outputs_name_untagged_pools() {
+        let tx = mined("0339650c", 312_500_000);
+        let labels = BTreeMap::from([(
+            0,
+            Label {
+                entity: "Ocean".to_string(),
+                category: EntityCategory::Other,
+                source: None,
+                confidence: 1.0,
+            },
+        )]);
+        let origin = CoinbaseOrigin::from_coinbase(&tx, &labels).unwrap();
+        assert_eq!(origin.pool.as_deref(), Some("Ocean"));
+        // The tag wins over the labels
+        let tagged = mined("0339650c0c2f466f756e64727920555341", 312_500_000);
+        let origin = CoinbaseOrigin::from_coinbase(&tagged, &labels).unwrap();
+        assert_eq!(origin.pool.as_deref(), Some("Foundry USA"));
+    }
+
+    #[test]
+    fn test_provenance_shares_the_coins_by_pool() {
+        // "/Foundry USA" at heights 812345 and 830000, "Mined by AntPool" at 830000
+        let foundry = "0c2f466f756e64727920555341";
+        let antpool = "104d696e656420627920416e74506f6f6c";
+        let coinbases = [
+            mined(&format!("0339650c{foundry}"), 300),
+            mined(&format!("0330aa0c{foundry}"), 300),
+            mined(&format!("0330aa0c{antpool}"), 100),
+        ];
+        let mut graph = TraceGraph::new();
+        for tx in &coinbases {
+            let mut node = TraceNode::new(tx, 1);
+            node.origin = CoinbaseOrigin::from_coinbase(tx, &BTreeMap::new());
+            graph.insert_node(node);
+        }
+        // Only the output of the genesis coinbase spent in the graph counts
+        let mut genesis = coinbase(9, &[400, 5_000_000_000]);
+        genesis.input[0].script_sig = ScriptBuf::from_hex(GENESIS).unwrap();
+        let mut node = TraceNode::new(&genesis, 1);
+        node.origin = CoinbaseOrigin::from_coinbase(&genesis, &BTreeMap::new());
+        graph.insert_node(node);
+        graph.insert_edge(TraceEdge {
+            spent_by: Some(Txid::all_zeros()),
+            ..TraceEdge::new(
+                OutPoint::new(genesis.compute_txid(), 0),
+                &genesis.output[0],
+                Network::Bitcoin,
+            )
+        });
+
+        let provenance = CoinbaseProvenance::from_graph(&graph).unwrap();
+        assert_eq!(provenance.total, Amount::from_sat(1_100));
+        let foundry = &provenance.pools[0];
+        assert_eq!(foundry.pool.as_deref(), Some("Foundry USA"));
+        assert_eq!(foundry.value, Amount::from_sat(600));
+        assert_eq!(foundry.coinbases, 2);
+        assert_eq!(foundry.heights, Some((812_345, 830_000)));
+        let unknown = &provenance.pools[1];
+        assert_eq!(unknown.pool, None);
+        assert_eq!(unknown.value, Amount::from_sat(400));
+        assert_eq!(unknown.heights, None);
+        assert_eq!(
+            provenance.to_string(),
+            "Ancestry resolves to 55% Foundry USA-mined coins from heights 812345–830000\n  \
+             36% coins of unknown pools\n  \
+             9% AntPool-mined coins at height 830000\n"
+        );
+
+        assert_eq!(CoinbaseProvenance::from_graph(&TraceGraph::new()), None);
+    }
+}
@@ -0,0 +1,402 @@
+//! CoinJoin detection.
+//!
+//! Following coins through a CoinJoin fans out into every participant's outputs and
+//! says nothing about where the traced coins went, so traces stop at CoinJoins by
+//! default (see `CoinJoinPolicy`).
+//!
+//! A transaction is flagged when it has a group of equal-valued outputs and about as
+//! many inputs from distinct sources, and is scored higher when the group is a known
+//! mixing denomination.
+
+use bitcoin::{Amount, Transaction, TxOut};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/// Whirlpool pool denominations
+pub const WHIRLPOOL_POOLS: [Amount; 4] = [
+    Amount::from_sat(100_000),
+    Amount::from_sat(1_000_000),
+    Amount::from_sat(5_000_000),
+    Amount::from_sat(50_000_000),
+];
+
+/// Denomination of Wasabi 1.x rounds, which drifted around 0.1 BTC
+pub const WASABI_DENOMINATION: Amount = Amount::from_sat(10_000_000);
+
+/// What a trace does at a transaction flagged as a CoinJoin
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CoinJoinPolicy {
+    /// Record every output as a `CoinJoin` leaf and stop there
+    #[default]
+    StopAndMark,
+    /// Trace through it like any other transaction
+    Continue,
+    /// Follow only the mixed outputs of a denomination the traced input could pay for
+    FollowMatchingValue,
+}
+
+/// Which implementation a CoinJoin looks like
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CoinJoinKind {
+    /// Equal outputs of a Whirlpool pool denomination and no change
+    Whirlpool,
+    /// Equal outputs around 0.1 BTC (Wasabi 1.x) or several groups of equal outputs
+    /// (Wasabi 2.0 standard denominations)
+    Wasabi,
+    /// Equal outputs of any other value
+    Generic,
+}
+
+impl CoinJoinKind {
+    /// Stable lowercase name of the kind, as used by the exports
+    pub fn code(&self) -> &'static str {
+        match self {
+            CoinJoinKind::Whirlpool => "whirlpool",
+            CoinJoinKind::Wasabi => "wasabi",
+            CoinJoinKind::Generic => "generic",
+        }
+    }
+}
+
+/// Why a transaction was flagged as a CoinJoin.
+///
+/// # Fields
+/// * `kind` - which implementation it looks like
+/// * `score` - how CoinJoin-like it is, from 0.0 to 1.0
+/// * `denomination` - value of the largest group of equal outputs
+/// * `equal_outputs` - size of that group
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CoinJoinVerdict {
+    pub kind: CoinJoinKind,
+    pub score: f64,
+    pub denomination: Amount,
+    pub equal_outputs: usize,
+}
+
+/// Flags CoinJoin transactions.
+///
+/// # Fields
+/// * `min_equal_outputs` - smallest group of equal outputs that can be a CoinJoin
+/// * `min_distinct_inputs` - fewest inputs from distinct sources that can be one
+/// * `tolerance` - relative difference under which two output values are equal
+/// * `denominations` - known mixing denominations, which raise the score
+/// * `min_score` - score from which a transaction is flagged
+#[derive(Debug, Clone, PartialEq)]
+pub struct CoinJoinDetector {
+    pub min_equal_outputs: usize,
+    pub min_distinct_inputs: usize,
+    pub tolerance: f64,
+    pub denominations: Vec<Amount>,
+    pub min_score: f64,
+}
+
+impl Default for CoinJoinDetector {
+    fn default() -> Self {
+        let mut denominations = WHIRLPOOL_POOLS.to_vec();
+        denominations.push(WASABI_DENOMINATION);
+        Self {
+            min_equal_outputs: 5,
+            min_distinct_inputs: 5,
+            tolerance: 0.0,
+            denominations,
+            min_score: 0.5,
+        }
+    }
+}
+
+impl CoinJoinDetector {
+    /// Smallest group of equal outputs that can be a CoinJoin
+    pub fn min_equal_outputs(mut self, count: usize) -> Self {
+        self.min_equal_outputs = count;
+        self
+    }
+
+    /// Fewest inputs from distinct sources that can be a CoinJoin
+    pub fn min_distinct_inputs(mut self, count: usize) -> Self {
+        self.min_distinct_inputs = count;
+        self
+    }
+
+    /// Relative difference under which two output values are equal (0.01 = 1%)
+    pub fn tolerance(mut self, tolerance: f64) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    /// Judges `tx`, given the outputs it spends as far as they are known.
+    ///
+    /// Inputs count as distinct by the script they spend when every prevout is known,
+    /// and by the transaction they come from otherwise.
+    pub fn detect(&self, tx: &Transaction, prevouts: &[TxOut]) -> Option<CoinJoinVerdict> {
+        if tx.is_coinbase() {
+            return None;
+        }
+        let groups = self.equal_groups(&tx.output);
+        let (denomination, equal_outputs) = groups.iter().copied().max_by_key(|&(_, n)| n)?;
+        let distinct_inputs = if prevouts.len() == tx.input.len() {
+            let scripts: HashSet<_> = prevouts.iter().map(|out| &out.script_pubkey).collect();
+            scripts.len()
+        } else {
+            let txids: HashSet<_> = tx
+                .input
+                .iter()
+                .map(|input| input.previous_output.txid)
+                .collect();
+            txids.len()
+        };
+        if equal_outputs < self.min_equal_outputs || distinct_inputs < self.min_distinct_inputs {
+            return None;
+        }
+
+        let known = self
+            .denominations
+            .iter()
+            .any(|&known| self.equal(known, denomination));
+        let score = 0.4 * equal_outputs as f64 / tx.output.len() as f64
+            + 0.3 * (distinct_inputs as f64 / equal_outputs as f64).min(1.0)
+            + if known { 0.3 } else { 0.0 };
+        if score < self.min_score {
+            return None;
+        }
+
+        let several_groups = groups.iter().filter(|&&(_, n)| n >= 2).count() >= 3;
+        let kind = if WHIRLPOOL_POOLS.contains(&denomination) && equal_outputs == tx.output.len() {
+            CoinJoinKind::Whirlpool
+        } else if several_groups || near(denomination, WASABI_DENOMINATION, 0.1) {
+            CoinJoinKind::Wasabi
+        } else {
+            CoinJoinKind::Generic
+        };
+        Some(CoinJoinVerdict {
+            kind,
+            score,
+            denomination,
+            equal_outputs,
+        })
+    }
+
+    /// Outputs of a flagged transaction to follow under `FollowMatchingValue`: the
+    /// mixed outputs, if the `traced` input was worth at least their denomination.
+    pub fn matching_outputs(
+        &self,
+        verdict: &CoinJoinVerdict,
+        outputs: &[TxOut],
+        traced: Amount,
+    ) -> Vec<usize> {
+        if traced < verdict.denomination {
+            return Vec::new();
+        }
+        (0..outputs.len())
+            .filter(|&vout| self.equal(outputs[vout].value, verdict.denomination))
+            .collect()
+    }
+
+    /// Groups of equal output values, as (smallest value, count)
+    fn equal_groups(&self, outputs: &[TxOut]) -> Vec<(Amount, usize)> {
+        let mut values: Vec<_> = outputs.iter().map(|out| out.value).collect();
+        values.sort_unstable();
+        let mut groups: Vec<(Amount, usize)> = Vec::new();
+        for value in values {
+            match groups.last_mut() {
+                Some((base, count)) if self.equal(*base, value) => *count += 1,
+                _ => groups.push((value, 1)),
+            }
+        }
+        groups
+    }
+
+    fn equal(&self, a: Amount, b: Amount) -> bool {
+        near(a, b, self.tolerance)
+    }
+}
+
+/// Whether `a` is within `tolerance` (relative) of `b`
+pub(crate) fn near(a: Amount, b: Amount, tolerance: f64) -> bool {
+    let (a, b) = (a.to_sat() as f64, b.to_sat() as f64);
+    (a - b).abs() <= b * tolerance
+}
+
+#[cfg(all(test, feature = "trace-engine"))]
+mod tests {
+    use super::*;
+    use crate::tracer::{
+        TerminalReason, TraceConfig, Tracer,
+        fixtures::{MockDataSource, script, spend},
+    };
+    use bitcoin::OutPoint;
+
+    /// Spends one output of each of `inputs` distinct funding transactions into
+    /// `values`; also returns the funding transactions
+    fn join(tag: u32, inputs: u32, values: &[u64]) -> (Transaction, Vec<Transaction>) {
+        let funding: Vec<_> = (0..inputs)
+            .map(|n| {
+                spend(
+                    tag * 1_000 + n,
+                    &[],
+                    &[values.iter().sum::<u64>() / u64::from(inputs) + 10_000],
+                )
+            })
+            .collect();
+        let prevouts: Vec<_> = funding
+            .iter()
+            .map(|tx| OutPoint::new(tx.compute_txid(), 0))
+            .collect();
+        let mut tx = spend(tag, &prevouts, values);
+        for (n, out) in tx.output.iter_mut().enumerate() {
+            out.script_pubkey = script(n as u8);
+        }
+        (tx, funding)
+    }
+
+    // Synthetic transactions with the structure of the real thing: the shape, not the
+    // history, is what the detector looks at.
+
+    /// Whirlpool 0.05 BTC pool mix: five inputs, five equal outputs, no change
+    fn whirlpool() -> (Transaction, Vec<Transaction>) {
+        join(1, 5, &[5_000_000; 5])
+    }
+
+    /// Wasabi 2.0 round: 60 inputs and outputs of several standard denominations plus
+    /// change
+    fn wasabi() -> (Transaction, Vec<Transaction>) {
+        let mut values = Vec::new();
+        for (denomination, count) in [(5_000_000, 12), (2_000_000, 10), (1_000_000, 15)] {
+            values.extend(std::iter::repeat_n(denomination, count));
+        }
+        values.extend([123_456, 98_765, 2_345_678, 777_777]);
+        join(2, 60, &values)
+    }
+
+    /// Exchange payout batch: one input, 40 payments of assorted values, change
+    fn batch() -> Transaction {
+        let values: Vec<u64> = (1..=40)
+            .map(|n| 100_000 + n * 7_919)
+            .chain([90_000_000])
+            .collect();
+        join(3, 1, &values).0
+    }
+
+    #[test]
+    fn test_flags_whirlpool_and_wasabi() {
+        let detector = CoinJoinDetector::default();
+
+        let verdict = detector.detect(&whirlpool().0, &[]).unwrap();
+        assert_eq!(verdict.kind, CoinJoinKind::Whirlpool);
+        assert_eq!(verdict.denomination, Amount::from_sat(5_000_000));
+        assert_eq!(verdict.equal_outputs, 5);
+        assert!((verdict.score - 1.0).abs() < 1e-9);
+
+        let verdict = detector.detect(&wasabi().0, &[]).unwrap();
+        assert_eq!(verdict.kind, CoinJoinKind::Wasabi);
+        assert_eq!(verdict.equal_outputs, 15);
+        assert!(verdict.score > 0.5 && verdict.score < 1.0);
+    }
+
+    #[test]
+    fn test_batch_payout_is_not_a_coinjoin() {
+        let detector = CoinJoinDetector::default();
+        assert_eq!(detector.detect(&batch(), &[]), None);
+
+        // Equal payouts from a single wallet are not one either
+        let (payroll, _) = join(4, 1, &[1_000_000; 10]);
+        assert_eq!(detector.detect(&payroll, &[]), None);
+    }
+
+    #[test]
+    fn test_tolerance_groups_close_values() {
+        let (tx, _) = join(5, 5, &[1_000_000, 1_000_400, 999_800, 1_000_100, 1_000_900]);
+
+        assert_eq!(CoinJoinDetector::default().detect(&tx, &[]), None);
+        let verdict = CoinJoinDetector::default()
+            .tolerance(0.002)
+            .detect(&tx, &[])
+            .unwrap();
+        assert_eq!(verdict.equal_outputs, 5);
+        assert_eq!(verdict.kind, CoinJoinKind::Generic);
+    }
+
+    /// Source with `mix` spending the outputs of its funding transactions
+    fn source(mix: &Transaction, funding: &[Transaction]) -> MockDataSource {
+        let mut source = MockDataSource::new(funding);
+        source.add(mix.clone());
+        source
+    }
+
+    #[tokio::test]
+    async fn test_policies() {
+        let (mix, funding) = whirlpool();
+        let root = OutPoint::new(funding[0].compute_txid(), 0);
+        let tracer = Tracer::new(source(&mix, &funding));
+        let trace = |policy| {
+            let config = TraceConfig::default().coinjoin_policy(policy);
+            let tracer = &tracer;
+            async move {
+                tracer
+                    .trace_forward(root, &config)
+                    .await
+                    .unwrap()
+                    .into_graph()
+            }
+        };
+
+        let graph = trace(CoinJoinPolicy::StopAndMark).await;
+        let node = graph.node(&mix.compute_txid()).unwrap();
+        assert_eq!(
+            node.coinjoin.as_ref().unwrap().kind,
+            CoinJoinKind::Whirlpool
+        );
+        assert!(
+            graph
+                .outputs_of(&mix.compute_txid())
+                .all(|edge| edge.terminal == Some(TerminalReason::CoinJoin))
+        );
+        // The mixed outputs are not looked up
+        assert_eq!(tracer.source().calls(), 2);
+
+        let graph = trace(CoinJoinPolicy::Continue).await;
+        assert!(graph.node(&mix.compute_txid()).unwrap().coinjoin.is_some());
+        assert!(
+            graph
+                .outputs_of(&mix.compute_txid())
+                .all(|edge| edge.terminal == Some(TerminalReason::Unspent))
+        );
+
+        // The traced input pays for the 0.05 pool, so every mixed output may be ours
+        let graph = trace(CoinJoinPolicy::FollowMatchingValue).await;
+        assert_eq!(
+            graph
+                .outputs_of(&mix.compute_txid())
+                .filter(|edge| edge.terminal == Some(TerminalReason::Unspent))
+                .count(),
+            5
+        );
+    }
+
+    #[tokio::test]
+    async fn test_follow_matching_value_skips_change() {
+        let (mix, funding) = wasabi();
+        let root = OutPoint::new(funding[0].compute_txid(), 0);
+        let tracer = Tracer::new(source(&mix, &funding));
+        let config = TraceConfig::default().coinjoin_policy(CoinJoinPolicy::FollowMatchingValue);
+
+        let graph = tracer
+            .trace_forward(root, &config)
+            .await
+            .unwrap()
+            .into_graph();
+
+        let outputs: Vec<_> = graph.outputs_of(&mix.compute_txid()).collect();
+        assert_eq!(outputs.len(), mix.output.len());
+        let followed = outputs
+            .iter()
+            .filter(|edge| edge.terminal == Some(TerminalReason::Unspent))
+            .count();
+        assert_eq!(followed, 15);
+        assert!(
+            outputs
+                .iter()
+                .filter(|edge| edge.value != Amount::from_sat(1_000_000))
+                .all(|edge| edge.terminal == Some(TerminalReason::CoinJoin))
+        );
+    }
+}
@@ -0,0 +1,1011 @@
+//! Configuration of a trace: how far to go and which outputs to follow.
+
+use crate::blockchain::TxStatus;
+use crate::tracer::{
+    Result, TerminalReason, TraceAnnotator, TracerError,
+    annotate::Annotators,
+    cancel::CancelToken,
+    change::{ChangeContext, ChangeDetector, ChangeVerdict},
+    checkpoint::CheckpointSchedule,
+    coinjoin::{CoinJoinDetector, CoinJoinPolicy},
+    events::DEFAULT_EVENT_BUFFER,
+    labels::{LabelPolicy, LabelStore},
+    lightning::LightningPolicy,
+    pattern::{BatchPolicy, PatternClassifier},
+    peel,
+    visited::VisitedSet,
+};
+use bitcoin::{Address, Amount, Network, Script, ScriptBuf, TxOut};
+use std::{collections::HashSet, path::PathBuf, sync::Arc, time::Duration};
+
+/// Default number of hops followed from the starting transaction
+pub const DEFAULT_MAX_DEPTH: usize = 10;
+
+/// Default cap on the number of transactions in a trace
+pub const DEFAULT_MAX_TRANSACTIONS: usize = 1_000;
+
+/// Default number of lookups a trace has in flight at once
+pub const DEFAULT_CONCURRENCY: usize = 4;
+
+/// Limits and strategy of a trace.
+///
+/// # Fields
+/// * `max_depth` - hops followed from the starting transaction (depth 0); outputs of
+///   transactions at this depth are recorded but not followed
+/// * `max_transactions` - cap on the number of transactions in the graph, outputs whose
+///   spender would go over it are recorded as truncated
+/// * `max_breadth` - cap on the number of transactions at any one depth (`None` =
+///   unlimited), since ancestry and payouts fan out fast
+/// * `max_requests` - cap on the requests made to the data source (`None` =
+///   unlimited); lookups a cache answers are free. Outputs still queued when it runs
+///   out are `BudgetExhausted` leaves
+/// * `dry_run` - depth a cost estimate samples the trace to (`None` = a real trace),
+///   see `TraceReport::estimated_requests`
+/// * `min_output_value` - outputs of a spending transaction worth less are leaves,
+///   never followed (0 by default: every output may be)
+/// * `min_block_height` - spending transactions confirmed below this height are out of
+///   the window, not traced through (`None` = no bound)
+/// * `max_block_height` - spending transactions confirmed above this height are out of
+///   the window (`None` = no bound)
+/// * `max_timestamp` - spending transactions in a block timestamped later (Unix
+///   seconds) are out of the window (`None` = no bound)
+/// * `include_unconfirmed` - whether unconfirmed spending transactions are in the
+///   window when it is bounded (`true` by default)
+/// * `branch` - which outputs of a spending transaction are followed
+/// * `network` - network used to derive addresses from output scripts
+/// * `stop` - target scripts the trace stops at
+/// * `coinjoin_detector` - how CoinJoin transactions are recognized
+/// * `coinjoin_policy` - what the trace does at a CoinJoin
+/// * `pattern_classifier` - how the shape of each transaction traced through is
+///   classified
+/// * `batch_policy` - which outputs of a batch payout a forward trace follows
+/// * `lightning_policy` - what a forward trace does at an output funding a Lightning
+///   channel
+/// * `change_detector` - how the change of the transactions traced through is scored,
+///   the verdicts recorded on their outputs
+/// * `labels` - known entities, marked on the outputs paying them (`None` = no labels)
+/// * `label_policy` - categories of labelled outputs the trace stops at
+/// * `annotators` - custom heuristics run on every transaction the trace adds, their
+///   annotations recorded on its node
+/// * `visited` - transactions earlier traces expanded, not expanded again (`None` =
+///   expand everything)
+/// * `retry` - how lookups failing with a transient error are retried
+/// * `continue_on_error` - whether a forward lookup failing for good, retries spent,
+///   ends only its branch, as an `Error` leaf, rather than the whole trace
+/// * `concurrency` - lookups in flight at once, at least 1. The graph does not depend
+///   on it; behind a `RateLimitedDataSource`, concurrent lookups share one bucket, so
+///   a higher value does not go past the limit of a public instance
+/// * `event_buffer` - events held for a slow consumer of a trace's events, at least 1
+/// * `cancel` - token stopping the trace early, with the graph traced so far
+/// * `checkpoint` - when and where the trace saves checkpoints to resume from
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceConfig {
+    pub max_depth: usize,
+    pub max_transactions: usize,
+    pub max_breadth: Option<usize>,
+    pub max_requests: Option<usize>,
+    pub dry_run: Option<usize>,
+    pub min_output_value: Amount,
+    pub min_block_height: Option<u32>,
+    pub max_block_height: Option<u32>,
+    pub max_timestamp: Option<u64>,
+    pub include_unconfirmed: bool,
+    pub branch: BranchStrategy,
+    pub network: Network,
+    pub stop: StopCondition,
+    pub coinjoin_detector: CoinJoinDetector,
+    pub coinjoin_policy: CoinJoinPolicy,
+    pub pattern_classifier: PatternClassifier,
+    pub batch_policy: BatchPolicy,
+    pub lightning_policy: LightningPolicy,
+    pub change_detector: ChangeDetector,
+    pub labels: Option<Arc<LabelStore>>,
+    pub label_policy: LabelPolicy,
+    pub annotators: Annotators,
+    pub visited: Option<Arc<VisitedSet>>,
+    pub retry: RetryPolicy,
+    pub continue_on_error: bool,
+    pub concurrency: usize,
+    pub event_buffer: usize,
+    pub cancel: Option<CancelToken>,
+    pub checkpoint: Option<CheckpointSchedule>,
+}
+
+impl Default for TraceConfig {
+    fn default() -> Self {
+        Self {
+            max_depth: DEFAULT_MAX_DEPTH,
+            max_transactions: DEFAULT_MAX_TRANSACTIONS,
+            max_breadth: None,
+            max_requests: None,
+            dry_run: None,
+            min_output_value: Amount::ZERO,
+            min_block_height: None,
+            max_block_height: None,
+            max_timestamp: None,
+            include_unconfirmed: true,
+            branch: BranchStrategy::AllOutputs,
+            network: Network::Bitcoin,
+            stop: StopCondition::default(),
+            coinjoin_detector: CoinJoinDetector::default(),
+            coinjoin_policy: CoinJoinPolicy::default(),
+            pattern_classifier: PatternClassifier::default(),
+            batch_policy: BatchPolicy::default(),
+            lightning_policy: LightningPolicy::default(),
+            change_detector: ChangeDetector::default(),
+            labels: None,
+            label_policy: LabelPolicy::default(),
+            annotators: Annotators::default(),
+            visited: None,
+            retry: RetryPolicy::default(),
+            continue_on_error: false,
+            concurrency: DEFAULT_CONCURRENCY,
+            event_buffer: DEFAULT_EVENT_BUFFER,
+            cancel: None,
+            checkpoint: None,
+        }
+    }
+}
+
+impl TraceConfig {
+    /// Hops followed from the starting transaction
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// Cap on the number of transactions in the graph
+    pub fn max_transactions(mut self, max_transactions: usize) -> Self {
+        self.max_transactions = max_transactions;
+        self
+    }
+
+    /// Cap on the number of transactions at any one depth
+    pub fn max_breadth(mut self, max_breadth: usize) -> Self {
+        self.max_breadth = Some(max_breadth);
+        self
+    }
+
+    /// Cap on the requests made to the data source
+    pub fn max_requests(mut self, max_requests: usize) -> Self {
+        self.max_requests = Some(max_requests);
+        self
+    }
+
+    /// Only samples the trace to `depth` (1 or 2 is usually enough) and estimates the
+    /// requests the full trace would make
+    pub fn dry_run(mut self, depth: usize) -> Self {
+        self.dry_run = Some(depth);
+        self
+    }
+
+    /// Leaves outputs worth less than `value` unfollowed, whatever the branch strategy:
+    /// dust multiplies the size of a trace for nothing
+    pub fn min_output_value(mut self, value: Amount) -> Self {
+        self.min_output_value = value;
+        self
+    }
+
+    /// Leaves spending transactions confirmed below `height` out of the window
+    pub fn min_block_height(mut self, height: u32) -> Self {
+        self.min_block_height = Some(height);
+        self
+    }
+
+    /// Leaves spending transactions confirmed above `height` out of the window
+    pub fn max_block_height(mut self, height: u32) -> Self {
+        self.max_block_height = Some(height);
+        self
+    }
+
+    /// Leaves spending transactions in blocks timestamped after `timestamp` (Unix
+    /// seconds) out of the window
+    pub fn max_timestamp(mut self, timestamp: u64) -> Self {
+        self.max_timestamp = Some(timestamp);
+        self
+    }
+
+    /// Whether unconfirmed spending transactions are in a bounded window
+    pub fn include_unconfirmed(mut self, include: bool) -> Self {
+        self.include_unconfirmed = include;
+        self
+    }
+
+    /// Whether the window is bounded at all: only then are the confirmation
+    /// statuses of the transactions traced looked up
+    pub fn windowed(&self) -> bool {
+        self.min_block_height.is_some()
+            || self.max_block_height.is_some()
+            || self.max_timestamp.is_some()
+    }
+
+    /// Whether a transaction with `status`, confirmed at `block_time` when known, is in
+    /// the window. A bound the status cannot be checked against (no height, or no
+    /// time) does not exclude it.
+    pub fn in_window(&self, status: &TxStatus, block_time: Option<u64>) -> bool {
+        if !status.confirmed {
+            return self.include_unconfirmed;
+        }
+        let height = status.block_height;
+        !(self
+            .min_block_height
+            .zip(height)
+            .is_some_and(|(min, h)| h < min)
+            || self
+                .max_block_height
+                .zip(height)
+                .is_some_and(|(max, h)| h > max)
+            || self
+                .max_timestamp
+                .zip(block_time)
+                .is_some_and(|(max, time)| time > max))
+    }
+
+    /// Which outputs of a spending transaction are followed
+    pub fn branch(mut self, branch: BranchStrategy) -> Self {
+        self.branch = branch;
+        self
+    }
+
+    /// Network used to derive addresses from output scripts
+    pub fn network(mut self, network: Network) -> Self {
+        self.network = network;
+        self
+    }
+
+    /// Target scripts the trace stops at
+    pub fn stop(mut self, stop: StopCondition) -> Self {
+        self.stop = stop;
+        self
+    }
+
+    /// How CoinJoin transactions are recognized
+    pub fn coinjoin_detector(mut self, detector: CoinJoinDetector) -> Self {
+        self.coinjoin_detector = detector;
+        self
+    }
+
+    /// What the trace does at a CoinJoin
+    pub fn coinjoin_policy(mut self, policy: CoinJoinPolicy) -> Self {
+        self.coinjoin_policy = policy;
+        self
+    }
+
+    /// How the shape of each transaction traced through is classified
+    pub fn pattern_classifier(mut self, classifier: PatternClassifier) -> Self {
+        self.pattern_classifier = classifier;
+        self
+    }
+
+    /// Which outputs of a batch payout a forward trace follows
+    pub fn batch_policy(mut self, policy: BatchPolicy) -> Self {
+        self.batch_policy = policy;
+        self
+    }
+
+    /// What a forward trace does at an output funding a Lightning channel
+    pub fn lightning_policy(mut self, policy: LightningPolicy) -> Self {
+        self.lightning_policy = policy;
+        self
+    }
+
+    /// How the change of the transactions traced through is scored
+    pub fn change_detector(mut self, detector: ChangeDetector) -> Self {
+        self.change_detector = detector;
+        self
+    }
+
+    /// Known entities to mark on the outputs paying them. The store is shared, so
+    /// configs of several traces can hold the same large store.
+    pub fn labels(mut self, labels: Arc<LabelStore>) -> Self {
+        self.labels = Some(labels);
+        self
+    }
+
+    /// Categories of labelled outputs the trace stops at
+    pub fn label_policy(mut self, policy: LabelPolicy) -> Self {
+        self.label_policy = policy;
+        self
+    }
+
+    /// Custom heuristics run, in this order, on every transaction the trace adds
+    pub fn annotators(mut self, annotators: Vec<Box<dyn TraceAnnotator + Send + Sync>>) -> Self {
+        self.annotators = Annotators::new(annotators);
+        self
+    }
+
+    /// Transactions not to expand again, as earlier traces already did. Like the
+    /// labels, the set is shared between configs.
+    pub fn visited(mut self, visited: Arc<VisitedSet>) -> Self {
+        self.visited = Some(visited);
+        self
+    }
+
+    /// How lookups failing with a transient error are retried
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Whether a forward lookup failing for good ends only its branch, recording the
+    /// error on it, rather than the whole trace
+    pub fn continue_on_error(mut self, continue_on_error: bool) -> Self {
+        self.continue_on_error = continue_on_error;
+        self
+    }
+
+    /// Lookups in flight at once
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency;
+        self
+    }
+
+    /// Events held for a slow consumer of the trace's events
+    pub fn event_buffer(mut self, event_buffer: usize) -> Self {
+        self.event_buffer = event_buffer;
+        self
+    }
+
+    /// Token stopping the trace early, with the graph traced so far
+    pub fn cancel(mut self, token: CancelToken) -> Self {
+        self.cancel = Some(token);
+        self
+    }
+
+    /// Saves a checkpoint to `path` every `requests` requests to the data source, and
+    /// when the trace is cancelled
+    pub fn checkpoint_every(mut self, requests: usize, path: impl Into<PathBuf>) -> Self {
+        self.checkpoint = Some(CheckpointSchedule {
+            every: Some(requests),
+            path: path.into(),
+        });
+        self
+    }
+
+    /// Saves a checkpoint to `path` when the trace is cancelled, of the work it had left
+    pub fn checkpoint_on_cancel(mut self, path: impl Into<PathBuf>) -> Self {
+        self.checkpoint = Some(CheckpointSchedule {
+            every: None,
+            path: path.into(),
+        });
+        self
+    }
+
+    /// Stable hash of the settings shaping the graph: the caps (the request budget
+    /// and dry runs included), minimum output value, window, branch strategy, network,
+    /// stop condition, CoinJoin handling, pattern thresholds, batch and Lightning
+    /// policies, change detector, labels, label policy, the names of the annotators and
+    /// the transactions visited before, and whether errors end only their branch.
+    ///
+    /// Retries, concurrency, events, cancellation and checkpoints are left out: resuming with other
+    /// values for them still yields the same graph.
+    pub fn fingerprint(&self) -> u64 {
+        let mut targets: Vec<_> = self
+            .stop
+            .targets
+            .iter()
+            .map(|script| script.to_hex_string())
+            .collect();
+        targets.sort();
+        let canonical = format!(
+            "{}|{}|{:?}|{:?}|{:?}|{}|{:?}|{:?}|{:?}|{}|{:?}|{}|{:?}|{}|{:?}|{:?}|{:?}|{:?}|{:?}|{:?}|{:?}|{:?}|{:?}|{:?}|{}",
+            self.max_depth,
+            self.max_transactions,
+            self.max_breadth,
+            self.max_requests,
+            self.dry_run,
+            self.min_output_value.to_sat(),
+            self.min_block_height,
+            self.max_block_height,
+            self.max_timestamp,
+            self.include_unconfirmed,
+            self.branch,
+            self.network,
+            targets,
+            self.stop.early_exit,
+            self.coinjoin_detector,
+            self.coinjoin_policy,
+            self.pattern_classifier,
+            self.batch_policy,
+            self.lightning_policy,
+            self.change_detector,
+            self.labels.as_ref().map(|labels| labels.digest()),
+            self.label_policy.stop,
+            self.annotators,
+            self.visited.as_ref().map(|visited| visited.digest()),
+            self.continue_on_error,
+        );
+        fnv1a(canonical.as_bytes())
+    }
+
+    /// Stable hash of the settings deciding how a single transaction is expanded:
+    /// those of `fingerprint` but the caps, the early exit and the visited set. Two
+    /// traces expand a transaction alike when their expansion fingerprints match,
+    /// however far each goes.
+    pub fn expansion_fingerprint(&self) -> u64 {
+        let mut targets: Vec<_> = self
+            .stop
+            .targets
+            .iter()
+            .map(|script| script.to_hex_string())
+            .collect();
+        targets.sort();
+        let canonical = format!(
+            "{}|{:?}|{:?}|{:?}|{}|{:?}|{}|{:?}|{:?}|{:?}|{:?}|{:?}|{:?}|{:?}|{:?}|{:?}|{:?}",
+            self.min_output_value.to_sat(),
+            self.min_block_height,
+            self.max_block_height,
+            self.max_timestamp,
+            self.include_unconfirmed,
+            self.branch,
+            self.network,
+            targets,
+            self.coinjoin_detector,
+            self.coinjoin_policy,
+            self.pattern_classifier,
+            self.batch_policy,
+            self.lightning_policy,
+            self.change_detector,
+            self.labels.as_ref().map(|labels| labels.digest()),
+            self.label_policy.stop,
+            self.annotators,
+        );
+        fnv1a(canonical.as_bytes())
+    }
+
+    /// Checks the configuration before a trace starts.
+    ///
+    /// # Errors
+    /// - `InvalidConfig` - `max_transactions`, `max_breadth`, `max_requests`, the
+    ///   `dry_run` depth, `concurrency`, the `k` of `TopKByValue` or the checkpoint
+    ///   interval is 0, the `min_share` of `ValueWeighted` is out
+    ///   of range, or a weight or margin of the change detector is negative or not
+    ///   finite, or its medium margin above its high one, or `min_block_height` is
+    ///   above `max_block_height`, or the tolerance of a batch policy is negative or
+    ///   not finite
+    pub fn validate(&self) -> Result<()> {
+        if self.max_transactions == 0 {
+            return Err(TracerError::InvalidConfig(
+                "max_transactions must be at least 1".to_string(),
+            ));
+        }
+        if self.max_breadth == Some(0) {
+            return Err(TracerError::InvalidConfig(
+                "max_breadth must be at least 1".to_string(),
+            ));
+        }
+        if self.max_requests == Some(0) {
+            return Err(TracerError::InvalidConfig(
+                "max_requests must be at least 1".to_string(),
+            ));
+        }
+        if self.dry_run == Some(0) {
+            return Err(TracerError::InvalidConfig(
+                "a dry run must sample at least 1 hop".to_string(),
+            ));
+        }
+        if self.concurrency == 0 {
+            return Err(TracerError::InvalidConfig(
+                "concurrency must be at least 1".to_string(),
+            ));
+        }
+        if self
+            .checkpoint
+            .as_ref()
+            .is_some_and(|schedule| schedule.every == Some(0))
+        {
+            return Err(TracerError::InvalidConfig(
+                "checkpoints must be at least 1 request apart".to_string(),
+            ));
+        }
+        if self.branch == BranchStrategy::TopKByValue(0) {
+            return Err(TracerError::InvalidConfig(
+                "top-k branch strategy must follow at least 1 output".to_string(),
+            ));
+        }
+        if let BranchStrategy::ValueWeighted { min_share } = self.branch
+            && !(0.0..=1.0).contains(&min_share)
+        {
+            return Err(TracerError::InvalidConfig(format!(
+                "value-weighted min_share must be within 0.0..=1.0, got {}",
+                min_share
+            )));
+        }
+        if let (Some(min), Some(max)) = (self.min_block_height, self.max_block_height)
+            && min > max
+        {
+            return Err(TracerError::InvalidConfig(format!(
+                "min_block_height {} is above max_block_height {}",
+                min, max
+            )));
+        }
+        if let BatchPolicy::FollowMatchingValue { tolerance } = self.batch_policy
+            && !(tolerance.is_finite() && tolerance >= 0.0)
+        {
+            return Err(TracerError::InvalidConfig(format!(
+                "batch payout tolerance must be finite and not negative, got {}",
+                tolerance
+            )));
+        }
+        let detector = &self.change_detector;
+        let weights = &detector.weights;
+        let valid = |value: f64| value.is_finite() && value >= 0.0;
+        if ![
+            weights.script_type,
+            weights.round_value,
+            weights.output_index,
+            weights.fee_sanity,
+            weights.fresh_address,
+            weights.cluster_reuse,
+            detector.medium_margin,
+            detector.high_margin,
+        ]
+        .into_iter()
+        .all(valid)
+            || detector.medium_margin > detector.high_margin
+        {
+            return Err(TracerError::InvalidConfig(format!(
+                "change detector weights and margins must be finite and not negative, \
+                 the medium margin at most the high one, got {:?}",
+                detector
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Retries of lookups failing with a transient error: a network failure or a rate
+/// limit. Other errors end the trace at once.
+///
+/// Unlike the backends' `blockchain::RetryPolicy`, the tracer's retries report
+/// themselves as `TraceEvent`s and stop when the trace is cancelled.
+///
+/// # Fields
+/// * `max_retries` - times a lookup is tried again before its error ends the trace (0
+///   by default: the first error does)
+/// * `backoff` - wait before retrying after a network failure, doubled on each further
+///   network failure of the same lookup
+/// * `rate_limit_wait` - wait before retrying a rate limited lookup, longer when the
+///   backend asks for more
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub backoff: Duration,
+    pub rate_limit_wait: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 0,
+            backoff: Duration::from_millis(500),
+            rate_limit_wait: Duration::from_secs(5),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Tries each lookup up to `max_retries` more times
+    pub fn new(max_retries: u32) -> Self {
+        Self {
+            max_retries,
+            ..Self::default()
+        }
+    }
+
+    /// Wait before the first retry after a network failure
+    pub fn backoff(mut self, backoff: Duration) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Wait before retrying a rate limited lookup
+    pub fn rate_limit_wait(mut self, wait: Duration) -> Self {
+        self.rate_limit_wait = wait;
+        self
+    }
+}
+
+/// Outputs a forward trace stops at: those paying one of the target scripts.
+///
+/// Targets are compared as script bytes, so every encoding of an address matches the
+/// same outputs. A matching output becomes a `ReachedTarget` leaf and is not followed,
+/// whatever the branch strategy.
+///
+/// # Fields
+/// * `targets` - scriptPubKeys to stop at
+/// * `early_exit` - abort the whole trace at the first target reached, keeping only
+///   the path to it
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StopCondition {
+    pub targets: HashSet<ScriptBuf>,
+    pub early_exit: bool,
+}
+
+impl StopCondition {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stops at outputs paying `address`
+    pub fn target_address(self, address: &Address) -> Self {
+        self.target_script(address.script_pubkey())
+    }
+
+    /// Stops at outputs paying `script`
+    pub fn target_script(mut self, script: ScriptBuf) -> Self {
+        self.targets.insert(script);
+        self
+    }
+
+    /// Aborts the whole trace at the first target reached
+    pub fn early_exit(mut self) -> Self {
+        self.early_exit = true;
+        self
+    }
+
+    /// Whether an output paying `script` is a target
+    pub fn matches(&self, script: &Script) -> bool {
+        self.targets.contains(script)
+    }
+}
+
+/// Which outputs of a spending transaction a trace continues from.
+///
+/// Outputs that are not followed still appear in the graph as leaf edges.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BranchStrategy {
+    /// Follow every output
+    AllOutputs,
+    /// Follow the output carrying the most value (the first one on ties)
+    LargestOutput,
+    /// Follow outputs worth at least the amount
+    AboveThreshold(Amount),
+    /// Follow the `k` outputs carrying the most value (earlier outputs first on ties)
+    TopKByValue(usize),
+    /// Follow outputs carrying at least `min_share` (0.0 to 1.0) of the transaction's
+    /// total output value
+    ValueWeighted { min_share: f64 },
+    /// Follow only the probable change of two-output transactions (see `peel`),
+    /// recording the payments as `Peeled`; follow every output when the change is
+    /// ambiguous or the transaction is not shaped like a peel
+    FollowPeelChain,
+    /// Follow only the output the change detector picks, when its verdict is at least
+    /// as confident as given; follow every output otherwise
+    FollowChange(peel::Confidence),
+}
+
+impl BranchStrategy {
+    /// Indexes of the `outputs` to follow, in output order. `inputs` are the outputs
+    /// the transaction spends, as far as they are known.
+    ///
+    /// OP_RETURN outputs cannot be spent: they are never selected, and the strategy
+    /// sees the other outputs only. `FollowChange` scores the outputs with the default
+    /// `ChangeDetector`, knowing nothing of the trace.
+    pub fn select(&self, outputs: &[TxOut], inputs: &[TxOut]) -> Vec<usize> {
+        let verdict = match self {
+            BranchStrategy::FollowChange(_) => {
+                ChangeDetector::default().detect(outputs, inputs, &ChangeContext::default())
+            }
+            _ => None,
+        };
+        self.select_with(outputs, inputs, verdict.as_ref())
+    }
+
+    /// `select`, with the change verdict of the transaction for `FollowChange`
+    pub(crate) fn select_with(
+        &self,
+        outputs: &[TxOut],
+        inputs: &[TxOut],
+        change: Option<&ChangeVerdict>,
+    ) -> Vec<usize> {
+        if let BranchStrategy::FollowChange(confidence) = *self
+            && let Some(vout) = change.and_then(|verdict| verdict.change_at(confidence))
+        {
+            return vec![vout];
+        }
+        let spendable: Vec<usize> = (0..outputs.len())
+            .filter(|&vout| !outputs[vout].script_pubkey.is_op_return())
+            .collect();
+        let candidates: Vec<TxOut> = spendable
+            .iter()
+            .map(|&vout| outputs[vout].clone())
+            .collect();
+        self.choose(&candidates, inputs)
+            .into_iter()
+            .map(|index| spendable[index])
+            .collect()
+    }
+
+    /// Indexes of the `outputs` to follow, all of them spendable
+    fn choose(&self, outputs: &[TxOut], inputs: &[TxOut]) -> Vec<usize> {
+        match *self {
+            BranchStrategy::AllOutputs => (0..outputs.len()).collect(),
+            BranchStrategy::LargestOutput => BranchStrategy::TopKByValue(1).choose(outputs, inputs),
+            BranchStrategy::AboveThreshold(threshold) => (0..outputs.len())
+                .filter(|&vout| outputs[vout].value >= threshold)
+                .collect(),
+            BranchStrategy::TopKByValue(k) => {
+                let mut by_value: Vec<usize> = (0..outputs.len()).collect();
+                // Stable, so ties keep output order
+                by_value.sort_by_key(|&vout| std::cmp::Reverse(outputs[vout].value));
+                by_value.truncate(k);
+                by_value.sort_unstable();
+                by_value
+            }
+            BranchStrategy::ValueWeighted { min_share } => {
+                let total: Amount = outputs.iter().map(|out| out.value).sum();
+                let threshold = total.to_sat() as f64 * min_share;
+                (0..outputs.len())
+                    .filter(|&vout| outputs[vout].value.to_sat() as f64 >= threshold)
+                    .collect()
+            }
+            BranchStrategy::FollowPeelChain => match peel::guess_change(outputs, inputs) {
+                Some(guess) if guess.confidence > peel::Confidence::Low => vec![guess.vout],
+                _ => BranchStrategy::AllOutputs.choose(outputs, inputs),
+            },
+            // Reached without a confident verdict
+            BranchStrategy::FollowChange(_) => BranchStrategy::AllOutputs.choose(outputs, inputs),
+        }
+    }
+
+    /// Terminal reason of the outputs the strategy does not select
+    pub(crate) fn skipped(&self) -> TerminalReason {
+        match self {
+            BranchStrategy::FollowPeelChain => TerminalReason::Peeled,
+            _ => TerminalReason::NotFollowed,
+        }
+    }
+}
+
+/// FNV-1a hash of `bytes`, stable across builds unlike std's hashers
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tracer::{
+        ChangeWeights,
+        fixtures::{op_return, script},
+    };
+    use bitcoin::ScriptBuf;
+    use bitcoin::hashes::Hash;
+
+    fn outputs(values: &[u64]) -> Vec<TxOut> {
+        values
+            .iter()
+            .map(|&sats| TxOut {
+                value: Amount::from_sat(sats),
+                script_pubkey: ScriptBuf::new(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_value_weighted_keeps_dominant_outputs() {
+        let outs = outputs(&[70_000, 20_000, 10_000]);
+
+        assert_eq!(BranchStrategy::AllOutputs.select(&outs, &[]), vec![0, 1, 2]);
+        assert_eq!(
+            BranchStrategy::ValueWeighted { min_share: 0.2 }.select(&outs, &[]),
+            vec![0, 1]
+        );
+        assert_eq!(
+            BranchStrategy::ValueWeighted { min_share: 0.0 }.select(&outs, &[]),
+            vec![0, 1, 2]
+        );
+    }
+
+    #[test]
+    fn test_value_strategies_select_by_amount() {
+        let outs = outputs(&[20_000, 70_000, 20_000, 10_000]);
+
+        assert_eq!(BranchStrategy::LargestOutput.select(&outs, &[]), vec![1]);
+        assert_eq!(
+            BranchStrategy::AboveThreshold(Amount::from_sat(20_000)).select(&outs, &[]),
+            vec![0, 1, 2]
+        );
+        // Ties go to the earlier output, the selection comes back in output order
+        assert_eq!(
+            BranchStrategy::TopKByValue(2).select(&outs, &[]),
+            vec![0, 1]
+        );
+        assert_eq!(
+            BranchStrategy::TopKByValue(9).select(&outs, &[]),
+            vec![0, 1, 2, 3]
+        );
+        assert!(BranchStrategy::LargestOutput.select(&[], &[]).is_empty());
+    }
+
+    #[test]
+    fn test_follow_change_needs_a_confident_verdict() {
+        let mut outs = outputs(&[250_000, 748_590]);
+        outs[0].script_pubkey =
+            ScriptBuf::new_p2pkh(&bitcoin::PubkeyHash::from_byte_array([1; 20]));
+        outs[1].script_pubkey = script(1);
+        let inputs = [TxOut {
+            value: Amount::from_sat(1_000_000),
+            script_pubkey: script(0),
+        }];
+
+        assert_eq!(
+            BranchStrategy::FollowChange(peel::Confidence::High).select(&outs, &inputs),
+            vec![1]
+        );
+        // Equal outputs to the same kind of script: every output is followed
+        let equal = vec![outs[1].clone(), outs[1].clone()];
+        assert_eq!(
+            BranchStrategy::FollowChange(peel::Confidence::Medium).select(&equal, &inputs),
+            vec![0, 1]
+        );
+    }
+
+    #[test]
+    fn test_op_return_outputs_are_never_selected() {
+        let mut outs = outputs(&[20_000, 90_000, 70_000]);
+        outs[1].script_pubkey = op_return();
+
+        assert_eq!(BranchStrategy::AllOutputs.select(&outs, &[]), vec![0, 2]);
+        assert_eq!(BranchStrategy::LargestOutput.select(&outs, &[]), vec![2]);
+        // The shares are of the spendable value
+        assert_eq!(
+            BranchStrategy::ValueWeighted { min_share: 0.5 }.select(&outs, &[]),
+            vec![2]
+        );
+    }
+
+    #[test]
+    fn test_invalid_config_is_rejected() {
+        assert!(TraceConfig::default().validate().is_ok());
+        assert!(matches!(
+            TraceConfig::default().max_transactions(0).validate(),
+            Err(TracerError::InvalidConfig(_))
+        ));
+        assert!(matches!(
+            TraceConfig::default()
+                .branch(BranchStrategy::ValueWeighted { min_share: 1.5 })
+                .validate(),
+            Err(TracerError::InvalidConfig(_))
+        ));
+        assert!(matches!(
+            TraceConfig::default()
+                .branch(BranchStrategy::TopKByValue(0))
+                .validate(),
+            Err(TracerError::InvalidConfig(_))
+        ));
+        assert!(matches!(
+            TraceConfig::default().max_requests(0).validate(),
+            Err(TracerError::InvalidConfig(_))
+        ));
+        assert!(matches!(
+            TraceConfig::default().dry_run(0).validate(),
+            Err(TracerError::InvalidConfig(_))
+        ));
+        assert!(matches!(
+            TraceConfig::default().concurrency(0).validate(),
+            Err(TracerError::InvalidConfig(_))
+        ));
+        assert!(matches!(
+            TraceConfig::default()
+                .checkpoint_every(0, "trace.checkpoint")
+                .validate(),
+            Err(TracerError::InvalidConfig(_))
+        ));
+        let negative = ChangeDetector::default().weights(ChangeWeights::default().fee_sanity(-1.0));
+        assert!(matches!(
+            TraceConfig::default().change_detector(negative).validate(),
+            Err(TracerError::InvalidConfig(_))
+        ));
+        let crossed = ChangeDetector::default().margins(0.5, 0.3);
+        assert!(matches!(
+            TraceConfig::default().change_detector(crossed).validate(),
+            Err(TracerError::InvalidConfig(_))
+        ));
+        assert!(matches!(
+            TraceConfig::default()
+                .min_block_height(800_001)
+                .max_block_height(800_000)
+                .validate(),
+            Err(TracerError::InvalidConfig(_))
+        ));
+        assert!(matches!(
+            TraceConfig::default()
+                .batch_policy(BatchPolicy::FollowMatchingValue {
+                    tolerance: f64::NAN
+                })
+                .validate(),
+            Err(TracerError::InvalidConfig(_))
+        ));
+    }
+
+    #[test]
+    fn test_window_bounds_height_and_time() {
+        let confirmed = |height| TxStatus {
+            confirmed: true,
+            block_height: Some(height),
+            block_hash: None,
+            block_time: None,
+        };
+        let config = TraceConfig::default()
+            .min_block_height(800_000)
+            .max_block_height(800_100);
+
+        assert!(config.windowed());
+        assert!(!TraceConfig::default().windowed());
+        assert!(config.in_window(&confirmed(800_000), None));
+        assert!(config.in_window(&confirmed(800_100), None));
+        assert!(!config.in_window(&confirmed(799_999), None));
+        assert!(!config.in_window(&confirmed(800_101), None));
+        assert!(config.in_window(&TxStatus::unconfirmed(), None));
+        assert!(
+            !config
+                .clone()
+                .include_unconfirmed(false)
+                .in_window(&TxStatus::unconfirmed(), None)
+        );
+
+        let config = TraceConfig::default().max_timestamp(1_700_000_000);
+        assert!(config.in_window(&confirmed(1), Some(1_700_000_000)));
+        assert!(!config.in_window(&confirmed(1), Some(1_700_000_001)));
+        // No time to check against
+        assert!(config.in_window(&confirmed(1), None));
+    }
+
+    #[test]
+    fn test_fingerprint_ignores_runtime_settings() {
+        let config = TraceConfig::default().stop(
+            StopCondition::new()
+                .target_script(script(1))
+                .target_script(script(2)),
+        );
+        let same = TraceConfig::default()
+            .stop(
+                StopCondition::new()
+                    .target_script(script(2))
+                    .target_script(script(1)),
+            )
+            .retry(RetryPolicy::new(5))
+            .concurrency(16)
+            .event_buffer(1)
+            .checkpoint_every(10, "trace.checkpoint");
+
+        assert_eq!(config.fingerprint(), same.fingerprint());
+        assert_ne!(
+            config.fingerprint(),
+            config.clone().max_depth(3).fingerprint()
+        );
+        assert_ne!(
+            config.fingerprint(),
+            config
+                .clone()
+                .branch(BranchStrategy::LargestOutput)
+                .fingerprint()
+        );
+        assert_ne!(
+            config.fingerprint(),
+            config.clone().max_block_height(800_000).fingerprint()
+        );
+        assert_ne!(
+            config.fingerprint(),
+            config.clone().include_unconfirmed(false).fingerprint()
+        );
+        assert_ne!(
+            config.fingerprint(),
+            config
+                .clone()
+                .pattern_classifier(PatternClassifier::default().min_batch_outputs(20))
+                .fingerprint()
+        );
+        assert_ne!(
+            config.fingerprint(),
+            config
+                .clone()
+                .lightning_policy(LightningPolicy::Continue)
+                .fingerprint()
+        );
+    }
+}
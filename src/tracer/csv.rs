@@ -0,0 +1,317 @@
+//! CSV export of a `TraceGraph`, for spreadsheets.
+//!
+//! Two tables: edges (one row per traced output) and nodes (one row per transaction).
+//! Both always start with a header row. Amounts appear twice, as integer sats and as
+//! decimal BTC with all eight places; missing values are empty cells.
+
+use crate::tracer::{DataCarrier, LightningChannel, Result, TraceEdge, TraceGraph, TraceNode};
+use bitcoin::{Amount, Denomination, hex::DisplayHex};
+use std::io::Write;
+
+/// Columns of `TraceGraph::to_csv`
+pub const EDGE_COLUMNS: [&str; 14] = [
+    "from_txid",
+    "to_txid",
+    "outpoint",
+    "value_sats",
+    "value_btc",
+    "address",
+    "block_height",
+    "timestamp",
+    "depth_from_root",
+    "terminal",
+    "change_score",
+    "change",
+    "change_confidence",
+    "label",
+];
+
+/// Columns of `TraceGraph::nodes_to_csv`
+pub const NODE_COLUMNS: [&str; 25] = [
+    "txid",
+    "depth_from_root",
+    "block_height",
+    "timestamp",
+    "input_value_sats",
+    "output_value_sats",
+    "output_value_btc",
+    "fee_sats",
+    "vsize",
+    "feerate_sat_vb",
+    "coinbase",
+    "coinbase_height",
+    "coinbase_pool",
+    "frontier",
+    "truncated",
+    "unspent",
+    "coinjoin_kind",
+    "coinjoin_score",
+    "pattern",
+    "lightning",
+    "seeds",
+    "op_return_hex",
+    "op_return_preview",
+    "labels",
+    "annotations",
+];
+
+impl TraceGraph {
+    /// Writes the edge list as CSV, in outpoint order.
+    ///
+    /// `to_txid` is empty for outputs the trace stopped at, and `terminal` says why
+    /// (`exchange: <name>` for reasons carrying a name). `block_height` and `timestamp`
+    /// are those of the spending transaction, when known; `depth_from_root` is the depth
+    /// of the transaction creating the output. The `change` columns hold the change
+    /// verdict of the output's transaction, when it was scored; `label` is the entity
+    /// the output pays, with its category in parentheses.
+    ///
+    /// # Errors
+    /// - `Export` - writing to `writer` failed
+    pub fn to_csv<W: Write>(&self, writer: W) -> Result<()> {
+        let mut csv = ::csv::Writer::from_writer(writer);
+        csv.write_record(EDGE_COLUMNS)?;
+        for edge in self.edges() {
+            csv.write_record(self.edge_row(edge))?;
+        }
+        csv.flush().map_err(::csv::Error::from)?;
+        Ok(())
+    }
+
+    /// Writes the node table as CSV, in txid order.
+    ///
+    /// `seeds` lists the indexes of the outpoints of a multi-source trace reaching the
+    /// transaction, separated by `;`. `op_return_hex` holds the payloads of its OP_RETURN
+    /// outputs, `op_return_preview` their text or protocol, also separated by `;`.
+    /// `labels` lists the outputs paying known entities as `<vout>: <entity>
+    /// (<category>)`, separated by `;`.
+    ///
+    /// # Errors
+    /// - `Export` - writing to `writer` failed
+    pub fn nodes_to_csv<W: Write>(&self, writer: W) -> Result<()> {
+        let mut csv = ::csv::Writer::from_writer(writer);
+        csv.write_record(NODE_COLUMNS)?;
+        for node in self.nodes() {
+            csv.write_record(node_row(node))?;
+        }
+        csv.flush().map_err(::csv::Error::from)?;
+        Ok(())
+    }
+
+    fn edge_row(&self, edge: &TraceEdge) -> [String; 14] {
+        let spender = edge.spent_by.and_then(|txid| self.node(&txid));
+        let terminal = edge.terminal.as_ref().map(|reason| match reason.detail() {
+            Some(detail) => format!("{}: {}", reason.code(), detail),
+            None => reason.code().to_string(),
+        });
+        [
+            edge.from().to_string(),
+            optional(edge.spent_by),
+            edge.outpoint.to_string(),
+            edge.value.to_sat().to_string(),
+            btc(edge.value),
+            optional(edge.address.as_ref()),
+            optional(spender.and_then(|node| node.height)),
+            optional(spender.and_then(|node| node.timestamp)),
+            optional(self.node(&edge.from()).map(|node| node.depth)),
+            terminal.unwrap_or_default(),
+            optional(
+                edge.change_score
+                    .map(|change| format!("{:.2}", change.score)),
+            ),
+            optional(edge.change_score.map(|change| change.change)),
+            optional(edge.change_score.map(|change| change.confidence.code())),
+            optional(self.label_of(&edge.outpoint)),
+        ]
+    }
+}
+
+fn node_row(node: &TraceNode) -> [String; 25] {
+    [
+        node.txid.to_string(),
+        node.depth.to_string(),
+        optional(node.height),
+        optional(node.timestamp),
+        optional(node.input_value.map(Amount::to_sat)),
+        node.output_value.to_sat().to_string(),
+        btc(node.output_value),
+        optional(node.fee.map(Amount::to_sat)),
+        node.vsize.to_string(),
+        optional(node.feerate().map(|feerate| format!("{:.2}", feerate))),
+        node.coinbase.to_string(),
+        optional(node.origin.as_ref().and_then(|origin| origin.height)),
+        optional(
+            node.origin
+                .as_ref()
+                .and_then(|origin| origin.pool.as_deref()),
+        ),
+        node.frontier.to_string(),
+        node.truncated.to_string(),
+        node.unspent.to_string(),
+        optional(node.coinjoin.as_ref().map(|verdict| verdict.kind.code())),
+        optional(node.coinjoin.as_ref().map(|verdict| verdict.score)),
+        node.pattern.code().to_string(),
+        optional(node.lightning.as_ref().map(LightningChannel::code)),
+        node.seeds
+            .iter()
+            .map(usize::to_string)
+            .collect::<Vec<_>>()
+            .join(";"),
+        node.data_carriers
+            .iter()
+            .map(|carrier| carrier.payload.to_lower_hex_string())
+            .collect::<Vec<_>>()
+            .join(";"),
+        node.data_carriers
+            .iter()
+            .map(DataCarrier::describe)
+            .collect::<Vec<_>>()
+            .join(";"),
+        node.labels
+            .iter()
+            .map(|(vout, label)| format!("{}: {}", vout, label))
+            .collect::<Vec<_>>()
+            .join(";"),
+        node.annotations
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect::<Vec<_>>()
+            .join(";"),
+    ]
+}
+
+/// Decimal BTC with all eight places, without the unit
+fn btc(amount: Amount) -> String {
+    format!("{:.8}", amount.display_in(Denomination::Bitcoin))
+}
+
+/// The value, or an empty cell
+fn optional<T: ToString>(value: Option<T>) -> String {
+    value.map(|value| value.to_string()).unwrap_or_default()
+}
+
+#[cfg(all(test, feature = "trace-engine"))]
+mod tests {
+    use super::*;
+    use crate::tracer::{
+        TerminalReason,
+        fixtures::{sample_graph, tagged},
+    };
+    use ::csv::{Reader, StringRecord};
+
+    fn parse(bytes: &[u8]) -> (StringRecord, Vec<StringRecord>) {
+        let mut reader = Reader::from_reader(bytes);
+        let header = reader.headers().unwrap().clone();
+        let rows = reader.records().map(|row| row.unwrap()).collect();
+        (header, rows)
+    }
+
+    fn cell<'a>(header: &StringRecord, row: &'a StringRecord, column: &str) -> &'a str {
+        let index = header.iter().position(|name| name == column).unwrap();
+        &row[index]
+    }
+
+    #[tokio::test]
+    async fn test_edges_parse_back() {
+        let mut graph = sample_graph().await;
+        let change = graph
+            .edges()
+            .find(|edge| edge.value == Amount::from_sat(39_000))
+            .unwrap()
+            .clone();
+        let address = change.address.clone().unwrap().to_string();
+        graph.insert_edge(change.terminal(TerminalReason::Exchange(
+            "Payward, Inc. \"Kraken\"".to_string(),
+        )));
+
+        let mut out = Vec::new();
+        graph.to_csv(&mut out).unwrap();
+        let (header, rows) = parse(&out);
+
+        assert_eq!(header.iter().collect::<Vec<_>>(), EDGE_COLUMNS);
+        assert_eq!(rows.len(), graph.edges().count());
+        let row = rows
+            .iter()
+            .find(|row| cell(&header, row, "value_sats") == "39000")
+            .unwrap();
+        assert_eq!(cell(&header, row, "value_btc"), "0.00039000");
+        assert_eq!(cell(&header, row, "to_txid"), "");
+        assert_eq!(cell(&header, row, "depth_from_root"), "1");
+        assert_eq!(
+            cell(&header, row, "terminal"),
+            "exchange: Payward, Inc. \"Kraken\""
+        );
+        assert_eq!(cell(&header, row, "address"), address);
+
+        // The funding output is spent by the split, which carries a block time
+        let row = rows
+            .iter()
+            .find(|row| cell(&header, row, "depth_from_root") == "0")
+            .unwrap();
+        assert_eq!(cell(&header, row, "timestamp"), "1700000000");
+        assert_eq!(cell(&header, row, "block_height"), "");
+        assert_eq!(cell(&header, row, "value_btc"), "0.00100000");
+    }
+
+    #[tokio::test]
+    async fn test_nodes_parse_back() {
+        let graph = sample_graph().await;
+
+        let mut out = Vec::new();
+        graph.nodes_to_csv(&mut out).unwrap();
+        let (header, rows) = parse(&out);
+
+        assert_eq!(header.iter().collect::<Vec<_>>(), NODE_COLUMNS);
+        assert_eq!(rows.len(), graph.len());
+        let root = rows
+            .iter()
+            .find(|row| cell(&header, row, "depth_from_root") == "0")
+            .unwrap();
+        assert_eq!(cell(&header, root, "output_value_sats"), "100000");
+        assert_eq!(cell(&header, root, "output_value_btc"), "0.00100000");
+        assert_eq!(cell(&header, root, "input_value_sats"), "");
+        assert_eq!(cell(&header, root, "fee_sats"), "");
+        assert_eq!(cell(&header, root, "pattern"), "simple_payment");
+        let split = rows
+            .iter()
+            .find(|row| cell(&header, row, "depth_from_root") == "1")
+            .unwrap();
+        // 100_000 in, 99_300 out
+        assert_eq!(cell(&header, split, "fee_sats"), "700");
+        let vsize: f64 = cell(&header, split, "vsize").parse().unwrap();
+        assert_eq!(
+            cell(&header, split, "feerate_sat_vb"),
+            format!("{:.2}", 700.0 / vsize)
+        );
+        assert_eq!(cell(&header, root, "unspent"), "false");
+    }
+
+    #[test]
+    fn test_nodes_list_their_op_return_data() {
+        let [_, tagged] = tagged();
+        let mut graph = TraceGraph::new();
+        graph.insert_node(TraceNode::new(&tagged, 1));
+
+        let mut out = Vec::new();
+        graph.nodes_to_csv(&mut out).unwrap();
+        let (header, rows) = parse(&out);
+
+        assert_eq!(
+            cell(&header, &rows[0], "op_return_hex"),
+            "636861726c6579206c6f766573206865696469"
+        );
+        assert_eq!(
+            cell(&header, &rows[0], "op_return_preview"),
+            "charley loves heidi"
+        );
+    }
+
+    #[test]
+    fn test_empty_graph_still_has_header() {
+        let mut out = Vec::new();
+        TraceGraph::new().to_csv(&mut out).unwrap();
+        let (header, rows) = parse(&out);
+
+        assert_eq!(header.len(), EDGE_COLUMNS.len());
+        assert!(rows.is_empty());
+    }
+}
@@ -0,0 +1,421 @@
+//! What changed between two traces of the same seed, e.g. a weekly re-trace: the
+//! frontier outputs spent since, and the transactions and outputs beyond them.
+//!
+//! Graphs are compared by txid and outpoint, so they may come from different
+//! configurations. A transaction in one graph only is flagged when the other graph
+//! stopped before it for a reason of its configuration
+//! (`TerminalReason::depends_on_config`), rather than because its input was unspent
+//! then.
+
+use crate::tracer::{TerminalReason, TraceEdge, TraceGraph, summary::btc};
+use bitcoin::{Amount, OutPoint, Txid};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// A transaction in one of the two graphs only.
+///
+/// # Fields
+/// * `txid` - the transaction
+/// * `depth` - its depth in the graph holding it
+/// * `config_dependent` - the other graph stopped short of it because of its
+///   configuration (a lower `max_depth`, another branch strategy, ...), or it hangs
+///   off such a transaction: the difference may not be a change on chain
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NodeChange {
+    pub txid: Txid,
+    pub depth: usize,
+    pub config_dependent: bool,
+}
+
+/// An output unspent in the old graph and spent in the new one.
+///
+/// # Fields
+/// * `outpoint` - the output
+/// * `value` - its value
+/// * `spent_by` - its spender, when the new trace followed it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrontierSpend {
+    pub outpoint: OutPoint,
+    pub value: Amount,
+    pub spent_by: Option<Txid>,
+}
+
+/// Differences between an old and a new trace.
+///
+/// # Fields
+/// * `added_nodes` - transactions only in the new graph, by depth then txid
+/// * `removed_nodes` - transactions only in the old graph, by depth then txid
+/// * `added_edges` - outputs only in the new graph, by outpoint
+/// * `removed_edges` - outputs only in the old graph, by outpoint
+/// * `newly_spent` - outputs of the old frontier spent in the new graph, by outpoint
+/// * `moved_value` - total value of `newly_spent`
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TraceDiff {
+    pub added_nodes: Vec<NodeChange>,
+    pub removed_nodes: Vec<NodeChange>,
+    pub added_edges: Vec<TraceEdge>,
+    pub removed_edges: Vec<OutPoint>,
+    pub newly_spent: Vec<FrontierSpend>,
+    pub moved_value: Amount,
+}
+
+impl TraceGraph {
+    /// What changed from `old` to `new`
+    pub fn diff(old: &TraceGraph, new: &TraceGraph) -> TraceDiff {
+        let newly_spent: Vec<FrontierSpend> = old
+            .edges()
+            .filter(|edge| edge.terminal == Some(TerminalReason::Unspent))
+            .filter_map(|edge| {
+                let now = new.edge(&edge.outpoint)?;
+                spent(now).then_some(FrontierSpend {
+                    outpoint: edge.outpoint,
+                    value: edge.value,
+                    spent_by: now.spent_by,
+                })
+            })
+            .collect();
+        let moved_value = newly_spent
+            .iter()
+            .map(|spend| spend.value)
+            .fold(Amount::ZERO, |sum, value| {
+                sum.checked_add(value).unwrap_or(Amount::MAX)
+            });
+        TraceDiff {
+            added_nodes: only_in(new, old),
+            removed_nodes: only_in(old, new),
+            added_edges: new
+                .edges()
+                .filter(|edge| old.edge(&edge.outpoint).is_none())
+                .cloned()
+                .collect(),
+            removed_edges: old
+                .edges()
+                .filter(|edge| new.edge(&edge.outpoint).is_none())
+                .map(|edge| edge.outpoint)
+                .collect(),
+            newly_spent,
+            moved_value,
+        }
+    }
+}
+
+/// Whether the trace found `edge` spent: it has a spender, or a cap stopped the trace
+/// after looking it up
+fn spent(edge: &TraceEdge) -> bool {
+    edge.spent_by.is_some()
+        || matches!(
+            edge.terminal,
+            Some(
+                TerminalReason::MaxTransactionsReached
+                    | TerminalReason::MaxBreadthReached
+                    | TerminalReason::OutOfWindow
+                    | TerminalReason::LightningChannel
+                    | TerminalReason::Visited
+            )
+        )
+}
+
+/// Transactions of `graph` missing from `other`, by depth then txid. Those `other`
+/// stopped short of for a reason of its configuration are flagged, and so are the
+/// ones spending their outputs.
+fn only_in(graph: &TraceGraph, other: &TraceGraph) -> Vec<NodeChange> {
+    let mut nodes: Vec<_> = graph
+        .nodes()
+        .filter(|node| !other.contains_node(&node.txid))
+        .collect();
+    nodes.sort_by_key(|node| (node.depth, node.txid));
+    let mut inputs: HashMap<Txid, Vec<&TraceEdge>> = HashMap::new();
+    for edge in graph.edges() {
+        if let Some(spender) = edge.spent_by {
+            inputs.entry(spender).or_default().push(edge);
+        }
+    }
+    let mut flagged = HashSet::new();
+    nodes
+        .into_iter()
+        .map(|node| {
+            let config_dependent = inputs.get(&node.txid).into_iter().flatten().any(|edge| {
+                flagged.contains(&edge.from())
+                    || other
+                        .edge(&edge.outpoint)
+                        .and_then(|edge| edge.terminal.as_ref())
+                        .is_some_and(TerminalReason::depends_on_config)
+            });
+            if config_dependent {
+                flagged.insert(node.txid);
+            }
+            NodeChange {
+                txid: node.txid,
+                depth: node.depth,
+                config_dependent,
+            }
+        })
+        .collect()
+}
+
+impl TraceDiff {
+    /// Whether the two graphs are the same as far as txids and outpoints go
+    pub fn is_empty(&self) -> bool {
+        self.added_nodes.is_empty()
+            && self.removed_nodes.is_empty()
+            && self.added_edges.is_empty()
+            && self.removed_edges.is_empty()
+            && self.newly_spent.is_empty()
+    }
+
+    /// The diff as a JSON document: its lists, amounts in satoshis and outpoints as
+    /// `txid:vout`, and the terminal reason codes of the added outputs
+    pub fn to_json(&self) -> String {
+        let document = Document {
+            added_nodes: self.added_nodes.iter().map(Node::from).collect(),
+            removed_nodes: self.removed_nodes.iter().map(Node::from).collect(),
+            added_edges: self
+                .added_edges
+                .iter()
+                .map(|edge| Edge {
+                    outpoint: edge.outpoint.to_string(),
+                    value_sat: edge.value.to_sat(),
+                    spent_by: edge.spent_by,
+                    terminal: edge.terminal.as_ref().map(TerminalReason::code),
+                })
+                .collect(),
+            removed_edges: self.removed_edges.iter().map(OutPoint::to_string).collect(),
+            newly_spent: self
+                .newly_spent
+                .iter()
+                .map(|spend| Spend {
+                    outpoint: spend.outpoint.to_string(),
+                    value_sat: spend.value.to_sat(),
+                    spent_by: spend.spent_by,
+                })
+                .collect(),
+            moved_value_sat: self.moved_value.to_sat(),
+        };
+        // Plain structs of strings and integers always serialize
+        serde_json::to_string_pretty(&document).expect("diff document serializes")
+    }
+}
+
+#[derive(Serialize)]
+struct Document {
+    added_nodes: Vec<Node>,
+    removed_nodes: Vec<Node>,
+    added_edges: Vec<Edge>,
+    removed_edges: Vec<String>,
+    newly_spent: Vec<Spend>,
+    moved_value_sat: u64,
+}
+
+#[derive(Serialize)]
+struct Node {
+    txid: Txid,
+    depth: usize,
+    config_dependent: bool,
+}
+
+impl From<&NodeChange> for Node {
+    fn from(change: &NodeChange) -> Self {
+        Self {
+            txid: change.txid,
+            depth: change.depth,
+            config_dependent: change.config_dependent,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Edge {
+    outpoint: String,
+    value_sat: u64,
+    spent_by: Option<Txid>,
+    terminal: Option<&'static str>,
+}
+
+#[derive(Serialize)]
+struct Spend {
+    outpoint: String,
+    value_sat: u64,
+    spent_by: Option<Txid>,
+}
+
+impl fmt::Display for TraceDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Transactions: +{} -{}, outputs: +{} -{}",
+            self.added_nodes.len(),
+            self.removed_nodes.len(),
+            self.added_edges.len(),
+            self.removed_edges.len()
+        )?;
+        writeln!(
+            f,
+            "Frontier outputs spent since: {}, moving {}",
+            self.newly_spent.len(),
+            btc(self.moved_value)
+        )?;
+        for spend in &self.newly_spent {
+            let spender = spend
+                .spent_by
+                .map_or("not followed".to_string(), |txid| txid.to_string());
+            writeln!(
+                f,
+                "  {}  {}  -> {}",
+                spend.outpoint,
+                btc(spend.value),
+                spender
+            )?;
+        }
+        for (title, changes) in [
+            ("Added transactions", &self.added_nodes),
+            ("Removed transactions", &self.removed_nodes),
+        ] {
+            if changes.is_empty() {
+                continue;
+            }
+            writeln!(f, "{}:", title)?;
+            for change in changes {
+                write!(f, "  {}  depth {}", change.txid, change.depth)?;
+                if change.config_dependent {
+                    write!(f, "  (configuration difference)")?;
+                }
+                writeln!(f)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "trace-engine"))]
+mod tests {
+    use super::*;
+    use crate::tracer::{
+        TraceConfig, Tracer,
+        fixtures::{Chain, MockDataSource, spend},
+    };
+
+    async fn trace(source: MockDataSource, root: OutPoint, config: &TraceConfig) -> TraceGraph {
+        Tracer::new(source)
+            .trace_forward(root, config)
+            .await
+            .unwrap()
+            .into_graph()
+    }
+
+    #[tokio::test]
+    async fn test_spent_frontier_output_brings_its_extension() {
+        let chain = Chain::new();
+        let old = trace(chain.source(), chain.root(), &TraceConfig::default()).await;
+        // Output 1 of hop 2, unspent a week ago, spent into a two-hop extension
+        let spent = OutPoint::new(chain.txid(2), 1);
+        let first = spend(20, &[spent], &[8_000]);
+        let second = spend(21, &[OutPoint::new(first.compute_txid(), 0)], &[7_000]);
+        let mut source = chain.source();
+        source.add(first.clone());
+        source.add(second.clone());
+        let new = trace(source, chain.root(), &TraceConfig::default()).await;
+
+        let diff = TraceGraph::diff(&old, &new);
+
+        assert_eq!(
+            diff.added_nodes,
+            [
+                NodeChange {
+                    txid: first.compute_txid(),
+                    depth: 3,
+                    config_dependent: false,
+                },
+                NodeChange {
+                    txid: second.compute_txid(),
+                    depth: 4,
+                    config_dependent: false,
+                },
+            ]
+        );
+        assert!(diff.removed_nodes.is_empty());
+        let added: Vec<_> = diff.added_edges.iter().map(|edge| edge.outpoint).collect();
+        let mut extension = vec![
+            OutPoint::new(first.compute_txid(), 0),
+            OutPoint::new(second.compute_txid(), 0),
+        ];
+        extension.sort();
+        assert_eq!(added, extension);
+        assert!(diff.removed_edges.is_empty());
+        assert_eq!(
+            diff.newly_spent,
+            [FrontierSpend {
+                outpoint: spent,
+                value: Amount::from_sat(9_000),
+                spent_by: Some(first.compute_txid()),
+            }]
+        );
+        assert_eq!(diff.moved_value, Amount::from_sat(9_000));
+        assert!(TraceGraph::diff(&new, &new).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_config_differences_are_flagged() {
+        let chain = Chain::new();
+        let shallow = trace(
+            chain.source(),
+            chain.root(),
+            &TraceConfig::default().max_depth(2),
+        )
+        .await;
+        let deep = trace(chain.source(), chain.root(), &TraceConfig::default()).await;
+
+        let diff = TraceGraph::diff(&shallow, &deep);
+
+        // Hop 3 is new only because the old trace stopped at depth 2
+        assert_eq!(
+            diff.added_nodes,
+            [NodeChange {
+                txid: chain.txid(3),
+                depth: 3,
+                config_dependent: true,
+            }]
+        );
+        assert!(diff.newly_spent.is_empty());
+        assert_eq!(diff.added_edges.len(), 2);
+
+        let reverse = TraceGraph::diff(&deep, &shallow);
+        assert!(reverse.added_nodes.is_empty());
+        assert_eq!(reverse.removed_nodes, diff.added_nodes);
+        assert_eq!(reverse.removed_edges.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_diff_renders_as_text_and_json() {
+        let chain = Chain::new();
+        let old = trace(chain.source(), chain.root(), &TraceConfig::default()).await;
+        let spent = OutPoint::new(chain.txid(1), 1);
+        let next = spend(22, &[spent], &[8_500]);
+        let mut source = chain.source();
+        source.add(next.clone());
+        let new = trace(source, chain.root(), &TraceConfig::default()).await;
+
+        let diff = TraceGraph::diff(&old, &new);
+
+        assert_eq!(
+            diff.to_string(),
+            format!(
+                "Transactions: +1 -0, outputs: +1 -0\n\
+                 Frontier outputs spent since: 1, moving 0.00009 BTC\n\
+                 \x20 {spent}  0.00009 BTC  -> {next}\n\
+                 Added transactions:\n\
+                 \x20 {next}  depth 2\n",
+                next = next.compute_txid()
+            )
+        );
+        let json: serde_json::Value = serde_json::from_str(&diff.to_json()).unwrap();
+        assert_eq!(json["moved_value_sat"], 9_000);
+        assert_eq!(json["newly_spent"][0]["outpoint"], spent.to_string());
+        assert_eq!(
+            json["added_nodes"][0]["txid"],
+            next.compute_txid().to_string()
+        );
+        assert_eq!(json["added_edges"][0]["terminal"], "unspent");
+        assert_eq!(json["added_nodes"][0]["config_dependent"], false);
+    }
+}
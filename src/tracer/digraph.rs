@@ -0,0 +1,228 @@
+//! Conversion of a `TraceGraph` to and from a petgraph `DiGraph`, to run petgraph's
+//! algorithms (centrality, strongly connected components, flows, ...) on a trace.
+//!
+//! Transactions are nodes and outputs are edges, weighted with the `TraceNode` and
+//! `TraceEdge` they come from. An output the trace stopped at has no spender to point
+//! to, so it is a loop on the transaction creating it: the conversion loses nothing,
+//! and `edge.weight().spent_by.is_some()` tells the outputs linking two transactions
+//! apart where loops get in the way.
+
+use crate::tracer::{TraceEdge, TraceGraph, TraceNode};
+use bitcoin::Txid;
+use petgraph::algo::dominators;
+use petgraph::graph::{DiGraph, NodeIndex};
+use std::collections::{HashMap, HashSet};
+
+impl TraceGraph {
+    /// The graph as a petgraph `DiGraph`, edges pointing from the transaction creating
+    /// an output to its spender.
+    ///
+    /// Nodes are added in txid order, so the index of a transaction is its rank in
+    /// `nodes()`, the mapping `petgraph_indexes` returns. Edges are added in outpoint
+    /// order.
+    pub fn to_petgraph(&self) -> DiGraph<TraceNode, TraceEdge> {
+        let indexes = self.petgraph_indexes();
+        let mut graph = DiGraph::with_capacity(self.len(), self.edges().count());
+        for node in self.nodes() {
+            graph.add_node(node.clone());
+        }
+        for edge in self.edges() {
+            let Some(&from) = indexes.get(&edge.from()) else {
+                continue;
+            };
+            // Leaves, and outputs spent by a transaction left out of the graph, loop
+            let to = edge
+                .spent_by
+                .and_then(|txid| indexes.get(&txid).copied())
+                .unwrap_or(from);
+            graph.add_edge(from, to, edge.clone());
+        }
+        graph
+    }
+
+    /// Index of each transaction in the graph `to_petgraph` returns
+    pub fn petgraph_indexes(&self) -> HashMap<Txid, NodeIndex> {
+        self.nodes()
+            .enumerate()
+            .map(|(index, node)| (node.txid, NodeIndex::new(index)))
+            .collect()
+    }
+
+    /// Graph of the node and edge weights of `graph`.
+    ///
+    /// The weights alone make the graph: an edge links the transactions its
+    /// `outpoint` and `spent_by` name, whatever nodes petgraph connects it to. Edges of
+    /// transactions missing from `graph` are dropped, as in any `TraceGraph`.
+    pub fn from_petgraph(graph: &DiGraph<TraceNode, TraceEdge>) -> TraceGraph {
+        let mut trace = TraceGraph::new();
+        for node in graph.node_weights() {
+            trace.insert_node(node.clone());
+        }
+        for edge in graph.edge_weights() {
+            if trace.contains_node(&edge.from()) {
+                trace.insert_edge(edge.clone());
+            }
+        }
+        trace
+    }
+
+    /// Chokepoints of the trace: transactions every path from its start to its
+    /// frontier goes through, so removing one disconnects the start from the whole
+    /// frontier.
+    ///
+    /// The start is the depth 0 transactions, and paths run away from them: along the
+    /// outputs in a forward trace, against them in a backward one (a graph whose start
+    /// has no output spent in the graph is taken as backward). The frontier is `frontier()`,
+    /// or for a trace that ran to completion, the transactions its branches end at.
+    /// The start itself is left out. Sorted by depth, then txid.
+    pub fn cut_vertices(&self) -> Vec<&TraceNode> {
+        let nodes: Vec<&TraceNode> = self.nodes().collect();
+        let mut flow = self.to_petgraph().map(|_, _| (), |_, _| ());
+        flow.retain_edges(|flow, edge| {
+            let (from, to) = flow.edge_endpoints(edge).expect("edge of the graph");
+            from != to
+        });
+        let start: Vec<NodeIndex> = flow
+            .node_indices()
+            .filter(|&index| nodes[index.index()].depth == 0)
+            .collect();
+        let forward = start
+            .iter()
+            .any(|&index| flow.neighbors(index).next().is_some());
+        if !forward {
+            flow.reverse();
+        }
+        let ends: Vec<NodeIndex> = match self.frontier().next() {
+            Some(_) => flow
+                .node_indices()
+                .filter(|&index| {
+                    let node = nodes[index.index()];
+                    node.frontier || node.truncated
+                })
+                .collect(),
+            None => flow
+                .node_indices()
+                .filter(|&index| flow.neighbors(index).next().is_none())
+                .collect(),
+        };
+        let root = flow.add_node(());
+        for &index in &start {
+            flow.add_edge(root, index, ());
+        }
+
+        let dominators = dominators::simple_fast(&flow, root);
+        let mut chokepoints: Option<HashSet<NodeIndex>> = None;
+        for end in ends {
+            let Some(strict) = dominators.strict_dominators(end) else {
+                continue;
+            };
+            let strict: HashSet<_> = strict.collect();
+            chokepoints = Some(match chokepoints {
+                Some(common) => common.intersection(&strict).copied().collect(),
+                None => strict,
+            });
+        }
+        let mut chokepoints: Vec<&TraceNode> = chokepoints
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|&index| index != root && !start.contains(&index))
+            .map(|index| nodes[index.index()])
+            .collect();
+        chokepoints.sort_by_key(|node| (node.depth, node.txid));
+        chokepoints
+    }
+}
+
+#[cfg(all(test, feature = "trace-engine"))]
+mod tests {
+    use super::*;
+    use crate::tracer::{
+        TraceConfig, Tracer,
+        fixtures::{Chain, MockDataSource, coinbase, converging, sample_graph, spend},
+    };
+    use bitcoin::OutPoint;
+
+    #[tokio::test]
+    async fn test_round_trip_keeps_everything() {
+        let graph = sample_graph().await;
+
+        let digraph = graph.to_petgraph();
+
+        assert_eq!(digraph.node_count(), graph.len());
+        assert_eq!(digraph.edge_count(), graph.edges().count());
+        for (txid, index) in graph.petgraph_indexes() {
+            assert_eq!(digraph[index].txid, txid);
+        }
+        // The sweep is the only spent output's target, the rest are leaves
+        let linking = digraph
+            .edge_indices()
+            .filter(|&edge| {
+                let (from, to) = digraph.edge_endpoints(edge).unwrap();
+                from != to
+            })
+            .count();
+        assert_eq!(linking, 2);
+        assert_eq!(TraceGraph::from_petgraph(&digraph), graph);
+    }
+
+    #[tokio::test]
+    async fn test_cut_vertices_are_chokepoints() {
+        let chain = Chain::new();
+        let graph = Tracer::new(chain.source())
+            .trace_forward(chain.root(), &TraceConfig::default())
+            .await
+            .unwrap()
+            .into_graph();
+        let txids: Vec<_> = graph.cut_vertices().iter().map(|node| node.txid).collect();
+        assert_eq!(txids, [chain.txid(1), chain.txid(2)]);
+
+        // Stopped at depth 2: the frontier is hop 2
+        let graph = Tracer::new(chain.source())
+            .trace_forward(chain.root(), &TraceConfig::default().max_depth(2))
+            .await
+            .unwrap()
+            .into_graph();
+        let txids: Vec<_> = graph.cut_vertices().iter().map(|node| node.txid).collect();
+        assert_eq!(txids, [chain.txid(1)]);
+
+        // Two seeds with paths of their own up to the merge: no chokepoint
+        let txs = converging();
+        let seeds = [
+            OutPoint::new(txs[0].compute_txid(), 0),
+            OutPoint::new(txs[1].compute_txid(), 0),
+        ];
+        let graph = Tracer::new(MockDataSource::new(&txs))
+            .trace_forward_multi(&seeds, &TraceConfig::default())
+            .await
+            .unwrap()
+            .into_graph();
+        assert!(graph.cut_vertices().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_backward_cut_vertices_run_against_the_outputs() {
+        // cb1 + cb2 -> a -> b -> c
+        let cb1 = coinbase(1, &[50_000]);
+        let cb2 = coinbase(2, &[25_000]);
+        let a = spend(
+            3,
+            &[
+                OutPoint::new(cb1.compute_txid(), 0),
+                OutPoint::new(cb2.compute_txid(), 0),
+            ],
+            &[74_000],
+        );
+        let b = spend(4, &[OutPoint::new(a.compute_txid(), 0)], &[73_000]);
+        let c = spend(5, &[OutPoint::new(b.compute_txid(), 0)], &[72_000]);
+        let txs = [cb1, cb2, a.clone(), b.clone(), c.clone()];
+        let graph = Tracer::new(MockDataSource::new(&txs))
+            .trace_backward(c.compute_txid(), &TraceConfig::default())
+            .await
+            .unwrap()
+            .into_graph();
+
+        let txids: Vec<_> = graph.cut_vertices().iter().map(|node| node.txid).collect();
+
+        assert_eq!(txids, [b.compute_txid(), a.compute_txid()]);
+    }
+}
@@ -0,0 +1,683 @@
+//! Graphviz DOT export of a `TraceGraph`.
+//!
+//! Transactions are boxes, spent outputs are edges between them. Outputs the trace
+//! stopped at are drawn as their own small nodes: a double ellipse for unspent outputs,
+//! a dashed ellipse labelled with the reason for the others. OP_RETURN outputs also
+//! show the data they carry.
+//!
+//! In a multi-source trace, transactions are outlined in the color of the seed they
+//! are reachable from; those reachable from several seeds are drawn bold, in the
+//! highlight color.
+//!
+//! With `link_reuse`, every reused address is also a dotted ellipse, linked with dotted
+//! lines to the transactions paying it.
+//!
+//! Speculative edges, such as those proposed by value matching, are dashed lines from
+//! the output they continue from to a dashed ellipse of the candidate output.
+
+use crate::tracer::{
+    AddressReuse, Clustering, DataCarrier, LightningChannel, TerminalReason, TraceEdge, TraceGraph,
+    TraceNode, TxPattern,
+};
+use bitcoin::{Address, Amount, Denomination, Txid};
+use std::collections::HashMap;
+use std::fmt::Write;
+
+/// Outputs below this value are dust, the standard limit for P2PKH outputs
+pub const DEFAULT_DUST_LIMIT: Amount = Amount::from_sat(546);
+
+/// Characters of OP_RETURN data shown on a leaf
+const PREVIEW_CHARS: usize = 32;
+
+/// Graphviz colors given to address clusters and the seeds of a multi-source trace,
+/// cycled through by cluster id or seed index
+pub const CLUSTER_COLORS: [&str; 8] = [
+    "blue",
+    "darkgreen",
+    "purple",
+    "brown",
+    "teal",
+    "crimson",
+    "darkgoldenrod",
+    "deeppink",
+];
+
+/// How much detail goes into node and edge labels
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LabelVerbosity {
+    /// Shortened txids on nodes, amounts on edges
+    Compact,
+    /// Also output values and block times on nodes, output indexes on edges
+    Detailed,
+}
+
+/// Rendering options of `TraceGraph::to_dot`.
+///
+/// # Fields
+/// * `verbosity` - how much detail goes into labels
+/// * `include_dust` - whether outputs below `dust_limit` are drawn
+/// * `dust_limit` - value under which an output is dust
+/// * `labels` - names of known addresses, shown on and highlighting their outputs
+/// * `highlight_color` - Graphviz color of outputs to a labelled address, and of
+///   transactions reachable from several seeds
+/// * `clustering` - address clusters, coloring the outputs to each cluster of more than
+///   one address alike
+/// * `link_reuse` - whether addresses paid more than once are drawn, linked to the
+///   transactions paying them
+#[derive(Debug, Clone)]
+pub struct DotOptions {
+    pub verbosity: LabelVerbosity,
+    pub include_dust: bool,
+    pub dust_limit: Amount,
+    pub labels: HashMap<Address, String>,
+    pub highlight_color: String,
+    pub clustering: Option<Clustering>,
+    pub link_reuse: bool,
+}
+
+impl Default for DotOptions {
+    fn default() -> Self {
+        Self {
+            verbosity: LabelVerbosity::Detailed,
+            include_dust: true,
+            dust_limit: DEFAULT_DUST_LIMIT,
+            labels: HashMap::new(),
+            highlight_color: "orange".to_string(),
+            clustering: None,
+            link_reuse: false,
+        }
+    }
+}
+
+impl DotOptions {
+    /// How much detail goes into labels
+    pub fn verbosity(mut self, verbosity: LabelVerbosity) -> Self {
+        self.verbosity = verbosity;
+        self
+    }
+
+    /// Whether outputs below the dust limit are drawn
+    pub fn include_dust(mut self, include_dust: bool) -> Self {
+        self.include_dust = include_dust;
+        self
+    }
+
+    /// Value under which an output is dust
+    pub fn dust_limit(mut self, dust_limit: Amount) -> Self {
+        self.dust_limit = dust_limit;
+        self
+    }
+
+    /// Names `address`, highlighting the outputs paying it
+    pub fn label(mut self, address: Address, name: impl Into<String>) -> Self {
+        self.labels.insert(address, name.into());
+        self
+    }
+
+    /// Graphviz color of outputs to a labelled address
+    pub fn highlight_color(mut self, color: impl Into<String>) -> Self {
+        self.highlight_color = color.into();
+        self
+    }
+
+    /// Colors outputs by the cluster of the address they pay
+    pub fn clustering(mut self, clustering: Clustering) -> Self {
+        self.clustering = Some(clustering);
+        self
+    }
+
+    /// Draws the addresses paid more than once, linked to the transactions paying them
+    pub fn link_reuse(mut self, link_reuse: bool) -> Self {
+        self.link_reuse = link_reuse;
+        self
+    }
+
+    /// Color of the cluster `edge` pays, if it shares it with other addresses
+    fn cluster_color(&self, edge: &TraceEdge) -> Option<&'static str> {
+        let clustering = self.clustering.as_ref()?;
+        let cluster = clustering.cluster_of(edge.address.as_ref()?)?;
+        let shared = clustering
+            .stats(cluster)
+            .is_some_and(|stats| stats.addresses > 1);
+        shared.then(|| CLUSTER_COLORS[cluster % CLUSTER_COLORS.len()])
+    }
+
+    fn name_of(&self, edge: &TraceEdge) -> Option<&str> {
+        edge.address
+            .as_ref()
+            .and_then(|address| self.labels.get(address))
+            .map(String::as_str)
+    }
+}
+
+impl TraceGraph {
+    /// Renders the graph as a Graphviz digraph, for `dot -Tsvg` and friends.
+    ///
+    /// Nodes and edges come out in txid and outpoint order, so the same graph always
+    /// renders to the same text. Outputs paying an entity labelled during the trace
+    /// are named and highlighted like those of `DotOptions::labels`, which take
+    /// precedence.
+    pub fn to_dot(&self, options: &DotOptions) -> String {
+        let mut dot = String::new();
+        dot.push_str("digraph trace {\n");
+        dot.push_str("  rankdir=LR;\n");
+        dot.push_str("  node [shape=box, fontname=\"monospace\"];\n");
+        dot.push_str("  edge [fontname=\"monospace\"];\n");
+
+        for node in self.nodes() {
+            let mut attributes = vec![format!("label={}", quote(&node_label(node, options)))];
+            if node.coinbase {
+                attributes.push("peripheries=2".to_string());
+            }
+            match node.seeds.len() {
+                0 => {}
+                1 => {
+                    let seed = node.seeds.first().copied().unwrap_or_default();
+                    let color = CLUSTER_COLORS[seed % CLUSTER_COLORS.len()];
+                    attributes.push(format!("color={}", quote(color)));
+                }
+                _ => {
+                    attributes.push(format!("color={}", quote(&options.highlight_color)));
+                    attributes.push("penwidth=3".to_string());
+                }
+            }
+            let mut styles = Vec::new();
+            if node.frontier || node.truncated {
+                styles.push("dashed");
+            }
+            if node.coinjoin.is_some() {
+                styles.push("filled");
+                attributes.push("fillcolor=\"lightgrey\"".to_string());
+            }
+            if !styles.is_empty() {
+                attributes.push(format!("style={}", quote(&styles.join(","))));
+            }
+            line(&mut dot, &quote(&node.txid.to_string()), &attributes);
+        }
+
+        for edge in self.edges() {
+            if !options.include_dust && edge.value < options.dust_limit {
+                continue;
+            }
+            let name = options.name_of(edge).or_else(|| {
+                self.label_of(&edge.outpoint)
+                    .map(|label| label.entity.as_str())
+            });
+            let mut attributes = vec![format!("label={}", quote(&edge_label(edge, name, options)))];
+            let color = match name {
+                Some(_) => Some(options.highlight_color.as_str()),
+                None => options.cluster_color(edge),
+            };
+            if let Some(color) = color {
+                let color = quote(color);
+                attributes.push(format!("color={}", color));
+                attributes.push(format!("fontcolor={}", color));
+            }
+            let target = match edge.spent_by {
+                Some(txid) => quote(&txid.to_string()),
+                None => {
+                    let leaf = quote(&edge.outpoint.to_string());
+                    line(&mut dot, &leaf, &leaf_attributes(edge, name, options));
+                    leaf
+                }
+            };
+            let from = quote(&edge.from().to_string());
+            line(&mut dot, &format!("{} -> {}", from, target), &attributes);
+        }
+
+        for speculative in self.speculative_edges() {
+            let candidate = quote(&format!("candidate:{}", speculative.outpoint));
+            let label = format!(
+                "{}:{}\n{}",
+                short_txid(&speculative.outpoint.txid),
+                speculative.outpoint.vout,
+                btc(speculative.value)
+            );
+            let attributes = [
+                "shape=ellipse".to_string(),
+                "style=\"dashed\"".to_string(),
+                format!("label={}", quote(&label)),
+            ];
+            line(&mut dot, &candidate, &attributes);
+            let from = quote(&speculative.deposit.to_string());
+            let attributes = [
+                format!(
+                    "label={}",
+                    quote(&format!("{:.0}% match", speculative.confidence * 100.0))
+                ),
+                "style=\"dashed\"".to_string(),
+            ];
+            line(&mut dot, &format!("{} -> {}", from, candidate), &attributes);
+        }
+
+        if options.link_reuse {
+            for reuse in self.address_reuse() {
+                let address = quote(&reuse_id(&reuse));
+                let attributes = [
+                    "shape=ellipse".to_string(),
+                    "style=\"dotted\"".to_string(),
+                    format!("label={}", quote(&reuse_label(&reuse))),
+                ];
+                line(&mut dot, &address, &attributes);
+                for occurrence in &reuse.occurrences {
+                    let from = quote(&occurrence.outpoint.txid.to_string());
+                    let attributes = [
+                        format!("label={}", quote(&format!("#{}", occurrence.outpoint.vout))),
+                        "style=\"dotted\"".to_string(),
+                        "arrowhead=none".to_string(),
+                        "constraint=false".to_string(),
+                    ];
+                    line(&mut dot, &format!("{} -> {}", from, address), &attributes);
+                }
+            }
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+/// Writes one indented statement with its attribute list
+fn line(dot: &mut String, statement: &str, attributes: &[String]) {
+    let _ = writeln!(dot, "  {} [{}];", statement, attributes.join(", "));
+}
+
+fn node_label(node: &TraceNode, options: &DotOptions) -> String {
+    let mut lines = vec![short_txid(&node.txid)];
+    if options.verbosity == LabelVerbosity::Detailed {
+        lines.push(btc(node.output_value));
+        if let (Some(fee), Some(feerate)) = (node.fee, node.feerate()) {
+            lines.push(format!("fee {} sat ({:.1} sat/vB)", fee.to_sat(), feerate));
+        }
+        if let Some(timestamp) = node.timestamp {
+            lines.push(utc(timestamp));
+        }
+        if node.coinbase {
+            match node
+                .origin
+                .as_ref()
+                .and_then(|origin| origin.pool.as_deref())
+            {
+                Some(pool) => lines.push(format!("coinbase, mined by {}", pool)),
+                None => lines.push("coinbase".to_string()),
+            }
+        }
+        match node.pattern {
+            TxPattern::Sweep { inputs } => lines.push(format!("sweep of {} inputs", inputs)),
+            TxPattern::BatchPayout { outputs } => {
+                lines.push(format!("batch payout to {} outputs", outputs))
+            }
+            TxPattern::SelfTransfer => lines.push("self-transfer".to_string()),
+            TxPattern::SimplePayment | TxPattern::Unknown => {}
+        }
+        if !node.seeds.is_empty() {
+            let seeds: Vec<_> = node.seeds.iter().map(usize::to_string).collect();
+            lines.push(format!("seeds {}", seeds.join(", ")));
+        }
+    }
+    if let Some(verdict) = &node.coinjoin {
+        lines.push(format!(
+            "coinjoin: {} ({:.2})",
+            verdict.kind.code(),
+            verdict.score
+        ));
+    }
+    match &node.lightning {
+        Some(LightningChannel::Open { vout, .. }) => {
+            lines.push(format!("lightning open (output {})", vout))
+        }
+        Some(LightningChannel::Close { forced: true, .. }) => {
+            lines.push("lightning close (forced)".to_string())
+        }
+        Some(LightningChannel::Close { forced: false, .. }) => {
+            lines.push("lightning close".to_string())
+        }
+        None => {}
+    }
+    lines.join("\n")
+}
+
+fn edge_label(edge: &TraceEdge, name: Option<&str>, options: &DotOptions) -> String {
+    let mut lines = vec![btc(edge.value)];
+    if options.verbosity == LabelVerbosity::Detailed {
+        lines[0] = format!("{} #{}", lines[0], edge.outpoint.vout);
+    }
+    if let Some(name) = name {
+        lines.push(name.to_string());
+    }
+    lines.join("\n")
+}
+
+fn leaf_attributes(edge: &TraceEdge, name: Option<&str>, options: &DotOptions) -> Vec<String> {
+    let (label, mut styles) = match &edge.terminal {
+        Some(TerminalReason::Unspent) | None => ("unspent", vec![]),
+        Some(reason) => (terminal_label(reason), vec!["dashed"]),
+    };
+    let mut lines = vec![label.to_string()];
+    if let Some(carrier) = DataCarrier::from_script(edge.outpoint.vout, &edge.script_pubkey) {
+        lines.push(preview(&carrier.describe()));
+    }
+    lines.extend(name.map(str::to_string));
+    let label = lines.join("\n");
+    let mut attributes = vec!["shape=ellipse".to_string()];
+    if styles.is_empty() {
+        attributes.push("peripheries=2".to_string());
+    }
+    attributes.push(format!("label={}", quote(&label)));
+    if name.is_some() {
+        styles.push("filled");
+        attributes.push(format!("fillcolor={}", quote(&options.highlight_color)));
+    }
+    if !styles.is_empty() {
+        attributes.push(format!("style={}", quote(&styles.join(","))));
+    }
+    attributes
+}
+
+/// Node id of a reused address
+pub(crate) fn reuse_id(reuse: &AddressReuse) -> String {
+    format!("script:{}", reuse.script_pubkey.to_hex_string())
+}
+
+fn reuse_label(reuse: &AddressReuse) -> String {
+    let mut lines = vec![match &reuse.address {
+        Some(address) => address.to_string(),
+        None => preview(&reuse.script_pubkey.to_hex_string()),
+    }];
+    lines.push(format!("paid {} times", reuse.occurrences.len()));
+    if reuse.round_trip {
+        lines.push("round trip".to_string());
+    }
+    lines.join("\n")
+}
+
+/// `text` cut to `PREVIEW_CHARS` characters
+fn preview(text: &str) -> String {
+    match text.char_indices().nth(PREVIEW_CHARS) {
+        Some((end, _)) => format!("{}...", &text[..end]),
+        None => text.to_string(),
+    }
+}
+
+fn terminal_label(reason: &TerminalReason) -> &'static str {
+    match reason {
+        TerminalReason::Unspent => "unspent",
+        TerminalReason::MaxDepthReached => "max depth",
+        TerminalReason::MaxTransactionsReached => "max transactions",
+        TerminalReason::MaxBreadthReached => "max breadth",
+        TerminalReason::NotFollowed => "not followed",
+        TerminalReason::Peeled => "peeled",
+        TerminalReason::CoinJoin => "coinjoin",
+        TerminalReason::LightningChannel => "lightning channel",
+        TerminalReason::DataCarrier => "op_return",
+        TerminalReason::ReachedTarget(_) => "target",
+        TerminalReason::Cancelled => "cancelled",
+        TerminalReason::BudgetExhausted => "budget exhausted",
+        TerminalReason::BelowMinValue => "below min value",
+        TerminalReason::OutOfWindow => "out of window",
+        TerminalReason::Visited => "visited before",
+        TerminalReason::Exchange(_) => "exchange",
+        TerminalReason::Mixer(_) => "mixer",
+        TerminalReason::Sanctioned(_) => "sanctioned",
+        TerminalReason::DataUnavailable => "data unavailable",
+        TerminalReason::Error { .. } => "error",
+        TerminalReason::Other(_) => "other",
+    }
+}
+
+/// First and last characters of a txid, enough to tell transactions apart on a chart
+pub(crate) fn short_txid(txid: &Txid) -> String {
+    let hex = txid.to_string();
+    format!("{}..{}", &hex[..8], &hex[hex.len() - 4..])
+}
+
+fn btc(amount: Amount) -> String {
+    amount
+        .display_in(Denomination::Bitcoin)
+        .show_denomination()
+        .to_string()
+}
+
+/// `YYYY-MM-DD HH:MM UTC` of a unix timestamp
+pub(crate) fn utc(timestamp: u64) -> String {
+    let (year, month, day) = civil(timestamp);
+    let seconds = timestamp % 86_400;
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        seconds / 3_600,
+        seconds % 3_600 / 60
+    )
+}
+
+/// RFC 3339 date and time of a unix timestamp, `YYYY-MM-DDTHH:MM:SSZ`
+pub(crate) fn rfc3339(timestamp: u64) -> String {
+    let (year, month, day) = civil(timestamp);
+    let seconds = timestamp % 86_400;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        seconds / 3_600,
+        seconds % 3_600 / 60,
+        seconds % 60
+    )
+}
+
+/// Year, month and day of a unix timestamp
+fn civil(timestamp: u64) -> (i64, i64, i64) {
+    let days = (timestamp / 86_400) as i64;
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Quotes `text` as a DOT string.
+///
+/// Backslashes and quotes are escaped, line breaks become DOT's `\n` line separator,
+/// so user-supplied names can never end the string or the statement early.
+fn quote(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '\\' => quoted.push_str("\\\\"),
+            '"' => quoted.push_str("\\\""),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => {}
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(all(test, feature = "trace-engine"))]
+mod tests {
+    use super::*;
+    use crate::tracer::{
+        ClusterOptions, TraceConfig, Tracer, cluster_addresses,
+        fixtures::{MockDataSource, converging, reused, sample_graph, script, tagged},
+    };
+    use bitcoin::Network;
+
+    const GOLDEN: &str = "src/tracer/testdata/trace.dot";
+
+    fn labelled() -> DotOptions {
+        let change = Address::from_script(&script(1), Network::Bitcoin).unwrap();
+        DotOptions::default().label(change, "Exchange \"hot\" wallet\\1")
+    }
+
+    #[tokio::test]
+    async fn test_matches_golden_file() {
+        let dot = sample_graph().await.to_dot(&labelled());
+
+        // UPDATE_GOLDEN=1 cargo test rewrites the file after an intended change
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            std::fs::write(GOLDEN, &dot).unwrap();
+        }
+        assert_eq!(dot, include_str!("testdata/trace.dot"));
+    }
+
+    #[tokio::test]
+    async fn test_seeds_color_their_transactions() {
+        let txs = converging();
+        let seeds = [
+            bitcoin::OutPoint::new(txs[0].compute_txid(), 0),
+            bitcoin::OutPoint::new(txs[1].compute_txid(), 0),
+        ];
+        let graph = Tracer::new(MockDataSource::new(&txs))
+            .trace_forward_multi(&seeds, &TraceConfig::default())
+            .await
+            .unwrap()
+            .into_graph();
+
+        let dot = graph.to_dot(&DotOptions::default());
+        let node_line = |index: usize| {
+            let txid = txs[index].compute_txid().to_string();
+            dot.lines()
+                .find(|line| line.starts_with(&format!("  \"{}\" [label", txid)))
+                .unwrap()
+                .to_string()
+        };
+        assert!(node_line(2).contains("color=\"blue\""));
+        assert!(node_line(3).contains("color=\"darkgreen\""));
+        let merge = node_line(4);
+        assert!(merge.contains("color=\"orange\", penwidth=3"));
+        assert!(merge.contains("seeds 0, 1"));
+    }
+
+    #[tokio::test]
+    async fn test_op_return_leaves_show_their_data() {
+        let [funding, tagged] = tagged();
+        let graph = Tracer::new(MockDataSource::new(&[funding.clone(), tagged.clone()]))
+            .trace_forward(
+                bitcoin::OutPoint::new(funding.compute_txid(), 0),
+                &TraceConfig::default(),
+            )
+            .await
+            .unwrap()
+            .into_graph();
+
+        let dot = graph.to_dot(&DotOptions::default());
+
+        assert!(dot.contains("label=\"op_return\\ncharley loves heidi\""));
+        assert_eq!(
+            preview("0123456789abcdefghijklmnopqrstuvwxyz"),
+            "0123456789abcdefghijklmnopqrstuv..."
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reused_addresses_are_linked() {
+        let txs = reused();
+        let graph = Tracer::new(MockDataSource::new(&txs))
+            .trace_forward(
+                bitcoin::OutPoint::new(txs[0].compute_txid(), 0),
+                &TraceConfig::default(),
+            )
+            .await
+            .unwrap()
+            .into_graph();
+        let address = Address::from_script(&script(40), Network::Bitcoin).unwrap();
+        let id = format!("\"script:{}\"", script(40).to_hex_string());
+
+        let dot = graph.to_dot(&DotOptions::default().link_reuse(true));
+
+        let node = dot
+            .lines()
+            .find(|line| line.starts_with(&format!("  {} [", id)))
+            .unwrap();
+        assert!(node.contains(&format!("{}\\npaid 2 times\\nround trip", address)));
+        for hop in [1, 4] {
+            let link = format!("  \"{}\" -> {} [label=\"#1\"", txs[hop].compute_txid(), id);
+            assert!(dot.contains(&link), "{link}");
+        }
+        assert!(!graph.to_dot(&DotOptions::default()).contains(&id));
+    }
+
+    #[tokio::test]
+    async fn test_dust_and_verbosity_options() {
+        let graph = sample_graph().await;
+        let dust_leaf = graph
+            .edges()
+            .find(|edge| edge.value == Amount::from_sat(300))
+            .unwrap()
+            .outpoint
+            .to_string();
+
+        let detailed = graph.to_dot(&DotOptions::default());
+        assert!(detailed.contains(&dust_leaf));
+        assert!(detailed.contains("0.0006 BTC #0"));
+        assert!(detailed.contains("2023-11-14 22:13 UTC"));
+
+        let compact = graph.to_dot(
+            &DotOptions::default()
+                .include_dust(false)
+                .verbosity(LabelVerbosity::Compact),
+        );
+        assert!(!compact.contains(&dust_leaf));
+        assert!(!compact.contains(" #0"));
+        assert!(!compact.contains("UTC"));
+    }
+
+    #[tokio::test]
+    async fn test_clusters_color_their_outputs() {
+        let mut graph = sample_graph().await;
+        // Let the sweep spend the change with the first output of the split, joining
+        // the change address with the address everything else pays
+        let sweep = graph
+            .edges()
+            .find(|edge| edge.value == Amount::from_sat(60_000))
+            .unwrap()
+            .spent_by;
+        let change = graph
+            .edges()
+            .find(|edge| edge.value == Amount::from_sat(39_000))
+            .unwrap()
+            .clone();
+        graph.insert_edge(TraceEdge {
+            spent_by: sweep,
+            terminal: None,
+            ..change.clone()
+        });
+        let clustering = cluster_addresses(&graph, ClusterOptions::default());
+        let cluster = clustering
+            .cluster_of(change.address.as_ref().unwrap())
+            .unwrap();
+        let color = format!("color=\"{}\"", CLUSTER_COLORS[cluster]);
+
+        let dot = graph.to_dot(&DotOptions::default().clustering(clustering.clone()));
+        // color and fontcolor of every edge but the dust one
+        assert_eq!(dot.matches(&color).count(), 2 * graph.edges().count() - 2);
+        // A labelled address keeps the highlight color
+        let dot = graph.to_dot(&labelled().clustering(clustering));
+        assert_eq!(dot.matches(&color).count(), 2 * graph.edges().count() - 4);
+    }
+
+    #[test]
+    fn test_quote_escapes_specials() {
+        assert_eq!(quote("plain"), "\"plain\"");
+        assert_eq!(quote("a \"b\" \\c"), "\"a \\\"b\\\" \\\\c\"");
+        assert_eq!(quote("two\r\nlines"), "\"two\\nlines\"");
+        assert_eq!(quote("}; x -> y [color=red"), "\"}; x -> y [color=red\"");
+    }
+
+    #[test]
+    fn test_utc_dates() {
+        assert_eq!(utc(0), "1970-01-01 00:00 UTC");
+        assert_eq!(utc(951_782_400), "2000-02-29 00:00 UTC");
+        assert_eq!(utc(1_231_006_505), "2009-01-03 18:15 UTC");
+    }
+}
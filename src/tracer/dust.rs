@@ -0,0 +1,190 @@
+//! Dusting: transactions sending tiny amounts to many addresses at once.
+//!
+//! A dusting attack pays a few hundred sats to each of a long list of addresses, in
+//! the hope that their owners later spend the dust together with their other coins and
+//! link their addresses. An address of the trace among the recipients of such a
+//! transaction means someone is watching it.
+
+use crate::tracer::TraceGraph;
+use bitcoin::{Amount, OutPoint, Txid};
+use serde::Serialize;
+use std::collections::HashSet;
+
+/// Default value at or under which an output counts as dust
+pub const DEFAULT_DUSTING_LIMIT: Amount = Amount::from_sat(1_000);
+
+/// Default number of dust outputs, to as many addresses, flagging a transaction
+pub const DEFAULT_DUSTING_OUTPUTS: usize = 10;
+
+/// What makes a transaction a dusting one.
+///
+/// # Fields
+/// * `dust_limit` - value at or under which an output counts as dust
+/// * `min_outputs` - dust outputs a dusting transaction creates at least, each to an
+///   address of its own
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DustingDetector {
+    pub dust_limit: Amount,
+    pub min_outputs: usize,
+}
+
+impl Default for DustingDetector {
+    fn default() -> Self {
+        Self {
+            dust_limit: DEFAULT_DUSTING_LIMIT,
+            min_outputs: DEFAULT_DUSTING_OUTPUTS,
+        }
+    }
+}
+
+impl DustingDetector {
+    /// Value at or under which an output counts as dust
+    pub fn dust_limit(mut self, dust_limit: Amount) -> Self {
+        self.dust_limit = dust_limit;
+        self
+    }
+
+    /// Dust outputs, to distinct addresses, a dusting transaction creates at least
+    pub fn min_outputs(mut self, min_outputs: usize) -> Self {
+        self.min_outputs = min_outputs;
+        self
+    }
+}
+
+/// A transaction of a trace flagged as dusting.
+///
+/// # Fields
+/// * `txid` - the transaction
+/// * `dust_outputs` - outputs it creates at or under the dust limit
+/// * `recipients` - distinct scripts those outputs pay
+/// * `dust_value` - total value of those outputs
+/// * `traced` - dust outputs paying a script some other output of the trace also pays,
+///   in outpoint order: addresses of the trace singled out by the duster
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Dusting {
+    pub txid: Txid,
+    pub dust_outputs: usize,
+    pub recipients: usize,
+    pub dust_value: Amount,
+    pub traced: Vec<OutPoint>,
+}
+
+impl TraceGraph {
+    /// Transactions of the graph creating at least `min_outputs` dust outputs to as
+    /// many distinct addresses, in txid order.
+    ///
+    /// Only the outputs in the graph are looked at: all of them for the transactions of
+    /// a forward trace, only those spent along the trace in a backward one. OP_RETURN
+    /// outputs are not dust.
+    pub fn detect_dusting(&self, detector: &DustingDetector) -> Vec<Dusting> {
+        self.nodes()
+            .filter_map(|node| {
+                let dust: Vec<_> = self
+                    .outputs_of(&node.txid)
+                    .filter(|edge| {
+                        edge.value <= detector.dust_limit && !edge.script_pubkey.is_op_return()
+                    })
+                    .collect();
+                let recipients: HashSet<_> = dust.iter().map(|edge| &edge.script_pubkey).collect();
+                if dust.is_empty() || recipients.len() < detector.min_outputs {
+                    return None;
+                }
+                let traced = dust
+                    .iter()
+                    .filter(|edge| {
+                        self.outputs_paying(&edge.script_pubkey)
+                            .any(|other| other.from() != node.txid)
+                    })
+                    .map(|edge| edge.outpoint)
+                    .collect();
+                Some(Dusting {
+                    txid: node.txid,
+                    dust_outputs: dust.len(),
+                    recipients: recipients.len(),
+                    dust_value: dust
+                        .iter()
+                        .map(|edge| edge.value)
+                        .fold(Amount::ZERO, |sum, value| {
+                            sum.checked_add(value).unwrap_or(Amount::MAX)
+                        }),
+                    traced,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tracer::{
+        TraceEdge, TraceNode,
+        fixtures::{script, spend},
+    };
+    use bitcoin::{Network, Transaction, hashes::Hash};
+
+    /// Graph of `txs` at depth 0, with every one of their outputs
+    fn graph_of(txs: &[&Transaction]) -> TraceGraph {
+        let mut graph = TraceGraph::new();
+        for tx in txs {
+            graph.insert_node(TraceNode::new(tx, 0));
+            let txid = tx.compute_txid();
+            for (output, vout) in tx.output.iter().zip(0..) {
+                graph.insert_edge(TraceEdge::new(
+                    OutPoint::new(txid, vout),
+                    output,
+                    Network::Bitcoin,
+                ));
+            }
+        }
+        graph
+    }
+
+    /// Transaction paying `values` sats to `script(n)` for each `(value, n)`
+    fn paying(tag: u32, outputs: &[(u64, u8)]) -> Transaction {
+        let values: Vec<u64> = outputs.iter().map(|(value, _)| *value).collect();
+        let mut tx = spend(
+            tag,
+            &[OutPoint::new(Txid::from_byte_array([1; 32]), tag)],
+            &values,
+        );
+        for (output, (_, n)) in tx.output.iter_mut().zip(outputs) {
+            output.script_pubkey = script(*n);
+        }
+        tx
+    }
+
+    #[test]
+    fn test_flags_dust_to_many_addresses() {
+        // The traced address, script(50), is among the 12 recipients of the dust
+        let payment = paying(60, &[(50_000, 50)]);
+        let mut outputs: Vec<(u64, u8)> = (100..111).map(|n| (600, n)).collect();
+        outputs.push((546, 50));
+        outputs.push((100_000, 99));
+        let dusting = paying(61, &outputs);
+        // Many outputs, none of them dust; and dust to too few addresses
+        let payouts = paying(62, &(120..132).map(|n| (50_000, n)).collect::<Vec<_>>());
+        let spam = paying(63, &[(600, 140); 12]);
+        let graph = graph_of(&[&payment, &dusting, &payouts, &spam]);
+
+        let flagged = graph.detect_dusting(&DustingDetector::default());
+
+        assert_eq!(
+            flagged,
+            [Dusting {
+                txid: dusting.compute_txid(),
+                dust_outputs: 12,
+                recipients: 12,
+                dust_value: Amount::from_sat(11 * 600 + 546),
+                traced: vec![OutPoint::new(dusting.compute_txid(), 11)],
+            }]
+        );
+        assert!(
+            graph
+                .detect_dusting(&DustingDetector::default().min_outputs(13))
+                .is_empty()
+        );
+        let strict = DustingDetector::default().dust_limit(Amount::from_sat(599));
+        assert!(graph.detect_dusting(&strict).is_empty());
+    }
+}
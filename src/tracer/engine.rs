@@ -1 +1,3715 @@
+//! Trace engine: walks the transaction graph through a `BlockchainDataSource`.
 
+use crate::blockchain::{
+    self, BlockchainDataSource, BlockchainError, CacheKey, ErrorContext, ResultExt, TxStatus,
+};
+use crate::time::{self, Clock};
+use crate::tracer::{
+    CancelToken, ChangeContext, CoinJoinPolicy, CoinbaseOrigin, LightningChannel, LightningPolicy,
+    ReplacementStatus, Result, TerminalReason, TraceCheckpoint, TraceConfig, TraceContext,
+    TraceEdge, TraceGraph, TraceItem, TraceNode, TraceReport, TracerError,
+    checkpoint::Frontier,
+    events::{self, EventSender, PROGRESS_INTERVAL, TraceEvent, TraceEvents},
+    peel::tx_out,
+    report,
+    stream::{self, ItemSink},
+    visited::{self, Direction, VisitedKey, VisitedSet},
+};
+use bitcoin::{Amount, BlockHash, OutPoint, Script, ScriptBuf, Transaction, TxOut, Txid};
+use futures::stream::{FuturesUnordered, Stream, StreamExt};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque, hash_map::Entry};
+use std::future::Future;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tracing::{Instrument, Span, instrument::Instrumented};
+
+/// How a trace ended
+#[derive(Debug, Clone, PartialEq)]
+pub enum TraceOutcome {
+    /// The trace ran to its limits, `max_requests` included: outputs still queued when
+    /// the request budget runs out are `BudgetExhausted` leaves, and the transactions
+    /// they (forward) or their inputs (backward) come from are on the frontier.
+    Complete(TraceGraph),
+    /// The trace's `CancelToken` was cancelled. The graph holds the work done so far:
+    /// outputs still queued are `Cancelled` leaves, and the transactions they (forward)
+    /// or their inputs (backward) come from are on the frontier.
+    Cancelled(TraceGraph),
+}
+
+impl TraceOutcome {
+    pub fn graph(&self) -> &TraceGraph {
+        match self {
+            TraceOutcome::Complete(graph) | TraceOutcome::Cancelled(graph) => graph,
+        }
+    }
+
+    pub fn into_graph(self) -> TraceGraph {
+        match self {
+            TraceOutcome::Complete(graph) | TraceOutcome::Cancelled(graph) => graph,
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        matches!(self, TraceOutcome::Cancelled(_))
+    }
+}
+
+/// Follows coins through the transaction graph of a data source.
+///
+/// Any `BlockchainDataSource` works; wrap it in a `CachingDataSource` so converging
+/// paths and repeated traces don't refetch the same transactions.
+///
+/// A trace keeps up to `TraceConfig::concurrency` lookups in flight: while it waits
+/// for the one it needs next, it already looks up what is queued behind. Results are
+/// still merged into the graph in queue order, so the graph is the same whatever the
+/// concurrency and whichever lookup completes first.
+///
+/// Clones share the data source, so one tracer (say over a shared cache) can serve
+/// concurrent traces from many tasks.
+///
+/// Retry backoffs are waited out, and traces timed, on the `SystemClock` unless the
+/// tracer is given another with `clock`.
+pub struct Tracer<D> {
+    source: Arc<D>,
+    clock: Arc<dyn Clock>,
+}
+
+impl<D> Clone for Tracer<D> {
+    fn clone(&self) -> Self {
+        Self {
+            source: Arc::clone(&self.source),
+            clock: Arc::clone(&self.clock),
+        }
+    }
+}
+
+impl<D: BlockchainDataSource + Sync> Tracer<D> {
+    pub fn new(source: D) -> Self {
+        Self {
+            source: Arc::new(source),
+            clock: time::system(),
+        }
+    }
+
+    /// Waits and times traces on `clock` instead of the `SystemClock`
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// The data source lookups go through
+    pub fn source(&self) -> &D {
+        &self.source
+    }
+
+    /// Whether another transaction spends the prevouts of `txid` in its place.
+    ///
+    /// # Errors
+    /// - `Source` - a lookup failed, including the source no longer knowing `txid`, as
+    ///   happens to replaced transactions dropped from the mempool: check a
+    ///   transaction held on to with `check_replacement_of`
+    pub async fn check_replacement(&self, txid: Txid) -> Result<ReplacementStatus> {
+        let tx = self
+            .source
+            .get_transaction(txid)
+            .await
+            .with_ctx(|| ErrorContext::new("get_transaction").txid(txid))?;
+        self.check_replacement_of(&tx).await
+    }
+
+    /// Whether another transaction spends the prevouts of `tx` in its place: the
+    /// spender of each of its prevouts is looked up again, and that of the first one
+    /// spent by another transaction is the replacement. Whether the replacement is
+    /// confirmed is looked up too, unless the source cannot report it.
+    ///
+    /// # Errors
+    /// - `Source` - a lookup failed
+    pub async fn check_replacement_of(&self, tx: &Transaction) -> Result<ReplacementStatus> {
+        let txid = tx.compute_txid();
+        for input in tx
+            .input
+            .iter()
+            .filter(|input| !input.previous_output.is_null())
+        {
+            let Some(spender) = self
+                .source
+                .get_spending_transaction(input.previous_output)
+                .await
+                .with_ctx(|| {
+                    ErrorContext::new("get_spending_transaction").outpoint(input.previous_output)
+                })?
+            else {
+                continue;
+            };
+            let by = spender.compute_txid();
+            if by == txid {
+                continue;
+            }
+            let status = self
+                .source
+                .get_transaction_status(by)
+                .await
+                .with_ctx(|| ErrorContext::new("get_transaction_status").txid(by));
+            let confirmed = match status {
+                Ok(status) => Some(status.confirmed),
+                Err(error)
+                    if matches!(error.inner(), BlockchainError::UnsupportedOperation { .. }) =>
+                {
+                    None
+                }
+                Err(error) => return Err(error.into()),
+            };
+            return Ok(ReplacementStatus::Replaced { by, confirmed });
+        }
+        Ok(ReplacementStatus::NotReplaced)
+    }
+
+    /// Follows `root` forward: who spent it, who spent the outputs of that spender, and
+    /// so on, breadth first.
+    ///
+    /// The transaction creating `root` is the depth 0 node. Every output of a traced
+    /// transaction becomes an edge; it is followed if the branch strategy selects it,
+    /// and is otherwise a leaf marked with why the trace stopped there: unspent, beyond
+    /// `max_depth`, over `max_transactions` or `max_breadth`, or not selected. A
+    /// transaction with an output cut by one of the caps is marked truncated.
+    ///
+    /// Transactions the CoinJoin detector flags carry its verdict; under the default
+    /// `StopAndMark` policy their outputs are `CoinJoin` leaves.
+    ///
+    /// Outputs paying an address of `config.labels` are labelled on their transaction;
+    /// those of a category `config.label_policy` stops at are leaves named after the
+    /// entity (`Exchange`, `Mixer`, `Sanctioned`, or `Other` with the category).
+    ///
+    /// Outputs worth less than `config.min_output_value` are `BelowMinValue` leaves,
+    /// `root` aside.
+    ///
+    /// With a block height or time window in `config`, the confirmation status of each
+    /// spender is looked up (one more request per transaction, plus a header per block
+    /// when the status lacks its time) and recorded on its node; outputs spent by a
+    /// transaction outside the window are `OutOfWindow` leaves.
+    ///
+    /// Outputs paying a target of `config.stop` are `ReachedTarget` leaves. With
+    /// `early_exit`, the first one reached ends the trace, and the graph returned is
+    /// the path from `root` to it.
+    ///
+    /// Cancelling `config.cancel` ends the trace with `TraceOutcome::Cancelled`: the
+    /// outputs still queued become `Cancelled` leaves of the partial graph. With
+    /// `config.checkpoint`, a checkpoint of where it stopped is saved first.
+    ///
+    /// # Errors
+    /// - `InvalidConfig` - `config` does not validate
+    /// - `InvalidInput` - the transaction of `root` has no such output
+    /// - `Source` - a lookup failed (including `root`'s transaction not being found),
+    ///   after the retries of `config.retry`
+    pub async fn trace_forward(
+        &self,
+        root: OutPoint,
+        config: &TraceConfig,
+    ) -> Result<TraceOutcome> {
+        let report = self
+            .report(Start::Forward(vec![root]), config, None)
+            .await?;
+        Ok(report.outcome)
+    }
+
+    /// `trace_forward`, reporting what the trace cost.
+    ///
+    /// Under `config.dry_run`, the trace stops at the depth sampled and the report
+    /// estimates the requests of the full trace.
+    pub async fn trace_forward_with_report(
+        &self,
+        root: OutPoint,
+        config: &TraceConfig,
+    ) -> Result<TraceReport> {
+        self.report(Start::Forward(vec![root]), config, None).await
+    }
+
+    /// `trace_forward`, reporting progress on a stream of events.
+    ///
+    /// The stream ends once the returned future completes; it holds the latest
+    /// `config.event_buffer` events when the consumer falls behind.
+    pub fn trace_forward_with_events<'a>(
+        &'a self,
+        root: OutPoint,
+        config: &'a TraceConfig,
+    ) -> (impl Future<Output = Result<TraceOutcome>> + 'a, TraceEvents) {
+        let (sender, events) = events::channel(config.event_buffer);
+        let trace = async move {
+            let report = self
+                .report(Start::Forward(vec![root]), config, Some(sender))
+                .await?;
+            Ok(report.outcome)
+        };
+        (trace, events)
+    }
+
+    /// `trace_forward`, handing out the nodes and edges of the graph as the trace
+    /// finalizes them rather than the whole graph at the end (see `stream`).
+    ///
+    /// An edge comes after the nodes of both its endpoints. The trace runs while the
+    /// stream is polled, and waits once `STREAM_BUFFER` items are held for the
+    /// consumer; dropping the stream ends it. An error ends the stream after the items
+    /// sent before it.
+    ///
+    /// What is sent is dropped from the graph the trace walks: the trace holds its
+    /// queue and what it has not sent yet rather than the whole graph, plus the txids
+    /// it traced, for converging paths, and the scripts of the outputs it dropped, for
+    /// change detection. `stream::collect` rebuilds the graph `trace_forward` returns,
+    /// but where the trace needed what it dropped: prevouts sent already are unknown to
+    /// the input value and pattern of their spender and to annotators, a path
+    /// converging on a node sent does not complete it, and no checkpoint is saved.
+    ///
+    /// # Errors
+    /// - `InvalidConfig` - `config` does not validate, or sets `early_exit`, whose
+    ///   graph is only known once the target is reached
+    /// - any error of `trace_forward`
+    pub fn trace_forward_streaming<'a>(
+        &'a self,
+        root: OutPoint,
+        config: &'a TraceConfig,
+    ) -> impl Stream<Item = Result<TraceItem>> + 'a {
+        let (sink, receiver) = stream::channel();
+        let sender = sink.sender();
+        let trace = async move { self.stream_forward(root, config, &sink).await };
+        stream::drive(trace, sender, receiver)
+    }
+
+    /// Forward trace of `trace_forward_streaming`, sending its items to `sink`
+    pub(crate) async fn stream_forward(
+        &self,
+        root: OutPoint,
+        config: &TraceConfig,
+        sink: &ItemSink,
+    ) -> Result<()> {
+        if config.stop.early_exit {
+            return Err(TracerError::InvalidConfig(
+                "an early exit trace cannot be streamed".to_string(),
+            ));
+        }
+        // A checkpoint of a graph dropped as it is sent would resume into a partial one
+        let config = TraceConfig {
+            checkpoint: None,
+            ..config.clone()
+        };
+        let session = Session {
+            items: Some(sink),
+            ..Session::new(&config, None, &*self.clock)
+        };
+        self.forward(vec![root], &session).await.map(|_| ())
+    }
+
+    /// Follows several outpoints forward at once, into one graph.
+    ///
+    /// The trace is `trace_forward` with every outpoint queued at the start: the
+    /// transactions creating them are depth 0 nodes, and a transaction reachable from
+    /// several of them is looked up and expanded once. Every node is tagged with the
+    /// indexes in `outpoints` it is reachable from (`TraceNode::seeds`); the nodes
+    /// tagged with more than one are where the paths converge
+    /// (`TraceGraph::convergences`). Repeated outpoints are traced once, under the
+    /// index they first appear at.
+    ///
+    /// # Errors
+    /// - `InvalidInput` - `outpoints` is empty, or the transaction of one of them has no
+    ///   such output
+    /// - any error of `trace_forward`
+    pub async fn trace_forward_multi(
+        &self,
+        outpoints: &[OutPoint],
+        config: &TraceConfig,
+    ) -> Result<TraceOutcome> {
+        if outpoints.is_empty() {
+            return Err(TracerError::InvalidInput(
+                "no outpoint to trace from".to_string(),
+            ));
+        }
+        let mut seeds = Vec::with_capacity(outpoints.len());
+        for outpoint in outpoints {
+            if !seeds.contains(outpoint) {
+                seeds.push(*outpoint);
+            }
+        }
+        let report = self.report(Start::Forward(seeds), config, None).await?;
+        Ok(report.outcome)
+    }
+
+    #[tracing::instrument(name = "trace", skip_all, fields(direction = "forward", roots = roots.len()))]
+    async fn forward(&self, roots: Vec<OutPoint>, session: &Session<'_>) -> Result<TraceOutcome> {
+        let config = session.config;
+        config.validate()?;
+        let mut graph = TraceGraph::new();
+        let mut budget = Budget::new(config);
+        let mut funding: HashMap<Txid, Transaction> = HashMap::new();
+        let mut pending = VecDeque::new();
+        for root in &roots {
+            let tx = match funding.entry(root.txid) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => {
+                    let lookup = self.transaction(session, root.txid);
+                    let Some(tx) = lookup.instrument(hop(root.txid, 0)).await? else {
+                        return Ok(TraceOutcome::Cancelled(TraceGraph::new()));
+                    };
+                    let mut node = traced(config, &tx, 0);
+                    node.annotations = annotations(config, &graph, &tx, &[], 0);
+                    budget.add(&mut graph, node);
+                    session.fetched(root.txid, 0);
+                    entry.insert(tx)
+                }
+            };
+            let output = tx.output.get(root.vout as usize).cloned().ok_or_else(|| {
+                TracerError::InvalidInput(format!("{} has no output {}", root.txid, root.vout))
+            })?;
+            pending.push_back((TraceEdge::new(*root, &output, config.network), 0));
+        }
+        self.run_forward(roots, graph, budget, pending, session)
+            .await
+    }
+
+    /// Forward trace from its state between two hops.
+    ///
+    /// `pending` holds the outputs to follow, as the edges they become, with the depth of
+    /// the transaction that created them.
+    async fn run_forward(
+        &self,
+        roots: Vec<OutPoint>,
+        mut graph: TraceGraph,
+        mut budget: Budget,
+        mut pending: VecDeque<(TraceEdge, usize)>,
+        session: &Session<'_>,
+    ) -> Result<TraceOutcome> {
+        let config = session.config;
+        let mut spenders = Lookahead::new(config.concurrency, move |outpoint| async move {
+            (outpoint, self.spender(session, outpoint).await)
+        });
+        // Outputs at the front of `pending` whose lookup is planned
+        let mut planned = 0;
+        // Values of the outputs queued, for the fees of their spenders; the outputs not
+        // followed, and those resolved, are edges of the graph
+        let mut queued: HashMap<OutPoint, Amount> = pending
+            .iter()
+            .map(|(edge, _)| (edge.outpoint, edge.value))
+            .collect();
+        for (edge, _) in &pending {
+            session.queued(edge);
+        }
+        while !session.cancelled() {
+            session.tick(session.traced(&graph), pending.len());
+            session.checkpoint(&graph, &budget, || Frontier::Forward {
+                roots: roots.clone(),
+                pending: pending.iter().cloned().collect(),
+            })?;
+            session.flush(&mut graph, false).await;
+            // Nothing past a target ending the trace is looked up
+            for (edge, depth) in pending.range(planned..) {
+                if config.stop.matches(&edge.script_pubkey) {
+                    if config.stop.early_exit {
+                        break;
+                    }
+                } else if *depth < config.max_depth {
+                    spenders.plan(edge.outpoint, || hop(edge.outpoint.txid, *depth));
+                }
+                planned += 1;
+            }
+            let Some((edge, depth)) = pending.pop_front() else {
+                break;
+            };
+            session.dequeued(&edge);
+            planned = planned.saturating_sub(1);
+            let outpoint = edge.outpoint;
+            // Resolved below into an edge of the graph, or queued again
+            queued.remove(&outpoint);
+            if config.stop.matches(&edge.script_pubkey) {
+                let target = edge.script_pubkey.clone();
+                session.terminate(&mut graph, edge, TerminalReason::ReachedTarget(target));
+                if config.stop.early_exit {
+                    let graph = seeded(graph, &roots);
+                    return Ok(TraceOutcome::Complete(graph.path_to(&outpoint)));
+                }
+                continue;
+            }
+            if depth >= config.max_depth {
+                mark(&mut graph, &outpoint.txid, |node| node.frontier = true);
+                session.terminate(&mut graph, edge, TerminalReason::MaxDepthReached);
+                continue;
+            }
+            let spender = match spenders.take(outpoint, || hop(outpoint.txid, depth)).await {
+                Err(TracerError::Source(error)) if config.continue_on_error => {
+                    session.terminate(&mut graph, edge, TerminalReason::error(&error));
+                    continue;
+                }
+                result => result?,
+            };
+            let Some(spender) = spender else {
+                pending.push_front((edge, depth));
+                break;
+            };
+            let Some(spender) = spender else {
+                mark(&mut graph, &outpoint.txid, |node| node.unspent = true);
+                session.terminate(&mut graph, edge, TerminalReason::Unspent);
+                continue;
+            };
+
+            let txid = spender.compute_txid();
+            if let Some(channel) =
+                LightningChannel::revealed_by(outpoint, &edge.script_pubkey, &spender)
+            {
+                let open = matches!(channel, LightningChannel::Open { .. });
+                reveal(&mut graph, &outpoint.txid, channel);
+                if open && config.lightning_policy == LightningPolicy::StopAtOpen {
+                    session.terminate(&mut graph, edge, TerminalReason::LightningChannel);
+                    continue;
+                }
+            }
+            let input_value = input_value(&spender, |prevout| match *prevout == outpoint {
+                true => Some(edge.value),
+                false => graph
+                    .edge(prevout)
+                    .map(|edge| edge.value)
+                    .or_else(|| queued.get(prevout).copied()),
+            });
+            // Converging paths: link to the node already traced, without expanding it again.
+            // More of its prevouts may be known by now, unless a streamed trace sent it.
+            if graph.contains_node(&txid) || session.sent(&txid) {
+                mark(&mut graph, &txid, |node| {
+                    if node.input_value.is_none() {
+                        node.set_input_value(input_value);
+                    }
+                });
+                session.link(&mut graph, edge, txid);
+                continue;
+            }
+            if session.visited(txid, Direction::Forward) {
+                session.terminate(&mut graph, edge, TerminalReason::Visited);
+                continue;
+            }
+            if let Some(reason) = budget.exhausted(session.traced(&graph), depth + 1) {
+                mark(&mut graph, &outpoint.txid, |node| node.truncated = true);
+                session.terminate(&mut graph, edge, reason);
+                continue;
+            }
+            let placement = if config.windowed() {
+                let placement = match self.placement(session, txid).await {
+                    Err(TracerError::Source(error)) if config.continue_on_error => {
+                        session.terminate(&mut graph, edge, TerminalReason::error(&error));
+                        continue;
+                    }
+                    result => result?,
+                };
+                let Some(placement) = placement else {
+                    pending.push_front((edge, depth));
+                    break;
+                };
+                if !placement.in_window(config) {
+                    session.terminate(&mut graph, edge, TerminalReason::OutOfWindow);
+                    continue;
+                }
+                Some(placement)
+            } else {
+                None
+            };
+
+            let mut node = traced(config, &spender, depth + 1);
+            node.set_input_value(input_value);
+            if let Some(placement) = placement {
+                placement.place(&mut node);
+            }
+            let output = tx_out(&edge);
+            // Prevouts known so far: the output followed, and those in the graph
+            let inputs: Vec<TxOut> = std::iter::once(output.clone())
+                .chain(
+                    spender
+                        .input
+                        .iter()
+                        .filter(|input| input.previous_output != outpoint)
+                        .filter_map(|input| graph.edge(&input.previous_output))
+                        .map(tx_out),
+                )
+                .collect();
+            node.pattern = config.pattern_classifier.classify(&spender, &inputs);
+            let change = session.with_seen(&graph, &output.script_pubkey, |seen| {
+                let context = ChangeContext {
+                    fee: node.fee,
+                    seen,
+                    clustered: None,
+                };
+                config
+                    .change_detector
+                    .detect(&spender.output, &inputs, &context)
+            });
+            let spent = std::slice::from_ref(&output);
+            let coinjoin = config.coinjoin_detector.detect(&spender, spent);
+            let (followed, skipped) = match (&coinjoin, config.coinjoin_policy) {
+                (Some(_), CoinJoinPolicy::StopAndMark) => (Vec::new(), TerminalReason::CoinJoin),
+                (Some(verdict), CoinJoinPolicy::FollowMatchingValue) => (
+                    config.coinjoin_detector.matching_outputs(
+                        verdict,
+                        &spender.output,
+                        output.value,
+                    ),
+                    TerminalReason::CoinJoin,
+                ),
+                _ => match config.batch_policy.matching_outputs(
+                    &node.pattern,
+                    &spender.output,
+                    output.value,
+                ) {
+                    Some(matching) => (matching, TerminalReason::NotFollowed),
+                    None => (
+                        config
+                            .branch
+                            .select_with(&spender.output, spent, change.as_ref()),
+                        config.branch.skipped(),
+                    ),
+                },
+            };
+            node.annotations = annotations(config, &graph, &spender, &inputs, depth + 1);
+            budget.add(&mut graph, TraceNode { coinjoin, ..node });
+            session.fetched(txid, depth + 1);
+            session.link(&mut graph, edge, txid);
+            for (vout, output) in spender.output.iter().enumerate() {
+                let edge = TraceEdge {
+                    change_score: change.as_ref().and_then(|verdict| verdict.score(vout)),
+                    ..TraceEdge::new(OutPoint::new(txid, vout as u32), output, config.network)
+                };
+                let label = config
+                    .labels
+                    .as_ref()
+                    .and_then(|labels| labels.get(&output.script_pubkey))
+                    .filter(|label| config.label_policy.stops(label.category));
+                if config.stop.matches(&output.script_pubkey) {
+                    queued.insert(edge.outpoint, edge.value);
+                    session.queued(&edge);
+                    pending.push_back((edge, depth + 1));
+                } else if let Some(label) = label {
+                    session.terminate(&mut graph, edge, label.terminal_reason());
+                } else if output.script_pubkey.is_op_return() {
+                    session.terminate(&mut graph, edge, TerminalReason::DataCarrier);
+                } else if output.value < config.min_output_value {
+                    session.terminate(&mut graph, edge, TerminalReason::BelowMinValue);
+                } else if followed.contains(&vout) {
+                    queued.insert(edge.outpoint, edge.value);
+                    session.queued(&edge);
+                    pending.push_back((edge, depth + 1));
+                } else {
+                    session.terminate(&mut graph, edge, skipped.clone());
+                }
+            }
+        }
+
+        let Some(reason) = session.stopped() else {
+            session.flush(&mut graph, true).await;
+            return Ok(TraceOutcome::Complete(seeded(graph, &roots)));
+        };
+        if reason == TerminalReason::Cancelled {
+            session.save_checkpoint(&graph, &budget, || Frontier::Forward {
+                roots: roots.clone(),
+                pending: pending.iter().cloned().collect(),
+            })?;
+        }
+        for (edge, _) in pending {
+            mark(&mut graph, &edge.from(), |node| node.frontier = true);
+            session.terminate(&mut graph, edge, reason.clone());
+        }
+        session.flush(&mut graph, true).await;
+        Ok(reason.outcome(seeded(graph, &roots)))
+    }
+
+    /// Continues a trace from `checkpoint`, to the graph an uninterrupted run would have
+    /// produced.
+    ///
+    /// `config` must be the configuration the checkpoint was taken under, as far as the
+    /// shape of the graph goes (see `TraceConfig::fingerprint`). `force` resumes under a
+    /// different one anyway, e.g. to raise `max_depth` of a finished part of a trace.
+    ///
+    /// # Errors
+    /// - `InvalidConfig` - `config` does not validate
+    /// - `IncompatibleCheckpoint` - the checkpoint was taken under another
+    ///   configuration, and `force` is not set, or has an unknown format version
+    /// - any error of `trace_forward` or `trace_backward`
+    pub async fn resume(
+        &self,
+        checkpoint: TraceCheckpoint,
+        config: &TraceConfig,
+        force: bool,
+    ) -> Result<TraceOutcome> {
+        config.validate()?;
+        checkpoint.check(config, force)?;
+        let session = Session {
+            requests: checkpoint.requests.into(),
+            saved: checkpoint.requests.into(),
+            ..Session::new(config, None, &*self.clock)
+        };
+        let budget = Budget {
+            per_depth: checkpoint.per_depth.clone(),
+            ..Budget::new(config)
+        };
+        match checkpoint.frontier.clone() {
+            Frontier::Forward { roots, pending } => {
+                let graph = checkpoint.into_graph();
+                let span = tracing::info_span!("trace", direction = "forward", roots = roots.len());
+                self.run_forward(roots, graph, budget, pending.into(), &session)
+                    .instrument(span)
+                    .await
+            }
+            Frontier::Backward {
+                start,
+                pending,
+                fetched,
+            } => {
+                let graph = checkpoint.into_graph();
+                let fetched = fetched
+                    .into_iter()
+                    .map(|tx| (tx.compute_txid(), tx))
+                    .collect();
+                let span = tracing::info_span!("trace", direction = "backward", txid = %start);
+                self.run_backward(start, graph, budget, pending.into(), fetched, &session)
+                    .instrument(span)
+                    .await
+            }
+        }
+    }
+
+    /// Follows `txid` backward: the transactions its inputs spend, their own inputs, and
+    /// so on, breadth first, until coinbase transactions or `max_depth`.
+    ///
+    /// `txid` is the depth 0 node, its parents are at depth 1. Coinbase transactions are
+    /// marked as origins. Every traced input becomes an edge from the parent to the
+    /// spending transaction; a transaction with inputs left out by `max_transactions`
+    /// or `max_breadth` is marked truncated. Under the default `StopAndMark` policy, the
+    /// inputs of a transaction flagged as a CoinJoin are not followed. To trace the
+    /// provenance of an outpoint, trace its txid.
+    ///
+    /// With a block height or time window in `config`, the confirmation status of each
+    /// parent is looked up and recorded on its node, as in `trace_forward`; parents
+    /// outside the window are kept, since the transactions in it spend them, but are
+    /// not expanded and sit on the frontier.
+    ///
+    /// Cancelling `config.cancel` ends the trace with `TraceOutcome::Cancelled`, the
+    /// transactions still to expand on the frontier of the partial graph. With
+    /// `config.checkpoint`, a checkpoint of where it stopped is saved first.
+    ///
+    /// # Errors
+    /// - `InvalidConfig` - `config` does not validate
+    /// - `Source` - a lookup failed after the retries of `config.retry`, or a parent
+    ///   lacks the output spent from it
+    pub async fn trace_backward(&self, txid: Txid, config: &TraceConfig) -> Result<TraceOutcome> {
+        let report = self.report(Start::Backward(txid), config, None).await?;
+        Ok(report.outcome)
+    }
+
+    /// `trace_backward`, reporting what the trace cost (see
+    /// `trace_forward_with_report`)
+    pub async fn trace_backward_with_report(
+        &self,
+        txid: Txid,
+        config: &TraceConfig,
+    ) -> Result<TraceReport> {
+        self.report(Start::Backward(txid), config, None).await
+    }
+
+    /// Runs a trace from `start` and accounts for its costs, sending the final
+    /// `Progress` event to `events`
+    async fn report(
+        &self,
+        start: Start,
+        config: &TraceConfig,
+        events: Option<EventSender>,
+    ) -> Result<TraceReport> {
+        config.validate()?;
+        let sample;
+        let (run, sampled) = match config.dry_run {
+            Some(depth) if depth < config.max_depth => {
+                sample = config.clone().max_depth(depth);
+                (&sample, Some(depth))
+            }
+            depth => (config, depth),
+        };
+        let session = Session::new(run, events, &*self.clock);
+        let started = self.clock.now();
+        let outcome = match start {
+            Start::Forward(roots) => self.forward(roots, &session).await?,
+            Start::Backward(txid) => self.backward(txid, &session).await?,
+        };
+        session.progress(outcome.graph().len(), 0);
+        let requests = session.requests();
+        tracing::info!(
+            transactions = outcome.graph().len(),
+            requests,
+            cancelled = outcome.is_cancelled(),
+            "trace finished"
+        );
+        let estimated_requests = sampled
+            .map(|depth| report::estimate_requests(outcome.graph(), requests, depth, config));
+        let (ignored_outputs, ignored_value) = report::ignored(outcome.graph());
+        Ok(TraceReport {
+            outcome,
+            requests,
+            cache_hits: session.cache_hits.load(Ordering::SeqCst),
+            elapsed: self.clock.now().saturating_duration_since(started),
+            estimated_requests,
+            ignored_outputs,
+            ignored_value,
+            skipped_expansions: session.skipped.load(Ordering::SeqCst),
+        })
+    }
+
+    /// Looks up the spender of `outpoint` through `session`
+    async fn spender(
+        &self,
+        session: &Session<'_>,
+        outpoint: OutPoint,
+    ) -> Result<Option<Option<Transaction>>> {
+        let cached = self.source.is_cached(&CacheKey::Spending(outpoint));
+        session
+            .request(cached, || async move {
+                self.source
+                    .get_spending_transaction(outpoint)
+                    .await
+                    .with_ctx(|| ErrorContext::new("get_spending_transaction").outpoint(outpoint))
+            })
+            .await
+    }
+
+    /// Looks up where `txid` was confirmed through `session`, once per trace. The time
+    /// of its block comes from the block's header when the status lacks it and
+    /// `max_timestamp` needs it, once per block.
+    async fn placement(&self, session: &Session<'_>, txid: Txid) -> Result<Option<Placement>> {
+        if let Some(placement) = session.placements.lock().expect("placements").get(&txid) {
+            return Ok(Some(*placement));
+        }
+        let Some(status) = session
+            .request(false, || async move {
+                self.source
+                    .get_transaction_status(txid)
+                    .await
+                    .with_ctx(|| ErrorContext::new("get_transaction_status").txid(txid))
+            })
+            .await?
+        else {
+            return Ok(None);
+        };
+        let mut time = status.block_time;
+        if let (None, Some(hash), Some(_)) = (time, status.block_hash, session.config.max_timestamp)
+        {
+            let known = session
+                .block_times
+                .lock()
+                .expect("block times")
+                .get(&hash)
+                .copied();
+            time = match known {
+                Some(time) => Some(time),
+                None => {
+                    let Some(header) = session
+                        .request(false, || async move {
+                            self.source
+                                .get_block_header(hash)
+                                .await
+                                .with_ctx(|| ErrorContext::new("get_block_header").txid(txid))
+                        })
+                        .await?
+                    else {
+                        return Ok(None);
+                    };
+                    let time = u64::from(header.time);
+                    session
+                        .block_times
+                        .lock()
+                        .expect("block times")
+                        .insert(hash, time);
+                    Some(time)
+                }
+            };
+        }
+        let placement = Placement { status, time };
+        session
+            .placements
+            .lock()
+            .expect("placements")
+            .insert(txid, placement);
+        Ok(Some(placement))
+    }
+
+    /// Looks up the transaction `txid` through `session`
+    async fn transaction(&self, session: &Session<'_>, txid: Txid) -> Result<Option<Transaction>> {
+        let cached = self.source.is_cached(&CacheKey::Transaction(txid));
+        session
+            .request(cached, || async move {
+                self.source
+                    .get_transaction(txid)
+                    .await
+                    .with_ctx(|| ErrorContext::new("get_transaction").txid(txid))
+            })
+            .await
+    }
+
+    /// `trace_backward`, reporting progress on a stream of events (see
+    /// `trace_forward_with_events`)
+    pub fn trace_backward_with_events<'a>(
+        &'a self,
+        txid: Txid,
+        config: &'a TraceConfig,
+    ) -> (impl Future<Output = Result<TraceOutcome>> + 'a, TraceEvents) {
+        let (sender, events) = events::channel(config.event_buffer);
+        let trace = async move {
+            let report = self
+                .report(Start::Backward(txid), config, Some(sender))
+                .await?;
+            Ok(report.outcome)
+        };
+        (trace, events)
+    }
+
+    #[tracing::instrument(name = "trace", skip_all, fields(direction = "backward", %txid))]
+    async fn backward(&self, txid: Txid, session: &Session<'_>) -> Result<TraceOutcome> {
+        let config = session.config;
+        config.validate()?;
+        let lookup = self.transaction(session, txid);
+        let Some(start) = lookup.instrument(hop(txid, 0)).await? else {
+            return Ok(TraceOutcome::Cancelled(TraceGraph::new()));
+        };
+
+        let mut graph = TraceGraph::new();
+        let mut budget = Budget::new(config);
+        let mut node = traced(config, &start, 0);
+        node.annotations = annotations(config, &graph, &start, &[], 0);
+        budget.add(&mut graph, node);
+        session.fetched(txid, 0);
+        let fetched = HashMap::from([(txid, start)]);
+        let pending = VecDeque::from([(txid, 0)]);
+        self.run_backward(txid, graph, budget, pending, fetched, session)
+            .await
+    }
+
+    /// Backward trace from its state between two hops.
+    ///
+    /// `pending` holds the transactions to expand with their depth, `fetched` every
+    /// transaction traced so far, to link inputs spending one already in the graph.
+    async fn run_backward(
+        &self,
+        start: Txid,
+        mut graph: TraceGraph,
+        mut budget: Budget,
+        mut pending: VecDeque<(Txid, usize)>,
+        mut fetched: HashMap<Txid, Transaction>,
+        session: &Session<'_>,
+    ) -> Result<TraceOutcome> {
+        let config = session.config;
+        let mut parents = Lookahead::new(config.concurrency, move |txid| async move {
+            (txid, self.transaction(session, txid).await)
+        });
+        // Transactions at the front of `pending` whose parents are planned, and the caps
+        // as they stand once those parents are in the graph
+        let mut planned = 0;
+        let mut planned_budget = budget.clone();
+        let mut planned_len = graph.len();
+        'pending: while !session.cancelled() {
+            session.tick(graph.len(), pending.len());
+            session.checkpoint(&graph, &budget, || Frontier::Backward {
+                start,
+                pending: pending.iter().copied().collect(),
+                fetched: fetched.values().cloned().collect(),
+            })?;
+            // Parents are planned the way they are fetched below, so exactly the ones
+            // the caps let in are looked up
+            for &(txid, depth) in pending.range(planned..) {
+                planned += 1;
+                let tx = &fetched[&txid];
+                if depth >= config.max_depth
+                    || tx.is_coinbase()
+                    || (config.coinjoin_policy == CoinJoinPolicy::StopAndMark
+                        && config.coinjoin_detector.detect(tx, &[]).is_some())
+                {
+                    continue;
+                }
+                for input in &tx.input {
+                    let parent = input.previous_output.txid;
+                    if fetched.contains_key(&parent)
+                        || parents.is_planned(&parent)
+                        || planned_budget.exhausted(planned_len, depth + 1).is_some()
+                    {
+                        continue;
+                    }
+                    parents.plan(parent, || hop(txid, depth));
+                    *planned_budget.per_depth.entry(depth + 1).or_default() += 1;
+                    planned_len += 1;
+                }
+            }
+            let Some((txid, depth)) = pending.pop_front() else {
+                break;
+            };
+            planned = planned.saturating_sub(1);
+            let tx = &fetched[&txid];
+            if tx.is_coinbase() {
+                continue;
+            }
+            if let Some(verdict) = config.coinjoin_detector.detect(tx, &[]) {
+                mark(&mut graph, &txid, |node| node.coinjoin = Some(verdict));
+                // Every input of a CoinJoin is another participant's history
+                if config.coinjoin_policy == CoinJoinPolicy::StopAndMark {
+                    continue;
+                }
+            }
+            if depth >= config.max_depth {
+                mark(&mut graph, &txid, |node| node.frontier = true);
+                continue;
+            }
+            let prevouts: Vec<OutPoint> =
+                tx.input.iter().map(|input| input.previous_output).collect();
+            // Sum of the prevouts, unknown as soon as one of them is left out
+            let mut input_value = Some(Amount::ZERO);
+            let mut known = Vec::new();
+            for prevout in prevouts {
+                if let Entry::Vacant(slot) = fetched.entry(prevout.txid) {
+                    if budget.exhausted(graph.len(), depth + 1).is_some() {
+                        mark(&mut graph, &txid, |node| node.truncated = true);
+                        input_value = None;
+                        continue;
+                    }
+                    let Some(parent) = parents.take(prevout.txid, || hop(txid, depth)).await?
+                    else {
+                        // Expanded again if the trace resumes, keeping the parents fetched
+                        // so far
+                        pending.push_front((txid, depth));
+                        break 'pending;
+                    };
+                    let mut node = traced(config, &parent, depth + 1);
+                    node.annotations = annotations(config, &graph, &parent, &[], depth + 1);
+                    if config.windowed() {
+                        let Some(placement) = self.placement(session, prevout.txid).await? else {
+                            pending.push_front((txid, depth));
+                            break 'pending;
+                        };
+                        placement.place(&mut node);
+                        node.frontier = !placement.in_window(config);
+                    }
+                    if !node.frontier && session.visited(prevout.txid, Direction::Backward) {
+                        node.frontier = true;
+                    }
+                    let expand = !node.frontier;
+                    budget.add(&mut graph, node);
+                    session.fetched(prevout.txid, depth + 1);
+                    if expand {
+                        pending.push_back((prevout.txid, depth + 1));
+                    }
+                    slot.insert(parent);
+                }
+                let output = fetched[&prevout.txid]
+                    .output
+                    .get(prevout.vout as usize)
+                    .ok_or_else(|| {
+                        BlockchainError::DataInconsistency(format!(
+                            "{} spends missing output {}",
+                            txid, prevout
+                        ))
+                    })?;
+                input_value = input_value.and_then(|sum| sum.checked_add(output.value));
+                if let Some(channel) =
+                    LightningChannel::revealed_by(prevout, &output.script_pubkey, &fetched[&txid])
+                {
+                    reveal(&mut graph, &prevout.txid, channel);
+                }
+                known.push(output.clone());
+                graph.insert_edge(TraceEdge {
+                    spent_by: Some(txid),
+                    ..TraceEdge::new(prevout, output, config.network)
+                });
+            }
+            let pattern = config.pattern_classifier.classify(&fetched[&txid], &known);
+            // Every prevout is known now, short of a truncated expansion
+            let found = annotations(config, &graph, &fetched[&txid], &known, depth);
+            mark(&mut graph, &txid, |node| {
+                node.set_input_value(input_value);
+                node.pattern = pattern;
+                node.annotations.extend(found);
+            });
+        }
+
+        let Some(reason) = session.stopped() else {
+            return Ok(TraceOutcome::Complete(graph));
+        };
+        if reason == TerminalReason::Cancelled {
+            session.save_checkpoint(&graph, &budget, || Frontier::Backward {
+                start,
+                pending: pending.iter().copied().collect(),
+                fetched: fetched.values().cloned().collect(),
+            })?;
+        }
+        for (txid, _) in pending {
+            mark(&mut graph, &txid, |node| node.frontier = true);
+        }
+        Ok(reason.outcome(graph))
+    }
+}
+
+/// `graph` of a forward trace from `roots`, its nodes tagged with the roots they are
+/// reachable from when there are several
+fn seeded(mut graph: TraceGraph, roots: &[OutPoint]) -> TraceGraph {
+    if roots.len() > 1 {
+        graph.tag_seeds(roots);
+    }
+    graph
+}
+
+/// Where a transaction was confirmed, for the window of a trace
+#[derive(Debug, Clone, Copy)]
+struct Placement {
+    status: TxStatus,
+    /// Time of its block, when known
+    time: Option<u64>,
+}
+
+impl Placement {
+    fn in_window(&self, config: &TraceConfig) -> bool {
+        config.in_window(&self.status, self.time)
+    }
+
+    /// Records the height and time on the transaction's node
+    fn place(&self, node: &mut TraceNode) {
+        node.height = self.status.block_height;
+        node.timestamp = self.time;
+        node.replaceable &= !self.status.confirmed;
+    }
+}
+
+/// Where a trace starts from
+#[derive(Debug, Clone)]
+enum Start {
+    Forward(Vec<OutPoint>),
+    Backward(Txid),
+}
+
+/// Bookkeeping of one trace: the requests it made and where its events go
+struct Session<'a> {
+    config: &'a TraceConfig,
+    events: Option<EventSender>,
+    requests: AtomicUsize,
+    cache_hits: AtomicUsize,
+    /// Set once a lookup was refused for going over `max_requests`
+    exhausted: AtomicBool,
+    /// Requests made when the last `Progress` event was sent
+    reported: AtomicUsize,
+    /// Requests made when the last checkpoint was saved
+    saved: AtomicUsize,
+    /// Confirmations looked up for the window, by txid
+    placements: Mutex<HashMap<Txid, Placement>>,
+    /// Times of the block headers looked up for the window
+    block_times: Mutex<HashMap<BlockHash, u64>>,
+    /// Where a streamed trace sends its nodes and edges
+    items: Option<&'a ItemSink>,
+    /// Transactions not to expand, with the expansion fingerprint of the config
+    visited: Option<(&'a VisitedSet, u64)>,
+    /// Expansions left out for being in `visited`
+    skipped: AtomicUsize,
+    /// What retries wait on
+    clock: &'a dyn Clock,
+}
+
+impl<'a> Session<'a> {
+    fn new(config: &'a TraceConfig, events: Option<EventSender>, clock: &'a dyn Clock) -> Self {
+        Self {
+            config,
+            events,
+            requests: AtomicUsize::new(0),
+            cache_hits: AtomicUsize::new(0),
+            exhausted: AtomicBool::new(false),
+            reported: AtomicUsize::new(0),
+            saved: AtomicUsize::new(0),
+            placements: Mutex::default(),
+            block_times: Mutex::default(),
+            items: None,
+            visited: config
+                .visited
+                .as_deref()
+                .map(|visited| (visited, config.expansion_fingerprint())),
+            skipped: AtomicUsize::new(0),
+            clock,
+        }
+    }
+
+    /// Whether an earlier trace in `direction` expanded `txid`, counting the expansion
+    /// skipped if so
+    fn visited(&self, txid: Txid, direction: Direction) -> bool {
+        let Some((set, expansion)) = self.visited else {
+            return false;
+        };
+        let key = VisitedKey {
+            txid,
+            flags: visited::flags(direction, expansion),
+        };
+        let visited = set.contains(&key);
+        if visited {
+            self.skipped.fetch_add(1, Ordering::SeqCst);
+        }
+        visited
+    }
+
+    fn emit(&self, event: impl FnOnce() -> TraceEvent) {
+        if let Some(events) = &self.events {
+            events.send(event());
+        }
+    }
+
+    fn fetched(&self, txid: Txid, depth: usize) {
+        self.emit(|| TraceEvent::TransactionFetched { txid, depth });
+        if let Some(items) = &self.items {
+            items.added(txid);
+        }
+    }
+
+    /// Records `edge` as a leaf the trace stopped at for `reason`
+    fn terminate(&self, graph: &mut TraceGraph, edge: TraceEdge, reason: TerminalReason) {
+        self.emit(|| TraceEvent::BranchTerminated {
+            outpoint: edge.outpoint,
+            reason: reason.clone(),
+        });
+        if let Some(items) = &self.items {
+            items.resolved(edge.outpoint);
+        }
+        graph.insert_edge(edge.terminal(reason));
+    }
+
+    /// Records `edge` as spent by `txid`, a node of the graph
+    fn link(&self, graph: &mut TraceGraph, edge: TraceEdge, txid: Txid) {
+        if let Some(items) = &self.items {
+            items.resolved(edge.outpoint);
+        }
+        graph.insert_edge(TraceEdge {
+            spent_by: Some(txid),
+            ..edge
+        });
+    }
+
+    /// An output was queued for a forward trace to follow
+    fn queued(&self, edge: &TraceEdge) {
+        if let Some(items) = &self.items {
+            items.queued(edge.from());
+        }
+    }
+
+    /// A queued output was taken off the queue
+    fn dequeued(&self, edge: &TraceEdge) {
+        if let Some(items) = &self.items {
+            items.dequeued(edge.from());
+        }
+    }
+
+    /// Whether a streamed trace sent the node of `txid`, dropping it from the graph
+    fn sent(&self, txid: &Txid) -> bool {
+        self.items.is_some_and(|items| items.sent(txid))
+    }
+
+    /// Transactions traced so far, `graph` holding them all unless the trace is
+    /// streamed
+    fn traced(&self, graph: &TraceGraph) -> usize {
+        match &self.items {
+            Some(items) => items.traced(graph),
+            None => graph.len(),
+        }
+    }
+
+    /// `detect` of the scripts seen by the trace: `output`'s, those of the outputs in
+    /// `graph`, and those of the outputs a streamed trace dropped from it
+    fn with_seen<T>(
+        &self,
+        graph: &TraceGraph,
+        output: &Script,
+        detect: impl FnOnce(HashSet<&Script>) -> T,
+    ) -> T {
+        let Some(items) = &self.items else {
+            return detect(scripts(graph, output).collect());
+        };
+        items.with_scripts(|dropped| {
+            detect(
+                scripts(graph, output)
+                    .chain(dropped.iter().map(ScriptBuf::as_script))
+                    .collect(),
+            )
+        })
+    }
+
+    /// Sends what is final in `graph` to the consumer of a streamed trace, everything
+    /// with `all`, dropping it from `graph`
+    async fn flush(&self, graph: &mut TraceGraph, all: bool) {
+        if let Some(items) = &self.items {
+            items.flush(graph, all).await;
+        }
+    }
+
+    /// Requests made to the data source so far
+    fn requests(&self) -> usize {
+        self.requests.load(Ordering::SeqCst)
+    }
+
+    fn progress(&self, visited: usize, frontier: usize) {
+        let requests_made = self.requests();
+        self.reported.store(requests_made, Ordering::SeqCst);
+        self.emit(|| TraceEvent::Progress {
+            visited,
+            frontier,
+            requests_made,
+        });
+    }
+
+    /// Sends a `Progress` event if `PROGRESS_INTERVAL` requests were made since the last
+    fn tick(&self, visited: usize, frontier: usize) {
+        if self.requests() >= self.reported.load(Ordering::SeqCst) + PROGRESS_INTERVAL {
+            self.progress(visited, frontier);
+        }
+    }
+
+    /// Saves a checkpoint if the schedule of `config.checkpoint` calls for one
+    fn checkpoint(
+        &self,
+        graph: &TraceGraph,
+        budget: &Budget,
+        frontier: impl FnOnce() -> Frontier,
+    ) -> Result<()> {
+        let Some(every) = self.config.checkpoint.as_ref().and_then(|s| s.every) else {
+            return Ok(());
+        };
+        if self.requests() < self.saved.load(Ordering::SeqCst) + every {
+            return Ok(());
+        }
+        self.save_checkpoint(graph, budget, frontier)
+    }
+
+    /// Saves a checkpoint to the path of `config.checkpoint`, if any, whatever its
+    /// schedule: that of a cancelled trace
+    fn save_checkpoint(
+        &self,
+        graph: &TraceGraph,
+        budget: &Budget,
+        frontier: impl FnOnce() -> Frontier,
+    ) -> Result<()> {
+        let Some(schedule) = &self.config.checkpoint else {
+            return Ok(());
+        };
+        let requests = self.requests();
+        self.saved.store(requests, Ordering::SeqCst);
+        TraceCheckpoint::new(
+            self.config,
+            graph.clone(),
+            frontier(),
+            budget.per_depth.clone(),
+            requests,
+        )
+        .save(&schedule.path)
+    }
+
+    /// Why the trace stopped with work left, if it did
+    fn stopped(&self) -> Option<TerminalReason> {
+        if self.cancelled() {
+            Some(TerminalReason::Cancelled)
+        } else if self.exhausted.load(Ordering::SeqCst) {
+            Some(TerminalReason::BudgetExhausted)
+        } else {
+            None
+        }
+    }
+
+    /// Counts a request against `config.max_requests`, false once the budget is spent
+    fn spend_request(&self) -> bool {
+        let max = self.config.max_requests.unwrap_or(usize::MAX);
+        let spent = self
+            .requests
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |requests| {
+                (requests < max).then_some(requests + 1)
+            })
+            .is_ok();
+        if !spent {
+            self.exhausted.store(true, Ordering::SeqCst);
+        }
+        spent
+    }
+
+    fn cancelled(&self) -> bool {
+        self.config
+            .cancel
+            .as_ref()
+            .is_some_and(CancelToken::is_cancelled)
+    }
+
+    /// Completes once the trace is cancelled, never without a token
+    async fn cancellation(&self) {
+        match &self.config.cancel {
+            Some(token) => token.cancelled().await,
+            None => std::future::pending().await,
+        }
+    }
+
+    /// Runs `lookup`, retrying network failures and rate limits as `config.retry` allows.
+    /// A `cached` lookup counts as a cache hit rather than a request.
+    ///
+    /// `None` if the trace is cancelled first, or the lookup would go over
+    /// `max_requests`. A lookup in flight when the trace is cancelled is dropped,
+    /// unless it completes in the same poll.
+    async fn request<T, F, Fut>(&self, cached: bool, lookup: F) -> Result<Option<T>>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = blockchain::Result<T>>,
+    {
+        let retry = self.config.retry;
+        let mut attempt = 0;
+        // Network failures so far, for the backoff
+        let mut failures = 0;
+        loop {
+            if self.cancelled() {
+                return Ok(None);
+            }
+            if cached {
+                self.cache_hits.fetch_add(1, Ordering::SeqCst);
+            } else if !self.spend_request() {
+                return Ok(None);
+            }
+            let result = tokio::select! {
+                biased;
+                result = lookup() => result,
+                _ = self.cancellation() => return Ok(None),
+            };
+            let error = match result {
+                Ok(value) => return Ok(Some(value)),
+                Err(error) => error,
+            };
+            if attempt >= retry.max_retries {
+                return Err(error.into());
+            }
+            let wait = match error.inner() {
+                BlockchainError::RateLimited { retry_after } => {
+                    // At least as long as the backend asks
+                    let wait = retry_after.unwrap_or_default().max(retry.rate_limit_wait);
+                    self.emit(|| TraceEvent::RateLimited { wait });
+                    wait
+                }
+                inner if inner.is_retryable() => {
+                    let error = match inner {
+                        BlockchainError::NetworkFailure(detail) => detail.clone(),
+                        _ => error.to_string(),
+                    };
+                    let wait = retry.backoff * 2u32.saturating_pow(failures);
+                    failures += 1;
+                    self.emit(|| TraceEvent::Retrying { error, wait });
+                    wait
+                }
+                _ => return Err(error.into()),
+            };
+            tokio::select! {
+                _ = self.clock.sleep(wait) => {}
+                _ = self.cancellation() => return Ok(None),
+            }
+            attempt += 1;
+        }
+    }
+}
+
+impl TerminalReason {
+    /// Outcome of a trace stopped for this reason with `graph`
+    fn outcome(self, graph: TraceGraph) -> TraceOutcome {
+        match self {
+            TerminalReason::Cancelled => TraceOutcome::Cancelled(graph),
+            _ => TraceOutcome::Complete(graph),
+        }
+    }
+}
+
+/// Enforces the `max_transactions` and `max_breadth` caps of a trace
+#[derive(Clone)]
+struct Budget {
+    max_transactions: usize,
+    max_breadth: Option<usize>,
+    /// Transactions added at each depth
+    per_depth: HashMap<usize, usize>,
+}
+
+impl Budget {
+    fn new(config: &TraceConfig) -> Self {
+        Self {
+            max_transactions: config.max_transactions,
+            max_breadth: config.max_breadth,
+            per_depth: HashMap::new(),
+        }
+    }
+
+    /// Why a new transaction cannot be added at `depth` to a graph of `transactions`,
+    /// if it cannot
+    fn exhausted(&self, transactions: usize, depth: usize) -> Option<TerminalReason> {
+        if transactions >= self.max_transactions {
+            return Some(TerminalReason::MaxTransactionsReached);
+        }
+        let at_depth = self.per_depth.get(&depth).copied().unwrap_or(0);
+        if self.max_breadth.is_some_and(|max| at_depth >= max) {
+            return Some(TerminalReason::MaxBreadthReached);
+        }
+        None
+    }
+
+    fn add(&mut self, graph: &mut TraceGraph, node: TraceNode) {
+        *self.per_depth.entry(node.depth).or_default() += 1;
+        graph.insert_node(node);
+    }
+}
+
+/// Lookups started ahead of the trace needing them, at most `concurrency` at a time.
+///
+/// Keys are looked up in the order they are planned, each in the span it was planned
+/// with. `start` runs the lookup of a key and yields the key back with its result.
+struct Lookahead<K, T, S, Fut> {
+    concurrency: usize,
+    start: S,
+    /// Planned keys not started yet, with their spans
+    queued: VecDeque<(K, Span)>,
+    in_flight: FuturesUnordered<Instrumented<Fut>>,
+    /// Keys planned and not taken yet
+    planned: HashSet<K>,
+    /// Results waiting to be taken
+    done: HashMap<K, Result<Option<T>>>,
+}
+
+impl<K, T, S, Fut> Lookahead<K, T, S, Fut>
+where
+    K: Copy + Eq + Hash,
+    S: Fn(K) -> Fut,
+    Fut: Future<Output = (K, Result<Option<T>>)>,
+{
+    fn new(concurrency: usize, start: S) -> Self {
+        Self {
+            concurrency,
+            start,
+            queued: VecDeque::new(),
+            in_flight: FuturesUnordered::new(),
+            planned: HashSet::new(),
+            done: HashMap::new(),
+        }
+    }
+
+    /// Queues the lookup of `key` in the span of `span`, unless it is already planned
+    fn plan(&mut self, key: K, span: impl FnOnce() -> Span) {
+        if self.planned.insert(key) {
+            self.queued.push_back((key, span()));
+        }
+    }
+
+    fn is_planned(&self, key: &K) -> bool {
+        self.planned.contains(key)
+    }
+
+    /// Result of the lookup of `key`, started now in the span of `span` if it was not
+    /// planned. Lookups of other keys make progress while it is awaited.
+    async fn take(&mut self, key: K, span: impl FnOnce() -> Span) -> Result<Option<T>> {
+        if self.planned.insert(key) {
+            self.queued.push_front((key, span()));
+        }
+        loop {
+            while self.in_flight.len() < self.concurrency
+                && let Some((next, span)) = self.queued.pop_front()
+            {
+                self.in_flight.push((self.start)(next).instrument(span));
+            }
+            if let Some(result) = self.done.remove(&key) {
+                self.planned.remove(&key);
+                return result;
+            }
+            let Some((key, result)) = self.in_flight.next().await else {
+                unreachable!("the lookup of a planned key is queued, in flight or done");
+            };
+            self.done.insert(key, result);
+        }
+    }
+}
+
+/// Span of the lookups expanding `txid`, at `depth`: the spenders of its outputs
+/// forward, the transactions its inputs spend backward. The depth 0 transaction is
+/// looked up in a hop of its own.
+fn hop(txid: Txid, depth: usize) -> Span {
+    tracing::debug_span!("trace_hop", %txid, depth)
+}
+
+/// Sum of the values of `tx`'s prevouts, if `value_of` knows all of them
+fn input_value(tx: &Transaction, value_of: impl Fn(&OutPoint) -> Option<Amount>) -> Option<Amount> {
+    tx.input.iter().try_fold(Amount::ZERO, |sum, input| {
+        sum.checked_add(value_of(&input.previous_output)?)
+    })
+}
+
+/// Node for `tx`, its outputs paying known entities labelled, its shape classified
+/// from its outpoints alone, whether it closes a Lightning channel recognized and the
+/// origin of a coinbase
+fn traced(config: &TraceConfig, tx: &Transaction, depth: usize) -> TraceNode {
+    let mut node = TraceNode::new(tx, depth);
+    node.pattern = config.pattern_classifier.classify(tx, &[]);
+    node.lightning = LightningChannel::close(tx);
+    if let Some(labels) = &config.labels {
+        node.labels = labels.outputs_of(tx);
+    }
+    node.origin = CoinbaseOrigin::from_coinbase(tx, &node.labels);
+    node
+}
+
+/// Annotations of `tx` by the configured annotators, spending `prevouts` as far as they
+/// are known
+fn annotations(
+    config: &TraceConfig,
+    graph: &TraceGraph,
+    tx: &Transaction,
+    prevouts: &[TxOut],
+    depth: usize,
+) -> BTreeMap<String, String> {
+    if config.annotators.is_empty() {
+        return BTreeMap::new();
+    }
+    let context = TraceContext {
+        prevouts,
+        depth,
+        graph,
+        labels: config.labels.as_deref(),
+    };
+    config.annotators.run(tx, &context)
+}
+
+/// Scripts of the outputs in `graph`, and `output`
+fn scripts<'a>(graph: &'a TraceGraph, output: &'a Script) -> impl Iterator<Item = &'a Script> {
+    graph
+        .edges()
+        .map(|edge| edge.script_pubkey.as_script())
+        .chain([output])
+}
+
+/// Records on the node of `txid` the role in a Lightning channel a spend of one of its
+/// outputs revealed, unless it already has one
+fn reveal(graph: &mut TraceGraph, txid: &Txid, channel: LightningChannel) {
+    mark(graph, txid, |node| {
+        if node.lightning.is_none() {
+            node.lightning = Some(channel);
+        }
+    });
+}
+
+/// Updates the node of `txid`, if it is in the graph
+fn mark(graph: &mut TraceGraph, txid: &Txid, update: impl FnOnce(&mut TraceNode)) {
+    if let Some(node) = graph.node_mut(txid) {
+        update(node);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "cache")]
+    use crate::blockchain::CachingDataSource;
+    use crate::testing::{ChaosDataSource, MockClock};
+    use crate::tracer::{
+        Annotation, BatchPolicy, BranchStrategy, CancelToken, RetryPolicy, StopCondition,
+        TraceAnnotator, TraceCheckpoint, TxPattern,
+        fixtures::{
+            Chain, Method, MockDataSource, channel, coinbase, converging, reused, script, spend,
+            tagged, windowed,
+        },
+    };
+    use bitcoin::{Address, Network, ScriptBuf, Sequence, Transaction, hashes::Hash};
+    use futures::StreamExt;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_forward_follows_chain_to_unspent_outputs() {
+        let chain = Chain::new();
+        let tracer = Tracer::new(chain.source());
+
+        let graph = tracer
+            .trace_forward(chain.root(), &TraceConfig::default())
+            .await
+            .unwrap()
+            .into_graph();
+
+        assert_eq!(graph.len(), 4);
+        for (index, tx) in chain.txs.iter().enumerate() {
+            assert_eq!(graph.node(&tx.compute_txid()).unwrap().depth, index);
+        }
+        let hop = graph.edge(&chain.root()).unwrap();
+        assert_eq!(hop.spent_by, Some(chain.txid(1)));
+        assert_eq!(hop.value, Amount::from_sat(100_000));
+        assert!(hop.address.is_some());
+
+        // Change of every hop and the end of the chain
+        let unspent: Vec<_> = graph
+            .edges()
+            .filter(|edge| edge.terminal == Some(TerminalReason::Unspent))
+            .map(|edge| edge.outpoint)
+            .collect();
+        assert_eq!(unspent.len(), 4);
+        assert!(unspent.contains(&OutPoint::new(chain.txid(3), 0)));
+        assert_eq!(graph.edges().count(), 7);
+    }
+
+    #[tokio::test]
+    async fn test_forward_stops_at_max_depth() {
+        let chain = Chain::new();
+        let tracer = Tracer::new(chain.source());
+
+        let graph = tracer
+            .trace_forward(chain.root(), &TraceConfig::default().max_depth(2))
+            .await
+            .unwrap()
+            .into_graph();
+
+        assert_eq!(graph.len(), 3);
+        assert!(!graph.contains_node(&chain.txid(3)));
+        let cut = graph.edge(&OutPoint::new(chain.txid(2), 0)).unwrap();
+        assert_eq!(cut.spent_by, None);
+        assert_eq!(cut.terminal, Some(TerminalReason::MaxDepthReached));
+        // Funding tx, root and the two outputs of hop 1; hop 2 outputs are not looked up
+        assert_eq!(tracer.source().calls(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_forward_caps_transactions() {
+        let chain = Chain::new();
+        let tracer = Tracer::new(chain.source());
+
+        let graph = tracer
+            .trace_forward(chain.root(), &TraceConfig::default().max_transactions(2))
+            .await
+            .unwrap()
+            .into_graph();
+
+        assert_eq!(graph.len(), 2);
+        assert_eq!(
+            graph
+                .edge(&OutPoint::new(chain.txid(1), 0))
+                .unwrap()
+                .terminal,
+            Some(TerminalReason::MaxTransactionsReached)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_forward_records_outputs_not_followed() {
+        let chain = Chain::new();
+        let tracer = Tracer::new(chain.source());
+        let config =
+            TraceConfig::default().branch(BranchStrategy::ValueWeighted { min_share: 0.5 });
+
+        let graph = tracer
+            .trace_forward(chain.root(), &config)
+            .await
+            .unwrap()
+            .into_graph();
+
+        assert_eq!(graph.len(), 4);
+        let change = graph.edge(&OutPoint::new(chain.txid(1), 1)).unwrap();
+        assert_eq!(change.terminal, Some(TerminalReason::NotFollowed));
+        // Only the followed outputs were looked up: root plus output 0 of each hop
+        assert_eq!(tracer.source().calls(), 1 + 4);
+    }
+
+    #[tokio::test]
+    async fn test_batch_payouts_follow_the_matching_output() {
+        // funding -> batch (12 outputs, 98_000 sats on output 5) -> sweep of 6 inputs
+        let funding = spend(0, &[], &[100_000]);
+        let root = OutPoint::new(funding.compute_txid(), 0);
+        let mut values = vec![150; 12];
+        values[5] = 98_000;
+        let batch = spend(1, &[root], &values);
+        let mut inputs = vec![OutPoint::new(batch.compute_txid(), 5)];
+        inputs.extend((0..5).map(|vout| OutPoint::new(Txid::from_byte_array([9; 32]), vout)));
+        let sweep = spend(2, &inputs, &[400_000]);
+        let source = MockDataSource::new(&[funding, batch.clone(), sweep.clone()]);
+        let tracer = Tracer::new(source);
+
+        let graph = tracer
+            .trace_forward(root, &TraceConfig::default())
+            .await
+            .unwrap()
+            .into_graph();
+        let pattern = |txid| graph.node(&txid).unwrap().pattern;
+        assert_eq!(
+            pattern(batch.compute_txid()),
+            TxPattern::BatchPayout { outputs: 12 }
+        );
+        assert_eq!(
+            pattern(sweep.compute_txid()),
+            TxPattern::Sweep { inputs: 6 }
+        );
+        assert_eq!(
+            graph
+                .edges()
+                .filter(|edge| edge.terminal == Some(TerminalReason::Unspent))
+                .count(),
+            12
+        );
+
+        let config = TraceConfig::default()
+            .batch_policy(BatchPolicy::FollowMatchingValue { tolerance: 0.02 });
+        let graph = tracer
+            .trace_forward(root, &config)
+            .await
+            .unwrap()
+            .into_graph();
+        let not_followed: Vec<_> = graph
+            .edges()
+            .filter(|edge| edge.terminal == Some(TerminalReason::NotFollowed))
+            .map(|edge| edge.outpoint.vout)
+            .collect();
+        assert_eq!(not_followed, [0, 1, 2, 3, 4, 6, 7, 8, 9, 10, 11]);
+        assert!(graph.contains_node(&sweep.compute_txid()));
+
+        // Nothing within 1% of the traced 100_000 sats: no output is followed
+        let config = TraceConfig::default()
+            .batch_policy(BatchPolicy::FollowMatchingValue { tolerance: 0.01 });
+        let graph = tracer
+            .trace_forward(root, &config)
+            .await
+            .unwrap()
+            .into_graph();
+        assert_eq!(graph.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_outputs_below_min_value_are_unexpanded_leaves() {
+        let chain = Chain::new();
+        let tracer = Tracer::new(chain.source());
+        let config = TraceConfig::default().min_output_value(Amount::from_sat(9_001));
+
+        let report = tracer
+            .trace_forward_with_report(chain.root(), &config)
+            .await
+            .unwrap();
+
+        let graph = report.graph();
+        assert_eq!(graph.len(), 4);
+        for hop in 1..=3 {
+            let dust = graph.edge(&OutPoint::new(chain.txid(hop), 1)).unwrap();
+            assert_eq!(dust.terminal, Some(TerminalReason::BelowMinValue));
+            assert_eq!(dust.spent_by, None);
+        }
+        assert_eq!(report.ignored_outputs, 3);
+        assert_eq!(report.ignored_value, Amount::from_sat(27_000));
+        // The funding transaction and output 0 of each transaction: no lookup for dust
+        assert_eq!(tracer.source().calls(), 1 + 4);
+
+        // The threshold is exclusive
+        let config = TraceConfig::default().min_output_value(Amount::from_sat(9_000));
+        let report = Tracer::new(chain.source())
+            .trace_forward_with_report(chain.root(), &config)
+            .await
+            .unwrap();
+        assert_eq!(report.ignored_outputs, 0);
+        assert_eq!(report.ignored_value, Amount::ZERO);
+        assert_ne!(config.fingerprint(), TraceConfig::default().fingerprint());
+    }
+
+    #[tokio::test]
+    async fn test_branch_leaving_the_window_is_an_out_of_window_leaf() {
+        let (source, [funding, split, left, right, onward]) = windowed();
+        let tracer = Tracer::new(source);
+        let root = OutPoint::new(funding.compute_txid(), 0);
+
+        let graph = tracer
+            .trace_forward(root, &TraceConfig::default().max_block_height(150))
+            .await
+            .unwrap()
+            .into_graph();
+
+        assert_eq!(graph.len(), 4);
+        assert!(!graph.contains_node(&right.compute_txid()));
+        let exit = graph.edge(&OutPoint::new(split.compute_txid(), 1)).unwrap();
+        assert_eq!(exit.terminal, Some(TerminalReason::OutOfWindow));
+        assert_eq!(exit.spent_by, None);
+        assert_eq!(graph.node(&left.compute_txid()).unwrap().height, Some(101));
+        assert_eq!(
+            graph.node(&onward.compute_txid()).unwrap().height,
+            Some(102)
+        );
+        // The root is not looked up, no header is needed without a time bound
+        assert_eq!(graph.node(&funding.compute_txid()).unwrap().height, None);
+        assert_eq!(graph.node(&left.compute_txid()).unwrap().timestamp, None);
+        assert_eq!(tracer.source().calls_to(Method::GetBlockHeader), 0);
+
+        // Without a window, no status is looked up
+        let before = tracer.source().calls();
+        let graph = tracer
+            .trace_forward(root, &TraceConfig::default())
+            .await
+            .unwrap()
+            .into_graph();
+        assert_eq!(graph.len(), 5);
+        assert_eq!(graph.node(&left.compute_txid()).unwrap().height, None);
+        assert_eq!(tracer.source().calls() - before, 1 + 6);
+    }
+
+    #[tokio::test]
+    async fn test_unconfirmed_spenders_are_in_the_window_unless_excluded() {
+        let (mut source, [funding, _, left, _, onward]) = windowed();
+        source.confirm(onward.compute_txid(), TxStatus::unconfirmed());
+        let tracer = Tracer::new(source);
+        let root = OutPoint::new(funding.compute_txid(), 0);
+        let config = TraceConfig::default().max_block_height(150);
+
+        let graph = tracer
+            .trace_forward(root, &config)
+            .await
+            .unwrap()
+            .into_graph();
+        assert!(graph.contains_node(&onward.compute_txid()));
+        assert_eq!(graph.node(&onward.compute_txid()).unwrap().height, None);
+
+        let graph = tracer
+            .trace_forward(root, &config.include_unconfirmed(false))
+            .await
+            .unwrap()
+            .into_graph();
+        assert!(!graph.contains_node(&onward.compute_txid()));
+        assert_eq!(
+            graph
+                .edge(&OutPoint::new(left.compute_txid(), 0))
+                .unwrap()
+                .terminal,
+            Some(TerminalReason::OutOfWindow)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_block_times_are_looked_up_once_per_block() {
+        let (source, [funding, split, left, right, _]) = windowed();
+        let tracer = Tracer::new(source);
+
+        let graph = tracer
+            .trace_forward(
+                OutPoint::new(funding.compute_txid(), 0),
+                &TraceConfig::default().max_timestamp(150 * 600),
+            )
+            .await
+            .unwrap()
+            .into_graph();
+
+        assert!(!graph.contains_node(&right.compute_txid()));
+        assert_eq!(
+            graph.node(&left.compute_txid()).unwrap().timestamp,
+            Some(101 * 600)
+        );
+        // split and left share a block: three headers for four statuses
+        assert_eq!(
+            graph.node(&split.compute_txid()).unwrap().timestamp,
+            Some(101 * 600)
+        );
+        assert_eq!(tracer.source().calls_to(Method::GetBlockHeader), 3);
+    }
+
+    #[tokio::test]
+    async fn test_backward_parents_out_of_the_window_are_not_expanded() {
+        let (source, [funding, split, left, _, onward]) = windowed();
+        let tracer = Tracer::new(source);
+
+        let graph = tracer
+            .trace_backward(
+                onward.compute_txid(),
+                &TraceConfig::default().min_block_height(101),
+            )
+            .await
+            .unwrap()
+            .into_graph();
+
+        assert_eq!(graph.len(), 4);
+        assert!(!graph.node(&left.compute_txid()).unwrap().frontier);
+        assert!(!graph.node(&split.compute_txid()).unwrap().frontier);
+        let funding = graph.node(&funding.compute_txid()).unwrap();
+        assert_eq!(funding.height, Some(100));
+        assert!(funding.frontier);
+    }
+
+    #[tokio::test]
+    async fn test_op_return_outputs_are_never_followed() {
+        let [funding, tagged] = tagged();
+        let tracer = Tracer::new(MockDataSource::new(&[funding.clone(), tagged.clone()]));
+
+        let graph = tracer
+            .trace_forward(
+                OutPoint::new(funding.compute_txid(), 0),
+                &TraceConfig::default(),
+            )
+            .await
+            .unwrap()
+            .into_graph();
+
+        let txid = tagged.compute_txid();
+        let carrier = graph.edge(&OutPoint::new(txid, 1)).unwrap();
+        assert_eq!(carrier.terminal, Some(TerminalReason::DataCarrier));
+        assert_eq!(carrier.address, None);
+        let node = graph.node(&txid).unwrap();
+        assert_eq!(node.data_carriers.len(), 1);
+        assert_eq!(node.data_carriers[0].vout, 1);
+        assert_eq!(
+            node.data_carriers[0].preview.as_deref(),
+            Some("charley loves heidi")
+        );
+        // Funding, its spender, and the spender of the payment: nothing for the tag
+        assert_eq!(tracer.source().calls(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_forward_stops_at_lightning_channel_opens() {
+        let txs = channel();
+        let [mined, funding, close, sweep] = &txs;
+        let tracer = Tracer::new(MockDataSource::new(&txs));
+        let root = OutPoint::new(mined.compute_txid(), 0);
+        let trace = |config: TraceConfig| {
+            let tracer = &tracer;
+            async move {
+                tracer
+                    .trace_forward(root, &config)
+                    .await
+                    .unwrap()
+                    .into_graph()
+            }
+        };
+
+        let graph = trace(TraceConfig::default()).await;
+        let opened = graph.node(&funding.compute_txid()).unwrap();
+        assert!(matches!(
+            &opened.lightning,
+            Some(LightningChannel::Open { vout: 0, funding_pubkeys }) if funding_pubkeys.len() == 2
+        ));
+        let into_channel = graph
+            .edge(&OutPoint::new(funding.compute_txid(), 0))
+            .unwrap();
+        assert_eq!(into_channel.spent_by, None);
+        assert_eq!(
+            into_channel.terminal,
+            Some(TerminalReason::LightningChannel)
+        );
+        assert!(!graph.contains_node(&close.compute_txid()));
+
+        let graph = trace(TraceConfig::default().lightning_policy(LightningPolicy::Continue)).await;
+        assert_eq!(graph.len(), 4);
+        assert!(matches!(
+            graph.node(&funding.compute_txid()).unwrap().lightning,
+            Some(LightningChannel::Open { .. })
+        ));
+        let closed = graph.node(&close.compute_txid()).unwrap();
+        assert!(matches!(
+            &closed.lightning,
+            Some(LightningChannel::Close { forced: true, funding_pubkeys }) if funding_pubkeys.len() == 2
+        ));
+        assert_eq!(graph.node(&sweep.compute_txid()).unwrap().lightning, None);
+        // The anchors and `to_remote` are unspent
+        assert_eq!(
+            graph
+                .edges()
+                .filter(|edge| edge.from() == close.compute_txid()
+                    && edge.terminal == Some(TerminalReason::Unspent))
+                .count(),
+            3
+        );
+    }
+
+    #[tokio::test]
+    async fn test_backward_recognizes_the_channel_behind_a_close() {
+        let txs = channel();
+        let [_, funding, close, sweep] = &txs;
+        let tracer = Tracer::new(MockDataSource::new(&txs));
+
+        let graph = tracer
+            .trace_backward(sweep.compute_txid(), &TraceConfig::default())
+            .await
+            .unwrap()
+            .into_graph();
+
+        assert_eq!(graph.len(), 4);
+        assert!(matches!(
+            graph.node(&close.compute_txid()).unwrap().lightning,
+            Some(LightningChannel::Close { forced: true, .. })
+        ));
+        assert!(matches!(
+            graph.node(&funding.compute_txid()).unwrap().lightning,
+            Some(LightningChannel::Open { vout: 0, .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_to_local_sweeps_reveal_forced_closes() {
+        // Without its anchors and funding witness, the commitment is only recognized
+        // by the sweep of its `to_local` output
+        let mut txs = channel();
+        txs[2].input[0].witness.clear();
+        txs[2].output.truncate(2);
+        let close = txs[2].compute_txid();
+        txs[3].input[0].previous_output = OutPoint::new(close, 0);
+        let tracer = Tracer::new(MockDataSource::new(&txs));
+        let forced = Some(LightningChannel::Close {
+            forced: true,
+            funding_pubkeys: Vec::new(),
+        });
+
+        let graph = tracer
+            .trace_forward(OutPoint::new(close, 0), &TraceConfig::default())
+            .await
+            .unwrap()
+            .into_graph();
+        assert_eq!(graph.node(&close).unwrap().lightning, forced);
+        let graph = tracer
+            .trace_backward(txs[3].compute_txid(), &TraceConfig::default())
+            .await
+            .unwrap()
+            .into_graph();
+        assert_eq!(graph.node(&close).unwrap().lightning, forced);
+        // The funding output is not known to be a channel: its spender, `close`, does
+        // not say
+        assert_eq!(graph.node(&txs[1].compute_txid()).unwrap().lightning, None);
+    }
+
+    #[tokio::test]
+    async fn test_forward_links_converging_paths_once() {
+        let funding = spend(0, &[], &[100_000]);
+        let root = OutPoint::new(funding.compute_txid(), 0);
+        let split = spend(1, &[root], &[50_000, 49_000]);
+        let merge = spend(
+            2,
+            &[
+                OutPoint::new(split.compute_txid(), 0),
+                OutPoint::new(split.compute_txid(), 1),
+            ],
+            &[98_000],
+        );
+        let tracer = Tracer::new(MockDataSource::new(&[
+            funding,
+            split.clone(),
+            merge.clone(),
+        ]));
+
+        let graph = tracer
+            .trace_forward(root, &TraceConfig::default())
+            .await
+            .unwrap()
+            .into_graph();
+
+        assert_eq!(graph.len(), 3);
+        for vout in 0..2 {
+            let edge = graph
+                .edge(&OutPoint::new(split.compute_txid(), vout))
+                .unwrap();
+            assert_eq!(edge.spent_by, Some(merge.compute_txid()));
+        }
+        // The merge is expanded once: a single lookup for its only output
+        assert_eq!(tracer.source().calls(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_forward_breadth_cap_truncates() {
+        let funding = spend(0, &[], &[100_000]);
+        let root = OutPoint::new(funding.compute_txid(), 0);
+        let fan_out = spend(1, &[root], &[30_000, 30_000, 30_000]);
+        let spenders: Vec<_> = (0..3)
+            .map(|vout| {
+                spend(
+                    2 + vout,
+                    &[OutPoint::new(fan_out.compute_txid(), vout)],
+                    &[1],
+                )
+            })
+            .collect();
+        let mut source = MockDataSource::new(&[funding, fan_out.clone()]);
+        for tx in spenders {
+            source.add(tx);
+        }
+        let tracer = Tracer::new(source);
+
+        let graph = tracer
+            .trace_forward(root, &TraceConfig::default().max_breadth(2))
+            .await
+            .unwrap()
+            .into_graph();
+
+        assert_eq!(graph.len(), 4);
+        assert!(graph.node(&fan_out.compute_txid()).unwrap().truncated);
+        assert_eq!(
+            graph
+                .edge(&OutPoint::new(fan_out.compute_txid(), 2))
+                .unwrap()
+                .terminal,
+            Some(TerminalReason::MaxBreadthReached)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_forward_rejects_missing_output() {
+        let chain = Chain::new();
+        let tracer = Tracer::new(chain.source());
+
+        let result = tracer
+            .trace_forward(OutPoint::new(chain.txid(0), 5), &TraceConfig::default())
+            .await;
+
+        assert!(matches!(result, Err(TracerError::InvalidInput(_))));
+    }
+
+    /// `funding -> hop1 -> hop2 -> hop3 -> hop4` along output 0, where output 1 of hop 3
+    /// pays the target script
+    fn chain_to_target() -> (Vec<Transaction>, ScriptBuf) {
+        let target = script(42);
+        let mut txs = vec![spend(0, &[], &[100_000])];
+        for hop in 1..=4u32 {
+            let previous = OutPoint::new(txs.last().unwrap().compute_txid(), 0);
+            let mut tx = spend(hop, &[previous], &[90_000 - 10_000 * u64::from(hop), 9_000]);
+            if hop == 3 {
+                tx.output[1].script_pubkey = target.clone();
+            }
+            txs.push(tx);
+        }
+        (txs, target)
+    }
+
+    #[tokio::test]
+    async fn test_forward_stops_branch_at_target() {
+        let (txs, target) = chain_to_target();
+        let tracer = Tracer::new(MockDataSource::new(&txs));
+        // Upper case bech32 is the same script as the lower case the chain pays
+        let address = Address::from_script(&target, Network::Bitcoin).unwrap();
+        let upper: Address = address
+            .to_string()
+            .to_uppercase()
+            .parse::<Address<_>>()
+            .unwrap()
+            .assume_checked();
+        let config = TraceConfig::default().stop(StopCondition::new().target_address(&upper));
+
+        let graph = tracer
+            .trace_forward(OutPoint::new(txs[0].compute_txid(), 0), &config)
+            .await
+            .unwrap()
+            .into_graph();
+
+        // The branch to the target stops, the rest of the chain is still traced
+        assert_eq!(graph.len(), 5);
+        let reached: Vec<_> = graph.targets_reached().collect();
+        assert_eq!(reached.len(), 1);
+        assert_eq!(reached[0].outpoint, OutPoint::new(txs[3].compute_txid(), 1));
+        assert_eq!(
+            reached[0].terminal,
+            Some(TerminalReason::ReachedTarget(target))
+        );
+        assert_eq!(graph.node(&reached[0].from()).unwrap().depth, 3);
+    }
+
+    #[tokio::test]
+    async fn test_forward_early_exit_returns_path_to_target() {
+        let (txs, target) = chain_to_target();
+        let tracer = Tracer::new(MockDataSource::new(&txs));
+        // Not selected by the branch strategy, but still checked against the targets
+        let config = TraceConfig::default()
+            .branch(BranchStrategy::ValueWeighted { min_share: 0.5 })
+            .stop(StopCondition::new().target_script(target).early_exit());
+
+        let graph = tracer
+            .trace_forward(OutPoint::new(txs[0].compute_txid(), 0), &config)
+            .await
+            .unwrap()
+            .into_graph();
+
+        assert_eq!(graph.len(), 4);
+        assert!(!graph.contains_node(&txs[4].compute_txid()));
+        assert_eq!(graph.targets_reached().count(), 1);
+        // Only the path: three hops into hop 3, and its output to the target
+        assert_eq!(graph.edges().count(), 4);
+        assert_eq!(
+            graph.path_to(&OutPoint::new(txs[3].compute_txid(), 1)),
+            graph
+        );
+    }
+
+    /// Funding output of 100_000 sats, then three levels of transactions each splitting
+    /// what they receive 50% / 30% / 20%; the outputs of the last level are unspent
+    fn split_tree() -> (OutPoint, MockDataSource) {
+        let funding = spend(0, &[], &[100_000]);
+        let root = OutPoint::new(funding.compute_txid(), 0);
+        let mut source = MockDataSource::new(&[funding]);
+        let mut level = vec![(root, 100_000)];
+        let mut tag = 1;
+        for _ in 0..3 {
+            let mut next = Vec::new();
+            for (outpoint, value) in level {
+                let split = spend(tag, &[outpoint], &[value / 2, value * 3 / 10, value / 5]);
+                tag += 1;
+                for (vout, out) in split.output.iter().enumerate() {
+                    next.push((
+                        OutPoint::new(split.compute_txid(), vout as u32),
+                        out.value.to_sat(),
+                    ));
+                }
+                source.add(split);
+            }
+            level = next;
+        }
+        (root, source)
+    }
+
+    #[tokio::test]
+    async fn test_branch_strategies_prune_the_tree() {
+        let (root, source) = split_tree();
+        let tracer = Tracer::new(source);
+
+        for (branch, expected) in [
+            (BranchStrategy::AllOutputs, 1 + 1 + 3 + 9),
+            (BranchStrategy::LargestOutput, 1 + 1 + 1 + 1),
+            (BranchStrategy::TopKByValue(2), 1 + 1 + 2 + 4),
+            // 50k, 30k and 20k, then only the 25k of the largest
+            (
+                BranchStrategy::AboveThreshold(Amount::from_sat(20_000)),
+                1 + 1 + 3 + 1,
+            ),
+        ] {
+            let graph = tracer
+                .trace_forward(root, &TraceConfig::default().branch(branch))
+                .await
+                .unwrap()
+                .into_graph();
+
+            assert_eq!(graph.len(), expected, "{:?}", branch);
+            // Every output of a traced split is in the graph, followed or not
+            assert_eq!(
+                graph.edges().count(),
+                1 + 3 * (expected - 1),
+                "{:?}",
+                branch
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_skipped_outputs_still_reach_targets() {
+        let (root, source) = split_tree();
+        let tracer = Tracer::new(source);
+        // Output 2 of every split pays script(2), the smallest output
+        let config = TraceConfig::default()
+            .branch(BranchStrategy::LargestOutput)
+            .stop(StopCondition::new().target_script(script(2)));
+
+        let graph = tracer
+            .trace_forward(root, &config)
+            .await
+            .unwrap()
+            .into_graph();
+
+        assert_eq!(graph.len(), 4);
+        assert_eq!(graph.targets_reached().count(), 3);
+        let not_followed = graph
+            .edges()
+            .filter(|edge| edge.terminal == Some(TerminalReason::NotFollowed))
+            .count();
+        assert_eq!(not_followed, 3);
+    }
+
+    #[tokio::test]
+    async fn test_multi_source_trace_tags_seeds() {
+        let txs = converging();
+        let txid = |index: usize| txs[index].compute_txid();
+        let seeds = [OutPoint::new(txid(0), 0), OutPoint::new(txid(1), 0)];
+        let tracer = Tracer::new(MockDataSource::new(&txs));
+
+        let graph = tracer
+            .trace_forward_multi(&seeds, &TraceConfig::default())
+            .await
+            .unwrap()
+            .into_graph();
+
+        assert_eq!(graph.len(), 5);
+        let seeds_of = |index| Vec::from_iter(graph.node(&txid(index)).unwrap().seeds.clone());
+        assert_eq!(seeds_of(0), [0]);
+        assert_eq!(seeds_of(2), [0]);
+        assert_eq!(seeds_of(3), [1]);
+        assert_eq!(seeds_of(4), [0, 1]);
+        let merge = graph.node(&txid(4)).unwrap();
+        assert_eq!(merge.depth, 2);
+        assert_eq!(merge.input_value, Some(Amount::from_sat(88_000)));
+        assert_eq!(
+            graph
+                .convergences()
+                .map(|node| node.txid)
+                .collect::<Vec<_>>(),
+            [txid(4)]
+        );
+        // Both seeds' transactions, the spenders of 4 outputs, and the spender of the
+        // merge's output looked up once
+        assert_eq!(tracer.source().calls(), 7);
+
+        // A repeated seed is traced once; single traces are not tagged
+        let repeated = tracer
+            .trace_forward_multi(&[seeds[0], seeds[0]], &TraceConfig::default())
+            .await
+            .unwrap()
+            .into_graph();
+        assert_eq!(repeated.len(), 3);
+        assert!(repeated.nodes().all(|node| node.seeds.is_empty()));
+        assert!(matches!(
+            tracer
+                .trace_forward_multi(&[], &TraceConfig::default())
+                .await,
+            Err(TracerError::InvalidInput(_))
+        ));
+    }
+
+    /// `coinbase -> a -> b`, where `a` also spends a second coinbase
+    fn ancestry() -> Vec<bitcoin::Transaction> {
+        let cb1 = coinbase(1, &[50_000]);
+        let cb2 = coinbase(2, &[25_000]);
+        let a = spend(
+            3,
+            &[
+                OutPoint::new(cb1.compute_txid(), 0),
+                OutPoint::new(cb2.compute_txid(), 0),
+            ],
+            &[74_000],
+        );
+        let b = spend(4, &[OutPoint::new(a.compute_txid(), 0)], &[73_000]);
+        vec![cb1, cb2, a, b]
+    }
+
+    #[tokio::test]
+    async fn test_backward_marks_coinbase_origins() {
+        let txs = ancestry();
+        let tracer = Tracer::new(MockDataSource::new(&txs));
+
+        let graph = tracer
+            .trace_backward(txs[3].compute_txid(), &TraceConfig::default())
+            .await
+            .unwrap()
+            .into_graph();
+
+        assert_eq!(graph.len(), 4);
+        let origins: Vec<_> = graph.nodes().filter(|node| node.coinbase).collect();
+        assert_eq!(origins.len(), 2);
+        assert!(origins.iter().all(|node| node.depth == 2));
+        assert!(graph.nodes().all(|node| !node.truncated));
+
+        let edge = graph
+            .edge(&OutPoint::new(txs[0].compute_txid(), 0))
+            .unwrap();
+        assert_eq!(edge.spent_by, Some(txs[2].compute_txid()));
+        assert_eq!(edge.value, Amount::from_sat(50_000));
+        assert_eq!(graph.edges().count(), 3);
+        // Every transaction fetched once, coinbases are not expanded
+        assert_eq!(tracer.source().calls(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_backward_report_resolves_the_mining_pools() {
+        let mut txs = ancestry();
+        // cb1 tagged by Foundry USA at height 812345, cb2 untagged at 830000
+        txs[0].input[0].script_sig =
+            ScriptBuf::from_hex("0339650c0c2f466f756e64727920555341").unwrap();
+        txs[1].input[0].script_sig = ScriptBuf::from_hex("0330aa0c").unwrap();
+        txs[2].input[0].previous_output.txid = txs[0].compute_txid();
+        txs[2].input[1].previous_output.txid = txs[1].compute_txid();
+        txs[3].input[0].previous_output.txid = txs[2].compute_txid();
+        let tracer = Tracer::new(MockDataSource::new(&txs));
+
+        let report = tracer
+            .trace_backward_with_report(txs[3].compute_txid(), &TraceConfig::default())
+            .await
+            .unwrap();
+
+        let origin = report.graph().node(&txs[0].compute_txid()).unwrap();
+        let origin = origin.origin.as_ref().unwrap();
+        assert_eq!(origin.height, Some(812_345));
+        assert_eq!(origin.pool.as_deref(), Some("Foundry USA"));
+        assert_eq!(
+            report.graph().node(&txs[2].compute_txid()).unwrap().origin,
+            None
+        );
+        assert_eq!(
+            report.provenance().unwrap().to_string(),
+            "Ancestry resolves to 67% Foundry USA-mined coins at height 812345\n  \
+             33% coins of unknown pools at height 830000\n"
+        );
+    }
+
+    /// Records how many prevouts and which depth the trace knew a transaction at
+    struct Known;
+
+    impl TraceAnnotator for Known {
+        fn name(&self) -> &str {
+            "known"
+        }
+
+        fn annotate(&self, _tx: &Transaction, ctx: &TraceContext) -> Vec<Annotation> {
+            vec![
+                Annotation::new("prevouts", ctx.prevouts.len().to_string()),
+                Annotation::new("depth", ctx.depth.to_string()),
+            ]
+        }
+    }
+
+    struct Panicking;
+
+    impl TraceAnnotator for Panicking {
+        fn name(&self) -> &str {
+            "broken"
+        }
+
+        fn annotate(&self, _tx: &Transaction, _ctx: &TraceContext) -> Vec<Annotation> {
+            panic!("annotator bug")
+        }
+    }
+
+    #[tokio::test]
+    async fn test_annotators_annotate_every_node() {
+        let chain = Chain::new();
+        let config = TraceConfig::default().annotators(vec![Box::new(Known), Box::new(Panicking)]);
+
+        let graph = Tracer::new(chain.source())
+            .trace_forward(chain.root(), &config)
+            .await
+            .unwrap()
+            .into_graph();
+
+        // The panicking annotator stops neither the trace nor the others
+        assert_eq!(graph.len(), 4);
+        for (index, tx) in chain.txs.iter().enumerate() {
+            let annotations = &graph.node(&tx.compute_txid()).unwrap().annotations;
+            assert_eq!(annotations["known.depth"], index.to_string());
+            assert_eq!(
+                annotations["known.prevouts"],
+                usize::from(index > 0).to_string()
+            );
+            assert_eq!(annotations["broken.error"], "panicked");
+        }
+
+        let graph = Tracer::new(chain.source())
+            .trace_forward(chain.root(), &TraceConfig::default())
+            .await
+            .unwrap()
+            .into_graph();
+        assert!(graph.nodes().all(|node| node.annotations.is_empty()));
+    }
+
+    #[tokio::test]
+    async fn test_backward_annotates_expanded_nodes_with_their_prevouts() {
+        let txs = ancestry();
+        let config = TraceConfig::default().annotators(vec![Box::new(Known)]);
+
+        let graph = Tracer::new(MockDataSource::new(&txs))
+            .trace_backward(txs[3].compute_txid(), &config)
+            .await
+            .unwrap()
+            .into_graph();
+
+        let prevouts = |tx: &Transaction| {
+            graph.node(&tx.compute_txid()).unwrap().annotations["known.prevouts"].clone()
+        };
+        assert_eq!(prevouts(&txs[3]), "1");
+        assert_eq!(prevouts(&txs[2]), "2");
+        // Coinbases spend nothing
+        assert_eq!(prevouts(&txs[0]), "0");
+        assert_eq!(
+            graph.node(&txs[0].compute_txid()).unwrap().annotations["known.depth"],
+            "2"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_forward_skips_what_an_earlier_trace_expanded() {
+        let chain = Chain::new();
+        let tracer = Tracer::new(chain.source());
+        let config = TraceConfig::default();
+        let first = tracer
+            .trace_forward(chain.root(), &config)
+            .await
+            .unwrap()
+            .into_graph();
+        let mut visited = VisitedSet::exact();
+        assert_eq!(visited.insert_graph(&first, Direction::Forward, &config), 4);
+        let calls = tracer.source().calls();
+
+        let again = config.clone().visited(Arc::new(visited.clone()));
+        let report = tracer
+            .trace_forward_with_report(chain.root(), &again)
+            .await
+            .unwrap();
+        // The root's spender was expanded last time
+        assert_eq!(report.skipped_expansions, 1);
+        assert_eq!(report.graph().len(), 1);
+        assert_eq!(
+            report.graph().edge(&chain.root()).unwrap().terminal,
+            Some(TerminalReason::Visited)
+        );
+        assert_eq!(tracer.source().calls() - calls, 2);
+
+        // Expanded under another branch strategy, which follows other outputs
+        let other = config
+            .branch(BranchStrategy::LargestOutput)
+            .visited(Arc::new(visited));
+        let report = tracer
+            .trace_forward_with_report(chain.root(), &other)
+            .await
+            .unwrap();
+        assert_eq!(report.skipped_expansions, 0);
+        assert_eq!(report.graph().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_backward_leaves_visited_parents_on_the_frontier() {
+        let txs = ancestry();
+        let tracer = Tracer::new(MockDataSource::new(&txs));
+        let config = TraceConfig::default();
+        let mut visited = VisitedSet::exact();
+        visited.insert(VisitedKey::new(
+            txs[2].compute_txid(),
+            Direction::Backward,
+            &config,
+        ));
+        // Expanded forward, which says nothing of its inputs
+        visited.insert(VisitedKey::new(
+            txs[3].compute_txid(),
+            Direction::Forward,
+            &config,
+        ));
+
+        let report = tracer
+            .trace_backward_with_report(txs[3].compute_txid(), &config.visited(Arc::new(visited)))
+            .await
+            .unwrap();
+
+        assert_eq!(report.skipped_expansions, 1);
+        let graph = report.graph();
+        assert_eq!(graph.len(), 2);
+        assert!(graph.node(&txs[2].compute_txid()).unwrap().frontier);
+        assert!(!graph.node(&txs[3].compute_txid()).unwrap().frontier);
+        // The coinbases were never fetched
+        assert_eq!(tracer.source().calls(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_false_positives_only_skip_work() {
+        let txs = reused();
+        let tracer = Tracer::new(MockDataSource::new(&txs));
+        let root = OutPoint::new(txs[0].compute_txid(), 0);
+        let config = TraceConfig::default();
+        let full = tracer
+            .trace_forward(root, &config)
+            .await
+            .unwrap()
+            .into_graph();
+
+        // A tiny filter saturated with transactions of no trace: nearly every lookup
+        // is a false positive
+        let mut visited = VisitedSet::approximate(1, 0.5).unwrap();
+        for tag in 1_000..1_200 {
+            let txid = spend(tag, &[OutPoint::null()], &[1]).compute_txid();
+            visited.insert(VisitedKey::new(txid, Direction::Forward, &config));
+        }
+        let report = tracer
+            .trace_forward_with_report(root, &config.clone().visited(Arc::new(visited)))
+            .await
+            .unwrap();
+
+        assert!(report.skipped_expansions > 0);
+        let graph = report.graph();
+        assert!(graph.len() < full.len());
+        assert!(graph.nodes().all(|node| full.contains_node(&node.txid)));
+        for edge in graph.edges() {
+            let truth = full.edge(&edge.outpoint).unwrap();
+            assert_eq!(edge.value, truth.value);
+            match &edge.terminal {
+                Some(TerminalReason::Visited) => assert!(truth.spent_by.is_some()),
+                _ => assert_eq!(edge.spent_by, truth.spent_by),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_nodes_carry_fees() {
+        let chain = Chain::new();
+        let graph = Tracer::new(chain.source())
+            .trace_forward(chain.root(), &TraceConfig::default())
+            .await
+            .unwrap()
+            .into_graph();
+
+        // The funding transaction's own inputs are not traced
+        assert_eq!(graph.node(&chain.txid(0)).unwrap().fee, None);
+        for hop in 1..=3 {
+            let node = graph.node(&chain.txid(hop)).unwrap();
+            assert_eq!(node.fee, Some(Amount::from_sat(1_000)));
+            assert_eq!(node.vsize, chain.txs[hop].vsize());
+            assert!(node.feerate().unwrap() > 0.0);
+        }
+
+        let txs = ancestry();
+        let graph = Tracer::new(MockDataSource::new(&txs))
+            .trace_backward(txs[3].compute_txid(), &TraceConfig::default())
+            .await
+            .unwrap()
+            .into_graph();
+        for tx in &txs[2..] {
+            let node = graph.node(&tx.compute_txid()).unwrap();
+            assert_eq!(node.fee, Some(Amount::from_sat(1_000)));
+        }
+        assert_eq!(
+            graph.node(&txs[0].compute_txid()).unwrap().fee,
+            Some(Amount::ZERO)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_backward_stops_at_max_depth() {
+        let txs = ancestry();
+        let tracer = Tracer::new(MockDataSource::new(&txs));
+
+        let graph = tracer
+            .trace_backward(txs[3].compute_txid(), &TraceConfig::default().max_depth(1))
+            .await
+            .unwrap()
+            .into_graph();
+
+        assert_eq!(graph.len(), 2);
+        assert!(graph.nodes().all(|node| !node.coinbase));
+    }
+
+    #[tokio::test]
+    async fn test_backward_caps_record_truncation() {
+        let parents: Vec<_> = (0..5).map(|n| coinbase(n, &[10_000])).collect();
+        let inputs: Vec<_> = parents
+            .iter()
+            .map(|tx| OutPoint::new(tx.compute_txid(), 0))
+            .collect();
+        let consolidation = spend(10, &inputs, &[49_000]);
+        let mut txs = parents.clone();
+        txs.push(consolidation.clone());
+        let tracer = Tracer::new(MockDataSource::new(&txs));
+
+        for config in [
+            TraceConfig::default().max_breadth(2),
+            TraceConfig::default().max_transactions(3),
+        ] {
+            let graph = tracer
+                .trace_backward(consolidation.compute_txid(), &config)
+                .await
+                .unwrap()
+                .into_graph();
+
+            assert_eq!(graph.len(), 3);
+            assert_eq!(graph.edges().count(), 2);
+            assert!(graph.node(&consolidation.compute_txid()).unwrap().truncated);
+        }
+    }
+
+    /// `MockDataSource` misbehaving on request
+    #[derive(Default)]
+    struct Faulty {
+        inner: MockDataSource,
+        /// Errors returned by the first spender lookups, in order
+        errors: std::sync::Mutex<VecDeque<BlockchainError>>,
+        /// Token cancelled once the inner source served that many calls
+        cancel_after: Option<(usize, CancelToken)>,
+        /// Time every spender lookup takes
+        delay: Option<Duration>,
+        /// Outputs whose spender lookup always fails
+        failing: HashSet<OutPoint>,
+    }
+
+    impl Faulty {
+        fn new(inner: MockDataSource) -> Self {
+            Self {
+                inner,
+                ..Self::default()
+            }
+        }
+
+        fn errors(mut self, errors: impl IntoIterator<Item = BlockchainError>) -> Self {
+            self.errors = std::sync::Mutex::new(errors.into_iter().collect());
+            self
+        }
+
+        fn cancel_after(mut self, calls: usize, token: &CancelToken) -> Self {
+            self.cancel_after = Some((calls, token.clone()));
+            self
+        }
+
+        fn delay(mut self, delay: Duration) -> Self {
+            self.delay = Some(delay);
+            self
+        }
+
+        fn fail(mut self, outpoint: OutPoint) -> Self {
+            self.failing.insert(outpoint);
+            self
+        }
+
+        fn served(&self) {
+            if let Some((calls, token)) = &self.cancel_after
+                && self.inner.calls() == *calls
+            {
+                token.cancel();
+            }
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl BlockchainDataSource for Faulty {
+        async fn get_transaction(&self, txid: Txid) -> blockchain::Result<Transaction> {
+            let tx = self.inner.get_transaction(txid).await;
+            self.served();
+            tx
+        }
+        async fn get_spending_transaction(
+            &self,
+            outpoint: OutPoint,
+        ) -> blockchain::Result<Option<Transaction>> {
+            if let Some(delay) = self.delay {
+                tokio::time::sleep(delay).await;
+            }
+            if let Some(error) = self.errors.lock().unwrap().pop_front() {
+                return Err(error);
+            }
+            if self.failing.contains(&outpoint) {
+                return Err(BlockchainError::Other("backend crashed".to_string()));
+            }
+            let spender = self.inner.get_spending_transaction(outpoint).await;
+            self.served();
+            spender
+        }
+        async fn get_address_transactions(
+            &self,
+            address: Address,
+        ) -> blockchain::Result<Vec<Transaction>> {
+            self.inner.get_address_transactions(address).await
+        }
+        async fn get_transactions_batch(
+            &self,
+            txids: &[Txid],
+        ) -> blockchain::Result<Vec<Option<Transaction>>> {
+            self.inner.get_transactions_batch(txids).await
+        }
+        async fn get_spending_transactions_batch(
+            &self,
+            outpoints: &[OutPoint],
+        ) -> blockchain::Result<Vec<Option<Transaction>>> {
+            self.inner.get_spending_transactions_batch(outpoints).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_check_replacement_finds_conflicting_spends() {
+        let funding = spend(
+            60,
+            &[OutPoint::new(Txid::all_zeros(), 3)],
+            &[50_000, 20_000],
+        );
+        let mut original = spend(
+            61,
+            &[
+                OutPoint::new(funding.compute_txid(), 0),
+                OutPoint::new(funding.compute_txid(), 1),
+            ],
+            &[69_000],
+        );
+        original.input[0].sequence = Sequence::ENABLE_RBF_NO_LOCKTIME;
+        // Spends only the second prevout, at a higher fee
+        let mut replacement = spend(62, &[OutPoint::new(funding.compute_txid(), 1)], &[18_000]);
+        replacement.input[0].sequence = Sequence::ENABLE_RBF_NO_LOCKTIME;
+        let txid = original.compute_txid();
+
+        let tracer = Tracer::new(MockDataSource::new(&[funding.clone(), original.clone()]));
+        assert_eq!(
+            tracer.check_replacement(txid).await.unwrap(),
+            ReplacementStatus::NotReplaced
+        );
+        let graph = tracer
+            .trace_forward(
+                OutPoint::new(funding.compute_txid(), 0),
+                &TraceConfig::default(),
+            )
+            .await
+            .unwrap()
+            .into_graph();
+        assert!(graph.node(&txid).unwrap().replaceable);
+        assert!(!graph.node(&funding.compute_txid()).unwrap().replaceable);
+
+        let mut source =
+            MockDataSource::new(&[funding.clone(), original.clone(), replacement.clone()]);
+        let tracer = Tracer::new(&source);
+        assert_eq!(
+            tracer.check_replacement(txid).await.unwrap(),
+            ReplacementStatus::Replaced {
+                by: replacement.compute_txid(),
+                confirmed: Some(false),
+            }
+        );
+
+        source.confirm(
+            replacement.compute_txid(),
+            TxStatus {
+                confirmed: true,
+                block_height: Some(900_000),
+                block_hash: None,
+                block_time: None,
+            },
+        );
+        let tracer = Tracer::new(&source);
+        assert_eq!(
+            tracer.check_replacement_of(&original).await.unwrap(),
+            ReplacementStatus::Replaced {
+                by: replacement.compute_txid(),
+                confirmed: Some(true),
+            }
+        );
+        // Once confirmed, the window's status lookup clears the flag
+        let graph = tracer
+            .trace_forward(
+                OutPoint::new(funding.compute_txid(), 1),
+                &TraceConfig::default().max_block_height(1_000_000),
+            )
+            .await
+            .unwrap()
+            .into_graph();
+        assert!(!graph.node(&replacement.compute_txid()).unwrap().replaceable);
+    }
+
+    #[tokio::test]
+    async fn test_branches_record_why_they_stopped() {
+        let funding = spend(50, &[OutPoint::new(Txid::all_zeros(), 9)], &[100_000]);
+        let split = spend(
+            51,
+            &[OutPoint::new(funding.compute_txid(), 0)],
+            &[60_000, 30_000, 5_000, 300, 2_000],
+        );
+        let hop = spend(52, &[OutPoint::new(split.compute_txid(), 0)], &[59_000]);
+        let far = spend(53, &[OutPoint::new(hop.compute_txid(), 0)], &[58_000]);
+        let root = OutPoint::new(funding.compute_txid(), 0);
+        let broken = OutPoint::new(split.compute_txid(), 2);
+        let source = || {
+            Faulty::new(MockDataSource::new(&[
+                funding.clone(),
+                split.clone(),
+                hop.clone(),
+                far.clone(),
+            ]))
+            .fail(broken)
+        };
+        let config = TraceConfig::default()
+            .max_depth(2)
+            .min_output_value(Amount::from_sat(1_000))
+            .stop(StopCondition::new().target_script(script(1)));
+
+        // By default, the failure ends the whole trace
+        let result = Tracer::new(source()).trace_forward(root, &config).await;
+        assert!(matches!(result, Err(TracerError::Source(_))));
+
+        let report = Tracer::new(source())
+            .trace_forward_with_report(root, &config.continue_on_error(true))
+            .await
+            .unwrap();
+        let graph = report.graph();
+        let leaves: Vec<_> = graph
+            .leaves()
+            .into_iter()
+            .map(|(edge, reason)| (edge.outpoint, reason.code()))
+            .collect();
+        assert_eq!(leaves.len(), 5);
+        for (outpoint, reason) in [
+            (OutPoint::new(hop.compute_txid(), 0), "max_depth"),
+            (OutPoint::new(split.compute_txid(), 1), "reached_target"),
+            (broken, "error"),
+            (OutPoint::new(split.compute_txid(), 3), "below_min_value"),
+            (OutPoint::new(split.compute_txid(), 4), "unspent"),
+        ] {
+            assert!(
+                leaves.contains(&(outpoint, reason)),
+                "{} {}",
+                outpoint,
+                reason
+            );
+        }
+        let error = graph.edge(&broken).unwrap().terminal.as_ref().unwrap();
+        let TerminalReason::Error { message, context } = error else {
+            panic!("expected an error, got {:?}", error);
+        };
+        assert_eq!(message, "backend crashed");
+        let context = context.as_ref().unwrap();
+        assert_eq!(context.operation, "get_spending_transaction");
+        assert_eq!(context.outpoint, Some(broken));
+
+        let summary = report.summary();
+        let sats = |reason| summary.frontier_value[reason].to_sat();
+        assert_eq!(sats("max_depth"), 59_000);
+        assert_eq!(sats("reached_target"), 30_000);
+        assert_eq!(sats("error"), 5_000);
+        assert_eq!(sats("below_min_value"), 300);
+        assert_eq!(sats("unspent"), 2_000);
+        assert!(
+            summary
+                .frontier_outputs
+                .values()
+                .all(|outputs| *outputs == 1)
+        );
+        assert_eq!(report.terminations().len(), 5);
+        // The reason and its error survive the exports
+        assert_eq!(&TraceGraph::from_json(&graph.to_json()).unwrap(), graph);
+    }
+
+    #[tokio::test]
+    async fn test_events_follow_the_trace() {
+        let chain = Chain::new();
+        let tracer = Tracer::new(chain.source());
+        let config = TraceConfig::default();
+
+        let (trace, events) = tracer.trace_forward_with_events(chain.root(), &config);
+        let graph = trace.await.unwrap().into_graph();
+        let events: Vec<_> = events.collect().await;
+
+        let fetched = |hop: usize| TraceEvent::TransactionFetched {
+            txid: chain.txid(hop),
+            depth: hop,
+        };
+        let unspent = |hop: usize, vout: u32| TraceEvent::BranchTerminated {
+            outpoint: OutPoint::new(chain.txid(hop), vout),
+            reason: TerminalReason::Unspent,
+        };
+        assert_eq!(
+            events,
+            vec![
+                fetched(0),
+                fetched(1),
+                fetched(2),
+                unspent(1, 1),
+                fetched(3),
+                unspent(2, 1),
+                unspent(3, 0),
+                unspent(3, 1),
+                TraceEvent::Progress {
+                    visited: graph.len(),
+                    frontier: 0,
+                    requests_made: tracer.source().calls(),
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_events_report_progress_periodically() {
+        let funding = spend(0, &[], &[100_000]);
+        let fan = spend(1, &[OutPoint::new(funding.compute_txid(), 0)], &[1_000; 24]);
+        let tracer = Tracer::new(MockDataSource::new(&[funding.clone(), fan]));
+        let config = TraceConfig::default();
+
+        let (trace, events) =
+            tracer.trace_forward_with_events(OutPoint::new(funding.compute_txid(), 0), &config);
+        trace.await.unwrap();
+        let progress: Vec<_> = events
+            .filter_map(|event| async move {
+                match event {
+                    TraceEvent::Progress {
+                        frontier,
+                        requests_made,
+                        ..
+                    } => Some((frontier, requests_made)),
+                    _ => None,
+                }
+            })
+            .collect()
+            .await;
+
+        // 26 requests: the funding transaction, its spender, then one per fan output
+        assert_eq!(progress, vec![(16, 10), (6, 20), (0, 26)]);
+    }
+
+    #[tokio::test]
+    async fn test_slow_consumer_keeps_latest_events() {
+        let chain = Chain::new();
+        let tracer = Tracer::new(chain.source());
+        let config = TraceConfig::default().event_buffer(2);
+
+        let (trace, events) = tracer.trace_forward_with_events(chain.root(), &config);
+        // Nothing is read until the trace is over, which it gets to without waiting
+        trace.await.unwrap();
+        let events: Vec<_> = events.collect().await;
+
+        assert_eq!(events.len(), 2);
+        assert!(matches!(events[1], TraceEvent::Progress { .. }));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_retries_are_reported() {
+        let chain = Chain::new();
+        let source = Faulty::new(chain.source()).errors([
+            BlockchainError::RateLimited { retry_after: None },
+            BlockchainError::NetworkFailure("reset by peer".to_string()),
+            BlockchainError::NetworkFailure("reset by peer".to_string()),
+        ]);
+        let tracer = Tracer::new(source);
+        let retry = RetryPolicy::new(3).backoff(Duration::from_millis(100));
+        let config = TraceConfig::default().retry(retry);
+
+        let (trace, events) = tracer.trace_forward_with_events(chain.root(), &config);
+        let start = tokio::time::Instant::now();
+        let graph = trace.await.unwrap().into_graph();
+        let retries: Vec<_> = events
+            .filter(|event| {
+                let retry = matches!(
+                    event,
+                    TraceEvent::Retrying { .. } | TraceEvent::RateLimited { .. }
+                );
+                async move { retry }
+            })
+            .collect()
+            .await;
+
+        assert_eq!(graph.len(), 4);
+        let network = |millis| TraceEvent::Retrying {
+            error: "reset by peer".to_string(),
+            wait: Duration::from_millis(millis),
+        };
+        assert_eq!(
+            retries,
+            vec![
+                TraceEvent::RateLimited {
+                    wait: retry.rate_limit_wait
+                },
+                network(100),
+                network(200),
+            ]
+        );
+        assert_eq!(start.elapsed(), Duration::from_millis(5_300));
+    }
+
+    #[tokio::test]
+    async fn test_retries_wait_on_the_tracer_clock() {
+        let chain = Chain::new();
+        let source = Faulty::new(chain.source()).errors([
+            BlockchainError::RateLimited { retry_after: None },
+            BlockchainError::NetworkFailure("reset by peer".to_string()),
+        ]);
+        let clock = MockClock::new();
+        let tracer = Tracer::new(source).clock(Arc::new(clock.clone()));
+        let retry = RetryPolicy::new(3).backoff(Duration::from_millis(100));
+        let config = TraceConfig::default().retry(retry);
+
+        let report = tracer
+            .trace_forward_with_report(chain.root(), &config)
+            .await
+            .unwrap();
+
+        // The rate limit wait, then the first backoff, passed on the clock alone
+        assert_eq!(report.graph().len(), 4);
+        assert_eq!(clock.elapsed(), Duration::from_millis(5_100));
+        assert_eq!(report.elapsed, clock.elapsed());
+    }
+
+    #[tokio::test]
+    async fn test_errors_end_the_trace_without_retries() {
+        let chain = Chain::new();
+        let source = Faulty::new(chain.source())
+            .errors([BlockchainError::RateLimited { retry_after: None }]);
+
+        let result = Tracer::new(source)
+            .trace_forward(chain.root(), &TraceConfig::default())
+            .await;
+
+        let Err(TracerError::Source(error)) = result else {
+            panic!("expected a source error, got {:?}", result);
+        };
+        assert!(matches!(error.inner(), BlockchainError::RateLimited { .. }));
+        let context = error.context().unwrap();
+        assert_eq!(context.operation, "get_spending_transaction");
+        assert_eq!(context.outpoint, Some(chain.root()));
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_forward_trace_keeps_work_done() {
+        let chain = Chain::new();
+        let token = CancelToken::new();
+        // Funding transaction, then the spenders of the root and of hop 1's output 0
+        let tracer = Tracer::new(Faulty::new(chain.source()).cancel_after(3, &token));
+        let config = TraceConfig::default().cancel(token);
+
+        let outcome = tracer.trace_forward(chain.root(), &config).await.unwrap();
+
+        assert!(outcome.is_cancelled());
+        let graph = outcome.into_graph();
+        assert_eq!(tracer.source().inner.calls(), 3);
+        assert_eq!(graph.len(), 3);
+        assert_eq!(
+            graph
+                .edge(&OutPoint::new(chain.txid(1), 0))
+                .unwrap()
+                .spent_by,
+            Some(chain.txid(2))
+        );
+        let cancelled: Vec<_> = graph
+            .edges()
+            .filter(|edge| edge.terminal == Some(TerminalReason::Cancelled))
+            .map(|edge| edge.outpoint)
+            .collect();
+        assert_eq!(cancelled.len(), 3);
+        assert!(cancelled.contains(&OutPoint::new(chain.txid(1), 1)));
+        assert!(cancelled.contains(&OutPoint::new(chain.txid(2), 0)));
+        let frontier: Vec<_> = graph.frontier().map(|node| node.txid).collect();
+        assert_eq!(frontier.len(), 2);
+        assert!(frontier.contains(&chain.txid(1)) && frontier.contains(&chain.txid(2)));
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_backward_trace_keeps_work_done() {
+        let parents: Vec<_> = (0..5).map(|n| coinbase(n, &[10_000])).collect();
+        let inputs: Vec<_> = parents
+            .iter()
+            .map(|tx| OutPoint::new(tx.compute_txid(), 0))
+            .collect();
+        let consolidation = spend(10, &inputs, &[49_000]);
+        let mut txs = parents.clone();
+        txs.push(consolidation.clone());
+        let token = CancelToken::new();
+        let tracer = Tracer::new(Faulty::new(MockDataSource::new(&txs)).cancel_after(3, &token));
+        let config = TraceConfig::default().cancel(token);
+
+        let outcome = tracer
+            .trace_backward(consolidation.compute_txid(), &config)
+            .await
+            .unwrap();
+
+        assert!(outcome.is_cancelled());
+        let graph = outcome.graph();
+        assert_eq!(graph.len(), 3);
+        assert_eq!(graph.edges().count(), 2);
+        // The consolidation, to fetch its other parents, and the parents still queued
+        assert_eq!(graph.frontier().count(), 3);
+        assert!(graph.node(&consolidation.compute_txid()).unwrap().frontier);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_cancel_aborts_lookup_in_flight() {
+        let chain = Chain::new();
+        let token = CancelToken::new();
+        let tracer = Tracer::new(Faulty::new(chain.source()).delay(Duration::from_secs(3_600)));
+        let config = TraceConfig::default().cancel(token.clone());
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            token.cancel();
+        });
+
+        let start = tokio::time::Instant::now();
+        let outcome = tracer.trace_forward(chain.root(), &config).await.unwrap();
+
+        assert_eq!(start.elapsed(), Duration::from_secs(1));
+        assert!(outcome.is_cancelled());
+        let root = outcome.graph().edge(&chain.root()).unwrap();
+        assert_eq!(root.terminal, Some(TerminalReason::Cancelled));
+        assert_eq!(outcome.graph().len(), 1);
+    }
+
+    /// Checkpoint of a trace saving one every `every` requests, cancelled after `calls`
+    /// lookups: the one saved as it stopped, read back from its file
+    async fn interrupted<F, Fut>(
+        every: usize,
+        calls: usize,
+        source: MockDataSource,
+        trace: F,
+    ) -> TraceCheckpoint
+    where
+        F: FnOnce(Tracer<Faulty>, TraceConfig) -> Fut,
+        Fut: Future<Output = Result<TraceOutcome>>,
+    {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("trace.checkpoint");
+        let token = CancelToken::new();
+        let tracer = Tracer::new(Faulty::new(source).cancel_after(calls, &token));
+        let config = TraceConfig::default()
+            .checkpoint_every(every, &path)
+            .cancel(token);
+
+        assert!(trace(tracer, config).await.unwrap().is_cancelled());
+        let json = serde_json::to_string(&TraceCheckpoint::load(&path).unwrap()).unwrap();
+        serde_json::from_str(&json).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_resumed_forward_trace_matches_uninterrupted() {
+        let chain = Chain::new();
+        let config = TraceConfig::default();
+        let full = Tracer::new(chain.source())
+            .trace_forward(chain.root(), &config)
+            .await
+            .unwrap();
+
+        let root = chain.root();
+        let checkpoint = interrupted(3, 5, chain.source(), |tracer, config| async move {
+            tracer.trace_forward(root, &config).await
+        })
+        .await;
+        assert_eq!(checkpoint.requests(), 5);
+        assert_ne!(checkpoint.graph(), full.graph());
+
+        let tracer = Tracer::new(chain.source());
+        let resumed = tracer.resume(checkpoint, &config, false).await.unwrap();
+
+        assert_eq!(resumed, full);
+        // Only the lookups after the checkpoint are made again
+        assert_eq!(tracer.source().calls(), 8 - 5);
+    }
+
+    #[tokio::test]
+    async fn test_checkpoints_follow_their_schedule() {
+        let chain = Chain::new();
+        let dir = tempfile::tempdir().unwrap();
+        let every = dir.path().join("every.checkpoint");
+        let on_cancel = dir.path().join("cancel.checkpoint");
+        let tracer = Tracer::new(chain.source());
+
+        let config = TraceConfig::default().checkpoint_every(3, &every);
+        tracer.trace_forward(chain.root(), &config).await.unwrap();
+        let config = TraceConfig::default().checkpoint_on_cancel(&on_cancel);
+        tracer.trace_forward(chain.root(), &config).await.unwrap();
+
+        // Due at 3 and 6 of the 8 requests, and not again as the trace finished
+        assert_eq!(TraceCheckpoint::load(&every).unwrap().requests(), 6);
+        assert!(!on_cancel.exists());
+
+        let token = CancelToken::new();
+        let tracer = Tracer::new(Faulty::new(chain.source()).cancel_after(2, &token));
+        let config = TraceConfig::default()
+            .checkpoint_on_cancel(&on_cancel)
+            .cancel(token);
+        assert!(
+            tracer
+                .trace_forward(chain.root(), &config)
+                .await
+                .unwrap()
+                .is_cancelled()
+        );
+        let checkpoint = TraceCheckpoint::load(&on_cancel).unwrap();
+        assert_eq!(checkpoint.requests(), 2);
+        let resumed = Tracer::new(chain.source())
+            .resume(checkpoint, &TraceConfig::default(), false)
+            .await
+            .unwrap();
+        let full = Tracer::new(chain.source())
+            .trace_forward(chain.root(), &TraceConfig::default())
+            .await
+            .unwrap();
+        assert_eq!(resumed, full);
+    }
+
+    #[tokio::test]
+    async fn test_resumed_backward_trace_matches_uninterrupted() {
+        let txs = ancestry();
+        let start = txs[3].compute_txid();
+        let config = TraceConfig::default();
+        let full = Tracer::new(MockDataSource::new(&txs))
+            .trace_backward(start, &config)
+            .await
+            .unwrap();
+
+        let checkpoint = interrupted(
+            2,
+            3,
+            MockDataSource::new(&txs),
+            |tracer, config| async move { tracer.trace_backward(start, &config).await },
+        )
+        .await;
+        let resumed = Tracer::new(MockDataSource::new(&txs))
+            .resume(checkpoint, &config, false)
+            .await
+            .unwrap();
+
+        assert_eq!(resumed, full);
+    }
+
+    #[tokio::test]
+    async fn test_resume_rejects_other_config_unless_forced() {
+        let chain = Chain::new();
+        let root = chain.root();
+        let checkpoint = interrupted(3, 5, chain.source(), |tracer, config| async move {
+            tracer.trace_forward(root, &config).await
+        })
+        .await;
+        let tracer = Tracer::new(chain.source());
+        let deeper = TraceConfig::default().max_depth(20);
+
+        let result = tracer.resume(checkpoint.clone(), &deeper, false).await;
+        assert!(matches!(
+            result,
+            Err(TracerError::IncompatibleCheckpoint(_))
+        ));
+        let outcome = tracer.resume(checkpoint, &deeper, true).await.unwrap();
+        assert_eq!(outcome.graph().len(), 4);
+    }
+
+    /// `MockDataSource` taking 100ms per lookup, counting the lookups of every key
+    struct Latency {
+        inner: MockDataSource,
+        lookups: std::sync::Arc<std::sync::Mutex<HashMap<String, usize>>>,
+    }
+
+    impl Latency {
+        const DELAY: Duration = Duration::from_millis(100);
+
+        fn new(inner: MockDataSource) -> Self {
+            Self {
+                inner,
+                lookups: Default::default(),
+            }
+        }
+
+        async fn lookup(&self, key: String) {
+            *self.lookups.lock().unwrap().entry(key).or_default() += 1;
+            tokio::time::sleep(Self::DELAY).await;
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl BlockchainDataSource for Latency {
+        async fn get_transaction(&self, txid: Txid) -> blockchain::Result<Transaction> {
+            self.lookup(format!("tx {}", txid)).await;
+            self.inner.get_transaction(txid).await
+        }
+        async fn get_spending_transaction(
+            &self,
+            outpoint: OutPoint,
+        ) -> blockchain::Result<Option<Transaction>> {
+            self.lookup(format!("spender {}", outpoint)).await;
+            self.inner.get_spending_transaction(outpoint).await
+        }
+        async fn get_address_transactions(
+            &self,
+            address: Address,
+        ) -> blockchain::Result<Vec<Transaction>> {
+            self.inner.get_address_transactions(address).await
+        }
+        async fn get_transactions_batch(
+            &self,
+            txids: &[Txid],
+        ) -> blockchain::Result<Vec<Option<Transaction>>> {
+            self.inner.get_transactions_batch(txids).await
+        }
+        async fn get_spending_transactions_batch(
+            &self,
+            outpoints: &[OutPoint],
+        ) -> blockchain::Result<Vec<Option<Transaction>>> {
+            self.inner.get_spending_transactions_batch(outpoints).await
+        }
+    }
+
+    /// Three-wide braid of `layers` layers over a coinbase: transaction `j` of a layer
+    /// spends output `j` of transactions `j` and `j + 1` (mod 3) of the layer before,
+    /// and a final transaction consolidates the last layer. Every transaction but the
+    /// final one has three outputs, so paths converge all over.
+    #[cfg(feature = "cache")]
+    fn braid(layers: u32) -> Vec<Transaction> {
+        let mut txs = vec![coinbase(0, &[30_000; 3])];
+        let mut previous = vec![txs[0].compute_txid(); 3];
+        for layer in 1..=layers {
+            let current: Vec<Transaction> = (0..3)
+                .map(|j| {
+                    let inputs = [
+                        OutPoint::new(previous[j], j as u32),
+                        OutPoint::new(previous[(j + 1) % 3], j as u32),
+                    ];
+                    spend(layer * 10 + j as u32, &inputs, &[9_000; 3])
+                })
+                .collect();
+            previous = current.iter().map(Transaction::compute_txid).collect();
+            txs.extend(current);
+        }
+        let last: Vec<_> = (0..3)
+            .flat_map(|vout| previous.iter().map(move |&txid| OutPoint::new(txid, vout)))
+            .collect();
+        txs.push(spend(1_000, &last, &[70_000]));
+        txs
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_concurrent_lookups_speed_up_wide_traces() {
+        let funding = spend(0, &[], &[100_000]);
+        let fan = spend(1, &[OutPoint::new(funding.compute_txid(), 0)], &[1_000; 16]);
+        let leaves: Vec<_> = (0..16)
+            .map(|vout| spend(2 + vout, &[OutPoint::new(fan.compute_txid(), vout)], &[900]))
+            .collect();
+        let mut txs = vec![funding.clone(), fan];
+        txs.extend(leaves);
+        let root = OutPoint::new(funding.compute_txid(), 0);
+
+        let mut traces = Vec::new();
+        for concurrency in [1, 4] {
+            let tracer = Tracer::new(Latency::new(MockDataSource::new(&txs)));
+            let config = TraceConfig::default().concurrency(concurrency);
+            let start = tokio::time::Instant::now();
+            let graph = tracer
+                .trace_forward(root, &config)
+                .await
+                .unwrap()
+                .into_graph();
+            traces.push((graph, start.elapsed()));
+        }
+
+        let (sequential, concurrent) = (&traces[0], &traces[1]);
+        assert_eq!(sequential.0.len(), 18);
+        assert_eq!(sequential.0, concurrent.0);
+        // 34 lookups: the funding transaction and root spender, one at a time, then 32
+        // spenders four at a time
+        assert_eq!(sequential.1, Latency::DELAY * 34);
+        assert_eq!(concurrent.1, Latency::DELAY * (2 + 8));
+    }
+
+    #[cfg(feature = "cache")]
+    #[tokio::test(start_paused = true)]
+    async fn test_concurrent_traces_fetch_every_key_once() {
+        let txs = braid(4);
+        let config = TraceConfig::default().concurrency(4);
+        let sequential = TraceConfig::default().concurrency(1);
+        let end = txs.last().unwrap().compute_txid();
+        let root = OutPoint::new(txs[0].compute_txid(), 0);
+        let expected_backward = Tracer::new(MockDataSource::new(&txs))
+            .trace_backward(end, &sequential)
+            .await
+            .unwrap();
+        let expected_forward = Tracer::new(MockDataSource::new(&txs))
+            .trace_forward(root, &sequential)
+            .await
+            .unwrap();
+
+        let source = Latency::new(MockDataSource::new(&txs));
+        let lookups = source.lookups.clone();
+        let cache = CachingDataSource::new(source, Duration::from_secs(300));
+        let (first, second) = (Tracer::new(cache.clone()), Tracer::new(cache));
+        let (backward, again, forward) = tokio::join!(
+            first.trace_backward(end, &config),
+            second.trace_backward(end, &config),
+            first.trace_forward(root, &config),
+        );
+
+        assert_eq!(backward.unwrap(), expected_backward);
+        assert_eq!(again.unwrap(), expected_backward);
+        assert_eq!(forward.unwrap(), expected_forward);
+        assert_eq!(expected_backward.graph().len(), txs.len());
+        let lookups = lookups.lock().unwrap();
+        assert!(!lookups.is_empty());
+        assert!(
+            lookups.values().all(|&count| count == 1),
+            "duplicate lookups: {:?}",
+            lookups
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_request_budget_stops_gracefully() {
+        let chain = Chain::new();
+        let mut graphs = Vec::new();
+        for concurrency in [1, 4] {
+            let tracer = Tracer::new(Latency::new(chain.source()));
+            let config = TraceConfig::default()
+                .max_requests(3)
+                .concurrency(concurrency);
+
+            let report = tracer
+                .trace_forward_with_report(chain.root(), &config)
+                .await
+                .unwrap();
+
+            // Funding transaction, then the spenders of the root and of hop 1's output 0
+            assert_eq!(report.requests, 3);
+            assert_eq!(tracer.source().inner.calls(), 3);
+            assert!(!report.outcome.is_cancelled());
+            assert_eq!(report.graph().len(), 3);
+            assert_eq!(
+                report.terminations(),
+                std::collections::BTreeMap::from([("budget_exhausted", 3)])
+            );
+            let frontier: Vec<_> = report.graph().frontier().map(|node| node.txid).collect();
+            assert_eq!(frontier.len(), 2);
+            assert!(frontier.contains(&chain.txid(1)) && frontier.contains(&chain.txid(2)));
+            graphs.push(report.outcome.into_graph());
+        }
+        assert_eq!(graphs[0], graphs[1]);
+    }
+
+    #[cfg(feature = "cache")]
+    #[tokio::test(start_paused = true)]
+    async fn test_report_counts_requests_and_cache_hits() {
+        let chain = Chain::new();
+        let cache = CachingDataSource::new(Latency::new(chain.source()), Duration::from_secs(300));
+        let tracer = Tracer::new(cache);
+        let config = TraceConfig::default().concurrency(1);
+
+        let first = tracer
+            .trace_forward_with_report(chain.root(), &config)
+            .await
+            .unwrap();
+        let second = tracer
+            .trace_forward_with_report(chain.root(), &config)
+            .await
+            .unwrap();
+
+        assert_eq!((first.requests, first.cache_hits), (8, 0));
+        assert_eq!(first.elapsed, Latency::DELAY * 8);
+        // The funding transaction and the three spenders are cached, unspent outputs
+        // are looked up again
+        assert_eq!((second.requests, second.cache_hits), (4, 4));
+        assert_eq!(second.elapsed, Latency::DELAY * 4);
+        assert_eq!(first.outcome, second.outcome);
+        assert_eq!(second.terminations(), first.terminations());
+        assert_eq!(first.terminations()["unspent"], 4);
+        assert_eq!(first.estimated_requests, None);
+
+        // Cache hits are free: the budget still covers every lookup
+        let budget = config.max_requests(4);
+        let third = tracer
+            .trace_forward_with_report(chain.root(), &budget)
+            .await
+            .unwrap();
+        assert_eq!(third.outcome, first.outcome);
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_estimates_full_trace() {
+        // Funding -> one transaction -> a binary tree of two-output transactions
+        let funding = spend(0, &[], &[1 << 20]);
+        let mut txs = vec![funding.clone()];
+        let mut level = vec![OutPoint::new(funding.compute_txid(), 0)];
+        for depth in 1..=5u32 {
+            let mut next = Vec::new();
+            for (n, outpoint) in level.iter().enumerate() {
+                let tx = spend(depth * 100 + n as u32, &[*outpoint], &[1_000, 1_000]);
+                next.push(OutPoint::new(tx.compute_txid(), 0));
+                next.push(OutPoint::new(tx.compute_txid(), 1));
+                txs.push(tx);
+            }
+            level = next;
+        }
+        let tracer = Tracer::new(MockDataSource::new(&txs));
+        let root = OutPoint::new(funding.compute_txid(), 0);
+        let config = TraceConfig::default().max_depth(5);
+
+        let full = tracer
+            .trace_forward_with_report(root, &config)
+            .await
+            .unwrap();
+        let dry = tracer
+            .trace_forward_with_report(root, &config.clone().dry_run(2))
+            .await
+            .unwrap();
+
+        // 1, 1, 2, 4, 8 and 16 transactions at depths 0 to 5
+        assert_eq!(full.graph().len(), 32);
+        assert_eq!(full.requests, 32);
+        assert_eq!(dry.graph().len(), 4);
+        assert_eq!(dry.requests, 4);
+        assert_eq!(dry.estimated_requests, Some(full.requests));
+        // Sampling as deep as the trace goes is the trace itself
+        let exact = tracer
+            .trace_forward_with_report(root, &config.dry_run(5))
+            .await
+            .unwrap();
+        assert_eq!(exact.outcome, full.outcome);
+        assert_eq!(exact.estimated_requests, Some(32));
+    }
+
+    /// Five peel chains of three hops out of a fan-out: 21 transactions, and the
+    /// fan-out's funding output to trace forward from
+    fn chaos_chain() -> (crate::testing::ChainBuilder, OutPoint) {
+        let mut chain = crate::testing::ChainBuilder::new();
+        let funding = chain.fund(&[10_000_000]);
+        let root = OutPoint::new(funding.compute_txid(), 0);
+        let fan_out = chain.fan_out(root, 5, 1_000);
+        for vout in 0..5 {
+            chain.peel_chain(
+                OutPoint::new(fan_out.compute_txid(), vout),
+                3,
+                50_000,
+                1_000,
+            );
+        }
+        (chain, root)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_retries_ride_out_chaos() {
+        let (chain, root) = chaos_chain();
+        let clean = Tracer::new(chain.source())
+            .trace_forward(root, &TraceConfig::default())
+            .await
+            .unwrap();
+
+        for concurrency in [1, 4] {
+            let source = ChaosDataSource::new(chain.source(), 11)
+                .failure_rate(0.1)
+                .latency(0.3, Duration::from_millis(500))
+                .rate_limit_every(9);
+            let tracer = Tracer::new(source);
+            let config = TraceConfig::default()
+                .retry(RetryPolicy::new(6).backoff(Duration::from_millis(100)))
+                .concurrency(concurrency);
+            let outcome = tracer.trace_forward(root, &config).await.unwrap();
+
+            assert_eq!(outcome.graph(), clean.graph());
+            let stats = tracer.source().stats();
+            assert!(stats.failures > 0 && stats.rate_limits > 0 && stats.delays > 0);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_request_budget_counts_chaos_retries() {
+        let (chain, root) = chaos_chain();
+        let clean = Tracer::new(chain.source())
+            .trace_forward(root, &TraceConfig::default())
+            .await
+            .unwrap();
+        let source = ChaosDataSource::new(chain.source(), 5)
+            .failure_rate(0.2)
+            .rate_limit_every(6);
+        let tracer = Tracer::new(source);
+        let config = TraceConfig::default()
+            .retry(RetryPolicy::new(6).backoff(Duration::from_millis(100)))
+            .max_requests(20);
+
+        let report = tracer
+            .trace_forward_with_report(root, &config)
+            .await
+            .unwrap();
+
+        // Failed attempts are spent from the budget like the ones that got through
+        let stats = tracer.source().stats();
+        assert_eq!(report.requests, 20);
+        assert_eq!(stats.calls, 20);
+        assert!(stats.failures + stats.rate_limits > 0);
+        assert_eq!(
+            tracer.source().inner().calls(),
+            20 - stats.failures - stats.rate_limits
+        );
+        assert!(report.terminations().contains_key("budget_exhausted"));
+        assert!(report.graph().len() < clean.graph().len());
+        assert!(
+            report
+                .graph()
+                .nodes()
+                .all(|node| clean.graph().contains_node(&node.txid))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_checkpoint_of_a_failed_chaos_trace_resumes_cleanly() {
+        let (chain, root) = chaos_chain();
+        let config = TraceConfig::default();
+        let clean = Tracer::new(chain.source())
+            .trace_forward(root, &config)
+            .await
+            .unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("trace.checkpoint");
+
+        // Without retries, the first injected failure ends the trace
+        let source = ChaosDataSource::new(chain.source(), 2).failure_rate(0.1);
+        let tracer = Tracer::new(source);
+        let chaotic = TraceConfig::default().checkpoint_every(2, &path);
+        assert!(tracer.trace_forward(root, &chaotic).await.is_err());
+        assert_eq!(tracer.source().stats().failures, 1);
+
+        let checkpoint = TraceCheckpoint::load(&path).unwrap();
+        assert!(checkpoint.requests() >= 2);
+        assert_ne!(checkpoint.graph(), clean.graph());
+        let resumed = Tracer::new(chain.source())
+            .resume(checkpoint, &config, false)
+            .await
+            .unwrap();
+
+        assert_eq!(resumed, clean);
+    }
+}
@@ -11,6 +11,20 @@ pub enum TracerError {
     TracingTooLarge(String),
     #[error("Internal tracing error, try again")]
     TraceLogic(String),
+    #[error("Unsupported trace schema version {0}, expected {expected}", expected = crate::tracer::json::SCHEMA_VERSION)]
+    UnsupportedSchema(u32),
+    #[error("Malformed trace JSON: {0}")]
+    MalformedJson(String),
+    #[error("Failed to write export: {0}")]
+    Export(#[from] csv::Error),
+    #[error("Failed to access checkpoint: {0}")]
+    Checkpoint(#[from] std::io::Error),
+    #[error("Checkpoint does not match this trace: {0}")]
+    IncompatibleCheckpoint(String),
+    #[error("Failed to read label file: {0}")]
+    LabelFile(std::io::Error),
+    #[error("Invalid label file, line {0}: {1}")]
+    InvalidLabels(usize, String),
     #[error("Blockchain source failed")]
     Source(#[from] BlockchainError),
 }
@@ -0,0 +1,173 @@
+//! Progress events of a running trace.
+//!
+//! A trace started with `Tracer::trace_forward_with_events` (or its backward
+//! counterpart) reports what it does on a bounded queue. The trace never waits for the
+//! consumer: once the queue is full, the oldest event is dropped to make room, so a
+//! slow progress bar only misses intermediate updates.
+
+use crate::tracer::TerminalReason;
+use bitcoin::{OutPoint, Txid};
+use futures::stream::{self, BoxStream, Stream, StreamExt};
+use std::{
+    collections::VecDeque,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::sync::Notify;
+
+/// Default number of events held for a slow consumer
+pub const DEFAULT_EVENT_BUFFER: usize = 1_024;
+
+/// A `Progress` event is sent every this many requests to the data source
+pub const PROGRESS_INTERVAL: usize = 10;
+
+/// Something a trace did
+#[derive(Debug, Clone, PartialEq)]
+pub enum TraceEvent {
+    /// A transaction was added to the graph, `depth` hops from the start
+    TransactionFetched { txid: Txid, depth: usize },
+    /// The trace stopped following `outpoint`
+    BranchTerminated {
+        outpoint: OutPoint,
+        reason: TerminalReason,
+    },
+    /// A lookup failed with a transient error and is tried again after `wait`
+    Retrying { error: String, wait: Duration },
+    /// The data source rate limited a lookup, which is tried again after `wait`
+    RateLimited { wait: Duration },
+    /// Counters so far: transactions in the graph, outputs (forward) or transactions
+    /// (backward) queued, and requests made to the data source, lookups its cache
+    /// answered left out. Also sent once when the trace ends.
+    Progress {
+        visited: usize,
+        frontier: usize,
+        requests_made: usize,
+    },
+}
+
+/// Events waiting for the consumer
+struct Queue {
+    events: VecDeque<TraceEvent>,
+    closed: bool,
+}
+
+struct Shared {
+    queue: Mutex<Queue>,
+    ready: Notify,
+    capacity: usize,
+}
+
+/// Trace side of the event queue; closes the stream when dropped
+pub(crate) struct EventSender {
+    shared: Arc<Shared>,
+}
+
+impl EventSender {
+    /// Queues `event`, dropping the oldest one if the queue is full
+    pub(crate) fn send(&self, event: TraceEvent) {
+        let mut queue = self.shared.queue.lock().unwrap();
+        if queue.events.len() >= self.shared.capacity {
+            queue.events.pop_front();
+        }
+        queue.events.push_back(event);
+        drop(queue);
+        self.shared.ready.notify_one();
+    }
+}
+
+impl Drop for EventSender {
+    fn drop(&mut self) {
+        self.shared.queue.lock().unwrap().closed = true;
+        self.shared.ready.notify_one();
+    }
+}
+
+/// Stream of the events of one trace, ending when the trace does
+pub struct TraceEvents {
+    inner: BoxStream<'static, TraceEvent>,
+}
+
+impl Stream for TraceEvents {
+    type Item = TraceEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<TraceEvent>> {
+        self.inner.poll_next_unpin(cx)
+    }
+}
+
+/// Event queue holding at most `capacity` events (at least one)
+pub(crate) fn channel(capacity: usize) -> (EventSender, TraceEvents) {
+    let shared = Arc::new(Shared {
+        queue: Mutex::new(Queue {
+            events: VecDeque::new(),
+            closed: false,
+        }),
+        ready: Notify::new(),
+        capacity: capacity.max(1),
+    });
+    let inner = stream::unfold(shared.clone(), |shared| async move {
+        loop {
+            {
+                let mut queue = shared.queue.lock().unwrap();
+                if let Some(event) = queue.events.pop_front() {
+                    return Some((event, shared.clone()));
+                }
+                if queue.closed {
+                    return None;
+                }
+            }
+            // notify_one keeps a permit when nobody waits, so no send is missed
+            shared.ready.notified().await;
+        }
+    });
+    (
+        EventSender { shared },
+        TraceEvents {
+            inner: inner.boxed(),
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn progress(visited: usize) -> TraceEvent {
+        TraceEvent::Progress {
+            visited,
+            frontier: 0,
+            requests_made: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_full_queue_drops_oldest() {
+        let (sender, events) = channel(2);
+        for visited in 0..5 {
+            sender.send(progress(visited));
+        }
+        drop(sender);
+
+        let events: Vec<_> = events.collect().await;
+        assert_eq!(events, vec![progress(3), progress(4)]);
+    }
+
+    #[tokio::test]
+    async fn test_consumer_waits_for_events() {
+        let (sender, mut events) = channel(DEFAULT_EVENT_BUFFER);
+        let producer = tokio::spawn(async move {
+            for visited in 0..3 {
+                tokio::task::yield_now().await;
+                sender.send(progress(visited));
+            }
+        });
+
+        assert_eq!(events.next().await, Some(progress(0)));
+        producer.await.unwrap();
+        assert_eq!(events.next().await, Some(progress(1)));
+        assert_eq!(events.next().await, Some(progress(2)));
+        assert_eq!(events.next().await, None);
+    }
+}
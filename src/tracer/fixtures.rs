@@ -0,0 +1,270 @@
+//! Hand-built transaction chains for tracer tests, served by `MockDataSource`.
+
+// Most of them feed traces, whose tests need the engine
+#![cfg_attr(not(feature = "trace-engine"), allow(dead_code))]
+
+#[cfg(feature = "runtime")]
+use crate::blockchain::TxStatus;
+#[cfg(feature = "trace-engine")]
+pub(crate) use crate::testing::Method;
+#[cfg(feature = "runtime")]
+pub(crate) use crate::testing::MockDataSource;
+#[cfg(feature = "trace-engine")]
+use crate::tracer::{TraceConfig, TraceGraph, Tracer};
+use bitcoin::{
+    Amount, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid, Witness,
+    absolute::LockTime, block::Header, hashes::Hash, transaction::Version,
+};
+
+/// P2WPKH script of a made-up key, distinct per `n`
+pub(crate) fn script(n: u8) -> ScriptBuf {
+    ScriptBuf::new_p2wpkh(&bitcoin::WPubkeyHash::from_byte_array([n; 20]))
+}
+
+/// Transaction spending `inputs` into outputs of `values` sats, each paying `script(n)`
+/// for its own index `n`. `tag` makes otherwise identical transactions distinct.
+pub(crate) fn spend(tag: u32, inputs: &[OutPoint], values: &[u64]) -> Transaction {
+    Transaction {
+        version: Version::TWO,
+        lock_time: LockTime::from_consensus(tag),
+        input: inputs
+            .iter()
+            .map(|&previous_output| TxIn {
+                previous_output,
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            })
+            .collect(),
+        output: values
+            .iter()
+            .enumerate()
+            .map(|(n, &sats)| TxOut {
+                value: Amount::from_sat(sats),
+                script_pubkey: script(n as u8),
+            })
+            .collect(),
+    }
+}
+
+/// Coinbase transaction paying `values` sats
+pub(crate) fn coinbase(tag: u32, values: &[u64]) -> Transaction {
+    spend(tag, &[OutPoint::null()], values)
+}
+
+/// Three-hop chain `funding -> hop1 -> hop2 -> hop3`, each hop spending output 0 of
+/// the previous one into two outputs (output 1 of each hop stays unspent).
+///
+/// Output 0 values: 100_000, 90_000, 80_000, 70_000 sats; output 1 is 9_000 sats.
+pub(crate) struct Chain {
+    pub(crate) txs: Vec<Transaction>,
+}
+
+impl Chain {
+    pub(crate) fn new() -> Self {
+        let funding = spend(0, &[OutPoint::new(Txid::all_zeros(), 7)], &[100_000]);
+        let mut txs = vec![funding];
+        for hop in 1..=3u32 {
+            let previous: &Transaction = txs.last().unwrap();
+            let value = previous.output[0].value.to_sat() - 10_000;
+            let previous = OutPoint::new(previous.compute_txid(), 0);
+            // 1_000 sats of fee per hop
+            txs.push(spend(hop, &[previous], &[value, 9_000]));
+        }
+        Self { txs }
+    }
+
+    /// Output traced from: output 0 of the funding transaction
+    pub(crate) fn root(&self) -> OutPoint {
+        OutPoint::new(self.txid(0), 0)
+    }
+
+    pub(crate) fn txid(&self, index: usize) -> Txid {
+        self.txs[index].compute_txid()
+    }
+
+    #[cfg(feature = "runtime")]
+    pub(crate) fn source(&self) -> MockDataSource {
+        MockDataSource::new(&self.txs)
+    }
+}
+
+/// Two seeds whose paths merge at depth 2: `seed_a -> a -> merge` and
+/// `seed_b -> b -> merge`, `merge` spending output 0 of both `a` and `b` into one
+/// unspent output. Transactions in that order; the seeds are output 0 of the first two.
+pub(crate) fn converging() -> Vec<Transaction> {
+    let seed_a = spend(10, &[OutPoint::new(Txid::all_zeros(), 1)], &[50_000]);
+    let seed_b = spend(11, &[OutPoint::new(Txid::all_zeros(), 2)], &[40_000]);
+    let a = spend(12, &[OutPoint::new(seed_a.compute_txid(), 0)], &[49_000]);
+    let b = spend(13, &[OutPoint::new(seed_b.compute_txid(), 0)], &[39_000]);
+    let merge = spend(
+        14,
+        &[
+            OutPoint::new(a.compute_txid(), 0),
+            OutPoint::new(b.compute_txid(), 0),
+        ],
+        &[87_000],
+    );
+    vec![seed_a, seed_b, a, b, merge]
+}
+
+/// Small forward trace `funding -> split -> sweep`, where `split` also pays change and
+/// dust that stay unspent. `split` carries a block time.
+#[cfg(feature = "trace-engine")]
+pub(crate) async fn sample_graph() -> TraceGraph {
+    let funding = spend(0, &[], &[100_000]);
+    let root = OutPoint::new(funding.compute_txid(), 0);
+    let split = spend(1, &[root], &[60_000, 39_000, 300]);
+    let sweep = spend(2, &[OutPoint::new(split.compute_txid(), 0)], &[59_000]);
+    let tracer = Tracer::new(MockDataSource::new(&[funding, split.clone(), sweep]));
+
+    let mut graph = tracer
+        .trace_forward(root, &TraceConfig::default())
+        .await
+        .unwrap()
+        .into_graph();
+    graph.node_mut(&split.compute_txid()).unwrap().timestamp = Some(1_700_000_000);
+    graph
+}
+
+/// OP_RETURN script carrying "charley loves heidi", a classic data-carrier test vector
+pub(crate) fn op_return() -> ScriptBuf {
+    ScriptBuf::from_hex("6a13636861726c6579206c6f766573206865696469").unwrap()
+}
+
+/// Forward trace `funding -> tagged`, where `tagged` pays 50_000 unspent sats on
+/// output 0 and tags the payment with `op_return()` on output 1. Transactions in that
+/// order.
+pub(crate) fn tagged() -> [Transaction; 2] {
+    let funding = spend(20, &[OutPoint::new(Txid::all_zeros(), 3)], &[51_000]);
+    let mut tagged = spend(
+        21,
+        &[OutPoint::new(funding.compute_txid(), 0)],
+        &[50_000, 0],
+    );
+    tagged.output[1].script_pubkey = op_return();
+    [funding, tagged]
+}
+
+/// Forward trace `funding -> hop1 -> hop2 -> hop3 -> hop4 -> sweep` along output 0,
+/// except for `sweep`, which spends output 1 of `hop4`. Output 1 of `hop1` (depth 1,
+/// unspent) and output 1 of `hop4` (depth 4) both pay `script(40)`; every other output
+/// pays a script of its own. Transactions in that order.
+pub(crate) fn reused() -> Vec<Transaction> {
+    let funding = spend(30, &[OutPoint::new(Txid::all_zeros(), 4)], &[100_000]);
+    let mut txs = vec![funding];
+    for (hop, values) in [
+        (1, &[60_000, 39_000][..]),
+        (2, &[59_000]),
+        (3, &[58_000]),
+        (4, &[30_000, 27_000]),
+    ] {
+        let previous = OutPoint::new(txs.last().unwrap().compute_txid(), 0);
+        let mut tx = spend(30 + hop, &[previous], values);
+        tx.output[0].script_pubkey = script(10 + hop as u8);
+        if let Some(output) = tx.output.get_mut(1) {
+            output.script_pubkey = script(40);
+        }
+        txs.push(tx);
+    }
+    let mut sweep = spend(35, &[OutPoint::new(txs[4].compute_txid(), 1)], &[26_000]);
+    sweep.output[0].script_pubkey = script(15);
+    txs.push(sweep);
+    txs
+}
+
+/// Header of a made-up block at `height`, timestamped `height * 600`
+pub(crate) fn header(height: u32) -> Header {
+    Header {
+        time: height * 600,
+        ..bitcoin::constants::genesis_block(bitcoin::Network::Regtest).header
+    }
+}
+
+/// Forward trace `funding -> split`, then `left` spending output 0 of `split` and
+/// `right` its output 1, then `onward` spending output 0 of `left`. Confirmed at
+/// heights 100 (funding), 101 (split and left, in the same block), 102 (onward) and
+/// 200 (right): under a window ending below 200, the `right` branch leaves it at
+/// depth 2. The statuses name their block but not its time, the source knows the
+/// `header` of each block. Transactions in that order.
+#[cfg(feature = "runtime")]
+pub(crate) fn windowed() -> (MockDataSource, [Transaction; 5]) {
+    let funding = coinbase(50, &[100_000]);
+    let split = spend(
+        51,
+        &[OutPoint::new(funding.compute_txid(), 0)],
+        &[60_000, 39_000],
+    );
+    let left = spend(52, &[OutPoint::new(split.compute_txid(), 0)], &[59_000]);
+    let right = spend(53, &[OutPoint::new(split.compute_txid(), 1)], &[38_000]);
+    let onward = spend(54, &[OutPoint::new(left.compute_txid(), 0)], &[58_000]);
+    let txs = [funding, split, left, right, onward];
+
+    let mut source = MockDataSource::new(&txs);
+    for (tx, height) in txs.iter().zip([100, 101, 101, 200, 102]) {
+        let header = header(height);
+        source.confirm(
+            tx.compute_txid(),
+            TxStatus {
+                confirmed: true,
+                block_height: Some(height),
+                block_hash: Some(header.block_hash()),
+                block_time: None,
+            },
+        );
+        source.add_header(header);
+    }
+    (source, txs)
+}
+
+/// Witness script of the funding output of the BOLT 3 test vectors, a 2-of-2 multisig
+pub(crate) const FUNDING_SCRIPT_HEX: &str = "5221023da092f6980e58d2c037173180e9a465476026ee50f96695963e8efe436f54eb21030e9f7b623d2ccc7c9bd44d66d5ce21ce504c0acf6385a132cec6d3c39fa711c152ae";
+
+/// Witness script of the `to_local` output of the BOLT 3 test vectors, delayed 144
+/// blocks
+pub(crate) const TO_LOCAL_SCRIPT_HEX: &str = "63210212a140cd0c6539d07cd08dfe09984dec3251ea808b892efeac3ede9402bf2b1967029000b2752103fd5960528dc152014952efdb702a88f71e3c1653b2314431701ec77e57fde83c68ac";
+
+/// Forward trace `mined -> funding -> close -> sweep` of a Lightning channel. Output 0
+/// of `funding` (800_000 sats) opens the channel, output 1 is change. `close` is a
+/// commitment transaction spending it, its witness revealing `FUNDING_SCRIPT_HEX`:
+/// `to_local` on output 0, `to_remote` on output 1 (unspent) and two 330-sat anchors.
+/// `sweep` spends `to_local` once its delay is over, revealing `TO_LOCAL_SCRIPT_HEX`.
+/// Signatures are placeholders. Transactions in that order.
+pub(crate) fn channel() -> [Transaction; 4] {
+    let funding_script = ScriptBuf::from_hex(FUNDING_SCRIPT_HEX).unwrap();
+    let to_local = ScriptBuf::from_hex(TO_LOCAL_SCRIPT_HEX).unwrap();
+    let signature = vec![0x30; 71];
+
+    let mined = coinbase(60, &[1_000_000]);
+    let mut funding = spend(
+        61,
+        &[OutPoint::new(mined.compute_txid(), 0)],
+        &[800_000, 199_000],
+    );
+    funding.output[0].script_pubkey = ScriptBuf::new_p2wsh(&funding_script.wscript_hash());
+
+    let mut close = spend(
+        0,
+        &[OutPoint::new(funding.compute_txid(), 0)],
+        &[500_000, 298_000, 330, 330],
+    );
+    // Obscured commitment number, split between the locktime and the sequence
+    close.lock_time = LockTime::from_consensus(0x2000_1234);
+    close.input[0].sequence = Sequence(0x8000_0042);
+    close.input[0].witness = Witness::from_slice(&[
+        Vec::new(),
+        signature.clone(),
+        signature.clone(),
+        funding_script.to_bytes(),
+    ]);
+    close.output[0].script_pubkey = ScriptBuf::new_p2wsh(&to_local.wscript_hash());
+    for (anchor, n) in close.output[2..].iter_mut().zip(1..) {
+        anchor.script_pubkey =
+            ScriptBuf::new_p2wsh(&bitcoin::WScriptHash::from_byte_array([n; 32]));
+    }
+
+    let mut sweep = spend(63, &[OutPoint::new(close.compute_txid(), 0)], &[499_000]);
+    sweep.input[0].sequence = Sequence(144);
+    sweep.input[0].witness = Witness::from_slice(&[signature, Vec::new(), to_local.to_bytes()]);
+    [mined, funding, close, sweep]
+}
@@ -0,0 +1,823 @@
+//! Transaction graph produced by a trace.
+//!
+//! Nodes are transactions keyed by txid, edges are outputs keyed by outpoint, running
+//! from the transaction that created the output to the one that spent it. An edge
+//! without a spender is a leaf, and records why the trace stopped there.
+//!
+//! Forward and backward traces produce the same structure, so their results can be
+//! merged and exported the same way.
+
+use crate::tracer::{
+    TerminalReason,
+    change::ChangeScore,
+    coinbase::CoinbaseOrigin,
+    coinjoin::CoinJoinVerdict,
+    dot::rfc3339,
+    labels::Label,
+    lightning::LightningChannel,
+    pattern::TxPattern,
+    rbf::is_rbf_signaling,
+    script::{DataCarrier, classify_script},
+    table,
+    value_match::SpeculativeEdge,
+};
+use bitcoin::{
+    Address, Amount, Network, OutPoint, Script, ScriptBuf, Transaction, TxOut, Txid,
+    address::NetworkUnchecked,
+};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+
+/// A transaction in a traced graph.
+///
+/// # Fields
+/// * `txid` - Transaction ID
+/// * `depth` - hops from the starting transaction (0 is the starting transaction)
+/// * `height` - block height of the transaction, when known
+/// * `timestamp` - block time of the transaction (unix seconds), when known
+/// * `input_value` - total value of the transaction's inputs, when all its prevouts
+///   are known
+/// * `output_value` - total value of the transaction's outputs
+/// * `fee` - fee the transaction paid, when all its prevouts are known (0 for a
+///   coinbase)
+/// * `vsize` - virtual size of the transaction in vbytes, witness discounted
+/// * `coinbase` - the transaction is a coinbase, an origin of its coins
+/// * `frontier` - the trace stopped at this transaction because of `max_depth`, or was
+///   cancelled before expanding it
+/// * `truncated` - some neighbours of the transaction were left out by the
+///   `max_transactions` or `max_breadth` caps
+/// * `unspent` - at least one output of the transaction is unspent
+/// * `replaceable` - the transaction signals replaceability (BIP 125) and is not
+///   known to be confirmed: a trace without a window does not look confirmations up
+/// * `coinjoin` - verdict of the CoinJoin detector, if it flagged the transaction
+/// * `seeds` - indexes of the outpoints of a multi-source trace the transaction is
+///   reachable from, empty for traces from a single start
+/// * `data_carriers` - data of the transaction's OP_RETURN outputs, in output order
+/// * `labels` - labels of the outputs paying known entities, by output index
+/// * `pattern` - shape of the transaction, `Unknown` until a trace classifies it
+/// * `lightning` - role of the transaction in a Lightning channel, if a trace
+///   recognized one
+/// * `origin` - height and pool of a coinbase, as far as it tells
+/// * `annotations` - findings of the trace's annotators, by `<annotator>.<key>`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TraceNode {
+    pub txid: Txid,
+    pub depth: usize,
+    pub height: Option<u32>,
+    pub timestamp: Option<u64>,
+    pub input_value: Option<Amount>,
+    pub output_value: Amount,
+    pub fee: Option<Amount>,
+    pub vsize: usize,
+    pub coinbase: bool,
+    pub frontier: bool,
+    pub truncated: bool,
+    pub unspent: bool,
+    #[serde(default)]
+    pub replaceable: bool,
+    pub coinjoin: Option<CoinJoinVerdict>,
+    #[serde(default)]
+    pub seeds: BTreeSet<usize>,
+    #[serde(default)]
+    pub data_carriers: Vec<DataCarrier>,
+    #[serde(default)]
+    pub labels: BTreeMap<u32, Label>,
+    #[serde(default)]
+    pub pattern: TxPattern,
+    #[serde(default)]
+    pub lightning: Option<LightningChannel>,
+    #[serde(default)]
+    pub origin: Option<CoinbaseOrigin>,
+    #[serde(default)]
+    pub annotations: BTreeMap<String, String>,
+}
+
+impl TraceNode {
+    /// Node for `tx`, found `depth` hops from the start of the trace
+    pub fn new(tx: &Transaction, depth: usize) -> Self {
+        Self {
+            txid: tx.compute_txid(),
+            depth,
+            height: None,
+            timestamp: None,
+            input_value: None,
+            output_value: tx.output.iter().map(|out| out.value).sum(),
+            fee: tx.is_coinbase().then_some(Amount::ZERO),
+            vsize: tx.vsize(),
+            coinbase: tx.is_coinbase(),
+            frontier: false,
+            truncated: false,
+            unspent: false,
+            replaceable: !tx.is_coinbase() && is_rbf_signaling(tx),
+            coinjoin: None,
+            seeds: BTreeSet::new(),
+            data_carriers: tx
+                .output
+                .iter()
+                .zip(0..)
+                .filter_map(|(out, vout)| DataCarrier::from_script(vout, &out.script_pubkey))
+                .collect(),
+            labels: BTreeMap::new(),
+            pattern: TxPattern::Unknown,
+            lightning: None,
+            origin: None,
+            annotations: BTreeMap::new(),
+        }
+    }
+
+    /// Records the total value of the inputs, `None` when some prevout is unknown, and
+    /// the fee it implies. A coinbase keeps its zero fee.
+    pub fn set_input_value(&mut self, input_value: Option<Amount>) {
+        self.input_value = input_value;
+        if !self.coinbase {
+            self.fee = input_value.and_then(|value| value.checked_sub(self.output_value));
+        }
+    }
+
+    /// Fee rate in sat/vB, when the fee is known
+    pub fn feerate(&self) -> Option<f64> {
+        self.fee
+            .filter(|_| self.vsize > 0)
+            .map(|fee| fee.to_sat() as f64 / self.vsize as f64)
+    }
+
+    /// Combines what two traces learned about the same transaction.
+    ///
+    /// Keeps the smallest depth and any known metadata. The node stays on the frontier
+    /// or truncated only if neither trace expanded it fully.
+    fn merge(&mut self, other: &TraceNode) {
+        self.depth = self.depth.min(other.depth);
+        self.height = self.height.or(other.height);
+        self.timestamp = self.timestamp.or(other.timestamp);
+        self.input_value = self.input_value.or(other.input_value);
+        self.fee = self.fee.or(other.fee);
+        self.coinbase |= other.coinbase;
+        self.frontier &= other.frontier;
+        self.truncated &= other.truncated;
+        self.unspent |= other.unspent;
+        // Either trace may have seen it confirmed
+        self.replaceable &= other.replaceable;
+        if self.coinjoin.is_none() {
+            self.coinjoin = other.coinjoin.clone();
+        }
+        self.seeds.extend(&other.seeds);
+        if self.data_carriers.is_empty() {
+            self.data_carriers = other.data_carriers.clone();
+        }
+        for (vout, label) in &other.labels {
+            self.labels.entry(*vout).or_insert_with(|| label.clone());
+        }
+        if self.pattern == TxPattern::Unknown {
+            self.pattern = other.pattern;
+        }
+        if self.lightning.is_none() {
+            self.lightning = other.lightning.clone();
+        }
+        if self.origin.is_none() {
+            self.origin = other.origin.clone();
+        }
+        for (key, value) in &other.annotations {
+            self.annotations
+                .entry(key.clone())
+                .or_insert_with(|| value.clone());
+        }
+    }
+}
+
+/// `<txid> at depth <n>, height <h> (<time>): <value> out, fee <fee>`, then the
+/// flags of the node. Heights, times and fees are left out when unknown.
+impl fmt::Display for TraceNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} at depth {}",
+            table::txid(&self.txid, f.alternate()),
+            self.depth
+        )?;
+        if let Some(height) = self.height {
+            write!(f, ", height {}", height)?;
+        }
+        if let Some(timestamp) = self.timestamp {
+            write!(f, " ({})", rfc3339(timestamp))?;
+        }
+        write!(f, ": {} out", table::amount(self.output_value))?;
+        if let Some(fee) = self.fee {
+            write!(f, ", fee {}", table::amount(fee))?;
+        }
+        let flags = [
+            (self.coinbase, "coinbase"),
+            (self.replaceable, "replaceable"),
+            (self.frontier, "frontier"),
+            (self.truncated, "truncated"),
+        ];
+        for (_, flag) in flags.iter().filter(|(set, _)| *set) {
+            write!(f, ", {}", flag)?;
+        }
+        Ok(())
+    }
+}
+
+/// An output in a traced graph, from the transaction creating it to its spender.
+///
+/// # Fields
+/// * `outpoint` - the output (its txid is the creating transaction)
+/// * `spent_by` - transaction spending the output, `None` for a leaf
+/// * `value` - amount carried by the output
+/// * `script_pubkey` - script the output pays to
+/// * `address` - address of `script_pubkey`, if it has a standard form (see
+///   `ScriptClass::has_address`)
+/// * `terminal` - why the trace did not go past this output, for leaves
+/// * `change_score` - what the change detector made of the output, when its
+///   transaction was scored
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TraceEdge {
+    pub outpoint: OutPoint,
+    pub spent_by: Option<Txid>,
+    pub value: Amount,
+    pub script_pubkey: ScriptBuf,
+    #[serde(deserialize_with = "deserialize_address")]
+    pub address: Option<Address>,
+    pub terminal: Option<TerminalReason>,
+    #[serde(default)]
+    pub change_score: Option<ChangeScore>,
+}
+
+impl TraceEdge {
+    /// Edge for `output` of `outpoint`, not yet linked to a spender
+    pub fn new(outpoint: OutPoint, output: &TxOut, network: Network) -> Self {
+        Self {
+            outpoint,
+            spent_by: None,
+            value: output.value,
+            script_pubkey: output.script_pubkey.clone(),
+            address: classify_script(&output.script_pubkey)
+                .has_address()
+                .then(|| Address::from_script(&output.script_pubkey, network).ok())
+                .flatten(),
+            terminal: None,
+            change_score: None,
+        }
+    }
+
+    /// Same edge, as a leaf the trace stopped at for `reason`
+    pub fn terminal(self, reason: TerminalReason) -> Self {
+        Self {
+            terminal: Some(reason),
+            ..self
+        }
+    }
+
+    /// Transaction that created the output
+    pub fn from(&self) -> Txid {
+        self.outpoint.txid
+    }
+}
+
+/// `<txid>:<vout> <value> to <address>`, then its spender or why the trace stopped
+/// there. An output without an address shows the class of its script instead.
+impl fmt::Display for TraceEdge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} to ",
+            table::outpoint(&self.outpoint, f.alternate()),
+            table::amount(self.value)
+        )?;
+        match &self.address {
+            Some(address) => write!(f, "{}", address)?,
+            None => write!(f, "({})", classify_script(&self.script_pubkey).code())?,
+        }
+        match (&self.terminal, self.spent_by) {
+            (Some(reason), _) => {
+                write!(f, ", {}", reason.code())?;
+                if let Some(detail) = reason.detail() {
+                    write!(f, " ({})", detail)?;
+                }
+            }
+            (None, Some(spender)) => {
+                write!(f, ", spent by {}", table::txid(&spender, f.alternate()))?
+            }
+            (None, None) => {}
+        }
+        Ok(())
+    }
+}
+
+/// Addresses are written out with their network prefix, which is trusted on the way
+/// back in: a graph is only read back by the tool that wrote it.
+pub(crate) fn deserialize_address<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Address>, D::Error> {
+    let address = Option::<Address<NetworkUnchecked>>::deserialize(deserializer)?;
+    Ok(address.map(Address::assume_checked))
+}
+
+/// Transactions and outputs discovered by a trace.
+///
+/// Kept in ordered maps, so iteration (and anything exported from it) does not depend
+/// on the order the trace discovered things in. Outputs are also indexed by the script
+/// they pay as edges are inserted, for address reuse.
+///
+/// Continuations proposed by analyses such as value matching are kept apart, as
+/// speculative edges: they are not outputs of the trace, and nothing walking its edges
+/// sees them.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(from = "GraphData")]
+pub struct TraceGraph {
+    nodes: BTreeMap<Txid, TraceNode>,
+    edges: BTreeMap<OutPoint, TraceEdge>,
+    #[serde(skip)]
+    by_script: HashMap<ScriptBuf, BTreeSet<OutPoint>>,
+    speculative_edges: Vec<SpeculativeEdge>,
+}
+
+/// Serialized form of a `TraceGraph`, its index rebuilt on the way back in
+#[derive(Deserialize)]
+struct GraphData {
+    nodes: BTreeMap<Txid, TraceNode>,
+    edges: BTreeMap<OutPoint, TraceEdge>,
+    #[serde(default)]
+    speculative_edges: Vec<SpeculativeEdge>,
+}
+
+impl From<GraphData> for TraceGraph {
+    fn from(data: GraphData) -> Self {
+        let mut graph = TraceGraph {
+            nodes: data.nodes,
+            speculative_edges: data.speculative_edges,
+            ..TraceGraph::default()
+        };
+        for edge in data.edges.into_values() {
+            graph.insert_edge(edge);
+        }
+        graph
+    }
+}
+
+impl TraceGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a node, keeping the existing one if the transaction is already present
+    pub fn insert_node(&mut self, node: TraceNode) {
+        self.nodes.entry(node.txid).or_insert(node);
+    }
+
+    /// Adds an edge, replacing any previous edge for the same outpoint
+    pub fn insert_edge(&mut self, edge: TraceEdge) {
+        self.remove_edge(&edge.outpoint);
+        self.by_script
+            .entry(edge.script_pubkey.clone())
+            .or_default()
+            .insert(edge.outpoint);
+        self.edges.insert(edge.outpoint, edge);
+    }
+
+    /// Drops the node of `txid`, leaving its edges
+    #[cfg(feature = "trace-engine")]
+    pub(crate) fn remove_node(&mut self, txid: &Txid) -> Option<TraceNode> {
+        self.nodes.remove(txid)
+    }
+
+    /// Drops the edge at `outpoint`
+    pub(crate) fn remove_edge(&mut self, outpoint: &OutPoint) -> Option<TraceEdge> {
+        let edge = self.edges.remove(outpoint)?;
+        if let Some(outpoints) = self.by_script.get_mut(&edge.script_pubkey) {
+            outpoints.remove(outpoint);
+            if outpoints.is_empty() {
+                self.by_script.remove(&edge.script_pubkey);
+            }
+        }
+        Some(edge)
+    }
+
+    pub fn node(&self, txid: &Txid) -> Option<&TraceNode> {
+        self.nodes.get(txid)
+    }
+
+    #[cfg(feature = "trace-engine")]
+    pub(crate) fn node_mut(&mut self, txid: &Txid) -> Option<&mut TraceNode> {
+        self.nodes.get_mut(txid)
+    }
+
+    pub fn edge(&self, outpoint: &OutPoint) -> Option<&TraceEdge> {
+        self.edges.get(outpoint)
+    }
+
+    pub fn contains_node(&self, txid: &Txid) -> bool {
+        self.nodes.contains_key(txid)
+    }
+
+    /// Nodes in txid order
+    pub fn nodes(&self) -> impl Iterator<Item = &TraceNode> {
+        self.nodes.values()
+    }
+
+    /// Edges in outpoint order
+    pub fn edges(&self) -> impl Iterator<Item = &TraceEdge> {
+        self.edges.values()
+    }
+
+    /// Outputs the trace stopped at, with why, in outpoint order
+    pub fn leaves(&self) -> Vec<(&TraceEdge, &TerminalReason)> {
+        self.edges()
+            .filter_map(|edge| Some((edge, edge.terminal.as_ref()?)))
+            .collect()
+    }
+
+    /// Edges for the outputs of `txid`, in output order
+    pub fn outputs_of(&self, txid: &Txid) -> impl Iterator<Item = &TraceEdge> {
+        self.edges
+            .range(OutPoint::new(*txid, 0)..=OutPoint::new(*txid, u32::MAX))
+            .map(|(_, edge)| edge)
+    }
+
+    /// Edges for the outputs paying `script`, in outpoint order
+    pub fn outputs_paying(&self, script: &Script) -> impl Iterator<Item = &TraceEdge> {
+        self.by_script
+            .get(script)
+            .into_iter()
+            .flatten()
+            .filter_map(|outpoint| self.edges.get(outpoint))
+    }
+
+    /// Scripts paid by more than one output of the graph, with those outputs
+    pub(crate) fn reused_scripts(&self) -> impl Iterator<Item = (&ScriptBuf, &BTreeSet<OutPoint>)> {
+        self.by_script
+            .iter()
+            .filter(|(_, outpoints)| outpoints.len() > 1)
+    }
+
+    /// Edges for the traced inputs of `txid` (scans every edge)
+    pub fn inputs_of(&self, txid: &Txid) -> impl Iterator<Item = &TraceEdge> {
+        self.edges
+            .values()
+            .filter(move |edge| edge.spent_by.as_ref() == Some(txid))
+    }
+
+    /// Traced transactions spending an output of `txid`, each once, in txid order
+    pub fn successors(&self, txid: &Txid) -> Vec<&TraceNode> {
+        let txids: BTreeSet<_> = self
+            .outputs_of(txid)
+            .filter_map(|edge| edge.spent_by)
+            .collect();
+        txids.iter().filter_map(|txid| self.node(txid)).collect()
+    }
+
+    /// Traced transactions with an output spent by `txid`, each once, in txid order
+    pub fn predecessors(&self, txid: &Txid) -> Vec<&TraceNode> {
+        let txids: BTreeSet<_> = self.inputs_of(txid).map(TraceEdge::from).collect();
+        txids.iter().filter_map(|txid| self.node(txid)).collect()
+    }
+
+    /// Nodes the trace could continue from: stopped at by `max_depth` or cancellation,
+    /// or truncated
+    pub fn frontier(&self) -> impl Iterator<Item = &TraceNode> {
+        self.nodes().filter(|node| node.frontier || node.truncated)
+    }
+
+    /// Leaves of the graph paying a target of the trace's stop condition
+    pub fn targets_reached(&self) -> impl Iterator<Item = &TraceEdge> {
+        self.edges()
+            .filter(|edge| matches!(edge.terminal, Some(TerminalReason::ReachedTarget(_))))
+    }
+
+    /// The edges and nodes of a forward trace leading from its start to `outpoint`.
+    ///
+    /// Walks back from `outpoint` through the inputs of each transaction to a parent
+    /// one hop shallower, so the path is a shortest one. Empty if `outpoint` is not in
+    /// the graph.
+    pub fn path_to(&self, outpoint: &OutPoint) -> TraceGraph {
+        let mut path = TraceGraph::new();
+        let Some(edge) = self.edge(outpoint) else {
+            return path;
+        };
+        path.insert_edge(edge.clone());
+        let mut txid = edge.from();
+        while let Some(node) = self.node(&txid) {
+            path.insert_node(node.clone());
+            let Some(edge) = self.inputs_of(&txid).find(|edge| {
+                self.node(&edge.from())
+                    .is_some_and(|parent| parent.depth < node.depth)
+            }) else {
+                break;
+            };
+            path.insert_edge(edge.clone());
+            txid = edge.from();
+        }
+        path
+    }
+
+    /// Tags every node with the indexes of the `seeds` it is reachable from: the
+    /// transaction creating a seed, and everything downstream of the seed's spender.
+    #[cfg(feature = "trace-engine")]
+    pub(crate) fn tag_seeds(&mut self, seeds: &[OutPoint]) {
+        for (index, seed) in seeds.iter().enumerate() {
+            let mut pending = vec![seed.txid];
+            pending.extend(self.edge(seed).and_then(|edge| edge.spent_by));
+            let mut visited = BTreeSet::new();
+            while let Some(txid) = pending.pop() {
+                if !visited.insert(txid) {
+                    continue;
+                }
+                let Some(node) = self.nodes.get_mut(&txid) else {
+                    continue;
+                };
+                node.seeds.insert(index);
+                // Outputs of the transaction creating a seed are not all that seed's
+                if txid != seed.txid {
+                    pending.extend(self.outputs_of(&txid).filter_map(|edge| edge.spent_by));
+                }
+            }
+        }
+    }
+
+    /// Transactions reachable from more than one seed of a multi-source trace, where
+    /// their paths converge
+    pub fn convergences(&self) -> impl Iterator<Item = &TraceNode> {
+        self.nodes().filter(|node| node.seeds.len() > 1)
+    }
+
+    /// Adds everything `other` traced to this graph.
+    ///
+    /// A transaction in both graphs becomes a single node (see `TraceNode` for how
+    /// their metadata combine). An outpoint in both becomes a single edge, the one
+    /// linked to a spender if only one of them is.
+    pub fn merge(&mut self, other: &TraceGraph) {
+        for node in other.nodes() {
+            self.nodes
+                .entry(node.txid)
+                .and_modify(|existing| existing.merge(node))
+                .or_insert_with(|| node.clone());
+        }
+        for edge in other.edges() {
+            match self.edges.get(&edge.outpoint) {
+                Some(existing) if existing.spent_by.is_some() || edge.spent_by.is_none() => {}
+                _ => self.insert_edge(edge.clone()),
+            }
+        }
+        self.add_speculative_edges(other.speculative_edges.iter().cloned());
+    }
+
+    /// Adds `spender`, a transaction spending outputs of the graph, one hop past them:
+    /// for a spend seen after the trace ran. The outputs are linked to it, and it
+    /// joins the frontier one hop deeper than the deepest of them, its own outputs
+    /// left at `MaxDepthReached` for a later trace to follow.
+    ///
+    /// Returns `false`, leaving the graph as is, when `spender` is in the graph
+    /// already or spends none of its outputs.
+    pub fn extend(&mut self, spender: &Transaction, network: Network) -> bool {
+        let txid = spender.compute_txid();
+        let spent: Vec<OutPoint> = spender
+            .input
+            .iter()
+            .map(|input| input.previous_output)
+            .filter(|outpoint| self.edges.contains_key(outpoint))
+            .collect();
+        if spent.is_empty() || self.contains_node(&txid) {
+            return false;
+        }
+
+        let parents: BTreeSet<Txid> = spent.iter().map(|outpoint| outpoint.txid).collect();
+        let mut node = TraceNode::new(spender, 0);
+        node.frontier = true;
+        for parent in parents.iter().filter_map(|txid| self.nodes.get(txid)) {
+            node.depth = node.depth.max(parent.depth + 1);
+            node.seeds.extend(&parent.seeds);
+        }
+        node.set_input_value(
+            spender
+                .input
+                .iter()
+                .map(|input| Some(self.edge(&input.previous_output)?.value))
+                .sum(),
+        );
+
+        for outpoint in &spent {
+            let edge = self.edges.get_mut(outpoint).expect("edge of the graph");
+            edge.spent_by = Some(txid);
+            edge.terminal = None;
+        }
+        for parent in &parents {
+            let unspent = self
+                .outputs_of(parent)
+                .any(|edge| edge.terminal == Some(TerminalReason::Unspent));
+            if let Some(node) = self.nodes.get_mut(parent) {
+                node.unspent = unspent;
+            }
+        }
+        for (output, vout) in spender.output.iter().zip(0..) {
+            let edge = TraceEdge::new(OutPoint::new(txid, vout), output, network);
+            self.insert_edge(edge.terminal(TerminalReason::MaxDepthReached));
+        }
+        self.insert_node(node);
+        true
+    }
+
+    /// Continuations proposed for the graph, ordered by the output they continue from,
+    /// then most confident first
+    pub fn speculative_edges(&self) -> &[SpeculativeEdge] {
+        &self.speculative_edges
+    }
+
+    /// Adds proposed continuations, replacing any already proposed from the same
+    /// output to the same candidate
+    pub fn add_speculative_edges(&mut self, edges: impl IntoIterator<Item = SpeculativeEdge>) {
+        for edge in edges {
+            self.speculative_edges.retain(|existing| {
+                (existing.deposit, existing.outpoint) != (edge.deposit, edge.outpoint)
+            });
+            self.speculative_edges.push(edge);
+        }
+        self.speculative_edges.sort_by(|a, b| {
+            a.deposit
+                .cmp(&b.deposit)
+                .then(b.confidence.total_cmp(&a.confidence))
+                .then(a.outpoint.cmp(&b.outpoint))
+        });
+    }
+
+    /// Number of transactions in the graph
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+}
+
+#[cfg(all(test, feature = "trace-engine"))]
+mod tests {
+    use super::*;
+    use crate::tracer::{
+        TraceConfig, Tracer,
+        fixtures::{Chain, MockDataSource, spend},
+    };
+    use bitcoin::{Witness, hashes::Hash};
+
+    async fn forward(chain: &Chain, root: OutPoint, max_depth: usize) -> TraceGraph {
+        Tracer::new(chain.source())
+            .trace_forward(root, &TraceConfig::default().max_depth(max_depth))
+            .await
+            .unwrap()
+            .into_graph()
+    }
+
+    #[tokio::test]
+    async fn test_neighbours_and_frontier() {
+        let chain = Chain::new();
+        let graph = forward(&chain, chain.root(), 2).await;
+
+        let successors: Vec<_> = graph
+            .successors(&chain.txid(1))
+            .iter()
+            .map(|node| node.txid)
+            .collect();
+        assert_eq!(successors, vec![chain.txid(2)]);
+        let predecessors: Vec<_> = graph
+            .predecessors(&chain.txid(1))
+            .iter()
+            .map(|node| node.txid)
+            .collect();
+        assert_eq!(predecessors, vec![chain.txid(0)]);
+        assert!(graph.predecessors(&chain.txid(0)).is_empty());
+
+        let frontier: Vec<_> = graph.frontier().map(|node| node.txid).collect();
+        assert_eq!(frontier, vec![chain.txid(2)]);
+        assert!(graph.node(&chain.txid(1)).unwrap().unspent);
+    }
+
+    #[tokio::test]
+    async fn test_neighbours_are_listed_once() {
+        let funding = spend(0, &[], &[60_000, 40_000]);
+        let merge = spend(
+            1,
+            &[
+                OutPoint::new(funding.compute_txid(), 0),
+                OutPoint::new(funding.compute_txid(), 1),
+            ],
+            &[99_000],
+        );
+        let graph = Tracer::new(MockDataSource::new(&[funding.clone(), merge.clone()]))
+            .trace_backward(merge.compute_txid(), &TraceConfig::default())
+            .await
+            .unwrap()
+            .into_graph();
+
+        assert_eq!(graph.inputs_of(&merge.compute_txid()).count(), 2);
+        assert_eq!(graph.outputs_of(&funding.compute_txid()).count(), 2);
+        assert_eq!(graph.successors(&funding.compute_txid()).len(), 1);
+        assert_eq!(graph.predecessors(&merge.compute_txid()).len(), 1);
+        assert_eq!(
+            graph.node(&merge.compute_txid()).unwrap().input_value,
+            Some(Amount::from_sat(100_000))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_merging_overlapping_traces_keeps_one_copy() {
+        let chain = Chain::new();
+        let full = forward(&chain, chain.root(), 10).await;
+
+        for (first_depth, later_depth) in [(1, 10), (2, 10), (2, 1), (3, 3), (10, 10)] {
+            let mut merged = forward(&chain, chain.root(), first_depth).await;
+            // Overlaps the first trace from hop 1 on
+            let later = forward(&chain, OutPoint::new(chain.txid(1), 0), later_depth).await;
+            merged.merge(&later);
+
+            let txids: BTreeSet<_> = merged.nodes().map(|node| node.txid).collect();
+            assert_eq!(txids.len(), merged.len());
+            let outpoints: BTreeSet<_> = merged.edges().map(|edge| edge.outpoint).collect();
+            assert_eq!(outpoints.len(), merged.edges().count());
+            for node in merged.nodes() {
+                assert!(full.contains_node(&node.txid));
+            }
+
+            // Merging is idempotent
+            let before = merged.clone();
+            merged.merge(&before);
+            assert_eq!(merged, before);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_merge_completes_a_shallow_trace() {
+        let chain = Chain::new();
+        let full = forward(&chain, chain.root(), 10).await;
+        let mut merged = forward(&chain, chain.root(), 2).await;
+        merged.merge(&forward(&chain, OutPoint::new(chain.txid(2), 0), 10).await);
+
+        assert_eq!(merged.len(), full.len());
+        assert_eq!(merged.edges().count(), full.edges().count());
+        for edge in full.edges() {
+            assert_eq!(merged.edge(&edge.outpoint).unwrap().spent_by, edge.spent_by);
+        }
+        // Hop 2 was on the frontier of the shallow trace only
+        assert_eq!(merged.frontier().count(), 0);
+        assert_eq!(merged.node(&chain.txid(3)).unwrap().depth, 1);
+    }
+
+    #[tokio::test]
+    async fn test_extend_adds_a_later_spend_one_hop_deeper() {
+        let chain = Chain::new();
+        let mut graph = forward(&chain, chain.root(), 10).await;
+        let leaf = OutPoint::new(chain.txid(3), 0);
+        let later = spend(9, &[leaf], &[69_000]);
+
+        assert!(graph.extend(&later, Network::Bitcoin));
+
+        let edge = graph.edge(&leaf).unwrap();
+        assert_eq!(edge.spent_by, Some(later.compute_txid()));
+        assert_eq!(edge.terminal, None);
+        let node = graph.node(&later.compute_txid()).unwrap();
+        assert_eq!(node.depth, graph.node(&chain.txid(3)).unwrap().depth + 1);
+        assert!(node.frontier);
+        assert_eq!(node.fee, Some(Amount::from_sat(1_000)));
+        let outputs: Vec<_> = graph.outputs_of(&later.compute_txid()).collect();
+        assert_eq!(outputs.len(), 1);
+        assert_eq!(outputs[0].terminal, Some(TerminalReason::MaxDepthReached));
+        // Output 1 of hop 3 is still unspent
+        assert!(graph.node(&chain.txid(3)).unwrap().unspent);
+
+        // Spends nothing of the graph, or is in it already
+        let unrelated = spend(10, &[OutPoint::new(Txid::all_zeros(), 3)], &[1_000]);
+        let before = graph.clone();
+        assert!(!graph.extend(&unrelated, Network::Bitcoin));
+        assert!(!graph.extend(&later, Network::Bitcoin));
+        assert_eq!(graph, before);
+    }
+
+    #[tokio::test]
+    async fn test_serde_round_trip() {
+        let chain = Chain::new();
+        let graph = forward(&chain, chain.root(), 2).await;
+
+        let json = serde_json::to_string(&graph).unwrap();
+        let back: TraceGraph = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(back, graph);
+        assert!(back.edges().any(|edge| edge.address.is_some()));
+    }
+
+    #[test]
+    fn test_feerate_discounts_witness_data() {
+        let mut tx = spend(1, &[OutPoint::null()], &[90_000]);
+        tx.input[0].previous_output = OutPoint::new(Txid::all_zeros(), 3);
+        tx.input[0].witness = Witness::from_slice(&[[7u8; 72].as_slice(), &[2u8; 33]]);
+
+        let mut node = TraceNode::new(&tx, 1);
+        assert_eq!(node.fee, None);
+        assert_eq!(node.feerate(), None);
+        node.set_input_value(Some(Amount::from_sat(91_410)));
+
+        assert_eq!(node.fee, Some(Amount::from_sat(1_410)));
+        assert!(node.vsize < tx.total_size());
+        assert_eq!(node.feerate(), Some(1_410.0 / node.vsize as f64));
+        // Inputs worth less than the outputs do not make a fee
+        node.set_input_value(Some(Amount::from_sat(1_000)));
+        assert_eq!(node.fee, None);
+    }
+}
@@ -0,0 +1,551 @@
+//! GraphML export of a `TraceGraph`, for Gephi, yEd and other graph tools.
+//!
+//! Transactions are nodes, spent outputs are edges between them. As in the DOT export,
+//! outputs the trace stopped at are nodes of their own (`kind` `output`), so every
+//! output of the trace is an edge. Every attribute is declared with its type up front,
+//! as Gephi drops data it has no declaration for; a missing value is left out rather
+//! than written empty.
+//!
+//! With `GraphmlOptions::link_reuse`, every reused address is a node of its own (`kind`
+//! `address`), linked by `reuse` edges to the transactions paying it.
+//!
+//! The candidate outputs of speculative edges are nodes of their own too (`kind`
+//! `candidate`), linked by `speculative` edges to the output they continue from.
+
+use crate::tracer::{
+    AddressReuse, DataCarrier, Label, Result, TraceEdge, TraceGraph, TraceNode, TracerError,
+    dot::reuse_id,
+};
+use bitcoin::{Amount, Denomination};
+use std::io::Write;
+
+/// Attribute types of GraphML
+#[derive(Clone, Copy)]
+enum AttrType {
+    Boolean,
+    Int,
+    Long,
+    Double,
+    String,
+}
+
+impl AttrType {
+    fn name(self) -> &'static str {
+        match self {
+            AttrType::Boolean => "boolean",
+            AttrType::Int => "int",
+            AttrType::Long => "long",
+            AttrType::Double => "double",
+            AttrType::String => "string",
+        }
+    }
+}
+
+/// Attributes of nodes, by key id: name and type
+const NODE_KEYS: [(&str, &str, AttrType); 22] = [
+    ("n_kind", "kind", AttrType::String),
+    ("n_label", "label", AttrType::String),
+    ("n_txid", "txid", AttrType::String),
+    ("n_depth", "depth", AttrType::Int),
+    ("n_height", "height", AttrType::Long),
+    ("n_timestamp", "timestamp", AttrType::Long),
+    ("n_value_sat", "value_sat", AttrType::Long),
+    ("n_fee_sat", "fee_sat", AttrType::Long),
+    ("n_coinbase", "coinbase", AttrType::Boolean),
+    ("n_coinjoin", "coinjoin", AttrType::Boolean),
+    ("n_pattern", "pattern", AttrType::String),
+    ("n_lightning", "lightning", AttrType::String),
+    ("n_frontier", "frontier", AttrType::Boolean),
+    ("n_truncated", "truncated", AttrType::Boolean),
+    ("n_unspent", "unspent", AttrType::Boolean),
+    ("n_terminal", "terminal", AttrType::String),
+    ("n_address", "address", AttrType::String),
+    ("n_data", "data", AttrType::String),
+    ("n_entity", "entity", AttrType::String),
+    ("n_category", "category", AttrType::String),
+    ("n_occurrences", "occurrences", AttrType::Int),
+    ("n_round_trip", "round_trip", AttrType::Boolean),
+];
+
+/// Attributes of edges, by key id: name and type
+const EDGE_KEYS: [(&str, &str, AttrType); 9] = [
+    ("e_kind", "kind", AttrType::String),
+    ("e_outpoint", "outpoint", AttrType::String),
+    ("e_value_sat", "value_sat", AttrType::Long),
+    ("e_value_btc", "value_btc", AttrType::Double),
+    ("e_address", "address", AttrType::String),
+    ("e_terminal", "terminal", AttrType::String),
+    ("e_entity", "entity", AttrType::String),
+    ("e_category", "category", AttrType::String),
+    ("e_confidence", "confidence", AttrType::Double),
+];
+
+/// Options of `TraceGraph::to_graphml_with`.
+///
+/// # Fields
+/// * `link_reuse` - whether addresses paid more than once are nodes, linked to the
+///   transactions paying them
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GraphmlOptions {
+    pub link_reuse: bool,
+}
+
+impl GraphmlOptions {
+    /// Adds the addresses paid more than once, linked to the transactions paying them
+    pub fn link_reuse(mut self, link_reuse: bool) -> Self {
+        self.link_reuse = link_reuse;
+        self
+    }
+}
+
+impl TraceGraph {
+    /// Writes the graph as GraphML, streaming it to `writer` (wrap a file in a
+    /// `BufWriter`: elements are written one at a time).
+    ///
+    /// Nodes come out in txid order, then one node per output the trace stopped at, in
+    /// outpoint order, then the edges in outpoint order. Transaction nodes are
+    /// identified by their txid and output nodes by their outpoint. `value_sat` of a
+    /// transaction is its output value; `terminal` is the reason code of the output
+    /// (see `TerminalReason::code`), with its detail after a colon. `data` is what an
+    /// OP_RETURN output carries, as text when it is printable. `entity` and `category`
+    /// name the known entity an output pays, on its edge and on its node when it is a
+    /// leaf. The candidate outputs of speculative edges come after the other nodes,
+    /// identified as `candidate:<outpoint>`, and their `speculative` edges last, with
+    /// their `confidence`.
+    ///
+    /// # Errors
+    /// - `Export` - writing to `writer` failed
+    pub fn to_graphml<W: Write>(&self, writer: W) -> Result<()> {
+        self.to_graphml_with(writer, &GraphmlOptions::default())
+    }
+
+    /// `to_graphml` with `options`.
+    ///
+    /// With `link_reuse`, the nodes of reused addresses come after those of the
+    /// outputs, identified as `script:<hex>` and ordered as `address_reuse` returns
+    /// them, and their `reuse` edges after the outputs, identified as
+    /// `reuse:<outpoint>`. `occurrences` is the number of outputs paying the address.
+    ///
+    /// # Errors
+    /// - `Export` - writing to `writer` failed
+    pub fn to_graphml_with<W: Write>(&self, mut writer: W, options: &GraphmlOptions) -> Result<()> {
+        self.write_graphml(&mut writer, options).map_err(export)?;
+        writer.flush().map_err(export)
+    }
+
+    fn write_graphml<W: Write>(
+        &self,
+        xml: &mut W,
+        options: &GraphmlOptions,
+    ) -> std::io::Result<()> {
+        writeln!(xml, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+        writeln!(
+            xml,
+            r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns" xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance" xsi:schemaLocation="http://graphml.graphdrawing.org/xmlns http://graphml.graphdrawing.org/xmlns/1.0/graphml.xsd">"#
+        )?;
+        for (domain, keys) in [("node", &NODE_KEYS[..]), ("edge", &EDGE_KEYS[..])] {
+            for (id, name, kind) in keys {
+                writeln!(
+                    xml,
+                    r#"  <key id="{}" for="{}" attr.name="{}" attr.type="{}"/>"#,
+                    id,
+                    domain,
+                    name,
+                    kind.name()
+                )?;
+            }
+        }
+        writeln!(xml, r#"  <graph id="trace" edgedefault="directed">"#)?;
+
+        for node in self.nodes() {
+            write_node(xml, node)?;
+        }
+        for edge in self.edges().filter(|edge| edge.spent_by.is_none()) {
+            writeln!(xml, r#"    <node id="{}">"#, edge.outpoint)?;
+            data(xml, "n_kind", "output")?;
+            data(xml, "n_label", escape(&terminal(edge).unwrap_or_default()))?;
+            data(xml, "n_value_sat", edge.value.to_sat())?;
+            if let Some(terminal) = terminal(edge) {
+                data(xml, "n_terminal", escape(&terminal))?;
+            }
+            if let Some(address) = &edge.address {
+                data(xml, "n_address", address)?;
+            }
+            if let Some(carrier) = DataCarrier::from_script(edge.outpoint.vout, &edge.script_pubkey)
+            {
+                data(xml, "n_data", escape(&carrier.describe()))?;
+            }
+            if let Some(label) = self.label_of(&edge.outpoint) {
+                data(xml, "n_entity", escape(&label.entity))?;
+                data(xml, "n_category", label.category.code())?;
+            }
+            writeln!(xml, "    </node>")?;
+        }
+        let reuse = match options.link_reuse {
+            true => self.address_reuse(),
+            false => Vec::new(),
+        };
+        for reuse in &reuse {
+            write_address(xml, reuse)?;
+        }
+        for speculative in self.speculative_edges() {
+            writeln!(xml, r#"    <node id="candidate:{}">"#, speculative.outpoint)?;
+            data(xml, "n_kind", "candidate")?;
+            data(xml, "n_label", "candidate")?;
+            data(xml, "n_value_sat", speculative.value.to_sat())?;
+            if let Some(address) = &speculative.address {
+                data(xml, "n_address", address)?;
+            }
+            writeln!(xml, "    </node>")?;
+        }
+        for edge in self.edges() {
+            write_edge(xml, edge, self.label_of(&edge.outpoint))?;
+        }
+        for reuse in &reuse {
+            let id = reuse_id(reuse);
+            for occurrence in &reuse.occurrences {
+                writeln!(
+                    xml,
+                    r#"    <edge id="reuse:{}" source="{}" target="{}">"#,
+                    occurrence.outpoint, occurrence.outpoint.txid, id
+                )?;
+                data(xml, "e_kind", "reuse")?;
+                data(xml, "e_outpoint", occurrence.outpoint)?;
+                data(xml, "e_value_sat", occurrence.value.to_sat())?;
+                data(xml, "e_value_btc", btc(occurrence.value))?;
+                writeln!(xml, "    </edge>")?;
+            }
+        }
+        for speculative in self.speculative_edges() {
+            writeln!(
+                xml,
+                r#"    <edge id="speculative:{}:{}" source="{}" target="candidate:{}">"#,
+                speculative.deposit,
+                speculative.outpoint,
+                speculative.deposit,
+                speculative.outpoint
+            )?;
+            data(xml, "e_kind", "speculative")?;
+            data(xml, "e_outpoint", speculative.outpoint)?;
+            data(xml, "e_value_sat", speculative.value.to_sat())?;
+            data(xml, "e_value_btc", btc(speculative.value))?;
+            if let Some(address) = &speculative.address {
+                data(xml, "e_address", address)?;
+            }
+            data(xml, "e_confidence", speculative.confidence)?;
+            writeln!(xml, "    </edge>")?;
+        }
+
+        writeln!(xml, "  </graph>")?;
+        writeln!(xml, "</graphml>")
+    }
+}
+
+fn write_node<W: Write>(xml: &mut W, node: &TraceNode) -> std::io::Result<()> {
+    writeln!(xml, r#"    <node id="{}">"#, node.txid)?;
+    data(xml, "n_kind", "transaction")?;
+    let txid = node.txid.to_string();
+    data(
+        xml,
+        "n_label",
+        format!("{}..{}", &txid[..8], &txid[txid.len() - 4..]),
+    )?;
+    data(xml, "n_txid", &txid)?;
+    data(xml, "n_depth", node.depth)?;
+    if let Some(height) = node.height {
+        data(xml, "n_height", height)?;
+    }
+    if let Some(timestamp) = node.timestamp {
+        data(xml, "n_timestamp", timestamp)?;
+    }
+    data(xml, "n_value_sat", node.output_value.to_sat())?;
+    if let Some(fee) = node.fee {
+        data(xml, "n_fee_sat", fee.to_sat())?;
+    }
+    data(xml, "n_coinbase", node.coinbase)?;
+    data(xml, "n_coinjoin", node.coinjoin.is_some())?;
+    data(xml, "n_pattern", node.pattern.code())?;
+    if let Some(channel) = &node.lightning {
+        data(xml, "n_lightning", channel.code())?;
+    }
+    data(xml, "n_frontier", node.frontier)?;
+    data(xml, "n_truncated", node.truncated)?;
+    data(xml, "n_unspent", node.unspent)?;
+    writeln!(xml, "    </node>")
+}
+
+fn write_edge<W: Write>(
+    xml: &mut W,
+    edge: &TraceEdge,
+    label: Option<&Label>,
+) -> std::io::Result<()> {
+    let target = match edge.spent_by {
+        Some(txid) => txid.to_string(),
+        None => edge.outpoint.to_string(),
+    };
+    writeln!(
+        xml,
+        r#"    <edge id="{}" source="{}" target="{}">"#,
+        edge.outpoint,
+        edge.from(),
+        target
+    )?;
+    data(xml, "e_kind", "output")?;
+    data(xml, "e_outpoint", edge.outpoint)?;
+    data(xml, "e_value_sat", edge.value.to_sat())?;
+    data(xml, "e_value_btc", btc(edge.value))?;
+    if let Some(address) = &edge.address {
+        data(xml, "e_address", address)?;
+    }
+    if let Some(terminal) = terminal(edge) {
+        data(xml, "e_terminal", escape(&terminal))?;
+    }
+    if let Some(label) = label {
+        data(xml, "e_entity", escape(&label.entity))?;
+        data(xml, "e_category", label.category.code())?;
+    }
+    writeln!(xml, "    </edge>")
+}
+
+fn write_address<W: Write>(xml: &mut W, reuse: &AddressReuse) -> std::io::Result<()> {
+    writeln!(xml, r#"    <node id="{}">"#, reuse_id(reuse))?;
+    data(xml, "n_kind", "address")?;
+    match &reuse.address {
+        Some(address) => {
+            data(xml, "n_label", address)?;
+            data(xml, "n_address", address)?;
+        }
+        None => data(xml, "n_label", reuse.script_pubkey.to_hex_string())?,
+    }
+    data(xml, "n_occurrences", reuse.occurrences.len())?;
+    data(xml, "n_round_trip", reuse.round_trip)?;
+    writeln!(xml, "    </node>")
+}
+
+/// One attribute value. Only values that may hold markup characters need escaping:
+/// numbers, txids, outpoints and addresses cannot.
+fn data<W: Write>(xml: &mut W, key: &str, value: impl std::fmt::Display) -> std::io::Result<()> {
+    writeln!(xml, r#"      <data key="{}">{}</data>"#, key, value)
+}
+
+/// Reason code of a leaf, with its detail
+fn terminal(edge: &TraceEdge) -> Option<String> {
+    edge.terminal.as_ref().map(|reason| match reason.detail() {
+        Some(detail) => format!("{}: {}", reason.code(), detail),
+        None => reason.code().to_string(),
+    })
+}
+
+/// Decimal BTC with all eight places, without the unit
+fn btc(amount: Amount) -> String {
+    format!("{:.8}", amount.display_in(Denomination::Bitcoin))
+}
+
+/// Escapes `text` for XML character data and attribute values.
+///
+/// Control characters XML 1.0 cannot represent at all are dropped.
+pub(crate) fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\t' | '\n' | '\r' => escaped.push(c),
+            c if c.is_control() => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn export(error: std::io::Error) -> TracerError {
+    TracerError::Export(::csv::Error::from(error))
+}
+
+#[cfg(all(test, feature = "trace-engine"))]
+mod tests {
+    use super::*;
+    use crate::tracer::{
+        TerminalReason, TraceConfig, Tracer,
+        fixtures::{MockDataSource, reused, sample_graph, script},
+    };
+    use bitcoin::OutPoint;
+    use roxmltree::{Document, Node};
+    use std::collections::HashMap;
+
+    fn graphml(graph: &TraceGraph) -> String {
+        let mut bytes = Vec::new();
+        graph.to_graphml(&mut bytes).unwrap();
+        String::from_utf8(bytes).unwrap()
+    }
+
+    /// Values of the `data` children of `element`, by attribute name
+    fn attributes<'a>(
+        keys: &HashMap<&str, &'a str>,
+        element: Node<'a, '_>,
+    ) -> HashMap<&'a str, &'a str> {
+        element
+            .children()
+            .filter(|child| child.has_tag_name("data"))
+            .map(|data| {
+                (
+                    keys[data.attribute("key").unwrap()],
+                    data.text().unwrap_or(""),
+                )
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_parses_with_declared_attributes() {
+        let mut graph = sample_graph().await;
+        let change = graph
+            .edges()
+            .find(|edge| edge.value == Amount::from_sat(39_000))
+            .unwrap()
+            .clone();
+        graph.insert_edge(change.clone().terminal(TerminalReason::Exchange(
+            "A&B <\"hot\"> 'wallet'\u{1}".to_string(),
+        )));
+        let xml = graphml(&graph);
+
+        let document = Document::parse(&xml).unwrap();
+        let root = document.root_element();
+        assert!(root.has_tag_name("graphml"));
+        let keys: HashMap<&str, &str> = root
+            .children()
+            .filter(|child| child.has_tag_name("key"))
+            .map(|key| {
+                (
+                    key.attribute("id").unwrap(),
+                    key.attribute("attr.name").unwrap(),
+                )
+            })
+            .collect();
+        let types: HashMap<&str, &str> = root
+            .children()
+            .filter(|child| child.has_tag_name("key"))
+            .map(|key| {
+                (
+                    key.attribute("id").unwrap(),
+                    key.attribute("attr.type").unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(types["e_value_sat"], "long");
+        assert_eq!(types["n_timestamp"], "long");
+        assert_eq!(types["n_coinjoin"], "boolean");
+        let elements: Vec<_> = root.descendants().collect();
+        let nodes: Vec<_> = elements.iter().filter(|e| e.has_tag_name("node")).collect();
+        let edges: Vec<_> = elements.iter().filter(|e| e.has_tag_name("edge")).collect();
+        // 3 transactions and 3 leaves, one edge per output
+        assert_eq!(nodes.len(), 6);
+        assert_eq!(edges.len(), graph.edges().count());
+        // Every data element refers to a declared key
+        assert!(
+            elements
+                .iter()
+                .filter(|e| e.has_tag_name("data"))
+                .all(|data| keys.contains_key(data.attribute("key").unwrap()))
+        );
+
+        let split = graph.nodes().find(|node| node.timestamp.is_some()).unwrap();
+        let node = nodes
+            .iter()
+            .find(|node| node.attribute("id") == Some(&split.txid.to_string()))
+            .unwrap();
+        let values = attributes(&keys, **node);
+        assert_eq!(values["kind"], "transaction");
+        assert_eq!(values["timestamp"], "1700000000");
+        assert_eq!(values["value_sat"], "99300");
+        assert_eq!(values["fee_sat"], "700");
+        assert_eq!(values["coinbase"], "false");
+        assert!(!values.contains_key("height"));
+
+        let edge = edges
+            .iter()
+            .find(|edge| edge.attribute("id") == Some(&change.outpoint.to_string()))
+            .unwrap();
+        assert_eq!(
+            edge.attribute("target"),
+            Some(change.outpoint.to_string().as_str())
+        );
+        let values = attributes(&keys, **edge);
+        assert_eq!(values["value_sat"], "39000");
+        assert_eq!(values["value_btc"], "0.00039000");
+        assert_eq!(values["address"], change.address.unwrap().to_string());
+        assert_eq!(values["terminal"], "exchange: A&B <\"hot\"> 'wallet'");
+    }
+
+    #[tokio::test]
+    async fn test_reused_addresses_are_linked() {
+        let txs = reused();
+        let graph = Tracer::new(MockDataSource::new(&txs))
+            .trace_forward(
+                OutPoint::new(txs[0].compute_txid(), 0),
+                &TraceConfig::default(),
+            )
+            .await
+            .unwrap()
+            .into_graph();
+        let mut bytes = Vec::new();
+        graph
+            .to_graphml_with(&mut bytes, &GraphmlOptions::default().link_reuse(true))
+            .unwrap();
+        let xml = String::from_utf8(bytes).unwrap();
+
+        let document = Document::parse(&xml).unwrap();
+        let keys: HashMap<&str, &str> = document
+            .descendants()
+            .filter(|element| element.has_tag_name("key"))
+            .map(|key| {
+                (
+                    key.attribute("id").unwrap(),
+                    key.attribute("attr.name").unwrap(),
+                )
+            })
+            .collect();
+        let id = format!("script:{}", script(40).to_hex_string());
+        let address = document
+            .descendants()
+            .find(|element| element.has_tag_name("node") && element.attribute("id") == Some(&id))
+            .unwrap();
+        let values = attributes(&keys, address);
+        assert_eq!(values["kind"], "address");
+        assert_eq!(values["occurrences"], "2");
+        assert_eq!(values["round_trip"], "true");
+        let links: Vec<_> = document
+            .descendants()
+            .filter(|element| {
+                element.has_tag_name("edge") && element.attribute("target") == Some(&id)
+            })
+            .map(|edge| edge.attribute("source").unwrap().to_string())
+            .collect();
+        assert_eq!(
+            links,
+            [
+                txs[1].compute_txid().to_string(),
+                txs[4].compute_txid().to_string()
+            ]
+        );
+        assert!(!graphml(&graph).contains(&id));
+    }
+
+    #[test]
+    fn test_empty_graph_is_valid() {
+        let xml = graphml(&TraceGraph::new());
+        let document = Document::parse(&xml).unwrap();
+        let graph = document
+            .descendants()
+            .find(|element| element.has_tag_name("graph"))
+            .unwrap();
+        assert_eq!(graph.attribute("edgedefault"), Some("directed"));
+        assert_eq!(
+            graph.children().filter(|child| child.is_element()).count(),
+            0
+        );
+    }
+}
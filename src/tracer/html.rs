@@ -0,0 +1,563 @@
+//! Self-contained HTML report of a trace, to share with readers who have no tool to
+//! open the other exports.
+//!
+//! The report is a single file that makes no request over the network: its styles
+//! and the script sorting its tables are inline, and the graph is an SVG laid out
+//! without Graphviz, one column per depth. It holds the summary of the trace and what
+//! it cost, the outputs the trace stopped at, the known entities it reached, the
+//! annotations of its transactions and, up to a size, the full list of its outputs.
+//!
+//! Every string that did not come from the chain (labels, annotations, pool tags,
+//! terminal details and the title) is escaped.
+
+use crate::tracer::{
+    TraceEdge, TraceGraph, TraceNode, TraceReport, TraceSummary,
+    dot::{short_txid, utc},
+    graphml::escape,
+    summary::btc,
+};
+use bitcoin::Amount;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// Outputs a graph can have for `TraceReport::to_html` to list them all by default
+pub const DEFAULT_EDGE_LIST_LIMIT: usize = 500;
+
+/// Width of a column of the graph, one per depth
+const COLUMN_WIDTH: usize = 220;
+/// Height of a row of the graph, one per transaction of a depth
+const ROW_HEIGHT: usize = 56;
+const BOX_WIDTH: usize = 160;
+const BOX_HEIGHT: usize = 32;
+const MARGIN: usize = 20;
+
+const STYLE: &str = "\
+body { font-family: sans-serif; margin: 2em; color: #222; }
+table { border-collapse: collapse; margin-bottom: 1.5em; }
+th, td { border: 1px solid #ccc; padding: 0.3em 0.6em; text-align: left; }
+td.amount { text-align: right; font-family: monospace; }
+td.id { font-family: monospace; }
+table.sortable th { cursor: pointer; background: #f0f0f0; }
+svg text { font-family: monospace; font-size: 11px; }
+svg rect { fill: #fff; stroke: #333; }
+svg rect.frontier { stroke-dasharray: 4 2; }
+svg rect.coinjoin { fill: #ddd; }
+svg rect.labelled { stroke: orange; stroke-width: 3; }
+svg line { stroke: #888; }
+";
+
+/// Sorts a table by the column whose header is clicked, by `data-sort` where a cell
+/// has one, numerically when both cells are numbers
+const SORT_SCRIPT: &str = "\
+for (const table of document.querySelectorAll(\"table.sortable\")) {
+  table.querySelectorAll(\"th\").forEach((th, column) => {
+    th.addEventListener(\"click\", () => {
+      const ascending = th.dataset.order !== \"asc\";
+      th.dataset.order = ascending ? \"asc\" : \"desc\";
+      const key = (row) => row.cells[column].dataset.sort ?? row.cells[column].textContent;
+      const body = table.tBodies[0];
+      const rows = Array.from(body.rows).sort((a, b) => {
+        const [x, y] = [key(a), key(b)];
+        const order = isNaN(x) || isNaN(y) ? x.localeCompare(y) : x - y;
+        return ascending ? order : -order;
+      });
+      body.append(...rows);
+    });
+  });
+}
+";
+
+/// Options of `TraceReport::to_html`.
+///
+/// # Fields
+/// * `title` - heading and title of the page
+/// * `edge_list_limit` - outputs a graph can have for the report to list them all
+/// * `full_edge_list` - whether every output is listed, however many there are
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HtmlOptions {
+    pub title: String,
+    pub edge_list_limit: usize,
+    pub full_edge_list: bool,
+}
+
+impl Default for HtmlOptions {
+    fn default() -> Self {
+        Self {
+            title: "Trace report".to_string(),
+            edge_list_limit: DEFAULT_EDGE_LIST_LIMIT,
+            full_edge_list: false,
+        }
+    }
+}
+
+impl HtmlOptions {
+    /// Heading and title of the page
+    pub fn title(mut self, title: impl Into<String>) -> Self {
+        self.title = title.into();
+        self
+    }
+
+    /// Outputs a graph can have for the report to list them all
+    pub fn edge_list_limit(mut self, edge_list_limit: usize) -> Self {
+        self.edge_list_limit = edge_list_limit;
+        self
+    }
+
+    /// Lists every output, however many there are
+    pub fn full_edge_list(mut self, full_edge_list: bool) -> Self {
+        self.full_edge_list = full_edge_list;
+        self
+    }
+}
+
+impl TraceReport {
+    /// Renders `graph`, usually `self.graph()`, with what the trace cost as a single
+    /// HTML page.
+    ///
+    /// Rows come out in a fixed order (outputs the trace stopped at largest first,
+    /// the rest in txid and outpoint order) and the page embeds no time of its own,
+    /// so the same report always renders to the same text. The list of every output
+    /// is left out of graphs of more than `edge_list_limit` outputs, unless
+    /// `full_edge_list` is set.
+    pub fn to_html(&self, graph: &TraceGraph, options: HtmlOptions) -> String {
+        let summary = TraceSummary::from_graph(graph);
+        let mut html = String::new();
+        html.push_str("<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n");
+        html.push_str("<meta charset=\"utf-8\">\n");
+        let _ = writeln!(html, "<title>{}</title>", escape(&options.title));
+        let _ = writeln!(html, "<style>\n{}</style>\n</head>\n<body>", STYLE);
+        let _ = writeln!(html, "<h1>{}</h1>", escape(&options.title));
+
+        self.summary_section(&mut html, &summary);
+        frontier_section(&mut html, graph);
+        entities_section(&mut html, graph);
+        annotations_section(&mut html, graph);
+        let _ = writeln!(html, "<h2>Graph</h2>\n{}", svg(graph));
+        let edges = graph.edges().count();
+        if options.full_edge_list || edges <= options.edge_list_limit {
+            edges_section(&mut html, graph);
+        } else {
+            let _ = writeln!(
+                html,
+                "<p>{} outputs, too many to list; see the CSV or JSON export.</p>",
+                edges
+            );
+        }
+
+        let _ = writeln!(html, "<script>\n{}</script>\n</body>\n</html>", SORT_SCRIPT);
+        html
+    }
+
+    fn summary_section(&self, html: &mut String, summary: &TraceSummary) {
+        let mut rows = vec![
+            (
+                "Outcome",
+                if self.outcome.is_cancelled() {
+                    "cancelled"
+                } else {
+                    "complete"
+                }
+                .to_string(),
+            ),
+            ("Transactions", summary.transactions.to_string()),
+            ("Outputs", summary.edges.to_string()),
+            ("Addresses", summary.addresses.to_string()),
+            (
+                "Transactions by depth",
+                summary
+                    .depths
+                    .iter()
+                    .map(|(depth, count)| format!("{}: {}", depth, count))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            ),
+            (
+                "Block times",
+                match (summary.first_seen, summary.last_seen) {
+                    (Some(first), Some(last)) => format!("{} to {}", utc(first), utc(last)),
+                    _ => "unknown".to_string(),
+                },
+            ),
+            (
+                "Fees paid",
+                match summary.unknown_fees {
+                    0 => btc(summary.fees),
+                    unknown => format!(
+                        "{} (not counting {} without a known fee)",
+                        btc(summary.fees),
+                        unknown
+                    ),
+                },
+            ),
+            ("Requests", self.requests.to_string()),
+            ("Cache hits", self.cache_hits.to_string()),
+            ("Time taken", format!("{:.3} s", self.elapsed.as_secs_f64())),
+        ];
+        if let Some(estimated) = self.estimated_requests {
+            rows.push(("Estimated requests", estimated.to_string()));
+        }
+        if self.ignored_outputs > 0 {
+            rows.push((
+                "Ignored outputs",
+                format!("{} ({})", self.ignored_outputs, btc(self.ignored_value)),
+            ));
+        }
+        if self.skipped_expansions > 0 {
+            rows.push((
+                "Skipped expansions",
+                format!("{} (visited by earlier traces)", self.skipped_expansions),
+            ));
+        }
+        if let Some(provenance) = self.provenance() {
+            rows.push(("Mined by", provenance.to_string()));
+        }
+
+        html.push_str("<h2>Summary</h2>\n<table>\n<tbody>\n");
+        for (name, value) in rows {
+            let _ = writeln!(
+                html,
+                "<tr><th>{}</th><td>{}</td></tr>",
+                name,
+                escape(&value)
+            );
+        }
+        html.push_str("</tbody>\n</table>\n");
+
+        if summary.frontier_value.is_empty() {
+            return;
+        }
+        html.push_str("<h3>Value at the frontier</h3>\n<table>\n");
+        html.push_str(
+            "<thead><tr><th>Reason</th><th>Outputs</th><th>Value</th></tr></thead>\n<tbody>\n",
+        );
+        for (reason, value) in &summary.frontier_value {
+            let _ = writeln!(
+                html,
+                "<tr><td>{}</td><td>{}</td>{}</tr>",
+                reason,
+                summary.frontier_outputs.get(reason).copied().unwrap_or(0),
+                amount_cell(*value)
+            );
+        }
+        html.push_str("</tbody>\n</table>\n");
+    }
+}
+
+/// Every output the trace stopped at, largest first
+fn frontier_section(html: &mut String, graph: &TraceGraph) {
+    let mut leaves: Vec<&TraceEdge> = graph
+        .edges()
+        .filter(|edge| edge.terminal.is_some())
+        .collect();
+    // Edges come in outpoint order, which breaks ties
+    leaves.sort_by_key(|edge| std::cmp::Reverse(edge.value));
+    html.push_str("<h2>Outputs at the frontier</h2>\n");
+    if leaves.is_empty() {
+        html.push_str("<p>None.</p>\n");
+        return;
+    }
+    html.push_str("<table class=\"sortable\">\n<thead><tr><th>Output</th><th>Address</th>");
+    html.push_str("<th>Value</th><th>Reason</th><th>Entity</th></tr></thead>\n<tbody>\n");
+    for edge in leaves {
+        let _ = writeln!(
+            html,
+            "<tr><td class=\"id\">{}</td><td class=\"id\">{}</td>{}<td>{}</td><td>{}</td></tr>",
+            edge.outpoint,
+            address(edge),
+            amount_cell(edge.value),
+            escape(&terminal(edge)),
+            graph
+                .label_of(&edge.outpoint)
+                .map(|label| escape(&label.entity))
+                .unwrap_or_default(),
+        );
+    }
+    html.push_str("</tbody>\n</table>\n");
+}
+
+/// Known entities the outputs of the graph pay, with how many outputs and how much
+fn entities_section(html: &mut String, graph: &TraceGraph) {
+    let mut entities: BTreeMap<(&str, &str), (usize, Amount)> = BTreeMap::new();
+    for edge in graph.edges() {
+        if let Some(label) = graph.label_of(&edge.outpoint) {
+            let (outputs, value) = entities
+                .entry((label.entity.as_str(), label.category.code()))
+                .or_insert((0, Amount::ZERO));
+            *outputs += 1;
+            *value = value.checked_add(edge.value).unwrap_or(Amount::MAX);
+        }
+    }
+    if entities.is_empty() {
+        return;
+    }
+    html.push_str("<h2>Entities reached</h2>\n<table class=\"sortable\">\n");
+    html.push_str("<thead><tr><th>Entity</th><th>Category</th><th>Outputs</th>");
+    html.push_str("<th>Value</th></tr></thead>\n<tbody>\n");
+    for ((entity, category), (outputs, value)) in entities {
+        let _ = writeln!(
+            html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td>{}</tr>",
+            escape(entity),
+            category,
+            outputs,
+            amount_cell(value),
+        );
+    }
+    html.push_str("</tbody>\n</table>\n");
+}
+
+/// What the trace's annotators found, by transaction
+fn annotations_section(html: &mut String, graph: &TraceGraph) {
+    if graph.nodes().all(|node| node.annotations.is_empty()) {
+        return;
+    }
+    html.push_str("<h2>Annotations</h2>\n<table class=\"sortable\">\n");
+    html.push_str("<thead><tr><th>Transaction</th><th>Key</th><th>Value</th></tr></thead>\n");
+    html.push_str("<tbody>\n");
+    for node in graph.nodes() {
+        for (key, value) in &node.annotations {
+            let _ = writeln!(
+                html,
+                "<tr><td class=\"id\">{}</td><td>{}</td><td>{}</td></tr>",
+                node.txid,
+                escape(key),
+                escape(value),
+            );
+        }
+    }
+    html.push_str("</tbody>\n</table>\n");
+}
+
+/// Every output of the graph, in outpoint order
+fn edges_section(html: &mut String, graph: &TraceGraph) {
+    html.push_str("<h2>Outputs</h2>\n<table class=\"sortable\">\n<thead><tr><th>Output</th>");
+    html.push_str("<th>Spent by</th><th>Address</th><th>Value</th><th>Terminal</th>");
+    html.push_str("</tr></thead>\n<tbody>\n");
+    for edge in graph.edges() {
+        let _ = writeln!(
+            html,
+            "<tr><td class=\"id\">{}</td><td class=\"id\">{}</td><td class=\"id\">{}</td>{}\
+             <td>{}</td></tr>",
+            edge.outpoint,
+            edge.spent_by
+                .map(|txid| txid.to_string())
+                .unwrap_or_default(),
+            address(edge),
+            amount_cell(edge.value),
+            escape(&terminal(edge)),
+        );
+    }
+    html.push_str("</tbody>\n</table>\n");
+}
+
+/// The graph as an SVG: a column per depth, transactions in txid order down each
+/// column, and a line for each output spent within the graph. Hovering a transaction
+/// shows its txid, value and annotations.
+fn svg(graph: &TraceGraph) -> String {
+    let mut positions = BTreeMap::new();
+    let mut rows: BTreeMap<usize, usize> = BTreeMap::new();
+    for node in graph.nodes() {
+        let row = rows.entry(node.depth).or_default();
+        let x = MARGIN + node.depth * COLUMN_WIDTH;
+        let y = MARGIN + *row * ROW_HEIGHT;
+        positions.insert(node.txid, (x, y));
+        *row += 1;
+    }
+    let columns = rows.keys().last().map_or(0, |depth| depth + 1);
+    let width = 2 * MARGIN + columns.saturating_sub(1) * COLUMN_WIDTH + BOX_WIDTH;
+    let height = 2 * MARGIN + rows.values().max().copied().unwrap_or(0) * ROW_HEIGHT;
+
+    let mut svg = String::new();
+    let _ = writeln!(
+        svg,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{0}\" height=\"{1}\" \
+         viewBox=\"0 0 {0} {1}\">",
+        width, height
+    );
+    for edge in graph.edges() {
+        let (Some(from), Some(to)) = (
+            positions.get(&edge.from()),
+            edge.spent_by.and_then(|txid| positions.get(&txid)),
+        ) else {
+            continue;
+        };
+        let _ = writeln!(
+            svg,
+            "<line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\"><title>{} {}</title></line>",
+            from.0 + BOX_WIDTH,
+            from.1 + BOX_HEIGHT / 2,
+            to.0,
+            to.1 + BOX_HEIGHT / 2,
+            edge.outpoint,
+            btc(edge.value),
+        );
+    }
+    for node in graph.nodes() {
+        let (x, y) = positions[&node.txid];
+        let _ = writeln!(
+            svg,
+            "<g><title>{}</title><rect{} x=\"{}\" y=\"{}\" width=\"{}\" \
+             height=\"{}\"/><text x=\"{}\" y=\"{}\">{}</text></g>",
+            escape(&tooltip(node)),
+            classes(node),
+            x,
+            y,
+            BOX_WIDTH,
+            BOX_HEIGHT,
+            x + 8,
+            y + BOX_HEIGHT / 2 + 4,
+            short_txid(&node.txid),
+        );
+    }
+    svg.push_str("</svg>");
+    svg
+}
+
+/// Class attribute of the box of `node`, empty without any
+fn classes(node: &TraceNode) -> String {
+    let mut classes = Vec::new();
+    if node.frontier || node.truncated {
+        classes.push("frontier");
+    }
+    if node.coinjoin.is_some() {
+        classes.push("coinjoin");
+    }
+    if !node.labels.is_empty() {
+        classes.push("labelled");
+    }
+    if classes.is_empty() {
+        String::new()
+    } else {
+        format!(" class=\"{}\"", classes.join(" "))
+    }
+}
+
+/// Txid, value and annotations of `node`, one per line
+fn tooltip(node: &TraceNode) -> String {
+    let mut lines = vec![node.txid.to_string(), btc(node.output_value)];
+    if let Some(timestamp) = node.timestamp {
+        lines.push(utc(timestamp));
+    }
+    for (vout, label) in &node.labels {
+        lines.push(format!("output {}: {}", vout, label));
+    }
+    for (key, value) in &node.annotations {
+        lines.push(format!("{}: {}", key, value));
+    }
+    lines.join("\n")
+}
+
+/// A cell of `value` in BTC, sorted by its value in sats
+fn amount_cell(value: Amount) -> String {
+    format!(
+        "<td class=\"amount\" data-sort=\"{}\">{}</td>",
+        value.to_sat(),
+        btc(value)
+    )
+}
+
+fn address(edge: &TraceEdge) -> String {
+    edge.address
+        .as_ref()
+        .map(|address| address.to_string())
+        .unwrap_or_default()
+}
+
+/// Reason code of a terminal output, with its detail after a colon
+fn terminal(edge: &TraceEdge) -> String {
+    match &edge.terminal {
+        Some(reason) => match reason.detail() {
+            Some(detail) => format!("{}: {}", reason.code(), detail),
+            None => reason.code().to_string(),
+        },
+        None => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tracer::{EntityCategory, Label, TraceOutcome, fixtures::sample_graph};
+    use std::time::Duration;
+
+    const GOLDEN: &str = "src/tracer/testdata/report.html";
+
+    fn report(graph: TraceGraph) -> TraceReport {
+        TraceReport {
+            outcome: TraceOutcome::Complete(graph),
+            requests: 3,
+            cache_hits: 1,
+            elapsed: Duration::from_millis(1_250),
+            estimated_requests: None,
+            ignored_outputs: 0,
+            ignored_value: Amount::ZERO,
+            skipped_expansions: 0,
+        }
+    }
+
+    /// The sample graph, its 39_000 sats output paying `entity`, annotated with
+    /// `annotation`
+    async fn labelled(entity: &str, annotation: &str) -> TraceGraph {
+        let mut graph = sample_graph().await;
+        let change = graph
+            .edges()
+            .find(|edge| edge.value == Amount::from_sat(39_000))
+            .unwrap()
+            .clone();
+        let label = Label {
+            entity: entity.to_string(),
+            category: EntityCategory::Exchange,
+            source: None,
+            confidence: 1.0,
+        };
+        graph.insert_edge(change.clone().terminal(label.terminal_reason()));
+        let node = graph.node_mut(&change.from()).unwrap();
+        node.labels.insert(change.outpoint.vout, label);
+        node.annotations
+            .insert("note.text".to_string(), annotation.to_string());
+        graph
+    }
+
+    #[tokio::test]
+    async fn test_html_matches_golden_file() {
+        let graph = labelled("Kraken", "round amounts").await;
+        let html = report(graph.clone()).to_html(&graph, HtmlOptions::default());
+
+        // UPDATE_GOLDEN=1 cargo test rewrites the file after an intended change
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            std::fs::write(GOLDEN, &html).unwrap();
+        }
+        assert_eq!(html, include_str!("testdata/report.html"));
+        // Nothing loaded from elsewhere
+        assert!(!html.contains("src="));
+        assert!(!html.contains("href="));
+    }
+
+    #[tokio::test]
+    async fn test_hostile_strings_are_escaped() {
+        let hostile = "<script>alert(\"pwned\")</script>";
+        let graph = labelled(hostile, hostile).await;
+        let options = HtmlOptions::default().title(hostile);
+        let html = report(graph.clone()).to_html(&graph, options);
+
+        // Only the report's own script
+        assert_eq!(html.matches("<script>").count(), 1);
+        assert!(!html.contains("alert(\"pwned\")"));
+        assert!(html.contains("&lt;script&gt;alert(&quot;pwned&quot;)&lt;/script&gt;"));
+    }
+
+    #[tokio::test]
+    async fn test_edge_list_is_left_out_of_big_graphs() {
+        let graph = sample_graph().await;
+        let report = report(graph.clone());
+        let listed = |options| report.to_html(&graph, options).contains("<h2>Outputs</h2>");
+
+        assert!(listed(HtmlOptions::default()));
+        assert!(!listed(HtmlOptions::default().edge_list_limit(4)));
+        assert!(listed(
+            HtmlOptions::default()
+                .edge_list_limit(4)
+                .full_edge_list(true)
+        ));
+    }
+}
@@ -0,0 +1,887 @@
+//! Versioned JSON export and import of a `TraceGraph`, for consumers outside Rust.
+//!
+//! The schema is independent of the in-memory types, so it only changes on purpose.
+//! Version 1:
+//!
+//! ```json
+//! {
+//!   "version": 1,
+//!   "nodes": [{
+//!     "txid": "<hex>",
+//!     "depth": 0,
+//!     "height": 800000,              // or null
+//!     "timestamp": 1700000000,       // unix seconds, or null
+//!     "input_value_sat": 100000,     // or null when not every prevout is known
+//!     "output_value_sat": 99000,
+//!     "fee_sat": 1000,               // or null when not every prevout is known
+//!     "vsize": 141,                  // vbytes
+//!     "feerate_sat_vb": 7.09,        // fee_sat / vsize, or null; ignored on import
+//!     "coinbase": false,
+//!     "frontier": false,
+//!     "truncated": false,
+//!     "unspent": true,
+//!     "replaceable": false,          // signals RBF, not known to be confirmed
+//!     "coinjoin": {                  // or null when not flagged as a CoinJoin
+//!       "kind": "whirlpool",         // whirlpool, wasabi or generic
+//!       "score": 0.93,
+//!       "denomination_sat": 5000000,
+//!       "equal_outputs": 5
+//!     },
+//!     "seeds": [0, 2],               // outpoints of a multi-source trace reaching it
+//!     "data_carriers": [{            // OP_RETURN outputs of the transaction
+//!       "vout": 1,
+//!       "payload_hex": "<hex>",
+//!       "protocol": "omni",          // or null when not a known protocol
+//!       "preview": "..."             // or null when not printable ASCII
+//!     }],
+//!     "labels": [{                   // outputs paying known entities
+//!       "vout": 0,
+//!       "entity": "Binance",
+//!       "category": "exchange",      // see below
+//!       "source": "walletexplorer",  // or null
+//!       "confidence": 0.9            // 0.0 to 1.0
+//!     }],
+//!     "pattern": {                   // or null when not classified
+//!       "kind": "batch_payout",      // see below
+//!       "count": 25                  // inputs of a sweep, outputs of a batch payout
+//!     },
+//!     "lightning": {                 // or null when not part of a Lightning channel
+//!       "kind": "lightning_open",    // lightning_open or lightning_close
+//!       "vout": 0,                   // funding output of an open, omitted for a close
+//!       "forced": true,              // unilateral close, omitted for an open
+//!       "funding_pubkeys": ["<hex>"] // funding multisig keys, empty when not revealed
+//!     },
+//!     "origin": {                    // or null unless a coinbase
+//!       "height": 812345,            // from the scriptSig (BIP 34), or null
+//!       "pool": "Foundry USA",       // or null when not known
+//!       "tag_hex": "<hex>"           // scriptSig after the height
+//!     },
+//!     "annotations": {               // findings of custom annotators
+//!       "round.0": "1.00000000 BTC"  // under <annotator>.<key>
+//!     }
+//!   }],
+//!   "edges": [{
+//!     "txid": "<hex>",               // transaction creating the output
+//!     "vout": 0,
+//!     "spent_by": "<hex>",           // or null for a leaf
+//!     "value_sat": 60000,
+//!     "script_pubkey": "<hex>",
+//!     "address": "bc1q...",          // or null for non-standard scripts
+//!     "terminal": {"reason": "exchange", "detail": "..."}, // or null, detail optional
+//!     // an error terminal also has the failed lookup, as "context": {"operation":
+//!     // "get_spending_transaction", "outpoint": "<hex>:0", "url": "..."}, ids and url optional
+//!     "change": {                    // or null when its transaction was not scored
+//!       "score": 0.81,               // 0.0 to 1.0
+//!       "change": true,              // scored highest among its transaction's outputs
+//!       "confidence": "high"         // low, medium or high
+//!     }
+//!   }],
+//!   "speculative_edges": [{          // continuations proposed by value matching
+//!     "deposit_txid": "<hex>",       // leaf of the graph they continue from
+//!     "deposit_vout": 1,
+//!     "txid": "<hex>",               // candidate output
+//!     "vout": 3,
+//!     "value_sat": 59000,
+//!     "address": "bc1q...",          // or null for non-standard scripts
+//!     "delay_secs": 7200,            // from the deposit's block to the candidate's
+//!     "confidence": 0.82             // 0.0 to 1.0
+//!   }]
+//! }
+//! ```
+//!
+//! Amounts are integer satoshis. Terminal reasons are `unspent`, `max_depth`,
+//! `max_transactions`, `max_breadth`, `not_followed`, `peeled`, `coinjoin`,
+//! `lightning_channel`, `data_carrier`, `reached_target` (detail: the target script as hex), `cancelled`,
+//! `budget_exhausted`, `below_min_value`, `out_of_window`, `visited`, `exchange`, `mixer`,
+//! `sanctioned`, `data_unavailable`, `error` (detail: the error) and `other`. Entity categories are `exchange`,
+//! `mixer`, `merchant`, `service`, `gambling`, `sanctioned` and `other`. Patterns are
+//! `sweep`, `batch_payout`, `simple_payment`, `self_transfer` and `unknown`. An
+//! unknown reason or entity category is read back as `other`, an unknown CoinJoin kind
+//! as `generic`, an unknown confidence as `low`, an unknown pattern as `unknown`, an
+//! unknown Lightning kind as null. Fields unknown to this version are ignored on
+//! import, and fields added to it are optional, so a version can gain fields without
+//! breaking readers on either side.
+//!
+//! A streamed trace is written one `TraceItem` per line instead (`to_json_line`): a
+//! node or an edge of the schema above, with a `"type"` of `"node"` or `"edge"`.
+
+use crate::blockchain::ErrorContext;
+use crate::tracer::{
+    ChangeScore, CoinJoinKind, CoinJoinVerdict, CoinbaseOrigin, Confidence, DataCarrier,
+    EntityCategory, Label, LightningChannel, Result, SpeculativeEdge, TerminalReason, TraceEdge,
+    TraceGraph, TraceItem, TraceNode, TracerError, TxPattern,
+};
+use bitcoin::{
+    Address, Amount, OutPoint, PublicKey, ScriptBuf, Txid,
+    address::NetworkUnchecked,
+    hex::{DisplayHex, FromHex},
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Schema version written by `TraceGraph::to_json`, the only one `from_json` reads
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct Document {
+    version: u32,
+    nodes: Vec<Node>,
+    edges: Vec<Edge>,
+    #[serde(default)]
+    speculative_edges: Vec<Speculative>,
+}
+
+/// A streamed node or edge, tagged with its kind
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Line {
+    Node(Node),
+    Edge(Edge),
+}
+
+/// Only the version, read first so an unknown version is reported as such rather than
+/// as whatever field it fails to parse
+#[derive(Deserialize)]
+struct Versioned {
+    version: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Node {
+    txid: Txid,
+    depth: usize,
+    height: Option<u32>,
+    timestamp: Option<u64>,
+    input_value_sat: Option<u64>,
+    output_value_sat: u64,
+    #[serde(default)]
+    fee_sat: Option<u64>,
+    #[serde(default)]
+    vsize: usize,
+    #[serde(default)]
+    feerate_sat_vb: Option<f64>,
+    coinbase: bool,
+    frontier: bool,
+    truncated: bool,
+    unspent: bool,
+    #[serde(default)]
+    replaceable: bool,
+    #[serde(default)]
+    coinjoin: Option<CoinJoin>,
+    #[serde(default)]
+    seeds: Vec<usize>,
+    #[serde(default)]
+    data_carriers: Vec<Carrier>,
+    #[serde(default)]
+    labels: Vec<EntityLabel>,
+    #[serde(default)]
+    pattern: Option<Pattern>,
+    #[serde(default)]
+    lightning: Option<Lightning>,
+    #[serde(default)]
+    origin: Option<Origin>,
+    #[serde(default)]
+    annotations: BTreeMap<String, String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Origin {
+    height: Option<u32>,
+    pool: Option<String>,
+    tag_hex: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Lightning {
+    kind: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    vout: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    forced: Option<bool>,
+    #[serde(default)]
+    funding_pubkeys: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Pattern {
+    kind: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    count: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct EntityLabel {
+    vout: u32,
+    entity: String,
+    category: String,
+    source: Option<String>,
+    confidence: f64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Carrier {
+    vout: u32,
+    payload_hex: String,
+    protocol: Option<String>,
+    preview: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CoinJoin {
+    kind: String,
+    score: f64,
+    denomination_sat: u64,
+    equal_outputs: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Edge {
+    txid: Txid,
+    vout: u32,
+    spent_by: Option<Txid>,
+    value_sat: u64,
+    script_pubkey: String,
+    address: Option<String>,
+    terminal: Option<Terminal>,
+    #[serde(default)]
+    change: Option<Change>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Speculative {
+    deposit_txid: Txid,
+    deposit_vout: u32,
+    txid: Txid,
+    vout: u32,
+    value_sat: u64,
+    address: Option<String>,
+    delay_secs: u64,
+    confidence: f64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Change {
+    score: f64,
+    change: bool,
+    confidence: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Terminal {
+    reason: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    detail: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    context: Option<ErrorContext>,
+}
+
+impl From<&TraceNode> for Node {
+    fn from(node: &TraceNode) -> Self {
+        Self {
+            txid: node.txid,
+            depth: node.depth,
+            height: node.height,
+            timestamp: node.timestamp,
+            input_value_sat: node.input_value.map(Amount::to_sat),
+            output_value_sat: node.output_value.to_sat(),
+            fee_sat: node.fee.map(Amount::to_sat),
+            vsize: node.vsize,
+            feerate_sat_vb: node.feerate(),
+            coinbase: node.coinbase,
+            frontier: node.frontier,
+            truncated: node.truncated,
+            unspent: node.unspent,
+            replaceable: node.replaceable,
+            coinjoin: node.coinjoin.as_ref().map(CoinJoin::from),
+            seeds: node.seeds.iter().copied().collect(),
+            data_carriers: node.data_carriers.iter().map(Carrier::from).collect(),
+            labels: node
+                .labels
+                .iter()
+                .map(|(vout, label)| EntityLabel {
+                    vout: *vout,
+                    entity: label.entity.clone(),
+                    category: label.category.code().to_string(),
+                    source: label.source.clone(),
+                    confidence: label.confidence,
+                })
+                .collect(),
+            pattern: (node.pattern != TxPattern::Unknown).then(|| Pattern {
+                kind: node.pattern.code().to_string(),
+                count: node.pattern.count(),
+            }),
+            lightning: node.lightning.as_ref().map(Lightning::from),
+            origin: node.origin.as_ref().map(|origin| Origin {
+                height: origin.height,
+                pool: origin.pool.clone(),
+                tag_hex: origin.tag_bytes.to_lower_hex_string(),
+            }),
+            annotations: node.annotations.clone(),
+        }
+    }
+}
+
+impl TryFrom<Node> for TraceNode {
+    type Error = TracerError;
+
+    fn try_from(node: Node) -> Result<Self> {
+        let txid = node.txid;
+        let data_carriers = node
+            .data_carriers
+            .into_iter()
+            .map(|carrier| {
+                let payload = Vec::from_hex(&carrier.payload_hex).map_err(|e| {
+                    TracerError::MalformedJson(format!(
+                        "payload of {}:{}: {}",
+                        txid, carrier.vout, e
+                    ))
+                })?;
+                Ok(DataCarrier {
+                    vout: carrier.vout,
+                    payload,
+                    protocol: carrier.protocol,
+                    preview: carrier.preview,
+                })
+            })
+            .collect::<Result<_>>()?;
+        let origin = node
+            .origin
+            .map(|origin| -> Result<CoinbaseOrigin> {
+                let tag_bytes = Vec::from_hex(&origin.tag_hex).map_err(|e| {
+                    TracerError::MalformedJson(format!("coinbase tag of {}: {}", txid, e))
+                })?;
+                Ok(CoinbaseOrigin {
+                    height: origin.height,
+                    pool: origin.pool,
+                    tag_bytes,
+                })
+            })
+            .transpose()?;
+        let lightning = match node.lightning {
+            Some(lightning) => lightning.into_channel(&txid)?,
+            None => None,
+        };
+        Ok(Self {
+            txid: node.txid,
+            depth: node.depth,
+            height: node.height,
+            timestamp: node.timestamp,
+            input_value: node.input_value_sat.map(Amount::from_sat),
+            output_value: Amount::from_sat(node.output_value_sat),
+            fee: node.fee_sat.map(Amount::from_sat),
+            vsize: node.vsize,
+            coinbase: node.coinbase,
+            frontier: node.frontier,
+            truncated: node.truncated,
+            unspent: node.unspent,
+            replaceable: node.replaceable,
+            coinjoin: node.coinjoin.map(CoinJoinVerdict::from),
+            seeds: node.seeds.into_iter().collect(),
+            data_carriers,
+            labels: node
+                .labels
+                .into_iter()
+                .map(|label| {
+                    let category = EntityCategory::from_code(&label.category)
+                        // A category added by a newer version of this schema
+                        .unwrap_or(EntityCategory::Other);
+                    let entity = Label {
+                        entity: label.entity,
+                        category,
+                        source: label.source,
+                        confidence: label.confidence,
+                    };
+                    (label.vout, entity)
+                })
+                .collect(),
+            pattern: node
+                .pattern
+                .and_then(|pattern| {
+                    TxPattern::from_code(&pattern.kind, pattern.count.unwrap_or_default())
+                })
+                .unwrap_or_default(),
+            lightning,
+            origin,
+            annotations: node.annotations,
+        })
+    }
+}
+
+impl From<&LightningChannel> for Lightning {
+    fn from(channel: &LightningChannel) -> Self {
+        let (vout, forced) = match channel {
+            LightningChannel::Open { vout, .. } => (Some(*vout), None),
+            LightningChannel::Close { forced, .. } => (None, Some(*forced)),
+        };
+        Self {
+            kind: channel.code().to_string(),
+            vout,
+            forced,
+            funding_pubkeys: channel
+                .funding_pubkeys()
+                .iter()
+                .map(PublicKey::to_string)
+                .collect(),
+        }
+    }
+}
+
+impl Lightning {
+    /// Role of the transaction `txid` in its channel, `None` for a kind added by a
+    /// newer version of this schema
+    fn into_channel(self, txid: &Txid) -> Result<Option<LightningChannel>> {
+        let funding_pubkeys = self
+            .funding_pubkeys
+            .iter()
+            .map(|key| {
+                key.parse::<PublicKey>().map_err(|e| {
+                    TracerError::MalformedJson(format!("funding pubkey of {}: {}", txid, e))
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(match self.kind.as_str() {
+            "lightning_open" => Some(LightningChannel::Open {
+                vout: self.vout.unwrap_or_default(),
+                funding_pubkeys,
+            }),
+            "lightning_close" => Some(LightningChannel::Close {
+                forced: self.forced.unwrap_or_default(),
+                funding_pubkeys,
+            }),
+            _ => None,
+        })
+    }
+}
+
+impl From<&DataCarrier> for Carrier {
+    fn from(carrier: &DataCarrier) -> Self {
+        Self {
+            vout: carrier.vout,
+            payload_hex: carrier.payload.to_lower_hex_string(),
+            protocol: carrier.protocol.clone(),
+            preview: carrier.preview.clone(),
+        }
+    }
+}
+
+impl From<&CoinJoinVerdict> for CoinJoin {
+    fn from(verdict: &CoinJoinVerdict) -> Self {
+        Self {
+            kind: verdict.kind.code().to_string(),
+            score: verdict.score,
+            denomination_sat: verdict.denomination.to_sat(),
+            equal_outputs: verdict.equal_outputs,
+        }
+    }
+}
+
+impl From<CoinJoin> for CoinJoinVerdict {
+    fn from(coinjoin: CoinJoin) -> Self {
+        let kind = match coinjoin.kind.as_str() {
+            "whirlpool" => CoinJoinKind::Whirlpool,
+            "wasabi" => CoinJoinKind::Wasabi,
+            // Generic, or a kind added by a newer version of this schema
+            _ => CoinJoinKind::Generic,
+        };
+        Self {
+            kind,
+            score: coinjoin.score,
+            denomination: Amount::from_sat(coinjoin.denomination_sat),
+            equal_outputs: coinjoin.equal_outputs,
+        }
+    }
+}
+
+impl From<&TraceEdge> for Edge {
+    fn from(edge: &TraceEdge) -> Self {
+        Self {
+            txid: edge.outpoint.txid,
+            vout: edge.outpoint.vout,
+            spent_by: edge.spent_by,
+            value_sat: edge.value.to_sat(),
+            script_pubkey: edge.script_pubkey.to_hex_string(),
+            address: edge.address.as_ref().map(Address::to_string),
+            terminal: edge.terminal.as_ref().map(Terminal::from),
+            change: edge.change_score.as_ref().map(Change::from),
+        }
+    }
+}
+
+impl TryFrom<Edge> for TraceEdge {
+    type Error = TracerError;
+
+    fn try_from(edge: Edge) -> Result<Self> {
+        let outpoint = OutPoint::new(edge.txid, edge.vout);
+        let script_pubkey = ScriptBuf::from_hex(&edge.script_pubkey).map_err(|e| {
+            TracerError::MalformedJson(format!("script_pubkey of {}: {}", outpoint, e))
+        })?;
+        let address = edge
+            .address
+            .map(|address| {
+                address
+                    .parse::<Address<NetworkUnchecked>>()
+                    .map(Address::assume_checked)
+                    .map_err(|e| {
+                        TracerError::MalformedJson(format!("address of {}: {}", outpoint, e))
+                    })
+            })
+            .transpose()?;
+        Ok(Self {
+            outpoint,
+            spent_by: edge.spent_by,
+            value: Amount::from_sat(edge.value_sat),
+            script_pubkey,
+            address,
+            terminal: edge.terminal.map(TerminalReason::from),
+            change_score: edge.change.map(ChangeScore::from),
+        })
+    }
+}
+
+impl From<&SpeculativeEdge> for Speculative {
+    fn from(edge: &SpeculativeEdge) -> Self {
+        Self {
+            deposit_txid: edge.deposit.txid,
+            deposit_vout: edge.deposit.vout,
+            txid: edge.outpoint.txid,
+            vout: edge.outpoint.vout,
+            value_sat: edge.value.to_sat(),
+            address: edge.address.as_ref().map(Address::to_string),
+            delay_secs: edge.delay,
+            confidence: edge.confidence,
+        }
+    }
+}
+
+impl TryFrom<Speculative> for SpeculativeEdge {
+    type Error = TracerError;
+
+    fn try_from(edge: Speculative) -> Result<Self> {
+        let outpoint = OutPoint::new(edge.txid, edge.vout);
+        let address = edge
+            .address
+            .map(|address| {
+                address
+                    .parse::<Address<NetworkUnchecked>>()
+                    .map(Address::assume_checked)
+                    .map_err(|e| {
+                        TracerError::MalformedJson(format!("address of {}: {}", outpoint, e))
+                    })
+            })
+            .transpose()?;
+        Ok(Self {
+            deposit: OutPoint::new(edge.deposit_txid, edge.deposit_vout),
+            outpoint,
+            value: Amount::from_sat(edge.value_sat),
+            address,
+            delay: edge.delay_secs,
+            confidence: edge.confidence,
+        })
+    }
+}
+
+impl From<&ChangeScore> for Change {
+    fn from(score: &ChangeScore) -> Self {
+        Self {
+            score: score.score,
+            change: score.change,
+            confidence: score.confidence.code().to_string(),
+        }
+    }
+}
+
+impl From<Change> for ChangeScore {
+    fn from(change: Change) -> Self {
+        let confidence = match change.confidence.as_str() {
+            "high" => Confidence::High,
+            "medium" => Confidence::Medium,
+            // Low, or a level added by a newer version of this schema
+            _ => Confidence::Low,
+        };
+        Self {
+            score: change.score,
+            change: change.change,
+            confidence,
+        }
+    }
+}
+
+impl From<&TerminalReason> for Terminal {
+    fn from(reason: &TerminalReason) -> Self {
+        match reason {
+            // The context apart, to be read back as it was
+            TerminalReason::Error { message, context } => Self {
+                reason: reason.code().to_string(),
+                detail: Some(message.clone()),
+                context: context.clone(),
+            },
+            _ => Self {
+                reason: reason.code().to_string(),
+                detail: reason.detail(),
+                context: None,
+            },
+        }
+    }
+}
+
+impl From<Terminal> for TerminalReason {
+    fn from(terminal: Terminal) -> Self {
+        let detail = terminal.detail.unwrap_or_default();
+        match terminal.reason.as_str() {
+            "unspent" => TerminalReason::Unspent,
+            "max_depth" => TerminalReason::MaxDepthReached,
+            "max_transactions" => TerminalReason::MaxTransactionsReached,
+            "max_breadth" => TerminalReason::MaxBreadthReached,
+            "not_followed" => TerminalReason::NotFollowed,
+            "peeled" => TerminalReason::Peeled,
+            "coinjoin" => TerminalReason::CoinJoin,
+            "lightning_channel" => TerminalReason::LightningChannel,
+            "data_carrier" => TerminalReason::DataCarrier,
+            "reached_target" => match ScriptBuf::from_hex(&detail) {
+                Ok(script) => TerminalReason::ReachedTarget(script),
+                Err(_) => TerminalReason::Other(format!("reached_target: {}", detail)),
+            },
+            "cancelled" => TerminalReason::Cancelled,
+            "budget_exhausted" => TerminalReason::BudgetExhausted,
+            "below_min_value" => TerminalReason::BelowMinValue,
+            "out_of_window" => TerminalReason::OutOfWindow,
+            "visited" => TerminalReason::Visited,
+            "exchange" => TerminalReason::Exchange(detail),
+            "mixer" => TerminalReason::Mixer(detail),
+            "sanctioned" => TerminalReason::Sanctioned(detail),
+            "data_unavailable" => TerminalReason::DataUnavailable,
+            "error" => TerminalReason::Error {
+                message: detail,
+                context: terminal.context,
+            },
+            "other" => TerminalReason::Other(detail),
+            // Written by a newer version of this schema
+            unknown if detail.is_empty() => TerminalReason::Other(unknown.to_string()),
+            unknown => TerminalReason::Other(format!("{}: {}", unknown, detail)),
+        }
+    }
+}
+
+impl TraceGraph {
+    /// Serializes the graph to the versioned JSON schema described in this module.
+    pub fn to_json(&self) -> String {
+        let document = Document {
+            version: SCHEMA_VERSION,
+            nodes: self.nodes().map(Node::from).collect(),
+            edges: self.edges().map(Edge::from).collect(),
+            speculative_edges: self
+                .speculative_edges()
+                .iter()
+                .map(Speculative::from)
+                .collect(),
+        };
+        // Plain structs of strings and integers always serialize
+        serde_json::to_string_pretty(&document).expect("trace document serializes")
+    }
+
+    /// Reads a graph written by `to_json`.
+    ///
+    /// # Errors
+    /// - `UnsupportedSchema` - the document has a version other than `SCHEMA_VERSION`
+    /// - `MalformedJson` - the document is not valid JSON of the schema, or holds an
+    ///   invalid script, address or payload
+    pub fn from_json(json: &str) -> Result<Self> {
+        let Versioned { version } =
+            serde_json::from_str(json).map_err(|e| TracerError::MalformedJson(e.to_string()))?;
+        if version != SCHEMA_VERSION {
+            return Err(TracerError::UnsupportedSchema(version));
+        }
+        let document: Document =
+            serde_json::from_str(json).map_err(|e| TracerError::MalformedJson(e.to_string()))?;
+
+        let mut graph = TraceGraph::new();
+        for node in document.nodes {
+            graph.insert_node(node.try_into()?);
+        }
+        for edge in document.edges {
+            graph.insert_edge(edge.try_into()?);
+        }
+        graph.add_speculative_edges(
+            document
+                .speculative_edges
+                .into_iter()
+                .map(SpeculativeEdge::try_from)
+                .collect::<Result<Vec<_>>>()?,
+        );
+        Ok(graph)
+    }
+}
+
+impl TraceItem {
+    /// Serializes the node or edge to one line of JSON, tagged with its `"type"`.
+    pub fn to_json_line(&self) -> String {
+        let line = match self {
+            TraceItem::Node(node) => Line::Node(node.into()),
+            TraceItem::Edge(edge) => Line::Edge(edge.into()),
+        };
+        serde_json::to_string(&line).expect("trace item serializes")
+    }
+}
+
+#[cfg(all(test, feature = "trace-engine"))]
+mod tests {
+    use super::*;
+    use crate::tracer::fixtures::{channel, sample_graph, tagged};
+
+    const FIXTURE: &str = "src/tracer/testdata/trace.json";
+
+    /// The sample graph, with a labelled terminal to pin how details and labels are
+    /// written
+    async fn fixture() -> TraceGraph {
+        let mut graph = sample_graph().await;
+        let change = graph
+            .edges()
+            .find(|edge| edge.value == Amount::from_sat(39_000))
+            .unwrap()
+            .clone();
+        let kraken = Label {
+            entity: "Kraken".to_string(),
+            category: EntityCategory::Exchange,
+            source: Some("walletexplorer".to_string()),
+            confidence: 0.9,
+        };
+        graph.insert_edge(change.clone().terminal(kraken.terminal_reason()));
+        let node = graph.node_mut(&change.from()).unwrap();
+        node.labels.insert(change.outpoint.vout, kraken);
+        node.annotations
+            .insert("round.0".to_string(), "0.00060000 BTC".to_string());
+        graph
+    }
+
+    #[tokio::test]
+    async fn test_round_trip_is_stable() {
+        let graph = fixture().await;
+
+        let json = graph.to_json();
+        let back = TraceGraph::from_json(&json).unwrap();
+
+        assert_eq!(back, graph);
+        assert_eq!(back.to_json(), json);
+    }
+
+    #[tokio::test]
+    async fn test_schema_is_pinned() {
+        let json = fixture().await.to_json();
+
+        // UPDATE_GOLDEN=1 cargo test rewrites the file after an intended schema change,
+        // which must come with a new SCHEMA_VERSION
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            std::fs::write(FIXTURE, &json).unwrap();
+        }
+        assert_eq!(json, include_str!("testdata/trace.json"));
+        assert_eq!(
+            TraceGraph::from_json(include_str!("testdata/trace.json")).unwrap(),
+            fixture().await
+        );
+    }
+
+    #[test]
+    fn test_data_carriers_round_trip() {
+        let [_, tagged] = tagged();
+        let mut graph = TraceGraph::new();
+        graph.insert_node(TraceNode::new(&tagged, 1));
+
+        let json = graph.to_json();
+        let mut document: serde_json::Value = serde_json::from_str(&json).unwrap();
+        let carrier = &document["nodes"][0]["data_carriers"][0];
+        assert_eq!(carrier["vout"], 1);
+        assert_eq!(carrier["protocol"], "charley");
+        assert_eq!(carrier["preview"], "charley loves heidi");
+        assert_eq!(TraceGraph::from_json(&json).unwrap(), graph);
+
+        document["nodes"][0]["data_carriers"][0]["payload_hex"] = "zz".into();
+        let result = TraceGraph::from_json(&document.to_string());
+        assert!(matches!(result, Err(TracerError::MalformedJson(_))));
+    }
+
+    #[test]
+    fn test_lightning_channels_round_trip() {
+        let [_, funding, close, _] = channel();
+        let mut graph = TraceGraph::new();
+        let mut opened = TraceNode::new(&funding, 0);
+        opened.lightning = LightningChannel::revealed_by(
+            OutPoint::new(funding.compute_txid(), 0),
+            &funding.output[0].script_pubkey,
+            &close,
+        );
+        graph.insert_node(opened);
+        let mut closed = TraceNode::new(&close, 1);
+        closed.lightning = LightningChannel::close(&close);
+        graph.insert_node(closed);
+
+        let json = graph.to_json();
+        let mut document: serde_json::Value = serde_json::from_str(&json).unwrap();
+        let open = document["nodes"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|node| node["lightning"]["kind"] == "lightning_open")
+            .unwrap();
+        assert_eq!(open["lightning"]["vout"], 0);
+        assert_eq!(open["lightning"]["forced"], serde_json::Value::Null);
+        assert_eq!(
+            open["lightning"]["funding_pubkeys"][0],
+            "023da092f6980e58d2c037173180e9a465476026ee50f96695963e8efe436f54eb"
+        );
+        assert_eq!(TraceGraph::from_json(&json).unwrap(), graph);
+
+        document["nodes"][0]["lightning"]["funding_pubkeys"][0] = "02ff".into();
+        let result = TraceGraph::from_json(&document.to_string());
+        assert!(matches!(result, Err(TracerError::MalformedJson(_))));
+        document["nodes"][0]["lightning"] = serde_json::json!({"kind": "splice"});
+        let back = TraceGraph::from_json(&document.to_string()).unwrap();
+        assert_eq!(
+            back.nodes().filter(|node| node.lightning.is_some()).count(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_unknown_version_is_rejected() {
+        let result = TraceGraph::from_json(r#"{"version": 2, "nodes": "changed"}"#);
+        assert!(matches!(result, Err(TracerError::UnsupportedSchema(2))));
+
+        let result = TraceGraph::from_json(r#"{"nodes": [], "edges": []}"#);
+        assert!(matches!(result, Err(TracerError::MalformedJson(_))));
+    }
+
+    #[tokio::test]
+    async fn test_unknown_fields_are_ignored() {
+        let graph = fixture().await;
+        let mut document: serde_json::Value = serde_json::from_str(&graph.to_json()).unwrap();
+        document["generator"] = "pathfinder 0.2".into();
+        document["nodes"][0]["label"] = "cold storage".into();
+        document["edges"][0]["terminal"] = serde_json::json!({"reason": "dusting"});
+
+        let back = TraceGraph::from_json(&document.to_string()).unwrap();
+
+        assert_eq!(back.len(), graph.len());
+        assert_eq!(
+            back.edges().next().unwrap().terminal,
+            Some(TerminalReason::Other("dusting".to_string()))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_items_are_one_tagged_line_each() {
+        let graph = fixture().await;
+        let node = graph.nodes().next().unwrap();
+        let edge = graph.edges().next().unwrap();
+
+        let node_line = TraceItem::Node(node.clone()).to_json_line();
+        let edge_line = TraceItem::Edge(edge.clone()).to_json_line();
+
+        assert!(!node_line.contains('\n') && !edge_line.contains('\n'));
+        let node_json: serde_json::Value = serde_json::from_str(&node_line).unwrap();
+        let edge_json: serde_json::Value = serde_json::from_str(&edge_line).unwrap();
+        assert_eq!(node_json["type"], "node");
+        assert_eq!(node_json["txid"], node.txid.to_string());
+        assert_eq!(edge_json["type"], "edge");
+        assert_eq!(edge_json["value_sat"], edge.value.to_sat());
+    }
+}
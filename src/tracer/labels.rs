@@ -0,0 +1,564 @@
+//! Known-entity labels: which addresses belong to exchanges, mixers, merchants, ...
+//!
+//! A `LabelStore` attached to a `TraceConfig` marks every traced transaction paying a
+//! labelled script with the label, and the `LabelPolicy` of the config decides
+//! whether a trace goes on past such an output: by default it stops at exchanges,
+//! mixers and sanctioned entities, where the coins change hands for good.
+//!
+//! Label files are CSV or JSON Lines, one label per row:
+//!
+//! ```text
+//! address,entity,category,source,confidence
+//! bc1qxy2kgdygjrsqtzq2n0yrf2493p83kkfjhx0wlh,Binance,exchange,walletexplorer,0.9
+//! ```
+//!
+//! ```json
+//! {"address": "bc1q...", "entity": "Binance", "category": "exchange", "source": "walletexplorer", "confidence": 0.9}
+//! ```
+//!
+//! `source` and `confidence` are optional (confidence defaults to 1.0). Categories are
+//! `exchange`, `mixer`, `merchant`, `service`, `gambling`, `sanctioned` and `other`.
+//! An address listed twice keeps its last label. Address prefixes are not checked
+//! against the network of the trace.
+
+use crate::tracer::{Result, TerminalReason, TraceGraph, TracerError};
+use bitcoin::{Address, OutPoint, Script, ScriptBuf, Transaction, address::NetworkUnchecked};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+
+/// Kind of entity behind a labelled address
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EntityCategory {
+    Exchange,
+    Mixer,
+    Merchant,
+    Service,
+    Gambling,
+    Sanctioned,
+    Other,
+}
+
+impl EntityCategory {
+    /// Stable lowercase name of the category, as used by label files and the exports
+    pub fn code(&self) -> &'static str {
+        match self {
+            EntityCategory::Exchange => "exchange",
+            EntityCategory::Mixer => "mixer",
+            EntityCategory::Merchant => "merchant",
+            EntityCategory::Service => "service",
+            EntityCategory::Gambling => "gambling",
+            EntityCategory::Sanctioned => "sanctioned",
+            EntityCategory::Other => "other",
+        }
+    }
+
+    /// Category named `code`
+    pub fn from_code(code: &str) -> Option<Self> {
+        [
+            EntityCategory::Exchange,
+            EntityCategory::Mixer,
+            EntityCategory::Merchant,
+            EntityCategory::Service,
+            EntityCategory::Gambling,
+            EntityCategory::Sanctioned,
+            EntityCategory::Other,
+        ]
+        .into_iter()
+        .find(|category| category.code() == code)
+    }
+}
+
+/// What is known of the owner of an address.
+///
+/// # Fields
+/// * `entity` - name of the owner, e.g. "Binance"
+/// * `category` - kind of owner
+/// * `source` - where the label comes from, if recorded
+/// * `confidence` - how sure the source is, from 0.0 to 1.0
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Label {
+    pub entity: String,
+    pub category: EntityCategory,
+    pub source: Option<String>,
+    pub confidence: f64,
+}
+
+impl Label {
+    /// Terminal reason of an output a trace stops at for paying this label
+    pub fn terminal_reason(&self) -> TerminalReason {
+        let entity = self.entity.clone();
+        match self.category {
+            EntityCategory::Exchange => TerminalReason::Exchange(entity),
+            EntityCategory::Mixer => TerminalReason::Mixer(entity),
+            EntityCategory::Sanctioned => TerminalReason::Sanctioned(entity),
+            category => TerminalReason::Other(format!("{}: {}", category.code(), entity)),
+        }
+    }
+}
+
+impl fmt::Display for Label {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.entity, self.category.code())
+    }
+}
+
+/// Categories of labelled outputs a trace stops at.
+///
+/// By default exchanges, mixers and sanctioned entities stop the branch; outputs of
+/// other categories are marked and followed like any other.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LabelPolicy {
+    pub stop: BTreeSet<EntityCategory>,
+}
+
+impl Default for LabelPolicy {
+    fn default() -> Self {
+        Self {
+            stop: BTreeSet::from([
+                EntityCategory::Exchange,
+                EntityCategory::Mixer,
+                EntityCategory::Sanctioned,
+            ]),
+        }
+    }
+}
+
+impl LabelPolicy {
+    /// Stops at outputs paying entities of `category`
+    pub fn stop_at(mut self, category: EntityCategory) -> Self {
+        self.stop.insert(category);
+        self
+    }
+
+    /// Follows outputs paying entities of `category`
+    pub fn continue_through(mut self, category: EntityCategory) -> Self {
+        self.stop.remove(&category);
+        self
+    }
+
+    /// Whether a trace stops at outputs paying entities of `category`
+    pub fn stops(&self, category: EntityCategory) -> bool {
+        self.stop.contains(&category)
+    }
+}
+
+/// Labels of known addresses, looked up by the script they pay to.
+#[derive(Clone, Default, PartialEq)]
+pub struct LabelStore {
+    labels: HashMap<ScriptBuf, Label>,
+}
+
+/// A row of a label file, before validation
+#[derive(Deserialize)]
+struct Row {
+    address: String,
+    entity: String,
+    category: String,
+    #[serde(default)]
+    source: Option<String>,
+    #[serde(default)]
+    confidence: Option<f64>,
+}
+
+impl LabelStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Labels `address`, replacing any label it had
+    pub fn insert(&mut self, address: &Address, label: Label) {
+        self.labels.insert(address.script_pubkey(), label);
+    }
+
+    /// Label of the address paid by `script`
+    pub fn get(&self, script: &Script) -> Option<&Label> {
+        self.labels.get(script)
+    }
+
+    /// Labels of the outputs of `tx` paying labelled addresses, by output index
+    pub fn outputs_of(&self, tx: &Transaction) -> BTreeMap<u32, Label> {
+        tx.output
+            .iter()
+            .zip(0..)
+            .filter_map(|(output, vout)| Some((vout, self.get(&output.script_pubkey)?.clone())))
+            .collect()
+    }
+
+    /// Number of labelled addresses
+    pub fn len(&self) -> usize {
+        self.labels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty()
+    }
+
+    /// Stable hash of the labels, whatever order they were inserted in
+    pub fn digest(&self) -> u64 {
+        self.labels
+            .iter()
+            .map(|(script, label)| {
+                let canonical = format!(
+                    "{}|{}|{}|{:?}|{}",
+                    script.to_hex_string(),
+                    label.entity,
+                    label.category.code(),
+                    label.source,
+                    label.confidence
+                );
+                // FNV-1a, as `TraceConfig::fingerprint`
+                canonical
+                    .bytes()
+                    .fold(0xcbf2_9ce4_8422_2325, |hash: u64, byte| {
+                        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+                    })
+            })
+            .fold(0, u64::wrapping_add)
+    }
+
+    /// Reads a label file, CSV if its extension is `csv` and JSON Lines otherwise.
+    ///
+    /// # Errors
+    /// - `LabelFile` - the file could not be read
+    /// - `InvalidLabels` - a row is malformed, see `from_csv` and `from_json_lines`
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file = std::fs::File::open(path).map_err(TracerError::LabelFile)?;
+        if path.extension().is_some_and(|extension| extension == "csv") {
+            Self::from_csv(file)
+        } else {
+            Self::from_json_lines(BufReader::new(file))
+        }
+    }
+
+    /// Reads labels from CSV with a header row naming the columns `address`, `entity`,
+    /// `category`, and optionally `source` and `confidence`, in any order.
+    ///
+    /// # Errors
+    /// - `InvalidLabels` - a row has missing columns, an invalid address, an unknown
+    ///   category, an empty entity or a confidence out of 0.0..=1.0, named by its line
+    ///   (the header is line 1)
+    pub fn from_csv<R: Read>(reader: R) -> Result<Self> {
+        let mut csv = ::csv::ReaderBuilder::new()
+            .trim(::csv::Trim::All)
+            .from_reader(reader);
+        let headers = csv
+            .headers()
+            .map_err(|e| TracerError::InvalidLabels(1, e.to_string()))?
+            .clone();
+        let mut store = Self::new();
+        for record in csv.records() {
+            let record = record.map_err(|e| {
+                let line = e.position().map_or(0, |position| position.line() as usize);
+                TracerError::InvalidLabels(line, e.to_string())
+            })?;
+            let line = record
+                .position()
+                .map_or(0, |position| position.line() as usize);
+            let row: Row = record
+                .deserialize(Some(&headers))
+                .map_err(|e| TracerError::InvalidLabels(line, e.to_string()))?;
+            store.insert_row(row, line)?;
+        }
+        Ok(store)
+    }
+
+    /// Reads labels from JSON Lines: one object per line with the fields `address`,
+    /// `entity`, `category`, and optionally `source` and `confidence`. Blank lines are
+    /// skipped.
+    ///
+    /// # Errors
+    /// - `LabelFile` - reading failed
+    /// - `InvalidLabels` - a line is not such an object, or holds an invalid address,
+    ///   an unknown category, an empty entity or a confidence out of 0.0..=1.0
+    pub fn from_json_lines<R: BufRead>(reader: R) -> Result<Self> {
+        let mut store = Self::new();
+        for (index, text) in reader.lines().enumerate() {
+            let line = index + 1;
+            let text = text.map_err(TracerError::LabelFile)?;
+            if text.trim().is_empty() {
+                continue;
+            }
+            let row: Row = serde_json::from_str(&text)
+                .map_err(|e| TracerError::InvalidLabels(line, e.to_string()))?;
+            store.insert_row(row, line)?;
+        }
+        Ok(store)
+    }
+
+    /// Validates `row`, found at `line`, and adds its label
+    fn insert_row(&mut self, row: Row, line: usize) -> Result<()> {
+        let invalid = |message: String| TracerError::InvalidLabels(line, message);
+        let address = row
+            .address
+            .parse::<Address<NetworkUnchecked>>()
+            .map_err(|e| invalid(format!("address {:?}: {}", row.address, e)))?
+            .assume_checked();
+        let entity = row.entity.trim();
+        if entity.is_empty() {
+            return Err(invalid("entity is empty".to_string()));
+        }
+        let category = EntityCategory::from_code(row.category.trim())
+            .ok_or_else(|| invalid(format!("unknown category {:?}", row.category)))?;
+        let confidence = row.confidence.unwrap_or(1.0);
+        if !(0.0..=1.0).contains(&confidence) {
+            return Err(invalid(format!(
+                "confidence must be within 0.0..=1.0, got {}",
+                confidence
+            )));
+        }
+        let source = row.source.filter(|source| !source.trim().is_empty());
+        self.insert(
+            &address,
+            Label {
+                entity: entity.to_string(),
+                category,
+                source,
+                confidence,
+            },
+        );
+        Ok(())
+    }
+}
+
+/// Only the size: a store can hold hundreds of thousands of labels
+impl fmt::Debug for LabelStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LabelStore")
+            .field("len", &self.labels.len())
+            .finish()
+    }
+}
+
+impl TraceGraph {
+    /// Label of the address `outpoint` pays, as recorded on its transaction
+    pub fn label_of(&self, outpoint: &OutPoint) -> Option<&Label> {
+        self.node(&outpoint.txid)?.labels.get(&outpoint.vout)
+    }
+}
+
+#[cfg(all(test, feature = "trace-engine"))]
+mod tests {
+    use super::*;
+    use crate::tracer::{
+        TraceConfig, TraceSummary, Tracer,
+        fixtures::{MockDataSource, coinbase, script, spend},
+    };
+    use bitcoin::Amount;
+    use std::sync::Arc;
+
+    const CSV: &str = "src/tracer/testdata/labels.csv";
+    const JSON_LINES: &str = "src/tracer/testdata/labels.jsonl";
+
+    fn fixture() -> Arc<LabelStore> {
+        Arc::new(LabelStore::load(CSV).unwrap())
+    }
+
+    /// Line and message of the error reading `text` as CSV
+    fn csv_error(text: &str) -> (usize, String) {
+        match LabelStore::from_csv(text.as_bytes()) {
+            Err(TracerError::InvalidLabels(line, message)) => (line, message),
+            other => panic!("expected InvalidLabels, got {:?}", other),
+        }
+    }
+
+    /// Funding -> split paying nobody (0), Kraken (1) and Bitrefill (2); both labelled
+    /// outputs are spent
+    fn payouts() -> Vec<Transaction> {
+        let funding = coinbase(1, &[100_000]);
+        let split = spend(
+            2,
+            &[OutPoint::new(funding.compute_txid(), 0)],
+            &[50_000, 30_000, 19_000],
+        );
+        let deposit = spend(3, &[OutPoint::new(split.compute_txid(), 1)], &[29_000]);
+        let purchase = spend(4, &[OutPoint::new(split.compute_txid(), 2)], &[18_000]);
+        vec![funding, split, deposit, purchase]
+    }
+
+    #[test]
+    fn test_loads_fixture_files() {
+        let store = LabelStore::load(CSV).unwrap();
+
+        assert_eq!(store.len(), 3);
+        let kraken = store.get(&script(1)).unwrap();
+        assert_eq!(kraken.entity, "Kraken");
+        assert_eq!(kraken.category, EntityCategory::Exchange);
+        assert_eq!(kraken.source.as_deref(), Some("walletexplorer"));
+        assert_eq!(kraken.confidence, 0.9);
+        // Empty optional cells
+        let bitrefill = store.get(&script(2)).unwrap();
+        assert_eq!(bitrefill.category, EntityCategory::Merchant);
+        assert_eq!(bitrefill.source, None);
+        assert_eq!(bitrefill.confidence, 1.0);
+        assert_eq!(store.get(&script(0)), None);
+
+        let json = LabelStore::load(JSON_LINES).unwrap();
+        assert_eq!(json, store);
+        assert_eq!(json.digest(), store.digest());
+        assert_eq!(format!("{:?}", store), "LabelStore { len: 3 }");
+    }
+
+    #[test]
+    fn test_invalid_rows_name_their_line() {
+        let header = "address,entity,category,source,confidence\n";
+        let kraken = "bc1qqyqszqgpqyqszqgpqyqszqgpqyqszqgpyfl4f3";
+
+        let (line, message) = csv_error(&format!(
+            "{header}{kraken},Kraken,exchange,,\nnope,X,exchange,,\n"
+        ));
+        assert_eq!(line, 3);
+        assert!(message.contains("\"nope\""), "{message}");
+        let (line, message) = csv_error(&format!("{header}{kraken},Kraken,bank,,\n"));
+        assert_eq!(line, 2);
+        assert!(message.contains("unknown category \"bank\""), "{message}");
+        let (_, message) = csv_error(&format!("{header}{kraken},Kraken,exchange,,1.5\n"));
+        assert!(message.contains("1.5"), "{message}");
+        let (_, message) = csv_error(&format!("{header}{kraken}, ,exchange,,\n"));
+        assert!(message.contains("entity is empty"), "{message}");
+        let (line, _) = csv_error(&format!("{header}{kraken},Kraken\n"));
+        assert_eq!(line, 2);
+        let (line, _) = csv_error(&format!("{header}{kraken},Kraken,exchange,,high\n"));
+        assert_eq!(line, 2);
+
+        // Blank lines still count
+        let text = format!(
+            "{{\"address\": \"{kraken}\", \"entity\": \"Kraken\", \"category\": \"exchange\"}}\n\n{{\"address\": \"{kraken}\"}}\n"
+        );
+        let error = LabelStore::from_json_lines(text.as_bytes()).unwrap_err();
+        assert!(matches!(error, TracerError::InvalidLabels(3, _)), "{error}");
+        assert!(
+            error
+                .to_string()
+                .starts_with("Invalid label file, line 3: ")
+        );
+
+        let error = LabelStore::load("src/tracer/testdata/missing.csv").unwrap_err();
+        assert!(matches!(error, TracerError::LabelFile(_)));
+    }
+
+    #[test]
+    fn test_later_labels_replace_earlier_ones() {
+        let text = "address,category,entity\n\
+             bc1qqyqszqgpqyqszqgpqyqszqgpqyqszqgpyfl4f3,exchange,Kraken\n\
+             bc1qqyqszqgpqyqszqgpqyqszqgpqyqszqgpyfl4f3,mixer,Wasabi\n";
+
+        let store = LabelStore::from_csv(text.as_bytes()).unwrap();
+
+        assert_eq!(store.len(), 1);
+        assert_eq!(store.get(&script(1)).unwrap().entity, "Wasabi");
+    }
+
+    #[tokio::test]
+    async fn test_trace_stops_at_labelled_exchange() {
+        let txs = payouts();
+        let config = TraceConfig::default().labels(fixture());
+        let root = OutPoint::new(txs[0].compute_txid(), 0);
+
+        let graph = Tracer::new(MockDataSource::new(&txs))
+            .trace_forward(root, &config)
+            .await
+            .unwrap()
+            .into_graph();
+
+        let split = txs[1].compute_txid();
+        let deposit = OutPoint::new(split, 1);
+        assert!(!graph.contains_node(&txs[2].compute_txid()));
+        assert_eq!(
+            graph.edge(&deposit).unwrap().terminal,
+            Some(TerminalReason::Exchange("Kraken".to_string()))
+        );
+        // Merchants are marked and followed
+        assert!(graph.contains_node(&txs[3].compute_txid()));
+        let labels = &graph.node(&split).unwrap().labels;
+        assert_eq!(labels.keys().copied().collect::<Vec<_>>(), [1, 2]);
+        assert_eq!(graph.label_of(&deposit).unwrap().entity, "Kraken");
+        assert_eq!(graph.label_of(&OutPoint::new(split, 0)), None);
+
+        // Through to every export
+        let summary = TraceSummary::from_graph(&graph);
+        assert_eq!(summary.labelled["Kraken"], Amount::from_sat(30_000));
+        assert!(
+            summary
+                .to_string()
+                .contains("0.0003 BTC reached Kraken-labelled addresses\n")
+        );
+        assert_eq!(TraceGraph::from_json(&graph.to_json()).unwrap(), graph);
+        let mut csv = Vec::new();
+        graph.to_csv(&mut csv).unwrap();
+        assert!(
+            String::from_utf8(csv)
+                .unwrap()
+                .contains(",Kraken (exchange)\n")
+        );
+        let mut csv = Vec::new();
+        graph.nodes_to_csv(&mut csv).unwrap();
+        assert!(
+            String::from_utf8(csv)
+                .unwrap()
+                .contains(",1: Kraken (exchange);2: Bitrefill (merchant),\n")
+        );
+        let mut xml = Vec::new();
+        graph.to_graphml(&mut xml).unwrap();
+        assert!(
+            String::from_utf8(xml)
+                .unwrap()
+                .contains(r#"<data key="n_entity">Kraken</data>"#)
+        );
+        assert!(graph.to_dot(&Default::default()).contains("Kraken"));
+    }
+
+    #[tokio::test]
+    async fn test_policy_picks_the_categories_stopping_a_trace() {
+        let txs = payouts();
+        let root = OutPoint::new(txs[0].compute_txid(), 0);
+        let tracer = Tracer::new(MockDataSource::new(&txs));
+
+        let policy = LabelPolicy::default()
+            .continue_through(EntityCategory::Exchange)
+            .stop_at(EntityCategory::Merchant);
+        let config = TraceConfig::default()
+            .labels(fixture())
+            .label_policy(policy);
+        let graph = tracer
+            .trace_forward(root, &config)
+            .await
+            .unwrap()
+            .into_graph();
+
+        assert!(graph.contains_node(&txs[2].compute_txid()));
+        assert!(!graph.contains_node(&txs[3].compute_txid()));
+        assert_eq!(
+            graph
+                .edge(&OutPoint::new(txs[1].compute_txid(), 2))
+                .unwrap()
+                .terminal,
+            Some(TerminalReason::Other("merchant: Bitrefill".to_string()))
+        );
+        assert_ne!(
+            config.fingerprint(),
+            TraceConfig::default().labels(fixture()).fingerprint()
+        );
+        assert_ne!(
+            TraceConfig::default().fingerprint(),
+            TraceConfig::default().labels(fixture()).fingerprint()
+        );
+
+        // Backward traces only mark the labels
+        let graph = tracer
+            .trace_backward(
+                txs[2].compute_txid(),
+                &TraceConfig::default().labels(fixture()),
+            )
+            .await
+            .unwrap()
+            .into_graph();
+        assert_eq!(graph.len(), 3);
+        let split = txs[1].compute_txid();
+        assert_eq!(graph.node(&split).unwrap().labels.len(), 2);
+    }
+}
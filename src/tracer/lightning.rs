@@ -0,0 +1,243 @@
+//! Recognition of the transactions opening and closing Lightning channels.
+//!
+//! A channel is funded by a P2WSH output locking its coins to a 2-of-2 multisig of the
+//! two peers' keys (BOLT 3), which looks like any other P2WSH output until it is spent:
+//! only the transaction closing the channel reveals the multisig. A cooperative close
+//! pays each peer its balance; a unilateral close is a commitment transaction, which
+//! hides its commitment number in its locktime and sequence, may carry two anchor
+//! outputs of 330 sats, and has a `to_local` output whose CSV-delayed script is
+//! revealed when it is swept.
+//!
+//! Coins going into a channel leave the chain until it closes, maybe months later and
+//! to outputs that say nothing of the payments made in between, so a forward trace
+//! stops at channel opens by default (see `LightningPolicy`).
+
+use crate::tracer::script::{funding_pubkeys, to_self_delay};
+use bitcoin::{Amount, OutPoint, PublicKey, Script, Transaction, TxIn, TxOut};
+use serde::{Deserialize, Serialize};
+
+/// Value of the anchor outputs of a commitment transaction
+pub const ANCHOR_OUTPUT_VALUE: Amount = Amount::from_sat(330);
+
+/// Role of a transaction in a Lightning channel.
+///
+/// `funding_pubkeys` are the keys of the channel's 2-of-2 multisig, empty when the
+/// transaction was recognized without them being revealed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LightningChannel {
+    /// Funds a channel with output `vout`
+    Open {
+        vout: u32,
+        funding_pubkeys: Vec<PublicKey>,
+    },
+    /// Closes a channel, unilaterally with a commitment transaction when `forced`
+    Close {
+        forced: bool,
+        funding_pubkeys: Vec<PublicKey>,
+    },
+}
+
+impl LightningChannel {
+    /// Stable snake_case name of the role, as used by the exports
+    pub fn code(&self) -> &'static str {
+        match self {
+            LightningChannel::Open { .. } => "lightning_open",
+            LightningChannel::Close { .. } => "lightning_close",
+        }
+    }
+
+    /// Keys of the channel's funding multisig, as far as they are known
+    pub fn funding_pubkeys(&self) -> &[PublicKey] {
+        match self {
+            LightningChannel::Open {
+                funding_pubkeys, ..
+            }
+            | LightningChannel::Close {
+                funding_pubkeys, ..
+            } => funding_pubkeys,
+        }
+    }
+
+    /// The channel close `tx` is, if it is one: a transaction spending a single
+    /// 2-of-2 multisig into a commitment transaction or a cooperative close of one or
+    /// two outputs, or a commitment transaction with anchors whose witness does not
+    /// reveal the multisig
+    pub fn close(tx: &Transaction) -> Option<Self> {
+        let [input] = tx.input.as_slice() else {
+            return None;
+        };
+        let forced = is_commitment(tx);
+        let funding_pubkeys: Vec<_> = revealed_funding(input).into_iter().flatten().collect();
+        let payouts = tx
+            .output
+            .iter()
+            .filter(|out| !out.script_pubkey.is_op_return())
+            .count();
+        let close = if funding_pubkeys.is_empty() {
+            forced && tx.output.iter().any(is_anchor)
+        } else {
+            forced || (1..=2).contains(&payouts)
+        };
+        close.then_some(LightningChannel::Close {
+            forced,
+            funding_pubkeys,
+        })
+    }
+
+    /// What `spender` reveals of the output `outpoint` paying `script_pubkey`: that
+    /// it opened a channel, when `spender` closes the channel and reveals the multisig
+    /// hashing to `script_pubkey`, or that its transaction closed one, when the output
+    /// was a `to_local` output
+    pub fn revealed_by(
+        outpoint: OutPoint,
+        script_pubkey: &Script,
+        spender: &Transaction,
+    ) -> Option<Self> {
+        let input = spender
+            .input
+            .iter()
+            .find(|input| input.previous_output == outpoint)?;
+        let witness_script = input.witness.witness_script()?;
+        if !script_pubkey.is_p2wsh() || witness_script.to_p2wsh() != *script_pubkey {
+            return None;
+        }
+        if to_self_delay(witness_script).is_some() {
+            return Some(LightningChannel::Close {
+                forced: true,
+                funding_pubkeys: Vec::new(),
+            });
+        }
+        let funding_pubkeys = funding_pubkeys(witness_script)?;
+        LightningChannel::close(spender)?;
+        Some(LightningChannel::Open {
+            vout: outpoint.vout,
+            funding_pubkeys: funding_pubkeys.to_vec(),
+        })
+    }
+}
+
+/// What a forward trace does at an output funding a Lightning channel
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LightningPolicy {
+    /// Record the output as a `LightningChannel` leaf, leaving out the close
+    #[default]
+    StopAtOpen,
+    /// Trace through the close like any other transaction
+    Continue,
+}
+
+/// Whether `tx` is shaped like a commitment transaction: a single input, the upper
+/// byte of its locktime 0x20 and that of its sequence 0x80
+pub fn is_commitment(tx: &Transaction) -> bool {
+    matches!(tx.input.as_slice(), [input]
+        if tx.lock_time.to_consensus_u32() >> 24 == 0x20 && input.sequence.0 >> 24 == 0x80)
+}
+
+/// Whether `output` looks like the anchor of a commitment transaction
+pub fn is_anchor(output: &TxOut) -> bool {
+    output.value == ANCHOR_OUTPUT_VALUE && output.script_pubkey.is_p2wsh()
+}
+
+/// Funding keys `input` reveals spending a channel's funding output
+fn revealed_funding(input: &TxIn) -> Option<[PublicKey; 2]> {
+    funding_pubkeys(input.witness.witness_script()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tracer::fixtures::{channel, script};
+    use bitcoin::{Sequence, absolute::LockTime};
+
+    #[test]
+    fn test_recognizes_commitment_transactions() {
+        let [mined, funding, close, sweep] = channel();
+        let keys = LightningChannel::close(&close).unwrap();
+        assert!(matches!(keys, LightningChannel::Close { forced: true, .. }));
+        assert_eq!(keys.funding_pubkeys().len(), 2);
+        assert_eq!(keys.code(), "lightning_close");
+        assert!(is_commitment(&close));
+        assert!(is_anchor(&close.output[2]) && !is_anchor(&close.output[1]));
+
+        // The anchors give a commitment away even without the multisig
+        let mut stripped = close.clone();
+        stripped.input[0].witness.clear();
+        assert_eq!(
+            LightningChannel::close(&stripped),
+            Some(LightningChannel::Close {
+                forced: true,
+                funding_pubkeys: Vec::new(),
+            })
+        );
+        stripped.output.truncate(2);
+        assert_eq!(LightningChannel::close(&stripped), None);
+
+        for tx in [&mined, &funding, &sweep] {
+            assert_eq!(LightningChannel::close(tx), None);
+        }
+    }
+
+    #[test]
+    fn test_recognizes_cooperative_closes() {
+        let [_, _, mut close, _] = channel();
+        close.lock_time = LockTime::ZERO;
+        close.input[0].sequence = Sequence::MAX;
+        assert!(!is_commitment(&close));
+        // Four outputs: no cooperative close
+        assert_eq!(LightningChannel::close(&close), None);
+
+        close.output.truncate(2);
+        let closed = LightningChannel::close(&close).unwrap();
+        assert!(matches!(
+            closed,
+            LightningChannel::Close { forced: false, .. }
+        ));
+        assert_eq!(closed.funding_pubkeys().len(), 2);
+    }
+
+    #[test]
+    fn test_spends_reveal_opens_and_to_local_outputs() {
+        let [mined, funding, close, sweep] = channel();
+        let funded = OutPoint::new(funding.compute_txid(), 0);
+        let open = LightningChannel::revealed_by(funded, &funding.output[0].script_pubkey, &close)
+            .unwrap();
+        assert!(matches!(open, LightningChannel::Open { vout: 0, .. }));
+        assert_eq!(open.code(), "lightning_open");
+        assert_eq!(open.funding_pubkeys(), keys_of(&close));
+
+        let to_local = OutPoint::new(close.compute_txid(), 0);
+        assert_eq!(
+            LightningChannel::revealed_by(to_local, &close.output[0].script_pubkey, &sweep),
+            Some(LightningChannel::Close {
+                forced: true,
+                funding_pubkeys: Vec::new(),
+            })
+        );
+
+        // The witness script must hash to the output spent
+        assert_eq!(
+            LightningChannel::revealed_by(funded, &script(0), &close),
+            None
+        );
+        let mined_out = OutPoint::new(mined.compute_txid(), 0);
+        assert_eq!(
+            LightningChannel::revealed_by(mined_out, &mined.output[0].script_pubkey, &funding),
+            None
+        );
+        // A spend of the multisig that closes no channel opened none
+        let mut multisig = close.clone();
+        multisig.lock_time = LockTime::ZERO;
+        multisig.input[0].sequence = Sequence::MAX;
+        assert_eq!(
+            LightningChannel::revealed_by(funded, &funding.output[0].script_pubkey, &multisig),
+            None
+        );
+    }
+
+    fn keys_of(close: &Transaction) -> Vec<PublicKey> {
+        LightningChannel::close(close)
+            .unwrap()
+            .funding_pubkeys()
+            .to_vec()
+    }
+}
@@ -0,0 +1,506 @@
+//! Paths between transactions of a traced graph.
+//!
+//! `TraceGraph::find_paths` enumerates the simple paths between two transactions,
+//! within a maximum count and length: the number of paths grows exponentially with the
+//! fan-out of a graph, so the enumeration always has a bound. `shortest_path` picks one
+//! path, with the fewest hops or moving the most value. A path never visits a
+//! transaction twice, so a graph with cycles (as merged or hand-edited graphs can have)
+//! cannot make either of them loop.
+//!
+//! `Tracer::find_connection` answers the question behind most traces: does this output
+//! reach that address, and how.
+
+use crate::blockchain::BlockchainDataSource;
+use crate::tracer::{Result, TraceConfig, TraceEdge, TraceGraph, Tracer, table};
+use bitcoin::{Address, Amount, OutPoint, Txid};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
+use std::fmt;
+
+/// Paths returned by `find_paths` by default
+pub const DEFAULT_MAX_PATHS: usize = 16;
+
+/// Hops a path found by `find_paths` can have by default
+pub const DEFAULT_MAX_PATH_LENGTH: usize = 32;
+
+/// Bounds of `TraceGraph::find_paths`.
+///
+/// # Fields
+/// * `max_paths` - paths to return at most; the enumeration stops once it found them
+/// * `max_length` - hops a path can have at most
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathOptions {
+    pub max_paths: usize,
+    pub max_length: usize,
+}
+
+impl Default for PathOptions {
+    fn default() -> Self {
+        Self {
+            max_paths: DEFAULT_MAX_PATHS,
+            max_length: DEFAULT_MAX_PATH_LENGTH,
+        }
+    }
+}
+
+impl PathOptions {
+    /// Paths to return at most
+    pub fn max_paths(mut self, max_paths: usize) -> Self {
+        self.max_paths = max_paths;
+        self
+    }
+
+    /// Hops a path can have at most
+    pub fn max_length(mut self, max_length: usize) -> Self {
+        self.max_length = max_length;
+        self
+    }
+}
+
+/// What `shortest_path` minimizes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathWeight {
+    /// Fewest hops
+    Hops,
+    /// Most value moved: the path whose smallest hop is the largest, the fewest hops
+    /// among those
+    Value,
+}
+
+/// One hop of a path: an output, from the transaction creating it to its spender.
+///
+/// # Fields
+/// * `outpoint` - the output
+/// * `from` - transaction creating the output
+/// * `to` - transaction spending it, `None` for the output a connection ends at
+/// * `value` - amount carried by the output
+/// * `address` - address the output pays, if it has a standard form
+#[derive(Debug, Clone, PartialEq)]
+pub struct PathHop {
+    pub outpoint: OutPoint,
+    pub from: Txid,
+    pub to: Option<Txid>,
+    pub value: Amount,
+    pub address: Option<Address>,
+}
+
+impl From<&TraceEdge> for PathHop {
+    fn from(edge: &TraceEdge) -> Self {
+        Self {
+            outpoint: edge.outpoint,
+            from: edge.from(),
+            to: edge.spent_by,
+            value: edge.value,
+            address: edge.address.clone(),
+        }
+    }
+}
+
+/// A chain of outputs through a traced graph.
+///
+/// Printed one hop per line; `graph` holds its transactions and outputs, to export it
+/// on its own like any trace.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TracePath {
+    pub hops: Vec<PathHop>,
+    graph: TraceGraph,
+}
+
+impl TracePath {
+    /// Path through the `edges` of `source`, in path order
+    fn new<'a>(source: &TraceGraph, edges: impl IntoIterator<Item = &'a TraceEdge>) -> Self {
+        let mut path = TracePath::default();
+        for edge in edges {
+            path.push(source, edge);
+        }
+        path
+    }
+
+    fn push(&mut self, source: &TraceGraph, edge: &TraceEdge) {
+        for txid in [Some(edge.from()), edge.spent_by].into_iter().flatten() {
+            if let Some(node) = source.node(&txid) {
+                self.graph.insert_node(node.clone());
+            }
+        }
+        self.graph.insert_edge(edge.clone());
+        self.hops.push(PathHop::from(edge));
+    }
+
+    /// Number of hops
+    pub fn len(&self) -> usize {
+        self.hops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hops.is_empty()
+    }
+
+    /// Transactions along the path, in order
+    pub fn txids(&self) -> Vec<Txid> {
+        let first = self.hops.first().map(|hop| hop.from);
+        first
+            .into_iter()
+            .chain(self.hops.iter().filter_map(|hop| hop.to))
+            .collect()
+    }
+
+    /// Value of the smallest hop, the most the path can have moved
+    pub fn value(&self) -> Option<Amount> {
+        self.hops.iter().map(|hop| hop.value).min()
+    }
+
+    /// The transactions and outputs of the path, as a graph of their own
+    pub fn graph(&self) -> &TraceGraph {
+        &self.graph
+    }
+}
+
+/// `<from>:<vout> -> <to>: <value>` per hop, the address instead of the spender for
+/// the output a connection ends at. Txids are shortened, unless with `{:#}`.
+impl fmt::Display for TracePath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for hop in &self.hops {
+            let to = match (hop.to, &hop.address) {
+                (Some(txid), _) => table::txid(&txid, f.alternate()),
+                (None, Some(address)) => address.to_string(),
+                (None, None) => "?".to_string(),
+            };
+            writeln!(
+                f,
+                "{} -> {}: {}",
+                table::outpoint(&hop.outpoint, f.alternate()),
+                to,
+                table::amount(hop.value)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl TraceGraph {
+    /// Outputs of `txid` spent by a traced transaction
+    fn hops_from(&self, txid: &Txid) -> impl Iterator<Item = &TraceEdge> {
+        self.outputs_of(txid).filter(|edge| edge.spent_by.is_some())
+    }
+
+    /// Simple paths from transaction `from` to transaction `to`, following spent
+    /// outputs, fewest hops first.
+    ///
+    /// Two outputs linking the same transactions make two paths. The enumeration is
+    /// depth first and stops at `options.max_paths` paths, so with more paths than
+    /// that, which ones are returned depends on txid order rather than length. There
+    /// is no path from a transaction to itself.
+    pub fn find_paths(&self, from: &Txid, to: &Txid, options: &PathOptions) -> Vec<TracePath> {
+        let mut paths: Vec<_> = self
+            .simple_paths(from, to, options)
+            .into_iter()
+            .map(|edges| TracePath::new(self, edges))
+            .collect();
+        paths.sort_by_key(TracePath::len);
+        paths
+    }
+
+    /// Edges of the simple paths from `from` to `to`, depth first
+    fn simple_paths(&self, from: &Txid, to: &Txid, options: &PathOptions) -> Vec<Vec<&TraceEdge>> {
+        let mut paths = Vec::new();
+        if from == to || options.max_paths == 0 || options.max_length == 0 {
+            return paths;
+        }
+        let mut on_path = HashSet::from([*from]);
+        let mut edges = Vec::new();
+        let mut stack = vec![self.hops_from(from)];
+        while let Some(hops) = stack.last_mut() {
+            let Some(edge) = hops.next() else {
+                stack.pop();
+                if let Some(edge) = edges.pop() {
+                    on_path.remove(edge_spender(edge));
+                }
+                continue;
+            };
+            let next = edge_spender(edge);
+            if next == to {
+                let mut path = edges.clone();
+                path.push(edge);
+                paths.push(path);
+                if paths.len() >= options.max_paths {
+                    break;
+                }
+            } else if edges.len() + 1 < options.max_length && on_path.insert(*next) {
+                edges.push(edge);
+                stack.push(self.hops_from(next));
+            }
+        }
+        paths
+    }
+
+    /// One path from transaction `from` to transaction `to`, the best under `weight`.
+    ///
+    /// Ties between equally good paths are broken by txid and outpoint order, so the
+    /// same graph always gives the same path.
+    pub fn shortest_path(&self, from: &Txid, to: &Txid, weight: PathWeight) -> Option<TracePath> {
+        if from == to {
+            return None;
+        }
+        let parents = match weight {
+            PathWeight::Hops => self.fewest_hops(from),
+            PathWeight::Value => self.widest(from),
+        };
+        let mut edges = Vec::new();
+        let mut txid = *to;
+        while txid != *from {
+            let edge = parents.get(&txid)?;
+            edges.push(*edge);
+            txid = edge.from();
+        }
+        edges.reverse();
+        Some(TracePath::new(self, edges))
+    }
+
+    /// Edge each transaction reachable from `from` is first reached through,
+    /// breadth first
+    fn fewest_hops(&self, from: &Txid) -> HashMap<Txid, &TraceEdge> {
+        let mut parents = HashMap::new();
+        let mut queue = VecDeque::from([*from]);
+        while let Some(txid) = queue.pop_front() {
+            for edge in self.hops_from(&txid) {
+                let next = *edge_spender(edge);
+                if next != *from && !parents.contains_key(&next) {
+                    parents.insert(next, edge);
+                    queue.push_back(next);
+                }
+            }
+        }
+        parents
+    }
+
+    /// Edge each transaction reachable from `from` is reached through on the path with
+    /// the largest smallest hop, the fewest hops among those (Dijkstra, maximizing the
+    /// bottleneck)
+    fn widest(&self, from: &Txid) -> HashMap<Txid, &TraceEdge> {
+        let mut parents = HashMap::new();
+        let mut settled = HashSet::new();
+        let mut best: HashMap<Txid, (Amount, Reverse<usize>)> = HashMap::new();
+        let mut heap = BinaryHeap::from([(Amount::MAX, Reverse(0), Reverse(*from))]);
+        while let Some((width, Reverse(hops), Reverse(txid))) = heap.pop() {
+            if !settled.insert(txid) {
+                continue;
+            }
+            for edge in self.hops_from(&txid) {
+                let next = *edge_spender(edge);
+                let candidate = (width.min(edge.value), Reverse(hops + 1));
+                if settled.contains(&next) || best.get(&next).is_some_and(|b| *b >= candidate) {
+                    continue;
+                }
+                best.insert(next, candidate);
+                parents.insert(next, edge);
+                heap.push((candidate.0, candidate.1, Reverse(next)));
+            }
+        }
+        parents
+    }
+}
+
+/// Spender of an edge known to be spent
+fn edge_spender(edge: &TraceEdge) -> &Txid {
+    edge.spent_by.as_ref().expect("hops are spent outputs")
+}
+
+impl<D: BlockchainDataSource + Sync> Tracer<D> {
+    /// Traces `outpoint` forward until it reaches `target`, and returns the paths to
+    /// the outputs paying it, fewest hops first.
+    ///
+    /// The trace runs under `config` with `target` added to its stop condition. Each
+    /// path starts with `outpoint` and ends with an output paying `target`; there are
+    /// at most `DEFAULT_MAX_PATHS` paths to each such output (see
+    /// `TraceGraph::find_paths`). No path means the trace did not reach `target` within
+    /// the limits of `config`.
+    ///
+    /// # Errors
+    /// - any error of `trace_forward`
+    pub async fn find_connection(
+        &self,
+        outpoint: OutPoint,
+        target: &Address,
+        config: &TraceConfig,
+    ) -> Result<Vec<TracePath>> {
+        let config = config
+            .clone()
+            .stop(config.stop.clone().target_address(target));
+        let graph = self.trace_forward(outpoint, &config).await?.into_graph();
+        let script = target.script_pubkey();
+        let options = PathOptions::default();
+        let Some(root) = graph.edge(&outpoint) else {
+            return Ok(Vec::new());
+        };
+        let mut paths = Vec::new();
+        for leaf in graph
+            .targets_reached()
+            .filter(|edge| edge.script_pubkey == script)
+        {
+            let Some(spender) = root.spent_by else {
+                // `outpoint` itself pays the target
+                paths.push(TracePath::new(&graph, [leaf]));
+                continue;
+            };
+            let middles = match spender == leaf.from() {
+                true => vec![Vec::new()],
+                false => graph.simple_paths(&spender, &leaf.from(), &options),
+            };
+            for middle in middles {
+                let edges = std::iter::once(root).chain(middle).chain([leaf]);
+                paths.push(TracePath::new(&graph, edges));
+            }
+        }
+        paths.sort_by_key(TracePath::len);
+        Ok(paths)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tracer::fixtures::{MockDataSource, script, spend};
+    use bitcoin::{Network, Transaction, hashes::Hash};
+
+    /// `funding -> split`, then two routes from `split` to `join`: `split:0 -> a ->
+    /// join` (2 hops, 10_000 sats at most) and `split:1 -> b -> c -> join` (3 hops,
+    /// 87_000 sats at most). `join` pays `script(42)`.
+    fn two_routes() -> Vec<Transaction> {
+        let funding = spend(0, &[OutPoint::new(Txid::all_zeros(), 5)], &[100_000]);
+        let split = spend(1, &[out(&funding, 0)], &[10_000, 89_000]);
+        let a = spend(2, &[out(&split, 0)], &[9_000]);
+        let b = spend(3, &[out(&split, 1)], &[88_000]);
+        let c = spend(4, &[out(&b, 0)], &[87_000]);
+        let mut join = spend(5, &[out(&a, 0), out(&c, 0)], &[95_000]);
+        join.output[0].script_pubkey = script(42);
+        vec![funding, split, a, b, c, join]
+    }
+
+    fn out(tx: &Transaction, vout: u32) -> OutPoint {
+        OutPoint::new(tx.compute_txid(), vout)
+    }
+
+    async fn traced(txs: &[Transaction]) -> TraceGraph {
+        Tracer::new(MockDataSource::new(txs))
+            .trace_forward(out(&txs[0], 0), &TraceConfig::default())
+            .await
+            .unwrap()
+            .into_graph()
+    }
+
+    fn txids(txs: &[Transaction], indexes: &[usize]) -> Vec<Txid> {
+        indexes.iter().map(|&i| txs[i].compute_txid()).collect()
+    }
+
+    #[tokio::test]
+    async fn test_finds_both_routes_shortest_first() {
+        let txs = two_routes();
+        let graph = traced(&txs).await;
+        let (split, join) = (txs[1].compute_txid(), txs[5].compute_txid());
+
+        let paths = graph.find_paths(&split, &join, &PathOptions::default());
+
+        assert_eq!(paths.len(), 2);
+        assert_eq!(paths[0].txids(), txids(&txs, &[1, 2, 5]));
+        assert_eq!(paths[0].value(), Some(Amount::from_sat(9_000)));
+        assert_eq!(paths[1].txids(), txids(&txs, &[1, 3, 4, 5]));
+        assert_eq!(
+            paths[1]
+                .hops
+                .iter()
+                .map(|hop| hop.value.to_sat())
+                .collect::<Vec<_>>(),
+            [89_000, 88_000, 87_000]
+        );
+        assert_eq!(paths[1].graph().len(), 4);
+        assert_eq!(paths[1].graph().edges().count(), 3);
+
+        let bounded = graph.find_paths(&split, &join, &PathOptions::default().max_length(2));
+        assert_eq!(bounded.len(), 1);
+        assert_eq!(bounded[0].len(), 2);
+        let bounded = graph.find_paths(&split, &join, &PathOptions::default().max_paths(1));
+        assert_eq!(bounded.len(), 1);
+        assert!(
+            graph
+                .find_paths(&join, &split, &PathOptions::default())
+                .is_empty()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_shortest_path_by_hops_or_value() {
+        let txs = two_routes();
+        let graph = traced(&txs).await;
+        let (funding, join) = (txs[0].compute_txid(), txs[5].compute_txid());
+
+        let fewest = graph
+            .shortest_path(&funding, &join, PathWeight::Hops)
+            .unwrap();
+        assert_eq!(fewest.txids(), txids(&txs, &[0, 1, 2, 5]));
+
+        let widest = graph
+            .shortest_path(&funding, &join, PathWeight::Value)
+            .unwrap();
+        assert_eq!(widest.txids(), txids(&txs, &[0, 1, 3, 4, 5]));
+        assert_eq!(widest.value(), Some(Amount::from_sat(87_000)));
+
+        assert_eq!(graph.shortest_path(&join, &funding, PathWeight::Hops), None);
+    }
+
+    #[tokio::test]
+    async fn test_cycles_do_not_loop() {
+        let txs = two_routes();
+        let mut graph = traced(&txs).await;
+        // An output of `join` spent back by `split`
+        let join = txs[5].compute_txid();
+        graph.insert_edge(TraceEdge {
+            spent_by: Some(txs[1].compute_txid()),
+            terminal: None,
+            ..graph.edge(&OutPoint::new(join, 0)).unwrap().clone()
+        });
+
+        let paths = graph.find_paths(&txs[0].compute_txid(), &join, &PathOptions::default());
+        assert_eq!(paths.len(), 2);
+        let back = graph.find_paths(&join, &txs[2].compute_txid(), &PathOptions::default());
+        assert_eq!(back.len(), 1);
+        assert_eq!(back[0].txids(), txids(&txs, &[5, 1, 2]));
+        for weight in [PathWeight::Hops, PathWeight::Value] {
+            assert!(
+                graph
+                    .shortest_path(&join, &txs[4].compute_txid(), weight)
+                    .is_some()
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_find_connection_returns_paths_to_target() {
+        let txs = two_routes();
+        let tracer = Tracer::new(MockDataSource::new(&txs));
+        let target = Address::from_script(&script(42), Network::Bitcoin).unwrap();
+
+        let paths = tracer
+            .find_connection(out(&txs[0], 0), &target, &TraceConfig::default())
+            .await
+            .unwrap();
+
+        assert_eq!(paths.len(), 2);
+        assert_eq!(paths[0].len(), 4);
+        assert_eq!(paths[0].hops[0].outpoint, out(&txs[0], 0));
+        let last = paths[0].hops.last().unwrap();
+        assert_eq!(last.to, None);
+        assert_eq!(last.address, Some(target.clone()));
+        assert_eq!(last.value, Amount::from_sat(95_000));
+        assert_eq!(paths[1].len(), 5);
+        let printed = paths[0].to_string();
+        assert_eq!(printed.lines().count(), 4);
+        assert!(printed.ends_with(&format!("-> {}: 0.00095 BTC (95000 sat)\n", target)));
+
+        let elsewhere = Address::from_script(&script(43), Network::Bitcoin).unwrap();
+        let none = tracer
+            .find_connection(out(&txs[0], 0), &elsewhere, &TraceConfig::default())
+            .await
+            .unwrap();
+        assert!(none.is_empty());
+    }
+}
@@ -0,0 +1,338 @@
+//! Structural patterns of the transactions of a trace.
+//!
+//! The shape of a transaction says something about who made it. Many inputs swept into
+//! one output are usually a single entity consolidating its coins; a few inputs paying
+//! many outputs are usually an exchange or a payment processor paying out a batch of
+//! withdrawals.
+
+use crate::tracer::coinjoin::near;
+use bitcoin::{Amount, Transaction, TxOut};
+use serde::{Deserialize, Serialize};
+
+/// Default fewest inputs of a sweep
+pub const DEFAULT_SWEEP_INPUTS: usize = 5;
+
+/// Default fewest outputs of a batch payout
+pub const DEFAULT_BATCH_OUTPUTS: usize = 10;
+
+/// Shape of a transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum TxPattern {
+    /// Many inputs gathered into few outputs: a consolidation by one entity
+    Sweep { inputs: usize },
+    /// Few inputs paying many outputs: typically a batch of exchange withdrawals
+    BatchPayout { outputs: usize },
+    /// One or two outputs, such as a payment and its change
+    SimplePayment,
+    /// Every output pays back a script the transaction spends from
+    SelfTransfer,
+    /// None of the above, or not classified
+    #[default]
+    Unknown,
+}
+
+impl TxPattern {
+    /// Stable lowercase name of the pattern, as used by the exports
+    pub fn code(&self) -> &'static str {
+        match self {
+            TxPattern::Sweep { .. } => "sweep",
+            TxPattern::BatchPayout { .. } => "batch_payout",
+            TxPattern::SimplePayment => "simple_payment",
+            TxPattern::SelfTransfer => "self_transfer",
+            TxPattern::Unknown => "unknown",
+        }
+    }
+
+    /// Pattern of its `code`, with the `count` of inputs or outputs a sweep or batch
+    /// payout carries; `None` for an unknown code
+    pub fn from_code(code: &str, count: usize) -> Option<Self> {
+        Some(match code {
+            "sweep" => TxPattern::Sweep { inputs: count },
+            "batch_payout" => TxPattern::BatchPayout { outputs: count },
+            "simple_payment" => TxPattern::SimplePayment,
+            "self_transfer" => TxPattern::SelfTransfer,
+            "unknown" => TxPattern::Unknown,
+            _ => return None,
+        })
+    }
+
+    /// Inputs of a sweep or outputs of a batch payout
+    pub fn count(&self) -> Option<usize> {
+        match self {
+            TxPattern::Sweep { inputs } => Some(*inputs),
+            TxPattern::BatchPayout { outputs } => Some(*outputs),
+            _ => None,
+        }
+    }
+}
+
+/// Thresholds telling the patterns apart.
+///
+/// OP_RETURN outputs are not counted: they carry no value to anyone.
+///
+/// # Fields
+/// * `min_sweep_inputs` - fewest inputs of a sweep
+/// * `max_sweep_outputs` - most outputs of a sweep
+/// * `min_batch_outputs` - fewest outputs of a batch payout
+/// * `max_batch_inputs` - most inputs of a batch payout
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PatternClassifier {
+    pub min_sweep_inputs: usize,
+    pub max_sweep_outputs: usize,
+    pub min_batch_outputs: usize,
+    pub max_batch_inputs: usize,
+}
+
+impl Default for PatternClassifier {
+    fn default() -> Self {
+        Self {
+            min_sweep_inputs: DEFAULT_SWEEP_INPUTS,
+            max_sweep_outputs: 1,
+            min_batch_outputs: DEFAULT_BATCH_OUTPUTS,
+            max_batch_inputs: 3,
+        }
+    }
+}
+
+impl PatternClassifier {
+    /// Fewest inputs of a sweep
+    pub fn min_sweep_inputs(mut self, count: usize) -> Self {
+        self.min_sweep_inputs = count;
+        self
+    }
+
+    /// Most outputs of a sweep
+    pub fn max_sweep_outputs(mut self, count: usize) -> Self {
+        self.max_sweep_outputs = count;
+        self
+    }
+
+    /// Fewest outputs of a batch payout
+    pub fn min_batch_outputs(mut self, count: usize) -> Self {
+        self.min_batch_outputs = count;
+        self
+    }
+
+    /// Most inputs of a batch payout
+    pub fn max_batch_inputs(mut self, count: usize) -> Self {
+        self.max_batch_inputs = count;
+        self
+    }
+
+    /// Classifies `tx`, given the outputs it spends as far as they are known.
+    ///
+    /// A self-transfer needs a known prevout: it is one whose outputs all pay scripts
+    /// of the known prevouts. A coinbase is `Unknown`.
+    pub fn classify(&self, tx: &Transaction, prevouts: &[TxOut]) -> TxPattern {
+        if tx.is_coinbase() {
+            return TxPattern::Unknown;
+        }
+        let outputs: Vec<_> = tx
+            .output
+            .iter()
+            .filter(|out| !out.script_pubkey.is_op_return())
+            .collect();
+        let inputs = tx.input.len();
+        if !prevouts.is_empty()
+            && !outputs.is_empty()
+            && outputs.iter().all(|out| {
+                prevouts
+                    .iter()
+                    .any(|prevout| prevout.script_pubkey == out.script_pubkey)
+            })
+        {
+            TxPattern::SelfTransfer
+        } else if inputs >= self.min_sweep_inputs
+            && (1..=self.max_sweep_outputs).contains(&outputs.len())
+        {
+            TxPattern::Sweep { inputs }
+        } else if outputs.len() >= self.min_batch_outputs && inputs <= self.max_batch_inputs {
+            TxPattern::BatchPayout {
+                outputs: outputs.len(),
+            }
+        } else if (1..=2).contains(&outputs.len()) {
+            TxPattern::SimplePayment
+        } else {
+            TxPattern::Unknown
+        }
+    }
+}
+
+/// Which outputs of a batch payout a forward trace follows
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum BatchPolicy {
+    /// Those the branch strategy selects, as at any other transaction
+    #[default]
+    Branch,
+    /// Only those within `tolerance` (relative, 0.01 = 1%) of the value of the output
+    /// traced into it, the likely withdrawal of the traced coins; none if no output
+    /// matches
+    FollowMatchingValue { tolerance: f64 },
+}
+
+impl BatchPolicy {
+    /// Outputs to follow of a transaction with `pattern`, when this policy overrides
+    /// the branch strategy there, the output traced into it being worth `traced`
+    pub fn matching_outputs(
+        &self,
+        pattern: &TxPattern,
+        outputs: &[TxOut],
+        traced: Amount,
+    ) -> Option<Vec<usize>> {
+        match (self, pattern) {
+            (BatchPolicy::FollowMatchingValue { tolerance }, TxPattern::BatchPayout { .. }) => {
+                Some(
+                    (0..outputs.len())
+                        .filter(|&vout| {
+                            !outputs[vout].script_pubkey.is_op_return()
+                                && near(outputs[vout].value, traced, *tolerance)
+                        })
+                        .collect(),
+                )
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tracer::fixtures::{coinbase, script, spend};
+    use bitcoin::{OutPoint, ScriptBuf, Txid, hashes::Hash};
+
+    /// Transaction spending `inputs` outputs of distinct transactions into `outputs`
+    fn shaped(tag: u32, inputs: u32, outputs: usize) -> Transaction {
+        let prevouts: Vec<_> = (0..inputs)
+            .map(|n| OutPoint::new(Txid::from_byte_array([7; 32]), tag * 100 + n))
+            .collect();
+        let mut tx = spend(tag, &prevouts, &vec![10_000; outputs]);
+        for (out, n) in tx.output.iter_mut().zip(0..) {
+            out.script_pubkey = script(n);
+        }
+        tx
+    }
+
+    #[test]
+    fn test_classifies_each_pattern() {
+        let classifier = PatternClassifier::default();
+
+        let sweep = shaped(1, 8, 1);
+        assert_eq!(
+            classifier.classify(&sweep, &[]),
+            TxPattern::Sweep { inputs: 8 }
+        );
+        let batch = shaped(2, 2, 25);
+        assert_eq!(
+            classifier.classify(&batch, &[]),
+            TxPattern::BatchPayout { outputs: 25 }
+        );
+        let payment = shaped(3, 1, 2);
+        assert_eq!(classifier.classify(&payment, &[]), TxPattern::SimplePayment);
+
+        // Both outputs pay back the script of the known prevout
+        let mut moved = shaped(4, 1, 2);
+        moved.output[1].script_pubkey = script(0);
+        let prevout = TxOut {
+            value: Amount::from_sat(20_500),
+            script_pubkey: script(0),
+        };
+        assert_eq!(
+            classifier.classify(&moved, std::slice::from_ref(&prevout)),
+            TxPattern::SelfTransfer
+        );
+        assert_eq!(classifier.classify(&moved, &[]), TxPattern::SimplePayment);
+
+        // Too many inputs for a batch, too many outputs for a sweep or a payment
+        assert_eq!(
+            classifier.classify(&shaped(5, 6, 12), &[]),
+            TxPattern::Unknown
+        );
+        assert_eq!(
+            classifier.classify(&coinbase(6, &[50_000]), &[]),
+            TxPattern::Unknown
+        );
+    }
+
+    #[test]
+    fn test_thresholds_and_op_return() {
+        let mut sweep = shaped(1, 3, 1);
+        sweep.output.push(TxOut {
+            value: Amount::ZERO,
+            script_pubkey: ScriptBuf::new_op_return([1, 2, 3]),
+        });
+        // The OP_RETURN output does not count, so this is a payment or a sweep
+        assert_eq!(
+            PatternClassifier::default().classify(&sweep, &[]),
+            TxPattern::SimplePayment
+        );
+        assert_eq!(
+            PatternClassifier::default()
+                .min_sweep_inputs(3)
+                .classify(&sweep, &[]),
+            TxPattern::Sweep { inputs: 3 }
+        );
+
+        let batch = shaped(2, 4, 6);
+        let classifier = PatternClassifier::default()
+            .min_batch_outputs(6)
+            .max_batch_inputs(4);
+        assert_eq!(
+            classifier.classify(&batch, &[]),
+            TxPattern::BatchPayout { outputs: 6 }
+        );
+        assert_eq!(
+            classifier
+                .max_sweep_outputs(2)
+                .min_sweep_inputs(2)
+                .classify(&shaped(3, 2, 2), &[]),
+            TxPattern::Sweep { inputs: 2 }
+        );
+    }
+
+    #[test]
+    fn test_codes_round_trip() {
+        for pattern in [
+            TxPattern::Sweep { inputs: 7 },
+            TxPattern::BatchPayout { outputs: 30 },
+            TxPattern::SimplePayment,
+            TxPattern::SelfTransfer,
+            TxPattern::Unknown,
+        ] {
+            let count = pattern.count().unwrap_or(0);
+            assert_eq!(TxPattern::from_code(pattern.code(), count), Some(pattern));
+        }
+        assert_eq!(TxPattern::from_code("mixer", 0), None);
+    }
+
+    #[test]
+    fn test_batch_policy_matches_the_traced_value() {
+        let mut batch = shaped(1, 1, 12);
+        batch.output[4].value = Amount::from_sat(99_500);
+        batch.output[9].value = Amount::from_sat(150_000);
+        let pattern = TxPattern::BatchPayout { outputs: 12 };
+        let traced = Amount::from_sat(100_000);
+
+        let policy = BatchPolicy::FollowMatchingValue { tolerance: 0.01 };
+        assert_eq!(
+            policy.matching_outputs(&pattern, &batch.output, traced),
+            Some(vec![4])
+        );
+        assert_eq!(
+            BatchPolicy::FollowMatchingValue { tolerance: 0.0 }.matching_outputs(
+                &pattern,
+                &batch.output,
+                traced
+            ),
+            Some(vec![])
+        );
+        assert_eq!(
+            BatchPolicy::Branch.matching_outputs(&pattern, &batch.output, traced),
+            None
+        );
+        assert_eq!(
+            policy.matching_outputs(&TxPattern::SimplePayment, &batch.output, traced),
+            None
+        );
+    }
+}
@@ -0,0 +1,390 @@
+//! Peel chain detection.
+//!
+//! A peel chain spends a large UTXO into a small payment and a large change output,
+//! then spends the change the same way, hop after hop. Following only the change keeps
+//! a trace of such a chain linear instead of fanning out into every payment.
+//!
+//! The change output of a two-output transaction is guessed from three signals, each
+//! voting for one output:
+//! - value: the change carries most of the input minus the fee, at least twice the
+//!   payment
+//! - script type: the change pays the same kind of script as the inputs spend
+//! - round number: payments tend to be round amounts, change does not
+//!
+//! Contradicting or missing signals make the guess ambiguous, and nothing is guessed.
+
+use crate::tracer::{
+    TraceEdge, TraceGraph,
+    script::{ScriptClass, classify_script},
+};
+use bitcoin::{Address, Amount, OutPoint, Script, TxOut, Txid};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/// An amount in sats divisible by this is round (0.0001 BTC)
+const ROUND_SATS: u64 = 10_000;
+
+/// How strongly the signals point at the change output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Confidence {
+    /// The signals are tied: the change is not guessed
+    Low,
+    /// One more signal for the change than for the payment
+    Medium,
+    /// At least two more signals for the change than for the payment
+    High,
+}
+
+impl Confidence {
+    /// Stable lowercase name of the level, as used by the exports
+    pub fn code(&self) -> &'static str {
+        match self {
+            Confidence::Low => "low",
+            Confidence::Medium => "medium",
+            Confidence::High => "high",
+        }
+    }
+}
+
+/// The change output picked among the outputs of a transaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChangeGuess {
+    /// Index of the change, meaningless when `confidence` is `Low`
+    pub vout: usize,
+    pub confidence: Confidence,
+}
+
+/// Guesses which of `outputs` is the change, given the outputs the transaction spends
+/// (all of them or only the known ones).
+///
+/// `None` unless there are exactly two outputs, the shape of a peel.
+pub fn guess_change(outputs: &[TxOut], inputs: &[TxOut]) -> Option<ChangeGuess> {
+    let [first, second] = outputs else {
+        return None;
+    };
+    let mut votes = [0i32; 2];
+    let mut vote = |first_wins: bool, second_wins: bool| match (first_wins, second_wins) {
+        (true, false) => votes[0] += 1,
+        (false, true) => votes[1] += 1,
+        _ => {}
+    };
+
+    vote(
+        first.value > second.value * 2,
+        second.value > first.value * 2,
+    );
+    let input_types: HashSet<_> = inputs
+        .iter()
+        .filter_map(|input| script_type(&input.script_pubkey))
+        .collect();
+    let matches_inputs =
+        |out: &TxOut| script_type(&out.script_pubkey).is_some_and(|t| input_types.contains(&t));
+    vote(matches_inputs(first), matches_inputs(second));
+    // A round amount is a vote for the other output
+    vote(is_round(second.value), is_round(first.value));
+    let [a, b] = votes;
+    let (vout, margin) = if a >= b { (0, a - b) } else { (1, b - a) };
+    let confidence = match margin {
+        0 => Confidence::Low,
+        1 => Confidence::Medium,
+        _ => Confidence::High,
+    };
+    Some(ChangeGuess { vout, confidence })
+}
+
+fn is_round(value: Amount) -> bool {
+    value.to_sat().is_multiple_of(ROUND_SATS)
+}
+
+/// Kind of a standard script, `None` for anything else
+fn script_type(script: &Script) -> Option<ScriptClass> {
+    Some(classify_script(script)).filter(ScriptClass::has_address)
+}
+
+/// One hop of a peel chain.
+///
+/// # Fields
+/// * `txid` - transaction of the hop
+/// * `change` - output continuing the chain
+/// * `payment` - output peeled off
+/// * `peeled` - value of the payment
+/// * `destination` - address of the payment, if it has a standard form
+/// * `confidence` - how strongly the signals pointed at `change`
+#[derive(Debug, Clone, PartialEq)]
+pub struct PeelHop {
+    pub txid: Txid,
+    pub change: OutPoint,
+    pub payment: OutPoint,
+    pub peeled: Amount,
+    pub destination: Option<Address>,
+    pub confidence: Confidence,
+}
+
+/// A peel chain found in a trace.
+///
+/// # Fields
+/// * `hops` - hops in chain order, from the starting transaction
+/// * `ambiguous_at` - transaction the chain could not be followed past because its
+///   change was ambiguous
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PeelChain {
+    pub hops: Vec<PeelHop>,
+    pub ambiguous_at: Option<Txid>,
+}
+
+impl PeelChain {
+    /// Weakest confidence along the chain, `Low` if it ended on an ambiguous hop
+    pub fn confidence(&self) -> Confidence {
+        if self.ambiguous_at.is_some() {
+            return Confidence::Low;
+        }
+        self.hops
+            .iter()
+            .map(|hop| hop.confidence)
+            .min()
+            .unwrap_or(Confidence::Low)
+    }
+
+    /// Total value peeled off along the chain
+    pub fn total_peeled(&self) -> Amount {
+        self.hops.iter().map(|hop| hop.peeled).sum()
+    }
+}
+
+impl TraceGraph {
+    /// Follows the peel chain starting at `start` through the graph.
+    ///
+    /// Each two-output transaction is a hop; the chain continues through the spender of
+    /// its change and ends at a transaction that is not a two-output transaction, whose
+    /// change is unspent or was not traced, or whose change is ambiguous (recorded in
+    /// `ambiguous_at`). Meant for forward traces, which record every output of the
+    /// transactions they visit.
+    pub fn detect_peel_chain(&self, start: &Txid) -> PeelChain {
+        let mut chain = PeelChain::default();
+        let mut visited = HashSet::new();
+        let mut txid = *start;
+        while self.contains_node(&txid) && visited.insert(txid) {
+            let edges: Vec<_> = self.outputs_of(&txid).collect();
+            let outputs: Vec<_> = edges.iter().map(|&edge| tx_out(edge)).collect();
+            let inputs: Vec<_> = self.inputs_of(&txid).map(tx_out).collect();
+            let Some(guess) = guess_change(&outputs, &inputs) else {
+                break;
+            };
+            if guess.confidence == Confidence::Low {
+                chain.ambiguous_at = Some(txid);
+                break;
+            }
+            let (change, payment) = (edges[guess.vout], edges[1 - guess.vout]);
+            chain.hops.push(PeelHop {
+                txid,
+                change: change.outpoint,
+                payment: payment.outpoint,
+                peeled: payment.value,
+                destination: payment.address.clone(),
+                confidence: guess.confidence,
+            });
+            let Some(next) = change.spent_by else {
+                break;
+            };
+            txid = next;
+        }
+        chain
+    }
+}
+
+pub(crate) fn tx_out(edge: &TraceEdge) -> TxOut {
+    TxOut {
+        value: edge.value,
+        script_pubkey: edge.script_pubkey.clone(),
+    }
+}
+
+#[cfg(all(test, feature = "trace-engine"))]
+mod tests {
+    use super::*;
+    use crate::tracer::{
+        BranchStrategy, TerminalReason, TraceConfig, Tracer,
+        fixtures::{MockDataSource, script, spend},
+    };
+    use bitcoin::{PubkeyHash, ScriptBuf, Transaction, hashes::Hash};
+
+    /// Legacy script of a made-up payee, distinct per `n`
+    fn payee(n: u8) -> ScriptBuf {
+        ScriptBuf::new_p2pkh(&PubkeyHash::from_byte_array([n; 20]))
+    }
+
+    fn out(sats: u64, script_pubkey: ScriptBuf) -> TxOut {
+        TxOut {
+            value: Amount::from_sat(sats),
+            script_pubkey,
+        }
+    }
+
+    /// Spends `input` into `outputs`
+    fn hop(tag: u32, input: OutPoint, outputs: Vec<TxOut>) -> Transaction {
+        let mut tx = spend(tag, &[input], &[]);
+        tx.output = outputs;
+        tx
+    }
+
+    /// Funding of 10 BTC to a segwit script, then `hops` hops each paying a round
+    /// 0.1 BTC to a legacy payee and the rest minus a 1_234 sat fee back to segwit
+    /// change. The change position alternates, and every payment is spent onward.
+    fn peel_chain(hops: u32) -> (OutPoint, Vec<Transaction>) {
+        let funding = spend(0, &[], &[1_000_000_000]);
+        let root = OutPoint::new(funding.compute_txid(), 0);
+        let mut txs = vec![funding];
+        let (mut input, mut value) = (root, 1_000_000_000);
+        for n in 1..=hops {
+            value -= 10_000_000 + 1_234;
+            let payment = out(10_000_000, payee(n as u8));
+            let change = out(value, script(0));
+            let change_vout = n % 2;
+            let outputs = if change_vout == 0 {
+                vec![change, payment]
+            } else {
+                vec![payment, change]
+            };
+            let tx = hop(n, input, outputs);
+            let txid = tx.compute_txid();
+            txs.push(hop(
+                100 + n,
+                OutPoint::new(txid, 1 - change_vout),
+                vec![out(1, script(9))],
+            ));
+            txs.push(tx);
+            input = OutPoint::new(txid, change_vout);
+        }
+        (root, txs)
+    }
+
+    #[test]
+    fn test_guess_change_signals() {
+        let inputs = [out(100_000_000, script(0))];
+        let guess = |outputs: &[TxOut]| guess_change(outputs, &inputs).unwrap();
+
+        // Value, script type and round number all agree
+        let clear = [out(10_000_000, payee(1)), out(89_998_766, script(0))];
+        assert_eq!(
+            guess(&clear),
+            ChangeGuess {
+                vout: 1,
+                confidence: Confidence::High
+            }
+        );
+        // Only the value speaks
+        let value_only = [out(70_001_234, payee(1)), out(29_997_532, payee(2))];
+        assert_eq!(guess(&value_only).confidence, Confidence::Medium);
+        assert_eq!(guess(&value_only).vout, 0);
+        // The larger output is a round amount: value and roundness disagree
+        let split = [out(80_000_000, script(1)), out(19_998_766, script(2))];
+        assert_eq!(guess(&split).confidence, Confidence::Low);
+
+        assert!(guess_change(&[out(1, script(0))], &inputs).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_detects_ten_hop_chain() {
+        let (root, txs) = peel_chain(10);
+        let tracer = Tracer::new(MockDataSource::new(&txs));
+        let graph = tracer
+            .trace_forward(root, &TraceConfig::default().max_depth(20))
+            .await
+            .unwrap()
+            .into_graph();
+        // Each hop and the spender of its payment
+        assert_eq!(graph.len(), 1 + 10 * 2);
+
+        let first_hop = graph.edge(&root).unwrap().spent_by.unwrap();
+        let chain = graph.detect_peel_chain(&first_hop);
+
+        assert_eq!(chain.hops.len(), 10);
+        assert_eq!(chain.confidence(), Confidence::High);
+        assert_eq!(chain.ambiguous_at, None);
+        assert_eq!(chain.total_peeled(), Amount::from_sat(100_000_000));
+        for (n, hop) in chain.hops.iter().enumerate() {
+            let n = n as u8 + 1;
+            assert_eq!(hop.peeled, Amount::from_sat(10_000_000));
+            assert_eq!(hop.destination.as_ref().unwrap().script_pubkey(), payee(n));
+            assert_eq!(hop.change.vout, u32::from(n % 2));
+        }
+        // Consecutive hops link through the change
+        for pair in chain.hops.windows(2) {
+            assert_eq!(
+                graph.edge(&pair[0].change).unwrap().spent_by,
+                Some(pair[1].txid)
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_follow_peel_chain_strategy() {
+        let (root, txs) = peel_chain(10);
+        let tracer = Tracer::new(MockDataSource::new(&txs));
+        let config = TraceConfig::default()
+            .max_depth(20)
+            .branch(BranchStrategy::FollowPeelChain);
+
+        let graph = tracer
+            .trace_forward(root, &config)
+            .await
+            .unwrap()
+            .into_graph();
+
+        // Only the chain is traced, the payments are side edges
+        assert_eq!(graph.len(), 1 + 10);
+        let peeled: Vec<_> = graph
+            .edges()
+            .filter(|edge| edge.terminal == Some(TerminalReason::Peeled))
+            .collect();
+        assert_eq!(peeled.len(), 10);
+        assert!(
+            peeled
+                .iter()
+                .all(|edge| edge.value == Amount::from_sat(10_000_000))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_ambiguous_hop_is_not_guessed() {
+        let (root, mut txs) = peel_chain(3);
+        // The last change is split into two similar, non-round outputs of one kind
+        let last = txs.last().unwrap();
+        let change = OutPoint::new(last.compute_txid(), 1);
+        let split = hop(
+            50,
+            change,
+            vec![out(480_001_111, script(0)), out(489_994_953, script(0))],
+        );
+        let split_txid = split.compute_txid();
+        txs.push(split);
+        let tracer = Tracer::new(MockDataSource::new(&txs));
+
+        let graph = tracer
+            .trace_forward(root, &TraceConfig::default())
+            .await
+            .unwrap()
+            .into_graph();
+        let first_hop = graph.edge(&root).unwrap().spent_by.unwrap();
+        let chain = graph.detect_peel_chain(&first_hop);
+
+        assert_eq!(chain.hops.len(), 3);
+        assert_eq!(chain.ambiguous_at, Some(split_txid));
+        assert_eq!(chain.confidence(), Confidence::Low);
+
+        // The strategy follows both outputs of the ambiguous hop rather than pick one
+        let graph = tracer
+            .trace_forward(
+                root,
+                &TraceConfig::default().branch(BranchStrategy::FollowPeelChain),
+            )
+            .await
+            .unwrap()
+            .into_graph();
+        assert!(
+            graph
+                .outputs_of(&split_txid)
+                .all(|edge| edge.terminal == Some(TerminalReason::Unspent))
+        );
+    }
+}
@@ -0,0 +1,60 @@
+//! Replace-by-fee: unconfirmed transactions that may still be replaced.
+//!
+//! Until it confirms, a transaction signalling BIP 125 replaceability can be
+//! replaced by another spending some of the same prevouts, usually at a higher fee.
+//! A trace through such a transaction may follow coins that never move that way:
+//! nodes record whether they are replaceable, and `Tracer::check_replacement` asks the
+//! data source whether a conflicting transaction took one's place.
+
+use bitcoin::{Transaction, Txid};
+use serde::{Deserialize, Serialize};
+
+/// Whether `tx` signals replaceability (BIP 125): an input with a sequence below
+/// 0xfffffffe
+pub fn is_rbf_signaling(tx: &Transaction) -> bool {
+    tx.is_explicitly_rbf()
+}
+
+/// Whether another transaction took the place of one, by spending its prevouts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ReplacementStatus {
+    /// No other transaction spends the prevouts of the transaction
+    NotReplaced,
+    /// `by` spends a prevout of the transaction instead: confirmed or in the mempool,
+    /// `None` when the source cannot tell
+    Replaced { by: Txid, confirmed: Option<bool> },
+}
+
+impl ReplacementStatus {
+    pub fn is_replaced(&self) -> bool {
+        matches!(self, ReplacementStatus::Replaced { .. })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tracer::{TraceNode, fixtures::spend};
+    use bitcoin::{OutPoint, Sequence, Txid, hashes::Hash};
+
+    #[test]
+    fn test_signaling_needs_one_input_below_the_final_sequences() {
+        let inputs = [
+            OutPoint::new(Txid::all_zeros(), 0),
+            OutPoint::new(Txid::all_zeros(), 1),
+        ];
+        let mut tx = spend(1, &inputs, &[1_000]);
+        assert!(!is_rbf_signaling(&tx));
+        // Final, or opting into locktime only
+        tx.input[1].sequence = Sequence::ENABLE_LOCKTIME_NO_RBF;
+        assert!(!is_rbf_signaling(&tx));
+        assert!(!TraceNode::new(&tx, 0).replaceable);
+
+        tx.input[1].sequence = Sequence::ENABLE_RBF_NO_LOCKTIME;
+        assert!(is_rbf_signaling(&tx));
+        assert!(TraceNode::new(&tx, 0).replaceable);
+        // A relative timelock signals too
+        tx.input[1].sequence = Sequence::from_height(144);
+        assert!(is_rbf_signaling(&tx));
+    }
+}
@@ -0,0 +1,175 @@
+//! What a trace cost: requests made, cache hits and time taken.
+//!
+//! `Tracer::trace_forward_with_report` (and its backward counterpart) wrap the outcome
+//! of a trace in a `TraceReport`. Under `TraceConfig::dry_run`, the trace only samples
+//! the first hops and the report extrapolates the requests the full trace would make,
+//! to check a trace fits a metered backend before running it.
+
+use crate::tracer::{
+    CoinbaseProvenance, TerminalReason, TraceConfig, TraceGraph, TraceOutcome, TraceSummary,
+};
+use bitcoin::Amount;
+use std::{collections::BTreeMap, time::Duration};
+
+/// Outcome of a trace with its costs.
+///
+/// # Fields
+/// * `outcome` - the graph traced and how the trace ended
+/// * `requests` - requests made to the data source, counted against `max_requests`
+/// * `cache_hits` - lookups the data source answered from its cache, for free
+/// * `elapsed` - time the trace took
+/// * `estimated_requests` - under `dry_run`, requests the full trace is expected to
+///   make, within its caps
+/// * `ignored_outputs` - outputs left unfollowed for being worth less than
+///   `min_output_value`
+/// * `ignored_value` - total value of those outputs
+/// * `skipped_expansions` - transactions left unexpanded for being in
+///   `TraceConfig::visited`
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceReport {
+    pub outcome: TraceOutcome,
+    pub requests: usize,
+    pub cache_hits: usize,
+    pub elapsed: Duration,
+    pub estimated_requests: Option<usize>,
+    pub ignored_outputs: usize,
+    pub ignored_value: Amount,
+    pub skipped_expansions: usize,
+}
+
+impl TraceReport {
+    pub fn graph(&self) -> &TraceGraph {
+        self.outcome.graph()
+    }
+
+    /// Aggregates of the graph traced
+    pub fn summary(&self) -> TraceSummary {
+        TraceSummary::from_graph(self.graph())
+    }
+
+    /// Mining pools behind the coinbases the trace reached, `None` if it reached none:
+    /// where the coins of a backward trace's ancestry were mined
+    pub fn provenance(&self) -> Option<CoinbaseProvenance> {
+        CoinbaseProvenance::from_graph(self.graph())
+    }
+
+    /// Branches ended for each reason, by reason code (see `TerminalReason::code`)
+    pub fn terminations(&self) -> BTreeMap<&'static str, usize> {
+        let mut terminations = BTreeMap::new();
+        for reason in self
+            .graph()
+            .edges()
+            .filter_map(|edge| edge.terminal.as_ref())
+        {
+            *terminations.entry(reason.code()).or_default() += 1;
+        }
+        terminations
+    }
+}
+
+/// Number and total value of the outputs of `graph` left unfollowed for being worth
+/// less than `min_output_value`
+pub(crate) fn ignored(graph: &TraceGraph) -> (usize, Amount) {
+    graph
+        .edges()
+        .filter(|edge| edge.terminal == Some(TerminalReason::BelowMinValue))
+        .fold((0, Amount::ZERO), |(count, value), edge| {
+            (
+                count + 1,
+                value.checked_add(edge.value).unwrap_or(Amount::MAX),
+            )
+        })
+}
+
+/// Requests a trace under `config` would make, extrapolated from `sample`, the same
+/// trace cut at depth `sampled` after `requests` requests.
+///
+/// The growth from the last but one sampled depth to the last one is assumed to hold
+/// down to `max_depth`, within `max_breadth` and `max_transactions`, at the requests
+/// per transaction the sample took.
+pub(crate) fn estimate_requests(
+    sample: &TraceGraph,
+    requests: usize,
+    sampled: usize,
+    config: &TraceConfig,
+) -> usize {
+    let mut per_depth = vec![0usize; sampled + 1];
+    for node in sample.nodes() {
+        if let Some(count) = per_depth.get_mut(node.depth) {
+            *count += 1;
+        }
+    }
+    // The sample is the whole trace when it never got as deep as it was cut
+    if sampled >= config.max_depth || sampled == 0 || per_depth[sampled] == 0 {
+        return requests;
+    }
+
+    let growth = per_depth[sampled] as f64 / per_depth[sampled - 1] as f64;
+    let mut transactions = sample.len() as f64;
+    let mut level = per_depth[sampled] as f64;
+    for _ in sampled..config.max_depth {
+        level *= growth;
+        if let Some(max) = config.max_breadth {
+            level = level.min(max as f64);
+        }
+        transactions += level;
+        if transactions >= config.max_transactions as f64 {
+            transactions = config.max_transactions as f64;
+            break;
+        }
+    }
+    let per_transaction = requests as f64 / sample.len() as f64;
+    let estimate = (transactions * per_transaction).ceil() as usize;
+    config
+        .max_requests
+        .map_or(estimate, |max| estimate.min(max))
+        .max(requests)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tracer::{TraceNode, fixtures::spend};
+    use bitcoin::OutPoint;
+
+    /// Graph of `per_depth[d]` made-up transactions at each depth `d`
+    fn sample(per_depth: &[usize]) -> TraceGraph {
+        let mut graph = TraceGraph::new();
+        let mut tag = 0;
+        for (depth, &count) in per_depth.iter().enumerate() {
+            for _ in 0..count {
+                tag += 1;
+                graph.insert_node(TraceNode::new(
+                    &spend(tag, &[OutPoint::null()], &[1]),
+                    depth,
+                ));
+            }
+        }
+        graph
+    }
+
+    #[test]
+    fn test_estimate_extrapolates_growth_within_caps() {
+        // 1 -> 2 -> 4 transactions, 14 requests: 2 per transaction
+        let graph = sample(&[1, 2, 4]);
+        let config = TraceConfig::default().max_depth(4);
+        // Depths 3 and 4 hold 8 and 16 more
+        assert_eq!(estimate_requests(&graph, 14, 2, &config), (7 + 8 + 16) * 2);
+
+        let capped = config.clone().max_breadth(5);
+        assert_eq!(estimate_requests(&graph, 14, 2, &capped), (7 + 5 + 5) * 2);
+        let capped = config.clone().max_transactions(10);
+        assert_eq!(estimate_requests(&graph, 14, 2, &capped), 20);
+        let capped = config.max_requests(30);
+        assert_eq!(estimate_requests(&graph, 14, 2, &capped), 30);
+    }
+
+    #[test]
+    fn test_estimate_of_complete_sample_is_exact() {
+        let config = TraceConfig::default().max_depth(2);
+        assert_eq!(estimate_requests(&sample(&[1, 2, 4]), 14, 2, &config), 14);
+        // Died out before the cut
+        let config = TraceConfig::default();
+        assert_eq!(estimate_requests(&sample(&[1, 2]), 5, 2, &config), 5);
+    }
+}
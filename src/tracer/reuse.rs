@@ -0,0 +1,194 @@
+//! Address reuse: scripts paid more than once within a trace.
+//!
+//! Reuse ties the outputs paying an address to a single owner, which makes it one of
+//! the strongest clustering signals. Stronger still is a round trip, an address that
+//! received funds and later spends another payment to it back into the trace.
+
+use crate::tracer::TraceGraph;
+use bitcoin::{Address, Amount, OutPoint, ScriptBuf, Txid};
+use serde::Serialize;
+
+/// An output paying a reused script.
+///
+/// # Fields
+/// * `outpoint` - the output
+/// * `value` - amount it carries
+/// * `depth` - depth of the transaction creating it
+/// * `spent_by` - transaction spending it, when traced
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ReuseOccurrence {
+    pub outpoint: OutPoint,
+    pub value: Amount,
+    pub depth: usize,
+    pub spent_by: Option<Txid>,
+}
+
+/// A script paid by more than one output of a trace.
+///
+/// # Fields
+/// * `script_pubkey` - the script
+/// * `address` - its address, if it has a standard form
+/// * `occurrences` - the outputs paying it, by depth then outpoint
+/// * `round_trip` - one of the outputs is spent by a transaction deeper in the trace
+///   than another of them was created: the address received funds, then spent from
+///   itself later on
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AddressReuse {
+    pub script_pubkey: ScriptBuf,
+    pub address: Option<Address>,
+    pub occurrences: Vec<ReuseOccurrence>,
+    pub round_trip: bool,
+}
+
+impl TraceGraph {
+    /// Every script paid by more than one output of the graph, OP_RETURN scripts aside.
+    ///
+    /// Outputs are looked up in the index the graph keeps of them by script, so this
+    /// is linear in the number of reused outputs. Outputs of transactions missing from
+    /// the graph are left out. Sorted by the depth and outpoint of the first
+    /// occurrence.
+    pub fn address_reuse(&self) -> Vec<AddressReuse> {
+        let mut reuse: Vec<AddressReuse> = self
+            .reused_scripts()
+            .filter(|(script, _)| !script.is_op_return())
+            .filter_map(|(script, outpoints)| {
+                let mut occurrences: Vec<ReuseOccurrence> = outpoints
+                    .iter()
+                    .filter_map(|outpoint| {
+                        let edge = self.edge(outpoint)?;
+                        Some(ReuseOccurrence {
+                            outpoint: *outpoint,
+                            value: edge.value,
+                            depth: self.node(&outpoint.txid)?.depth,
+                            spent_by: edge.spent_by,
+                        })
+                    })
+                    .collect();
+                if occurrences.len() < 2 {
+                    return None;
+                }
+                occurrences.sort_by_key(|occurrence| (occurrence.depth, occurrence.outpoint));
+                let round_trip = occurrences.iter().enumerate().any(|(index, spent)| {
+                    let Some(depth) = spent
+                        .spent_by
+                        .and_then(|txid| self.node(&txid))
+                        .map(|node| node.depth)
+                    else {
+                        return false;
+                    };
+                    // The shallowest of the other occurrences
+                    let earliest = &occurrences[if index == 0 { 1 } else { 0 }];
+                    earliest.depth < depth
+                });
+                let address = outpoints
+                    .first()
+                    .and_then(|outpoint| self.edge(outpoint)?.address.clone());
+                Some(AddressReuse {
+                    script_pubkey: script.clone(),
+                    address,
+                    occurrences,
+                    round_trip,
+                })
+            })
+            .collect();
+        reuse.sort_by_key(|reuse| {
+            let first = &reuse.occurrences[0];
+            (first.depth, first.outpoint)
+        });
+        reuse
+    }
+}
+
+#[cfg(all(test, feature = "trace-engine"))]
+mod tests {
+    use super::*;
+    use crate::tracer::{
+        TraceConfig, TraceEdge, Tracer,
+        fixtures::{MockDataSource, reused, sample_graph, script},
+    };
+
+    async fn trace(max_depth: usize) -> TraceGraph {
+        let txs = reused();
+        Tracer::new(MockDataSource::new(&txs))
+            .trace_forward(
+                OutPoint::new(txs[0].compute_txid(), 0),
+                &TraceConfig::default().max_depth(max_depth),
+            )
+            .await
+            .unwrap()
+            .into_graph()
+    }
+
+    #[tokio::test]
+    async fn test_reports_reuse_across_depths() {
+        let txs = reused();
+        let graph = trace(10).await;
+
+        let reuse = graph.address_reuse();
+
+        assert_eq!(reuse.len(), 1);
+        let reused = &reuse[0];
+        assert_eq!(reused.script_pubkey, script(40));
+        assert!(reused.address.is_some());
+        let occurrences: Vec<_> = reused
+            .occurrences
+            .iter()
+            .map(|occurrence| {
+                (
+                    occurrence.outpoint,
+                    occurrence.value.to_sat(),
+                    occurrence.depth,
+                )
+            })
+            .collect();
+        assert_eq!(
+            occurrences,
+            [
+                (OutPoint::new(txs[1].compute_txid(), 1), 39_000, 1),
+                (OutPoint::new(txs[4].compute_txid(), 1), 27_000, 4),
+            ]
+        );
+        // Received at depth 1, spent from at depth 5
+        assert_eq!(reused.occurrences[1].spent_by, Some(txs[5].compute_txid()));
+        assert!(reused.round_trip);
+        assert_eq!(graph.outputs_paying(&script(40)).count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_reuse_without_spending_is_no_round_trip() {
+        // The sweep is past max_depth: the second payment is never spent
+        let graph = trace(4).await;
+
+        let reuse = graph.address_reuse();
+
+        assert_eq!(reuse.len(), 1);
+        assert!(!reuse[0].round_trip);
+    }
+
+    #[tokio::test]
+    async fn test_index_follows_replaced_and_merged_edges() {
+        let graph = sample_graph().await;
+        // Funding, split and sweep all pay script(0); the sweep spends one of them
+        let reuse = graph.address_reuse();
+        assert_eq!(reuse.len(), 1);
+        assert_eq!(reuse[0].occurrences.len(), 3);
+        assert!(reuse[0].round_trip);
+
+        let mut moved = graph.clone();
+        let edge = moved.outputs_paying(&script(0)).last().unwrap().clone();
+        moved.insert_edge(TraceEdge {
+            script_pubkey: script(9),
+            ..edge
+        });
+        assert_eq!(moved.outputs_paying(&script(0)).count(), 2);
+        assert_eq!(moved.outputs_paying(&script(9)).count(), 1);
+
+        let mut merged = TraceGraph::new();
+        merged.merge(&graph);
+        assert_eq!(merged.address_reuse(), reuse);
+        let back: TraceGraph =
+            serde_json::from_str(&serde_json::to_string(&graph).unwrap()).unwrap();
+        assert_eq!(back, graph);
+        assert_eq!(back.address_reuse(), reuse);
+    }
+}
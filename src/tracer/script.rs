@@ -0,0 +1,342 @@
+//! Classification of output scripts, and decoding of the data OP_RETURN outputs
+//! carry.
+//!
+//! An OP_RETURN output is provably unspendable: a trace never follows one, but its
+//! payload (a proof-of-reserves tag, an Omni or Runes message, ...) often says more
+//! about the transaction than its other outputs do.
+//!
+//! Witness scripts are only revealed by the inputs spending P2WSH outputs. Those of
+//! Lightning channels are recognized here: the 2-of-2 multisig locking a channel's
+//! funding output, and the CSV-delayed `to_local` output of a commitment transaction
+//! (BOLT 3).
+
+use bitcoin::{
+    PublicKey, Script,
+    opcodes::all::{
+        OP_CHECKMULTISIG, OP_CHECKSIG, OP_CSV, OP_DROP, OP_ELSE, OP_ENDIF, OP_IF, OP_PUSHNUM_2,
+        OP_PUSHNUM_13,
+    },
+    script::{Instruction, PushBytes},
+};
+use serde::{Deserialize, Serialize};
+
+/// Standard form of an output script.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScriptClass {
+    P2pkh,
+    P2sh,
+    P2wpkh,
+    P2wsh,
+    P2tr,
+    /// Starts with OP_RETURN: carries data, cannot be spent
+    OpReturn,
+    /// Anything else (bare multisig, unknown witness versions, ...)
+    NonStandard,
+}
+
+impl ScriptClass {
+    /// Stable snake_case name of the class, as used by the exports
+    pub fn code(&self) -> &'static str {
+        match self {
+            ScriptClass::P2pkh => "p2pkh",
+            ScriptClass::P2sh => "p2sh",
+            ScriptClass::P2wpkh => "p2wpkh",
+            ScriptClass::P2wsh => "p2wsh",
+            ScriptClass::P2tr => "p2tr",
+            ScriptClass::OpReturn => "op_return",
+            ScriptClass::NonStandard => "nonstandard",
+        }
+    }
+
+    /// Whether scripts of the class have an address
+    pub fn has_address(&self) -> bool {
+        !matches!(self, ScriptClass::OpReturn | ScriptClass::NonStandard)
+    }
+}
+
+/// Class of `script`
+pub fn classify_script(script: &Script) -> ScriptClass {
+    if script.is_p2pkh() {
+        ScriptClass::P2pkh
+    } else if script.is_p2sh() {
+        ScriptClass::P2sh
+    } else if script.is_p2wpkh() {
+        ScriptClass::P2wpkh
+    } else if script.is_p2wsh() {
+        ScriptClass::P2wsh
+    } else if script.is_p2tr() {
+        ScriptClass::P2tr
+    } else if script.is_op_return() {
+        ScriptClass::OpReturn
+    } else {
+        ScriptClass::NonStandard
+    }
+}
+
+/// Funding keys of the witness script of a Lightning channel's funding output,
+/// `OP_2 <pubkey1> <pubkey2> OP_2 OP_CHECKMULTISIG`; `None` for any other script
+pub fn funding_pubkeys(witness_script: &Script) -> Option<[PublicKey; 2]> {
+    let instructions: Vec<_> = witness_script
+        .instructions()
+        .collect::<Result<_, _>>()
+        .ok()?;
+    match instructions.as_slice() {
+        [
+            Instruction::Op(m),
+            Instruction::PushBytes(first),
+            Instruction::PushBytes(second),
+            Instruction::Op(n),
+            Instruction::Op(check),
+        ] if *m == OP_PUSHNUM_2 && *n == OP_PUSHNUM_2 && *check == OP_CHECKMULTISIG => {
+            Some([pubkey(first)?, pubkey(second)?])
+        }
+        _ => None,
+    }
+}
+
+/// `to_self_delay` (in blocks) of the witness script of a commitment transaction's
+/// `to_local` output, `OP_IF <revocationpubkey> OP_ELSE <to_self_delay> OP_CSV OP_DROP
+/// <local_delayedpubkey> OP_ENDIF OP_CHECKSIG`; `None` for any other script
+pub fn to_self_delay(witness_script: &Script) -> Option<u16> {
+    let instructions: Vec<_> = witness_script
+        .instructions()
+        .collect::<Result<_, _>>()
+        .ok()?;
+    match instructions.as_slice() {
+        [
+            Instruction::Op(if_),
+            Instruction::PushBytes(revocation),
+            Instruction::Op(else_),
+            delay,
+            Instruction::Op(csv),
+            Instruction::Op(drop),
+            Instruction::PushBytes(delayed),
+            Instruction::Op(endif),
+            Instruction::Op(check),
+        ] if *if_ == OP_IF
+            && *else_ == OP_ELSE
+            && *csv == OP_CSV
+            && *drop == OP_DROP
+            && *endif == OP_ENDIF
+            && *check == OP_CHECKSIG =>
+        {
+            pubkey(revocation)?;
+            pubkey(delayed)?;
+            u16::try_from(delay.script_num()?).ok()
+        }
+        _ => None,
+    }
+}
+
+/// Compressed public key pushed by `bytes`, as Lightning scripts use
+fn pubkey(bytes: &PushBytes) -> Option<PublicKey> {
+    PublicKey::from_slice(bytes.as_bytes())
+        .ok()
+        .filter(|key| key.compressed)
+}
+
+/// Prefixes of the payloads of known data-carrier protocols, and their names
+const PROTOCOL_PREFIXES: &[(&[u8], &str)] = &[
+    (b"omni", "omni"),
+    (b"CNTRPRTY", "counterparty"),
+    (b"charley", "charley"),
+    (b"RETURN", "return"),
+];
+
+/// Data carried by an OP_RETURN output.
+///
+/// # Fields
+/// * `vout` - index of the output in its transaction
+/// * `payload` - bytes pushed after the OP_RETURN, concatenated
+/// * `protocol` - protocol the payload belongs to, when its prefix is a known one
+/// * `preview` - the payload as text, when it is all printable ASCII
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DataCarrier {
+    pub vout: u32,
+    pub payload: Vec<u8>,
+    pub protocol: Option<String>,
+    pub preview: Option<String>,
+}
+
+impl DataCarrier {
+    /// Data carried by output `vout` paying to `script`, `None` unless it is an
+    /// OP_RETURN output.
+    ///
+    /// Decoding is best effort: pushes are read up to the first malformed one, and
+    /// opcodes other than pushes are skipped (Runes mark their payload with
+    /// OP_RETURN OP_13).
+    pub fn from_script(vout: u32, script: &Script) -> Option<Self> {
+        if classify_script(script) != ScriptClass::OpReturn {
+            return None;
+        }
+        let mut instructions = script.instructions().skip(1).peekable();
+        let runes = matches!(
+            instructions.peek(),
+            Some(Ok(Instruction::Op(op))) if *op == OP_PUSHNUM_13
+        );
+        let payload: Vec<u8> = instructions
+            .map_while(|instruction| instruction.ok())
+            .filter_map(|instruction| match instruction {
+                Instruction::PushBytes(bytes) => Some(bytes.as_bytes().to_vec()),
+                Instruction::Op(_) => None,
+            })
+            .flatten()
+            .collect();
+        let protocol = if runes {
+            Some("runes")
+        } else {
+            PROTOCOL_PREFIXES
+                .iter()
+                .find(|(prefix, _)| payload.starts_with(prefix))
+                .map(|(_, name)| *name)
+        };
+        let preview = (!payload.is_empty()
+            && payload.iter().all(|byte| (b' '..=b'~').contains(byte)))
+        .then(|| String::from_utf8_lossy(&payload).into_owned());
+        Some(Self {
+            vout,
+            payload,
+            protocol: protocol.map(str::to_string),
+            preview,
+        })
+    }
+
+    /// Short description of the data: its preview, else its protocol and size
+    pub fn describe(&self) -> String {
+        match (&self.preview, &self.protocol) {
+            (Some(preview), _) => preview.clone(),
+            (None, Some(protocol)) => format!("{protocol} ({} bytes)", self.payload.len()),
+            (None, None) => format!("{} bytes", self.payload.len()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tracer::fixtures::{FUNDING_SCRIPT_HEX, TO_LOCAL_SCRIPT_HEX};
+    use bitcoin::{ScriptBuf, hex::FromHex};
+
+    fn script(hex: &str) -> ScriptBuf {
+        ScriptBuf::from_hex(hex).unwrap()
+    }
+
+    #[test]
+    fn test_classifies_real_scripts() {
+        let cases = [
+            // 1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa, the genesis coinbase's address
+            (
+                "76a91462e907b15cbf27d5425399ebf6f0fb50ebb88f1888ac",
+                ScriptClass::P2pkh,
+            ),
+            (
+                "a914748284390f9e263a4b766a75d0633c50426eb87587",
+                ScriptClass::P2sh,
+            ),
+            // BIP 173 test vectors
+            (
+                "0014751e76e8199196d454941c45d1b3a323f1433bd6",
+                ScriptClass::P2wpkh,
+            ),
+            (
+                "00201863143c14c5166804bd19203356da136c985678cd4d27a1b8c6329604903262",
+                ScriptClass::P2wsh,
+            ),
+            // BIP 341 test vector
+            (
+                "5120a60869f0dbcf1dc659c9cecbaf8050135ea9e8cdc487053f1dc6880949dc684c",
+                ScriptClass::P2tr,
+            ),
+            (
+                "6a13636861726c6579206c6f766573206865696469",
+                ScriptClass::OpReturn,
+            ),
+            ("6a", ScriptClass::OpReturn),
+            // Bare 1-of-1 multisig, and OP_TRUE
+            (
+                "51210279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f8179851ae",
+                ScriptClass::NonStandard,
+            ),
+            ("51", ScriptClass::NonStandard),
+            ("", ScriptClass::NonStandard),
+        ];
+
+        for (hex, class) in cases {
+            assert_eq!(classify_script(&script(hex)), class, "{hex}");
+            assert_eq!(
+                DataCarrier::from_script(0, &script(hex)).is_some(),
+                class == ScriptClass::OpReturn
+            );
+        }
+    }
+
+    #[test]
+    fn test_decodes_data_carriers() {
+        let charley =
+            DataCarrier::from_script(1, &script("6a13636861726c6579206c6f766573206865696469"))
+                .unwrap();
+        assert_eq!(charley.vout, 1);
+        assert_eq!(charley.protocol.as_deref(), Some("charley"));
+        assert_eq!(charley.preview.as_deref(), Some("charley loves heidi"));
+        assert_eq!(charley.describe(), "charley loves heidi");
+
+        // An Omni Layer simple send: binary after its prefix
+        let omni =
+            DataCarrier::from_script(0, &script("6a146f6d6e69000000000000001f0000002b3b1e2c00"))
+                .unwrap();
+        assert_eq!(omni.payload.len(), 20);
+        assert_eq!(omni.protocol.as_deref(), Some("omni"));
+        assert_eq!(omni.preview, None);
+        assert_eq!(omni.describe(), "omni (20 bytes)");
+
+        let runes = DataCarrier::from_script(0, &script("6a5d0814c0a23303c0843d")).unwrap();
+        assert_eq!(runes.protocol.as_deref(), Some("runes"));
+        assert_eq!(
+            runes.payload,
+            Vec::<u8>::from_hex("14c0a23303c0843d").unwrap()
+        );
+
+        // Several pushes are joined, a bare OP_RETURN carries nothing
+        let split = DataCarrier::from_script(0, &script("6a0652455455524e0474657374")).unwrap();
+        assert_eq!(split.protocol.as_deref(), Some("return"));
+        assert_eq!(split.preview.as_deref(), Some("RETURNtest"));
+        let empty = DataCarrier::from_script(0, &script("6a")).unwrap();
+        assert!(empty.payload.is_empty());
+        assert_eq!(empty.preview, None);
+        assert_eq!(empty.describe(), "0 bytes");
+    }
+
+    #[test]
+    fn test_parses_lightning_witness_scripts() {
+        let funding = funding_pubkeys(&script(FUNDING_SCRIPT_HEX)).unwrap();
+        assert_eq!(
+            funding.map(|key| key.to_string()),
+            [
+                "023da092f6980e58d2c037173180e9a465476026ee50f96695963e8efe436f54eb",
+                "030e9f7b623d2ccc7c9bd44d66d5ce21ce504c0acf6385a132cec6d3c39fa711c1",
+            ]
+        );
+        assert_eq!(to_self_delay(&script(TO_LOCAL_SCRIPT_HEX)), Some(144));
+        assert_eq!(to_self_delay(&script(FUNDING_SCRIPT_HEX)), None);
+        assert_eq!(funding_pubkeys(&script(TO_LOCAL_SCRIPT_HEX)), None);
+
+        // A delay small enough for OP_PUSHNUM
+        let short = TO_LOCAL_SCRIPT_HEX.replace("029000", "56");
+        assert_eq!(to_self_delay(&script(&short)), Some(6));
+
+        let cases = [
+            // 2-of-3 and 1-of-1 multisig
+            "52210279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f8179821023da092f6980e58d2c037173180e9a465476026ee50f96695963e8efe436f54eb21030e9f7b623d2ccc7c9bd44d66d5ce21ce504c0acf6385a132cec6d3c39fa711c153ae",
+            "51210279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f8179851ae",
+            // A "2-of-2" of a single key, and a P2WSH output script
+            "5221023da092f6980e58d2c037173180e9a465476026ee50f96695963e8efe436f5452ae",
+            "00201863143c14c5166804bd19203356da136c985678cd4d27a1b8c6329604903262",
+            "",
+        ];
+        for hex in cases {
+            assert_eq!(funding_pubkeys(&script(hex)), None, "{hex}");
+            assert_eq!(to_self_delay(&script(hex)), None, "{hex}");
+        }
+    }
+}
@@ -0,0 +1,463 @@
+//! Streaming of a forward trace: its nodes and edges handed out as they are finalized,
+//! for consumers writing a large trace somewhere else as it goes.
+//!
+//! A node is final once every output of it queued by the trace is resolved, an edge
+//! once it is a leaf or links to its spender. An edge comes after both of its
+//! endpoints. The trace waits for the consumer: at most `STREAM_BUFFER` items are
+//! held for it.
+//!
+//! What is sent is dropped from the trace's graph: the trace keeps the txids it
+//! traced, the scripts of the outputs it dropped, and the nodes and edges not sent
+//! yet, so that its memory follows its frontier rather than its size.
+
+#[cfg(feature = "trace-engine")]
+use crate::tracer::{Result, TraceGraph};
+use crate::tracer::{TraceEdge, TraceNode};
+#[cfg(feature = "trace-engine")]
+use bitcoin::{OutPoint, ScriptBuf, Txid};
+#[cfg(feature = "trace-engine")]
+use futures::stream::{self, Stream, StreamExt};
+#[cfg(feature = "trace-engine")]
+use std::collections::{BTreeSet, HashMap, HashSet};
+#[cfg(feature = "trace-engine")]
+use std::sync::Mutex;
+#[cfg(feature = "trace-engine")]
+use tokio::sync::mpsc;
+
+/// Items held for a slow consumer of a streamed trace before the trace waits
+#[cfg(feature = "trace-engine")]
+pub const STREAM_BUFFER: usize = 64;
+
+/// Part of a streamed trace
+#[derive(Debug, Clone, PartialEq)]
+pub enum TraceItem {
+    /// A transaction, final. Its input value is that of the prevouts known when it was
+    /// sent: a path converging on it later does not complete it, as `trace_forward`
+    /// may.
+    Node(TraceNode),
+    /// An output, sent once both the transaction creating it and its spender are
+    Edge(TraceEdge),
+}
+
+#[cfg(feature = "trace-engine")]
+/// The graph of a streamed trace, as `trace_forward` would have returned it.
+///
+/// # Errors
+/// The first error of the stream, which ends it.
+pub async fn collect(items: impl Stream<Item = Result<TraceItem>>) -> Result<TraceGraph> {
+    let mut items = std::pin::pin!(items);
+    let mut graph = TraceGraph::new();
+    while let Some(item) = items.next().await {
+        match item? {
+            TraceItem::Node(node) => graph.insert_node(node),
+            TraceItem::Edge(edge) => graph.insert_edge(edge),
+        }
+    }
+    Ok(graph)
+}
+
+#[cfg(feature = "trace-engine")]
+/// Stream of the items `trace` sends to its sink, then of its error if it fails, sent
+/// with `sender`. The trace only runs while the stream is polled.
+pub(crate) fn drive<'a>(
+    trace: impl Future<Output = Result<()>> + 'a,
+    sender: mpsc::Sender<Result<TraceItem>>,
+    receiver: mpsc::Receiver<Result<TraceItem>>,
+) -> impl Stream<Item = Result<TraceItem>> + 'a {
+    let trace = async move {
+        if let Err(error) = trace.await {
+            // Behind the items already sent; a consumer gone has no use for it
+            let _ = sender.send(Err(error)).await;
+        }
+    };
+    let received = stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|item| (item, receiver))
+    });
+    stream::select(
+        stream::once(trace).filter_map(|()| std::future::ready(None)),
+        received,
+    )
+}
+
+#[cfg(feature = "trace-engine")]
+/// Trace side of a stream, and a receiver for `drive`
+pub(crate) fn channel() -> (ItemSink, mpsc::Receiver<Result<TraceItem>>) {
+    let (sender, receiver) = mpsc::channel(STREAM_BUFFER);
+    let sink = ItemSink {
+        sender,
+        state: Mutex::default(),
+    };
+    (sink, receiver)
+}
+
+#[cfg(feature = "trace-engine")]
+/// What the trace did since the last flush, and what is still to send
+#[derive(Default)]
+struct SinkState {
+    /// Outputs of each transaction queued and not yet resolved
+    open: HashMap<Txid, usize>,
+    /// Transactions in the graph not sent yet
+    unsent: BTreeSet<Txid>,
+    /// Transactions sent, and dropped from the graph since
+    sent: HashSet<Txid>,
+    /// Scripts of the edges sent, and dropped from the graph since
+    scripts: HashSet<ScriptBuf>,
+    /// Transactions whose outputs may all be resolved
+    candidates: Vec<Txid>,
+    /// Edges resolved since the last flush
+    resolved: Vec<OutPoint>,
+    /// Edges waiting for an endpoint to be sent, by that endpoint
+    waiting: HashMap<Txid, Vec<OutPoint>>,
+    /// Most transactions the graph held at a flush
+    #[cfg(test)]
+    peak: usize,
+}
+
+#[cfg(feature = "trace-engine")]
+impl SinkState {
+    /// Endpoint of the edge at `outpoint` not sent yet, if any
+    fn unsent_endpoint(&self, graph: &TraceGraph, outpoint: &OutPoint) -> Option<Txid> {
+        let spender = graph.edge(outpoint).and_then(|edge| edge.spent_by);
+        [Some(outpoint.txid), spender]
+            .into_iter()
+            .flatten()
+            .find(|txid| !self.sent.contains(txid))
+    }
+
+    /// Sends the edge at `outpoint` to `items` if both its endpoints were, else has it
+    /// wait for the missing one
+    fn release(&mut self, graph: &TraceGraph, outpoint: OutPoint, items: &mut Vec<TraceItem>) {
+        match self.unsent_endpoint(graph, &outpoint) {
+            Some(txid) => self.waiting.entry(txid).or_default().push(outpoint),
+            None => {
+                if let Some(edge) = graph.edge(&outpoint) {
+                    items.push(TraceItem::Edge(edge.clone()));
+                }
+            }
+        }
+    }
+
+    /// Sends the node of `txid` to `items`, and the edges that waited for it
+    fn send_node(&mut self, graph: &TraceGraph, txid: Txid, items: &mut Vec<TraceItem>) {
+        self.unsent.remove(&txid);
+        self.sent.insert(txid);
+        if let Some(node) = graph.node(&txid) {
+            items.push(TraceItem::Node(node.clone()));
+        }
+        for outpoint in self.waiting.remove(&txid).unwrap_or_default() {
+            self.release(graph, outpoint, items);
+        }
+    }
+}
+
+#[cfg(feature = "trace-engine")]
+/// Where a streamed trace sends its items
+pub(crate) struct ItemSink {
+    sender: mpsc::Sender<Result<TraceItem>>,
+    state: Mutex<SinkState>,
+}
+
+#[cfg(feature = "trace-engine")]
+impl ItemSink {
+    /// Another handle on the channel, for the error ending the trace
+    pub(crate) fn sender(&self) -> mpsc::Sender<Result<TraceItem>> {
+        self.sender.clone()
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, SinkState> {
+        self.state.lock().expect("stream state")
+    }
+
+    /// The transaction `txid` was added to the graph
+    pub(crate) fn added(&self, txid: Txid) {
+        let mut state = self.state();
+        state.unsent.insert(txid);
+        state.candidates.push(txid);
+    }
+
+    /// An output of `txid` was queued
+    pub(crate) fn queued(&self, txid: Txid) {
+        *self.state().open.entry(txid).or_default() += 1;
+    }
+
+    /// An output of `txid` was taken off the queue
+    pub(crate) fn dequeued(&self, txid: Txid) {
+        let mut state = self.state();
+        if let Some(open) = state.open.get_mut(&txid) {
+            *open -= 1;
+            if *open == 0 {
+                state.open.remove(&txid);
+                state.candidates.push(txid);
+            }
+        }
+    }
+
+    /// The edge at `outpoint` is final
+    pub(crate) fn resolved(&self, outpoint: OutPoint) {
+        self.state().resolved.push(outpoint);
+    }
+
+    /// Whether the node of `txid` was sent, and dropped from the graph
+    pub(crate) fn sent(&self, txid: &Txid) -> bool {
+        self.state().sent.contains(txid)
+    }
+
+    /// Transactions traced: those in `graph` and those dropped from it
+    pub(crate) fn traced(&self, graph: &TraceGraph) -> usize {
+        graph.len() + self.state().sent.len()
+    }
+
+    /// `f` of the scripts paid by the edges dropped from the graph
+    pub(crate) fn with_scripts<T>(&self, f: impl FnOnce(&HashSet<ScriptBuf>) -> T) -> T {
+        f(&self.state().scripts)
+    }
+
+    /// Most transactions the graph held at once
+    #[cfg(test)]
+    pub(crate) fn peak(&self) -> usize {
+        self.state().peak
+    }
+
+    /// Sends what is final in `graph`, everything with `all` (the trace is over),
+    /// dropping it from `graph`, and waits for the consumer to make room
+    pub(crate) async fn flush(&self, graph: &mut TraceGraph, all: bool) {
+        let items = {
+            let mut state = self.state();
+            #[cfg(test)]
+            {
+                state.peak = state.peak.max(graph.len());
+            }
+            let mut items = Vec::new();
+            let candidates: Vec<Txid> = if all {
+                state.unsent.iter().copied().collect()
+            } else {
+                std::mem::take(&mut state.candidates)
+            };
+            for txid in candidates {
+                if all || (state.unsent.contains(&txid) && !state.open.contains_key(&txid)) {
+                    state.send_node(graph, txid, &mut items);
+                }
+            }
+            for outpoint in std::mem::take(&mut state.resolved) {
+                state.release(graph, outpoint, &mut items);
+            }
+            for item in &items {
+                match item {
+                    TraceItem::Node(node) => {
+                        graph.remove_node(&node.txid);
+                    }
+                    TraceItem::Edge(edge) => {
+                        graph.remove_edge(&edge.outpoint);
+                        state.scripts.insert(edge.script_pubkey.clone());
+                    }
+                }
+            }
+            items
+        };
+        for item in items {
+            // The consumer is gone with the stream, and the trace with it
+            if self.sender.send(Ok(item)).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "trace-engine"))]
+mod tests {
+    use super::*;
+    use crate::tracer::{
+        StopCondition, TraceConfig, Tracer, TracerError,
+        fixtures::{Chain, MockDataSource, coinbase, reused, script, spend, windowed},
+    };
+    use bitcoin::Transaction;
+
+    /// `funding -> split`, whose two outputs `a` and `b` spend, then `merge` spending
+    /// output 0 of both
+    fn diamond() -> Vec<Transaction> {
+        let funding = coinbase(60, &[100_000]);
+        let split = spend(
+            61,
+            &[OutPoint::new(funding.compute_txid(), 0)],
+            &[50_000, 40_000],
+        );
+        let a = spend(62, &[OutPoint::new(split.compute_txid(), 0)], &[49_000]);
+        let b = spend(63, &[OutPoint::new(split.compute_txid(), 1)], &[39_000]);
+        let merge = spend(
+            64,
+            &[
+                OutPoint::new(a.compute_txid(), 0),
+                OutPoint::new(b.compute_txid(), 0),
+            ],
+            &[87_000],
+        );
+        vec![funding, split, a, b, merge]
+    }
+
+    /// Asserts every edge of `items` comes after the nodes of its endpoints
+    fn assert_ordered(items: &[TraceItem]) {
+        let mut sent = HashSet::new();
+        for item in items {
+            match item {
+                TraceItem::Node(node) => {
+                    sent.insert(node.txid);
+                }
+                TraceItem::Edge(edge) => {
+                    assert!(
+                        sent.contains(&edge.from()),
+                        "{} before its node",
+                        edge.outpoint
+                    );
+                    if let Some(spender) = edge.spent_by {
+                        assert!(
+                            sent.contains(&spender),
+                            "{} before its spender",
+                            edge.outpoint
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_collected_stream_is_the_trace_graph() {
+        let chain = Chain::new();
+        let (windowed, windowed_txs) = windowed();
+        let diamond = diamond();
+        let reused = reused();
+        let cases = [
+            (chain.source(), chain.root(), TraceConfig::default()),
+            (
+                chain.source(),
+                chain.root(),
+                TraceConfig::default().max_depth(2),
+            ),
+            (
+                windowed,
+                OutPoint::new(windowed_txs[0].compute_txid(), 0),
+                TraceConfig::default().max_block_height(150),
+            ),
+            (
+                MockDataSource::new(&diamond),
+                OutPoint::new(diamond[0].compute_txid(), 0),
+                TraceConfig::default(),
+            ),
+            (
+                MockDataSource::new(&reused),
+                OutPoint::new(reused[0].compute_txid(), 0),
+                TraceConfig::default().stop(StopCondition::new().target_script(script(13))),
+            ),
+        ];
+
+        for (source, root, config) in cases {
+            let tracer = Tracer::new(source);
+            let expected = tracer
+                .trace_forward(root, &config)
+                .await
+                .unwrap()
+                .into_graph();
+
+            let items: Vec<TraceItem> = tracer
+                .trace_forward_streaming(root, &config)
+                .map(|item| item.unwrap())
+                .collect()
+                .await;
+
+            assert_ordered(&items);
+            let nodes = items
+                .iter()
+                .filter(|item| matches!(item, TraceItem::Node(_)))
+                .count();
+            assert_eq!(nodes, expected.len());
+            let graph = collect(stream::iter(items.into_iter().map(Ok)))
+                .await
+                .unwrap();
+            assert_eq!(graph, expected);
+        }
+    }
+
+    /// `funding -> hop 1 -> ... -> hop hops`, each paying an unspent output on the side
+    fn long_chain(hops: u32) -> Vec<Transaction> {
+        let mut txs = vec![coinbase(70, &[10_000_000])];
+        for hop in 1..=hops {
+            let previous = OutPoint::new(txs.last().unwrap().compute_txid(), 0);
+            txs.push(spend(
+                70 + hop,
+                &[previous],
+                &[10_000_000 - u64::from(hop) * 1_000, 500],
+            ));
+        }
+        txs
+    }
+
+    #[tokio::test]
+    async fn test_slow_consumer_holds_the_trace_back() {
+        let txs = long_chain(300);
+        let tracer = Tracer::new(MockDataSource::new(&txs));
+        let root = OutPoint::new(txs[0].compute_txid(), 0);
+        let config = TraceConfig::default().max_depth(400).max_transactions(400);
+        let mut items = std::pin::pin!(tracer.trace_forward_streaming(root, &config));
+
+        for _ in 0..10 {
+            items.next().await.unwrap().unwrap();
+        }
+        // Three items a hop: the trace stopped once the buffer was full
+        let calls = tracer.source().calls();
+        assert!(calls < 10 + STREAM_BUFFER, "{calls} lookups");
+
+        let mut rest = 10;
+        while let Some(item) = items.next().await {
+            item.unwrap();
+            rest += 1;
+        }
+        // The nodes, the root output and both outputs of each hop
+        assert_eq!(rest, 301 + 1 + 300 * 2);
+    }
+
+    #[tokio::test]
+    async fn test_sent_items_are_dropped_from_the_graph() {
+        let txs = long_chain(500);
+        let tracer = Tracer::new(MockDataSource::new(&txs));
+        let root = OutPoint::new(txs[0].compute_txid(), 0);
+        let config = TraceConfig::default().max_depth(600).max_transactions(600);
+        let (sink, mut receiver) = channel();
+        let mut trace = std::pin::pin!(tracer.stream_forward(root, &config, &sink));
+
+        let mut items = 0;
+        loop {
+            tokio::select! {
+                result = &mut trace => break result.unwrap(),
+                Some(item) = receiver.recv() => {
+                    item.unwrap();
+                    items += 1;
+                }
+            }
+        }
+        while receiver.try_recv().is_ok() {
+            items += 1;
+        }
+        assert_eq!(items, 501 + 1 + 500 * 2);
+        // A hop is dropped once sent, before the next one is traced
+        assert!(sink.peak() <= 2, "{} nodes held", sink.peak());
+    }
+
+    #[tokio::test]
+    async fn test_errors_end_the_stream() {
+        let chain = Chain::new();
+        let tracer = Tracer::new(MockDataSource::new(&[]));
+        let items: Vec<_> = tracer
+            .trace_forward_streaming(chain.root(), &TraceConfig::default())
+            .collect()
+            .await;
+        assert!(matches!(items[..], [Err(TracerError::Source(_))]));
+
+        let config =
+            TraceConfig::default().stop(StopCondition::new().target_script(script(0)).early_exit());
+        let tracer = Tracer::new(chain.source());
+        assert!(matches!(
+            collect(tracer.trace_forward_streaming(chain.root(), &config)).await,
+            Err(TracerError::InvalidConfig(_))
+        ));
+    }
+}
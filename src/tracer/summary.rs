@@ -0,0 +1,334 @@
+//! One-screen summary of a traced graph, to read before the graph itself.
+
+use crate::tracer::{LightningChannel, TraceEdge, TraceGraph, TxPattern, dot::utc};
+use bitcoin::{Address, Amount, Denomination, OutPoint};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+
+/// Largest outputs at the frontier listed in a summary
+pub const TOP_OUTPUTS: usize = 10;
+
+/// An output the trace stopped at.
+///
+/// # Fields
+/// * `outpoint` - the output
+/// * `value` - amount it carries
+/// * `address` - address it pays, if it has a standard form
+/// * `reason` - why the trace stopped there (see `TerminalReason::code`)
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FrontierOutput {
+    pub outpoint: OutPoint,
+    pub value: Amount,
+    pub address: Option<Address>,
+    pub reason: &'static str,
+}
+
+/// Aggregates of a traced graph.
+///
+/// Amounts are sums of `Amount`s; a sum past the largest amount stops there rather than
+/// wrapping around.
+///
+/// # Fields
+/// * `transactions` - transactions in the graph
+/// * `edges` - outputs in the graph, spent or not
+/// * `depths` - transactions at each depth
+/// * `frontier_value` - value of the outputs the trace stopped at, by reason code
+/// * `frontier_outputs` - number of those outputs, by reason code
+/// * `top_outputs` - the `TOP_OUTPUTS` largest of those outputs, largest first
+/// * `first_seen` - earliest block time in the graph, when any is known
+/// * `last_seen` - latest block time in the graph
+/// * `fees` - fees paid by the transactions whose fee is known
+/// * `unknown_fees` - transactions whose fee is not known
+/// * `addresses` - distinct addresses paid by the outputs in the graph
+/// * `labelled` - value of the outputs paying known entities, by entity
+/// * `sweeps` - transactions classified as sweeps
+/// * `batch_payouts` - transactions classified as batch payouts
+/// * `lightning_opens` - transactions recognized as funding Lightning channels
+/// * `lightning_closes` - transactions recognized as closing Lightning channels
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct TraceSummary {
+    pub transactions: usize,
+    pub edges: usize,
+    pub depths: BTreeMap<usize, usize>,
+    pub frontier_value: BTreeMap<&'static str, Amount>,
+    pub frontier_outputs: BTreeMap<&'static str, usize>,
+    pub top_outputs: Vec<FrontierOutput>,
+    pub first_seen: Option<u64>,
+    pub last_seen: Option<u64>,
+    pub fees: Amount,
+    pub unknown_fees: usize,
+    pub addresses: usize,
+    pub labelled: BTreeMap<String, Amount>,
+    pub sweeps: usize,
+    pub batch_payouts: usize,
+    pub lightning_opens: usize,
+    pub lightning_closes: usize,
+}
+
+impl TraceSummary {
+    pub fn from_graph(graph: &TraceGraph) -> Self {
+        let mut summary = TraceSummary {
+            transactions: graph.len(),
+            edges: graph.edges().count(),
+            ..TraceSummary::default()
+        };
+        for node in graph.nodes() {
+            *summary.depths.entry(node.depth).or_default() += 1;
+            if let Some(timestamp) = node.timestamp {
+                summary.first_seen =
+                    Some(summary.first_seen.map_or(timestamp, |t| t.min(timestamp)));
+                summary.last_seen = Some(summary.last_seen.map_or(timestamp, |t| t.max(timestamp)));
+            }
+            match node.fee {
+                Some(fee) => summary.fees = add(summary.fees, fee),
+                None => summary.unknown_fees += 1,
+            }
+            match node.pattern {
+                TxPattern::Sweep { .. } => summary.sweeps += 1,
+                TxPattern::BatchPayout { .. } => summary.batch_payouts += 1,
+                _ => {}
+            }
+            match node.lightning {
+                Some(LightningChannel::Open { .. }) => summary.lightning_opens += 1,
+                Some(LightningChannel::Close { .. }) => summary.lightning_closes += 1,
+                None => {}
+            }
+        }
+
+        let mut leaves: Vec<(&TraceEdge, &'static str)> = graph
+            .leaves()
+            .into_iter()
+            .map(|(edge, reason)| (edge, reason.code()))
+            .collect();
+        for (edge, reason) in &leaves {
+            let value = summary.frontier_value.entry(reason).or_default();
+            *value = add(*value, edge.value);
+            *summary.frontier_outputs.entry(reason).or_default() += 1;
+        }
+        // Edges come in outpoint order, which breaks ties
+        leaves.sort_by_key(|(edge, _)| std::cmp::Reverse(edge.value));
+        summary.top_outputs = leaves
+            .into_iter()
+            .take(TOP_OUTPUTS)
+            .map(|(edge, reason)| FrontierOutput {
+                outpoint: edge.outpoint,
+                value: edge.value,
+                address: edge.address.clone(),
+                reason,
+            })
+            .collect();
+
+        summary.addresses = graph
+            .edges()
+            .filter_map(|edge| edge.address.as_ref())
+            .collect::<HashSet<_>>()
+            .len();
+        for edge in graph.edges() {
+            if let Some(label) = graph.label_of(&edge.outpoint) {
+                let value = summary.labelled.entry(label.entity.clone()).or_default();
+                *value = add(*value, edge.value);
+            }
+        }
+        summary
+    }
+}
+
+/// `a + b`, or the largest amount if that overflows
+fn add(a: Amount, b: Amount) -> Amount {
+    a.checked_add(b).unwrap_or(Amount::MAX)
+}
+
+/// `amount` in BTC, with the unit
+pub(crate) fn btc(amount: Amount) -> String {
+    amount
+        .display_in(Denomination::Bitcoin)
+        .show_denomination()
+        .to_string()
+}
+
+impl fmt::Display for TraceSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Transactions: {}, outputs: {}, addresses: {}",
+            self.transactions, self.edges, self.addresses
+        )?;
+        let depths: Vec<_> = self
+            .depths
+            .iter()
+            .map(|(depth, count)| format!("{}: {}", depth, count))
+            .collect();
+        writeln!(f, "Transactions by depth: {}", join_or_none(&depths))?;
+        match (self.first_seen, self.last_seen) {
+            (Some(first), Some(last)) => {
+                writeln!(f, "Block times: {} to {}", utc(first), utc(last))?
+            }
+            _ => writeln!(f, "Block times: unknown")?,
+        }
+        write!(f, "Fees paid: {}", btc(self.fees))?;
+        match self.unknown_fees {
+            0 => writeln!(f)?,
+            unknown => writeln!(f, " (not counting {} without a known fee)", unknown)?,
+        }
+        if self.batch_payouts > 0 {
+            writeln!(
+                f,
+                "Trace passed through {} batch payout{}, likely exchange withdrawals",
+                self.batch_payouts,
+                plural(self.batch_payouts)
+            )?;
+        }
+        if self.sweeps > 0 {
+            writeln!(
+                f,
+                "Trace passed through {} sweep{}, likely consolidations by one entity",
+                self.sweeps,
+                plural(self.sweeps)
+            )?;
+        }
+        if self.lightning_opens + self.lightning_closes > 0 {
+            writeln!(
+                f,
+                "Lightning channels: {} opened, {} closed",
+                self.lightning_opens, self.lightning_closes
+            )?;
+        }
+
+        let width = self.frontier_value.keys().map(|reason| reason.len()).max();
+        writeln!(
+            f,
+            "Value at the frontier:{}",
+            if width.is_none() { " none" } else { "" }
+        )?;
+        let value_width = self
+            .frontier_value
+            .values()
+            .map(|value| btc(*value).len())
+            .max()
+            .unwrap_or(0);
+        for (reason, value) in &self.frontier_value {
+            let outputs = self.frontier_outputs.get(reason).copied().unwrap_or(0);
+            writeln!(
+                f,
+                "  {:width$}  {:value_width$}  {} output{}",
+                reason,
+                btc(*value),
+                outputs,
+                plural(outputs),
+                width = width.unwrap_or(0),
+                value_width = value_width
+            )?;
+        }
+        if !self.top_outputs.is_empty() {
+            writeln!(f, "Largest outputs at the frontier:")?;
+        }
+        let width = self
+            .top_outputs
+            .iter()
+            .map(|output| btc(output.value).len())
+            .max()
+            .unwrap_or(0);
+        for output in &self.top_outputs {
+            let address = output
+                .address
+                .as_ref()
+                .map_or("-".to_string(), Address::to_string);
+            writeln!(
+                f,
+                "  {:>width$}  {}  {}  {}",
+                btc(output.value),
+                output.outpoint,
+                address,
+                output.reason,
+                width = width
+            )?;
+        }
+        let mut labelled: Vec<_> = self.labelled.iter().collect();
+        labelled.sort_by_key(|(_, value)| std::cmp::Reverse(**value));
+        for (entity, value) in labelled {
+            writeln!(f, "{} reached {}-labelled addresses", btc(*value), entity)?;
+        }
+        Ok(())
+    }
+}
+
+fn plural(count: usize) -> &'static str {
+    if count == 1 { "" } else { "s" }
+}
+
+fn join_or_none(items: &[String]) -> String {
+    if items.is_empty() {
+        "none".to_string()
+    } else {
+        items.join(", ")
+    }
+}
+
+#[cfg(all(test, feature = "trace-engine"))]
+mod tests {
+    use super::*;
+    use crate::tracer::{EntityCategory, Label, fixtures::sample_graph};
+
+    const GOLDEN: &str = "src/tracer/testdata/summary.txt";
+
+    #[tokio::test]
+    async fn test_display_matches_golden_file() {
+        let mut graph = sample_graph().await;
+        let change = graph
+            .edges()
+            .find(|edge| edge.value == Amount::from_sat(39_000))
+            .unwrap()
+            .clone();
+        let kraken = Label {
+            entity: "Kraken".to_string(),
+            category: EntityCategory::Exchange,
+            source: None,
+            confidence: 1.0,
+        };
+        graph.insert_edge(change.clone().terminal(kraken.terminal_reason()));
+        let node = graph.node_mut(&change.from()).unwrap();
+        node.labels.insert(change.outpoint.vout, kraken);
+        node.pattern = TxPattern::BatchPayout { outputs: 3 };
+        node.lightning = Some(LightningChannel::Close {
+            forced: false,
+            funding_pubkeys: Vec::new(),
+        });
+        let summary = TraceSummary::from_graph(&graph);
+
+        assert_eq!(summary.transactions, 3);
+        assert_eq!(summary.frontier_value["unspent"], Amount::from_sat(59_300));
+        assert_eq!(summary.frontier_value["exchange"], Amount::from_sat(39_000));
+        assert_eq!(summary.top_outputs[0].value, Amount::from_sat(59_000));
+        assert_eq!(summary.fees, Amount::from_sat(1_700));
+        assert_eq!(summary.unknown_fees, 1);
+        assert_eq!(summary.first_seen, Some(1_700_000_000));
+        assert_eq!(summary.labelled["Kraken"], Amount::from_sat(39_000));
+        assert_eq!((summary.batch_payouts, summary.sweeps), (1, 0));
+        assert_eq!((summary.lightning_opens, summary.lightning_closes), (0, 1));
+        let text = summary.to_string();
+
+        // UPDATE_GOLDEN=1 cargo test rewrites the file after an intended change
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            std::fs::write(GOLDEN, &text).unwrap();
+        }
+        assert_eq!(text, include_str!("testdata/summary.txt"));
+        let json = serde_json::to_value(&summary).unwrap();
+        assert_eq!(json["frontier_value"]["exchange"], 39_000);
+    }
+
+    #[test]
+    fn test_empty_graph_and_overflow() {
+        let summary = TraceSummary::from_graph(&TraceGraph::new());
+        assert_eq!(summary, TraceSummary::default());
+        assert_eq!(
+            summary.to_string(),
+            "Transactions: 0, outputs: 0, addresses: 0\n\
+             Transactions by depth: none\n\
+             Block times: unknown\n\
+             Fees paid: 0 BTC\n\
+             Value at the frontier: none\n"
+        );
+        assert_eq!(add(Amount::MAX, Amount::ONE_SAT), Amount::MAX);
+    }
+}
@@ -0,0 +1,242 @@
+//! Plain text rendering of traced graphs for terminals.
+//!
+//! The `Display` impls of `TraceNode`, `TraceEdge` and `TracePath` shorten txids to
+//! tell transactions apart at a glance; the alternate flag (`{:#}`) prints them in
+//! full. Amounts are given in BTC, then in sats. `TablePrinter` lines outputs up in
+//! aligned columns, their outpoints in full to be copied into further commands.
+
+use crate::tracer::{FrontierOutput, TraceEdge, classify_script, dot::short_txid, summary::btc};
+use bitcoin::{Amount, OutPoint, Txid};
+use std::fmt;
+
+/// `amount` in BTC, then in sats: `0.0006 BTC (60000 sat)`
+pub(crate) fn amount(amount: Amount) -> String {
+    format!("{} ({} sat)", btc(amount), amount.to_sat())
+}
+
+/// `txid` in full, or shortened unless `full`
+pub(crate) fn txid(txid: &Txid, full: bool) -> String {
+    if full {
+        txid.to_string()
+    } else {
+        short_txid(txid)
+    }
+}
+
+/// `outpoint` as `<txid>:<vout>`, its txid shortened unless `full`
+pub(crate) fn outpoint(outpoint: &OutPoint, full: bool) -> String {
+    format!("{}:{}", txid(&outpoint.txid, full), outpoint.vout)
+}
+
+/// Rows of text in aligned columns, under a header and a rule.
+///
+/// Columns are separated by two spaces and left-aligned unless set with
+/// `right_aligned`. A row with fewer cells than there are columns leaves the rest
+/// blank; extra cells are dropped.
+///
+/// ```
+/// use pathfinder::tracer::table::TablePrinter;
+///
+/// let mut table = TablePrinter::new(&["Entity", "Outputs"]).right_aligned(1);
+/// table.row(["Kraken", "12"]);
+/// table.row(["Wasabi", "3"]);
+/// assert_eq!(
+///     table.to_string(),
+///     "Entity  Outputs\n------  -------\nKraken       12\nWasabi        3\n"
+/// );
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TablePrinter {
+    headers: Vec<String>,
+    right: Vec<bool>,
+    rows: Vec<Vec<String>>,
+}
+
+impl TablePrinter {
+    pub fn new(headers: &[&str]) -> Self {
+        Self {
+            headers: headers.iter().map(|header| header.to_string()).collect(),
+            right: vec![false; headers.len()],
+            rows: Vec::new(),
+        }
+    }
+
+    /// Aligns `column` to the right, as numbers read best
+    pub fn right_aligned(mut self, column: usize) -> Self {
+        if let Some(right) = self.right.get_mut(column) {
+            *right = true;
+        }
+        self
+    }
+
+    /// Appends a row
+    pub fn row<I>(&mut self, cells: I)
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        let mut row: Vec<String> = cells
+            .into_iter()
+            .take(self.headers.len())
+            .map(Into::into)
+            .collect();
+        row.resize(self.headers.len(), String::new());
+        self.rows.push(row);
+    }
+
+    /// Number of rows, the header left out
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// Table of `edges`: each output, its value, the address it pays and where it went
+    pub fn outputs<'a>(edges: impl IntoIterator<Item = &'a TraceEdge>) -> Self {
+        let mut table = TablePrinter::new(&["Output", "Value", "Sats", "Address", "Status"])
+            .right_aligned(1)
+            .right_aligned(2);
+        for edge in edges {
+            let status = match (&edge.terminal, edge.spent_by) {
+                (Some(reason), _) => reason.code().to_string(),
+                (None, Some(spender)) => format!("spent by {}", spender),
+                (None, None) => String::new(),
+            };
+            table.row([
+                outpoint(&edge.outpoint, true),
+                btc(edge.value),
+                edge.value.to_sat().to_string(),
+                address(edge),
+                status,
+            ]);
+        }
+        table
+    }
+
+    /// Table of outputs at the frontier of a trace, as `TraceSummary::top_outputs`
+    /// lists them
+    pub fn frontier(outputs: &[FrontierOutput]) -> Self {
+        let mut table = TablePrinter::new(&["Value", "Sats", "Output", "Address", "Reason"])
+            .right_aligned(0)
+            .right_aligned(1);
+        for output in outputs {
+            table.row([
+                btc(output.value),
+                output.value.to_sat().to_string(),
+                outpoint(&output.outpoint, true),
+                output
+                    .address
+                    .as_ref()
+                    .map_or("-".to_string(), ToString::to_string),
+                output.reason.to_string(),
+            ]);
+        }
+        table
+    }
+}
+
+/// Address `edge` pays, else the class of its script
+fn address(edge: &TraceEdge) -> String {
+    match &edge.address {
+        Some(address) => address.to_string(),
+        None => format!("({})", classify_script(&edge.script_pubkey).code()),
+    }
+}
+
+impl fmt::Display for TablePrinter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let widths: Vec<usize> = (0..self.headers.len())
+            .map(|column| {
+                self.rows
+                    .iter()
+                    .map(|row| row[column].chars().count())
+                    .chain([self.headers[column].chars().count()])
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect();
+        let rule: Vec<String> = widths.iter().map(|width| "-".repeat(*width)).collect();
+        for row in [&self.headers, &rule].into_iter().chain(&self.rows) {
+            let cells: Vec<String> = row
+                .iter()
+                .zip(&widths)
+                .zip(&self.right)
+                .map(|((cell, width), right)| {
+                    if *right {
+                        format!("{:>width$}", cell, width = width)
+                    } else {
+                        format!("{:width$}", cell, width = width)
+                    }
+                })
+                .collect();
+            writeln!(f, "{}", cells.join("  ").trim_end())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "trace-engine"))]
+mod tests {
+    use super::*;
+    use crate::tracer::{PathOptions, TraceSummary, fixtures::sample_graph};
+    use std::fmt::Write;
+
+    const GOLDEN: &str = "src/tracer/testdata/display.txt";
+
+    #[tokio::test]
+    async fn test_display_matches_golden_file() {
+        let mut graph = sample_graph().await;
+        let mut nodes: Vec<_> = graph.nodes().cloned().collect();
+        nodes.sort_by_key(|node| node.depth);
+        let split = graph.node_mut(&nodes[1].txid).unwrap();
+        split.height = Some(812_345);
+        split.replaceable = true;
+        let nodes: Vec<_> = nodes
+            .iter()
+            .map(|node| graph.node(&node.txid).unwrap().clone())
+            .collect();
+
+        let mut text = String::new();
+        writeln!(text, "Nodes:").unwrap();
+        for node in &nodes {
+            writeln!(text, "{}", node).unwrap();
+            writeln!(text, "{:#}", node).unwrap();
+        }
+        writeln!(text, "Edges:").unwrap();
+        for edge in graph.edges() {
+            writeln!(text, "{}", edge).unwrap();
+        }
+        writeln!(text, "{:#}", graph.edges().next().unwrap()).unwrap();
+        let path = &graph.find_paths(&nodes[0].txid, &nodes[2].txid, &PathOptions::default())[0];
+        writeln!(text, "Path:\n{}{:#}", path, path).unwrap();
+        writeln!(text, "Outputs:\n{}", TablePrinter::outputs(graph.edges())).unwrap();
+        let summary = TraceSummary::from_graph(&graph);
+        write!(
+            text,
+            "Frontier:\n{}",
+            TablePrinter::frontier(&summary.top_outputs)
+        )
+        .unwrap();
+
+        // UPDATE_GOLDEN=1 cargo test rewrites the file after an intended change
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            std::fs::write(GOLDEN, &text).unwrap();
+        }
+        assert_eq!(text, include_str!("testdata/display.txt"));
+    }
+
+    #[test]
+    fn test_rows_are_padded_to_the_header() {
+        let mut table = TablePrinter::new(&["A", "B", "C"]).right_aligned(7);
+        table.row(["1"]);
+        table.row(["22", "333", "4444", "dropped"]);
+        assert_eq!(table.len(), 2);
+        assert_eq!(
+            table.to_string(),
+            "A   B    C\n--  ---  ----\n1\n22  333  4444\n"
+        );
+        assert!(TablePrinter::new(&["A"]).is_empty());
+    }
+}
@@ -0,0 +1,424 @@
+//! Taint propagation through a traced graph.
+//!
+//! Taint measures how much of an output derives from a source outpoint. It starts as
+//! the whole value of the source and flows forward through every transaction spending
+//! tainted outputs, split between its outputs (and fee) according to a `TaintModel`.
+//!
+//! All amounts are integer sats. A transaction's tainted input is split exactly: what
+//! its outputs receive plus what goes to the fee always sums to what it spent.
+
+use crate::tracer::{TraceEdge, TraceGraph};
+use bitcoin::{Amount, OutPoint, Txid};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+/// Output index under which `FeeTaint::Track` records the taint paid as fee by a
+/// transaction. No transaction has that many outputs.
+pub const FEE_VOUT: u32 = u32::MAX;
+
+/// What happens to the part of the taint a transaction pays as fee
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FeeTaint {
+    /// It leaves the graph with the fee
+    #[default]
+    Burn,
+    /// It is reported under the outpoint `(txid, FEE_VOUT)` of the transaction
+    Track,
+}
+
+/// How a transaction passes the taint of its inputs on to its outputs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaintModel {
+    /// Each output, and the fee, takes a share of the tainted input proportional to its
+    /// value
+    Haircut { fees: FeeTaint },
+    /// Every output of a transaction spending any taint is fully tainted
+    Poison { fees: FeeTaint },
+}
+
+impl TaintModel {
+    fn fees(&self) -> FeeTaint {
+        match self {
+            TaintModel::Haircut { fees } | TaintModel::Poison { fees } => *fees,
+        }
+    }
+}
+
+impl Default for TaintModel {
+    fn default() -> Self {
+        TaintModel::Haircut {
+            fees: FeeTaint::default(),
+        }
+    }
+}
+
+/// Taint carried by an output
+///
+/// # Fields
+/// * `value` - value of the output
+/// * `tainted` - part of `value` deriving from the source, never more than `value`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TaintShare {
+    pub value: Amount,
+    pub tainted: Amount,
+}
+
+impl TaintShare {
+    /// Tainted fraction of the output, from 0 to 1 (0 for an empty output)
+    pub fn ratio(&self) -> f64 {
+        if self.value == Amount::ZERO {
+            return 0.0;
+        }
+        self.tainted.to_sat() as f64 / self.value.to_sat() as f64
+    }
+
+    pub fn is_clean(&self) -> bool {
+        self.tainted == Amount::ZERO
+    }
+}
+
+impl TraceGraph {
+    /// Taint of every output downstream of `source`, the source included.
+    ///
+    /// Transactions are visited in dependency order, so one spending several tainted
+    /// outputs (two paths from the source converging) is visited once, after all of
+    /// them, and splits their summed taint. Inputs the graph does not link to the
+    /// source are clean.
+    ///
+    /// A transaction's input value is its `input_value` when known, otherwise the sum of
+    /// its traced inputs (never less than its outputs): without a known input value the
+    /// fee cannot be told apart from clean inputs and is taken as zero.
+    ///
+    /// Under `Haircut`, the outputs receive `floor(tainted * outputs / inputs)` and the
+    /// fee the rest. That amount is split between the outputs by largest remainder:
+    /// each output gets the floor of its proportional share, and the sats left over go
+    /// one each to the outputs with the largest remainders, lowest index first on ties.
+    ///
+    /// Empty if `source` is not in the graph.
+    pub fn compute_taint(
+        &self,
+        source: OutPoint,
+        model: TaintModel,
+    ) -> HashMap<OutPoint, TaintShare> {
+        let mut shares = HashMap::new();
+        let Some(edge) = self.edge(&source) else {
+            return shares;
+        };
+        shares.insert(
+            source,
+            TaintShare {
+                value: edge.value,
+                tainted: edge.value,
+            },
+        );
+
+        // Tainted inputs still to be resolved, per reachable transaction
+        let mut waiting = self.tainted_inputs(edge);
+        let mut ready = BTreeSet::new();
+        if let Some(txid) = edge.spent_by {
+            decrement(&mut waiting, &txid, &mut ready);
+        }
+
+        while let Some(txid) = ready.pop_first() {
+            let inputs: Vec<&TraceEdge> = self.inputs_of(&txid).collect();
+            let outputs: Vec<&TraceEdge> = self.outputs_of(&txid).collect();
+            let tainted: u64 = inputs
+                .iter()
+                .filter_map(|input| shares.get(&input.outpoint))
+                .map(|share| share.tainted.to_sat())
+                .sum();
+            let traced: u64 = inputs.iter().map(|input| input.value.to_sat()).sum();
+            let out_value: u64 = outputs.iter().map(|output| output.value.to_sat()).sum();
+            let in_value = self
+                .node(&txid)
+                .and_then(|node| node.input_value)
+                .map_or(traced, Amount::to_sat)
+                .max(out_value);
+
+            let (received, fee) = match model {
+                TaintModel::Haircut { .. } => {
+                    let received = proportion(tainted, out_value, in_value);
+                    let split = largest_remainder(
+                        received,
+                        &outputs
+                            .iter()
+                            .map(|output| output.value.to_sat())
+                            .collect::<Vec<_>>(),
+                    );
+                    (split, tainted - received)
+                }
+                TaintModel::Poison { .. } => {
+                    let received = outputs.iter().map(|output| output.value.to_sat());
+                    let fee = if tainted > 0 { in_value - out_value } else { 0 };
+                    (received.collect(), fee)
+                }
+            };
+            for (output, tainted) in outputs.iter().zip(received) {
+                shares.insert(
+                    output.outpoint,
+                    TaintShare {
+                        value: output.value,
+                        tainted: Amount::from_sat(tainted),
+                    },
+                );
+                if let Some(spender) = output.spent_by {
+                    decrement(&mut waiting, &spender, &mut ready);
+                }
+            }
+            if model.fees() == FeeTaint::Track && in_value > out_value {
+                shares.insert(
+                    OutPoint::new(txid, FEE_VOUT),
+                    TaintShare {
+                        value: Amount::from_sat(in_value - out_value),
+                        tainted: Amount::from_sat(fee),
+                    },
+                );
+            }
+        }
+        shares
+    }
+
+    /// Number of reachable inputs of each transaction reachable from `source`, the
+    /// edges taint can arrive through
+    fn tainted_inputs(&self, source: &TraceEdge) -> BTreeMap<Txid, usize> {
+        let mut waiting = BTreeMap::new();
+        let mut seen = BTreeSet::new();
+        let mut stack = vec![source];
+        while let Some(edge) = stack.pop() {
+            let Some(spender) = edge.spent_by else {
+                continue;
+            };
+            *waiting.entry(spender).or_default() += 1;
+            if seen.insert(spender) {
+                stack.extend(self.outputs_of(&spender));
+            }
+        }
+        waiting
+    }
+}
+
+/// Counts one more input of `txid` as resolved, queueing it once all of them are
+fn decrement(waiting: &mut BTreeMap<Txid, usize>, txid: &Txid, ready: &mut BTreeSet<Txid>) {
+    if let Some(count) = waiting.get_mut(txid) {
+        *count -= 1;
+        if *count == 0 {
+            ready.insert(*txid);
+        }
+    }
+}
+
+/// `floor(amount * numerator / denominator)`, without overflow (0 for a zero
+/// denominator)
+fn proportion(amount: u64, numerator: u64, denominator: u64) -> u64 {
+    if denominator == 0 {
+        return 0;
+    }
+    (amount as u128 * numerator as u128 / denominator as u128) as u64
+}
+
+/// Splits `amount` in proportion to `weights`, exactly, by largest remainder
+fn largest_remainder(amount: u64, weights: &[u64]) -> Vec<u64> {
+    let total: u64 = weights.iter().sum();
+    if total == 0 {
+        return vec![0; weights.len()];
+    }
+    let mut split: Vec<u64> = weights
+        .iter()
+        .map(|&weight| proportion(amount, weight, total))
+        .collect();
+    let left = amount - split.iter().sum::<u64>();
+    let mut by_remainder: Vec<usize> = (0..weights.len()).collect();
+    // Stable sort: lowest index first among equal remainders
+    by_remainder
+        .sort_by_key(|&i| std::cmp::Reverse(amount as u128 * weights[i] as u128 % total as u128));
+    for &i in by_remainder.iter().take(left as usize) {
+        split[i] += 1;
+    }
+    split
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tracer::{TraceNode, fixtures::spend};
+    use bitcoin::{Network, Transaction};
+
+    /// Graph of `txs`, linking every output to its spender among them. Input values
+    /// are known when every prevout is one of `txs`.
+    fn graph_of(txs: &[Transaction]) -> TraceGraph {
+        let by_txid: HashMap<Txid, &Transaction> =
+            txs.iter().map(|tx| (tx.compute_txid(), tx)).collect();
+        let mut graph = TraceGraph::new();
+        for (depth, tx) in txs.iter().enumerate() {
+            let input_value = tx
+                .input
+                .iter()
+                .map(|input| {
+                    let prevout = input.previous_output;
+                    by_txid
+                        .get(&prevout.txid)
+                        .map(|parent| parent.output[prevout.vout as usize].value)
+                })
+                .sum::<Option<Amount>>()
+                .filter(|_| !tx.input.is_empty());
+            graph.insert_node(TraceNode {
+                input_value,
+                ..TraceNode::new(tx, depth)
+            });
+            for (vout, output) in tx.output.iter().enumerate() {
+                let outpoint = OutPoint::new(tx.compute_txid(), vout as u32);
+                let spent_by = txs
+                    .iter()
+                    .find(|spender| {
+                        spender
+                            .input
+                            .iter()
+                            .any(|input| input.previous_output == outpoint)
+                    })
+                    .map(Transaction::compute_txid);
+                graph.insert_edge(TraceEdge {
+                    spent_by,
+                    ..TraceEdge::new(outpoint, output, Network::Bitcoin)
+                });
+            }
+        }
+        graph
+    }
+
+    fn out(tx: &Transaction, vout: u32) -> OutPoint {
+        OutPoint::new(tx.compute_txid(), vout)
+    }
+
+    fn tainted(shares: &HashMap<OutPoint, TaintShare>, outpoint: OutPoint) -> u64 {
+        shares[&outpoint].tainted.to_sat()
+    }
+
+    const HAIRCUT: TaintModel = TaintModel::Haircut {
+        fees: FeeTaint::Burn,
+    };
+
+    #[test]
+    fn test_haircut_mixes_with_clean_inputs() {
+        let dirty = spend(0, &[], &[30_000]);
+        let clean = spend(1, &[], &[70_000]);
+        // 100_000 in, 99_000 out: 30% of every output is tainted
+        let mix = spend(2, &[out(&dirty, 0), out(&clean, 0)], &[60_000, 39_000]);
+        let graph = graph_of(&[dirty.clone(), clean.clone(), mix.clone()]);
+
+        let shares = graph.compute_taint(out(&dirty, 0), HAIRCUT);
+
+        assert_eq!(tainted(&shares, out(&dirty, 0)), 30_000);
+        assert!(!shares.contains_key(&out(&clean, 0)));
+        // 30_000 * 99_000 / 100_000 = 29_700 to the outputs, 300 burnt with the fee
+        assert_eq!(tainted(&shares, out(&mix, 0)), 18_000);
+        assert_eq!(tainted(&shares, out(&mix, 1)), 11_700);
+        assert!((shares[&out(&mix, 0)].ratio() - 0.3).abs() < 1e-9);
+        assert!(!shares.contains_key(&OutPoint::new(mix.compute_txid(), FEE_VOUT)));
+    }
+
+    #[test]
+    fn test_haircut_tracks_fees_and_rounds_exactly() {
+        let source = spend(0, &[], &[10_000]);
+        // 10_000 in, 9_001 out, three equal outputs: 9_001 / 3 does not divide
+        let split = spend(1, &[out(&source, 0)], &[3_001, 3_000, 3_000]);
+        let graph = graph_of(&[source.clone(), split.clone()]);
+
+        let shares = graph.compute_taint(
+            out(&source, 0),
+            TaintModel::Haircut {
+                fees: FeeTaint::Track,
+            },
+        );
+
+        let outputs: Vec<u64> = (0..3)
+            .map(|vout| tainted(&shares, out(&split, vout)))
+            .collect();
+        // Fully tainted input: each output is fully tainted, whatever the rounding
+        assert_eq!(outputs, vec![3_001, 3_000, 3_000]);
+        let fee = shares[&OutPoint::new(split.compute_txid(), FEE_VOUT)];
+        assert_eq!(fee.value.to_sat(), 999);
+        assert_eq!(fee.tainted.to_sat(), 999);
+        assert_eq!(outputs.iter().sum::<u64>() + fee.tainted.to_sat(), 10_000);
+    }
+
+    #[test]
+    fn test_largest_remainder_sums_exactly() {
+        // 100 * 1/3 each: 33.33.. three times, the extra sat goes to the first output
+        assert_eq!(largest_remainder(100, &[1, 1, 1]), vec![34, 33, 33]);
+        // Remainders 0.4, 0.6 (x7/10 and x3/10 of 2 sats): the second output wins
+        assert_eq!(largest_remainder(2, &[7, 3]), vec![1, 1]);
+        assert_eq!(largest_remainder(10, &[0, 0]), vec![0, 0]);
+        assert_eq!(largest_remainder(7, &[5, 0, 2]), vec![5, 0, 2]);
+    }
+
+    #[test]
+    fn test_converging_paths_are_counted_once() {
+        // source splits in two, both halves pass through one hop each, then merge
+        let source = spend(0, &[], &[100_000]);
+        let split = spend(1, &[out(&source, 0)], &[50_000, 50_000]);
+        let left = spend(2, &[out(&split, 0)], &[50_000]);
+        let right = spend(3, &[out(&split, 1)], &[25_000, 25_000]);
+        let clean = spend(4, &[], &[100_000]);
+        let merge = spend(
+            5,
+            &[out(&left, 0), out(&right, 0), out(&clean, 0)],
+            &[175_000],
+        );
+        let graph = graph_of(&[
+            source.clone(),
+            split,
+            left,
+            right.clone(),
+            clean,
+            merge.clone(),
+        ]);
+
+        let shares = graph.compute_taint(out(&source, 0), HAIRCUT);
+
+        // 75_000 tainted of 175_000 in: all of it reaches the single output
+        assert_eq!(tainted(&shares, out(&merge, 0)), 75_000);
+        assert_eq!(tainted(&shares, out(&right, 1)), 25_000);
+
+        let poisoned = graph.compute_taint(
+            out(&source, 0),
+            TaintModel::Poison {
+                fees: FeeTaint::Burn,
+            },
+        );
+        assert_eq!(tainted(&poisoned, out(&merge, 0)), 175_000);
+    }
+
+    #[test]
+    fn test_poison_taints_every_output() {
+        let dirty = spend(0, &[], &[1_000]);
+        let clean = spend(1, &[], &[99_000]);
+        let mix = spend(2, &[out(&dirty, 0), out(&clean, 0)], &[50_000, 49_500]);
+        let next = spend(3, &[out(&mix, 1)], &[49_000]);
+        let graph = graph_of(&[dirty.clone(), clean, mix.clone(), next.clone()]);
+
+        let shares = graph.compute_taint(
+            out(&dirty, 0),
+            TaintModel::Poison {
+                fees: FeeTaint::Track,
+            },
+        );
+
+        assert_eq!(tainted(&shares, out(&mix, 0)), 50_000);
+        assert_eq!(shares[&out(&mix, 1)].ratio(), 1.0);
+        assert_eq!(tainted(&shares, out(&next, 0)), 49_000);
+        assert_eq!(
+            tainted(&shares, OutPoint::new(mix.compute_txid(), FEE_VOUT)),
+            500
+        );
+    }
+
+    #[test]
+    fn test_unknown_source_has_no_taint() {
+        let tx = spend(0, &[], &[1_000]);
+        let graph = graph_of(std::slice::from_ref(&tx));
+
+        assert!(graph.compute_taint(out(&tx, 5), HAIRCUT).is_empty());
+        let shares = graph.compute_taint(out(&tx, 0), HAIRCUT);
+        assert_eq!(shares.len(), 1);
+        assert!(!shares[&out(&tx, 0)].is_clean());
+    }
+}
@@ -0,0 +1,161 @@
+//! Absolute and relative timelocks of transactions.
+//!
+//! A transaction's locktime keeps it out of blocks until a height or a time, and
+//! since BIP 68 the sequence of each input of a version 2 transaction can hold it
+//! back until its prevout is old enough. Contracts (HTLCs, Lightning sweeps, vaults)
+//! are spent under such locks; wallets also set height locktimes just below the tip
+//! against fee sniping, so relative locks say more than absolute ones.
+
+use bitcoin::{Sequence, Transaction, relative};
+use serde::{Deserialize, Serialize};
+
+/// Locktimes below this are block heights, the others unix times
+pub const LOCKTIME_THRESHOLD: u32 = 500_000_000;
+
+/// What the locktime of a transaction holds it back until.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LocktimeKind {
+    /// Nothing: the locktime is 0, or every input's sequence is final
+    None,
+    /// The block at this height
+    BlockHeight(u32),
+    /// The median time past of the chain reaching this unix time
+    Timestamp(u32),
+}
+
+/// What the locktime of `tx` holds it back until
+pub fn locktime_kind(tx: &Transaction) -> LocktimeKind {
+    let locktime = tx.lock_time.to_consensus_u32();
+    let enforced = tx.input.iter().any(|input| input.sequence != Sequence::MAX);
+    match locktime {
+        0 => LocktimeKind::None,
+        _ if !enforced => LocktimeKind::None,
+        height if height < LOCKTIME_THRESHOLD => LocktimeKind::BlockHeight(height),
+        time => LocktimeKind::Timestamp(time),
+    }
+}
+
+/// Relative lock (BIP 68) of input `vin` of `tx`: `None` for a version 1
+/// transaction, an input whose sequence disables it, or no such input
+pub fn relative_lock(tx: &Transaction, vin: usize) -> Option<relative::LockTime> {
+    if tx.version.0 < 2 {
+        return None;
+    }
+    tx.input.get(vin)?.sequence.to_relative_lock_time()
+}
+
+/// Flags the transactions spent under a timelock.
+///
+/// Annotates `locktime` with `height <n>` or `time <t>`, and `relative.<vin>` with
+/// `<n> blocks` or `<s> s` for each input under a relative lock.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TimelockDetector;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tracer::{TraceAnnotator, TraceContext, TraceGraph, fixtures::spend};
+    use bitcoin::{OutPoint, Txid, absolute::LockTime, hashes::Hash, transaction::Version};
+
+    fn locked(locktime: u32, sequences: &[Sequence]) -> Transaction {
+        let inputs: Vec<_> = (0..sequences.len() as u32)
+            .map(|vout| OutPoint::new(Txid::all_zeros(), vout))
+            .collect();
+        let mut tx = spend(1, &inputs, &[1_000]);
+        tx.lock_time = LockTime::from_consensus(locktime);
+        for (input, sequence) in tx.input.iter_mut().zip(sequences) {
+            input.sequence = *sequence;
+        }
+        tx
+    }
+
+    #[test]
+    fn test_locktimes_are_heights_or_times_when_enforced() {
+        let enforced = [Sequence::ENABLE_LOCKTIME_NO_RBF];
+        assert_eq!(
+            locktime_kind(&locked(812_345, &enforced)),
+            LocktimeKind::BlockHeight(812_345)
+        );
+        assert_eq!(
+            locktime_kind(&locked(1_700_000_000, &enforced)),
+            LocktimeKind::Timestamp(1_700_000_000)
+        );
+        assert_eq!(
+            locktime_kind(&locked(LOCKTIME_THRESHOLD - 1, &enforced)),
+            LocktimeKind::BlockHeight(LOCKTIME_THRESHOLD - 1)
+        );
+        assert_eq!(locktime_kind(&locked(0, &enforced)), LocktimeKind::None);
+        // Every sequence final: the locktime is ignored
+        assert_eq!(
+            locktime_kind(&locked(812_345, &[Sequence::MAX, Sequence::MAX])),
+            LocktimeKind::None
+        );
+        assert_eq!(
+            locktime_kind(&locked(812_345, &[Sequence::MAX, Sequence::ZERO])),
+            LocktimeKind::BlockHeight(812_345)
+        );
+    }
+
+    #[test]
+    fn test_relative_locks_follow_bip68() {
+        let mut tx = locked(
+            0,
+            &[
+                Sequence::from_height(144),
+                Sequence::from_512_second_intervals(8),
+                // Disable flag set, with a height that would otherwise apply
+                Sequence(0x8000_0090),
+                Sequence::MAX,
+            ],
+        );
+        assert_eq!(
+            relative_lock(&tx, 0),
+            Some(relative::LockTime::from_height(144))
+        );
+        assert_eq!(
+            relative_lock(&tx, 1),
+            Some(relative::LockTime::from_512_second_intervals(8))
+        );
+        assert_eq!(relative_lock(&tx, 2), None);
+        assert_eq!(relative_lock(&tx, 3), None);
+        assert_eq!(relative_lock(&tx, 4), None);
+        // Version 1 transactions predate BIP 68
+        tx.version = Version::ONE;
+        assert_eq!(relative_lock(&tx, 0), None);
+    }
+
+    #[test]
+    fn test_detector_flags_timelocked_spends() {
+        let graph = TraceGraph::new();
+        let ctx = TraceContext {
+            prevouts: &[],
+            depth: 1,
+            graph: &graph,
+            labels: None,
+        };
+        let tx = locked(
+            812_345,
+            &[
+                Sequence::from_height(144),
+                Sequence::from_512_second_intervals(2),
+            ],
+        );
+        let annotations: Vec<_> = TimelockDetector
+            .annotate(&tx, &ctx)
+            .into_iter()
+            .map(|annotation| (annotation.key, annotation.value))
+            .collect();
+        assert_eq!(
+            annotations,
+            [
+                ("locktime", "height 812345"),
+                ("relative.0", "144 blocks"),
+                ("relative.1", "1024 s"),
+            ]
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+        );
+
+        let plain = locked(0, &[Sequence::MAX]);
+        assert!(TimelockDetector.annotate(&plain, &ctx).is_empty());
+    }
+}
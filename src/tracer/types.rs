@@ -3,10 +3,11 @@
 //!
 //! This module defines the structues returned by the tracer and used internally
 //! to representg traced transactions graphs, terminal endpoints and stats.
-use serde::Serialize;
+use crate::blockchain::{BlockchainError, ErrorContext};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
-use bitcoin::{Address, Amount, OutPoint, Transaction, Txid};
+use bitcoin::{Address, Amount, OutPoint, ScriptBuf, Transaction, Txid};
 
 /// Complete result of a UTXO trace operation
 ///
@@ -83,14 +84,39 @@ pub struct Terminal {
 /// Reason why a trace terminated at a particular output
 ///
 /// Indicates the condition that caused the tracer to stop following a path.
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum TerminalReason {
     /// Output has not been spent
     Unspent,
     /// Reached max trace depth limit
     MaxDepthReached,
+    /// Spender not traced, the trace already holds `max_transactions` transactions
+    MaxTransactionsReached,
+    /// Spender not traced, its depth already holds `max_breadth` transactions
+    MaxBreadthReached,
+    /// Output left out by the branch strategy
+    NotFollowed,
+    /// Payment peeled off a peel chain, left out by `FollowPeelChain`
+    Peeled,
+    /// Output of a CoinJoin, not followed under the trace's `CoinJoinPolicy`
+    CoinJoin,
+    /// Output funding a Lightning channel, not followed under the trace's
+    /// `LightningPolicy`
+    LightningChannel,
+    /// OP_RETURN output, unspendable: its data is on the node of its transaction
+    DataCarrier,
+    /// Output pays one of the stop condition's target scripts
+    ReachedTarget(ScriptBuf),
+    /// The trace was cancelled before following the output
+    Cancelled,
+    /// The trace ran out of `max_requests` before following the output
+    BudgetExhausted,
     /// Output value below min threshold
     BelowMinValue,
+    /// Spender confirmed outside the trace's block height or time window
+    OutOfWindow,
+    /// Spender expanded by an earlier trace, in the trace's `TraceConfig::visited`
+    Visited,
     /// Output spent to identified excchange address
     Exchange(String),
     /// Output spent to identified coin mixer
@@ -99,10 +125,87 @@ pub enum TerminalReason {
     Sanctioned(String),
     /// Could not determine if spent
     DataUnavailable,
+    /// Looking up the spender failed for good, under `TraceConfig::continue_on_error`:
+    /// the error and the lookup it failed, for the trace to be diagnosed without logs
+    Error {
+        message: String,
+        context: Option<ErrorContext>,
+    },
     /// Other termination reason (catch-all)
     Other(String),
 }
 
+impl TerminalReason {
+    /// Stable snake_case name of the reason, as used by the exports
+    pub fn code(&self) -> &'static str {
+        match self {
+            TerminalReason::Unspent => "unspent",
+            TerminalReason::MaxDepthReached => "max_depth",
+            TerminalReason::MaxTransactionsReached => "max_transactions",
+            TerminalReason::MaxBreadthReached => "max_breadth",
+            TerminalReason::NotFollowed => "not_followed",
+            TerminalReason::Peeled => "peeled",
+            TerminalReason::CoinJoin => "coinjoin",
+            TerminalReason::LightningChannel => "lightning_channel",
+            TerminalReason::DataCarrier => "data_carrier",
+            TerminalReason::ReachedTarget(_) => "reached_target",
+            TerminalReason::Cancelled => "cancelled",
+            TerminalReason::BudgetExhausted => "budget_exhausted",
+            TerminalReason::BelowMinValue => "below_min_value",
+            TerminalReason::OutOfWindow => "out_of_window",
+            TerminalReason::Visited => "visited",
+            TerminalReason::Exchange(_) => "exchange",
+            TerminalReason::Mixer(_) => "mixer",
+            TerminalReason::Sanctioned(_) => "sanctioned",
+            TerminalReason::DataUnavailable => "data_unavailable",
+            TerminalReason::Error { .. } => "error",
+            TerminalReason::Other(_) => "other",
+        }
+    }
+
+    /// Whether the trace stopped here because of its configuration (a limit, the
+    /// branch strategy, a stop condition or policy) rather than the chain itself: the
+    /// output may have a spender another configuration follows
+    pub fn depends_on_config(&self) -> bool {
+        !matches!(
+            self,
+            TerminalReason::Unspent
+                | TerminalReason::DataCarrier
+                | TerminalReason::DataUnavailable
+                | TerminalReason::Error { .. }
+        )
+    }
+
+    /// Name or description carried by the reason, if any (the script hex for
+    /// `ReachedTarget`)
+    pub fn detail(&self) -> Option<String> {
+        match self {
+            TerminalReason::ReachedTarget(script) => Some(script.to_hex_string()),
+            TerminalReason::Exchange(detail)
+            | TerminalReason::Mixer(detail)
+            | TerminalReason::Sanctioned(detail)
+            | TerminalReason::Other(detail) => Some(detail.clone()),
+            TerminalReason::Error {
+                message,
+                context: Some(context),
+            } => Some(format!("{:#}: {}", context, message)),
+            TerminalReason::Error {
+                message,
+                context: None,
+            } => Some(message.clone()),
+            _ => None,
+        }
+    }
+
+    /// `Error` reason for a lookup that failed with `error`, its context kept apart
+    pub fn error(error: &BlockchainError) -> Self {
+        TerminalReason::Error {
+            message: error.inner().to_string(),
+            context: error.context().cloned(),
+        }
+    }
+}
+
 /// Summary statistics about a completed trace.
 ///
 ///  # Fields
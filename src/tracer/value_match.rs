@@ -0,0 +1,530 @@
+//! Value matching through exchanges: where deposited coins may have left again.
+//!
+//! Coins deposited to an exchange are pooled with everyone else's, so no transaction
+//! links a deposit to its withdrawal. What is left is a weak heuristic: a withdrawal
+//! soon after the deposit, of about the deposited amount less the exchange's fee, may
+//! be the same user's. Matches are proposed as `SpeculativeEdge`s with a confidence,
+//! kept apart from the traced edges of a graph (see `TraceGraph::speculative_edges`).
+
+#[cfg(feature = "trace-engine")]
+use crate::blockchain::BlockchainDataSource;
+#[cfg(feature = "trace-engine")]
+use crate::tracer::{Result, Tracer, TracerError};
+use crate::tracer::{TerminalReason, TraceEdge, TraceGraph, graph::deserialize_address};
+use bitcoin::{Address, Amount, Network, OutPoint};
+#[cfg(feature = "trace-engine")]
+use bitcoin::{ScriptBuf, Transaction, Txid};
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "trace-engine")]
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+/// Default relative difference of a match from the deposited value
+pub const DEFAULT_MATCH_TOLERANCE: f64 = 0.01;
+
+/// Default withdrawal fee a match may have had taken off the deposited value
+pub const DEFAULT_WITHDRAWAL_FEE: Amount = Amount::from_sat(100_000);
+
+/// Default time after the deposit a withdrawal is looked for
+pub const DEFAULT_MATCH_WINDOW: Duration = Duration::from_secs(7 * 24 * 3600);
+
+/// Default number of candidates proposed for a deposit
+pub const DEFAULT_MAX_CANDIDATES: usize = 5;
+
+/// Share of the confidence of a candidate given by how close its value is; the rest
+/// is given by how soon it came
+#[cfg(feature = "trace-engine")]
+const VALUE_WEIGHT: f64 = 0.7;
+
+/// A continuation of a trace proposed by value matching, not part of its edges.
+///
+/// # Fields
+/// * `deposit` - leaf of the graph the coins were traced into
+/// * `outpoint` - output proposed as where they went on
+/// * `value` - value of that output
+/// * `address` - address it pays, if it has a standard form
+/// * `delay` - seconds from the deposit's block to the candidate's
+/// * `confidence` - how good a match it is, from 0.0 to 1.0
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SpeculativeEdge {
+    pub deposit: OutPoint,
+    pub outpoint: OutPoint,
+    pub value: Amount,
+    #[serde(deserialize_with = "deserialize_address")]
+    pub address: Option<Address>,
+    pub delay: u64,
+    pub confidence: f64,
+}
+
+/// What matches a deposit.
+///
+/// A candidate is an output of a transaction of the cluster, paying outside of it,
+/// confirmed within `window` after the deposit, worth between the deposited value
+/// less `max_fee` and the `tolerance`, and the deposited value plus the `tolerance`.
+///
+/// # Fields
+/// * `tolerance` - relative difference from the deposited value allowed either way
+/// * `max_fee` - withdrawal fee the exchange may have taken off on top of it
+/// * `window` - time after the deposit's block a withdrawal is looked for
+/// * `max_candidates` - candidates proposed for a deposit, the most confident ones
+/// * `network` - network used to derive the addresses of the candidates
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValueMatchFollower {
+    pub tolerance: f64,
+    pub max_fee: Amount,
+    pub window: Duration,
+    pub max_candidates: usize,
+    pub network: Network,
+}
+
+impl Default for ValueMatchFollower {
+    fn default() -> Self {
+        Self {
+            tolerance: DEFAULT_MATCH_TOLERANCE,
+            max_fee: DEFAULT_WITHDRAWAL_FEE,
+            window: DEFAULT_MATCH_WINDOW,
+            max_candidates: DEFAULT_MAX_CANDIDATES,
+            network: Network::Bitcoin,
+        }
+    }
+}
+
+impl ValueMatchFollower {
+    /// Relative difference from the deposited value allowed either way (0.01 = 1%)
+    pub fn tolerance(mut self, tolerance: f64) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    /// Withdrawal fee the exchange may have taken off the deposited value
+    pub fn max_fee(mut self, max_fee: Amount) -> Self {
+        self.max_fee = max_fee;
+        self
+    }
+
+    /// Time after the deposit's block a withdrawal is looked for
+    pub fn window(mut self, window: Duration) -> Self {
+        self.window = window;
+        self
+    }
+
+    /// Candidates proposed for a deposit
+    pub fn max_candidates(mut self, max_candidates: usize) -> Self {
+        self.max_candidates = max_candidates;
+        self
+    }
+
+    /// Network used to derive the addresses of the candidates
+    pub fn network(mut self, network: Network) -> Self {
+        self.network = network;
+        self
+    }
+
+    /// Confidence of a candidate worth `value`, `delay` seconds after a deposit of
+    /// `deposited`; `None` if it does not match
+    #[cfg(feature = "trace-engine")]
+    fn score(&self, deposited: Amount, value: Amount, delay: u64) -> Option<f64> {
+        let deposited_sat = deposited.to_sat() as f64;
+        let slack = deposited_sat * self.tolerance;
+        let gap = deposited_sat - value.to_sat() as f64;
+        let window = self.window.as_secs();
+        // Below the deposit, the fee widens the range
+        let range = if gap > 0.0 {
+            slack + self.max_fee.to_sat() as f64
+        } else {
+            slack
+        };
+        if gap.abs() > range || delay > window {
+            return None;
+        }
+        let value_score = if range > 0.0 {
+            1.0 - gap.abs() / range
+        } else {
+            1.0
+        };
+        let time_score = if window > 0 {
+            1.0 - delay as f64 / window as f64
+        } else {
+            1.0
+        };
+        let confidence = VALUE_WEIGHT * value_score + (1.0 - VALUE_WEIGHT) * time_score;
+        // Four places are plenty, and survive a round trip through JSON
+        Some((confidence * 10_000.0).round() / 10_000.0)
+    }
+
+    #[cfg(feature = "trace-engine")]
+    fn validate(&self) -> Result<()> {
+        if !(self.tolerance.is_finite() && self.tolerance >= 0.0) {
+            return Err(TracerError::InvalidConfig(format!(
+                "value match tolerance must be finite and not negative, got {}",
+                self.tolerance
+            )));
+        }
+        if self.max_candidates == 0 {
+            return Err(TracerError::InvalidConfig(
+                "value matching must propose at least 1 candidate".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl TraceGraph {
+    /// Leaves of the graph paying an exchange, which value matching can continue from
+    pub fn exchange_deposits(&self) -> impl Iterator<Item = &TraceEdge> {
+        self.edges()
+            .filter(|edge| matches!(edge.terminal, Some(TerminalReason::Exchange(_))))
+    }
+}
+
+#[cfg(feature = "trace-engine")]
+impl<D: BlockchainDataSource + Sync> Tracer<D> {
+    /// Proposes where the coins of `deposit` left the exchange, most confident first.
+    ///
+    /// `cluster` holds the addresses of the exchange, such as the members of the
+    /// deposit address's cluster or the addresses labelled with its entity. Their
+    /// transactions are looked up with `get_address_transactions`, and the block time
+    /// of each one with a matching output. Transactions in `graph` and unconfirmed ones
+    /// are left out, and nothing is proposed for a deposit whose block time is not
+    /// known. The candidates are returned, not added to `graph`: see
+    /// `TraceGraph::add_speculative_edges`.
+    ///
+    /// The confidence of a candidate falls from 1.0, for the deposited value itself
+    /// right after the deposit, as its value moves away from the deposited one and as
+    /// it comes later in the window. Two candidates matching equally well are ranked
+    /// by how soon they came.
+    ///
+    /// # Errors
+    /// - `InvalidInput` - `deposit` is not a leaf of `graph` paying an exchange
+    /// - `InvalidConfig` - the tolerance of `follower` is negative or not finite, or it
+    ///   proposes no candidate
+    /// - `Source` - a lookup failed
+    pub async fn value_matches(
+        &self,
+        graph: &TraceGraph,
+        deposit: OutPoint,
+        cluster: &[Address],
+        follower: &ValueMatchFollower,
+    ) -> Result<Vec<SpeculativeEdge>> {
+        follower.validate()?;
+        let Some(edge) = graph
+            .exchange_deposits()
+            .find(|edge| edge.outpoint == deposit)
+        else {
+            return Err(TracerError::InvalidInput(format!(
+                "{} is not an exchange deposit of the graph",
+                deposit
+            )));
+        };
+        let deposited_at = match graph.node(&deposit.txid).and_then(|node| node.timestamp) {
+            Some(time) => Some(time),
+            None => self.block_time(deposit.txid).await?,
+        };
+        let Some(deposited_at) = deposited_at else {
+            return Ok(Vec::new());
+        };
+
+        let scripts: HashSet<ScriptBuf> = cluster.iter().map(Address::script_pubkey).collect();
+        let mut history: HashMap<Txid, Transaction> = HashMap::new();
+        for address in cluster {
+            for tx in self
+                .source()
+                .get_address_transactions(address.clone())
+                .await?
+            {
+                let txid = tx.compute_txid();
+                if txid != deposit.txid && !graph.contains_node(&txid) {
+                    history.entry(txid).or_insert(tx);
+                }
+            }
+        }
+
+        let low = Amount::from_sat(
+            (edge.value.to_sat() as f64 * (1.0 - follower.tolerance)).max(0.0) as u64,
+        )
+        .checked_sub(follower.max_fee)
+        .unwrap_or(Amount::ZERO);
+        let mut candidates = Vec::new();
+        for (txid, tx) in &history {
+            let outputs: Vec<_> = tx
+                .output
+                .iter()
+                .zip(0..)
+                .filter(|(out, _)| {
+                    out.value >= low
+                        && !out.script_pubkey.is_op_return()
+                        && !scripts.contains(&out.script_pubkey)
+                })
+                .collect();
+            if outputs.is_empty() {
+                continue;
+            }
+            let Some(time) = self.block_time(*txid).await? else {
+                continue;
+            };
+            let Some(delay) = time.checked_sub(deposited_at) else {
+                continue;
+            };
+            for (out, vout) in outputs {
+                let Some(confidence) = follower.score(edge.value, out.value, delay) else {
+                    continue;
+                };
+                let outpoint = OutPoint::new(*txid, vout);
+                candidates.push(SpeculativeEdge {
+                    deposit,
+                    outpoint,
+                    value: out.value,
+                    address: TraceEdge::new(outpoint, out, follower.network).address,
+                    delay,
+                    confidence,
+                });
+            }
+        }
+        candidates.sort_by(|a, b| {
+            b.confidence
+                .total_cmp(&a.confidence)
+                .then(a.delay.cmp(&b.delay))
+                .then(a.outpoint.cmp(&b.outpoint))
+        });
+        candidates.truncate(follower.max_candidates);
+        Ok(candidates)
+    }
+
+    /// Block time of `txid`, `None` while it is unconfirmed
+    async fn block_time(&self, txid: Txid) -> Result<Option<u64>> {
+        let status = self.source().get_transaction_status(txid).await?;
+        if status.block_time.is_some() {
+            return Ok(status.block_time);
+        }
+        match status.block_hash {
+            Some(hash) => Ok(Some(u64::from(
+                self.source().get_block_header(hash).await?.time,
+            ))),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "trace-engine"))]
+mod tests {
+    use super::*;
+    use crate::blockchain::TxStatus;
+    use crate::tracer::{
+        EntityCategory, Label, LabelStore, TraceConfig,
+        fixtures::{MockDataSource, header, script, spend},
+    };
+    use bitcoin::hashes::Hash;
+    use std::sync::Arc;
+
+    const DEPOSIT: u64 = 1_234_567;
+
+    fn address(n: u8) -> Address {
+        Address::from_script(&script(n), Network::Bitcoin).unwrap()
+    }
+
+    /// Transaction of the exchange, spending from its hot wallet `script(41)` and
+    /// paying `outputs` as `(sats, n)` to `script(n)`
+    fn withdrawal(tag: u32, outputs: &[(u64, u8)]) -> Transaction {
+        let hot = OutPoint::new(Txid::from_byte_array([4; 32]), tag);
+        let values: Vec<u64> = outputs.iter().map(|(value, _)| *value).collect();
+        let mut tx = spend(tag, &[hot], &values);
+        for (out, (_, n)) in tx.output.iter_mut().zip(outputs) {
+            out.script_pubkey = script(*n);
+        }
+        tx
+    }
+
+    /// `funding -> payment`, whose output 0 deposits `DEPOSIT` sats to the exchange at
+    /// `script(40)`, at height 1_000. The exchange's hot wallet `script(41)` then pays:
+    /// - at height 1_012 (2 hours later), a batch withdrawal of 1_230_000 sats to
+    ///   `script(50)`, with 20 small payouts and change of `DEPOSIT` sats to itself
+    /// - at 1_864 (6 days later), 1_234_000 sats to `script(70)`: an amount collision
+    /// - at 2_100 (past the week), `DEPOSIT` sats to `script(71)`
+    /// - at 990 (before the deposit), `DEPOSIT` sats to `script(72)`
+    /// - unconfirmed, `DEPOSIT` sats to `script(73)`
+    ///
+    /// Returns the source, the outpoint of the funding output, and the withdrawals in
+    /// that order
+    fn deposit_then_withdrawal() -> (MockDataSource, OutPoint, Vec<Transaction>) {
+        let funding = spend(1, &[], &[1_300_000]);
+        let root = OutPoint::new(funding.compute_txid(), 0);
+        let mut payment = spend(2, &[root], &[DEPOSIT, 60_000]);
+        payment.output[0].script_pubkey = script(40);
+        let mut batch: Vec<(u64, u8)> = (0..20).map(|n| (20_000, 100 + n)).collect();
+        batch.push((1_230_000, 50));
+        batch.push((DEPOSIT, 41));
+        let withdrawals = vec![
+            withdrawal(10, &batch),
+            withdrawal(11, &[(1_234_000, 70), (500_000, 41)]),
+            withdrawal(12, &[(DEPOSIT, 71), (500_000, 41)]),
+            withdrawal(13, &[(DEPOSIT, 72), (500_000, 41)]),
+            withdrawal(14, &[(DEPOSIT, 73), (500_000, 41)]),
+        ];
+
+        let mut source = MockDataSource::new(&[funding, payment.clone()]);
+        let heights = [
+            Some(1_000),
+            Some(1_012),
+            Some(1_864),
+            Some(2_100),
+            Some(990),
+            None,
+        ];
+        for (tx, height) in std::iter::once(&payment).chain(&withdrawals).zip(heights) {
+            source.add(tx.clone());
+            let Some(height) = height else {
+                continue;
+            };
+            let header = header(height);
+            source.confirm(
+                tx.compute_txid(),
+                TxStatus {
+                    confirmed: true,
+                    block_height: Some(height),
+                    block_hash: Some(header.block_hash()),
+                    block_time: None,
+                },
+            );
+            source.add_header(header);
+        }
+        (source, root, withdrawals)
+    }
+
+    async fn traced(tracer: &Tracer<MockDataSource>, root: OutPoint) -> TraceGraph {
+        let mut labels = LabelStore::new();
+        let exchange = Label {
+            entity: "Exchange".to_string(),
+            category: EntityCategory::Exchange,
+            source: None,
+            confidence: 1.0,
+        };
+        labels.insert(&address(40), exchange);
+        tracer
+            .trace_forward(root, &TraceConfig::default().labels(Arc::new(labels)))
+            .await
+            .unwrap()
+            .into_graph()
+    }
+
+    #[tokio::test]
+    async fn test_ranks_the_withdrawal_above_a_collision() {
+        let (source, root, withdrawals) = deposit_then_withdrawal();
+        let tracer = Tracer::new(source);
+        let mut graph = traced(&tracer, root).await;
+        let deposit = graph.exchange_deposits().next().unwrap().outpoint;
+        let cluster = [address(40), address(41)];
+
+        let candidates = tracer
+            .value_matches(&graph, deposit, &cluster, &ValueMatchFollower::default())
+            .await
+            .unwrap();
+
+        let outpoints: Vec<_> = candidates.iter().map(|edge| edge.outpoint).collect();
+        assert_eq!(
+            outpoints,
+            [
+                OutPoint::new(withdrawals[0].compute_txid(), 20),
+                OutPoint::new(withdrawals[1].compute_txid(), 0),
+            ]
+        );
+        let [withdrawal, collision] = &candidates[..] else {
+            unreachable!()
+        };
+        assert_eq!(withdrawal.delay, 12 * 600);
+        assert_eq!(withdrawal.address, Some(address(50)));
+        assert!(withdrawal.confidence > 0.9);
+        // Closer in value, but days later
+        assert_eq!(collision.delay, 864 * 600);
+        assert!(collision.confidence < withdrawal.confidence - 0.1);
+
+        // Candidates stay out of the traced edges
+        let edges = graph.edges().count();
+        graph.add_speculative_edges(candidates.clone());
+        assert_eq!(graph.edges().count(), edges);
+        assert_eq!(graph.speculative_edges(), &candidates[..]);
+        let back = TraceGraph::from_json(&graph.to_json()).unwrap();
+        assert_eq!(back.speculative_edges(), &candidates[..]);
+        let dot = graph.to_dot(&Default::default());
+        let candidate = format!("\"candidate:{}\"", candidates[0].outpoint);
+        assert!(dot.contains(&format!("{} [shape=ellipse, style=\"dashed\"", candidate)));
+        assert!(dot.contains(&format!("\"{}\" -> {} [label=\"", deposit, candidate)));
+        let mut xml = Vec::new();
+        graph.to_graphml(&mut xml).unwrap();
+        let xml = String::from_utf8(xml).unwrap();
+        assert_eq!(
+            xml.matches(r#"<data key="e_kind">speculative</data>"#)
+                .count(),
+            2
+        );
+        assert!(xml.contains(&format!(
+            r#"<node id="candidate:{}">"#,
+            candidates[1].outpoint
+        )));
+    }
+
+    #[tokio::test]
+    async fn test_tolerance_window_and_candidates_are_configurable() {
+        let (source, root, withdrawals) = deposit_then_withdrawal();
+        let tracer = Tracer::new(source);
+        let graph = traced(&tracer, root).await;
+        let deposit = graph.exchange_deposits().next().unwrap().outpoint;
+        let cluster = [address(40), address(41)];
+        let matches = |follower: ValueMatchFollower| {
+            let (tracer, graph, cluster) = (&tracer, &graph, &cluster);
+            async move {
+                tracer
+                    .value_matches(graph, deposit, cluster, &follower)
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .map(|edge| edge.outpoint)
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        let one = matches(ValueMatchFollower::default().max_candidates(1)).await;
+        assert_eq!(one, [OutPoint::new(withdrawals[0].compute_txid(), 20)]);
+        // Only the collision is within 1_000 sats, and only the withdrawal within a day
+        let exact = ValueMatchFollower::default()
+            .tolerance(0.0)
+            .max_fee(Amount::from_sat(1_000));
+        assert_eq!(
+            matches(exact).await,
+            [OutPoint::new(withdrawals[1].compute_txid(), 0)]
+        );
+        let day = ValueMatchFollower::default().window(Duration::from_secs(24 * 3600));
+        assert_eq!(
+            matches(day).await,
+            [OutPoint::new(withdrawals[0].compute_txid(), 20)]
+        );
+        // Past the week
+        let month = ValueMatchFollower::default().window(Duration::from_secs(30 * 24 * 3600));
+        assert!(
+            matches(month)
+                .await
+                .contains(&OutPoint::new(withdrawals[2].compute_txid(), 0))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_needs_an_exchange_deposit() {
+        let (source, root, _) = deposit_then_withdrawal();
+        let tracer = Tracer::new(source);
+        let graph = traced(&tracer, root).await;
+
+        let result = tracer
+            .value_matches(&graph, root, &[address(41)], &ValueMatchFollower::default())
+            .await;
+        assert!(matches!(result, Err(TracerError::InvalidInput(_))));
+        let deposit = graph.exchange_deposits().next().unwrap().outpoint;
+        let result = tracer
+            .value_matches(
+                &graph,
+                deposit,
+                &[address(41)],
+                &ValueMatchFollower::default().tolerance(-0.1),
+            )
+            .await;
+        assert!(matches!(result, Err(TracerError::InvalidConfig(_))));
+    }
+}
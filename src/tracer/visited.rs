@@ -0,0 +1,375 @@
+//! Transactions earlier traces expanded, for later traces to leave out.
+//!
+//! A `VisitedSet` is keyed by txid and by the flags of the trace that expanded the
+//! transaction: its direction and `TraceConfig::expansion_fingerprint`, since a
+//! transaction expanded under another branch strategy or CoinJoin policy was not
+//! expanded the way a trace under this one would. Fill a set from finished traces with
+//! `insert_graph`, keep it with serde and hand it to later traces with
+//! `TraceConfig::visited`. Forward, an output whose spender is in the set is then a
+//! `Visited` leaf; backward, a parent in the set is on the frontier rather than
+//! expanded. `TraceReport::skipped_expansions` counts both.
+//!
+//! An exact set holds every key. For memory-constrained runs, `VisitedSet::Approximate`
+//! is a Bloom filter of fixed size: it may claim a transaction was visited when it was
+//! not, at about the rate it was sized for, but never misses one that was. A false
+//! positive costs a branch the trace does not follow; every edge it does record is
+//! still one the chain holds.
+
+use crate::tracer::{
+    Result, TerminalReason, TraceConfig, TraceEdge, TraceGraph, TracerError, config::fnv1a,
+};
+use bitcoin::{Txid, hashes::Hash};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashSet};
+use std::f64::consts::LN_2;
+
+/// False positive rate to size an approximate set for, short of other constraints
+pub const DEFAULT_FALSE_POSITIVE_RATE: f64 = 0.01;
+
+/// Which way a trace expands transactions
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Direction {
+    /// To the transactions spending their outputs
+    Forward,
+    /// To the transactions their inputs spend
+    Backward,
+}
+
+/// A transaction expanded by a trace with the given flags (see `flags`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct VisitedKey {
+    pub txid: Txid,
+    pub flags: u64,
+}
+
+impl VisitedKey {
+    pub fn new(txid: Txid, direction: Direction, config: &TraceConfig) -> Self {
+        Self {
+            txid,
+            flags: flags(direction, config.expansion_fingerprint()),
+        }
+    }
+}
+
+/// Flags of the keys of a trace in `direction` under a configuration of expansion
+/// fingerprint `expansion`
+pub fn flags(direction: Direction, expansion: u64) -> u64 {
+    let mut bytes = expansion.to_le_bytes().to_vec();
+    bytes.push(match direction {
+        Direction::Forward => b'f',
+        Direction::Backward => b'b',
+    });
+    fnv1a(&bytes)
+}
+
+/// Transactions expanded by earlier traces.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum VisitedSet {
+    /// Every key, as inserted
+    Exact(BTreeSet<VisitedKey>),
+    /// A Bloom filter of the keys: may contain keys never inserted, at about its false
+    /// positive rate, but always contains those inserted
+    Approximate(BloomFilter),
+}
+
+impl Default for VisitedSet {
+    fn default() -> Self {
+        VisitedSet::Exact(BTreeSet::new())
+    }
+}
+
+impl VisitedSet {
+    pub fn exact() -> Self {
+        Self::default()
+    }
+
+    /// An approximate set sized for `capacity` keys at `false_positive_rate`; more keys
+    /// raise the rate.
+    ///
+    /// # Errors
+    /// - `InvalidConfig` - `capacity` is 0, or `false_positive_rate` is not strictly
+    ///   between 0 and 1
+    pub fn approximate(capacity: usize, false_positive_rate: f64) -> Result<Self> {
+        if capacity == 0 {
+            return Err(TracerError::InvalidConfig(
+                "visited set capacity must be at least 1".to_string(),
+            ));
+        }
+        if !(false_positive_rate > 0.0 && false_positive_rate < 1.0) {
+            return Err(TracerError::InvalidConfig(format!(
+                "false positive rate must be between 0 and 1, got {}",
+                false_positive_rate
+            )));
+        }
+        Ok(VisitedSet::Approximate(BloomFilter::new(
+            capacity,
+            false_positive_rate,
+        )))
+    }
+
+    /// Whether `key` was inserted; an approximate set may also say so of keys that
+    /// were not
+    pub fn contains(&self, key: &VisitedKey) -> bool {
+        match self {
+            VisitedSet::Exact(keys) => keys.contains(key),
+            VisitedSet::Approximate(filter) => filter.contains(key),
+        }
+    }
+
+    /// Inserts `key`. Whether the set did not contain it yet.
+    pub fn insert(&mut self, key: VisitedKey) -> bool {
+        match self {
+            VisitedSet::Exact(keys) => keys.insert(key),
+            VisitedSet::Approximate(filter) => filter.insert(&key),
+        }
+    }
+
+    /// Keys in the set. An approximate set counts the keys it did not already seem to
+    /// contain when they were inserted, so it may undercount.
+    pub fn len(&self) -> usize {
+        match self {
+            VisitedSet::Exact(keys) => keys.len(),
+            VisitedSet::Approximate(filter) => filter.inserted,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn is_approximate(&self) -> bool {
+        matches!(self, VisitedSet::Approximate(_))
+    }
+
+    /// Inserts the transactions of `graph`, traced in `direction` under `config`, that
+    /// the trace expanded in full: forward, those with every output followed or ended
+    /// for a reason of its own rather than a cap; backward, those off the frontier.
+    /// How many keys were new.
+    pub fn insert_graph(
+        &mut self,
+        graph: &TraceGraph,
+        direction: Direction,
+        config: &TraceConfig,
+    ) -> usize {
+        let flags = flags(direction, config.expansion_fingerprint());
+        // Forward, the transactions with an output a cap stopped at
+        let capped: HashSet<Txid> = graph
+            .edges()
+            .filter(|edge| edge.terminal.as_ref().is_some_and(capped))
+            .map(TraceEdge::from)
+            .collect();
+        graph
+            .nodes()
+            .filter(|node| !node.frontier && !node.truncated)
+            .filter(|node| direction == Direction::Backward || !capped.contains(&node.txid))
+            .filter(|node| {
+                self.insert(VisitedKey {
+                    txid: node.txid,
+                    flags,
+                })
+            })
+            .count()
+    }
+
+    /// Stable hash of the set, as `TraceConfig::fingerprint` needs
+    pub fn digest(&self) -> u64 {
+        match self {
+            VisitedSet::Exact(keys) => {
+                let bytes: Vec<u8> = keys
+                    .iter()
+                    .flat_map(|key| {
+                        key.txid
+                            .to_byte_array()
+                            .into_iter()
+                            .chain(key.flags.to_le_bytes())
+                    })
+                    .collect();
+                fnv1a(&bytes)
+            }
+            VisitedSet::Approximate(filter) => {
+                let bytes: Vec<u8> = filter
+                    .bits
+                    .iter()
+                    .flat_map(|word| word.to_le_bytes())
+                    .chain(filter.hashes.to_le_bytes())
+                    .collect();
+                fnv1a(&bytes)
+            }
+        }
+    }
+}
+
+/// Whether an output ended for `reason` might have been followed by a trace with
+/// higher caps
+fn capped(reason: &TerminalReason) -> bool {
+    matches!(
+        reason,
+        TerminalReason::MaxDepthReached
+            | TerminalReason::MaxTransactionsReached
+            | TerminalReason::MaxBreadthReached
+            | TerminalReason::BudgetExhausted
+            | TerminalReason::Cancelled
+            | TerminalReason::OutOfWindow
+            | TerminalReason::DataUnavailable
+            | TerminalReason::Error { .. }
+    )
+}
+
+/// Bloom filter of the keys of an approximate `VisitedSet`.
+///
+/// Positions come from double hashing an FNV-1a hash of the key, so a filter built on
+/// one machine reads the same on another.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    hashes: u32,
+    capacity: usize,
+    false_positive_rate: f64,
+    inserted: usize,
+}
+
+impl BloomFilter {
+    /// The optimal filter for `capacity` keys at `false_positive_rate`:
+    /// `-n ln p / ln² 2` bits and `m / n ln 2` hashes
+    fn new(capacity: usize, false_positive_rate: f64) -> Self {
+        let bits = (-(capacity as f64) * false_positive_rate.ln() / (LN_2 * LN_2)).ceil();
+        let words = (bits as usize).div_ceil(64).max(1);
+        let hashes = ((words * 64) as f64 / capacity as f64 * LN_2).round();
+        Self {
+            bits: vec![0; words],
+            hashes: (hashes as u32).max(1),
+            capacity,
+            false_positive_rate,
+            inserted: 0,
+        }
+    }
+
+    /// Keys the filter was sized for
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// False positive rate the filter has at `capacity` keys
+    pub fn false_positive_rate(&self) -> f64 {
+        self.false_positive_rate
+    }
+
+    /// Size of the filter in bits
+    pub fn bit_count(&self) -> usize {
+        self.bits.len() * 64
+    }
+
+    fn contains(&self, key: &VisitedKey) -> bool {
+        self.positions(key)
+            .all(|bit| self.bits[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+
+    fn insert(&mut self, key: &VisitedKey) -> bool {
+        if self.contains(key) {
+            return false;
+        }
+        for bit in self.positions(key).collect::<Vec<_>>() {
+            self.bits[bit / 64] |= 1 << (bit % 64);
+        }
+        self.inserted += 1;
+        true
+    }
+
+    fn positions(&self, key: &VisitedKey) -> impl Iterator<Item = usize> + use<> {
+        let mut bytes = key.txid.to_byte_array().to_vec();
+        bytes.extend(key.flags.to_le_bytes());
+        let first = fnv1a(&bytes);
+        bytes.reverse();
+        // Odd, so the positions cycle through every bit
+        let second = fnv1a(&bytes) | 1;
+        let bit_count = self.bit_count() as u64;
+        (0..u64::from(self.hashes))
+            .map(move |index| (first.wrapping_add(index.wrapping_mul(second)) % bit_count) as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tracer::fixtures::spend;
+    use bitcoin::OutPoint;
+
+    fn key(tag: u32) -> VisitedKey {
+        VisitedKey {
+            txid: spend(tag, &[OutPoint::null()], &[1]).compute_txid(),
+            flags: 7,
+        }
+    }
+
+    #[test]
+    fn test_exact_set_holds_what_was_inserted() {
+        let mut set = VisitedSet::exact();
+        assert!(set.is_empty() && !set.is_approximate());
+        assert!(set.insert(key(1)));
+        assert!(!set.insert(key(1)));
+        assert!(set.contains(&key(1)));
+        assert!(!set.contains(&key(2)));
+        // Same transaction, other flags
+        assert!(!set.contains(&VisitedKey { flags: 8, ..key(1) }));
+        assert_eq!(set.len(), 1);
+
+        let json = serde_json::to_string(&set).unwrap();
+        let back: VisitedSet = serde_json::from_str(&json).unwrap();
+        assert_eq!(back, set);
+        assert_eq!(back.digest(), set.digest());
+        assert_ne!(VisitedSet::exact().digest(), set.digest());
+    }
+
+    #[test]
+    fn test_approximate_set_never_misses_and_keeps_its_rate() {
+        let mut set = VisitedSet::approximate(200, 0.01).unwrap();
+        assert!(set.is_approximate());
+        for tag in 0..200 {
+            set.insert(key(tag));
+        }
+        assert!((0..200).all(|tag| set.contains(&key(tag))));
+        let false_positives = (1_000..6_000)
+            .filter(|tag| set.contains(&key(*tag)))
+            .count();
+        // About 50 expected out of 5_000
+        assert!(false_positives < 150, "{} false positives", false_positives);
+        assert!(set.len() <= 200);
+
+        let json = serde_json::to_string(&set).unwrap();
+        let back: VisitedSet = serde_json::from_str(&json).unwrap();
+        assert!((0..200).all(|tag| back.contains(&key(tag))));
+        let VisitedSet::Approximate(filter) = back else {
+            panic!("read back as exact");
+        };
+        assert_eq!(filter.capacity(), 200);
+        assert_eq!(filter.false_positive_rate(), 0.01);
+        assert!(filter.bit_count() >= 1_917);
+    }
+
+    #[test]
+    fn test_approximate_set_rejects_nonsense_sizes() {
+        for (capacity, rate) in [(0, 0.01), (10, 0.0), (10, 1.0), (10, f64::NAN)] {
+            assert!(matches!(
+                VisitedSet::approximate(capacity, rate),
+                Err(TracerError::InvalidConfig(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_flags_tell_directions_and_configs_apart() {
+        let config = TraceConfig::default();
+        let txid = key(1).txid;
+        let forward = VisitedKey::new(txid, Direction::Forward, &config);
+        assert_eq!(forward, VisitedKey::new(txid, Direction::Forward, &config));
+        assert_ne!(forward, VisitedKey::new(txid, Direction::Backward, &config));
+        // Caps do not change how a transaction is expanded; policies do
+        let deeper = config.clone().max_depth(50);
+        assert_eq!(forward, VisitedKey::new(txid, Direction::Forward, &deeper));
+        let above_dust = config.min_output_value(bitcoin::Amount::from_sat(546));
+        assert_ne!(
+            forward,
+            VisitedKey::new(txid, Direction::Forward, &above_dust)
+        );
+    }
+}
@@ -0,0 +1,688 @@
+//! Runs the `pathfinder` binary against a mock Esplora server.
+
+#![cfg(not(target_arch = "wasm32"))]
+
+use assert_cmd::Command;
+use assert_cmd::cargo::cargo_bin_cmd;
+use bitcoin::absolute::LockTime;
+use bitcoin::consensus::encode::serialize_hex;
+use bitcoin::hashes::Hash;
+use bitcoin::transaction::Version;
+use bitcoin::{
+    Address, Amount, Network, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid,
+    WPubkeyHash, Witness,
+};
+use pathfinder::tracer::{TerminalReason, TraceCheckpoint, TraceGraph};
+use serde_json::json;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+/// Pays `values` to P2WPKH outputs of key hashes `tag`, `tag + 1`..., spending
+/// `prevout`
+fn tx(prevout: OutPoint, tag: u8, values: &[u64]) -> Transaction {
+    Transaction {
+        version: Version::TWO,
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: prevout,
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::MAX,
+            witness: Witness::new(),
+        }],
+        output: values
+            .iter()
+            .enumerate()
+            .map(|(i, value)| TxOut {
+                value: Amount::from_sat(*value),
+                script_pubkey: script(tag + i as u8),
+            })
+            .collect(),
+    }
+}
+
+fn script(tag: u8) -> ScriptBuf {
+    ScriptBuf::new_p2wpkh(&WPubkeyHash::from_byte_array([tag; 20]))
+}
+
+/// A funding transaction whose first output is spent by a second one
+struct Chain {
+    server: MockServer,
+    root: Transaction,
+    spender: Transaction,
+}
+
+impl Chain {
+    async fn new() -> Self {
+        let server = MockServer::start().await;
+        let root = tx(
+            OutPoint::new(Txid::from_byte_array([7; 32]), 0),
+            1,
+            &[60_000, 40_000],
+        );
+        let spender = tx(OutPoint::new(root.compute_txid(), 0), 10, &[59_000]);
+        for tx in [&root, &spender] {
+            mount(
+                &server,
+                &format!("/tx/{}/hex", tx.compute_txid()),
+                serialize_hex(tx),
+            )
+            .await;
+        }
+        let outspends = [
+            (
+                root.compute_txid(),
+                0,
+                json!({"spent": true, "txid": spender.compute_txid(), "vin": 0}),
+            ),
+            (root.compute_txid(), 1, json!({"spent": false})),
+            (spender.compute_txid(), 0, json!({"spent": false})),
+        ];
+        for (txid, vout, outspend) in outspends {
+            mount(
+                &server,
+                &format!("/tx/{}/outspend/{}", txid, vout),
+                outspend,
+            )
+            .await;
+        }
+        Self {
+            server,
+            root,
+            spender,
+        }
+    }
+
+    /// `pathfinder` querying this chain's server
+    fn pathfinder(&self) -> Command {
+        let mut command = cargo_bin_cmd!("pathfinder");
+        command.args(["--esplora", &self.server.uri()]);
+        command
+    }
+
+    fn root_output(&self, vout: u32) -> String {
+        format!("{}:{}", self.root.compute_txid(), vout)
+    }
+}
+
+async fn mount(server: &MockServer, at: &str, body: impl Into<Body>) {
+    let response = match body.into() {
+        Body::Text(text) => ResponseTemplate::new(200).set_body_string(text),
+        Body::Json(json) => ResponseTemplate::new(200).set_body_json(json),
+    };
+    Mock::given(method("GET"))
+        .and(path(at))
+        .respond_with(response)
+        .mount(server)
+        .await;
+}
+
+enum Body {
+    Text(String),
+    Json(serde_json::Value),
+}
+
+impl From<String> for Body {
+    fn from(text: String) -> Self {
+        Body::Text(text)
+    }
+}
+
+impl From<serde_json::Value> for Body {
+    fn from(json: serde_json::Value) -> Self {
+        Body::Json(json)
+    }
+}
+
+fn stdout(command: &mut Command) -> String {
+    let output = command.assert().success();
+    String::from_utf8(output.get_output().stdout.clone()).unwrap()
+}
+
+fn exit_code(command: &mut Command) -> Option<i32> {
+    command.output().unwrap().status.code()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_tx_prints_the_transaction() {
+    let chain = Chain::new().await;
+    let txid = chain.root.compute_txid();
+    let status = json!({"fee": 500, "status": {"confirmed": true, "block_height": 100}});
+    mount(&chain.server, &format!("/tx/{}", txid), status).await;
+    mount(&chain.server, "/blocks/tip/height", "105".to_string()).await;
+
+    let text = stdout(chain.pathfinder().args(["tx", &txid.to_string()]));
+
+    assert!(
+        text.starts_with(&format!("Transaction {}\n", txid)),
+        "{}",
+        text
+    );
+    assert!(text.contains("6 confirmations, block 100"), "{}", text);
+    assert!(text.contains("Fee: 500 sat"), "{}", text);
+    let address = Address::from_script(&script(2), Network::Bitcoin).unwrap();
+    assert!(text.contains(&address.to_string()), "{}", text);
+    assert!(text.contains("40000"), "{}", text);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_spend_prints_the_spender_or_unspent() {
+    let chain = Chain::new().await;
+
+    let spent = stdout(chain.pathfinder().args(["spend", &chain.root_output(0)]));
+    let unspent = stdout(chain.pathfinder().args(["spend", &chain.root_output(1)]));
+    // As explorers write outputs
+    let hashed = stdout(
+        chain
+            .pathfinder()
+            .args(["spend", &chain.root_output(0).replace(':', "#")]),
+    );
+
+    assert_eq!(
+        spent,
+        format!("spent by {}\n", chain.spender.compute_txid())
+    );
+    assert_eq!(unspent, "unspent\n");
+    assert_eq!(hashed, spent);
+}
+
+#[test]
+fn test_completions_cover_the_commands() {
+    let script = cargo_bin_cmd!("pathfinder")
+        .args(["completions", "bash"])
+        .output()
+        .unwrap();
+
+    assert!(script.status.success());
+    let script = String::from_utf8(script.stdout).unwrap();
+    for command in [
+        "tx",
+        "spend",
+        "address",
+        "trace",
+        "warm",
+        "watch",
+        "completions",
+    ] {
+        assert!(
+            script.contains(&format!("pathfinder,{})", command)),
+            "{}",
+            command
+        );
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_address_lists_the_history() {
+    let chain = Chain::new().await;
+    let address = Address::from_script(&script(1), Network::Bitcoin).unwrap();
+    let history = json!([
+        {"txid": chain.spender.compute_txid(), "status": {"confirmed": false}},
+        {"txid": chain.root.compute_txid(), "status": {"confirmed": true}},
+    ]);
+    mount(&chain.server, &format!("/address/{}/txs", address), history).await;
+
+    let text = stdout(chain.pathfinder().args(["address", &address.to_string()]));
+
+    let lines: Vec<_> = text.lines().skip(2).collect();
+    assert_eq!(lines.len(), 2, "{}", text);
+    assert!(lines[0].starts_with(&chain.spender.compute_txid().to_string()));
+    assert!(lines[1].starts_with(&chain.root.compute_txid().to_string()));
+    assert!(lines[1].contains("0.0006 BTC"), "{}", text);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_trace_writes_the_graph_to_a_file() {
+    let chain = Chain::new().await;
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("trace.json");
+
+    let text = stdout(chain.pathfinder().args([
+        "trace",
+        &chain.root_output(0),
+        "--depth",
+        "3",
+        "--format",
+        "json",
+        "-o",
+        file.to_str().unwrap(),
+    ]));
+
+    assert_eq!(text, "");
+    let graph = TraceGraph::from_json(&std::fs::read_to_string(&file).unwrap()).unwrap();
+    let mut txids: Vec<_> = graph.nodes().map(|node| node.txid).collect();
+    txids.sort();
+    let mut expected = vec![chain.root.compute_txid(), chain.spender.compute_txid()];
+    expected.sort();
+    assert_eq!(txids, expected);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_trace_prints_a_summary_by_default() {
+    let chain = Chain::new().await;
+
+    let output = chain
+        .pathfinder()
+        .args(["trace", &chain.root_output(0), "--max-requests", "50"])
+        .assert()
+        .success();
+
+    let text = String::from_utf8(output.get_output().stdout.clone()).unwrap();
+    assert!(text.starts_with("Transactions: 2"), "{}", text);
+    // Not on a terminal, the progress is logged, down to the final status
+    let log = String::from_utf8(output.get_output().stderr.clone()).unwrap();
+    let last = log.lines().last().unwrap();
+    assert!(
+        last.starts_with("depth 1, 2 transactions, 0 queued, "),
+        "{}",
+        log
+    );
+    assert!(last.contains("/50 requests"), "{}", log);
+    assert!(last.ends_with("(done)"), "{}", log);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_trace_streams_json_lines_ending_with_a_summary() {
+    let chain = Chain::new().await;
+
+    let text =
+        stdout(
+            chain
+                .pathfinder()
+                .args(["trace", &chain.root_output(0), "--format", "jsonl"]),
+        );
+
+    let lines: Vec<serde_json::Value> = text
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let (summary, items) = lines.split_last().unwrap();
+    let mut nodes: Vec<_> = items
+        .iter()
+        .filter(|item| item["type"] == "node")
+        .map(|node| node["txid"].as_str().unwrap())
+        .collect();
+    nodes.sort();
+    // Written once each, the trace dropping a node once it is written
+    let written = nodes.len();
+    nodes.dedup();
+    assert_eq!(written, nodes.len(), "{}", text);
+    let edges = items.iter().filter(|item| item["type"] == "edge").count();
+    assert_eq!(summary["type"], "summary", "{}", text);
+    assert_eq!(summary["nodes"], nodes.len());
+    assert_eq!(summary["edges"], edges);
+    assert_eq!(summary["cancelled"], false);
+    assert_eq!(summary["error"], serde_json::Value::Null);
+    assert_eq!(nodes.len(), 2, "{}", text);
+    assert!(
+        items
+            .iter()
+            .all(|item| item["type"] == "node" || item["type"] == "edge")
+    );
+
+    // A failed trace still ends with the summary, holding the error
+    let missing = format!("{}:0", Txid::from_byte_array([9; 32]));
+    let output = chain
+        .pathfinder()
+        .args(["trace", &missing, "--format", "jsonl"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let text = String::from_utf8(output.stdout).unwrap();
+    let summary: serde_json::Value = serde_json::from_str(text.lines().last().unwrap()).unwrap();
+    assert_eq!(summary["type"], "summary");
+    assert!(summary["error"].is_string(), "{}", text);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_trace_input_traces_every_seed_that_resolves() {
+    let chain = Chain::new().await;
+    // Paid by the spender, never spent
+    let address = Address::from_script(&script(10), Network::Bitcoin).unwrap();
+    let history = json!([{"txid": chain.spender.compute_txid(), "status": {"confirmed": true}}]);
+    mount(&chain.server, &format!("/address/{}/txs", address), history).await;
+    let missing = format!("{}:0", Txid::from_byte_array([9; 32]));
+    let dir = tempfile::tempdir().unwrap();
+    let seeds = dir.path().join("seeds.txt");
+    std::fs::write(
+        &seeds,
+        format!(
+            "# case 17\n{}\n{}  # change\nnot-an-outpoint\n\n{}\n{}\n",
+            chain.root_output(0),
+            chain.root_output(1),
+            address,
+            missing
+        ),
+    )
+    .unwrap();
+    let graph = dir.path().join("trace.json");
+    let summary = dir.path().join("seeds.csv");
+
+    let output = chain
+        .pathfinder()
+        .args(["trace", "--format", "json", "--input"])
+        .arg(&seeds)
+        .arg("-o")
+        .arg(&graph)
+        .arg("--seed-summary")
+        .arg(&summary)
+        .assert()
+        .success();
+
+    let log = String::from_utf8(output.get_output().stderr.clone()).unwrap();
+    let lines: Vec<_> = log.lines().collect();
+    // The malformed line is reported first, before any lookup fails
+    assert!(
+        lines[0].contains("seeds.txt:4: invalid address 'not-an-outpoint'"),
+        "{}",
+        log
+    );
+    assert!(lines[1].contains("seeds.txt:7: "), "{}", log);
+    assert_eq!(lines[2], "Traced 3 of 5 seeds", "{}", log);
+    let graph = TraceGraph::from_json(&std::fs::read_to_string(&graph).unwrap()).unwrap();
+    let mut txids: Vec<_> = graph.nodes().map(|node| node.txid).collect();
+    txids.sort();
+    let mut expected = vec![chain.root.compute_txid(), chain.spender.compute_txid()];
+    expected.sort();
+    assert_eq!(txids, expected);
+    let summary = std::fs::read_to_string(&summary).unwrap();
+    let rows: Vec<Vec<&str>> = summary
+        .lines()
+        .map(|line| line.splitn(6, ',').collect())
+        .collect();
+    assert_eq!(
+        rows[0],
+        [
+            "line",
+            "seed",
+            "status",
+            "outpoints",
+            "transactions",
+            "error"
+        ]
+    );
+    // Line, status, outpoints and transactions of each seed
+    let expected = [
+        ["2", "traced", "1", "2"],
+        ["3", "traced", "1", "1"],
+        ["4", "failed", "0", "0"],
+        ["6", "traced", "1", "1"],
+        ["7", "failed", "0", "0"],
+    ];
+    assert_eq!(rows.len(), 6, "{}", summary);
+    for (row, expected) in rows[1..].iter().zip(expected) {
+        assert_eq!([row[0], row[2], row[3], row[4]], expected, "{}", summary);
+    }
+    assert_eq!(rows[4][1], address.to_string());
+    assert!(!rows[5][5].is_empty(), "{}", summary);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_warm_prefetches_the_seeds_and_prints_what_was_found() {
+    let chain = Chain::new().await;
+    let outspends = json!([
+        {"spent": true, "txid": chain.spender.compute_txid(), "vin": 0},
+        {"spent": false},
+    ]);
+    let at = format!("/tx/{}/outspends", chain.root.compute_txid());
+    mount(&chain.server, &at, outspends).await;
+    let missing = format!("{}:0", Txid::from_byte_array([9; 32]));
+    let dir = tempfile::tempdir().unwrap();
+    let seeds = dir.path().join("seeds.txt");
+    std::fs::write(
+        &seeds,
+        format!(
+            "{}\n{}  # change\nnot-an-outpoint\n{}\n",
+            chain.root_output(0),
+            chain.root_output(1),
+            missing
+        ),
+    )
+    .unwrap();
+
+    let output = chain
+        .pathfinder()
+        .arg("warm")
+        .arg(&seeds)
+        .assert()
+        .success();
+
+    let stdout = String::from_utf8(output.get_output().stdout.clone()).unwrap();
+    assert_eq!(
+        stdout,
+        "Transactions: 1 fetched, 0 already cached, 1 not found, 0 failed\n\
+         Spenders: 1 fetched, 0 already cached, 1 unspent, 0 failed\n"
+    );
+    let log = String::from_utf8(output.get_output().stderr.clone()).unwrap();
+    assert!(
+        log.contains("seeds.txt:3: invalid address 'not-an-outpoint'"),
+        "{}",
+        log
+    );
+    assert!(log.contains("seeds.txt:4: "), "{}", log);
+    assert!(log.ends_with("Warmed 2 of 4 seeds\n"), "{}", log);
+
+    // Nothing to warm
+    std::fs::write(&seeds, "not-an-outpoint\n").unwrap();
+    assert_eq!(
+        exit_code(chain.pathfinder().arg("warm").arg(&seeds)),
+        Some(3)
+    );
+}
+
+#[cfg(unix)]
+#[tokio::test(flavor = "multi_thread")]
+async fn test_interrupted_trace_saves_a_checkpoint_to_resume() {
+    let chain = Chain::new().await;
+    // The trace hangs on its last lookup, the first time it is made
+    let last = format!("/tx/{}/outspend/0", chain.spender.compute_txid());
+    Mock::given(method("GET"))
+        .and(path(last.as_str()))
+        .respond_with(ResponseTemplate::new(200).set_delay(std::time::Duration::from_secs(60)))
+        .up_to_n_times(1)
+        .with_priority(1)
+        .mount(&chain.server)
+        .await;
+    let dir = tempfile::tempdir().unwrap();
+    let graph = dir.path().join("trace.json");
+    let checkpoint = dir.path().join("trace.checkpoint.json");
+
+    let child = tokio::process::Command::new(env!("CARGO_BIN_EXE_pathfinder"))
+        .args([
+            "--esplora",
+            &chain.server.uri(),
+            "trace",
+            &chain.root_output(0),
+        ])
+        .args(["--depth", "3", "--format", "json", "-o"])
+        .arg(&graph)
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    while !chain
+        .server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .any(|request| request.url.path() == last)
+    {
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    let pid = child.id().unwrap().to_string();
+    assert!(
+        std::process::Command::new("kill")
+            .args(["-INT", &pid])
+            .status()
+            .unwrap()
+            .success()
+    );
+    let output = tokio::time::timeout(std::time::Duration::from_secs(30), child.wait_with_output())
+        .await
+        .unwrap()
+        .unwrap();
+
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(output.status.code(), Some(130), "{}", stderr);
+    let resume = format!(
+        "Resume it with: pathfinder trace --resume {} --depth 3",
+        checkpoint.display()
+    );
+    assert!(stderr.contains(&resume), "{}", stderr);
+    let partial = TraceGraph::from_json(&std::fs::read_to_string(&graph).unwrap()).unwrap();
+    assert_eq!(partial.len(), 2);
+    let saved = TraceCheckpoint::load(&checkpoint).unwrap();
+    assert_eq!(saved.graph().len(), 2);
+
+    let resumed = dir.path().join("resumed.json");
+    stdout(chain.pathfinder().args([
+        "trace",
+        "--resume",
+        checkpoint.to_str().unwrap(),
+        "--depth",
+        "3",
+        "--format",
+        "json",
+        "-o",
+        resumed.to_str().unwrap(),
+    ]));
+    let resumed = TraceGraph::from_json(&std::fs::read_to_string(&resumed).unwrap()).unwrap();
+    let unspent = OutPoint::new(chain.spender.compute_txid(), 0);
+    assert!(resumed.node(&chain.spender.compute_txid()).unwrap().unspent);
+    assert_eq!(
+        resumed.edge(&unspent).unwrap().terminal,
+        Some(TerminalReason::Unspent)
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_watch_reports_a_spend_and_extends_the_trace() {
+    let chain = Chain::new().await;
+    let dir = tempfile::tempdir().unwrap();
+    let trace = dir.path().join("trace.json");
+    chain
+        .pathfinder()
+        .args(["trace", &chain.root_output(0), "--format", "json", "-o"])
+        .arg(&trace)
+        .assert()
+        .success();
+
+    // The last output of the trace is spent between the second and third polls
+    let leaf = OutPoint::new(chain.spender.compute_txid(), 0);
+    let next = tx(leaf, 20, &[58_000]);
+    let outspend = format!("/tx/{}/outspend/0", leaf.txid);
+    Mock::given(method("GET"))
+        .and(path(outspend.as_str()))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({"spent": false})))
+        .up_to_n_times(2)
+        .with_priority(1)
+        .mount(&chain.server)
+        .await;
+    let spent = json!({"spent": true, "txid": next.compute_txid(), "vin": 0});
+    Mock::given(method("GET"))
+        .and(path(outspend.as_str()))
+        .respond_with(ResponseTemplate::new(200).set_body_json(spent))
+        .with_priority(2)
+        .mount(&chain.server)
+        .await;
+    mount(
+        &chain.server,
+        &format!("/tx/{}/hex", next.compute_txid()),
+        serialize_hex(&next),
+    )
+    .await;
+    // Watched alongside, never spent
+    let targets = dir.path().join("targets.txt");
+    std::fs::write(
+        &targets,
+        format!("# still unspent\n{}\n\n", chain.root_output(1)),
+    )
+    .unwrap();
+
+    let output = chain
+        .pathfinder()
+        .args([
+            "watch",
+            &leaf.to_string(),
+            "--interval",
+            "100ms",
+            "--timeout",
+            "3s",
+        ])
+        .arg("--targets-file")
+        .arg(&targets)
+        .arg("--extend-trace")
+        .arg(&trace)
+        .assert()
+        .success();
+
+    let text = String::from_utf8(output.get_output().stdout.clone()).unwrap();
+    assert!(
+        text.starts_with(&format!("{} spent by {}\n", leaf, next.compute_txid())),
+        "{}",
+        text
+    );
+    assert!(text.contains("58000"), "{}", text);
+    let log = String::from_utf8(output.get_output().stderr.clone()).unwrap();
+    assert!(
+        log.contains("Timed out, 1 targets still watched"),
+        "{}",
+        log
+    );
+    let requests = chain.server.received_requests().await.unwrap();
+    let polls = requests
+        .iter()
+        .filter(|request| request.url.path() == outspend)
+        .count();
+    // One poll of the trace, three of the watch
+    assert_eq!(polls, 4);
+    let graph = TraceGraph::from_json(&std::fs::read_to_string(&trace).unwrap()).unwrap();
+    assert_eq!(
+        graph.edge(&leaf).unwrap().spent_by,
+        Some(next.compute_txid())
+    );
+    assert_eq!(graph.node(&next.compute_txid()).unwrap().depth, 2);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_exit_codes_tell_failures_apart() {
+    let chain = Chain::new().await;
+    Mock::given(method("GET"))
+        .and(path(format!(
+            "/tx/{}/outspend/5",
+            chain.root.compute_txid()
+        )))
+        .respond_with(ResponseTemplate::new(502))
+        .mount(&chain.server)
+        .await;
+
+    // Arguments that do not parse
+    assert_eq!(exit_code(chain.pathfinder().args(["spend"])), Some(2));
+    assert_eq!(exit_code(chain.pathfinder().args(["teleport"])), Some(2));
+    // Arguments that parse, but are no txid, outpoint or address
+    assert_eq!(exit_code(chain.pathfinder().args(["tx", "abc"])), Some(3));
+    assert_eq!(
+        exit_code(chain.pathfinder().args(["spend", "abc:0"])),
+        Some(3)
+    );
+    assert_eq!(
+        exit_code(
+            chain
+                .pathfinder()
+                .args(["address", "tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx"])
+        ),
+        Some(3)
+    );
+    // The backend failing
+    assert_eq!(
+        exit_code(chain.pathfinder().args(["spend", &chain.root_output(5)])),
+        Some(4)
+    );
+    assert_eq!(
+        exit_code(
+            chain
+                .pathfinder()
+                .args(["tx", &Txid::from_byte_array([9; 32]).to_string()])
+        ),
+        Some(4)
+    );
+}
@@ -0,0 +1,56 @@
+//! Every feature builds on its own, on top of the core: `cargo check` of every
+//! target, tests included, with no feature, each feature alone and all of them.
+//!
+//! `cargo test --test features -- --ignored`
+
+use std::path::Path;
+
+const FEATURES: &[&str] = &[
+    "esplora",
+    "rpc",
+    "blockbook",
+    "bitcoind-rest",
+    "electrum",
+    "electrum-tls",
+    "cache",
+    "persistent-cache",
+    "moka-cache",
+    "trace-engine",
+    "blocking",
+    "cli",
+    "keyring",
+    "metrics",
+    "petgraph",
+    "test-utils",
+];
+
+/// `cargo check` of the library, binary, tests and any other target with `args`,
+/// into a target directory apart from the one whose lock the running test holds
+fn check(name: &str, args: &[&str]) {
+    let target = Path::new(env!("CARGO_TARGET_TMPDIR")).join("features");
+    let status = std::process::Command::new(env!("CARGO"))
+        .args(["check", "--all-targets", "--no-default-features"])
+        .args(args)
+        .arg("--target-dir")
+        .arg(target)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .env("RUSTFLAGS", "-D warnings")
+        .status()
+        .expect("cargo runs");
+    assert!(
+        status.success(),
+        "cargo check with {} failed: {}",
+        name,
+        status
+    );
+}
+
+#[test]
+#[ignore = "checks the crate once per feature, which takes minutes"]
+fn test_each_feature_builds_alone() {
+    check("no feature", &[]);
+    for feature in FEATURES {
+        check(feature, &["--features", feature]);
+    }
+    check("all features", &["--all-features"]);
+}
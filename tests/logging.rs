@@ -0,0 +1,253 @@
+//! Records what a trace logs through bitcoind, with a subscriber capturing every span
+//! and event.
+
+#![cfg(not(target_arch = "wasm32"))]
+
+use bitcoin::absolute::LockTime;
+use bitcoin::consensus::encode::serialize_hex;
+use bitcoin::hashes::Hash;
+use bitcoin::transaction::Version;
+use bitcoin::{
+    Amount, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, WPubkeyHash, Witness,
+};
+use pathfinder::blockchain::{BitcoinRpcClient, CachingDataSource};
+use pathfinder::tracer::{TraceConfig, Tracer};
+use serde_json::json;
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+use wiremock::matchers::{body_partial_json, method};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+const PASSWORD: &str = "hunter2";
+const URL_PASSWORD: &str = "urlsecret";
+
+/// A span or event, with the names of the spans it is in, outermost first
+#[derive(Debug, Clone)]
+struct Recorded {
+    name: String,
+    scope: Vec<String>,
+    fields: Vec<(String, String)>,
+}
+
+impl Recorded {
+    fn field(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(field, _)| field == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+#[derive(Default)]
+struct Fields(Vec<(String, String)>);
+
+impl Visit for Fields {
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        self.0
+            .push((field.name().to_string(), format!("{:?}", value)));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.push((field.name().to_string(), value.to_string()));
+    }
+}
+
+/// Keeps every span, as it is when closed, and every event
+#[derive(Clone, Default)]
+struct Capture {
+    spans: Arc<Mutex<Vec<Recorded>>>,
+    events: Arc<Mutex<Vec<Recorded>>>,
+}
+
+/// Fields of a span so far, kept in its extensions
+struct SpanFields(Vec<(String, String)>);
+
+impl<S> Layer<S> for Capture
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attributes: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let mut fields = Fields::default();
+        attributes.record(&mut fields);
+        let span = ctx.span(id).unwrap();
+        span.extensions_mut().insert(SpanFields(fields.0));
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let mut fields = Fields::default();
+        values.record(&mut fields);
+        let span = ctx.span(id).unwrap();
+        if let Some(known) = span.extensions_mut().get_mut::<SpanFields>() {
+            known.0.extend(fields.0);
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        let scope = ctx
+            .event_scope(event)
+            .map(|scope| {
+                scope
+                    .from_root()
+                    .map(|span| span.name().to_string())
+                    .collect()
+            })
+            .unwrap_or_default();
+        self.events.lock().unwrap().push(Recorded {
+            name: event.metadata().name().to_string(),
+            scope,
+            fields: fields.0,
+        });
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let span = ctx.span(&id).unwrap();
+        let mut scope: Vec<String> = span
+            .scope()
+            .from_root()
+            .map(|span| span.name().to_string())
+            .collect();
+        scope.pop();
+        let fields = span
+            .extensions()
+            .get::<SpanFields>()
+            .map(|fields| fields.0.clone())
+            .unwrap_or_default();
+        self.spans.lock().unwrap().push(Recorded {
+            name: span.name().to_string(),
+            scope,
+            fields,
+        });
+    }
+}
+
+fn tx(input: OutPoint, value: u64) -> Transaction {
+    Transaction {
+        version: Version::TWO,
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: input,
+            script_sig: ScriptBuf::from_bytes(vec![1, 2]),
+            sequence: Sequence::MAX,
+            witness: Witness::new(),
+        }],
+        output: vec![TxOut {
+            value: Amount::from_sat(value),
+            script_pubkey: ScriptBuf::new_p2wpkh(&WPubkeyHash::from_byte_array([3; 20])),
+        }],
+    }
+}
+
+async fn mount_transaction(server: &MockServer, tx: &Transaction) {
+    Mock::given(method("POST"))
+        .and(body_partial_json(json!({
+            "method": "getrawtransaction",
+            "params": [tx.compute_txid()],
+        })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "result": {"hex": serialize_hex(tx)},
+            "error": null,
+            "id": 1,
+        })))
+        .mount(server)
+        .await;
+}
+
+#[tokio::test]
+async fn test_a_trace_logs_hops_requests_and_lookups_without_secrets() {
+    let server = MockServer::start().await;
+    let coinbase = tx(OutPoint::null(), 50_000);
+    let child = tx(OutPoint::new(coinbase.compute_txid(), 0), 49_000);
+    mount_transaction(&server, &coinbase).await;
+    mount_transaction(&server, &child).await;
+    let url = server
+        .uri()
+        .replace("http://", &format!("http://alice:{}@", URL_PASSWORD));
+    let client = BitcoinRpcClient::new(url, "alice".into(), PASSWORD.into());
+
+    let capture = Capture::default();
+    let subscriber = tracing_subscriber::registry().with(capture.clone());
+    let _default = tracing::subscriber::set_default(subscriber);
+    tracing::debug!(?client, "tracing through");
+    let source = CachingDataSource::new(client, Duration::from_secs(60));
+    let outcome = Tracer::new(&source)
+        .trace_backward(child.compute_txid(), &TraceConfig::default())
+        .await
+        .unwrap();
+
+    assert_eq!(outcome.graph().len(), 2);
+    let spans = capture.spans.lock().unwrap().clone();
+    let events = capture.events.lock().unwrap().clone();
+    let trace: Vec<_> = spans.iter().filter(|span| span.name == "trace").collect();
+    assert_eq!(trace.len(), 1);
+    assert_eq!(trace[0].field("direction"), Some("backward"));
+    assert!(trace[0].scope.is_empty());
+    // The child is looked up in a depth 0 hop of its own, then expanded into its parent
+    let hops: Vec<_> = spans
+        .iter()
+        .filter(|span| span.name == "trace_hop")
+        .map(|span| {
+            assert_eq!(span.scope, ["trace"]);
+            (
+                span.field("txid").unwrap().to_string(),
+                span.field("depth").unwrap().to_string(),
+            )
+        })
+        .collect();
+    let child_hop = (child.compute_txid().to_string(), "0".to_string());
+    assert_eq!(hops, [child_hop.clone(), child_hop]);
+    let requests: Vec<_> = spans
+        .iter()
+        .filter(|span| span.name == "rpc_request")
+        .collect();
+    assert_eq!(requests.len(), 2);
+    for request in requests {
+        assert_eq!(request.scope, ["trace", "trace_hop"]);
+        assert_eq!(request.field("method"), Some("getrawtransaction"));
+        assert_eq!(request.field("status"), Some("200"));
+        assert!(request.field("elapsed_ms").is_some());
+        assert_eq!(
+            request.field("url").unwrap(),
+            server.uri().replace("http://", "http://***@")
+        );
+    }
+    let lookups: Vec<_> = events
+        .iter()
+        .filter(|event| event.field("message") == Some("cache lookup"))
+        .collect();
+    assert_eq!(lookups.len(), 2);
+    assert!(lookups.iter().all(
+        |event| event.field("outcome") == Some("miss") && event.scope == ["trace", "trace_hop"]
+    ));
+    assert!(
+        events
+            .iter()
+            .any(|event| event.field("message") == Some("trace finished"))
+    );
+
+    let secrets = [
+        PASSWORD.to_string(),
+        URL_PASSWORD.to_string(),
+        // alice:hunter2, as a basic authorization header carries it
+        "YWxpY2U6aHVudGVyMg".to_string(),
+    ];
+    for recorded in spans.iter().chain(&events) {
+        for (field, value) in &recorded.fields {
+            for secret in &secrets {
+                assert!(
+                    !value.contains(secret.as_str()),
+                    "{} of {:?}",
+                    field,
+                    recorded
+                );
+            }
+        }
+    }
+}
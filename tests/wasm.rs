@@ -0,0 +1,136 @@
+//! The library built for the browser: checked to compile natively, and run against a
+//! mocked `fetch` serving Esplora answers on wasm.
+//!
+//! `cargo test --test wasm -- --ignored`, then
+//! `wasm-pack test --headless --firefox -- --test wasm`
+
+#[cfg(not(target_arch = "wasm32"))]
+#[test]
+#[ignore = "needs the wasm32-unknown-unknown target, and clang to build secp256k1"]
+fn test_library_compiles_to_wasm() {
+    let status = std::process::Command::new(env!("CARGO"))
+        .args(["check", "--lib", "--target", "wasm32-unknown-unknown"])
+        .arg("--target-dir")
+        .arg(env!("CARGO_TARGET_TMPDIR"))
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .status()
+        .expect("cargo runs");
+    assert!(status.success(), "cargo check for wasm failed: {}", status);
+}
+
+#[cfg(target_arch = "wasm32")]
+mod browser {
+    use bitcoin::absolute::LockTime;
+    use bitcoin::consensus::encode::serialize_hex;
+    use bitcoin::hashes::Hash;
+    use bitcoin::transaction::Version;
+    use bitcoin::{
+        Amount, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid, WPubkeyHash, Witness,
+    };
+    use pathfinder::blockchain::{
+        BlockchainDataSource, CachingDataSource, EsploraClient, RateLimitedDataSource,
+    };
+    use pathfinder::tracer::{TraceConfig, Tracer};
+    use serde_json::{Map, Value, json};
+    use std::time::Duration;
+    use wasm_bindgen::prelude::*;
+    use wasm_bindgen_test::{wasm_bindgen_test, wasm_bindgen_test_configure};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen(inline_js = r#"
+    export function mock_fetch(routes) {
+        const table = JSON.parse(routes);
+        globalThis.fetch = async (request) => {
+            const path = new URL(request.url).pathname;
+            if (path in table) {
+                return new Response(table[path], { status: 200 });
+            }
+            return new Response("not found", { status: 404 });
+        };
+    }
+    "#)]
+    extern "C" {
+        /// Answers each fetch of a path of `routes` (a JSON object of paths to bodies)
+        /// with its body, and any other with a 404
+        fn mock_fetch(routes: &str);
+    }
+
+    /// Pays `values` to P2WPKH outputs of key hashes `tag`, `tag + 1`..., spending
+    /// `prevout`
+    fn tx(prevout: OutPoint, tag: u8, values: &[u64]) -> Transaction {
+        Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: prevout,
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }],
+            output: values
+                .iter()
+                .enumerate()
+                .map(|(i, value)| TxOut {
+                    value: Amount::from_sat(*value),
+                    script_pubkey: ScriptBuf::new_p2wpkh(&WPubkeyHash::from_byte_array(
+                        [tag + i as u8; 20],
+                    )),
+                })
+                .collect(),
+        }
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_trace_runs_in_the_browser() {
+        let root = tx(
+            OutPoint::new(Txid::from_byte_array([7; 32]), 0),
+            1,
+            &[60_000, 40_000],
+        );
+        let spender = tx(OutPoint::new(root.compute_txid(), 0), 10, &[59_000]);
+        let mut routes = Map::new();
+        for tx in [&root, &spender] {
+            routes.insert(
+                format!("/api/tx/{}/hex", tx.compute_txid()),
+                Value::String(serialize_hex(tx)),
+            );
+        }
+        let outspends = [
+            (
+                root.compute_txid(),
+                0,
+                json!({"spent": true, "txid": spender.compute_txid(), "vin": 0}),
+            ),
+            (root.compute_txid(), 1, json!({"spent": false})),
+            (spender.compute_txid(), 0, json!({"spent": false})),
+        ];
+        for (txid, vout, outspend) in outspends {
+            routes.insert(
+                format!("/api/tx/{}/outspend/{}", txid, vout),
+                Value::String(outspend.to_string()),
+            );
+        }
+        mock_fetch(&Value::Object(routes).to_string());
+
+        let esplora =
+            EsploraClient::new("https://esplora.test/api").timeout(Duration::from_secs(5));
+        assert_eq!(
+            esplora.get_transaction(root.compute_txid()).await.unwrap(),
+            root
+        );
+
+        // Paced to one request per 10ms, waits on the browser's timers
+        let limited = RateLimitedDataSource::new(esplora, 100.0, 1);
+        let tracer = Tracer::new(CachingDataSource::new(limited, Duration::from_secs(300)));
+        let outcome = tracer
+            .trace_forward(
+                OutPoint::new(root.compute_txid(), 0),
+                &TraceConfig::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(outcome.graph().len(), 2);
+        assert!(outcome.graph().contains_node(&spender.compute_txid()));
+    }
+}